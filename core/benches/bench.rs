@@ -1,18 +1,27 @@
 //! Benchmarks of the system.
 //!
-//! Supports sequential/random write/read workloads.
+//! Supports sequential/random write/read workloads, and mixed workloads with
+//! a configurable read ratio and per-thread rate limits.
+//! Random positions follow a configurable key distribution (uniform, zipfian,
+//! or sequential with random jumps).
 //! Write/read amount, concurrency and I/O buffer size are configurable.
 //! Provides a baseline named `EncDisk`, which simply protects data using authenticated encryption.
-//! Results are displayed as throughput in MiB/sec.
+//! Workloads can also be described by fio job files (see `fio::parse_job_file`),
+//! given as the first non-option argument of the bench binary.
+//! A WAF sweep (see `BenchBuilder::waf_sweep`) reports the write amplification
+//! and the GC statistics over disk utilizations and GC thresholds as CSV.
+//! Results are displayed as throughput in MiB/sec, along with latency percentiles
+//! (p50/p95/p99/p999/max), which expose tail latency caused by GC and compaction.
 use sworndisk_v2::*;
 
-use self::benches::{Bench, BenchBuilder, IoPattern, IoType};
+use self::benches::{Bench, BenchBuilder, IoPattern, IoType, KeyDistribution};
 use self::consts::*;
-use self::disks::{DiskType, FileAsDisk};
-use self::util::{DisplayData, DisplayThroughput};
+use self::disks::{scratch_disk, DiskType};
+use self::util::{
+    DisplayData, DisplayThroughput, LatencySnapshot, OpStats, RateLimiter, LATENCY_RECORDER,
+};
 
-use libc::{fdatasync, ftruncate, open, pread, pwrite, unlink, O_CREAT, O_DIRECT, O_RDWR, O_TRUNC};
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -57,6 +66,53 @@ fn main() {
     //     .concurrency(1)
     //     .build()
     //     .unwrap(),
+    // BenchBuilder::new("SwornDisk::write_zipf")
+    //     .disk_type(DiskType::SwornDisk)
+    //     .io_type(IoType::Write)
+    //     .io_pattern(IoPattern::Rnd)
+    //     .key_dist(KeyDistribution::Zipfian { theta: 0.99 })
+    //     .total_bytes(total_bytes)
+    //     .buf_size(4 * KiB)
+    //     .concurrency(1)
+    //     .build()
+    //     .unwrap(),
+    // Compare with the above to measure the cost of `Config::ordered_data_writes`,
+    // in a separate run since the config is global
+    // BenchBuilder::new("SwornDisk::write_rnd_ordered")
+    //     .disk_type(DiskType::SwornDisk)
+    //     .io_type(IoType::Write)
+    //     .io_pattern(IoPattern::Rnd)
+    //     .total_bytes(total_bytes)
+    //     .buf_size(4 * KiB)
+    //     .concurrency(1)
+    //     .ordered_data_writes(true)
+    //     .build()
+    //     .unwrap(),
+    // Compare with `SwornDisk::write_rnd` to measure the cost of full
+    // durability, i.e., journaling the records of every write
+    // BenchBuilder::new("SwornDisk::write_rnd_relaxed")
+    //     .disk_type(DiskType::SwornDisk)
+    //     .io_type(IoType::Write)
+    //     .io_pattern(IoPattern::Rnd)
+    //     .total_bytes(total_bytes)
+    //     .buf_size(4 * KiB)
+    //     .concurrency(1)
+    //     .relaxed_durability(true)
+    //     .build()
+    //     .unwrap(),
+    // Reproduce the WAF-vs-utilization curves, in a separate run since
+    // the config is global
+    // BenchBuilder::new("SwornDisk::waf_sweep")
+    //     .disk_type(DiskType::SwornDisk)
+    //     .io_type(IoType::Write)
+    //     .io_pattern(IoPattern::Rnd)
+    //     .total_bytes(total_bytes)
+    //     .buf_size(4 * KiB)
+    //     .batch_bytes(batch_bytes)
+    //     .loop_times(5)
+    //     .waf_sweep(&[0.5, 0.6, 0.7, 0.8, 0.9], &[0.1, 0.3, 0.6], "waf_sweep.csv")
+    //     .build()
+    //     .unwrap(),
     // BenchBuilder::new("SwornDisk::read_seq")
     //     .disk_type(DiskType::SwornDisk)
     //     .io_type(IoType::Read)
@@ -75,6 +131,16 @@ fn main() {
     //     .concurrency(1)
     //     .build()
     //     .unwrap(),
+    // BenchBuilder::new("SwornDisk::mixed_rnd")
+    //     .disk_type(DiskType::SwornDisk)
+    //     .io_type(IoType::Mixed { read_pct: 70 })
+    //     .io_pattern(IoPattern::Rnd)
+    //     .total_bytes(total_bytes)
+    //     .buf_size(4 * KiB)
+    //     .concurrency(4)
+    //     .rate_limit(64 * MiB)
+    //     .build()
+    //     .unwrap(),
     // Benchmark on `EncDisk` not enabled by default
     // BenchBuilder::new("EncDisk::write_seq")
     //     .disk_type(DiskType::EncDisk)
@@ -87,6 +153,18 @@ fn main() {
     //     .unwrap(),
     //   ];
 
+    // Benchmarks described by a fio job file take the place of the default ones
+    let benches = match std::env::args().skip(1).find(|arg| !arg.starts_with('-')) {
+        Some(job_file) => match fio::load_job_file(&job_file) {
+            Ok(benches) => benches,
+            Err(e) => {
+                println!("failed to load fio job file {}: {:?}", job_file, e);
+                return;
+            }
+        },
+        None => benches,
+    };
+
     // Run all benchmarks and output the results
     run_benches(benches);
 }
@@ -94,6 +172,14 @@ fn main() {
 fn run_benches(benches: Vec<Box<dyn Bench>>) {
     println!("");
 
+    // The statistics of each benchmark are dumped as a phase to the paths of
+    // `--stats-json=<path>` and `--stats-csv=<path>`, if given
+    let stats_path = |flag: &str| {
+        std::env::args().find_map(|arg| arg.strip_prefix(flag).map(|path| path.to_string()))
+    };
+    let (stats_json, stats_csv) = (stats_path("--stats-json="), stats_path("--stats-csv="));
+    let mut phases = Vec::new();
+
     let mut benched_count = 0;
     let mut failed_count = 0;
     for b in benches {
@@ -101,12 +187,18 @@ fn run_benches(benches: Vec<Box<dyn Bench>>) {
         let _ = b.prepare();
 
         let start = Instant::now();
+        let before = StatsExport::take_global();
         let res = b.run();
         if let Err(e) = res {
             failed_count += 1;
             println!("failed due to error {:?}", e);
             continue;
         }
+        phases.push((
+            b.name().to_string(),
+            StatsExport::take_global().since(&before),
+        ));
+        dump_phase_stats(&phases, stats_json.as_deref(), stats_csv.as_deref());
         //  let elapsed = start.elapsed();
 
         // let throughput = DisplayThroughput::new(b.total_bytes(), elapsed);
@@ -123,6 +215,30 @@ fn run_benches(benches: Vec<Box<dyn Bench>>) {
     );
 }
 
+/// Dump the statistics of the finished benchmarks each time, in case of a later failure.
+fn dump_phase_stats(phases: &[(String, StatsExport)], json: Option<&str>, csv: Option<&str>) {
+    let phases: Vec<_> = phases
+        .iter()
+        .map(|(name, stats)| (name.as_str(), stats.clone()))
+        .collect();
+    let dumps = [
+        (json, StatsExport::phases_to_json(&phases)),
+        (csv, StatsExport::phases_to_csv(&phases)),
+    ];
+    for (path, dump) in dumps {
+        let Some(path) = path else {
+            continue;
+        };
+        let res = match dump {
+            Ok(dump) => std::fs::write(path, dump).map_err(|e| format!("{:?}", e)),
+            Err(e) => Err(format!("{:?}", e)),
+        };
+        if let Err(e) = res {
+            println!("failed to dump the statistics to {}: {}", path, e);
+        }
+    }
+}
+
 type Result<T> = core::result::Result<T, Error>;
 
 mod benches {
@@ -165,6 +281,21 @@ mod benches {
         used_rate: Option<f64>,
         interval_sec: Option<Duration>,
         loop_times: Option<usize>,
+        rate_limit: Option<usize>,
+        latency_csv: Option<String>,
+        key_dist: KeyDistribution,
+        runtime: Option<Duration>,
+        victim_policy: VictimPolicyKind,
+        ordered_data_writes: bool,
+        relaxed_durability: bool,
+        waf_sweep: Option<WafSweep>,
+    }
+
+    /// The points and the report of a WAF sweep, see `BenchBuilder::waf_sweep`.
+    struct WafSweep {
+        used_rates: Vec<f64>,
+        gc_thresholds: Vec<f64>,
+        report_csv: String,
     }
 
     impl BenchBuilder {
@@ -181,6 +312,14 @@ mod benches {
                 used_rate: None,
                 interval_sec: None,
                 loop_times: None,
+                rate_limit: None,
+                latency_csv: None,
+                key_dist: KeyDistribution::Uniform,
+                runtime: None,
+                victim_policy: VictimPolicyKind::Greedy,
+                ordered_data_writes: false,
+                relaxed_durability: false,
+                waf_sweep: None,
             }
         }
 
@@ -234,6 +373,72 @@ mod benches {
             self
         }
 
+        /// Limit the throughput of each thread in bytes per second.
+        /// Only applies to mixed workloads.
+        pub fn rate_limit(mut self, bytes_per_sec: usize) -> Self {
+            self.rate_limit = Some(bytes_per_sec);
+            self
+        }
+
+        /// Dump the per-second throughput and latency time series to a CSV file.
+        pub fn latency_csv(mut self, path: &str) -> Self {
+            self.latency_csv = Some(path.to_string());
+            self
+        }
+
+        /// Specify the distribution of positions for random I/O.
+        pub fn key_dist(mut self, key_dist: KeyDistribution) -> Self {
+            self.key_dist = key_dist;
+            self
+        }
+
+        /// Stop the benchmark once it has run for `runtime`, even if
+        /// `total_bytes` is not reached.
+        pub fn runtime(mut self, runtime: Duration) -> Self {
+            self.runtime = Some(runtime);
+            self
+        }
+
+        /// The GC victim policy of `SwornDisk`.
+        pub fn victim_policy(mut self, victim_policy: VictimPolicyKind) -> Self {
+            self.victim_policy = victim_policy;
+            self
+        }
+
+        /// Whether `SwornDisk` flushes the data blocks before inserting their
+        /// records (see `Config::ordered_data_writes`), to measure its cost.
+        pub fn ordered_data_writes(mut self, ordered_data_writes: bool) -> Self {
+            self.ordered_data_writes = ordered_data_writes;
+            self
+        }
+
+        /// Whether `SwornDisk` writes with relaxed durability (see
+        /// `SwornDisk::write_relaxed`), to compare with full durability.
+        pub fn relaxed_durability(mut self, relaxed_durability: bool) -> Self {
+            self.relaxed_durability = relaxed_durability;
+            self
+        }
+
+        /// Run the random write workload on a fresh `SwornDisk` for each pair
+        /// of the used rates and the GC thresholds, and report the WAF and
+        /// the GC statistics of each run as a row of `report_csv`.
+        ///
+        /// Each run fills the disk to the used rate, then writes `batch_bytes`
+        /// randomly over the used blocks for `loop_times` rounds.
+        pub fn waf_sweep(
+            mut self,
+            used_rates: &[f64],
+            gc_thresholds: &[f64],
+            report_csv: &str,
+        ) -> Self {
+            self.waf_sweep = Some(WafSweep {
+                used_rates: used_rates.to_vec(),
+                gc_thresholds: gc_thresholds.to_vec(),
+                report_csv: report_csv.to_string(),
+            });
+            self
+        }
+
         pub fn build(self) -> Result<Box<dyn Bench>> {
             let Self {
                 name,
@@ -247,6 +452,14 @@ mod benches {
                 used_rate,
                 interval_sec,
                 loop_times,
+                rate_limit,
+                latency_csv,
+                key_dist,
+                runtime,
+                victim_policy,
+                ordered_data_writes,
+                relaxed_durability,
+                waf_sweep,
             } = self;
 
             let disk_type = match disk_type {
@@ -276,6 +489,87 @@ mod benches {
             if concurrency == 0 {
                 return_errno_with_msg!(Errno::InvalidArgs, "concurrency must be greater than 0");
             }
+            if let IoType::Mixed { read_pct } = io_type {
+                if read_pct > 100 {
+                    return_errno_with_msg!(
+                        Errno::InvalidArgs,
+                        "read_pct of a mixed workload must not exceed 100"
+                    );
+                }
+            } else if rate_limit.is_some() {
+                return_errno_with_msg!(
+                    Errno::InvalidArgs,
+                    "rate_limit is only supported by mixed workloads"
+                );
+            }
+            match key_dist {
+                KeyDistribution::Zipfian { theta } if !(theta > 0.0 && theta < 1.0) => {
+                    return_errno_with_msg!(
+                        Errno::InvalidArgs,
+                        "theta of a zipfian distribution must be in (0, 1)"
+                    );
+                }
+                KeyDistribution::SeqWithJumps { jump_pct } if jump_pct > 100 => {
+                    return_errno_with_msg!(Errno::InvalidArgs, "jump_pct must not exceed 100");
+                }
+                _ => {}
+            }
+            if rate_limit == Some(0) {
+                return_errno_with_msg!(Errno::InvalidArgs, "rate_limit must be greater than 0");
+            }
+            if relaxed_durability && disk_type != DiskType::SwornDisk {
+                return_errno_with_msg!(
+                    Errno::InvalidArgs,
+                    "relaxed_durability is only supported by SwornDisk"
+                );
+            }
+
+            if let Some(waf_sweep) = waf_sweep {
+                if disk_type != DiskType::SwornDisk
+                    || io_type != IoType::Write
+                    || io_pattern != IoPattern::Rnd
+                {
+                    return_errno_with_msg!(
+                        Errno::InvalidArgs,
+                        "waf_sweep only supports random writes on SwornDisk"
+                    );
+                }
+                if waf_sweep.used_rates.is_empty() || waf_sweep.gc_thresholds.is_empty() {
+                    return_errno_with_msg!(
+                        Errno::InvalidArgs,
+                        "used_rates and gc_thresholds of waf_sweep must not be empty"
+                    );
+                }
+                if waf_sweep
+                    .used_rates
+                    .iter()
+                    .chain(waf_sweep.gc_thresholds.iter())
+                    .any(|rate| !(*rate > 0.0 && *rate < 1.0))
+                {
+                    return_errno_with_msg!(
+                        Errno::InvalidArgs,
+                        "used_rates and gc_thresholds of waf_sweep must be in (0, 1)"
+                    );
+                }
+                let batch_bytes = match batch_bytes {
+                    Some(batch_bytes) => batch_bytes,
+                    None => return_errno_with_msg!(
+                        Errno::InvalidArgs,
+                        "batch_bytes must be given if waf_sweep is given"
+                    ),
+                };
+                return Ok(Box::new(WafSweepBench {
+                    name,
+                    buf_size,
+                    total_bytes,
+                    batch_bytes,
+                    loop_times: loop_times.unwrap_or(1),
+                    key_dist,
+                    victim_policy,
+                    ordered_data_writes,
+                    sweep: waf_sweep,
+                }));
+            }
 
             if let Some(interval_sec) = interval_sec {
                 let batch_bytes = match batch_bytes {
@@ -299,7 +593,13 @@ mod benches {
                         "loop_times must be given if interval_sec is given"
                     ),
                 };
-                let disk = Self::create_disk(total_bytes / BLOCK_SIZE, disk_type)?;
+                let disk = Self::create_disk(
+                    total_bytes / BLOCK_SIZE,
+                    disk_type,
+                    victim_policy,
+                    ordered_data_writes,
+                    relaxed_durability,
+                )?;
                 return Ok(Box::new(CleaningBench {
                     name,
                     disk,
@@ -309,10 +609,17 @@ mod benches {
                     used_rate,
                     interval_sec,
                     loop_times,
+                    key_dist,
                 }));
             }
 
-            let disk = Self::create_disk(total_bytes / BLOCK_SIZE, disk_type)?;
+            let disk = Self::create_disk(
+                total_bytes / BLOCK_SIZE,
+                disk_type,
+                victim_policy,
+                ordered_data_writes,
+                relaxed_durability,
+            )?;
             Ok(Box::new(SimpleDiskBench {
                 name,
                 disk,
@@ -321,35 +628,55 @@ mod benches {
                 buf_size,
                 total_bytes,
                 concurrency,
+                rate_limit,
+                op_stats: Arc::new(OpStats::new()),
+                latency_csv,
+                key_dist,
+                runtime,
             }))
         }
 
-        fn create_disk(total_nblocks: usize, disk_type: DiskType) -> Result<Arc<dyn BenchDisk>> {
+        fn create_disk(
+            total_nblocks: usize,
+            disk_type: DiskType,
+            victim_policy: VictimPolicyKind,
+            ordered_data_writes: bool,
+            relaxed_durability: bool,
+        ) -> Result<Arc<dyn BenchDisk>> {
             static DISK_ID: AtomicU32 = AtomicU32::new(0);
 
             let config = Some(Config {
                 enable_gc: true,
+                victim_policy_kind: victim_policy,
+                ordered_data_writes,
                 ..Default::default()
             });
 
             let disk: Arc<dyn BenchDisk> = match disk_type {
-                DiskType::SwornDisk => Arc::new(SwornDisk::create(
-                    FileAsDisk::create(
-                        total_nblocks * 5 / 4, // TBD
-                        &format!(
-                            "sworndisk-{}.image",
-                            DISK_ID.fetch_add(1, Ordering::Release)
-                        ),
-                    ),
-                    AeadKey::default(),
-                    None,
-                    config,
-                )?),
+                DiskType::SwornDisk => {
+                    let disk = SwornDisk::create(
+                        scratch_disk(
+                            total_nblocks * 5 / 4, // TBD
+                            &format!(
+                                "sworndisk-{}.image",
+                                DISK_ID.fetch_add(1, Ordering::Release)
+                            ),
+                        )?,
+                        AeadKey::default(),
+                        None,
+                        config,
+                    )?;
+                    if relaxed_durability {
+                        Arc::new(RelaxedSwornDisk(disk))
+                    } else {
+                        Arc::new(disk)
+                    }
+                }
 
                 DiskType::EncDisk => Arc::new(EncDisk::create(
                     total_nblocks,
                     &format!("encdisk-{}.image", DISK_ID.fetch_add(1, Ordering::Release)),
-                )),
+                )?),
             };
             Ok(disk)
         }
@@ -363,6 +690,11 @@ mod benches {
         buf_size: usize,
         total_bytes: usize,
         concurrency: u32,
+        rate_limit: Option<usize>,
+        op_stats: Arc<OpStats>,
+        latency_csv: Option<String>,
+        key_dist: KeyDistribution,
+        runtime: Option<Duration>,
     }
 
     impl Bench for SimpleDiskBench {
@@ -380,44 +712,67 @@ mod benches {
             let buf_nblocks = self.buf_size / BLOCK_SIZE;
             let total_nblocks = self.total_bytes / BLOCK_SIZE;
             let concurrency = self.concurrency;
+            let rate_limit = self.rate_limit;
+            let key_dist = self.key_dist;
 
             let local_nblocks = total_nblocks / (concurrency as usize);
-            let join_handles: Vec<JoinHandle<Result<()>>> = (0..concurrency)
-                .map(|i| {
-                    let disk = self.disk.clone();
-                    let local_pos = (i as BlockId) * local_nblocks;
-                    thread::spawn(move || match (io_type, io_pattern) {
-                        (IoType::Read, IoPattern::Seq) => {
-                            disk.read_seq(local_pos, local_nblocks, buf_nblocks)
-                        }
-                        (IoType::Write, IoPattern::Seq) => {
-                            disk.write_seq(local_pos, local_nblocks, buf_nblocks)
-                        }
-
-                        (IoType::Read, IoPattern::Rnd) => {
-                            disk.read_rnd(local_pos, local_nblocks, buf_nblocks)
-                        }
-                        (IoType::Write, IoPattern::Rnd) => {
-                            disk.write_rnd(local_pos, local_nblocks, local_nblocks, buf_nblocks)
-                        }
+            run_with_progress(self.latency_csv.as_deref(), || {
+                let start = Instant::now();
+                util::set_deadline(self.runtime.map(|runtime| start + runtime));
+                let join_handles: Vec<JoinHandle<Result<()>>> = (0..concurrency)
+                    .map(|i| {
+                        let disk = self.disk.clone();
+                        let op_stats = self.op_stats.clone();
+                        let local_pos = (i as BlockId) * local_nblocks;
+                        thread::spawn(move || match (io_type, io_pattern) {
+                            (IoType::Read, IoPattern::Seq) => {
+                                disk.read_seq(local_pos, local_nblocks, buf_nblocks)
+                            }
+                            (IoType::Write, IoPattern::Seq) => {
+                                disk.write_seq(local_pos, local_nblocks, buf_nblocks)
+                            }
+
+                            (IoType::Read, IoPattern::Rnd) => {
+                                disk.read_rnd(local_pos, local_nblocks, buf_nblocks, key_dist)
+                            }
+                            (IoType::Write, IoPattern::Rnd) => disk.write_rnd(
+                                local_pos,
+                                local_nblocks,
+                                local_nblocks,
+                                buf_nblocks,
+                                key_dist,
+                            ),
+                            (IoType::Mixed { read_pct }, io_pattern) => disk.mixed_rw(
+                                local_pos,
+                                local_nblocks,
+                                buf_nblocks,
+                                io_pattern,
+                                key_dist,
+                                read_pct,
+                                rate_limit.map(RateLimiter::new),
+                                &op_stats,
+                            ),
+                        })
                     })
-                })
-                .collect();
-
-            let mut any_error = None;
-            for join_handle in join_handles {
-                let res = join_handle
-                    .join()
-                    .expect("couldn't join on the associated thread");
-                if let Err(e) = res {
-                    println!("benchmark task error: {:?}", &e);
-                    any_error = Some(e);
+                    .collect();
+
+                let mut any_error = None;
+                for join_handle in join_handles {
+                    let res = join_handle
+                        .join()
+                        .expect("couldn't join on the associated thread");
+                    if let Err(e) = res {
+                        println!("benchmark task error: {:?}", &e);
+                        any_error = Some(e);
+                    }
                 }
-            }
-            match any_error {
-                None => Ok(()),
-                Some(e) => Err(e),
-            }
+                util::set_deadline(None);
+                self.op_stats.set_elapsed(start.elapsed());
+                match any_error {
+                    None => Ok(()),
+                    Some(e) => Err(e),
+                }
+            })
         }
 
         fn prepare(&self) -> Result<()> {
@@ -427,12 +782,25 @@ mod benches {
             // Fill the disk before a read bench
             let disk = self.disk.clone();
             let total_nblocks = self.total_bytes / BLOCK_SIZE;
-            thread::spawn(move || disk.write_seq(0 as BlockId, total_nblocks, 1024))
-                .join()
-                .unwrap()
+            run_with_progress(None, || {
+                thread::spawn(move || disk.write_seq(0 as BlockId, total_nblocks, 1024))
+                    .join()
+                    .unwrap()
+            })
         }
 
-        fn display_ext(&self) {}
+        fn display_ext(&self) {
+            if let IoType::Mixed { .. } = self.io_type {
+                let elapsed = self.op_stats.elapsed();
+                println!(
+                    "read throughput: {} ({}), write throughput: {} ({})",
+                    DisplayThroughput::new(self.op_stats.read_bytes(), elapsed),
+                    DisplayData::new(self.op_stats.read_bytes()),
+                    DisplayThroughput::new(self.op_stats.write_bytes(), elapsed),
+                    DisplayData::new(self.op_stats.write_bytes()),
+                );
+            }
+        }
     }
 
     impl fmt::Display for SimpleDiskBench {
@@ -457,6 +825,7 @@ mod benches {
         used_rate: f64,
         interval_sec: Duration,
         loop_times: usize,
+        key_dist: KeyDistribution,
     }
 
     impl Bench for CleaningBench {
@@ -474,9 +843,11 @@ mod benches {
             let disk = self.disk.clone();
             let total_nblocks =
                 (self.total_bytes as f64 * self.used_rate / BLOCK_SIZE as f64) as usize;
-            thread::spawn(move || disk.write_seq(0 as BlockId, total_nblocks, 1024))
-                .join()
-                .unwrap()
+            run_with_progress(None, || {
+                thread::spawn(move || disk.write_seq(0 as BlockId, total_nblocks, 1024))
+                    .join()
+                    .unwrap()
+            })
         }
 
         fn run(&self) -> Result<()> {
@@ -485,15 +856,23 @@ mod benches {
             let count = self.batch_bytes / BLOCK_SIZE;
             let total_nblocks = count;
             let disk = self.disk.clone();
-            for i in 0..self.loop_times {
-                let start = Instant::now();
-                disk.write_rnd(0 as BlockId, count, total_nblocks, buf_nblocks)?;
-                let elapsed = start.elapsed();
-                let throughput = DisplayThroughput::new(self.batch_bytes, elapsed);
-                info!("round[{}]: throughput: {}", i, throughput);
-                std::thread::sleep(self.interval_sec);
-            }
-            Ok(())
+            run_with_progress(None, || {
+                for i in 0..self.loop_times {
+                    let start = Instant::now();
+                    disk.write_rnd(
+                        0 as BlockId,
+                        count,
+                        total_nblocks,
+                        buf_nblocks,
+                        self.key_dist,
+                    )?;
+                    let elapsed = start.elapsed();
+                    let throughput = DisplayThroughput::new(self.batch_bytes, elapsed);
+                    info!("round[{}]: throughput: {}", i, throughput);
+                    std::thread::sleep(self.interval_sec);
+                }
+                Ok(())
+            })
         }
     }
 
@@ -509,10 +888,265 @@ mod benches {
         }
     }
 
+    /// A sweep of the write amplification over the disk utilizations and
+    /// the GC thresholds, built by `BenchBuilder::waf_sweep`.
+    ///
+    /// The statistics of the disk of each run are reset before its workload,
+    /// so a row covers the random writes only, not the filling.
+    pub struct WafSweepBench {
+        name: String,
+        buf_size: usize,
+        total_bytes: usize,
+        batch_bytes: usize,
+        loop_times: usize,
+        key_dist: KeyDistribution,
+        victim_policy: VictimPolicyKind,
+        ordered_data_writes: bool,
+        sweep: WafSweep,
+    }
+
+    /// The results of a run of `WafSweepBench`.
+    struct WafSweepRow {
+        used_rate: f64,
+        gc_threshold: f64,
+        logical_bytes: u64,
+        physical_bytes: u64,
+        waf: f64,
+        gc_rounds: u64,
+        gc_failed_rounds: u64,
+        elapsed: Duration,
+    }
+
+    /// A victim policy with a fixed GC threshold, which replaces the one
+    /// given by the GC worker.
+    struct FixedThresholdPolicy {
+        inner: Arc<dyn VictimPolicy>,
+        threshold: f64,
+    }
+
+    impl VictimPolicy for FixedThresholdPolicy {
+        fn pick_victim(&self, segment_table: &[Segment], _threshold: f64) -> Option<Victim> {
+            self.inner.pick_victim(segment_table, self.threshold)
+        }
+
+        fn pick_victim_with_ctx(&self, ctx: &GcContext) -> Option<Victim> {
+            self.inner.pick_victim_with_ctx(&GcContext {
+                threshold: self.threshold,
+                ..*ctx
+            })
+        }
+    }
+
+    impl WafSweepBench {
+        /// Runs a point of the sweep on a new image, which is removed afterwards
+        /// even if the GC threads still hold the disk (thus the image) then.
+        fn run_one(&self, used_rate: f64, gc_threshold: f64) -> Result<WafSweepRow> {
+            static SWEEP_ID: AtomicU32 = AtomicU32::new(0);
+
+            let path = format!(
+                "sworndisk-sweep-{}.image",
+                SWEEP_ID.fetch_add(1, Ordering::Release)
+            );
+            let res = self.run_on(&path, used_rate, gc_threshold);
+            let _ = std::fs::remove_file(&path);
+            res
+        }
+
+        fn run_on(&self, path: &str, used_rate: f64, gc_threshold: f64) -> Result<WafSweepRow> {
+            let total_nblocks = self.total_bytes / BLOCK_SIZE;
+            let used_nblocks = (total_nblocks as f64 * used_rate) as usize;
+            let buf_nblocks = self.buf_size / BLOCK_SIZE;
+            let config = Config {
+                enable_gc: true,
+                stat_waf: true,
+                victim_policy: Some(Arc::new(FixedThresholdPolicy {
+                    inner: self.victim_policy.build(),
+                    threshold: gc_threshold,
+                })),
+                ordered_data_writes: self.ordered_data_writes,
+                ..Default::default()
+            };
+            let disk = SwornDisk::create(
+                scratch_disk(total_nblocks * 5 / 4, path)?,
+                AeadKey::default(),
+                None,
+                Some(config),
+            )?;
+            disk.write_seq(0 as BlockId, used_nblocks, 1024)?;
+
+            disk.stats().reset();
+            let start = Instant::now();
+            for _ in 0..self.loop_times {
+                disk.write_rnd(
+                    0 as BlockId,
+                    self.batch_bytes / BLOCK_SIZE,
+                    used_nblocks,
+                    buf_nblocks,
+                    self.key_dist,
+                )?;
+            }
+            let elapsed = start.elapsed();
+
+            let (waf_stats, gc_stats) = (disk.stats().waf(), disk.stats().gc().get_stats());
+            Ok(WafSweepRow {
+                used_rate,
+                gc_threshold,
+                logical_bytes: waf_stats.get_logical(),
+                physical_bytes: waf_stats.get_physical(),
+                waf: waf_stats.waf(),
+                gc_rounds: gc_stats.num_rounds,
+                gc_failed_rounds: gc_stats.num_failed_rounds,
+                elapsed,
+            })
+        }
+
+        fn dump_report(path: &str, rows: &[WafSweepRow]) -> std::io::Result<()> {
+            use std::io::Write;
+
+            let mut file = std::fs::File::create(path)?;
+            writeln!(
+                file,
+                "used_rate,gc_threshold,logical_bytes,physical_bytes,waf,gc_rounds,gc_failed_rounds,elapsed_sec"
+            )?;
+            for row in rows {
+                writeln!(
+                    file,
+                    "{},{},{},{},{:.4},{},{},{:.3}",
+                    row.used_rate,
+                    row.gc_threshold,
+                    row.logical_bytes,
+                    row.physical_bytes,
+                    row.waf,
+                    row.gc_rounds,
+                    row.gc_failed_rounds,
+                    row.elapsed.as_secs_f64(),
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Bench for WafSweepBench {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn total_bytes(&self) -> usize {
+            self.batch_bytes
+                * self.loop_times
+                * self.sweep.used_rates.len()
+                * self.sweep.gc_thresholds.len()
+        }
+
+        fn run(&self) -> Result<()> {
+            let mut rows = Vec::new();
+            for &used_rate in &self.sweep.used_rates {
+                for &gc_threshold in &self.sweep.gc_thresholds {
+                    let row = self.run_one(used_rate, gc_threshold)?;
+                    info!(
+                        "used_rate: {}, gc_threshold: {}, waf: {:.3}, gc rounds: {}",
+                        used_rate, gc_threshold, row.waf, row.gc_rounds
+                    );
+                    rows.push(row);
+                    // Dump the finished runs each time, in case of a later failure
+                    Self::dump_report(&self.sweep.report_csv, &rows).map_err(|_| {
+                        Error::with_msg(Errno::IoFailed, "failed to write the WAF sweep report")
+                    })?;
+                }
+            }
+            Ok(())
+        }
+
+        fn display_ext(&self) {
+            println!("WAF sweep report: {}", self.sweep.report_csv);
+        }
+    }
+
+    impl fmt::Display for WafSweepBench {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "{} (total = {}, buf = {}, used_rates = {:?}, gc_thresholds = {:?})",
+                self.name(),
+                DisplayData::new(self.total_bytes),
+                DisplayData::new(self.buf_size),
+                self.sweep.used_rates,
+                self.sweep.gc_thresholds,
+            )
+        }
+    }
+
+    /// Run a benchmark task with `f`, printing the throughput and the tail
+    /// latency every second, and the latency percentiles at the end.
+    ///
+    /// Disk operations record their latency into `LATENCY_RECORDER`.
+    /// If `csv_path` is given, the per-second time series is dumped to it.
+    pub fn run_with_progress<F>(csv_path: Option<&str>, f: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        LATENCY_RECORDER.reset();
+        let interval = Duration::from_secs(1);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let progress_thread = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let start = Instant::now();
+                let mut series = Vec::new();
+                let mut last = LatencySnapshot::default();
+                while !stop.load(Ordering::Acquire) {
+                    thread::sleep(interval);
+                    let now = LATENCY_RECORDER.snapshot();
+                    let delta = now.sub(&last);
+                    if delta.count() > 0 {
+                        let throughput = DisplayThroughput::new(delta.total_bytes(), interval);
+                        println!(
+                            "throughput: {}, p99 latency: {:?}, total: {}",
+                            throughput,
+                            delta.percentile(99.0),
+                            DisplayData::new(now.total_bytes())
+                        );
+                    }
+                    series.push((start.elapsed(), delta));
+                    last = now;
+                }
+                series
+            })
+        };
+
+        let res = f();
+        stop.store(true, Ordering::Release);
+        let series = progress_thread.join().unwrap();
+
+        let total = LATENCY_RECORDER.snapshot();
+        if total.count() > 0 {
+            println!(
+                "latency: p50 = {:?}, p95 = {:?}, p99 = {:?}, p999 = {:?}, max = {:?}",
+                total.percentile(50.0),
+                total.percentile(95.0),
+                total.percentile(99.0),
+                total.percentile(99.9),
+                total.max()
+            );
+        }
+        if let Some(csv_path) = csv_path {
+            if let Err(e) = util::dump_latency_csv(csv_path, &series) {
+                println!("failed to dump latency series to {}: {}", csv_path, e);
+            }
+        }
+        res
+    }
+
     #[derive(Copy, Clone, Debug, PartialEq, Eq)]
     pub enum IoType {
         Read,
         Write,
+        /// Each operation is a read with the probability of `read_pct`%,
+        /// otherwise a write.
+        Mixed {
+            read_pct: u8,
+        },
     }
 
     #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -520,6 +1154,207 @@ mod benches {
         Seq,
         Rnd,
     }
+
+    /// The distribution of positions for random I/O.
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum KeyDistribution {
+        Uniform,
+        /// Zipfian with the skew `theta` in (0, 1), lower positions are hotter.
+        Zipfian {
+            theta: f64,
+        },
+        /// Sequential, but jumps to a uniformly random position with
+        /// the probability of `jump_pct`%.
+        SeqWithJumps {
+            jump_pct: u8,
+        },
+    }
+}
+
+/// A front-end for a subset of the fio job file syntax.
+///
+/// Each job section is mapped onto a `BenchBuilder` on `SwornDisk`.
+/// Supported options (in the `[global]` section or a job section) are:
+/// `rw`/`readwrite`, `bs`, `size`, `iodepth`, `numjobs`, `runtime`,
+/// `rwmixread` and `rwmixwrite`. Other options are ignored with a warning.
+///
+/// Besides, the non-fio option `ordered_data_writes=1` enables
+/// `Config::ordered_data_writes` of `SwornDisk`, to measure its cost,
+/// and `relaxed_durability=1` writes with `SwornDisk::write_relaxed`,
+/// to compare with full durability.
+///
+/// As `SwornDisk` serves I/O synchronously, an `iodepth` of N is emulated
+/// by N threads per job.
+mod fio {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    type JobOptions = BTreeMap<String, String>;
+
+    /// Load a fio job file and build the benchmarks it describes.
+    pub fn load_job_file(path: &str) -> Result<Vec<Box<dyn Bench>>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|_| Error::with_msg(Errno::IoFailed, "failed to read fio job file"))?;
+        parse_job_file(&content)
+    }
+
+    /// Parse the content of a fio job file and build the benchmarks it describes.
+    pub fn parse_job_file(content: &str) -> Result<Vec<Box<dyn Bench>>> {
+        let mut global = JobOptions::new();
+        let mut jobs: Vec<(String, JobOptions)> = Vec::new();
+        let mut in_global = false;
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let section = line[1..line.len() - 1].trim().to_string();
+                in_global = section == "global";
+                if !in_global {
+                    jobs.push((section, JobOptions::new()));
+                }
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (line, ""),
+            };
+            let options = if in_global {
+                &mut global
+            } else {
+                match jobs.last_mut() {
+                    Some((_, options)) => options,
+                    None => return_errno_with_msg!(
+                        Errno::InvalidArgs,
+                        "fio option appears before any section"
+                    ),
+                }
+            };
+            options.insert(key.to_string(), value.to_string());
+        }
+
+        jobs.into_iter()
+            .map(|(name, options)| {
+                let mut merged = global.clone();
+                merged.extend(options);
+                build_job(&name, &merged)
+            })
+            .collect()
+    }
+
+    fn build_job(name: &str, options: &JobOptions) -> Result<Box<dyn Bench>> {
+        for key in options.keys() {
+            if !matches!(
+                key.as_str(),
+                "rw" | "readwrite"
+                    | "bs"
+                    | "size"
+                    | "iodepth"
+                    | "numjobs"
+                    | "runtime"
+                    | "rwmixread"
+                    | "rwmixwrite"
+                    | "ordered_data_writes"
+                    | "relaxed_durability"
+            ) {
+                println!("fio job [{}]: option `{}` is ignored", name, key);
+            }
+        }
+
+        let rw = options
+            .get("rw")
+            .or_else(|| options.get("readwrite"))
+            .map(|rw| rw.as_str())
+            .unwrap_or("read");
+        let read_pct = match (options.get("rwmixread"), options.get("rwmixwrite")) {
+            (Some(read_pct), _) => parse_num(read_pct)?,
+            (None, Some(write_pct)) => 100usize.saturating_sub(parse_num(write_pct)?),
+            (None, None) => 50,
+        };
+        let read_pct = u8::try_from(read_pct)
+            .map_err(|_| Error::with_msg(Errno::InvalidArgs, "invalid rwmixread"))?;
+        let (io_type, io_pattern) = match rw {
+            "read" => (IoType::Read, IoPattern::Seq),
+            "write" => (IoType::Write, IoPattern::Seq),
+            "randread" => (IoType::Read, IoPattern::Rnd),
+            "randwrite" => (IoType::Write, IoPattern::Rnd),
+            "rw" | "readwrite" => (IoType::Mixed { read_pct }, IoPattern::Seq),
+            "randrw" => (IoType::Mixed { read_pct }, IoPattern::Rnd),
+            _ => return_errno_with_msg!(Errno::InvalidArgs, "unsupported fio rw type"),
+        };
+
+        let buf_size = match options.get("bs") {
+            Some(bs) => parse_size(bs)?,
+            None => 4 * KiB,
+        };
+        let size = match options.get("size") {
+            Some(size) => parse_size(size)?,
+            None => return_errno_with_msg!(Errno::InvalidArgs, "fio option `size` is not given"),
+        };
+        let numjobs = match options.get("numjobs") {
+            Some(numjobs) => parse_num(numjobs)?,
+            None => 1,
+        };
+        let iodepth = match options.get("iodepth") {
+            Some(iodepth) => parse_num(iodepth)?,
+            None => 1,
+        };
+
+        let mut builder = BenchBuilder::new(name)
+            .disk_type(DiskType::SwornDisk)
+            .io_type(io_type)
+            .io_pattern(io_pattern)
+            .buf_size(buf_size)
+            .total_bytes(size * numjobs)
+            .concurrency((numjobs * iodepth) as u32);
+        if let Some(runtime) = options.get("runtime") {
+            builder = builder.runtime(parse_time(runtime)?);
+        }
+        if let Some(ordered) = options.get("ordered_data_writes") {
+            builder = builder.ordered_data_writes(parse_num(ordered)? != 0);
+        }
+        if let Some(relaxed) = options.get("relaxed_durability") {
+            builder = builder.relaxed_durability(parse_num(relaxed)? != 0);
+        }
+        builder.build()
+    }
+
+    fn parse_num(value: &str) -> Result<usize> {
+        value
+            .parse::<usize>()
+            .map_err(|_| Error::with_msg(Errno::InvalidArgs, "invalid number in fio job file"))
+    }
+
+    /// Parse a size with an optional unit suffix, units are powers of 1024 as in fio.
+    fn parse_size(value: &str) -> Result<usize> {
+        let value = value.to_ascii_lowercase();
+        let value = value.trim_end_matches(|c| c == 'b' || c == 'i');
+        let (num, unit) = match value.chars().last() {
+            Some('k') => (&value[..value.len() - 1], KiB),
+            Some('m') => (&value[..value.len() - 1], MiB),
+            Some('g') => (&value[..value.len() - 1], GiB),
+            _ => (value, B),
+        };
+        Ok(parse_num(num)? * unit)
+    }
+
+    /// Parse a time with an optional unit suffix, the default unit is second as in fio.
+    fn parse_time(value: &str) -> Result<Duration> {
+        let value = value.to_ascii_lowercase();
+        if let Some(ms) = value.strip_suffix("ms") {
+            return Ok(Duration::from_millis(parse_num(ms)? as u64));
+        }
+        let (num, unit_secs) = match value.chars().last() {
+            Some('s') => (&value[..value.len() - 1], 1),
+            Some('m') => (&value[..value.len() - 1], 60),
+            Some('h') => (&value[..value.len() - 1], 3600),
+            _ => (value.as_str(), 1),
+        };
+        Ok(Duration::from_secs((parse_num(num)? * unit_secs) as u64))
+    }
 }
 
 #[allow(non_upper_case_globals)]
@@ -538,12 +1373,6 @@ mod consts {
 #[allow(dead_code, temporary_cstring_as_ptr)]
 mod disks {
     use super::*;
-    use std::{
-        ffi::CString,
-        ops::Range,
-        sync::atomic::{AtomicBool, AtomicUsize},
-        time::Duration,
-    };
 
     #[derive(Copy, Clone, Debug, PartialEq, Eq)]
     pub enum DiskType {
@@ -555,191 +1384,199 @@ mod disks {
         fn read_seq(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()>;
         fn write_seq(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()>;
 
-        fn read_rnd(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()>;
+        fn read_rnd(
+            &self,
+            pos: BlockId,
+            total_nblocks: usize,
+            buf_nblocks: usize,
+            key_dist: KeyDistribution,
+        ) -> Result<()>;
         fn write_rnd(
             &self,
             pos: BlockId,
             count: usize,
             total_nblocks: usize,
             buf_nblocks: usize,
+            key_dist: KeyDistribution,
         ) -> Result<()>;
-    }
 
-    #[derive(Clone)]
-    pub struct FileAsDisk {
-        fd: i32,
-        path: String,
-        range: Range<BlockId>,
-    }
+        fn read_at(&self, pos: BlockId, buf: BufMut) -> Result<()>;
+        fn write_at(&self, pos: BlockId, buf: BufRef) -> Result<()>;
+        fn sync_all(&self) -> Result<()>;
 
-    impl FileAsDisk {
-        pub fn create(nblocks: usize, path: &str) -> Self {
-            unsafe {
-                // let oflag = O_RDWR | O_CREAT | O_TRUNC;
-                let oflag = O_RDWR | O_CREAT | O_TRUNC | O_DIRECT;
-                let fd = open(CString::new(path).unwrap().as_ptr() as _, oflag, 0o666);
-                if fd == -1 {
-                    println!("open error: {}", std::io::Error::last_os_error());
-                }
-                assert!(fd > 0);
+        /// Mix reads and writes in one workload, recording the bytes of
+        /// each operation type in `op_stats`.
+        fn mixed_rw(
+            &self,
+            pos: BlockId,
+            total_nblocks: usize,
+            buf_nblocks: usize,
+            io_pattern: IoPattern,
+            key_dist: KeyDistribution,
+            read_pct: u8,
+            mut rate_limiter: Option<RateLimiter>,
+            op_stats: &OpStats,
+        ) -> Result<()> {
+            let mut buf = Buf::alloc(buf_nblocks)?;
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
+            let mut pos_gen = PosGenerator::new(key_dist, total_nblocks, buf_nblocks);
 
-                let res = ftruncate(fd, (nblocks * BLOCK_SIZE) as _);
-                if res == -1 {
-                    println!("ftruncate error: {}", std::io::Error::last_os_error());
+            for i in 0..total_nblocks / buf_nblocks {
+                if util::deadline_reached() {
+                    break;
                 }
-                assert!(res >= 0);
-
-                Self {
-                    fd,
-                    path: path.to_string(),
-                    range: 0..nblocks,
+                let op_pos = match io_pattern {
+                    IoPattern::Seq => i * buf_nblocks,
+                    IoPattern::Rnd => pos_gen.next_pos(),
+                };
+                if gen_rnd_pct() < read_pct {
+                    timed(buf_bytes, || self.read_at(pos + op_pos, buf.as_mut()))?;
+                    op_stats.add_read(buf_bytes);
+                } else {
+                    timed(buf_bytes, || self.write_at(pos + op_pos, buf.as_ref()))?;
+                    op_stats.add_write(buf_bytes);
+                }
+                if let Some(rate_limiter) = rate_limiter.as_mut() {
+                    rate_limiter.throttle(buf_bytes);
                 }
             }
+
+            self.sync_all()
         }
     }
 
-    impl BlockSet for FileAsDisk {
-        fn read(&self, mut pos: BlockId, mut buf: BufMut) -> Result<()> {
-            pos += self.range.start;
-            debug_assert!(pos + buf.nblocks() <= self.range.end);
+    /// Creates a scratch image with direct I/O, removed once dropped.
+    pub fn scratch_disk(nblocks: usize, path: &str) -> Result<FileDisk> {
+        let options = FileDiskOptions {
+            direct_io: true,
+            remove_on_drop: true,
+            ..FileDiskOptions::create(nblocks)
+        };
+        FileDisk::open_with(path, &options)
+    }
 
-            let buf_mut_slice = buf.as_mut_slice();
-            unsafe {
-                let res = pread(
-                    self.fd,
-                    buf_mut_slice.as_ptr() as _,
-                    buf_mut_slice.len(),
-                    (pos * BLOCK_SIZE) as _,
-                );
-                if res == -1 {
-                    return_errno_with_msg!(Errno::IoFailed, "file read failed");
+    impl BenchDisk for SwornDisk<FileDisk> {
+        fn read_seq(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()> {
+            let mut buf = Buf::alloc(buf_nblocks)?;
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
+
+            for i in 0..total_nblocks / buf_nblocks {
+                if util::deadline_reached() {
+                    break;
                 }
+                timed(buf_bytes, || self.read(pos + i * buf_nblocks, buf.as_mut()))?;
             }
 
             Ok(())
         }
 
-        fn write(&self, mut pos: BlockId, buf: BufRef) -> Result<()> {
-            pos += self.range.start;
-            debug_assert!(pos + buf.nblocks() <= self.range.end);
+        fn write_seq(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()> {
+            let buf = Buf::alloc(buf_nblocks)?;
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
 
-            let buf_slice = buf.as_slice();
-            unsafe {
-                let res = pwrite(
-                    self.fd,
-                    buf_slice.as_ptr() as _,
-                    buf_slice.len(),
-                    (pos * BLOCK_SIZE) as _,
-                );
-                if res == -1 {
-                    return_errno_with_msg!(Errno::IoFailed, "file write failed");
+            for i in 0..total_nblocks / buf_nblocks {
+                if util::deadline_reached() {
+                    break;
                 }
+                timed(buf_bytes, || {
+                    self.write(pos + i * buf_nblocks, buf.as_ref())
+                })?;
             }
-
+            self.sync()?;
             Ok(())
         }
 
-        fn subset(&self, range: Range<BlockId>) -> Result<Self>
-        where
-            Self: Sized,
-        {
-            debug_assert!(self.range.start + range.end <= self.range.end);
-            Ok(Self {
-                fd: self.fd,
-                path: self.path.clone(),
-                range: Range {
-                    start: self.range.start + range.start,
-                    end: self.range.start + range.end,
-                },
-            })
+        fn read_rnd(
+            &self,
+            pos: BlockId,
+            total_nblocks: usize,
+            buf_nblocks: usize,
+            key_dist: KeyDistribution,
+        ) -> Result<()> {
+            let mut buf = Buf::alloc(buf_nblocks)?;
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
+            let mut pos_gen = PosGenerator::new(key_dist, total_nblocks, buf_nblocks);
+
+            for _ in 0..total_nblocks / buf_nblocks {
+                if util::deadline_reached() {
+                    break;
+                }
+                let rnd_pos = pos_gen.next_pos();
+                timed(buf_bytes, || self.read(pos + rnd_pos, buf.as_mut()))?;
+            }
+
+            Ok(())
         }
 
-        fn flush(&self) -> Result<()> {
-            unsafe {
-                let res = fdatasync(self.fd);
-                if res == -1 {
-                    return_errno_with_msg!(Errno::IoFailed, "file sync failed");
+        fn write_rnd(
+            &self,
+            pos: BlockId,
+            count: usize,
+            total_nblocks: usize,
+            buf_nblocks: usize,
+            key_dist: KeyDistribution,
+        ) -> Result<()> {
+            let buf = Buf::alloc(buf_nblocks)?;
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
+            let mut pos_gen = PosGenerator::new(key_dist, total_nblocks, buf_nblocks);
+
+            for _ in 0..count / buf_nblocks {
+                if util::deadline_reached() {
+                    break;
                 }
+                let rnd_pos = pos_gen.next_pos();
+                timed(buf_bytes, || self.write(pos + rnd_pos, buf.as_ref()))?;
             }
+            self.sync()?;
             Ok(())
         }
 
-        fn nblocks(&self) -> usize {
-            self.range.len()
+        fn read_at(&self, pos: BlockId, buf: BufMut) -> Result<()> {
+            self.read(pos, buf)
         }
-    }
 
-    impl Drop for FileAsDisk {
-        fn drop(&mut self) {
-            unsafe {
-                unlink(self.path.as_ptr() as _);
-            }
+        fn write_at(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+            self.write(pos, buf)
         }
-    }
 
-    impl BenchDisk for SwornDisk<FileAsDisk> {
-        fn read_seq(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()> {
-            let mut buf = Buf::alloc(buf_nblocks)?;
+        fn sync_all(&self) -> Result<()> {
+            self.sync()
+        }
+    }
 
-            for i in 0..total_nblocks / buf_nblocks {
-                self.read(pos + i * buf_nblocks, buf.as_mut())?;
-            }
+    /// A `SwornDisk` whose writes are of relaxed durability, i.e., with
+    /// `SwornDisk::write_relaxed`.
+    pub struct RelaxedSwornDisk(SwornDisk<FileDisk>);
 
-            Ok(())
+    impl BenchDisk for RelaxedSwornDisk {
+        fn read_seq(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()> {
+            self.0.read_seq(pos, total_nblocks, buf_nblocks)
         }
 
         fn write_seq(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()> {
             let buf = Buf::alloc(buf_nblocks)?;
-
-            let current_bytes = Arc::new(AtomicUsize::new(0));
-            let stop = Arc::new(AtomicBool::new(false));
-            let interval = Duration::from_secs(1);
-
-            // Clone the Arc to share it with the spawned thread
-            let current_bytes_clone = Arc::clone(&current_bytes);
-            let stop_clone = Arc::clone(&stop);
-            std::thread::spawn(move || {
-                let mut last_bytes = 0usize;
-                loop {
-                    std::thread::sleep(interval);
-                    let bytes = current_bytes_clone.load(Ordering::Acquire);
-
-                    let delta = bytes.saturating_sub(last_bytes);
-                    last_bytes = bytes;
-
-                    if delta > 0 {
-                        let throughput = DisplayThroughput::new(delta, interval);
-                        println!(
-                            "throughput: {}, total_written: {}",
-                            throughput,
-                            DisplayData::new(bytes)
-                        );
-                    }
-
-                    if stop_clone.load(Ordering::Acquire) {
-                        break;
-                    }
-                }
-            });
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
 
             for i in 0..total_nblocks / buf_nblocks {
-                self.write(pos + i * buf_nblocks, buf.as_ref())?;
-                current_bytes.fetch_add(buf_nblocks * BLOCK_SIZE, Ordering::Release);
+                if util::deadline_reached() {
+                    break;
+                }
+                timed(buf_bytes, || {
+                    self.0.write_relaxed(pos + i * buf_nblocks, buf.as_ref())
+                })?;
             }
-            stop.store(true, Ordering::Release);
-            self.sync()?;
+            self.0.sync()?;
             Ok(())
         }
 
-        fn read_rnd(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()> {
-            let mut buf = Buf::alloc(buf_nblocks)?;
-
-            for _ in 0..total_nblocks / buf_nblocks {
-                let rnd_pos = gen_rnd_pos(total_nblocks, buf_nblocks);
-                self.read(pos + rnd_pos, buf.as_mut())?;
-            }
-
-            Ok(())
+        fn read_rnd(
+            &self,
+            pos: BlockId,
+            total_nblocks: usize,
+            buf_nblocks: usize,
+            key_dist: KeyDistribution,
+        ) -> Result<()> {
+            self.0.read_rnd(pos, total_nblocks, buf_nblocks, key_dist)
         }
 
         fn write_rnd(
@@ -748,67 +1585,158 @@ mod disks {
             count: usize,
             total_nblocks: usize,
             buf_nblocks: usize,
+            key_dist: KeyDistribution,
         ) -> Result<()> {
             let buf = Buf::alloc(buf_nblocks)?;
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
+            let mut pos_gen = PosGenerator::new(key_dist, total_nblocks, buf_nblocks);
 
-            let current_bytes = Arc::new(AtomicUsize::new(0));
-            let stop = Arc::new(AtomicBool::new(false));
-            let interval = Duration::from_secs(1);
+            for _ in 0..count / buf_nblocks {
+                if util::deadline_reached() {
+                    break;
+                }
+                let rnd_pos = pos_gen.next_pos();
+                timed(buf_bytes, || {
+                    self.0.write_relaxed(pos + rnd_pos, buf.as_ref())
+                })?;
+            }
+            self.0.sync()?;
+            Ok(())
+        }
 
-            // Clone the Arc to share it with the spawned thread
-            let current_bytes_clone = Arc::clone(&current_bytes);
-            let stop_clone = Arc::clone(&stop);
-            std::thread::spawn(move || {
-                let mut last_bytes = 0usize;
-                loop {
-                    std::thread::sleep(interval);
-                    let bytes = current_bytes_clone.load(Ordering::Acquire);
+        fn read_at(&self, pos: BlockId, buf: BufMut) -> Result<()> {
+            self.0.read(pos, buf)
+        }
 
-                    let delta = bytes.saturating_sub(last_bytes);
-                    last_bytes = bytes;
+        fn write_at(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+            self.0.write_relaxed(pos, buf)
+        }
 
-                    if delta > 0 {
-                        let throughput = DisplayThroughput::new(delta, interval);
-                        println!(
-                            "throughput: {}, total_written: {}",
-                            throughput,
-                            DisplayData::new(bytes)
-                        );
-                    }
+        fn sync_all(&self) -> Result<()> {
+            self.0.sync()
+        }
+    }
 
-                    if stop_clone.load(Ordering::Acquire) {
-                        return;
+    /// Run a disk operation of `nbytes`, recording its latency.
+    fn timed<F: FnOnce() -> Result<()>>(nbytes: usize, op: F) -> Result<()> {
+        let start = Instant::now();
+        op()?;
+        LATENCY_RECORDER.record(start.elapsed(), nbytes);
+        Ok(())
+    }
+
+    fn gen_rnd_pos(total_nblocks: usize, buf_nblocks: usize) -> BlockId {
+        let mut rnd_pos_bytes = [0u8; 8];
+        Rng::new(&[]).fill_bytes(&mut rnd_pos_bytes).unwrap();
+        BlockId::from_le_bytes(rnd_pos_bytes) % (total_nblocks - buf_nblocks)
+    }
+
+    /// Generates a random number in `[0, 1)`.
+    fn gen_rnd_f64() -> f64 {
+        let mut rnd_bytes = [0u8; 8];
+        Rng::new(&[]).fill_bytes(&mut rnd_bytes).unwrap();
+        (u64::from_le_bytes(rnd_bytes) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A generator of I/O positions following a `KeyDistribution`.
+    ///
+    /// Positions are aligned to `buf_nblocks` except in the uniform
+    /// distribution, which keeps the behavior of `gen_rnd_pos`.
+    pub struct PosGenerator {
+        key_dist: KeyDistribution,
+        total_nblocks: usize,
+        buf_nblocks: usize,
+        nslots: usize,
+        cursor: usize,
+        zipf: Option<ZipfState>,
+    }
+
+    /// Precomputed constants of the zipfian generator (Gray et al.,
+    /// "Quickly Generating Billion-Record Synthetic Databases").
+    struct ZipfState {
+        theta: f64,
+        alpha: f64,
+        zetan: f64,
+        eta: f64,
+    }
+
+    impl PosGenerator {
+        pub fn new(key_dist: KeyDistribution, total_nblocks: usize, buf_nblocks: usize) -> Self {
+            let nslots = (total_nblocks / buf_nblocks).max(1);
+            let zipf = match key_dist {
+                KeyDistribution::Zipfian { theta } => {
+                    let zeta = |n: usize| (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum();
+                    let zetan: f64 = zeta(nslots);
+                    let zeta2: f64 = zeta(2);
+                    Some(ZipfState {
+                        theta,
+                        alpha: 1.0 / (1.0 - theta),
+                        zetan,
+                        eta: (1.0 - (2.0 / nslots as f64).powf(1.0 - theta))
+                            / (1.0 - zeta2 / zetan),
+                    })
+                }
+                _ => None,
+            };
+            Self {
+                key_dist,
+                total_nblocks,
+                buf_nblocks,
+                nslots,
+                cursor: 0,
+                zipf,
+            }
+        }
+
+        /// Returns the next position.
+        pub fn next_pos(&mut self) -> BlockId {
+            match self.key_dist {
+                KeyDistribution::Uniform => gen_rnd_pos(self.total_nblocks, self.buf_nblocks),
+                KeyDistribution::Zipfian { .. } => self.next_zipf_slot() * self.buf_nblocks,
+                KeyDistribution::SeqWithJumps { jump_pct } => {
+                    if gen_rnd_pct() < jump_pct {
+                        self.cursor = (gen_rnd_f64() * self.nslots as f64) as usize;
                     }
+                    let slot = self.cursor % self.nslots;
+                    self.cursor = slot + 1;
+                    slot * self.buf_nblocks
                 }
-            });
+            }
+        }
 
-            for _ in 0..count / buf_nblocks {
-                let rnd_pos = gen_rnd_pos(total_nblocks, buf_nblocks);
-                self.write(pos + rnd_pos, buf.as_ref())?;
-                current_bytes.fetch_add(buf_nblocks * BLOCK_SIZE, Ordering::Release);
+        fn next_zipf_slot(&self) -> usize {
+            let zipf = self.zipf.as_ref().unwrap();
+            let u = gen_rnd_f64();
+            let uz = u * zipf.zetan;
+            if uz < 1.0 {
+                return 0;
             }
-            stop.store(true, Ordering::Release);
-            self.sync()?;
-            Ok(())
+            if uz < 1.0 + 0.5f64.powf(zipf.theta) {
+                return 1.min(self.nslots - 1);
+            }
+            let slot =
+                (self.nslots as f64 * (zipf.eta * u - zipf.eta + 1.0).powf(zipf.alpha)) as usize;
+            slot.min(self.nslots - 1)
         }
     }
 
-    fn gen_rnd_pos(total_nblocks: usize, buf_nblocks: usize) -> BlockId {
-        let mut rnd_pos_bytes = [0u8; 8];
-        Rng::new(&[]).fill_bytes(&mut rnd_pos_bytes).unwrap();
-        BlockId::from_le_bytes(rnd_pos_bytes) % (total_nblocks - buf_nblocks)
+    /// Generates a random percentage in `[0, 100)`.
+    fn gen_rnd_pct() -> u8 {
+        let mut rnd_bytes = [0u8; 1];
+        Rng::new(&[]).fill_bytes(&mut rnd_bytes).unwrap();
+        ((rnd_bytes[0] as usize * 100) >> 8) as u8
     }
 
     #[derive(Clone)]
     pub struct EncDisk {
-        file_disk: FileAsDisk,
+        file_disk: FileDisk,
     }
 
     impl EncDisk {
-        pub fn create(nblocks: usize, path: &str) -> Self {
-            Self {
-                file_disk: FileAsDisk::create(nblocks, path),
-            }
+        pub fn create(nblocks: usize, path: &str) -> Result<Self> {
+            Ok(Self {
+                file_disk: scratch_disk(nblocks, path)?,
+            })
         }
 
         fn dummy_encrypt() -> Result<()> {
@@ -843,12 +1771,15 @@ mod disks {
     impl BenchDisk for EncDisk {
         fn read_seq(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()> {
             let mut buf = Buf::alloc(buf_nblocks)?;
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
 
             for i in 0..total_nblocks / buf_nblocks {
-                for _ in 0..buf_nblocks {
-                    Self::dummy_decrypt().unwrap();
+                if util::deadline_reached() {
+                    break;
                 }
-                self.file_disk.read(pos + i * buf_nblocks, buf.as_mut())?;
+                timed(buf_bytes, || {
+                    self.read_at(pos + i * buf_nblocks, buf.as_mut())
+                })?;
             }
 
             Ok(())
@@ -856,26 +1787,37 @@ mod disks {
 
         fn write_seq(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()> {
             let buf = Buf::alloc(buf_nblocks)?;
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
 
             for i in 0..total_nblocks / buf_nblocks {
-                for _ in 0..buf_nblocks {
-                    Self::dummy_encrypt().unwrap();
+                if util::deadline_reached() {
+                    break;
                 }
-                self.file_disk.write(pos + i * buf_nblocks, buf.as_ref())?;
+                timed(buf_bytes, || {
+                    self.write_at(pos + i * buf_nblocks, buf.as_ref())
+                })?;
             }
 
             self.file_disk.flush()
         }
 
-        fn read_rnd(&self, pos: BlockId, total_nblocks: usize, buf_nblocks: usize) -> Result<()> {
+        fn read_rnd(
+            &self,
+            pos: BlockId,
+            total_nblocks: usize,
+            buf_nblocks: usize,
+            key_dist: KeyDistribution,
+        ) -> Result<()> {
             let mut buf = Buf::alloc(buf_nblocks)?;
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
+            let mut pos_gen = PosGenerator::new(key_dist, total_nblocks, buf_nblocks);
 
             for _ in 0..total_nblocks / buf_nblocks {
-                for _ in 0..buf_nblocks {
-                    Self::dummy_decrypt().unwrap();
+                if util::deadline_reached() {
+                    break;
                 }
-                let rnd_pos = gen_rnd_pos(total_nblocks, buf_nblocks);
-                self.file_disk.read(pos + rnd_pos, buf.as_mut())?;
+                let rnd_pos = pos_gen.next_pos();
+                timed(buf_bytes, || self.read_at(pos + rnd_pos, buf.as_mut()))?;
             }
 
             Ok(())
@@ -887,17 +1829,38 @@ mod disks {
             count: usize,
             total_nblocks: usize,
             buf_nblocks: usize,
+            key_dist: KeyDistribution,
         ) -> Result<()> {
             let buf = Buf::alloc(buf_nblocks)?;
+            let buf_bytes = buf_nblocks * BLOCK_SIZE;
+            let mut pos_gen = PosGenerator::new(key_dist, total_nblocks, buf_nblocks);
 
             for _ in 0..count / buf_nblocks {
-                for _ in 0..buf_nblocks {
-                    Self::dummy_encrypt().unwrap();
+                if util::deadline_reached() {
+                    break;
                 }
-                let rnd_pos = gen_rnd_pos(total_nblocks, buf_nblocks);
-                self.file_disk.write(pos + rnd_pos, buf.as_ref())?;
+                let rnd_pos = pos_gen.next_pos();
+                timed(buf_bytes, || self.write_at(pos + rnd_pos, buf.as_ref()))?;
+            }
+
+            self.file_disk.flush()
+        }
+
+        fn read_at(&self, pos: BlockId, buf: BufMut) -> Result<()> {
+            for _ in 0..buf.nblocks() {
+                Self::dummy_decrypt().unwrap();
             }
+            self.file_disk.read(pos, buf)
+        }
+
+        fn write_at(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+            for _ in 0..buf.nblocks() {
+                Self::dummy_encrypt().unwrap();
+            }
+            self.file_disk.write(pos, buf)
+        }
 
+        fn sync_all(&self) -> Result<()> {
             self.file_disk.flush()
         }
     }
@@ -906,6 +1869,8 @@ mod disks {
 mod util {
     use super::*;
     use std::fmt::{self};
+    use std::sync::atomic::{AtomicU64, AtomicUsize};
+    use std::sync::OnceLock;
     use std::time::Duration;
 
     pub fn init_logger() {
@@ -975,4 +1940,269 @@ mod util {
             write!(f, "{:.2} {}", throughput_in_unit, unit_str)
         }
     }
+
+    /// Statistics of bytes read and written in a mixed workload.
+    pub struct OpStats {
+        read_bytes: AtomicUsize,
+        write_bytes: AtomicUsize,
+        elapsed_ns: AtomicU64,
+    }
+
+    impl OpStats {
+        pub fn new() -> Self {
+            Self {
+                read_bytes: AtomicUsize::new(0),
+                write_bytes: AtomicUsize::new(0),
+                elapsed_ns: AtomicU64::new(0),
+            }
+        }
+
+        pub fn add_read(&self, nbytes: usize) {
+            self.read_bytes.fetch_add(nbytes, Ordering::Relaxed);
+        }
+
+        pub fn add_write(&self, nbytes: usize) {
+            self.write_bytes.fetch_add(nbytes, Ordering::Relaxed);
+        }
+
+        pub fn read_bytes(&self) -> usize {
+            self.read_bytes.load(Ordering::Relaxed)
+        }
+
+        pub fn write_bytes(&self) -> usize {
+            self.write_bytes.load(Ordering::Relaxed)
+        }
+
+        pub fn set_elapsed(&self, elapsed: Duration) {
+            self.elapsed_ns
+                .store(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            Duration::from_nanos(self.elapsed_ns.load(Ordering::Relaxed))
+        }
+    }
+
+    /// Limit the throughput of a thread by sleeping when it runs ahead
+    /// of the given rate.
+    pub struct RateLimiter {
+        bytes_per_sec: usize,
+        start: Instant,
+        total_bytes: usize,
+    }
+
+    impl RateLimiter {
+        pub fn new(bytes_per_sec: usize) -> Self {
+            Self {
+                bytes_per_sec,
+                start: Instant::now(),
+                total_bytes: 0,
+            }
+        }
+
+        /// Account `nbytes` just transferred, sleep if running ahead of the rate.
+        pub fn throttle(&mut self, nbytes: usize) {
+            self.total_bytes += nbytes;
+            let expected =
+                Duration::from_secs_f64(self.total_bytes as f64 / self.bytes_per_sec as f64);
+            let elapsed = self.start.elapsed();
+            if expected > elapsed {
+                std::thread::sleep(expected - elapsed);
+            }
+        }
+    }
+
+    /// Number of linear sub-buckets per power-of-two range of latencies.
+    const SUB_BUCKET_BITS: u32 = 4;
+    const NR_SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+    const NR_BUCKETS: usize = 64 * NR_SUB_BUCKETS;
+
+    /// The global recorder of disk operation latencies.
+    pub static LATENCY_RECORDER: LatencyRecorder = LatencyRecorder::new();
+
+    /// A concurrent latency histogram in the style of HDR histograms.
+    ///
+    /// Latencies (in nanoseconds) are grouped by power-of-two ranges, each of
+    /// which is further divided into `NR_SUB_BUCKETS` linear buckets, bounding
+    /// the relative error of a recorded value by `1 / NR_SUB_BUCKETS`.
+    pub struct LatencyRecorder {
+        buckets: [AtomicU64; NR_BUCKETS],
+        max_ns: AtomicU64,
+        total_bytes: AtomicUsize,
+    }
+
+    impl LatencyRecorder {
+        pub const fn new() -> Self {
+            #[allow(clippy::declare_interior_mutable_const)]
+            const ZERO: AtomicU64 = AtomicU64::new(0);
+            Self {
+                buckets: [ZERO; NR_BUCKETS],
+                max_ns: AtomicU64::new(0),
+                total_bytes: AtomicUsize::new(0),
+            }
+        }
+
+        /// Record the latency of an operation that transfers `nbytes`.
+        pub fn record(&self, latency: Duration, nbytes: usize) {
+            let latency_ns = latency.as_nanos() as u64;
+            self.buckets[Self::bucket_idx(latency_ns)].fetch_add(1, Ordering::Relaxed);
+            self.max_ns.fetch_max(latency_ns, Ordering::Relaxed);
+            self.total_bytes.fetch_add(nbytes, Ordering::Relaxed);
+        }
+
+        pub fn reset(&self) {
+            for bucket in self.buckets.iter() {
+                bucket.store(0, Ordering::Relaxed);
+            }
+            self.max_ns.store(0, Ordering::Relaxed);
+            self.total_bytes.store(0, Ordering::Relaxed);
+        }
+
+        pub fn snapshot(&self) -> LatencySnapshot {
+            LatencySnapshot {
+                buckets: self
+                    .buckets
+                    .iter()
+                    .map(|bucket| bucket.load(Ordering::Relaxed))
+                    .collect(),
+                max_ns: self.max_ns.load(Ordering::Relaxed),
+                total_bytes: self.total_bytes.load(Ordering::Relaxed),
+            }
+        }
+
+        fn bucket_idx(latency_ns: u64) -> usize {
+            if latency_ns < NR_SUB_BUCKETS as u64 {
+                return latency_ns as usize;
+            }
+            let msb = 63 - latency_ns.leading_zeros();
+            let shift = msb - SUB_BUCKET_BITS;
+            let sub_idx = (latency_ns >> shift) as usize - NR_SUB_BUCKETS;
+            (shift as usize + 1) * NR_SUB_BUCKETS + sub_idx
+        }
+
+        /// Returns the upper bound of the latencies in a bucket.
+        fn bucket_upper_bound(idx: usize) -> u64 {
+            if idx < NR_SUB_BUCKETS {
+                return idx as u64;
+            }
+            let shift = (idx / NR_SUB_BUCKETS - 1) as u32;
+            let sub_idx = (idx % NR_SUB_BUCKETS + NR_SUB_BUCKETS) as u64;
+            ((sub_idx + 1) << shift) - 1
+        }
+    }
+
+    /// A point-in-time copy of a `LatencyRecorder`.
+    #[derive(Clone, Debug, Default)]
+    pub struct LatencySnapshot {
+        buckets: Vec<u64>,
+        max_ns: u64,
+        total_bytes: usize,
+    }
+
+    impl LatencySnapshot {
+        /// Returns the number of recorded operations.
+        pub fn count(&self) -> u64 {
+            self.buckets.iter().sum()
+        }
+
+        pub fn total_bytes(&self) -> usize {
+            self.total_bytes
+        }
+
+        pub fn max(&self) -> Duration {
+            Duration::from_nanos(self.max_ns)
+        }
+
+        /// Returns the latency at the given percentile (e.g., 99.9).
+        pub fn percentile(&self, percentile: f64) -> Duration {
+            let count = self.count();
+            if count == 0 {
+                return Duration::ZERO;
+            }
+            let target = ((count as f64 * percentile / 100.0).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (idx, nops) in self.buckets.iter().enumerate() {
+                seen += nops;
+                if seen >= target {
+                    let upper_bound = LatencyRecorder::bucket_upper_bound(idx);
+                    return Duration::from_nanos(upper_bound.min(self.max_ns));
+                }
+            }
+            self.max()
+        }
+
+        /// Returns the operations recorded after `earlier` was taken.
+        /// The maximum latency is approximated by the highest bucket.
+        pub fn sub(&self, earlier: &LatencySnapshot) -> LatencySnapshot {
+            let buckets: Vec<u64> = self
+                .buckets
+                .iter()
+                .enumerate()
+                .map(|(idx, nops)| nops - earlier.buckets.get(idx).unwrap_or(&0))
+                .collect();
+            let max_ns = buckets
+                .iter()
+                .rposition(|nops| *nops > 0)
+                .map(|idx| LatencyRecorder::bucket_upper_bound(idx).min(self.max_ns))
+                .unwrap_or(0);
+            LatencySnapshot {
+                buckets,
+                max_ns,
+                total_bytes: self.total_bytes - earlier.total_bytes,
+            }
+        }
+    }
+
+    /// The deadline of the running benchmark in nanoseconds since `DEADLINE_EPOCH`,
+    /// zero if it has no limited runtime. It's checked on every I/O, thus kept
+    /// in an atomic rather than behind a lock.
+    static DEADLINE_NS: AtomicU64 = AtomicU64::new(0);
+    static DEADLINE_EPOCH: OnceLock<Instant> = OnceLock::new();
+
+    pub fn set_deadline(deadline: Option<Instant>) {
+        let epoch = *DEADLINE_EPOCH.get_or_init(Instant::now);
+        let deadline_ns = deadline.map_or(0, |deadline| {
+            (deadline.saturating_duration_since(epoch).as_nanos() as u64).max(1)
+        });
+        DEADLINE_NS.store(deadline_ns, Ordering::Relaxed);
+    }
+
+    /// Returns whether the running benchmark should stop due to its runtime limit.
+    pub fn deadline_reached() -> bool {
+        let deadline_ns = DEADLINE_NS.load(Ordering::Relaxed);
+        if deadline_ns == 0 {
+            return false;
+        }
+        let epoch = *DEADLINE_EPOCH.get().unwrap();
+        epoch.elapsed().as_nanos() as u64 >= deadline_ns
+    }
+
+    /// Dump the per-interval throughput and latency time series as CSV.
+    pub fn dump_latency_csv(
+        path: &str,
+        series: &[(Duration, LatencySnapshot)],
+    ) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let mut file = std::fs::File::create(path)?;
+        writeln!(
+            file,
+            "elapsed_sec,ops,bytes,p50_us,p95_us,p99_us,p999_us,max_us"
+        )?;
+        for (elapsed, snapshot) in series {
+            writeln!(
+                file,
+                "{:.3},{},{},{},{},{},{},{}",
+                elapsed.as_secs_f64(),
+                snapshot.count(),
+                snapshot.total_bytes(),
+                snapshot.percentile(50.0).as_micros(),
+                snapshot.percentile(95.0).as_micros(),
+                snapshot.percentile(99.0).as_micros(),
+                snapshot.percentile(99.9).as_micros(),
+                snapshot.max().as_micros(),
+            )?;
+        }
+        Ok(())
+    }
 }
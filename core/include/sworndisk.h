@@ -0,0 +1,48 @@
+/*
+ * The C ABI of SwornDisk, exported by the `sworndisk-v2` crate with the `linux`
+ * feature. See `src/ffi.rs` for the details.
+ *
+ * All addresses and lengths are in units of 4 KiB blocks. The functions
+ * returning int return zero on success, or a negated errno on failure.
+ */
+#ifndef _SWORNDISK_H
+#define _SWORNDISK_H
+
+#ifdef __KERNEL__
+#include <linux/types.h>
+#else
+#include <stdint.h>
+typedef uint8_t u8;
+typedef uint64_t u64;
+#endif
+
+#define SWORNDISK_BLOCK_SIZE 4096
+#define SWORNDISK_KEY_SIZE 16
+
+/* The callbacks of the underlying disk, which may be called concurrently. */
+struct sworndisk_ops {
+	void *ctx;
+	u64 nblocks;
+	int (*read)(void *ctx, u64 pos, u8 *buf, u64 nblocks);
+	int (*write)(void *ctx, u64 pos, const u8 *buf, u64 nblocks);
+	int (*flush)(void *ctx);
+};
+
+/* The opaque handle of a SwornDisk. */
+struct sworndisk;
+
+int sworndisk_create(const struct sworndisk_ops *ops, const u8 *root_key,
+		     struct sworndisk **handle);
+int sworndisk_open(const struct sworndisk_ops *ops, const u8 *root_key,
+		   struct sworndisk **handle);
+void sworndisk_close(struct sworndisk *handle);
+
+u64 sworndisk_total_blocks(const struct sworndisk *handle);
+int sworndisk_read(const struct sworndisk *handle, u64 lba, u8 *buf,
+		   u64 nblocks);
+int sworndisk_write(const struct sworndisk *handle, u64 lba, const u8 *buf,
+		    u64 nblocks);
+int sworndisk_sync(const struct sworndisk *handle);
+int sworndisk_discard(const struct sworndisk *handle, u64 lba, u64 nblocks);
+
+#endif /* _SWORNDISK_H */
@@ -0,0 +1,148 @@
+//! Audits the integrity of a `SwornDisk` image, and prints the report as JSON.
+//!
+//! ```text
+//! sworndisk-audit <IMAGE> <ROOT_KEY>
+//! ```
+//!
+//! The root key is given in hex. The image is opened read-only and never
+//! modified, every written block is authenticated (see `SwornDisk::audit()`).
+//! Exits with 0 if the image passes the audit, 1 if not, or 2 if the image
+//! can't be opened (e.g., with a wrong root key).
+use std::fs::File;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use sworndisk_v2::{
+    AeadKey, AuditReport, BlockId, BlockSet, BufMut, BufRef, Config, Errno, Error, SwornDisk,
+    BLOCK_SIZE,
+};
+
+type Result<T> = core::result::Result<T, Error>;
+
+/// An image file as a read-only `BlockSet`.
+#[derive(Clone)]
+struct ImageDisk {
+    file: Arc<File>,
+    range: Range<BlockId>,
+}
+
+impl ImageDisk {
+    fn open(path: &str) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let nblocks = file.metadata()?.len() as usize / BLOCK_SIZE;
+        Ok(Self {
+            file: Arc::new(file),
+            range: 0..nblocks,
+        })
+    }
+}
+
+impl BlockSet for ImageDisk {
+    fn read(&self, pos: BlockId, mut buf: BufMut) -> Result<()> {
+        if pos + buf.nblocks() > self.range.len() {
+            return Err(Error::with_msg(
+                Errno::InvalidArgs,
+                "read position is out of range",
+            ));
+        }
+        let offset = (self.range.start + pos) * BLOCK_SIZE;
+        self.file
+            .read_exact_at(buf.as_mut_slice(), offset as u64)
+            .map_err(|_| Error::with_msg(Errno::IoFailed, "image read failed"))
+    }
+
+    fn write(&self, _pos: BlockId, _buf: BufRef) -> Result<()> {
+        Err(Error::with_msg(
+            Errno::PermissionDenied,
+            "image is opened read-only",
+        ))
+    }
+
+    fn subset(&self, range: Range<BlockId>) -> Result<Self> {
+        if self.range.start + range.end > self.range.end {
+            return Err(Error::with_msg(
+                Errno::InvalidArgs,
+                "subset is out of range",
+            ));
+        }
+        Ok(Self {
+            file: self.file.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn nblocks(&self) -> usize {
+        self.range.len()
+    }
+}
+
+fn parse_key(hex: &str) -> Option<AeadKey> {
+    let mut key = AeadKey::default();
+    if hex.len() != key.len() * 2 {
+        return None;
+    }
+    for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(key)
+}
+
+fn audit(disk: ImageDisk, root_key: AeadKey) -> Result<AuditReport> {
+    // Open with GC if the disk is created with it, so that the records of
+    // the blocks freed by GC are dropped on recovery as in normal opens
+    let config = Config {
+        enable_gc: true,
+        ..Default::default()
+    };
+    let sworndisk = match SwornDisk::open_readonly(disk.clone(), root_key, None, Some(config)) {
+        Err(e) if e.errno() == Errno::InvalidArgs => {
+            SwornDisk::open_readonly(disk, root_key, None, None)?
+        }
+        res => res?,
+    };
+    sworndisk.audit()
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <IMAGE> <ROOT_KEY>", args[0]);
+        return ExitCode::from(2);
+    }
+    let Some(root_key) = parse_key(&args[2]) else {
+        eprintln!(
+            "invalid root key: expect {} hex digits",
+            AeadKey::default().len() * 2
+        );
+        return ExitCode::from(2);
+    };
+    let disk = match ImageDisk::open(&args[1]) {
+        Ok(disk) => disk,
+        Err(e) => {
+            eprintln!("failed to open {}: {e}", args[1]);
+            return ExitCode::from(2);
+        }
+    };
+
+    let report = match audit(disk, root_key) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("failed to audit {}: {e}", args[1]);
+            return ExitCode::from(2);
+        }
+    };
+    let mut json = String::new();
+    report.print_json(&mut json).unwrap();
+    print!("{json}");
+    if report.is_clean() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
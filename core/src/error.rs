@@ -13,6 +13,8 @@ pub enum Errno {
     OutOfMemory,
     /// Out of disk space.
     OutOfDisk,
+    /// No space left for user data.
+    NoSpaceLeft,
     /// IO error.
     IoFailed,
     /// Permission denied.
@@ -31,6 +33,10 @@ pub enum Errno {
     NotBlockSizeAligned,
     /// Try lock failed.
     TryLockFailed,
+    /// Timed out.
+    Timeout,
+    /// Rolled back to an older state than the trusted one.
+    RolledBack,
 }
 
 /// The error with an error type and an error message used in this crate.
@@ -0,0 +1,325 @@
+//! The C ABI of `SwornDisk`, for the Linux helpers written in C (e.g., a
+//! device mapper target or a ublk server) to link against this crate.
+//!
+//! A `SwornDisk` is referred by an opaque `SwornDiskHandle`. The underlying
+//! untrusted disk is provided by the caller as the callbacks in `SwornDiskOps`.
+//! The functions return zero on success, or a negated errno (e.g., `-EINVAL`)
+//! on failure. See `include/sworndisk.h` for the C declarations.
+//!
+//! All addresses and lengths are in units of `BLOCK_SIZE` (4 KiB) blocks.
+use crate::layers::bio::{BlockId, BlockSet, BufMut, BufRef, BLOCK_SIZE};
+use crate::layers::disk::SwornDisk;
+use crate::os::{AeadKey as Key, Box};
+use crate::prelude::*;
+use crate::Errno;
+
+use core::ffi::{c_int, c_void};
+use core::mem::size_of;
+use core::ops::Range;
+use core::slice;
+use pod::Pod;
+
+/// The callbacks of the underlying disk, provided by the caller.
+///
+/// The callbacks may be called concurrently from multiple threads, and
+/// must return zero on success, or a nonzero value on failure.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SwornDiskOps {
+    /// The opaque context passed to each callback.
+    pub ctx: *mut c_void,
+    /// The number of blocks of the underlying disk.
+    pub nblocks: u64,
+    /// Reads `nblocks` blocks at the block `pos` into `buf`.
+    pub read: Option<
+        unsafe extern "C" fn(ctx: *mut c_void, pos: u64, buf: *mut u8, nblocks: u64) -> c_int,
+    >,
+    /// Writes `nblocks` blocks in `buf` at the block `pos`.
+    pub write: Option<
+        unsafe extern "C" fn(ctx: *mut c_void, pos: u64, buf: *const u8, nblocks: u64) -> c_int,
+    >,
+    /// Persists the written blocks.
+    pub flush: Option<unsafe extern "C" fn(ctx: *mut c_void) -> c_int>,
+}
+
+/// The opaque handle of a `SwornDisk` on the disk of `SwornDiskOps`.
+pub struct SwornDiskHandle {
+    disk: SwornDisk<CDisk>,
+}
+
+/// A `BlockSet` backed by the callbacks in `SwornDiskOps`.
+struct CDisk {
+    ops: SwornDiskOps,
+    region: Range<BlockId>,
+}
+
+// SAFETY: The callbacks are required to be thread-safe, see `SwornDiskOps`.
+unsafe impl Send for CDisk {}
+unsafe impl Sync for CDisk {}
+
+impl CDisk {
+    fn new(ops: SwornDiskOps) -> Result<Self> {
+        if ops.read.is_none() || ops.write.is_none() || ops.flush.is_none() {
+            return_errno_with_msg!(InvalidArgs, "callbacks of disk ops are missing");
+        }
+        Ok(Self {
+            region: 0..ops.nblocks as BlockId,
+            ops,
+        })
+    }
+
+    fn check_range(&self, pos: BlockId, nblocks: usize) -> Result<()> {
+        if self.region.start + pos + nblocks > self.region.end {
+            return_errno_with_msg!(InvalidArgs, "access is out of range of disk ops");
+        }
+        Ok(())
+    }
+}
+
+impl BlockSet for CDisk {
+    fn read(&self, pos: BlockId, mut buf: BufMut) -> Result<()> {
+        self.check_range(pos, buf.nblocks())?;
+        let nblocks = buf.nblocks() as u64;
+        // SAFETY: The buffer is valid for `nblocks` blocks, and the callback
+        // is checked to be non-null on creation.
+        let ret = unsafe {
+            (self.ops.read.unwrap())(
+                self.ops.ctx,
+                (self.region.start + pos) as u64,
+                buf.as_mut_slice().as_mut_ptr(),
+                nblocks,
+            )
+        };
+        if ret != 0 {
+            return_errno_with_msg!(IoFailed, "read callback of disk ops failed");
+        }
+        Ok(())
+    }
+
+    fn write(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+        self.check_range(pos, buf.nblocks())?;
+        // SAFETY: The same as `read()`.
+        let ret = unsafe {
+            (self.ops.write.unwrap())(
+                self.ops.ctx,
+                (self.region.start + pos) as u64,
+                buf.as_slice().as_ptr(),
+                buf.nblocks() as u64,
+            )
+        };
+        if ret != 0 {
+            return_errno_with_msg!(IoFailed, "write callback of disk ops failed");
+        }
+        Ok(())
+    }
+
+    fn subset(&self, range: Range<BlockId>) -> Result<Self> {
+        self.check_range(range.start, range.len())?;
+        Ok(Self {
+            ops: self.ops,
+            region: self.region.start + range.start..self.region.start + range.end,
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        // SAFETY: The callback is checked to be non-null on creation.
+        let ret = unsafe { (self.ops.flush.unwrap())(self.ops.ctx) };
+        if ret != 0 {
+            return_errno_with_msg!(IoFailed, "flush callback of disk ops failed");
+        }
+        Ok(())
+    }
+
+    fn nblocks(&self) -> usize {
+        self.region.len()
+    }
+}
+
+/// The errno values of Linux.
+const ENOENT: c_int = 2;
+const EIO: c_int = 5;
+const EAGAIN: c_int = 11;
+const ENOMEM: c_int = 12;
+const EACCES: c_int = 13;
+const EBUSY: c_int = 16;
+const EINVAL: c_int = 22;
+const ENOSPC: c_int = 28;
+const EILSEQ: c_int = 84;
+const EOPNOTSUPP: c_int = 95;
+const ETIMEDOUT: c_int = 110;
+const ESTALE: c_int = 116;
+
+/// Converts an `Errno` to the errno of Linux.
+pub fn to_linux_errno(errno: Errno) -> c_int {
+    match errno {
+        Errno::TxAborted => EAGAIN,
+        Errno::NotFound => ENOENT,
+        Errno::InvalidArgs | Errno::NotBlockSizeAligned => EINVAL,
+        Errno::OutOfMemory => ENOMEM,
+        Errno::OutOfDisk | Errno::NoSpaceLeft => ENOSPC,
+        Errno::IoFailed | Errno::OsSpecUnknown | Errno::EncryptFailed => EIO,
+        Errno::PermissionDenied => EACCES,
+        Errno::Unsupported => EOPNOTSUPP,
+        // The same as dm-integrity on integrity check failures
+        Errno::DecryptFailed | Errno::MacMismatched => EILSEQ,
+        Errno::TryLockFailed => EBUSY,
+        Errno::Timeout => ETIMEDOUT,
+        Errno::RolledBack => ESTALE,
+    }
+}
+
+fn to_ret(res: Result<()>) -> c_int {
+    match res {
+        Ok(()) => 0,
+        Err(e) => -to_linux_errno(e.errno()),
+    }
+}
+
+/// Creates or opens a `SwornDisk`, stores its handle to `handle` on success.
+///
+/// # Safety
+///
+/// `ops` and `handle` must be valid, `root_key` must point to a 16-byte key.
+unsafe fn new_handle(
+    ops: *const SwornDiskOps,
+    root_key: *const u8,
+    handle: *mut *mut SwornDiskHandle,
+    new_disk: impl FnOnce(CDisk, Key) -> Result<SwornDisk<CDisk>>,
+) -> c_int {
+    if ops.is_null() || root_key.is_null() || handle.is_null() {
+        return -EINVAL;
+    }
+    let res = CDisk::new(*ops).and_then(|disk| {
+        let root_key = Key::from_bytes(slice::from_raw_parts(root_key, size_of::<Key>()));
+        new_disk(disk, root_key)
+    });
+    match res {
+        Ok(disk) => {
+            *handle = Box::into_raw(Box::new(SwornDiskHandle { disk }));
+            0
+        }
+        Err(e) => -to_linux_errno(e.errno()),
+    }
+}
+
+/// Creates a new `SwornDisk` on the disk of `ops` with the root key,
+/// with the default configuration.
+///
+/// # Safety
+///
+/// `ops` and `handle` must be valid, `root_key` must point to a 16-byte key.
+#[no_mangle]
+pub unsafe extern "C" fn sworndisk_create(
+    ops: *const SwornDiskOps,
+    root_key: *const u8,
+    handle: *mut *mut SwornDiskHandle,
+) -> c_int {
+    new_handle(ops, root_key, handle, |disk, root_key| {
+        SwornDisk::create(disk, root_key, None, None)
+    })
+}
+
+/// Opens an existing `SwornDisk` on the disk of `ops` with the root key,
+/// with the default configuration.
+///
+/// # Safety
+///
+/// `ops` and `handle` must be valid, `root_key` must point to a 16-byte key.
+#[no_mangle]
+pub unsafe extern "C" fn sworndisk_open(
+    ops: *const SwornDiskOps,
+    root_key: *const u8,
+    handle: *mut *mut SwornDiskHandle,
+) -> c_int {
+    new_handle(ops, root_key, handle, |disk, root_key| {
+        SwornDisk::open(disk, root_key, None, None)
+    })
+}
+
+/// Closes the `SwornDisk`, the handle is invalid afterwards.
+///
+/// The buffered blocks are not synced, call `sworndisk_sync()` beforehand
+/// for durability.
+///
+/// # Safety
+///
+/// `handle` must be returned by `sworndisk_create()` or `sworndisk_open()`,
+/// and not used by any other thread.
+#[no_mangle]
+pub unsafe extern "C" fn sworndisk_close(handle: *mut SwornDiskHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Returns the number of blocks of the `SwornDisk`.
+///
+/// # Safety
+///
+/// `handle` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn sworndisk_total_blocks(handle: *const SwornDiskHandle) -> u64 {
+    (*handle).disk.total_blocks() as u64
+}
+
+/// Reads `nblocks` blocks at `lba` into `buf`.
+///
+/// # Safety
+///
+/// `handle` must be valid, `buf` must be valid for `nblocks` blocks.
+#[no_mangle]
+pub unsafe extern "C" fn sworndisk_read(
+    handle: *const SwornDiskHandle,
+    lba: u64,
+    buf: *mut u8,
+    nblocks: u64,
+) -> c_int {
+    if buf.is_null() {
+        return -EINVAL;
+    }
+    let buf = slice::from_raw_parts_mut(buf, nblocks as usize * BLOCK_SIZE);
+    to_ret(BufMut::try_from(buf).and_then(|buf| (*handle).disk.read(lba as _, buf)))
+}
+
+/// Writes `nblocks` blocks in `buf` at `lba`.
+///
+/// # Safety
+///
+/// `handle` must be valid, `buf` must be valid for `nblocks` blocks.
+#[no_mangle]
+pub unsafe extern "C" fn sworndisk_write(
+    handle: *const SwornDiskHandle,
+    lba: u64,
+    buf: *const u8,
+    nblocks: u64,
+) -> c_int {
+    if buf.is_null() {
+        return -EINVAL;
+    }
+    let buf = slice::from_raw_parts(buf, nblocks as usize * BLOCK_SIZE);
+    to_ret(BufRef::try_from(buf).and_then(|buf| (*handle).disk.write(lba as _, buf)))
+}
+
+/// Syncs all the written blocks to the underlying disk for durability.
+///
+/// # Safety
+///
+/// `handle` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn sworndisk_sync(handle: *const SwornDiskHandle) -> c_int {
+    to_ret((*handle).disk.sync())
+}
+
+/// Discards `nblocks` blocks at `lba`, which are read as zeros afterwards.
+///
+/// # Safety
+///
+/// `handle` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn sworndisk_discard(
+    handle: *const SwornDiskHandle,
+    lba: u64,
+    nblocks: u64,
+) -> c_int {
+    to_ret((*handle).disk.discard(lba as _, nblocks as _))
+}
@@ -0,0 +1,22 @@
+use super::{BlockId, Buf};
+use crate::prelude::*;
+
+/// The asynchronous counterpart of `BlockSet`.
+///
+/// Unlike `BlockSet`, buffers are passed by value and handed back on
+/// completion. Thus a buffer always outlives the I/O on it, even if the
+/// future is dropped before completion.
+#[allow(async_fn_in_trait)]
+pub trait AsyncBlockSet: Sync + Send {
+    /// Read blocks at a specified position into the buffer.
+    async fn read(&self, pos: BlockId, buf: Buf) -> Result<Buf>;
+
+    /// Write the blocks in the buffer at a specified position.
+    async fn write(&self, pos: BlockId, buf: Buf) -> Result<Buf>;
+
+    /// Ensure that blocks are persisted to the disk.
+    async fn flush(&self) -> Result<()>;
+
+    /// Returns the number of blocks.
+    fn nblocks(&self) -> usize;
+}
@@ -1,9 +1,10 @@
 use super::{Buf, BufMut, BufRef};
 use crate::error::Errno;
-use crate::os::Mutex;
+use crate::os::{sleep, Mutex, Rng, RwLock};
 use crate::prelude::*;
 
 use core::ops::Range;
+use core::time::Duration;
 use inherit_methods_macro::inherit_methods;
 
 /// A fixed set of data blocks that can support random reads and writes.
@@ -73,6 +74,21 @@ pub trait BlockSet: Sync + Send {
         Ok(())
     }
 
+    /// Read one or multiple blocks at a specified position from a redundant
+    /// copy, without touching the primary copy.
+    ///
+    /// This is called when the blocks returned by `read()` fail integrity
+    /// checks. A block set without redundancy returns an error.
+    fn read_redundant(&self, _pos: BlockId, _buf: BufMut) -> Result<()> {
+        return_errno_with_msg!(Errno::NotFound, "no redundant copy to read from");
+    }
+
+    /// Repair the primary copy with the blocks read by `read_redundant()`,
+    /// which must have passed the integrity checks of the caller.
+    fn repair(&self, _pos: BlockId, _buf: BufRef) -> Result<()> {
+        return_errno_with_msg!(Errno::NotFound, "no redundant copy to repair from");
+    }
+
     /// Get a subset of the blocks in the block set.
     fn subset(&self, range: Range<BlockId>) -> Result<Self>
     where
@@ -91,6 +107,8 @@ macro_rules! impl_blockset_for {
         impl<T: BlockSet> BlockSet for $typ {
             fn read(&self, pos: BlockId, buf: BufMut) -> Result<()>;
             fn read_slice(&self, offset: usize, buf: &mut [u8]) -> Result<()>;
+            fn read_redundant(&self, pos: BlockId, buf: BufMut) -> Result<()>;
+            fn repair(&self, pos: BlockId, buf: BufRef) -> Result<()>;
             fn write(&self, pos: BlockId, buf: BufRef) -> Result<()>;
             fn write_slice(&self, offset: usize, buf: &[u8]) -> Result<()>;
             fn flush(&self) -> Result<()>;
@@ -124,16 +142,60 @@ impl_blockset_for!(Arc<T>, "(**self)", |this: &Arc<T>, range| {
 
 /// A disk that impl `BlockSet`.
 ///
-/// The `region` is the accessible subset.
+/// The `region` is the accessible subset. The behavior of a real device
+/// (latency, bandwidth and failures) can be emulated with a `MemDiskProfile`,
+/// which is shared by all the subsets.
 #[derive(Clone)]
 pub struct MemDisk {
     disk: Arc<Mutex<Buf>>,
     region: Range<BlockId>,
+    profile: Arc<RwLock<MemDiskProfile>>,
+}
+
+/// The emulated device behavior of a `MemDisk`.
+///
+/// The default profile is a perfect device that completes every request
+/// instantly.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MemDiskProfile {
+    /// The fixed latency of each read request.
+    pub read_latency: Duration,
+    /// The fixed latency of each write request.
+    pub write_latency: Duration,
+    /// The latency of each flush request.
+    pub flush_latency: Duration,
+    /// The bandwidth in bytes per second, which adds a transfer time
+    /// proportional to the size of a read or write. Zero means unlimited.
+    pub bandwidth: usize,
+    /// The probability (in `[0.0, 1.0]`) that a read request fails.
+    pub read_error_rate: f64,
+    /// The probability (in `[0.0, 1.0]`) that a write request fails.
+    /// A failed write leaves the blocks untouched.
+    pub write_error_rate: f64,
+    /// The probability (in `[0.0, 1.0]`) that a flush request fails.
+    pub flush_error_rate: f64,
+}
+
+impl MemDiskProfile {
+    /// Returns the time to transfer the number of blocks under the bandwidth cap.
+    fn transfer_time(&self, nblocks: usize) -> Duration {
+        if self.bandwidth == 0 {
+            return Duration::ZERO;
+        }
+        let nbytes = (nblocks * BLOCK_SIZE) as u128;
+        Duration::from_nanos((nbytes * 1_000_000_000 / self.bandwidth as u128) as u64)
+    }
 }
 
 impl MemDisk {
     /// Create a `MemDisk` with the number of blocks.
     pub fn create(num_blocks: usize) -> Result<Self> {
+        Self::create_with_profile(num_blocks, MemDiskProfile::default())
+    }
+
+    /// Create a `MemDisk` with the number of blocks, which emulates the device
+    /// behavior described by `profile`.
+    pub fn create_with_profile(num_blocks: usize, profile: MemDiskProfile) -> Result<Self> {
         let blocks = Buf::alloc(num_blocks)?;
         Ok(Self {
             disk: Arc::new(Mutex::new(blocks)),
@@ -141,8 +203,38 @@ impl MemDisk {
                 start: 0,
                 end: num_blocks,
             },
+            profile: Arc::new(RwLock::new(profile)),
         })
     }
+
+    /// Returns the device profile.
+    pub fn profile(&self) -> MemDiskProfile {
+        *self.profile.read()
+    }
+
+    /// Replaces the device profile, which also takes effect on all subsets
+    /// of the disk, e.g., to inject failures after the disk is formatted.
+    pub fn set_profile(&self, profile: MemDiskProfile) {
+        *self.profile.write() = profile;
+    }
+
+    /// Emulates the latency and the failure of a request on the device.
+    fn emulate(&self, latency: Duration, error_rate: f64, op: &'static str) -> Result<()> {
+        if !latency.is_zero() {
+            sleep(latency);
+        }
+        if error_rate > 0.0 && Self::random_ratio()? < error_rate {
+            return_errno_with_msg!(Errno::IoFailed, op);
+        }
+        Ok(())
+    }
+
+    /// Returns a random ratio in `[0.0, 1.0)`.
+    fn random_ratio() -> Result<f64> {
+        let mut bytes = [0u8; 8];
+        Rng::new(&[]).fill_bytes(&mut bytes)?;
+        Ok((u64::from_le_bytes(bytes) >> 11) as f64 / (1u64 << 53) as f64)
+    }
 }
 
 impl BlockSet for MemDisk {
@@ -150,6 +242,12 @@ impl BlockSet for MemDisk {
         if pos + buf.nblocks() > self.region.end {
             return_errno_with_msg!(Errno::InvalidArgs, "read position is out of range");
         }
+        let profile = self.profile();
+        self.emulate(
+            profile.read_latency + profile.transfer_time(buf.nblocks()),
+            profile.read_error_rate,
+            "injected read failure",
+        )?;
         let offset = (self.region.start + pos) * BLOCK_SIZE;
         let buf_len = buf.as_slice().len();
 
@@ -163,6 +261,12 @@ impl BlockSet for MemDisk {
         if pos + buf.nblocks() > self.region.end {
             return_errno_with_msg!(Errno::InvalidArgs, "write position is out of range");
         }
+        let profile = self.profile();
+        self.emulate(
+            profile.write_latency + profile.transfer_time(buf.nblocks()),
+            profile.write_error_rate,
+            "injected write failure",
+        )?;
         let offset = (self.region.start + pos) * BLOCK_SIZE;
         let buf_len = buf.as_slice().len();
 
@@ -182,11 +286,17 @@ impl BlockSet for MemDisk {
                 start: self.region.start + range.start,
                 end: self.region.start + range.end,
             },
+            profile: self.profile.clone(),
         })
     }
 
     fn flush(&self) -> Result<()> {
-        Ok(())
+        let profile = self.profile();
+        self.emulate(
+            profile.flush_latency,
+            profile.flush_error_rate,
+            "injected flush failure",
+        )
     }
 
     fn nblocks(&self) -> usize {
@@ -196,8 +306,10 @@ impl BlockSet for MemDisk {
 
 #[cfg(test)]
 mod tests {
-    use crate::layers::bio::{BlockSet, Buf, MemDisk};
+    use crate::layers::bio::{BlockSet, Buf, MemDisk, MemDiskProfile};
+    use crate::Errno;
     use core::ops::Range;
+    use core::time::Duration;
 
     #[test]
     fn mem_disk() {
@@ -222,4 +334,39 @@ mod tests {
         subset.read_slice(4096 - 8, &mut buf).unwrap();
         assert_eq!(buf, [1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 0, 0, 0]);
     }
+
+    #[test]
+    fn mem_disk_profile() {
+        let disk = MemDisk::create(16).unwrap();
+        let subset = disk.subset(Range { start: 8, end: 16 }).unwrap();
+        let mut buf = Buf::alloc(4).unwrap();
+        buf.as_mut_slice().fill(1);
+
+        // Failures are injected to all subsets
+        disk.set_profile(MemDiskProfile {
+            write_error_rate: 1.0,
+            flush_error_rate: 1.0,
+            ..Default::default()
+        });
+        let err = subset.write(0, buf.as_ref()).unwrap_err();
+        assert_eq!(err.errno(), Errno::IoFailed);
+        assert!(subset.flush().is_err());
+        subset.read(0, buf.as_mut()).unwrap();
+        assert_eq!(buf.as_slice(), [0u8; 4 * 4096]);
+
+        // Latency plus the transfer time of 4 blocks at 40 blocks per second
+        disk.set_profile(MemDiskProfile {
+            write_latency: Duration::from_millis(10),
+            bandwidth: 40 * 4096,
+            ..Default::default()
+        });
+        buf.as_mut_slice().fill(1);
+        let start = std::time::Instant::now();
+        subset.write(0, buf.as_ref()).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(110));
+        subset.flush().unwrap();
+
+        disk.read(8, buf.as_mut()).unwrap();
+        assert_eq!(buf.as_slice(), [1u8; 4 * 4096]);
+    }
 }
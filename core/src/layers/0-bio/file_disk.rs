@@ -0,0 +1,421 @@
+//! The block sets backed by files of the host, for `std` environments.
+//!
+//! A `FileDisk` reads and writes a file with positioned I/O, optionally with
+//! `O_DIRECT` to bypass the page cache of the host. A `MmapDisk` maps a file
+//! into memory instead, which saves a system call per I/O but leaves the
+//! write-back to the host until `flush()`. Both extend a file shorter than
+//! the requested capacity, so that an image can be reopened larger.
+use super::{BlockId, BlockSet, BufMut, BufRef, BLOCK_SIZE};
+use crate::os::RwLock;
+use crate::prelude::*;
+
+use core::ops::Range;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// The options to open a file as a `FileDisk` or a `MmapDisk`.
+#[derive(Clone, Debug, Default)]
+pub struct FileDiskOptions {
+    /// The number of blocks of the disk. A shorter file is extended (with
+    /// holes), while a longer one is covered only up to the capacity.
+    /// If `None`, the disk covers the whole existing file.
+    pub nblocks: Option<usize>,
+    /// Create the file if it doesn't exist.
+    pub create: bool,
+    /// Truncate the file before extending it, discarding the contents.
+    pub truncate: bool,
+    /// Bypass the page cache of the host with `O_DIRECT` (Linux only).
+    /// Ignored by `MmapDisk`.
+    pub direct_io: bool,
+    /// Remove the file once the disk and all its subsets are dropped,
+    /// e.g., for a scratch image.
+    pub remove_on_drop: bool,
+}
+
+impl FileDiskOptions {
+    /// The options to create an empty disk of `nblocks`.
+    pub fn create(nblocks: usize) -> Self {
+        Self {
+            nblocks: Some(nblocks),
+            create: true,
+            truncate: true,
+            ..Default::default()
+        }
+    }
+
+    fn open_file(&self, path: &Path, direct_io: bool) -> Result<(FileHandle, usize)> {
+        let mut options = OpenOptions::new();
+        options
+            .read(true)
+            .write(true)
+            .create(self.create)
+            .truncate(self.truncate);
+        if direct_io {
+            #[cfg(target_os = "linux")]
+            options.custom_flags(libc::O_DIRECT);
+            #[cfg(not(target_os = "linux"))]
+            return_errno_with_msg!(Unsupported, "direct I/O is only supported on Linux");
+        }
+        let file = options
+            .open(path)
+            .map_err(io_error(path, "failed to open the disk file"))?;
+        let handle = FileHandle {
+            file,
+            path: path.to_path_buf(),
+            remove_on_drop: self.remove_on_drop,
+        };
+
+        let len = handle
+            .file
+            .metadata()
+            .map_err(io_error(path, "failed to stat the disk file"))?
+            .len() as usize;
+        let nblocks = self.nblocks.unwrap_or(len / BLOCK_SIZE);
+        if nblocks == 0 {
+            return_errno_with_msg!(InvalidArgs, "the disk file has no blocks");
+        }
+        if len < nblocks * BLOCK_SIZE {
+            handle
+                .file
+                .set_len((nblocks * BLOCK_SIZE) as u64)
+                .map_err(io_error(path, "failed to extend the disk file"))?;
+        }
+        Ok((handle, nblocks))
+    }
+}
+
+/// An opened disk file, shared by a disk and its subsets.
+struct FileHandle {
+    file: File,
+    path: PathBuf,
+    remove_on_drop: bool,
+}
+
+impl Drop for FileHandle {
+    fn drop(&mut self) {
+        if self.remove_on_drop {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Maps an I/O error of the host to `IoFailed`, logging the details.
+fn io_error<'a>(path: &'a Path, msg: &'static str) -> impl FnOnce(io::Error) -> Error + 'a {
+    move |e| {
+        warn!("[FileDisk] {msg} {}: {e}", path.display());
+        Error::with_msg(IoFailed, msg)
+    }
+}
+
+/// Checks that the blocks `pos..pos + nblocks` lie in `range`, returns the
+/// byte offset of them in the file.
+fn offset_of(range: &Range<BlockId>, pos: BlockId, nblocks: usize) -> Result<usize> {
+    if pos + nblocks > range.len() {
+        return_errno_with_msg!(InvalidArgs, "the blocks are out of the disk");
+    }
+    Ok((range.start + pos) * BLOCK_SIZE)
+}
+
+fn subset_of(range: &Range<BlockId>, subset: Range<BlockId>) -> Result<Range<BlockId>> {
+    if subset.start > subset.end || subset.end > range.len() {
+        return_errno_with_msg!(InvalidArgs, "the subset is out of the disk");
+    }
+    Ok(range.start + subset.start..range.start + subset.end)
+}
+
+/// A disk backed by a file, accessed with positioned reads and writes.
+#[derive(Clone)]
+pub struct FileDisk {
+    handle: Arc<FileHandle>,
+    range: Range<BlockId>,
+}
+
+impl FileDisk {
+    /// Creates an empty disk of `nblocks` at `path`, truncating the file if
+    /// it exists.
+    pub fn create<P: AsRef<Path>>(path: P, nblocks: usize) -> Result<Self> {
+        Self::open_with(path, &FileDiskOptions::create(nblocks))
+    }
+
+    /// Opens an existing file as a disk covering the whole file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with(path, &FileDiskOptions::default())
+    }
+
+    /// Opens a file as a disk with the `options`.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &FileDiskOptions) -> Result<Self> {
+        let (handle, nblocks) = options.open_file(path.as_ref(), options.direct_io)?;
+        Ok(Self {
+            handle: Arc::new(handle),
+            range: 0..nblocks,
+        })
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.handle.path
+    }
+}
+
+impl BlockSet for FileDisk {
+    fn read(&self, pos: BlockId, mut buf: BufMut) -> Result<()> {
+        let offset = offset_of(&self.range, pos, buf.nblocks())?;
+        self.handle
+            .file
+            .read_exact_at(buf.as_mut_slice(), offset as u64)
+            .map_err(io_error(&self.handle.path, "file read failed"))
+    }
+
+    fn write(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+        let offset = offset_of(&self.range, pos, buf.nblocks())?;
+        self.handle
+            .file
+            .write_all_at(buf.as_slice(), offset as u64)
+            .map_err(io_error(&self.handle.path, "file write failed"))
+    }
+
+    fn subset(&self, range: Range<BlockId>) -> Result<Self> {
+        Ok(Self {
+            handle: self.handle.clone(),
+            range: subset_of(&self.range, range)?,
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.handle
+            .file
+            .sync_data()
+            .map_err(io_error(&self.handle.path, "file sync failed"))
+    }
+
+    fn nblocks(&self) -> usize {
+        self.range.len()
+    }
+}
+
+/// A disk backed by a file mapped into memory.
+///
+/// The writes reach the file when the host writes back the dirty pages, or
+/// at the latest on `flush()`. The file must not be shrunk by others while
+/// mapped, or the accesses beyond its end fault.
+#[derive(Clone)]
+pub struct MmapDisk {
+    mapping: Arc<Mapping>,
+    range: Range<BlockId>,
+}
+
+/// A shared mapping of a whole disk file.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+    // Guarantees the atomicity of reading and writing individual blocks
+    lock: RwLock<()>,
+    // Dropped after unmapping
+    handle: FileHandle,
+}
+
+// SAFETY: The mapping is owned by `Mapping` until dropped, and the accesses
+// through `ptr` are synchronized by `lock`.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn new(handle: FileHandle, nblocks: usize) -> Result<Self> {
+        let len = nblocks * BLOCK_SIZE;
+        // SAFETY: A new shared mapping of the file, which covers `len` bytes
+        // as extended on open.
+        let ptr = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                handle.file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io_error(&handle.path, "failed to map the disk file")(
+                io::Error::last_os_error(),
+            ));
+        }
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+            lock: RwLock::new(()),
+            handle,
+        })
+    }
+
+    fn slice(&self, offset: usize, len: usize) -> &[u8] {
+        debug_assert!(offset + len <= self.len);
+        // SAFETY: The range is checked to lie in the mapping.
+        unsafe { core::slice::from_raw_parts(self.ptr.add(offset), len) }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn slice_mut(&self, offset: usize, len: usize) -> &mut [u8] {
+        debug_assert!(offset + len <= self.len);
+        // SAFETY: The range is checked to lie in the mapping, and the writers
+        // are serialized by `lock`.
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.add(offset), len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `len` are from a successful `mmap()`.
+        unsafe {
+            libc::munmap(self.ptr as _, self.len);
+        }
+    }
+}
+
+impl MmapDisk {
+    /// Creates an empty disk of `nblocks` at `path`, truncating the file if
+    /// it exists.
+    pub fn create<P: AsRef<Path>>(path: P, nblocks: usize) -> Result<Self> {
+        Self::open_with(path, &FileDiskOptions::create(nblocks))
+    }
+
+    /// Opens an existing file as a disk covering the whole file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with(path, &FileDiskOptions::default())
+    }
+
+    /// Opens a file as a disk with the `options`, ignoring `direct_io`.
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &FileDiskOptions) -> Result<Self> {
+        let (handle, nblocks) = options.open_file(path.as_ref(), false)?;
+        Ok(Self {
+            mapping: Arc::new(Mapping::new(handle, nblocks)?),
+            range: 0..nblocks,
+        })
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.mapping.handle.path
+    }
+}
+
+impl BlockSet for MmapDisk {
+    fn read(&self, pos: BlockId, mut buf: BufMut) -> Result<()> {
+        let offset = offset_of(&self.range, pos, buf.nblocks())?;
+        let buf = buf.as_mut_slice();
+        let _guard = self.mapping.lock.read();
+        buf.copy_from_slice(self.mapping.slice(offset, buf.len()));
+        Ok(())
+    }
+
+    fn write(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+        let offset = offset_of(&self.range, pos, buf.nblocks())?;
+        let buf = buf.as_slice();
+        let _guard = self.mapping.lock.write();
+        self.mapping
+            .slice_mut(offset, buf.len())
+            .copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn subset(&self, range: Range<BlockId>) -> Result<Self> {
+        Ok(Self {
+            mapping: self.mapping.clone(),
+            range: subset_of(&self.range, range)?,
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mapping = &self.mapping;
+        // SAFETY: `ptr` and `len` are from a successful `mmap()`.
+        let res = unsafe { libc::msync(mapping.ptr as _, mapping.len, libc::MS_SYNC) };
+        if res != 0 {
+            return Err(io_error(&mapping.handle.path, "file sync failed")(
+                io::Error::last_os_error(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn nblocks(&self) -> usize {
+        self.range.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileDisk, FileDiskOptions, MmapDisk};
+    use crate::layers::bio::{BlockSet, Buf, BLOCK_SIZE};
+
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sworndisk-{}-{name}.image", std::process::id()))
+    }
+
+    fn check_disk<D: BlockSet>(disk: &D) {
+        let mut buf = Buf::alloc(2).unwrap();
+        buf.as_mut_slice()[..BLOCK_SIZE].fill(1);
+        buf.as_mut_slice()[BLOCK_SIZE..].fill(2);
+        disk.write(6, buf.as_ref()).unwrap();
+        disk.flush().unwrap();
+
+        let subset = disk.subset(7..8).unwrap();
+        let mut rbuf = Buf::alloc(1).unwrap();
+        subset.read(0, rbuf.as_mut()).unwrap();
+        assert_eq!(rbuf.as_slice(), &buf.as_slice()[BLOCK_SIZE..]);
+        assert!(subset.read(1, rbuf.as_mut()).is_err());
+        assert!(disk.write(7, buf.as_ref()).is_err());
+        assert!(disk.subset(4..9).is_err());
+    }
+
+    #[test]
+    fn file_disk() {
+        let path = temp_path("file-disk");
+        let disk = FileDisk::create(&path, 8).unwrap();
+        assert_eq!(disk.nblocks(), 8);
+        check_disk(&disk);
+        drop(disk);
+
+        // Extend on reopen, keeping the contents
+        let options = FileDiskOptions {
+            nblocks: Some(16),
+            remove_on_drop: true,
+            ..Default::default()
+        };
+        let disk = FileDisk::open_with(&path, &options).unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            16 * BLOCK_SIZE as u64
+        );
+        let mut rbuf = Buf::alloc(1).unwrap();
+        disk.read(6, rbuf.as_mut()).unwrap();
+        assert!(rbuf.as_slice().iter().all(|b| *b == 1));
+        disk.read(15, rbuf.as_mut()).unwrap();
+        assert!(rbuf.as_slice().iter().all(|b| *b == 0));
+
+        let subset = disk.subset(0..4).unwrap();
+        drop(disk);
+        assert!(path.exists());
+        drop(subset);
+        assert!(!path.exists());
+        assert!(FileDisk::open(&path).is_err());
+    }
+
+    #[test]
+    fn mmap_disk() {
+        let path = temp_path("mmap-disk");
+        let disk = MmapDisk::create(&path, 8).unwrap();
+        check_disk(&disk);
+        drop(disk);
+
+        let disk = FileDisk::open(&path).unwrap();
+        assert_eq!(disk.nblocks(), 8);
+        let mut rbuf = Buf::alloc(1).unwrap();
+        disk.read(7, rbuf.as_mut()).unwrap();
+        assert!(rbuf.as_slice().iter().all(|b| *b == 2));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use super::{BlockId, BlockSet, BufMut, BufRef};
+use crate::error::Errno;
+use crate::prelude::*;
+
+use core::ops::Range;
+
+/// A disk that mirrors every block onto two underlying block sets
+/// (RAID-1 style).
+///
+/// Writes go to both copies, while reads are served by the primary copy.
+/// When the blocks read from the primary copy are found corrupted by the
+/// upper layer (e.g., MAC verification fails), `read_redundant()` reads
+/// the blocks from the mirror copy, and once they pass the checks, `repair()`
+/// writes them back to the primary copy. A corrupted mirror copy never
+/// overwrites the primary copy.
+/// This tolerates corruption of either copy by the untrusted host, rather
+/// than just detecting it.
+#[derive(Clone)]
+pub struct MirroredDisk<D> {
+    primary: D,
+    mirror: D,
+}
+
+impl<D: BlockSet> MirroredDisk<D> {
+    /// Create a `MirroredDisk` over a primary disk and a mirror disk.
+    ///
+    /// The capacity of the `MirroredDisk` is that of the smaller disk.
+    pub fn new(primary: D, mirror: D) -> Result<Self> {
+        let nblocks = primary.nblocks().min(mirror.nblocks());
+        Ok(Self {
+            primary: primary.subset(0..nblocks)?,
+            mirror: mirror.subset(0..nblocks)?,
+        })
+    }
+
+    /// Returns the primary copy.
+    pub fn primary(&self) -> &D {
+        &self.primary
+    }
+
+    /// Returns the mirror copy.
+    pub fn mirror(&self) -> &D {
+        &self.mirror
+    }
+}
+
+impl<D: BlockSet> BlockSet for MirroredDisk<D> {
+    fn read(&self, pos: BlockId, mut buf: BufMut) -> Result<()> {
+        if self
+            .primary
+            .read(pos, BufMut::try_from(buf.as_mut_slice())?)
+            .is_ok()
+        {
+            return Ok(());
+        }
+
+        #[cfg(not(feature = "linux"))]
+        warn!("[MirroredDisk] read primary copy failed at {pos}, fall back to mirror copy");
+        self.mirror.read(pos, buf)
+    }
+
+    fn read_redundant(&self, pos: BlockId, buf: BufMut) -> Result<()> {
+        self.mirror.read(pos, buf)
+    }
+
+    fn repair(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+        self.primary.write(pos, buf)?;
+
+        #[cfg(not(feature = "linux"))]
+        warn!(
+            "[MirroredDisk] repaired {} blocks of primary copy at {pos}",
+            buf.nblocks()
+        );
+        Ok(())
+    }
+
+    fn write(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+        self.primary.write(pos, buf)?;
+        self.mirror.write(pos, buf)
+    }
+
+    fn subset(&self, range: Range<BlockId>) -> Result<Self> {
+        Ok(MirroredDisk {
+            primary: self.primary.subset(range.clone())?,
+            mirror: self.mirror.subset(range)?,
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.primary.flush()?;
+        self.mirror.flush()
+    }
+
+    fn nblocks(&self) -> usize {
+        self.primary.nblocks()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MirroredDisk;
+    use crate::layers::bio::{BlockSet, Buf, MemDisk, BLOCK_SIZE};
+
+    #[test]
+    fn mirrored_disk() {
+        let primary = MemDisk::create(64).unwrap();
+        let mirror = MemDisk::create(64).unwrap();
+        let disk = MirroredDisk::new(primary.clone(), mirror.clone()).unwrap();
+        assert_eq!(disk.nblocks(), 64);
+
+        let mut buf = Buf::alloc(2).unwrap();
+        buf.as_mut_slice().fill(1);
+        disk.write(8, buf.as_ref()).unwrap();
+
+        // Corrupt the primary copy
+        let mut corrupted = Buf::alloc(1).unwrap();
+        corrupted.as_mut_slice().fill(0xff);
+        primary.write(9, corrupted.as_ref()).unwrap();
+
+        let mut rbuf = Buf::alloc(2).unwrap();
+        disk.read(8, rbuf.as_mut()).unwrap();
+        assert_eq!(rbuf.as_slice()[BLOCK_SIZE], 0xff);
+
+        // Read from the mirror copy, the primary copy is intact until repaired
+        disk.read_redundant(8, rbuf.as_mut()).unwrap();
+        assert_eq!(rbuf.as_slice(), buf.as_slice());
+        primary.read(9, corrupted.as_mut()).unwrap();
+        assert_eq!(corrupted.as_slice()[0], 0xff);
+        disk.repair(8, rbuf.as_ref()).unwrap();
+        primary.read(8, rbuf.as_mut()).unwrap();
+        assert_eq!(rbuf.as_slice(), buf.as_slice());
+    }
+}
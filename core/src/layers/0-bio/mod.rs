@@ -2,17 +2,36 @@
 
 use static_assertions::assert_eq_size;
 
+#[cfg(feature = "async")]
+mod async_block_set;
 mod block_buf;
 mod block_log;
 mod block_ring;
 mod block_set;
+#[cfg(all(feature = "std", unix))]
+mod file_disk;
+mod mirrored_disk;
+mod overlay_disk;
+mod striped_disk;
 
+#[cfg(feature = "async")]
+pub use self::async_block_set::AsyncBlockSet;
 pub use self::block_buf::{Buf, BufMut, BufRef};
 pub use self::block_log::{BlockLog, MemLog};
 pub use self::block_ring::BlockRing;
-pub use self::block_set::{BlockSet, MemDisk};
+pub use self::block_set::{BlockSet, MemDisk, MemDiskProfile};
+#[cfg(all(feature = "std", unix))]
+pub use self::file_disk::{FileDisk, FileDiskOptions, MmapDisk};
+pub use self::mirrored_disk::MirroredDisk;
+pub use self::overlay_disk::OverlayDisk;
+pub use self::striped_disk::StripedDisk;
 
 pub type BlockId = usize;
+/// The size of a block, fixed at compile time.
+///
+/// All layers and the on-disk format (the superblock, the logs, the MHT
+/// nodes and the segments) are laid out in units of this size, so a disk
+/// can only be opened by a build with the same `BLOCK_SIZE`.
 pub const BLOCK_SIZE: usize = 0x1000;
 pub const BID_SIZE: usize = core::mem::size_of::<BlockId>();
 
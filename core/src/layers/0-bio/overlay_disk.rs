@@ -0,0 +1,130 @@
+use super::{BlockId, BlockSet, BufMut, BufRef, BLOCK_SIZE};
+use crate::error::Errno;
+use crate::os::{BTreeMap, Mutex};
+use crate::prelude::*;
+
+use core::ops::Range;
+
+/// A disk that keeps the underlying block set untouched by redirecting
+/// all writes to a volatile in-memory overlay.
+///
+/// Reads are served by the overlay if the blocks have been written,
+/// otherwise by the underlying block set. The overlay is discarded on drop,
+/// and `flush()` never reaches the underlying block set.
+///
+/// The overlay is shared by all the subsets, and is indexed by the
+/// positions on the underlying block set, so that subsets never overlap.
+#[derive(Clone)]
+pub struct OverlayDisk<D> {
+    disk: Arc<D>,
+    overlay: Arc<Mutex<BTreeMap<BlockId, Box<[u8]>>>>,
+    region: Range<BlockId>,
+}
+
+impl<D: BlockSet> OverlayDisk<D> {
+    /// Create an `OverlayDisk` over the given disk.
+    pub fn new(disk: D) -> Self {
+        let nblocks = disk.nblocks();
+        Self {
+            disk: Arc::new(disk),
+            overlay: Arc::new(Mutex::new(BTreeMap::new())),
+            region: Range {
+                start: 0,
+                end: nblocks,
+            },
+        }
+    }
+
+    /// Returns the number of blocks written to the overlay.
+    pub fn overlay_nblocks(&self) -> usize {
+        self.overlay.lock().len()
+    }
+}
+
+impl<D: BlockSet> BlockSet for OverlayDisk<D> {
+    fn read(&self, pos: BlockId, mut buf: BufMut) -> Result<()> {
+        if pos + buf.nblocks() > self.region.len() {
+            return_errno_with_msg!(Errno::InvalidArgs, "read position is out of range");
+        }
+
+        let start = self.region.start + pos;
+        self.disk
+            .read(start, BufMut::try_from(buf.as_mut_slice())?)?;
+
+        let overlay = self.overlay.lock();
+        let end = start + buf.nblocks();
+        for (block_pos, block) in overlay.range(start..end) {
+            let offset = (block_pos - start) * BLOCK_SIZE;
+            buf.as_mut_slice()[offset..offset + BLOCK_SIZE].copy_from_slice(block);
+        }
+        Ok(())
+    }
+
+    fn write(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+        if pos + buf.nblocks() > self.region.len() {
+            return_errno_with_msg!(Errno::InvalidArgs, "write position is out of range");
+        }
+
+        let start = self.region.start + pos;
+        let mut overlay = self.overlay.lock();
+        for (i, block) in buf.as_slice().chunks(BLOCK_SIZE).enumerate() {
+            overlay.insert(start + i, block.into());
+        }
+        Ok(())
+    }
+
+    fn subset(&self, range: Range<BlockId>) -> Result<Self> {
+        if range.start > range.end || self.region.start + range.end > self.region.end {
+            return_errno_with_msg!(Errno::InvalidArgs, "subset is out of range");
+        }
+
+        Ok(OverlayDisk {
+            disk: self.disk.clone(),
+            overlay: self.overlay.clone(),
+            region: Range {
+                start: self.region.start + range.start,
+                end: self.region.start + range.end,
+            },
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn nblocks(&self) -> usize {
+        self.region.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OverlayDisk;
+    use crate::layers::bio::{BlockSet, Buf, MemDisk, BLOCK_SIZE};
+
+    #[test]
+    fn overlay_disk() {
+        let mem_disk = MemDisk::create(16).unwrap();
+        let mut buf = Buf::alloc(4).unwrap();
+        buf.as_mut_slice().fill(1);
+        mem_disk.write(0, buf.as_ref()).unwrap();
+
+        let disk = OverlayDisk::new(mem_disk.clone());
+        let subset = disk.subset(2..10).unwrap();
+        let mut block = Buf::alloc(1).unwrap();
+        block.as_mut_slice().fill(2);
+        subset.write(1, block.as_ref()).unwrap();
+        subset.flush().unwrap();
+        assert_eq!(disk.overlay_nblocks(), 1);
+
+        // The overlay is visible through all the subsets
+        disk.read(0, buf.as_mut()).unwrap();
+        assert_eq!(&buf.as_slice()[..3 * BLOCK_SIZE], [1u8; 3 * BLOCK_SIZE]);
+        assert_eq!(&buf.as_slice()[3 * BLOCK_SIZE..], [2u8; BLOCK_SIZE]);
+
+        // The underlying disk is untouched
+        mem_disk.read(3, block.as_mut()).unwrap();
+        assert_eq!(block.as_slice(), [1u8; BLOCK_SIZE]);
+        assert!(subset.write(8, block.as_ref()).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use super::{BlockId, BlockSet, BufMut, BufRef, BLOCK_SIZE};
+use crate::error::Errno;
+use crate::prelude::*;
+
+use core::ops::Range;
+
+/// A disk that stripes blocks across multiple underlying block sets
+/// (RAID-0 style).
+///
+/// The logical address space is divided into stripes of `stripe_nblocks`
+/// blocks, and the stripes are assigned to the underlying disks in a
+/// round-robin fashion:
+///
+/// ```text
+/// logical: | stripe 0 | stripe 1 | stripe 2 | stripe 3 | ...
+/// disk 0:  | stripe 0 | stripe 2 | ...
+/// disk 1:  | stripe 1 | stripe 3 | ...
+/// ```
+///
+/// The `region` is the accessible subset of the logical address space.
+/// A subset shares the same stripe mapping with its parent, i.e., a logical
+/// block is always mapped to the same physical block no matter which subset
+/// it is accessed from. Thus, disjoint subsets never overlap on the
+/// underlying disks.
+#[derive(Clone)]
+pub struct StripedDisk<D> {
+    disks: Arc<Vec<D>>,
+    stripe_nblocks: usize,
+    region: Range<BlockId>,
+}
+
+impl<D: BlockSet> StripedDisk<D> {
+    /// Create a `StripedDisk` over the given disks with the number of blocks
+    /// in a stripe.
+    ///
+    /// The capacity of the `StripedDisk` is determined by the smallest disk,
+    /// rounded down to a multiple of the stripe size.
+    pub fn new(disks: Vec<D>, stripe_nblocks: usize) -> Result<Self> {
+        if disks.is_empty() {
+            return_errno_with_msg!(Errno::InvalidArgs, "no disk to stripe across");
+        }
+        if stripe_nblocks == 0 {
+            return_errno_with_msg!(Errno::InvalidArgs, "stripe size must not be zero");
+        }
+
+        let min_nblocks = disks.iter().map(|disk| disk.nblocks()).min().unwrap();
+        let nstripes_per_disk = min_nblocks / stripe_nblocks;
+        let total_nblocks = nstripes_per_disk * stripe_nblocks * disks.len();
+        Ok(Self {
+            disks: Arc::new(disks),
+            stripe_nblocks,
+            region: Range {
+                start: 0,
+                end: total_nblocks,
+            },
+        })
+    }
+
+    /// Returns the number of blocks in a stripe.
+    pub fn stripe_nblocks(&self) -> usize {
+        self.stripe_nblocks
+    }
+
+    /// Returns the number of underlying disks.
+    pub fn ndisks(&self) -> usize {
+        self.disks.len()
+    }
+
+    /// Maps a logical block to the index of the underlying disk and
+    /// the position on that disk.
+    fn locate(&self, logical_pos: BlockId) -> (usize, BlockId) {
+        let stripe = logical_pos / self.stripe_nblocks;
+        let disk_idx = stripe % self.disks.len();
+        let disk_pos =
+            (stripe / self.disks.len()) * self.stripe_nblocks + logical_pos % self.stripe_nblocks;
+        (disk_idx, disk_pos)
+    }
+
+    /// Splits the logical blocks `[pos, pos + nblocks)` into pieces that do
+    /// not cross stripe boundaries. Each piece is described by
+    /// `(disk_idx, disk_pos, offset_in_blocks, nblocks)`.
+    fn split(&self, pos: BlockId, nblocks: usize) -> Vec<(usize, BlockId, usize, usize)> {
+        let mut pieces = Vec::new();
+        let mut logical_pos = self.region.start + pos;
+        let end = logical_pos + nblocks;
+        let mut offset = 0;
+        while logical_pos < end {
+            let stripe_end = (logical_pos / self.stripe_nblocks + 1) * self.stripe_nblocks;
+            let piece_nblocks = stripe_end.min(end) - logical_pos;
+            let (disk_idx, disk_pos) = self.locate(logical_pos);
+            pieces.push((disk_idx, disk_pos, offset, piece_nblocks));
+            logical_pos += piece_nblocks;
+            offset += piece_nblocks;
+        }
+        pieces
+    }
+}
+
+impl<D: BlockSet> BlockSet for StripedDisk<D> {
+    fn read(&self, pos: BlockId, mut buf: BufMut) -> Result<()> {
+        if pos + buf.nblocks() > self.region.len() {
+            return_errno_with_msg!(Errno::InvalidArgs, "read position is out of range");
+        }
+
+        let slice = buf.as_mut_slice();
+        for (disk_idx, disk_pos, offset, nblocks) in self.split(pos, slice.len() / BLOCK_SIZE) {
+            let piece = &mut slice[offset * BLOCK_SIZE..(offset + nblocks) * BLOCK_SIZE];
+            self.disks[disk_idx].read(disk_pos, BufMut::try_from(piece)?)?;
+        }
+        Ok(())
+    }
+
+    fn read_redundant(&self, pos: BlockId, mut buf: BufMut) -> Result<()> {
+        if pos + buf.nblocks() > self.region.len() {
+            return_errno_with_msg!(Errno::InvalidArgs, "read position is out of range");
+        }
+
+        let slice = buf.as_mut_slice();
+        for (disk_idx, disk_pos, offset, nblocks) in self.split(pos, slice.len() / BLOCK_SIZE) {
+            let piece = &mut slice[offset * BLOCK_SIZE..(offset + nblocks) * BLOCK_SIZE];
+            self.disks[disk_idx].read_redundant(disk_pos, BufMut::try_from(piece)?)?;
+        }
+        Ok(())
+    }
+
+    fn repair(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+        if pos + buf.nblocks() > self.region.len() {
+            return_errno_with_msg!(Errno::InvalidArgs, "repair position is out of range");
+        }
+
+        let slice = buf.as_slice();
+        for (disk_idx, disk_pos, offset, nblocks) in self.split(pos, buf.nblocks()) {
+            let piece = &slice[offset * BLOCK_SIZE..(offset + nblocks) * BLOCK_SIZE];
+            self.disks[disk_idx].repair(disk_pos, BufRef::try_from(piece)?)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, pos: BlockId, buf: BufRef) -> Result<()> {
+        if pos + buf.nblocks() > self.region.len() {
+            return_errno_with_msg!(Errno::InvalidArgs, "write position is out of range");
+        }
+
+        let slice = buf.as_slice();
+        for (disk_idx, disk_pos, offset, nblocks) in self.split(pos, buf.nblocks()) {
+            let piece = &slice[offset * BLOCK_SIZE..(offset + nblocks) * BLOCK_SIZE];
+            self.disks[disk_idx].write(disk_pos, BufRef::try_from(piece)?)?;
+        }
+        Ok(())
+    }
+
+    fn subset(&self, range: Range<BlockId>) -> Result<Self> {
+        if range.start > range.end || self.region.start + range.end > self.region.end {
+            return_errno_with_msg!(Errno::InvalidArgs, "subset is out of range");
+        }
+
+        Ok(StripedDisk {
+            disks: self.disks.clone(),
+            stripe_nblocks: self.stripe_nblocks,
+            region: Range {
+                start: self.region.start + range.start,
+                end: self.region.start + range.end,
+            },
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        for disk in self.disks.iter() {
+            disk.flush()?;
+        }
+        Ok(())
+    }
+
+    fn nblocks(&self) -> usize {
+        self.region.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StripedDisk;
+    use crate::layers::bio::{BlockSet, Buf, MemDisk, BLOCK_SIZE};
+    use core::ops::Range;
+
+    #[test]
+    fn striped_disk() {
+        let disks = vec![
+            MemDisk::create(40).unwrap(),
+            MemDisk::create(36).unwrap(),
+            MemDisk::create(50).unwrap(),
+        ];
+        let disk = StripedDisk::new(disks.clone(), 4).unwrap();
+        // 36 / 4 = 9 stripes per disk
+        assert_eq!(disk.nblocks(), 9 * 4 * 3);
+
+        // Write a range crossing several stripes
+        let mut buf = Buf::alloc(10).unwrap();
+        for (i, block) in buf.as_mut_slice().chunks_mut(BLOCK_SIZE).enumerate() {
+            block.fill(i as u8 + 1);
+        }
+        disk.write(2, buf.as_ref()).unwrap();
+
+        // Logical block 4 is the first block of stripe 1, on disk 1
+        let mut block = Buf::alloc(1).unwrap();
+        disks[1].read(0, block.as_mut()).unwrap();
+        assert_eq!(block.as_slice(), [3u8; BLOCK_SIZE]);
+        // Logical block 11 is the last block of stripe 2, on disk 2
+        disks[2].read(3, block.as_mut()).unwrap();
+        assert_eq!(block.as_slice(), [10u8; BLOCK_SIZE]);
+
+        let mut read_buf = Buf::alloc(10).unwrap();
+        disk.read(2, read_buf.as_mut()).unwrap();
+        assert_eq!(read_buf.as_slice(), buf.as_slice());
+
+        // A subset keeps the stripe mapping of its parent
+        let subset = disk.subset(Range { start: 8, end: 16 }).unwrap();
+        assert_eq!(subset.nblocks(), 8);
+        subset.read(3, block.as_mut()).unwrap();
+        assert_eq!(block.as_slice(), [10u8; BLOCK_SIZE]);
+        assert!(subset.read(8, block.as_mut()).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use super::{Iv, Key, Mac};
 use crate::layers::bio::{BlockId, BlockLog, Buf, BufMut, BufRef, BLOCK_SIZE};
 use crate::os::{Aead, HashMap, RwLock};
-use crate::{prelude::*, CONFIG};
+use crate::prelude::*;
 
 use core::any::Any;
 use core::cell::RefCell;
@@ -181,6 +181,13 @@ pub trait NodeCache {
         pos: Pbid,
         value: Arc<dyn Any + Send + Sync>,
     ) -> Option<Arc<dyn Any + Send + Sync>>;
+
+    /// Whether the cache is the lower tier of two-level caching (see
+    /// `Config::two_level_caching`), whose upper tier caches the data nodes
+    /// instead, so only MHT nodes are cached here.
+    fn is_two_level(&self) -> bool {
+        false
+    }
 }
 
 /// Context for a search request.
@@ -518,7 +525,7 @@ impl<L: BlockLog> MhtStorage<L> {
             let mac = Aead::new().encrypt(&node.0, &key, &Iv::new_zeroed(), &[], cipher)?;
 
             node_entries.push(MhtNodeEntry { pos, key, mac });
-            if !CONFIG.get().two_level_caching {
+            if !self.node_cache.is_two_level() {
                 // When two-level caching is disabled, cache data nodes as well.
                 self.node_cache.put(pos, node.clone());
             }
@@ -551,12 +558,13 @@ impl<L: BlockLog> MhtStorage<L> {
 
     fn read_data_node(&self, entry: &MhtNodeEntry, node_buf: &mut [u8]) -> Result<()> {
         debug_assert_eq!(node_buf.len(), BLOCK_SIZE);
-        if !CONFIG.get().two_level_caching {
-            if let Some(node) = self.node_cache.get(entry.pos) {
-                let data_node = node.downcast::<DataNode>().unwrap();
-                node_buf.copy_from_slice(&data_node.0);
-                return Ok(());
-            }
+        let is_two_level = self.node_cache.is_two_level();
+        if !is_two_level && let Some(node) = self.node_cache.get(entry.pos) {
+            let data_node = node.downcast::<DataNode>().map_err(|_| {
+                Error::with_msg(InvalidArgs, "cache node downcasts to data node failed")
+            })?;
+            node_buf.copy_from_slice(&data_node.0);
+            return Ok(());
         }
 
         let mut cipher = self.crypt_buf.cipher.borrow_mut();
@@ -569,7 +577,13 @@ impl<L: BlockLog> MhtStorage<L> {
             &[],
             &entry.mac,
             node_buf,
-        )
+        )?;
+
+        if !is_two_level {
+            let data_node = Arc::new(DataNode(node_buf.try_into().unwrap()));
+            self.node_cache.put(entry.pos, data_node);
+        }
+        Ok(())
     }
 }
 
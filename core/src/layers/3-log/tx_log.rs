@@ -63,6 +63,7 @@ use super::chunk::{ChunkAlloc, ChunkAllocEdit, ChunkAllocState};
 use super::raw_log::{RawLog, RawLogId, RawLogStore, RawLogStoreEdit, RawLogStoreState};
 use crate::layers::bio::{BlockId, BlockSet, Buf, BufMut, BufRef, BLOCK_SIZE};
 use crate::layers::crypto::{CryptoLog, NodeCache, RootMhtMeta};
+use crate::layers::disk::{CacheTier, CACHE_STATS};
 use crate::layers::edit::{CompactPolicy, Edit, EditJournal, EditJournalMeta};
 use crate::layers::log::chunk::CHUNK_NBLOCKS;
 use crate::os::{AeadKey as Key, HashMap, HashSet, Mutex, Skcipher, SkcipherIv, SkcipherKey};
@@ -166,6 +167,20 @@ impl<D: BlockSet + 'static> TxLogStore<D> {
         ))
     }
 
+    /// Formats the disk as `format()` does, with two-level caching enabled
+    /// or not regardless of `Config::two_level_caching`, e.g., to compare
+    /// the cache tiers in tests.
+    #[cfg(test)]
+    pub(crate) fn format_with_two_level_caching(
+        disk: D,
+        root_key: Key,
+        two_level_caching: bool,
+    ) -> Result<Self> {
+        let store = Self::format(disk, root_key)?;
+        store.state.lock().two_level_caching = two_level_caching;
+        Ok(store)
+    }
+
     /// Calculate the number of blocks required for the store and the journal.
     fn calc_store_and_journal_nblocks(total_nblocks: usize) -> (usize, usize) {
         let log_store_nblocks = {
@@ -266,18 +281,25 @@ impl<D: BlockSet + 'static> TxLogStore<D> {
         tx_provider: Arc<TxProvider>,
     ) -> Self {
         let new_self = {
+            let two_level_caching = CONFIG.get().two_level_caching;
             // Prepare lazy deletes and log caches first from persistent state
             let (lazy_deletes, log_caches) = {
                 let (mut delete_table, mut cache_table) = (HashMap::new(), HashMap::new());
                 for log_id in state.list_all_logs() {
                     Self::add_lazy_delete(log_id, &mut delete_table, &raw_log_store);
-                    cache_table.insert(log_id, Arc::new(CryptoLogCache::new(log_id, &tx_provider)));
+                    let log_cache = CryptoLogCache::new(log_id, &tx_provider, two_level_caching);
+                    cache_table.insert(log_id, Arc::new(log_cache));
                 }
                 (delete_table, cache_table)
             };
 
             Self {
-                state: Arc::new(Mutex::new(State::new(state, lazy_deletes, log_caches))),
+                state: Arc::new(Mutex::new(State::new(
+                    state,
+                    lazy_deletes,
+                    log_caches,
+                    two_level_caching,
+                ))),
                 raw_log_store,
                 journal: journal.clone(),
                 superblock,
@@ -400,7 +422,7 @@ impl<D: BlockSet + 'static> TxLogStore<D> {
     }
 
     fn apply_log_caches(state: &mut State, current_tx: &mut CurrentTx<'_>) {
-        if !CONFIG.get().two_level_caching {
+        if !state.two_level_caching {
             return;
         }
 
@@ -421,7 +443,7 @@ impl<D: BlockSet + 'static> TxLogStore<D> {
                 }
 
                 open_cache.lru_cache.iter().for_each(|(&pos, node)| {
-                    cache_inner.lru_cache.put(pos, node.clone());
+                    let _ = cache_inner.put(pos, node.clone());
                 });
             }
         });
@@ -438,11 +460,17 @@ impl<D: BlockSet + 'static> TxLogStore<D> {
         let raw_log = self.raw_log_store.create_log()?;
         let log_id = raw_log.id();
 
-        let log_cache = Arc::new(CryptoLogCache::new(log_id, &self.tx_provider));
-        self.state
-            .lock()
-            .log_caches
-            .insert(log_id, log_cache.clone());
+        let (log_cache, two_level_caching) = {
+            let mut state = self.state.lock();
+            let two_level_caching = state.two_level_caching;
+            let log_cache = Arc::new(CryptoLogCache::new(
+                log_id,
+                &self.tx_provider,
+                two_level_caching,
+            ));
+            state.log_caches.insert(log_id, log_cache.clone());
+            (log_cache, two_level_caching)
+        };
         let key = Key::random();
         let crypto_log = CryptoLog::new(raw_log, key, log_cache);
 
@@ -455,6 +483,7 @@ impl<D: BlockSet + 'static> TxLogStore<D> {
             crypto_log,
             lazy_delete: None,
             is_dirty: AtomicBool::new(false),
+            two_level_caching,
         });
 
         current_tx.data_mut_with(|store_edit: &mut TxLogStoreEdit| {
@@ -465,7 +494,7 @@ impl<D: BlockSet + 'static> TxLogStore<D> {
             let _ = open_log_table.open_table.insert(log_id, inner_log.clone());
         });
 
-        if CONFIG.get().two_level_caching {
+        if two_level_caching {
             current_tx.data_mut_with(|open_cache_table: &mut OpenLogCache| {
                 let _ = open_cache_table
                     .open_table
@@ -530,7 +559,8 @@ impl<D: BlockSet + 'static> TxLogStore<D> {
         };
 
         // Prepare cache before opening `CryptoLog`
-        if CONFIG.get().two_level_caching {
+        let two_level_caching = state.two_level_caching;
+        if two_level_caching {
             current_tx.data_mut_with(|open_cache_table: &mut OpenLogCache| {
                 let _ = open_cache_table
                     .open_table
@@ -555,6 +585,7 @@ impl<D: BlockSet + 'static> TxLogStore<D> {
             crypto_log,
             lazy_delete: Some(lazy_delete),
             is_dirty: AtomicBool::new(false),
+            two_level_caching,
         });
 
         current_tx.data_mut_with(|open_log_table: &mut OpenLogTable<D>| {
@@ -700,10 +731,21 @@ impl<D: BlockSet + 'static> TxLogStore<D> {
         self.tx_provider.current()
     }
 
+    /// Returns the memory (in bytes) taken by the caches of the logs,
+    /// excluding those of the logs opened by the ongoing TXs.
+    pub fn cached_bytes(&self) -> usize {
+        self.state
+            .lock()
+            .log_caches
+            .values()
+            .map(|cache| cache.inner.lock().lru_cache.len() * BLOCK_SIZE)
+            .sum()
+    }
+
     /// Syncs all the data managed by `TxLogStore` for persistence.
     pub fn sync(&self) -> Result<()> {
-        self.raw_log_store.sync().unwrap();
-        self.journal.lock().flush().unwrap();
+        self.raw_log_store.sync()?;
+        self.journal.lock().flush()?;
 
         self.raw_disk.flush()
     }
@@ -784,6 +826,8 @@ struct TxLogInner<D> {
     crypto_log: CryptoLog<RawLog<D>>,
     lazy_delete: Option<Arc<LazyDelete<TxLogId>>>,
     is_dirty: AtomicBool,
+    /// Whether the log is cached by two tiers, see `TxLog::is_two_level_cached()`.
+    two_level_caching: bool,
 }
 
 impl<D: BlockSet + 'static> TxLog<D> {
@@ -815,6 +859,13 @@ impl<D: BlockSet + 'static> TxLog<D> {
         self.can_append
     }
 
+    /// Returns whether the data blocks of the log are left to be cached by
+    /// the upper tier (e.g., the record blocks of SSTs), with the lower tier
+    /// caching MHT nodes only, see `Config::two_level_caching`.
+    pub fn is_two_level_cached(&self) -> bool {
+        self.inner_log.two_level_caching
+    }
+
     /// Reads one or multiple data blocks at a specified position.
     ///
     /// # Panics
@@ -870,6 +921,7 @@ pub struct CryptoLogCache {
     inner: Mutex<CacheInner>,
     log_id: TxLogId,
     tx_provider: Arc<TxProvider>,
+    two_level_caching: bool,
 }
 
 pub(super) struct CacheInner {
@@ -877,18 +929,19 @@ pub(super) struct CacheInner {
 }
 
 impl CryptoLogCache {
-    fn new(log_id: TxLogId, tx_provider: &Arc<TxProvider>) -> Self {
+    fn new(log_id: TxLogId, tx_provider: &Arc<TxProvider>, two_level_caching: bool) -> Self {
         Self {
             inner: Mutex::new(CacheInner::new()),
             log_id,
             tx_provider: tx_provider.clone(),
+            two_level_caching,
         }
     }
 }
 
 impl NodeCache for CryptoLogCache {
     fn get(&self, pos: BlockId) -> Option<Arc<dyn Any + Send + Sync>> {
-        if CONFIG.get().two_level_caching {
+        if self.two_level_caching {
             let mut current = self.tx_provider.current();
 
             let value_opt = current.data_mut_with(|open_cache_table: &mut OpenLogCache| {
@@ -899,12 +952,15 @@ impl NodeCache for CryptoLogCache {
                     .flatten()
             });
             if value_opt.is_some() {
+                CACHE_STATS.record_lookup(CacheTier::LogBlock, true);
                 return value_opt;
             }
         }
 
         let mut inner = self.inner.lock();
-        inner.lru_cache.get(&pos).cloned()
+        let value_opt = inner.lru_cache.get(&pos).cloned();
+        CACHE_STATS.record_lookup(CacheTier::LogBlock, value_opt.is_some());
+        value_opt
     }
 
     fn put(
@@ -912,18 +968,22 @@ impl NodeCache for CryptoLogCache {
         pos: BlockId,
         value: Arc<dyn Any + Send + Sync>,
     ) -> Option<Arc<dyn Any + Send + Sync>> {
-        if CONFIG.get().two_level_caching {
+        if self.two_level_caching {
             let mut current = self.tx_provider.current();
 
             return current.data_mut_with(|open_cache_table: &mut OpenLogCache| {
                 debug_assert!(open_cache_table.open_table.contains_key(&self.log_id));
                 let open_cache = open_cache_table.open_table.get_mut(&self.log_id).unwrap();
-                open_cache.lru_cache.put(pos, value)
+                open_cache.put(pos, value)
             });
         }
 
         let mut inner = self.inner.lock();
-        inner.lru_cache.put(pos, value)
+        inner.put(pos, value)
+    }
+
+    fn is_two_level(&self) -> bool {
+        self.two_level_caching
     }
 }
 
@@ -935,16 +995,33 @@ impl CacheInner {
         }
     }
 
+    /// Puts a node into the cache, return the old node of the position if any.
+    /// The node evicted for the capacity is recorded to `CACHE_STATS`.
+    fn put(
+        &mut self,
+        pos: BlockId,
+        value: Arc<dyn Any + Send + Sync>,
+    ) -> Option<Arc<dyn Any + Send + Sync>> {
+        match self.lru_cache.push(pos, value) {
+            Some((old_pos, old_value)) if old_pos == pos => Some(old_value),
+            Some(_) => {
+                CACHE_STATS.record_eviction(CacheTier::LogBlock);
+                None
+            }
+            None => None,
+        }
+    }
+
     /// Calculate cache capacity (in blocks) per CryptoLog based on global config.
     ///
-    /// Distributes the global cache_size (bytes) evenly across an estimated
-    /// maximum number of logs. Falls back to a default when cache_size is unset.
+    /// Distributes the size (bytes) of the log block cache tier evenly across
+    /// an estimated maximum number of logs. Falls back to a default when unset.
     fn cache_capacity() -> usize {
         const MAX_LOG_COUNT: usize = 64; // Conservative upper bound of concurrently cached logs
         const MIN_CACHE_CAP: usize = 64; // Minimum blocks per log
         const DEFAULT_CACHE_CAP: usize = 1024; // Legacy default when cache_size unset
 
-        let total_cache_bytes = CONFIG.get().cache_size;
+        let total_cache_bytes = CONFIG.get().cache_tier_size(CacheTier::LogBlock);
         if total_cache_bytes == usize::MAX {
             return DEFAULT_CACHE_CAP;
         }
@@ -963,6 +1040,8 @@ struct State {
     persistent: TxLogStoreState,
     lazy_deletes: HashMap<TxLogId, Arc<LazyDelete<TxLogId>>>,
     log_caches: HashMap<TxLogId, Arc<CryptoLogCache>>,
+    /// Whether the logs are cached by two tiers, see `Config::two_level_caching`.
+    two_level_caching: bool,
 }
 
 /// The persistent state of a `TxLogStore`.
@@ -991,11 +1070,13 @@ impl State {
         persistent: TxLogStoreState,
         lazy_deletes: HashMap<TxLogId, Arc<LazyDelete<TxLogId>>>,
         log_caches: HashMap<TxLogId, Arc<CryptoLogCache>>,
+        two_level_caching: bool,
     ) -> Self {
         Self {
             persistent,
             lazy_deletes,
             log_caches,
+            two_level_caching,
         }
     }
 
@@ -0,0 +1,127 @@
+//! Bloom filter of the keys in a `SSTable`.
+use crate::prelude::*;
+
+/// A Bloom filter over the 64-bit hashes of keys.
+///
+/// The bit positions of a key are derived from its hash by double hashing.
+/// Encoded format (padded to blocks):
+///
+/// ```text
+/// | nbits (u32) | num_hashes (u32) | bits ... |
+/// ```
+pub(super) struct BloomFilter {
+    bits: Vec<u8>,
+    num_hashes: u32,
+}
+
+/// The number of bits per key, with a false positive rate of about 1%.
+const BITS_PER_KEY: usize = 10;
+/// The optimal number of hashes for `BITS_PER_KEY`, i.e., `BITS_PER_KEY * ln2`.
+const NUM_HASHES: u32 = 7;
+
+impl BloomFilter {
+    const HEADER_SIZE: usize = 8;
+
+    /// Builds a Bloom filter from the hashes of all keys (see `Self::hash()`).
+    pub fn build(hashes: &[u64]) -> Self {
+        let nbytes = (hashes.len() * BITS_PER_KEY).div_ceil(8).max(8);
+        let mut filter = Self {
+            bits: vec![0; nbytes],
+            num_hashes: NUM_HASHES,
+        };
+        for &hash in hashes {
+            for pos in filter.bit_positions(hash) {
+                filter.bits[pos / 8] |= 1 << (pos % 8);
+            }
+        }
+        filter
+    }
+
+    /// Returns the hash of a key, FNV-1a with a final mix.
+    pub fn hash(key: &[u8]) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for &byte in key {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^ (hash >> 33)
+    }
+
+    /// Whether the key of the hash may be in the filter. A key
+    /// is definitely absent if it returns `false`.
+    pub fn may_contain(&self, hash: u64) -> bool {
+        self.bit_positions(hash)
+            .all(|pos| self.bits[pos / 8] & (1 << (pos % 8)) != 0)
+    }
+
+    fn bit_positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let nbits = (self.bits.len() * 8) as u64;
+        let delta = hash.rotate_left(32) | 1;
+        (0..self.num_hashes as u64)
+            .map(move |i| (hash.wrapping_add(i.wrapping_mul(delta)) % nbits) as usize)
+    }
+
+    /// Returns the number of blocks of the encoded filter.
+    pub fn nblocks(&self) -> usize {
+        (Self::HEADER_SIZE + self.bits.len()).div_ceil(BLOCK_SIZE)
+    }
+
+    /// Encodes the filter into a buffer of `self.nblocks()` blocks.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.nblocks() * BLOCK_SIZE);
+        buf.extend_from_slice(&((self.bits.len() * 8) as u32).to_le_bytes());
+        buf.extend_from_slice(&self.num_hashes.to_le_bytes());
+        buf.extend_from_slice(&self.bits);
+        buf.resize(self.nblocks() * BLOCK_SIZE, 0);
+        buf
+    }
+
+    /// Decodes a filter from the buffer.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < Self::HEADER_SIZE {
+            return_errno_with_msg!(InvalidArgs, "bloom filter is truncated");
+        }
+        let nbits = u32::from_le_bytes(buf[..4].try_into().unwrap()) as usize;
+        let num_hashes = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+        let nbytes = nbits / 8;
+        if nbytes == 0
+            || nbits % 8 != 0
+            || Self::HEADER_SIZE + nbytes > buf.len()
+            || !(1..=32).contains(&num_hashes)
+        {
+            return_errno_with_msg!(InvalidArgs, "bloom filter is corrupted");
+        }
+        Ok(Self {
+            bits: buf[Self::HEADER_SIZE..Self::HEADER_SIZE + nbytes].to_vec(),
+            num_hashes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BloomFilter;
+
+    #[test]
+    fn bloom_filter() {
+        let num_keys = 10000usize;
+        let hashes: Vec<u64> = (0..num_keys)
+            .map(|key| BloomFilter::hash(&key.to_le_bytes()))
+            .collect();
+        let filter = BloomFilter::build(&hashes);
+        let filter = BloomFilter::decode(&filter.encode()).unwrap();
+
+        // No false negatives, and few false positives
+        assert!(hashes.iter().all(|&hash| filter.may_contain(hash)));
+        let false_positives = (num_keys..2 * num_keys)
+            .filter(|key| filter.may_contain(BloomFilter::hash(&key.to_le_bytes())))
+            .count();
+        assert!(false_positives < num_keys / 20);
+
+        assert!(BloomFilter::decode(&[0u8; 4]).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Checkpoints of the mutable `MemTable` in `TxLsmTree`.
+use super::mem_table::MemTable;
+use super::tombstone::RangeTombstones;
+use super::wal::WalEntry;
+use super::{RecordKey, RecordValue, SyncId, ValueFormat};
+use crate::layers::bio::{BlockSet, Buf, BufRef};
+use crate::layers::log::TxLog;
+use crate::prelude::*;
+
+use core::mem::size_of;
+use pod::Pod;
+
+/// The bucket name of checkpoints.
+pub(super) const BUCKET_CHECKPOINT: &str = "CKPT";
+
+/// A checkpoint of the mutable `MemTable` in `TxLsmTree`.
+///
+/// A checkpoint persists the synced records and range tombstones of the
+/// mutable `MemTable` on a `TxLog` (L3), which replaces the WAL up to then.
+/// The recovery starts from the latest checkpoint, then only replays the
+/// WAL appended after it.
+///
+/// Checkpoint format:
+///
+/// ```text
+/// | Meta (one block) | Records (K, V) ... | Range tombstones |
+/// ```
+///
+/// The records are packed in blocks, no record spans two blocks. The values
+/// are encoded in the format of the tree (see `LsmParams::value_format`).
+/// The range tombstones are encoded by `RangeTombstones::encode()`.
+pub(super) struct Checkpoint<K, V> {
+    sync_id: SyncId,
+    records: Vec<(K, V)>,
+    tombstones: RangeTombstones<K>,
+}
+
+/// Metadata of a `Checkpoint`, which resides in its first block.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Debug)]
+struct CheckpointMeta {
+    magic: u64,
+    num_records: u64,
+    sync_id: SyncId,
+}
+
+impl<K: RecordKey<K>, V: RecordValue> Checkpoint<K, V> {
+    const MAGIC: u64 = 0x434b_5054_4c53_4d54;
+    /// The maximum number of blocks of records appended at once.
+    const MAX_APPEND_NBLOCKS: usize = 1024;
+
+    /// Takes a checkpoint of the synced records and range tombstones
+    /// of the given `MemTable`.
+    pub fn new(memtable: &MemTable<K, V>) -> Self {
+        let records = memtable
+            .iter()
+            .filter_map(|(key, value_ex)| value_ex.synced().map(|value| (key, *value)))
+            .collect();
+        let mut tombstones = RangeTombstones::new();
+        for (range, synced) in memtable.tombstones().iter() {
+            if synced {
+                tombstones.insert(range.clone(), true);
+            }
+        }
+        Self {
+            sync_id: memtable.sync_id(),
+            records,
+            tombstones,
+        }
+    }
+
+    /// Return the sync ID of the checkpointed `MemTable`.
+    pub fn sync_id(&self) -> SyncId {
+        self.sync_id
+    }
+
+    /// Returns the size (in bytes) of a record whose value is in `value_format`.
+    fn record_size(value_format: ValueFormat) -> usize {
+        size_of::<K>() + V::encoded_size(value_format)
+    }
+
+    /// Writes the checkpoint to the given `TxLog`, the values are
+    /// encoded in `value_format`.
+    pub fn write<D: BlockSet + 'static>(
+        &self,
+        tx_log: &TxLog<D>,
+        value_format: ValueFormat,
+    ) -> Result<()> {
+        let meta = CheckpointMeta {
+            magic: Self::MAGIC,
+            num_records: self.records.len() as _,
+            sync_id: self.sync_id,
+        };
+        let mut append_buf = Vec::with_capacity(BLOCK_SIZE);
+        append_buf.extend_from_slice(meta.as_bytes());
+        append_buf.resize(BLOCK_SIZE, 0);
+        tx_log.append(BufRef::try_from(&append_buf[..]).unwrap())?;
+
+        let records_per_block = BLOCK_SIZE / Self::record_size(value_format);
+        let records_per_append = records_per_block * Self::MAX_APPEND_NBLOCKS;
+        for records in self.records.chunks(records_per_append) {
+            append_buf.clear();
+            for block_records in records.chunks(records_per_block) {
+                for (key, value) in block_records {
+                    append_buf.extend_from_slice(key.as_bytes());
+                    value.encode(value_format, &mut append_buf);
+                }
+                append_buf.resize(align_up(append_buf.len(), BLOCK_SIZE), 0);
+            }
+            tx_log.append(BufRef::try_from(&append_buf[..]).unwrap())?;
+        }
+
+        let tombstones_buf = self.tombstones.encode();
+        if !tombstones_buf.is_empty() {
+            tx_log.append(BufRef::try_from(&tombstones_buf[..]).unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// Reads a checkpoint from the given `TxLog`, whose values are in `value_format`.
+    pub fn from_log<D: BlockSet + 'static>(
+        tx_log: &TxLog<D>,
+        value_format: ValueFormat,
+    ) -> Result<Self> {
+        let nblocks = tx_log.nblocks();
+        if nblocks == 0 {
+            return_errno_with_msg!(InvalidArgs, "empty checkpoint");
+        }
+        let mut buf = Buf::alloc(nblocks)?;
+        tx_log.read(0 as BlockId, buf.as_mut())?;
+        let buf_slice = buf.as_slice();
+
+        let meta = CheckpointMeta::from_bytes(&buf_slice[..size_of::<CheckpointMeta>()]);
+        let num_records = meta.num_records as usize;
+        let record_size = Self::record_size(value_format);
+        let records_nblocks = num_records.div_ceil(BLOCK_SIZE / record_size);
+        if meta.magic != Self::MAGIC || 1 + records_nblocks > nblocks {
+            return_errno_with_msg!(InvalidArgs, "invalid checkpoint");
+        }
+
+        let k_size = size_of::<K>();
+        let records_buf = &buf_slice[BLOCK_SIZE..(1 + records_nblocks) * BLOCK_SIZE];
+        let records = records_buf
+            .chunks_exact(BLOCK_SIZE)
+            .flat_map(|block| block.chunks_exact(record_size))
+            .take(num_records)
+            .map(|record| {
+                (
+                    K::from_bytes(&record[..k_size]),
+                    V::decode(value_format, &record[k_size..]),
+                )
+            })
+            .collect();
+        let tombstones = RangeTombstones::decode(&buf_slice[(1 + records_nblocks) * BLOCK_SIZE..])?;
+
+        Ok(Self {
+            sync_id: meta.sync_id,
+            records,
+            tombstones,
+        })
+    }
+
+    /// Return the entries to recover the checkpointed `MemTable`, in order.
+    /// The range tombstones go first, since the records of a `MemTable`
+    /// are always newer than its own tombstones.
+    pub fn into_entries(self) -> impl Iterator<Item = WalEntry<K, V>> {
+        let tombstones: Vec<_> = self
+            .tombstones
+            .iter()
+            .map(|(range, _)| WalEntry::RangeDelete(range.clone()))
+            .collect();
+        tombstones.into_iter().chain(
+            self.records
+                .into_iter()
+                .map(|(key, value)| WalEntry::Record(key, value)),
+        )
+    }
+}
@@ -1,27 +1,237 @@
 //! Compaction in `TxLsmTree`.
 use super::mem_table::ValueEx;
 use super::sstable::SSTable;
-use super::tx_lsm_tree::SSTABLE_CAPACITY;
+use super::tombstone::RangeTombstones;
+use super::tx_lsm_tree::{LsmParams, SstManager};
 use super::{LsmLevel, RecordKey, RecordValue, SyncId, TxEventListener};
 use crate::layers::bio::BlockSet;
-use crate::layers::log::TxLogStore;
-use crate::os::{JoinHandle, Mutex};
+use crate::layers::disk::Config;
+use crate::layers::log::{TxLogId, TxLogStore};
+use crate::os::{Condvar, CvarMutex, JoinHandle, Mutex};
 use crate::prelude::*;
 
 use core::marker::PhantomData;
+use core::ops::RangeInclusive;
+use core::str::FromStr;
+use pod::Pod;
+
+/// The maximum number of SSTs of the lower level (or the upper level if the
+/// lower one is not involved) compacted in a partition of a major compaction.
+const MAX_PARTITION_SSTS: usize = 4;
+
+/// The built-in compaction policies of `TxLsmTree`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CompactionPolicyKind {
+    /// `LeveledCompaction`.
+    Leveled = 0,
+    /// `TieredCompaction`.
+    Tiered = 1,
+}
+
+/// A policy that picks the SSTs of each major compaction, which decides
+/// how SSTs are organized in each level.
+///
+/// A `TxLsmTree` must be recovered with the same policy it's formatted with.
+pub(super) trait CompactionPolicy<K, V>: Send + Sync {
+    /// Picks the SSTs of a major compaction from `from_level` to its lower level.
+    fn pick(&self, sst_manager: &SstManager<K, V>, from_level: LsmLevel) -> CompactionInput<K, V>;
+}
+
+/// The SSTs picked by a `CompactionPolicy`, which are merged and replaced
+/// by new SSTs at the lower level.
+pub(super) struct CompactionInput<K, V> {
+    /// The sorted runs from the upper level, from newer to older.
+    /// SSTs within a run are disjoint and sorted by their ranges.
+    pub upper_runs: Vec<Vec<(TxLogId, Arc<SSTable<K, V>>)>>,
+    /// The sorted runs from the lower level, from newer to older,
+    /// which are all older than the upper runs.
+    pub lower_runs: Vec<Vec<(TxLogId, Arc<SSTable<K, V>>)>>,
+}
+
+/// Leveled compaction, which keeps the SSTs of each level (except L0) disjoint.
+///
+/// Each major compaction merges the oldest SST of the upper level with the
+/// overlapped SSTs of the lower level. It favors reads and space.
+pub(super) struct LeveledCompaction;
+
+/// Size-tiered compaction, which lets each level (except L0 and the last
+/// level) hold several sorted runs that may overlap with each other.
+///
+/// Each major compaction merges all the runs of the upper level into a new
+/// run of the lower level, leaving the runs already there untouched. A record
+/// is rewritten once per level, rather than up to `level_ratio` times as
+/// leveled compaction does, which favors writes.
+///
+/// The last level has no lower level to compact to, so the SSTs there that
+/// overlap with the upper runs are merged as well. It stays a single run,
+/// whose overwritten records are dropped rather than piling up.
+pub(super) struct TieredCompaction;
+
+impl<K: RecordKey<K>, V: RecordValue> CompactionPolicy<K, V> for LeveledCompaction {
+    fn pick(&self, sst_manager: &SstManager<K, V>, from_level: LsmLevel) -> CompactionInput<K, V> {
+        let (upper_sst_id, upper_sst) = sst_manager
+            .list_level(from_level)
+            .last() // Choose the oldest SST from upper level
+            .map(|(id, sst)| (*id, sst.clone()))
+            .unwrap();
+        let mut lower_ssts = sst_manager
+            .find_overlapped_ssts(&upper_sst.range(), from_level.lower_level())
+            .map(|(id, sst)| (*id, sst.clone()))
+            .collect::<Vec<_>>();
+        lower_ssts.sort_by_key(|(_, sst)| *sst.range().start());
+
+        CompactionInput {
+            upper_runs: vec![vec![(upper_sst_id, upper_sst)]],
+            lower_runs: if lower_ssts.is_empty() {
+                vec![]
+            } else {
+                vec![lower_ssts]
+            },
+        }
+    }
+}
+
+impl<K: RecordKey<K>, V: RecordValue> CompactionPolicy<K, V> for TieredCompaction {
+    fn pick(&self, sst_manager: &SstManager<K, V>, from_level: LsmLevel) -> CompactionInput<K, V> {
+        let upper_runs = Self::split_into_runs(
+            sst_manager
+                .list_level(from_level)
+                .map(|(id, sst)| (*id, sst.clone()))
+                .collect(),
+        );
+        let to_level = from_level.lower_level();
+        if to_level != LsmLevel::L5 {
+            return CompactionInput {
+                upper_runs,
+                lower_runs: vec![],
+            };
+        }
+
+        // Extend the range until no more SST of the last level overlaps,
+        // so that the SSTs left there don't overlap with the new ones
+        let upper_ssts = || upper_runs.iter().flatten();
+        let mut range = upper_ssts()
+            .map(|(_, sst)| *sst.range().start())
+            .min()
+            .unwrap()
+            ..=upper_ssts()
+                .map(|(_, sst)| *sst.range().end())
+                .max()
+                .unwrap();
+        let lower_ssts = loop {
+            let ssts = sst_manager
+                .find_overlapped_ssts(&range, to_level)
+                .map(|(id, sst)| (*id, sst.clone()))
+                .collect::<Vec<_>>();
+            let start = ssts
+                .iter()
+                .map(|(_, sst)| *sst.range().start())
+                .fold(*range.start(), core::cmp::min);
+            let end = ssts
+                .iter()
+                .map(|(_, sst)| *sst.range().end())
+                .fold(*range.end(), core::cmp::max);
+            if start == *range.start() && end == *range.end() {
+                break ssts;
+            }
+            range = start..=end;
+        };
+
+        CompactionInput {
+            upper_runs,
+            lower_runs: Self::split_into_runs(lower_ssts),
+        }
+    }
+}
+
+impl TieredCompaction {
+    /// Splits the SSTs of a level, given from newer to older, into sorted
+    /// runs from newer to older. The SSTs of a run are built in order.
+    fn split_into_runs<K: RecordKey<K>, V: RecordValue>(
+        mut ssts: Vec<(TxLogId, Arc<SSTable<K, V>>)>,
+    ) -> Vec<Vec<(TxLogId, Arc<SSTable<K, V>>)>> {
+        // SSTs from older to newer
+        ssts.reverse();
+
+        let mut runs: Vec<Vec<(TxLogId, Arc<SSTable<K, V>>)>> = Vec::new();
+        for (id, sst) in ssts {
+            if let Some(run) = runs.last_mut()
+                && run.last().unwrap().1.range().end() < sst.range().start()
+            {
+                run.push((id, sst));
+                continue;
+            }
+            runs.push(vec![(id, sst)]);
+        }
+        runs.reverse();
+        runs
+    }
+}
+
+impl CompactionPolicyKind {
+    /// Build the compaction policy of the kind.
+    pub(super) fn build<K: RecordKey<K>, V: RecordValue>(&self) -> Arc<dyn CompactionPolicy<K, V>> {
+        match self {
+            Self::Leveled => Arc::new(LeveledCompaction),
+            Self::Tiered => Arc::new(TieredCompaction),
+        }
+    }
+}
+
+impl TryFrom<u64> for CompactionPolicyKind {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Leveled),
+            1 => Ok(Self::Tiered),
+            _ => Err(Error::with_msg(InvalidArgs, "invalid compaction policy")),
+        }
+    }
+}
+
+impl FromStr for CompactionPolicyKind {
+    type Err = Error;
+
+    /// Parse from `leveled` or `tiered`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "leveled" => Ok(Self::Leveled),
+            "tiered" => Ok(Self::Tiered),
+            _ => Err(Error::with_msg(InvalidArgs, "unknown compaction policy")),
+        }
+    }
+}
 
 /// A `Compactor` is currently used for asynchronous compaction
 /// and specific compaction algorithm of `TxLsmTree`.
+///
+/// A background compaction first flushes the immutable `MemTable`,
+/// then does major compactions if necessary. The foreground only waits
+/// for the flush before reusing the immutable `MemTable`.
 pub(super) struct Compactor<K, V> {
     handle: Mutex<Option<JoinHandle<Result<()>>>>,
+    flush_state: CvarMutex<FlushState>,
+    cvar: Condvar,
     phantom: PhantomData<(K, V)>,
 }
 
+/// The state of flushing the immutable `MemTable`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FlushState {
+    Idle,
+    Flushing,
+    Failed,
+}
+
 impl<K: RecordKey<K>, V: RecordValue> Compactor<K, V> {
     /// Create a new `Compactor` instance.
     pub fn new() -> Self {
         Self {
             handle: Mutex::new(None),
+            flush_state: CvarMutex::new(FlushState::Idle),
+            cvar: Condvar::new(),
             phantom: PhantomData,
         }
     }
@@ -33,6 +243,46 @@ impl<K: RecordKey<K>, V: RecordValue> Compactor<K, V> {
         let _ = handle_opt.insert(handle);
     }
 
+    /// Take the handle of the last compaction thread, which
+    /// the next one should wait for.
+    pub fn take_handle(&self) -> Option<JoinHandle<Result<()>>> {
+        self.handle.lock().take()
+    }
+
+    /// Mark the immutable `MemTable` as being flushed.
+    pub fn begin_flush(&self) {
+        let mut state = self.flush_state.lock().unwrap();
+        debug_assert_ne!(*state, FlushState::Flushing);
+        *state = FlushState::Flushing;
+    }
+
+    /// Mark the flush of the immutable `MemTable` as finished.
+    pub fn finish_flush(&self, is_ok: bool) {
+        let mut state = self.flush_state.lock().unwrap();
+        *state = if is_ok {
+            FlushState::Idle
+        } else {
+            FlushState::Failed
+        };
+        self.cvar.notify_all();
+    }
+
+    /// Wait until the immutable `MemTable` is flushed, while the
+    /// major compactions after the flush may still be running.
+    pub fn wait_flush(&self) -> Result<()> {
+        let mut state = self.flush_state.lock().unwrap();
+        while *state == FlushState::Flushing {
+            state = self.cvar.wait(state).unwrap();
+        }
+        if *state == FlushState::Failed {
+            *state = FlushState::Idle;
+            drop(state);
+            // Report the error of the failed compaction
+            return self.wait_compaction();
+        }
+        Ok(())
+    }
+
     /// Wait until the compaction is finished.
     pub fn wait_compaction(&self) -> Result<()> {
         if let Some(handle) = self.handle.lock().take() {
@@ -43,65 +293,220 @@ impl<K: RecordKey<K>, V: RecordValue> Compactor<K, V> {
     }
 
     /// Core function for compacting overlapped records and building new SSTs.
+    /// The records of each run are sorted by keys, and the runs are given
+    /// from newer to older, each with its range tombstones within `partition`.
+    ///
+    /// The records of a run are deleted by the range tombstones of the newer
+    /// runs, and all the tombstones are kept in the new SSTs for the older
+    /// SSTs not involved.
     ///
     /// # Panics
     ///
     /// This method must be called within a TX. Otherwise, this method panics.
-    pub fn compact_records_and_build_ssts<D: BlockSet + 'static>(
-        upper_records: impl Iterator<Item = (K, ValueEx<V>)>,
-        lower_records: impl Iterator<Item = (K, ValueEx<V>)>,
+    pub fn compact_records_and_build_ssts<'a, D: BlockSet + 'static>(
+        runs: Vec<(
+            Box<dyn Iterator<Item = (K, ValueEx<V>)> + 'a>,
+            RangeTombstones<K>,
+        )>,
+        partition: &RangeInclusive<K>,
         tx_log_store: &Arc<TxLogStore<D>>,
         event_listener: &Arc<dyn TxEventListener<K, V>>,
         to_level: LsmLevel,
         sync_id: SyncId,
+        params: &LsmParams,
+        config: &Config,
     ) -> Result<Vec<SSTable<K, V>>> {
+        let sst_capacity = params.memtable_capacity as usize;
         let mut created_ssts = Vec::new();
-        let mut upper_iter = upper_records.peekable();
-        let mut lower_iter = lower_records.peekable();
+        let (mut runs, run_tombstones): (Vec<_>, Vec<_>) = runs
+            .into_iter()
+            .map(|(run, tombstones)| (run.peekable(), tombstones))
+            .unzip();
+        let mut all_tombstones = RangeTombstones::new();
+        for tombstones in run_tombstones.iter() {
+            all_tombstones.extend(tombstones);
+        }
 
-        loop {
-            let mut record_cnt = 0;
-            let records_iter = core::iter::from_fn(|| {
-                if record_cnt == SSTABLE_CAPACITY {
-                    return None;
-                }
-
-                record_cnt += 1;
-                match (upper_iter.peek(), lower_iter.peek()) {
-                    (Some((upper_k, _)), Some((lower_k, _))) => match upper_k.cmp(lower_k) {
-                        core::cmp::Ordering::Less => upper_iter.next(),
-                        core::cmp::Ordering::Greater => lower_iter.next(),
-                        core::cmp::Ordering::Equal => {
-                            let (k, new_v_ex) = upper_iter.next().unwrap();
-                            let (_, old_v_ex) = lower_iter.next().unwrap();
-                            let (next_v_ex, dropped_v_opt) =
-                                Self::compact_value_ex(new_v_ex, old_v_ex);
-
-                            if let Some(dropped_v) = dropped_v_opt {
-                                event_listener.on_drop_record(&(k, dropped_v)).unwrap();
-                            }
-                            Some((k, next_v_ex))
+        // Take the minimum key of all runs, with the records of the key
+        // compacted from newer to older
+        let next_record = || {
+            let min_key = runs
+                .iter_mut()
+                .filter_map(|run| run.peek().map(|(k, _)| *k))
+                .min()?;
+            let mut compacted: Option<ValueEx<V>> = None;
+            for (nth, run) in runs.iter_mut().enumerate() {
+                let Some((_, old_v_ex)) = run.next_if(|(k, _)| *k == min_key) else {
+                    continue;
+                };
+
+                // Delete the record by the tombstones of the newer runs,
+                // a synced deletion wins over the unsynced ones
+                let deleted_by_synced = run_tombstones[..nth]
+                    .iter()
+                    .filter_map(|tombstones| tombstones.covering(&min_key))
+                    .max();
+                let old_v_ex = match deleted_by_synced {
+                    None => old_v_ex,
+                    Some(true) => {
+                        let (v, extra_v) = match old_v_ex {
+                            ValueEx::Synced(v)
+                            | ValueEx::Unsynced(v)
+                            | ValueEx::SyncedAndDeleted(v) => (v, None),
+                            ValueEx::SyncedAndUnsynced(sv, usv) => (sv, Some(usv)),
+                        };
+                        for dropped_v in core::iter::once(v).chain(extra_v) {
+                            Self::drop_value(
+                                event_listener,
+                                compacted.as_ref(),
+                                min_key,
+                                dropped_v,
+                            );
+                        }
+                        continue;
+                    }
+                    Some(false) => {
+                        let (remained, dropped_v_opt) = old_v_ex.delete();
+                        if let Some(dropped_v) = dropped_v_opt {
+                            Self::drop_value(
+                                event_listener,
+                                compacted.as_ref(),
+                                min_key,
+                                dropped_v,
+                            );
                         }
-                    },
-                    (Some(_), None) => upper_iter.next(),
-                    (None, Some(_)) => lower_iter.next(),
-                    (None, None) => None,
-                }
-            });
-            let mut records_iter = records_iter.peekable();
-            if records_iter.peek().is_none() {
+                        let Some(remained) = remained else {
+                            continue;
+                        };
+                        remained
+                    }
+                };
+
+                compacted = Some(match compacted {
+                    None => old_v_ex,
+                    Some(new_v_ex) => {
+                        let (next_v_ex, dropped_v_opt) = Self::compact_value_ex(new_v_ex, old_v_ex);
+
+                        if let Some(dropped_v) = dropped_v_opt {
+                            Self::drop_value(event_listener, Some(&next_v_ex), min_key, dropped_v);
+                        }
+                        next_v_ex
+                    }
+                });
+            }
+            compacted.map(|v_ex| (min_key, v_ex))
+        };
+
+        // Each new SST takes the tombstones up to its last record, so the new
+        // SSTs stay disjoint. The tombstones after the last record, if any,
+        // are put in a new SST of their own.
+        let mut records = core::iter::from_fn(next_record).peekable();
+        let mut start = *partition.start();
+        loop {
+            let has_records = records.peek().is_some();
+            let rest_tombstones = all_tombstones.clip(&(start..=*partition.end()));
+            if !has_records && rest_tombstones.is_empty() {
                 break;
             }
 
             let new_log = tx_log_store.create_log(to_level.bucket())?;
-            let new_sst = SSTable::build(records_iter, sync_id, &new_log, None)?;
+            let new_sst = SSTable::build(
+                records.by_ref().take(sst_capacity),
+                |last_key| match last_key {
+                    Some(last_key) => rest_tombstones.clip(&(start..=*last_key)),
+                    None => rest_tombstones.clone(),
+                },
+                sync_id,
+                params.sst_block_size as _,
+                params.value_format,
+                config,
+                &new_log,
+                None,
+            )?;
 
+            start = *new_sst.range().end() + 1;
             created_ssts.push(new_sst);
+            if !has_records {
+                break;
+            }
         }
 
         Ok(created_ssts)
     }
 
+    /// Drop a value during compaction, unless it is a duplicate of the
+    /// compacted value of the newer runs. A record may be compacted twice
+    /// if a partitioned compaction is interrupted, its duplicate isn't dropped.
+    fn drop_value(
+        event_listener: &Arc<dyn TxEventListener<K, V>>,
+        compacted: Option<&ValueEx<V>>,
+        key: K,
+        value: V,
+    ) {
+        if !compacted.is_some_and(|v_ex| Self::contains_value(v_ex, &value)) {
+            event_listener.on_drop_record(&(key, value)).unwrap();
+        }
+    }
+
+    /// Whether the value is (a duplicate of) one of the values in `v_ex`.
+    fn contains_value(v_ex: &ValueEx<V>, value: &V) -> bool {
+        let is_dup = |v: &V| v.as_bytes() == value.as_bytes();
+        match v_ex {
+            ValueEx::Synced(v) | ValueEx::Unsynced(v) | ValueEx::SyncedAndDeleted(v) => is_dup(v),
+            ValueEx::SyncedAndUnsynced(sv, usv) => is_dup(sv) || is_dup(usv),
+        }
+    }
+
+    /// Splits the key range of a major compaction into partitions, each of
+    /// which is compacted in a TX of its own. The partitions are cut at the
+    /// ends of every `MAX_PARTITION_SSTS` lower SSTs (or upper SSTs if there
+    /// are no lower ones), so that no lower SST straddles two partitions.
+    pub fn partition(input: &CompactionInput<K, V>) -> Vec<RangeInclusive<K>> {
+        let lower_ssts = || input.lower_runs.iter().flatten();
+        let all_ssts = || input.upper_runs.iter().flatten().chain(lower_ssts());
+        let first = all_ssts()
+            .map(|(_, sst)| *sst.range().start())
+            .min()
+            .unwrap();
+        let last = all_ssts().map(|(_, sst)| *sst.range().end()).max().unwrap();
+
+        let mut ends: Vec<K> = if input.lower_runs.is_empty() {
+            input
+                .upper_runs
+                .iter()
+                .flatten()
+                .map(|(_, sst)| *sst.range().end())
+                .collect()
+        } else {
+            // The lower runs may overlap, skip the ends within other SSTs
+            lower_ssts()
+                .map(|(_, sst)| *sst.range().end())
+                .filter(|end| {
+                    !lower_ssts()
+                        .any(|(_, sst)| sst.range().start() <= end && end < sst.range().end())
+                })
+                .collect()
+        };
+        ends.sort();
+        ends.dedup();
+
+        let mut partitions = Vec::new();
+        let mut start = first;
+        for &cut in ends
+            .iter()
+            .skip(MAX_PARTITION_SSTS - 1)
+            .step_by(MAX_PARTITION_SSTS)
+        {
+            if cut >= last {
+                break;
+            }
+            partitions.push(start..=cut);
+            start = cut + 1;
+        }
+        partitions.push(start..=last);
+        partitions
+    }
+
     /// Compact two `ValueEx<V>`s with the same key, returning
     /// the compacted value and the dropped value if any.
     fn compact_value_ex(new: ValueEx<V>, old: ValueEx<V>) -> (ValueEx<V>, Option<V>) {
@@ -121,6 +526,25 @@ impl<K: RecordKey<K>, V: RecordValue> Compactor<K, V> {
             (ValueEx::SyncedAndUnsynced(new_sv, new_usv), ValueEx::Synced(old_sv)) => {
                 (ValueEx::SyncedAndUnsynced(new_sv, new_usv), Some(old_sv))
             }
+            // Only a duplicate of the new one
+            (
+                ValueEx::SyncedAndUnsynced(new_sv, new_usv),
+                ValueEx::SyncedAndUnsynced(_, old_usv),
+            ) => (ValueEx::SyncedAndUnsynced(new_sv, new_usv), Some(old_usv)),
+            // The old synced value deleted by an unsynced range tombstone
+            (ValueEx::Synced(new_v), ValueEx::SyncedAndDeleted(old_v)) => {
+                (ValueEx::Synced(new_v), Some(old_v))
+            }
+            (ValueEx::Unsynced(new_v), ValueEx::SyncedAndDeleted(old_v)) => {
+                (ValueEx::SyncedAndUnsynced(old_v, new_v), None)
+            }
+            (ValueEx::SyncedAndUnsynced(new_sv, new_usv), ValueEx::SyncedAndDeleted(old_v)) => {
+                (ValueEx::SyncedAndUnsynced(new_sv, new_usv), Some(old_v))
+            }
+            (
+                ValueEx::SyncedAndDeleted(new_v),
+                ValueEx::Synced(old_v) | ValueEx::SyncedAndDeleted(old_v),
+            ) => (ValueEx::SyncedAndDeleted(new_v), Some(old_v)),
             _ => {
                 unreachable!()
             }
@@ -1,17 +1,23 @@
 //! MemTable.
+use super::skiplist::SkipList;
+use super::tombstone::{Lookup, RangeTombstones};
+use super::tree_iter::MemTableSnapshot;
 use super::{AsKV, RangeQueryCtx, RecordKey, RecordValue, SyncId};
-use crate::os::{BTreeMap, Condvar, CvarMutex, Mutex, RwLock, RwLockReadGuard};
+use crate::os::{Condvar, CvarMutex, Mutex, RwLock, RwLockReadGuard};
 use crate::prelude::*;
 
 use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 /// Manager for an mutable `MemTable` and an immutable `MemTable`
 /// in a `TxLsmTree`.
 pub(super) struct MemTableManager<K: RecordKey<K>, V> {
-    mutable: Mutex<MemTable<K, V>>,
+    // Shared by concurrent writers, exclusive only on sync and switch
+    mutable: RwLock<MemTable<K, V>>,
     immutable: RwLock<MemTable<K, V>>, // Read-only most of the time
+    is_full: AtomicBool,
     cvar: Condvar,
-    is_full: CvarMutex<bool>,
+    switch_lock: CvarMutex<()>,
 }
 
 /// MemTable for LSM-Tree.
@@ -20,12 +26,22 @@ pub(super) struct MemTableManager<K: RecordKey<K>, V> {
 /// Each `MemTable` is sync-aware (tagged with current sync ID).
 /// Both synced and unsynced records can co-exist.
 /// Also supports user-defined callback when a record is dropped.
+///
+/// Records are kept in a concurrent skiplist, so puts and gets
+/// through a shared reference do not contend on a table-wide lock.
+///
+/// A range delete deletes the records within the range in place, and
+/// keeps a range tombstone for the older tables. A slot is left empty
+/// if all its values are deleted. Each tombstone takes a record's room.
 pub(super) struct MemTable<K: RecordKey<K>, V> {
-    table: BTreeMap<K, ValueEx<V>>,
-    size: usize,
+    table: SkipList<K, Mutex<Option<ValueEx<V>>>>,
+    tombstones: RangeTombstones<K>,
+    size: AtomicUsize,
     cap: usize,
     sync_id: SyncId,
-    unsynced_range: Option<Range<K>>,
+    // Offsets of the unsynced keys from `K::new_uninit()`
+    unsynced_start: AtomicUsize,
+    unsynced_end: AtomicUsize,
     on_drop_record: Option<Arc<dyn Fn(&dyn AsKV<K, V>)>>,
 }
 
@@ -36,6 +52,8 @@ pub(super) enum ValueEx<V> {
     Synced(V),
     Unsynced(V),
     SyncedAndUnsynced(V, V),
+    /// A synced value deleted by an unsynced range tombstone.
+    SyncedAndDeleted(V),
 }
 
 impl<K: RecordKey<K>, V: RecordValue> MemTableManager<K, V> {
@@ -46,7 +64,7 @@ impl<K: RecordKey<K>, V: RecordValue> MemTableManager<K, V> {
         capacity: usize,
         on_drop_record_in_memtable: Option<Arc<dyn Fn(&dyn AsKV<K, V>)>>,
     ) -> Self {
-        let mutable = Mutex::new(MemTable::new(
+        let mutable = RwLock::new(MemTable::new(
             capacity,
             sync_id,
             on_drop_record_in_memtable.clone(),
@@ -56,27 +74,25 @@ impl<K: RecordKey<K>, V: RecordValue> MemTableManager<K, V> {
         Self {
             mutable,
             immutable,
+            is_full: AtomicBool::new(false),
             cvar: Condvar::new(),
-            is_full: CvarMutex::new(false),
+            switch_lock: CvarMutex::new(()),
         }
     }
 
-    /// Gets the target value of the given key from the `MemTable`s.
-    pub fn get(&self, key: &K) -> Option<V> {
-        if let Some(value) = self.mutable.lock().get(key) {
-            return Some(value.clone());
-        }
-
-        if let Some(value) = self.immutable.read().get(key) {
-            return Some(value.clone());
+    /// Looks up the given key in the `MemTable`s.
+    pub fn get(&self, key: &K) -> Lookup<V> {
+        match self.mutable.read().get(key) {
+            Lookup::Missing => {}
+            lookup => return lookup,
         }
 
-        None
+        self.immutable.read().get(key)
     }
 
     /// Gets the range of values from the `MemTable`s.
     pub fn get_range(&self, range_query_ctx: &mut RangeQueryCtx<K, V>) -> bool {
-        let is_completed = self.mutable.lock().get_range(range_query_ctx);
+        let is_completed = self.mutable.read().get_range(range_query_ctx);
         if is_completed {
             return is_completed;
         }
@@ -86,35 +102,65 @@ impl<K: RecordKey<K>, V: RecordValue> MemTableManager<K, V> {
 
     /// Puts a key-value pair into the mutable `MemTable`, and
     /// return whether the mutable `MemTable` is full.
+    ///
+    /// Exactly one put observes the `MemTable` becoming full, the later
+    /// ones wait until it is switched.
     pub fn put(&self, key: K, value: V) -> bool {
-        let mut is_full = self.is_full.lock().unwrap();
-        while *is_full {
-            is_full = self.cvar.wait(is_full).unwrap();
+        loop {
+            let mutable = self.mutable.read();
+            if !self.is_full.load(Ordering::Acquire) {
+                let _ = mutable.put(key, value);
+                return mutable.at_capacity() && !self.is_full.swap(true, Ordering::AcqRel);
+            }
+            drop(mutable);
+
+            self.wait_switch();
         }
-        debug_assert!(!*is_full);
+    }
 
-        let mut mutable = self.mutable.lock();
-        let _ = mutable.put(key, value);
+    /// Returns the number of puts the mutable `MemTable` takes at least
+    /// before it becomes full, since a put adds at most one record.
+    pub fn remaining_capacity(&self) -> usize {
+        let mutable = self.mutable.read();
+        mutable.cap.saturating_sub(mutable.size())
+    }
+
+    /// Deletes the records within the range from the mutable `MemTable`,
+    /// and return whether the mutable `MemTable` is full as `put()` does.
+    pub fn delete_range(&self, range: Range<K>) -> bool {
+        loop {
+            let mut mutable = self.mutable.write();
+            if !self.is_full.load(Ordering::Acquire) {
+                mutable.delete_range(range);
+                return mutable.at_capacity() && !self.is_full.swap(true, Ordering::AcqRel);
+            }
+            drop(mutable);
 
-        if mutable.at_capacity() {
-            *is_full = true;
+            self.wait_switch();
+        }
+    }
+
+    /// Wait until the full mutable `MemTable` is switched.
+    fn wait_switch(&self) {
+        let mut guard = self.switch_lock.lock().unwrap();
+        while self.is_full.load(Ordering::Acquire) {
+            guard = self.cvar.wait(guard).unwrap();
         }
-        *is_full
     }
 
     /// Sync the mutable `MemTable` with the given sync ID.
     pub fn sync(&self, sync_id: SyncId) {
-        self.mutable.lock().sync(sync_id)
+        self.mutable.write().sync(sync_id)
     }
 
     /// Switch two `MemTable`s. Should only be called in a situation that
     /// the mutable `MemTable` becomes full and the immutable `MemTable` is
     /// ready to be cleared.
     pub fn switch(&self) -> Result<()> {
-        let mut is_full = self.is_full.lock().unwrap();
-        debug_assert!(*is_full);
+        let _guard = self.switch_lock.lock().unwrap();
+        debug_assert!(self.is_full.load(Ordering::Acquire));
 
-        let mut mutable = self.mutable.lock();
+        let mut mutable = self.mutable.write();
         let sync_id = mutable.sync_id();
 
         let mut immutable = self.immutable.write();
@@ -126,11 +172,36 @@ impl<K: RecordKey<K>, V: RecordValue> MemTableManager<K, V> {
         // Update sync ID of the switched mutable `MemTable`
         mutable.sync(sync_id);
 
-        *is_full = false;
+        self.is_full.store(false, Ordering::Release);
         self.cvar.notify_all();
         Ok(())
     }
 
+    /// Collects the most recent records in the mutable `MemTable`.
+    pub fn mutable_records(&self) -> Vec<(K, V)> {
+        self.mutable
+            .read()
+            .iter()
+            .filter_map(|(k, v_ex)| v_ex.get().map(|v| (k, *v)))
+            .collect()
+    }
+
+    /// Takes snapshots of the mutable `MemTable` and the immutable one.
+    pub fn snapshot(&self) -> Vec<MemTableSnapshot<K, V>> {
+        // Exclude the concurrent writers of the mutable `MemTable`
+        let mutable = self.mutable.write();
+        let immutable = self.immutable.read();
+        vec![
+            MemTableSnapshot::new(&mutable),
+            MemTableSnapshot::new(&immutable),
+        ]
+    }
+
+    /// Gets the mutable `MemTable` instance (read-only).
+    pub fn mutable_memtable(&self) -> RwLockReadGuard<MemTable<K, V>> {
+        self.mutable.read()
+    }
+
     /// Gets the immutable `MemTable` instance (read-only).
     pub fn immutable_memtable(&self) -> RwLockReadGuard<MemTable<K, V>> {
         self.immutable.read()
@@ -146,19 +217,30 @@ impl<K: RecordKey<K>, V: RecordValue> MemTable<K, V> {
         on_drop_record: Option<Arc<dyn Fn(&dyn AsKV<K, V>)>>,
     ) -> Self {
         Self {
-            table: BTreeMap::new(),
-            size: 0,
+            table: SkipList::new(),
+            tombstones: RangeTombstones::new(),
+            size: AtomicUsize::new(0),
             cap,
             sync_id,
-            unsynced_range: None,
+            unsynced_start: AtomicUsize::new(usize::MAX),
+            unsynced_end: AtomicUsize::new(0),
             on_drop_record,
         }
     }
 
-    /// Gets the target value given the key.
-    pub fn get(&self, key: &K) -> Option<&V> {
-        let value_ex = self.table.get(key)?;
-        Some(value_ex.get())
+    /// Looks up the given key.
+    pub fn get(&self, key: &K) -> Lookup<V> {
+        if let Some(value_ex) = self.table.get(key)
+            && let Some(value) = value_ex.lock().as_ref().and_then(ValueEx::get)
+        {
+            return Lookup::Found(*value);
+        }
+
+        if self.tombstones.covers(key) {
+            Lookup::Deleted
+        } else {
+            Lookup::Missing
+        }
     }
 
     /// Range query, returns whether the request is completed.
@@ -166,78 +248,136 @@ impl<K: RecordKey<K>, V: RecordValue> MemTable<K, V> {
         debug_assert!(!range_query_ctx.is_completed());
         let target_range = range_query_ctx.range_uncompleted().unwrap();
 
-        for (k, v_ex) in self.table.range(target_range) {
-            range_query_ctx.complete(*k, *v_ex.get());
+        for (k, v_ex) in self
+            .table
+            .iter_from(target_range.start())
+            .take_while(|(k, _)| *k <= target_range.end())
+        {
+            if let Some(value) = v_ex.lock().as_ref().and_then(ValueEx::get) {
+                range_query_ctx.complete(*k, *value);
+            }
+        }
+
+        if !self.tombstones.is_empty() {
+            for key in range_query_ctx.uncompleted_keys() {
+                if self.tombstones.covers(&key) {
+                    range_query_ctx.mark_deleted(key);
+                }
+            }
         }
 
         range_query_ctx.is_completed()
     }
 
     /// Puts a new K-V record to the table, drop the old one.
-    pub fn put(&mut self, key: K, value: V) -> Option<V> {
-        let dropped_value = if let Some(value_ex) = self.table.get_mut(&key) {
-            if let Some(dropped) = value_ex.put(value) {
-                self.on_drop_record
-                    .as_ref()
-                    .map(|on_drop_record| on_drop_record(&(key, dropped)));
-                Some(dropped)
-            } else {
-                self.size += 1;
-                None
-            }
-        } else {
-            let _ = self.table.insert(key, ValueEx::new(value));
-            self.size += 1;
+    /// Can be called concurrently.
+    pub fn put(&self, key: K, value: V) -> Option<V> {
+        let (value_ex, inserted) = self
+            .table
+            .get_or_insert_with(key, || Mutex::new(Some(ValueEx::new(value))));
+        let dropped_value = if inserted {
             None
+        } else {
+            let mut value_ex = value_ex.lock();
+            match value_ex.as_mut() {
+                Some(value_ex) => value_ex.put(value),
+                None => {
+                    let _ = value_ex.insert(ValueEx::new(value));
+                    None
+                }
+            }
         };
 
-        if let Some(range) = self.unsynced_range.as_mut() {
-            if range.is_empty() {
-                *range = key..key + 1;
-            } else {
-                let start = key.min(range.start);
-                let end = (key + 1).max(range.end);
-                *range = start..end;
-            }
+        if let Some(dropped) = dropped_value {
+            self.on_drop_record
+                .as_ref()
+                .map(|on_drop_record| on_drop_record(&(key, dropped)));
+        } else {
+            self.size.fetch_add(1, Ordering::Relaxed);
         }
+
+        self.extend_unsynced_range(key..key + 1);
         dropped_value
     }
 
-    /// Sync the table, update the sync ID, drop the replaced one.
-    pub fn sync(&mut self, sync_id: SyncId) {
-        debug_assert!(self.sync_id <= sync_id);
-        if self.sync_id == sync_id {
+    /// Deletes the records within the range, drop the deleted unsynced
+    /// values, and keep a range tombstone for the older tables.
+    pub fn delete_range(&mut self, range: Range<K>) {
+        if range.is_empty() {
             return;
         }
 
-        let filter_unsynced: Box<dyn Iterator<Item = _>> = if let Some(range) = &self.unsynced_range
+        for (k, v_ex) in self
+            .table
+            .iter_from(&range.start)
+            .take_while(|(k, _)| **k < range.end)
         {
-            Box::new(
-                self.table
-                    .range_mut(range.clone())
-                    .filter(|(_, v_ex)| v_ex.contains_unsynced()),
-            )
-        } else {
-            Box::new(
-                self.table
-                    .iter_mut()
-                    .filter(|(_, v_ex)| v_ex.contains_unsynced()),
-            )
-        };
-        for (k, v_ex) in filter_unsynced {
-            if let Some(dropped) = v_ex.sync() {
+            let mut v_ex = v_ex.lock();
+            let Some(existed) = v_ex.take() else {
+                continue;
+            };
+            let (deleted, dropped_value) = existed.delete();
+            *v_ex = deleted;
+
+            if let Some(dropped) = dropped_value {
                 self.on_drop_record
                     .as_ref()
                     .map(|on_drop_record| on_drop_record(&(*k, dropped)));
-                self.size -= 1;
+                self.size.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+
+        self.tombstones.insert(range.clone(), false);
+        self.size.fetch_add(1, Ordering::Relaxed);
+        self.extend_unsynced_range(range);
+    }
+
+    /// Extends the range of keys which may have unsynced values.
+    fn extend_unsynced_range(&self, range: Range<K>) {
+        let (start, end) = (range.start - K::new_uninit(), range.end - K::new_uninit());
+        self.unsynced_start.fetch_min(start, Ordering::Relaxed);
+        self.unsynced_end.fetch_max(end, Ordering::Relaxed);
+    }
+
+    /// Sync the table, update the sync ID, drop the replaced one.
+    pub fn sync(&mut self, sync_id: SyncId) {
+        debug_assert!(self.sync_id <= sync_id);
+        if self.sync_id == sync_id {
+            return;
+        }
+
+        if let Some(range) = self.unsynced_range() {
+            for (k, v_ex) in self
+                .table
+                .iter_from(&range.start)
+                .take_while(|(k, _)| **k < range.end)
+            {
+                let mut v_ex = v_ex.lock();
+                if !v_ex.as_ref().is_some_and(ValueEx::contains_unsynced) {
+                    continue;
+                }
+                let (synced, dropped_value) = v_ex.take().unwrap().sync();
+                *v_ex = synced;
+                if let Some(dropped) = dropped_value {
+                    self.on_drop_record
+                        .as_ref()
+                        .map(|on_drop_record| on_drop_record(&(*k, dropped)));
+                    self.size.fetch_sub(1, Ordering::Relaxed);
+                }
             }
         }
 
+        self.tombstones.sync();
         self.sync_id = sync_id;
-        // Insert an empty range upon first sync
-        let _ = self
-            .unsynced_range
-            .get_or_insert_with(|| K::new_uninit()..K::new_uninit());
+        *self.unsynced_start.get_mut() = usize::MAX;
+        *self.unsynced_end.get_mut() = 0;
+    }
+
+    /// Return the range of keys which may have unsynced values.
+    fn unsynced_range(&self) -> Option<Range<K>> {
+        let start = self.unsynced_start.load(Ordering::Relaxed);
+        let end = self.unsynced_end.load(Ordering::Relaxed);
+        (start < end).then(|| K::new_uninit() + start..K::new_uninit() + end)
     }
 
     /// Return the sync ID of this table.
@@ -246,30 +386,39 @@ impl<K: RecordKey<K>, V: RecordValue> MemTable<K, V> {
     }
 
     /// Return an iterator over the table.
-    pub fn iter(&self) -> impl Iterator<Item = (&K, &ValueEx<V>)> {
-        self.table.iter()
+    pub fn iter(&self) -> impl Iterator<Item = (K, ValueEx<V>)> + '_ {
+        self.table
+            .iter()
+            .filter_map(|(k, v_ex)| v_ex.lock().clone().map(|v_ex| (*k, v_ex)))
+    }
+
+    /// Return the range tombstones of the table.
+    pub fn tombstones(&self) -> &RangeTombstones<K> {
+        &self.tombstones
     }
 
     /// Return the number of records in the table.
     pub fn size(&self) -> usize {
-        self.size
+        self.size.load(Ordering::Relaxed)
     }
 
     /// Return whether the table is empty.
     pub fn is_empty(&self) -> bool {
-        self.size == 0
+        self.size() == 0
     }
 
     /// Return whether the table is full.
     pub fn at_capacity(&self) -> bool {
-        self.size >= self.cap
+        self.size() >= self.cap
     }
 
     /// Clear all records from the table.
     pub fn clear(&mut self) {
         self.table.clear();
-        self.size = 0;
-        self.unsynced_range = None;
+        self.tombstones = RangeTombstones::new();
+        *self.size.get_mut() = 0;
+        *self.unsynced_start.get_mut() = usize::MAX;
+        *self.unsynced_end.get_mut() = 0;
     }
 }
 
@@ -279,12 +428,23 @@ impl<V: RecordValue> ValueEx<V> {
         Self::Unsynced(value)
     }
 
-    /// Gets the most recent value.
-    pub fn get(&self) -> &V {
+    /// Gets the most recent value, return `None` if it is deleted.
+    pub fn get(&self) -> Option<&V> {
+        match self {
+            Self::Synced(v) => Some(v),
+            Self::Unsynced(v) => Some(v),
+            Self::SyncedAndUnsynced(_, v) => Some(v),
+            Self::SyncedAndDeleted(_) => None,
+        }
+    }
+
+    /// Gets the synced value, return `None` if there is none.
+    pub fn synced(&self) -> Option<&V> {
         match self {
-            Self::Synced(v) => v,
-            Self::Unsynced(v) => v,
-            Self::SyncedAndUnsynced(_, v) => v,
+            Self::Synced(v) => Some(v),
+            Self::Unsynced(_) => None,
+            Self::SyncedAndUnsynced(v, _) => Some(v),
+            Self::SyncedAndDeleted(v) => Some(v),
         }
     }
 
@@ -305,33 +465,45 @@ impl<V: RecordValue> ValueEx<V> {
                 *self = Self::SyncedAndUnsynced(sv, value);
                 Some(usv)
             }
+            ValueEx::SyncedAndDeleted(sv) => {
+                *self = Self::SyncedAndUnsynced(sv, value);
+                None
+            }
         };
         dropped
     }
 
-    /// Sync the value, return the replaced value if any.
-    fn sync(&mut self) -> Option<V> {
+    /// Sync the value, return the synced value (`None` if it is deleted)
+    /// and the replaced value if any.
+    fn sync(self) -> (Option<Self>, Option<V>) {
         debug_assert!(self.contains_unsynced());
-        let existed = core::mem::take(self);
+        match self {
+            ValueEx::Unsynced(v) => (Some(Self::Synced(v)), None),
+            ValueEx::SyncedAndUnsynced(sv, usv) => (Some(Self::Synced(usv)), Some(sv)),
+            ValueEx::SyncedAndDeleted(sv) => (None, Some(sv)),
+            ValueEx::Synced(_) => unreachable!(),
+        }
+    }
 
-        let dropped = match existed {
-            ValueEx::Unsynced(v) => {
-                *self = Self::Synced(v);
-                None
-            }
-            ValueEx::SyncedAndUnsynced(sv, usv) => {
-                *self = Self::Synced(usv);
-                Some(sv)
+    /// Deletes the value by an unsynced range tombstone, return the
+    /// remained value (`None` if nothing remains) and the dropped value if any.
+    /// The synced value is kept until the deletion is synced.
+    pub fn delete(self) -> (Option<Self>, Option<V>) {
+        match self {
+            ValueEx::Synced(sv) | ValueEx::SyncedAndDeleted(sv) => {
+                (Some(Self::SyncedAndDeleted(sv)), None)
             }
-            ValueEx::Synced(_) => unreachable!(),
-        };
-        dropped
+            ValueEx::Unsynced(usv) => (None, Some(usv)),
+            ValueEx::SyncedAndUnsynced(sv, usv) => (Some(Self::SyncedAndDeleted(sv)), Some(usv)),
+        }
     }
 
-    /// Whether the value contains an unsynced value.
+    /// Whether the value contains an unsynced value or an unsynced deletion.
     pub fn contains_unsynced(&self) -> bool {
         match self {
-            ValueEx::Unsynced(_) | ValueEx::SyncedAndUnsynced(_, _) => true,
+            ValueEx::Unsynced(_)
+            | ValueEx::SyncedAndUnsynced(_, _)
+            | ValueEx::SyncedAndDeleted(_) => true,
             ValueEx::Synced(_) => false,
         }
     }
@@ -346,7 +518,7 @@ impl<V: RecordValue> Default for ValueEx<V> {
 impl<K: RecordKey<K>, V: RecordValue> Debug for MemTableManager<K, V> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("MemTableManager")
-            .field("mutable_memtable_size", &self.mutable.lock().size())
+            .field("mutable_memtable_size", &self.mutable.read().size())
             .field("immutable_memtable_size", &self.immutable_memtable().size())
             .finish()
     }
@@ -377,7 +549,7 @@ mod tests {
         table.sync(1);
         table.put(2, 32);
         assert_eq!(table.size(), 3);
-        assert_eq!(*table.get(&2).unwrap(), 32);
+        assert_eq!(table.get(&2), Lookup::Found(32));
 
         table.sync(2);
         assert_eq!(drop_count.load(Ordering::Relaxed), 2);
@@ -394,4 +566,39 @@ mod tests {
         assert_eq!(table.is_empty(), true);
         Ok(())
     }
+
+    #[test]
+    fn memtable_delete_range() -> Result<()> {
+        let drop_count = Arc::new(AtomicU16::new(0));
+        let dc = drop_count.clone();
+        let drop_fn = move |_: &dyn AsKV<usize, u16>| {
+            dc.fetch_add(1, Ordering::Relaxed);
+        };
+        let mut table = MemTable::<usize, u16>::new(8, 0, Some(Arc::new(drop_fn)));
+
+        table.put(1, 11);
+        table.put(2, 12);
+        table.sync(1);
+        table.put(2, 22);
+        table.put(3, 13);
+
+        // The unsynced values are dropped at once, the synced ones on sync
+        table.delete_range(2..4);
+        assert_eq!(drop_count.load(Ordering::Relaxed), 2);
+        assert_eq!(table.get(&1), Lookup::Found(11));
+        assert_eq!(table.get(&2), Lookup::Deleted);
+        assert_eq!(table.get(&5), Lookup::Missing);
+
+        table.put(3, 23);
+        let mut range_query_ctx = RangeQueryCtx::new(1, 3);
+        assert_eq!(table.get_range(&mut range_query_ctx), true);
+        assert_eq!(range_query_ctx.into_results(), vec![(1, 11), (3, 23)]);
+
+        table.sync(2);
+        assert_eq!(drop_count.load(Ordering::Relaxed), 3);
+        assert_eq!(table.get(&2), Lookup::Deleted);
+        assert_eq!(table.iter().count(), 2);
+        assert_eq!(table.tombstones().covering(&2), Some(true));
+        Ok(())
+    }
 }
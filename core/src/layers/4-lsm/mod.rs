@@ -1,14 +1,14 @@
 //! The layer of transactional Lsm-Tree.
 //!
 //! This module provides the implementation for `TxLsmTree`.
-//! `TxLsmTree` is similar to general-purpose LSM-Tree, supporting `put()`, `get()`, `get_range()`
-//! key-value records, which are managed in MemTables and SSTables.
+//! `TxLsmTree` is similar to general-purpose LSM-Tree, supporting `put()`, `get()`, `get_range()`,
+//! `delete_range()` and `iter()` key-value records, which are managed in MemTables and SSTables.
 //!
 //! `TxLsmTree` is transactional in the sense that
 //! 1) it supports `sync()` that guarantees changes are persisted atomically and irreversibly,
 //! synchronized records and unsynchronized records can co-existed.
 //! 2) its internal data is securely stored in `TxLogStore` (L3) and updated in transactions for consistency,
-//! WALs and SSTables are stored and managed in `TxLogStore`.
+//! WALs, checkpoints and SSTables are stored and managed in `TxLogStore`.
 //!
 //! `TxLsmTree` supports piggybacking callbacks during compaction and recovery.
 //!
@@ -61,15 +61,22 @@
 //! }
 //! ```
 
+mod bloom_filter;
+mod checkpoint;
 mod compaction;
 mod mem_table;
 mod range_query_ctx;
+mod skiplist;
 mod sstable;
+mod tombstone;
+mod tree_iter;
 mod tx_lsm_tree;
 mod wal;
 
+pub use self::compaction::CompactionPolicyKind;
 pub use self::range_query_ctx::RangeQueryCtx;
+pub use self::tree_iter::TreeIter;
 pub use self::tx_lsm_tree::{
-    AsKV, LsmLevel, RecordKey, RecordValue, SyncId, SyncIdStore, TxEventListener,
-    TxEventListenerFactory, TxLsmTree, TxType,
+    AsKV, CompactionScheduler, LsmLevel, LsmParams, RecordKey, RecordValue, SyncId, SyncIdStore,
+    TxEventListener, TxEventListenerFactory, TxLsmTree, TxType, ValueFormat,
 };
@@ -15,6 +15,7 @@ pub struct RangeQueryCtx<K, V> {
     complete_table: BitMap,
     min_uncompleted: usize,
     res: Vec<(K, V)>,
+    deleted: Vec<K>,
 }
 
 impl<K: RecordKey<K>, V: RecordValue> RangeQueryCtx<K, V> {
@@ -27,6 +28,7 @@ impl<K: RecordKey<K>, V: RecordValue> RangeQueryCtx<K, V> {
             complete_table: BitMap::repeat(false, num_values),
             min_uncompleted: 0,
             res: Vec::with_capacity(num_values),
+            deleted: Vec::new(),
         }
     }
 
@@ -76,12 +78,40 @@ impl<K: RecordKey<K>, V: RecordValue> RangeQueryCtx<K, V> {
         self.update_min_uncompleted(nth);
     }
 
+    /// Complete one slot within the range whose key is deleted
+    /// by a range tombstone, so it has no value.
+    pub fn mark_deleted(&mut self, key: K) {
+        if self.contains_uncompleted(&key) {
+            self.deleted.push(key);
+            self.mark_completed(key);
+        }
+    }
+
+    /// Gets the keys of the slots completed by `mark_deleted()`.
+    pub fn deleted_keys(&self) -> &[K] {
+        &self.deleted
+    }
+
     /// Turn the context into final results.
     pub fn into_results(self) -> Vec<(K, V)> {
         debug_assert!(self.is_completed());
         self.res
     }
 
+    /// Gets the keys of all the uncompleted slots.
+    pub fn uncompleted_keys(&self) -> Vec<K> {
+        (self.min_uncompleted..self.num_values)
+            .filter(|&nth| !self.complete_table[nth])
+            .map(|nth| self.start + nth)
+            .collect()
+    }
+
+    /// Turn the context into the results of completed slots,
+    /// the uncompleted slots are left out.
+    pub fn into_completed_results(self) -> Vec<(K, V)> {
+        self.res
+    }
+
     fn update_min_uncompleted(&mut self, completed_nth: usize) {
         if self.min_uncompleted == completed_nth {
             if let Some(next_uncompleted) = self.complete_table.first_zero(completed_nth) {
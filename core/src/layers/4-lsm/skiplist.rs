@@ -0,0 +1,249 @@
+//! Concurrent skiplist of a `MemTable`.
+use crate::prelude::*;
+
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+/// The maximum height of a node, enough for millions of entries.
+const MAX_HEIGHT: usize = 12;
+
+/// An ordered map based on a lock-free skiplist.
+///
+/// Insertions link a new node level by level with CAS, so concurrent
+/// writers and readers never block each other. Entries are never removed
+/// individually; the values are updated in place through interior mutability.
+/// All nodes are reclaimed at once by `clear()` or on drop, both requiring
+/// exclusive access, i.e., a `MemTable` generation acts as the reclamation
+/// epoch. So a reader can never observe a freed node.
+pub(super) struct SkipList<K, V> {
+    head: [AtomicPtr<Node<K, V>>; MAX_HEIGHT],
+    len: AtomicUsize,
+    seed: AtomicU64,
+    _marker: PhantomData<Box<Node<K, V>>>,
+}
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    next: Box<[AtomicPtr<Node<K, V>>]>,
+}
+
+/// An iterator over the entries of a `SkipList` in key order.
+pub(super) struct Iter<'a, K, V> {
+    next: *const Node<K, V>,
+    _marker: PhantomData<&'a SkipList<K, V>>,
+}
+
+impl<K: Ord, V> SkipList<K, V> {
+    /// Creates an empty skiplist.
+    pub fn new() -> Self {
+        Self {
+            head: Default::default(),
+            len: AtomicUsize::new(0),
+            seed: AtomicU64::new(0x2545_f491_4f6c_dd1d),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Gets the value of the key.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let node = unsafe { self.lower_bound(key).as_ref()? };
+        (node.key == *key).then_some(&node.value)
+    }
+
+    /// Gets the value of the key, or inserts the value made by `f`
+    /// if the key is absent. Return the value and whether it is inserted.
+    ///
+    /// If a concurrent insertion of the same key wins, the value made
+    /// by `f` is dropped and the winner's one is returned.
+    pub fn get_or_insert_with(&self, key: K, f: impl FnOnce() -> V) -> (&V, bool) {
+        let mut preds = [&self.head[..]; MAX_HEIGHT];
+        let mut succs = [ptr::null_mut(); MAX_HEIGHT];
+        if let Some(node) = self.find(&key, &mut preds, &mut succs) {
+            return (&node.value, false);
+        }
+
+        let height = self.random_height();
+        let new = Box::into_raw(Box::new(Node {
+            key,
+            value: f(),
+            next: succs[..height]
+                .iter()
+                .map(|&succ| AtomicPtr::new(succ))
+                .collect(),
+        }));
+        // Safety: the node is not freed until the skiplist is cleared
+        let node = unsafe { &*new };
+
+        // Linking the bottom level makes the node visible
+        while preds[0][0]
+            .compare_exchange(succs[0], new, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            if let Some(existed) = self.find(&node.key, &mut preds, &mut succs) {
+                drop(unsafe { Box::from_raw(new) });
+                return (&existed.value, false);
+            }
+            for (level, next) in node.next.iter().enumerate() {
+                next.store(succs[level], Ordering::Relaxed);
+            }
+        }
+
+        // Link the upper levels, which only speeds up searches
+        for level in 1..height {
+            loop {
+                node.next[level].store(succs[level], Ordering::Release);
+                if preds[level][level]
+                    .compare_exchange(succs[level], new, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    break;
+                }
+                let _ = self.find(&node.key, &mut preds, &mut succs);
+            }
+        }
+
+        self.len.fetch_add(1, Ordering::Relaxed);
+        (&node.value, true)
+    }
+
+    /// Return an iterator over all entries.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            next: self.head[0].load(Ordering::Acquire),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Return an iterator over the entries whose keys are not less than `key`.
+    pub fn iter_from(&self, key: &K) -> Iter<'_, K, V> {
+        Iter {
+            next: self.lower_bound(key),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Return the number of entries.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Finds the first node whose key is not less than `key`.
+    fn lower_bound(&self, key: &K) -> *mut Node<K, V> {
+        let mut tower = &self.head[..];
+        let mut next = ptr::null_mut();
+        for level in (0..MAX_HEIGHT).rev() {
+            next = tower[level].load(Ordering::Acquire);
+            while let Some(node) = unsafe { next.as_ref() }
+                && node.key < *key
+            {
+                tower = &node.next;
+                next = tower[level].load(Ordering::Acquire);
+            }
+        }
+        next
+    }
+
+    /// Finds the predecessor towers and successors of `key` on each level.
+    /// Return the node of the key if it is visible.
+    fn find<'a>(
+        &'a self,
+        key: &K,
+        preds: &mut [&'a [AtomicPtr<Node<K, V>>]; MAX_HEIGHT],
+        succs: &mut [*mut Node<K, V>; MAX_HEIGHT],
+    ) -> Option<&'a Node<K, V>> {
+        let mut tower = &self.head[..];
+        for level in (0..MAX_HEIGHT).rev() {
+            let mut next = tower[level].load(Ordering::Acquire);
+            while let Some(node) = unsafe { next.as_ref() }
+                && node.key < *key
+            {
+                tower = &node.next;
+                next = tower[level].load(Ordering::Acquire);
+            }
+            preds[level] = tower;
+            succs[level] = next;
+        }
+        unsafe { succs[0].as_ref() }.filter(|node| node.key == *key)
+    }
+
+    /// Picks a height with a branching factor of 4.
+    fn random_height(&self) -> usize {
+        let mut x = self
+            .seed
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed);
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^= x >> 31;
+        (1 + x.trailing_zeros() as usize / 2).min(MAX_HEIGHT)
+    }
+}
+
+impl<K, V> SkipList<K, V> {
+    /// Removes all entries and frees the nodes.
+    pub fn clear(&mut self) {
+        let mut next = *self.head[0].get_mut();
+        while !next.is_null() {
+            let mut node = unsafe { Box::from_raw(next) };
+            next = *node.next[0].get_mut();
+        }
+        for head in self.head.iter_mut() {
+            *head.get_mut() = ptr::null_mut();
+        }
+        *self.len.get_mut() = 0;
+    }
+}
+
+impl<K, V> Drop for SkipList<K, V> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = unsafe { self.next.as_ref()? };
+        self.next = node.next[0].load(Ordering::Acquire);
+        Some((&node.key, &node.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SkipList;
+    use crate::os::{spawn, Arc};
+
+    #[test]
+    fn skiplist_concurrent_insert() {
+        let list = Arc::new(SkipList::<usize, usize>::new());
+        let num_threads = 4;
+        let num_keys = 2000;
+
+        let handles: Vec<_> = (0..num_threads)
+            .map(|tid| {
+                let list = list.clone();
+                // Interleave keys and insert each key from two threads
+                spawn(move || {
+                    for i in 0..num_keys {
+                        let key = (i * num_threads + tid) / 2;
+                        let _ = list.get_or_insert_with(key, || key * 10);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let total = num_keys * num_threads / 2;
+        assert_eq!(list.len(), total);
+        assert!(list.iter().map(|(k, _)| *k).eq(0..total));
+        assert!(list.iter().all(|(k, v)| *v == k * 10));
+        assert_eq!(list.get(&7), Some(&70));
+        assert_eq!(list.get(&total), None);
+        assert_eq!(list.iter_from(&(total - 2)).count(), 2);
+    }
+}
@@ -1,11 +1,14 @@
 //! Sorted String Table.
+use super::bloom_filter::BloomFilter;
 use super::mem_table::ValueEx;
+use super::tombstone::{Lookup, RangeTombstones};
 use super::tx_lsm_tree::AsKVex;
-use super::{RangeQueryCtx, RecordKey, RecordValue, SyncId, TxEventListener};
+use super::{RangeQueryCtx, RecordKey, RecordValue, SyncId, TxEventListener, ValueFormat};
 use crate::layers::bio::{BlockSet, Buf, BufMut, BufRef, BID_SIZE};
+use crate::layers::disk::{CacheTier, Config, CACHE_STATS};
 use crate::layers::log::{TxLog, TxLogId, TxLogStore};
 use crate::os::Mutex;
-use crate::{prelude::*, CONFIG};
+use crate::prelude::*;
 
 use core::marker::PhantomData;
 use core::mem::size_of;
@@ -19,10 +22,19 @@ use pod::Pod;
 /// Responsible for storing, managing key-value records on a `TxLog` (L3).
 /// Records are serialized, sorted, organized on the `TxLog`.
 /// Supports three access modes: point query, range query and whole scan.
+///
+/// Range tombstones are kept aside from the records, they only delete the
+/// records of the older `SSTable`s. The range of a `SSTable` covers both.
 pub(super) struct SSTable<K, V> {
     id: TxLogId,
     footer: Footer<K>,
+    range: RangeInclusive<K>,
+    tombstones: RangeTombstones<K>,
+    /// The format of the values, see `LsmParams::value_format`.
+    value_format: ValueFormat,
     cache: Option<Mutex<LruCache<BlockId, Arc<RecordBlock>>>>,
+    /// The Bloom filter of keys, loaded on the first lookup.
+    filter: Mutex<Option<Arc<BloomFilter>>>,
     phantom: PhantomData<(K, V)>,
 }
 
@@ -42,10 +54,26 @@ struct FooterMeta {
     index_nblocks: u16,
     total_records: u32,
     record_block_size: u32,
+    /// The number of blocks of the Bloom filter, which resides between
+    /// the record blocks and the index blocks. Zero if there is no filter.
+    filter_nblocks: u32,
+    /// The number of blocks of the range tombstones, which reside between
+    /// the filter and the index blocks. Zero if there is no tombstone.
+    tombstone_nblocks: u32,
+    /// The version of the footer layout, see `FOOTER_VERSION`. It resides
+    /// right before the sync ID at the end of the footer block, so that the
+    /// footer of any version tells its version.
+    version: u32,
     sync_id: SyncId,
 }
 const FOOTER_META_SIZE: usize = size_of::<FooterMeta>();
 
+/// The version of the footer layout, bumped on each incompatible change.
+/// Footers of newer versions are refused. Those of version zero are written
+/// before the version is recorded (in a reserved field), whose layout is
+/// the same as version one.
+const FOOTER_VERSION: u32 = 1;
+
 /// Index entry to describe a `RecordBlock` in a `SSTable`.
 #[derive(Debug)]
 struct IndexEntry<K> {
@@ -58,9 +86,11 @@ struct IndexEntry<K> {
 struct RecordBlock {
     buf: Vec<u8>,
 }
-const RECORD_BLOCK_NBLOCKS: usize = 32;
-/// The size of a `RecordBlock`, which is a multiple of `BLOCK_SIZE`.
-const RECORD_BLOCK_SIZE: usize = RECORD_BLOCK_NBLOCKS * BLOCK_SIZE;
+/// The default size of a `RecordBlock`, which is a multiple of `BLOCK_SIZE`.
+/// The size is configurable (see `LsmParams`) and recorded in the footer.
+pub(super) const RECORD_BLOCK_SIZE: usize = 32 * BLOCK_SIZE;
+/// The maximum size of a `RecordBlock`.
+pub(super) const MAX_RECORD_BLOCK_SIZE: usize = 256 * BLOCK_SIZE;
 
 /// Accessor for a query.
 enum QueryAccessor<K> {
@@ -72,12 +102,15 @@ enum QueryAccessor<K> {
 struct BlockQueryIter<'a, K, V> {
     block: &'a RecordBlock,
     offset: usize,
+    value_format: ValueFormat,
     accessor: &'a QueryAccessor<K>,
+    extent: Option<ExtentCursor<K, V>>,
     phantom: PhantomData<(K, V)>,
 }
 
 /// Accessor for a whole table scan.
 struct ScanAccessor<'a, K, V> {
+    value_format: ValueFormat,
     all_synced: bool,
     discard_unsynced: bool,
     event_listener: Option<&'a Arc<dyn TxEventListener<K, V>>>,
@@ -88,8 +121,32 @@ struct BlockScanIter<'a, K, V> {
     block: Arc<RecordBlock>,
     offset: usize,
     accessor: ScanAccessor<'a, K, V>,
+    extent: Option<ExtentCursor<K, V>>,
+}
+
+/// The last record of an extent being built in a `RecordBlock`.
+struct ExtentTail<K, V> {
+    key: K,
+    value: V,
+    synced: bool,
+    /// The offset of the flag of the first record of the extent.
+    flag_offset: usize,
+    /// The number of the successors of the first record.
+    count: u16,
 }
 
+/// The last record split from an extent being iterated in a `RecordBlock`.
+struct ExtentCursor<K, V> {
+    key: K,
+    value: V,
+    synced: bool,
+    /// The number of the successors not split yet.
+    remaining: usize,
+}
+
+/// The size of the number of the successors in an extent.
+const EXTENT_COUNT_SIZE: usize = size_of::<u16>();
+
 /// Iterator over `SSTable`.
 pub(super) struct SstIter<'a, K, V, D> {
     sst: &'a SSTable<K, V>,
@@ -101,41 +158,51 @@ pub(super) struct SstIter<'a, K, V, D> {
 /// Format on a `TxLog`:
 ///
 /// ```text
-/// |    [Record]     |    [Record]     |...|         Footer            |
-/// |K|flag|V(V)| ... |    [Record]     |...| [IndexEntry] | FooterMeta |
-/// |RECORD_BLOCK_SIZE|RECORD_BLOCK_SIZE|...|                           |
+/// |    [Record]     |    [Record]     |...|  Filter  |   Tombstones   |         Footer            |
+/// |K|flag|V(V)| ... |    [Record]     |...|  [bits]  | [start|end|..] | [IndexEntry] | FooterMeta |
+/// |record_block_size|record_block_size|...|          |                |                           |
 /// ```
+///
+/// An extent, i.e., a run of records whose keys and values succeed the
+/// previous ones (see `RecordValue::succeeds()`), is stored within a record
+/// block as `|K|flag|V|n|S|..|`, where `S` are the `n` successors of `V`.
+/// Extents are split back into records when they are read.
 impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
     const K_SIZE: usize = size_of::<K>();
-    const V_SIZE: usize = size_of::<V>();
     const FLAG_SIZE: usize = size_of::<RecordFlag>();
-    const MIN_RECORD_SIZE: usize = BID_SIZE + Self::FLAG_SIZE + Self::V_SIZE;
-    const MAX_RECORD_SIZE: usize = BID_SIZE + Self::FLAG_SIZE + 2 * Self::V_SIZE;
     const INDEX_ENTRY_SIZE: usize = BID_SIZE + 2 * Self::K_SIZE;
     const CACHE_CAP: usize = 1024;
 
-    /// Calculate cache capacity per SSTable based on global configuration.
+    /// Return the size of a value encoded in `value_format`.
+    fn v_size(value_format: ValueFormat) -> usize {
+        V::encoded_size(value_format)
+    }
+
+    /// Return the maximum size of a record whose values are in `value_format`.
+    fn max_record_size(value_format: ValueFormat) -> usize {
+        BID_SIZE + Self::FLAG_SIZE + 2 * Self::v_size(value_format)
+    }
+
+    /// Calculate cache capacity per SSTable based on the configuration of the disk.
     ///
-    /// Distributes the global cache_size (in bytes) evenly across all SSTables.
+    /// Distributes the size (in bytes) of the SST cache tier evenly across all SSTables.
     /// Converts bytes to number of RecordBlocks for LRU cache.
     /// For a 100GB disk with 8GB per SSTable, we have ~13 SSTables max.
-    fn cache_capacity() -> usize {
-        use crate::layers::disk::CONFIG;
-
+    fn cache_capacity(record_block_size: usize, config: &Config) -> usize {
         // Maximum number of SSTables: 100GB disk / 8GB per SSTable
         const MAX_SST_COUNT: usize = 13;
         const MIN_CACHE_CAP: usize = 64; // Minimum cache blocks per SSTable
 
-        let total_cache_bytes = CONFIG.get().cache_size;
+        let total_cache_bytes = config.cache_tier_size(CacheTier::SstBlock);
 
-        // If cache_size is default (usize::MAX), use the original hardcoded value
+        // If the size is default (usize::MAX), use the original hardcoded value
         if total_cache_bytes == usize::MAX {
             return Self::CACHE_CAP;
         }
 
         // Convert bytes to number of RecordBlocks
-        // Each RecordBlock is RECORD_BLOCK_SIZE bytes
-        let total_cache_blocks = total_cache_bytes / RECORD_BLOCK_SIZE;
+        // Each RecordBlock is record_block_size bytes
+        let total_cache_blocks = total_cache_bytes / record_block_size;
 
         // Evenly distribute cache blocks across all SSTables, with a minimum threshold
         let cache_per_sst = (total_cache_blocks / MAX_SST_COUNT).max(MIN_CACHE_CAP);
@@ -159,12 +226,40 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
         self.footer.meta.sync_id
     }
 
-    /// The range of keys covered by this `SSTable`.
+    /// Return the size of the record blocks of this `SSTable`.
+    fn record_block_size(&self) -> usize {
+        self.footer.meta.record_block_size as _
+    }
+
+    /// Return the memory (in bytes) taken by the cached record blocks.
+    pub fn cached_bytes(&self) -> usize {
+        self.cache
+            .as_ref()
+            .map_or(0, |cache| cache.lock().len() * self.record_block_size())
+    }
+
+    /// The range of keys covered by this `SSTable`, including
+    /// the range tombstones.
     pub fn range(&self) -> RangeInclusive<K> {
-        RangeInclusive::new(
-            self.footer.index[0].first,
-            self.footer.index[self.footer.meta.num_index as usize - 1].last,
-        )
+        self.range.clone()
+    }
+
+    /// Calculate the range of keys covered by the records of the
+    /// index entries and the range tombstones.
+    fn calc_range(
+        index: &[IndexEntry<K>],
+        tombstones: &RangeTombstones<K>,
+    ) -> Option<RangeInclusive<K>> {
+        let records_range = index
+            .first()
+            .zip(index.last())
+            .map(|(first, last)| first.first..=last.last);
+        match (records_range, tombstones.span()) {
+            (Some(lhs), Some(rhs)) => {
+                Some(*lhs.start().min(rhs.start())..=*lhs.end().max(rhs.end()))
+            }
+            (range, None) | (None, range) => range,
+        }
     }
 
     /// Whether the target key is within the range, "within the range" doesn't mean
@@ -179,9 +274,70 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
         !(lhs_range.end() < rhs_range.start() || lhs_range.start() > rhs_range.end())
     }
 
+    /// Whether the key may be in this `SSTable` according to its Bloom filter.
+    /// The key is definitely absent if it returns `false`.
+    ///
+    /// # Panics
+    ///
+    /// This method must be called within a TX. Otherwise, this method panics.
+    pub fn may_contain<D: BlockSet + 'static>(
+        &self,
+        key: &K,
+        tx_log_store: &Arc<TxLogStore<D>>,
+    ) -> Result<bool> {
+        if self.footer.meta.filter_nblocks == 0 {
+            return Ok(true);
+        }
+        let filter = self.filter(tx_log_store)?;
+        Ok(filter.may_contain(BloomFilter::hash(key.as_bytes())))
+    }
+
+    /// Return the Bloom filter, load it from the log if not loaded yet.
+    fn filter<D: BlockSet + 'static>(
+        &self,
+        tx_log_store: &Arc<TxLogStore<D>>,
+    ) -> Result<Arc<BloomFilter>> {
+        let mut filter = self.filter.lock();
+        if let Some(filter) = filter.as_ref() {
+            return Ok(filter.clone());
+        }
+
+        let meta = &self.footer.meta;
+        let tx_log = tx_log_store.open_log(self.id, false)?;
+        let filter_pos = tx_log.nblocks()
+            - meta.index_nblocks as usize
+            - meta.tombstone_nblocks as usize
+            - meta.filter_nblocks as usize;
+        let mut buf = Buf::alloc(meta.filter_nblocks as _)?;
+        tx_log.read(filter_pos, buf.as_mut())?;
+        let loaded = Arc::new(BloomFilter::decode(buf.as_slice())?);
+        let _ = filter.insert(loaded.clone());
+        Ok(loaded)
+    }
+
+    /// Whether the key is deleted by the range tombstones of this `SSTable`,
+    /// i.e., the records of the key in older `SSTable`s are hidden.
+    pub fn is_deleted(&self, key: &K) -> bool {
+        self.tombstones.covers(key)
+    }
+
+    /// Return the range tombstones of this `SSTable`. They are treated
+    /// as synced ones if `sync_id` is newer, or the unsynced ones are
+    /// discarded if required, which is the same as `iter()`.
+    pub fn tombstones(&self, sync_id: SyncId, discard_unsynced: bool) -> RangeTombstones<K> {
+        let mut tombstones = self.tombstones.clone();
+        if sync_id > self.sync_id() {
+            tombstones.sync();
+        } else if discard_unsynced {
+            tombstones.discard_unsynced();
+        }
+        tombstones
+    }
+
     /// Accessing functions below
 
-    /// Point query.
+    /// Point query of the records, the range tombstones are not
+    /// checked (see `is_deleted()`).
     ///
     /// # Panics
     ///
@@ -190,20 +346,17 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
         &self,
         key: &K,
         tx_log_store: &Arc<TxLogStore<D>>,
-    ) -> Result<V> {
+    ) -> Result<Lookup<V>> {
         debug_assert!(self.range().contains(key));
-        let target_rb_pos = self
-            .footer
-            .index
-            .iter()
-            .find_map(|entry| {
-                if entry.is_within_range(key) {
-                    Some(entry.pos)
-                } else {
-                    None
-                }
-            })
-            .ok_or(Error::with_msg(NotFound, "target key not found in sst"))?;
+        let Some(target_rb_pos) = self.footer.index.iter().find_map(|entry| {
+            if entry.is_within_range(key) {
+                Some(entry.pos)
+            } else {
+                None
+            }
+        }) else {
+            return Ok(Lookup::Missing);
+        };
 
         let accessor = QueryAccessor::Point(*key);
         let target_rb = self.target_record_block(target_rb_pos, tx_log_store)?;
@@ -211,12 +364,14 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
         let mut iter = BlockQueryIter::<'_, K, V> {
             block: &target_rb,
             offset: 0,
+            value_format: self.value_format,
             accessor: &accessor,
+            extent: None,
             phantom: PhantomData,
         };
 
-        iter.find_map(|(k, v_opt)| if k == *key { v_opt } else { None })
-            .ok_or(Error::with_msg(NotFound, "target value not found in SST"))
+        let lookup = iter.find_map(|(k, lookup_opt)| if k == *key { lookup_opt } else { None });
+        Ok(lookup.unwrap_or(Lookup::Missing))
     }
 
     /// Range query.    
@@ -246,26 +401,44 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
             let iter = BlockQueryIter::<'_, K, V> {
                 block: &target_rb,
                 offset: 0,
+                value_format: self.value_format,
                 accessor: &accessor,
+                extent: None,
                 phantom: PhantomData,
             };
 
             let targets: Vec<_> = iter
-                .filter_map(|(k, v_opt)| {
+                .filter_map(|(k, lookup_opt)| {
                     if range_uncompleted.contains(&k) {
-                        Some((k, v_opt.unwrap()))
+                        Some((k, lookup_opt.unwrap()))
                     } else {
                         None
                     }
                 })
                 .collect();
-            for (target_k, target_v) in targets {
-                range_query_ctx.complete(target_k, target_v);
+            for (target_k, target) in targets {
+                match target {
+                    Lookup::Found(target_v) => range_query_ctx.complete(target_k, target_v),
+                    _ => range_query_ctx.mark_deleted(target_k),
+                }
             }
         }
         Ok(())
     }
 
+    /// Complete the uncompleted slots of a range query which are
+    /// deleted by the range tombstones of this `SSTable`.
+    pub fn complete_deleted(&self, range_query_ctx: &mut RangeQueryCtx<K, V>) {
+        if self.tombstones.is_empty() {
+            return;
+        }
+        for key in range_query_ctx.uncompleted_keys() {
+            if self.tombstones.covers(&key) {
+                range_query_ctx.mark_deleted(key);
+            }
+        }
+    }
+
     /// Locate the target record block given its position, it
     /// resides in either the cache or the log.
     fn target_record_block<D: BlockSet + 'static>(
@@ -275,20 +448,21 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
     ) -> Result<Arc<RecordBlock>> {
         if let Some(cache) = self.cache.as_ref() {
             let mut cache = cache.lock();
-            if let Some(cached_rb) = cache.get(&target_pos) {
-                return Ok(cached_rb.clone());
+            let cached_rb = cache.get(&target_pos).cloned();
+            CACHE_STATS.record_lookup(CacheTier::SstBlock, cached_rb.is_some());
+            if let Some(cached_rb) = cached_rb {
+                return Ok(cached_rb);
             }
         }
 
-        let mut rb = RecordBlock::from_buf(vec![0; RECORD_BLOCK_SIZE]);
+        let mut rb = RecordBlock::from_buf(vec![0; self.record_block_size()]);
         // TODO: Avoid opening the log on every call
         let tx_log = tx_log_store.open_log(self.id, false)?;
         tx_log.read(target_pos, BufMut::try_from(rb.as_mut_slice()).unwrap())?;
         let rb = Arc::new(rb);
 
         if let Some(cache) = self.cache.as_ref() {
-            let mut cache = cache.lock();
-            cache.put(target_pos, rb.clone());
+            cache_record_block(&mut cache.lock(), target_pos, rb.clone());
         }
         Ok(rb)
     }
@@ -306,26 +480,75 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
         discard_unsynced: bool,
         tx_log_store: &'a Arc<TxLogStore<D>>,
         event_listener: Option<&'a Arc<dyn TxEventListener<K, V>>>,
+    ) -> SstIter<'a, K, V, D> {
+        self.iter_from_nth_index(0, sync_id, discard_unsynced, tx_log_store, event_listener)
+    }
+
+    /// Return the iterator over the records within `range` of this `SSTable`,
+    /// the record blocks before the range are skipped.
+    ///
+    /// # Panics
+    ///
+    /// This method must be called within a TX. Otherwise, this method panics.
+    pub fn iter_range<'a, D: BlockSet + 'static>(
+        &'a self,
+        range: &RangeInclusive<K>,
+        sync_id: SyncId,
+        discard_unsynced: bool,
+        tx_log_store: &'a Arc<TxLogStore<D>>,
+        event_listener: Option<&'a Arc<dyn TxEventListener<K, V>>>,
+    ) -> impl Iterator<Item = (K, ValueEx<V>)> + 'a {
+        let (start, end) = (*range.start(), *range.end());
+        let nth_index = self
+            .footer
+            .index
+            .partition_point(|entry| entry.last < start);
+        let iter = (nth_index < self.footer.index.len()).then(|| {
+            self.iter_from_nth_index(
+                nth_index,
+                sync_id,
+                discard_unsynced,
+                tx_log_store,
+                event_listener,
+            )
+        });
+        iter.into_iter()
+            .flatten()
+            .skip_while(move |(k, _)| *k < start)
+            .take_while(move |(k, _)| *k <= end)
+    }
+
+    fn iter_from_nth_index<'a, D: BlockSet + 'static>(
+        &'a self,
+        nth_index: usize,
+        sync_id: SyncId,
+        discard_unsynced: bool,
+        tx_log_store: &'a Arc<TxLogStore<D>>,
+        event_listener: Option<&'a Arc<dyn TxEventListener<K, V>>>,
     ) -> SstIter<'a, K, V, D> {
         let all_synced = sync_id > self.sync_id();
         let accessor = ScanAccessor {
+            value_format: self.value_format,
             all_synced,
             discard_unsynced,
             event_listener,
         };
 
-        let first_rb = self
-            .target_record_block(self.footer.index[0].pos, tx_log_store)
-            .unwrap();
-
-        SstIter {
-            sst: self,
-            curr_nth_index: 0,
-            curr_rb_iter: Some(BlockScanIter {
+        // A `SSTable` of range tombstones only has no record block
+        let curr_rb_iter = self.footer.index.get(nth_index).map(|entry| {
+            let first_rb = self.target_record_block(entry.pos, tx_log_store).unwrap();
+            BlockScanIter {
                 block: first_rb,
                 offset: 0,
                 accessor,
-            }),
+                extent: None,
+            }
+        });
+
+        SstIter {
+            sst: self,
+            curr_nth_index: nth_index,
+            curr_rb_iter,
             tx_log_store,
         }
     }
@@ -348,35 +571,115 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
         Ok(all_records)
     }
 
+    /// Return the number of record blocks of this `SSTable`.
+    pub fn num_record_blocks(&self) -> usize {
+        self.footer.index.len()
+    }
+
+    /// Return the first key of the `nth` record block.
+    pub fn record_block_first_key(&self, nth: usize) -> K {
+        self.footer.index[nth].first
+    }
+
+    /// Collect all records of the `nth` record block, which are treated
+    /// as synced ones if `sync_id` is newer, the same as `iter()`.
+    ///
+    /// # Panics
+    ///
+    /// This method must be called within a TX. Otherwise, this method panics.
+    pub fn access_record_block<D: BlockSet + 'static>(
+        &self,
+        nth: usize,
+        sync_id: SyncId,
+        tx_log_store: &Arc<TxLogStore<D>>,
+    ) -> Result<Vec<(K, ValueEx<V>)>> {
+        let block = self.target_record_block(self.footer.index[nth].pos, tx_log_store)?;
+        let iter = BlockScanIter {
+            block,
+            offset: 0,
+            accessor: ScanAccessor {
+                value_format: self.value_format,
+                all_synced: sync_id > self.sync_id(),
+                discard_unsynced: false,
+                event_listener: None,
+            },
+            extent: None,
+        };
+        Ok(iter.collect())
+    }
+
     /// Building functions below
 
     /// Builds a SST given a bunch of records, after the SST becomes immutable.
+    /// The records are organized in record blocks of `record_block_size` bytes,
+    /// their values are encoded in `value_format`. The record blocks are
+    /// cached as configured in `config`.
+    /// The range tombstones are given by `tombstones_of` with the last key of
+    /// the records (`None` if there is no record), which are stored after them.
     /// The given `event_listener` (optional) is used on adding records.
     ///
     /// # Panics
     ///
     /// This method must be called within a TX. Otherwise, this method panics.
-    pub fn build<'a, D: BlockSet + 'static, I, KVex>(
+    pub fn build<'a, D: BlockSet + 'static, I, KVex, T>(
         records_iter: I,
+        tombstones_of: T,
         sync_id: SyncId,
+        record_block_size: usize,
+        value_format: ValueFormat,
+        config: &Config,
         tx_log: &'a Arc<TxLog<D>>,
         event_listener: Option<&'a Arc<dyn TxEventListener<K, V>>>,
     ) -> Result<Self>
     where
         I: Iterator<Item = KVex>,
         KVex: AsKVex<K, V>,
+        T: FnOnce(Option<&K>) -> RangeTombstones<K>,
         Self: 'a,
     {
-        let cache_cap = Self::cache_capacity();
+        debug_assert!(
+            record_block_size % BLOCK_SIZE == 0
+                && record_block_size >= Self::max_record_size(value_format)
+        );
+        let cache_cap = Self::cache_capacity(record_block_size, config);
         println!("build a SST with cache_capacity: {}", cache_cap);
 
         let mut cache = LruCache::new(NonZeroUsize::new(cache_cap).unwrap());
 
-        let (total_records, index_vec) =
-            Self::build_record_blocks(records_iter, tx_log, &mut cache, event_listener)?;
-        let footer = Self::build_footer::<D>(index_vec, total_records, sync_id, tx_log)?;
+        let mut key_hashes = Vec::new();
+        let (total_records, index_vec) = Self::build_record_blocks(
+            records_iter,
+            record_block_size,
+            value_format,
+            tx_log,
+            &mut cache,
+            &mut key_hashes,
+            event_listener,
+        )?;
+
+        let filter = BloomFilter::build(&key_hashes);
+        drop(key_hashes);
+        tx_log.append(BufRef::try_from(&filter.encode()[..]).unwrap())?;
+
+        let tombstones = tombstones_of(index_vec.last().map(|entry| &entry.last));
+        let tombstones_buf = tombstones.encode();
+        if !tombstones_buf.is_empty() {
+            tx_log.append(BufRef::try_from(&tombstones_buf[..]).unwrap())?;
+        }
+        let range = Self::calc_range(&index_vec, &tombstones)
+            .ok_or(Error::with_msg(InvalidArgs, "build an empty SST"))?;
 
-        let mut cache = if CONFIG.get().two_level_caching {
+        let footer = Self::build_footer::<D>(
+            index_vec,
+            total_records,
+            sync_id,
+            record_block_size,
+            filter.nblocks(),
+            tombstones_buf.len() / BLOCK_SIZE,
+            tx_log,
+        )?;
+
+        let mut cache = if tx_log.is_two_level_cached() {
             Some(Mutex::new(cache))
         } else {
             None
@@ -385,17 +688,24 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
         Ok(Self {
             id: tx_log.id(),
             footer,
+            range,
+            tombstones,
+            value_format,
             cache,
+            filter: Mutex::new(Some(Arc::new(filter))),
             phantom: PhantomData,
         })
     }
 
     /// Builds all the record blocks from the given records. Put the blocks to the log
-    /// and the cache.
+    /// and the cache, and the hashes of the keys to `key_hashes`.
     fn build_record_blocks<'a, D: BlockSet + 'static, I, KVex>(
         records_iter: I,
+        record_block_size: usize,
+        value_format: ValueFormat,
         tx_log: &'a TxLog<D>,
         cache: &mut LruCache<BlockId, Arc<RecordBlock>>,
+        key_hashes: &mut Vec<u64>,
         event_listener: Option<&'a Arc<dyn TxEventListener<K, V>>>,
     ) -> Result<(usize, Vec<IndexEntry<K>>)>
     where
@@ -403,16 +713,21 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
         KVex: AsKVex<K, V>,
         Self: 'a,
     {
+        let max_record_size = Self::max_record_size(value_format);
         let mut index_vec = Vec::new();
         let mut total_records = 0;
         let mut pos = 0 as BlockId;
         let (mut first_k, mut curr_k) = (None, None);
         let mut inner_offset = 0;
+        // The last record put in the current record block, which is
+        // extended by its successors, if any, into an extent
+        let mut extent: Option<ExtentTail<K, V>> = None;
 
-        let mut block_buf = Vec::with_capacity(RECORD_BLOCK_SIZE);
+        let mut block_buf = Vec::with_capacity(record_block_size);
         for kv_ex in records_iter {
             let (key, value_ex) = (*kv_ex.key(), kv_ex.value_ex());
             total_records += 1;
+            key_hashes.push(BloomFilter::hash(key.as_bytes()));
 
             if inner_offset == 0 {
                 debug_assert!(block_buf.is_empty());
@@ -420,43 +735,29 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
             }
             let _ = curr_k.insert(key);
 
-            block_buf.extend_from_slice(key.as_bytes());
-            inner_offset += Self::K_SIZE;
-
-            match value_ex {
-                ValueEx::Synced(v) => {
-                    block_buf.push(RecordFlag::Synced as u8);
-                    block_buf.extend_from_slice(v.as_bytes());
-
-                    if let Some(listener) = event_listener {
-                        listener.on_add_record(&(&key, v))?;
-                    }
-                    inner_offset += 1 + Self::V_SIZE;
-                }
-                ValueEx::Unsynced(v) => {
-                    block_buf.push(RecordFlag::Unsynced as u8);
-                    block_buf.extend_from_slice(v.as_bytes());
-
-                    if let Some(listener) = event_listener {
-                        listener.on_add_record(&(&key, v))?;
-                    }
-                    inner_offset += 1 + Self::V_SIZE;
-                }
-                ValueEx::SyncedAndUnsynced(sv, usv) => {
-                    block_buf.push(RecordFlag::SyncedAndUnsynced as u8);
-                    block_buf.extend_from_slice(sv.as_bytes());
-                    block_buf.extend_from_slice(usv.as_bytes());
-
-                    if let Some(listener) = event_listener {
-                        listener.on_add_record(&(&key, sv))?;
-                        listener.on_add_record(&(&key, usv))?;
-                    }
-                    inner_offset += Self::MAX_RECORD_SIZE;
+            if let Some(tail) = extent.as_mut()
+                && let Some(nbytes) = tail.extend(key, value_ex, value_format, &mut block_buf)
+            {
+                if let Some(listener) = event_listener {
+                    listener.on_add_record(&(&key, &tail.value))?;
                 }
+                inner_offset += nbytes;
+            } else {
+                block_buf.extend_from_slice(key.as_bytes());
+                inner_offset += Self::K_SIZE;
+                extent = ExtentTail::start(key, value_ex, block_buf.len(), value_format);
+                Self::put_record(
+                    key,
+                    value_ex,
+                    value_format,
+                    &mut block_buf,
+                    &mut inner_offset,
+                    event_listener,
+                )?;
             }
 
-            let cap_remained = RECORD_BLOCK_SIZE - inner_offset;
-            if cap_remained >= Self::MAX_RECORD_SIZE {
+            let cap_remained = record_block_size - inner_offset;
+            if cap_remained >= max_record_size {
                 continue;
             }
 
@@ -465,14 +766,20 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
                 first: first_k.unwrap(),
                 last: key,
             };
-            build_one_record_block(&index_entry, &mut block_buf, tx_log, cache)?;
+            build_one_record_block(
+                &index_entry,
+                &mut block_buf,
+                record_block_size,
+                tx_log,
+                cache,
+            )?;
             index_vec.push(index_entry);
 
-            pos += RECORD_BLOCK_NBLOCKS;
+            pos += record_block_size / BLOCK_SIZE;
             inner_offset = 0;
             block_buf.clear();
+            extent = None;
         }
-        debug_assert!(total_records > 0);
 
         if !block_buf.is_empty() {
             let last_entry = IndexEntry {
@@ -480,33 +787,100 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
                 first: first_k.unwrap(),
                 last: curr_k.unwrap(),
             };
-            build_one_record_block(&last_entry, &mut block_buf, tx_log, cache)?;
+            build_one_record_block(
+                &last_entry,
+                &mut block_buf,
+                record_block_size,
+                tx_log,
+                cache,
+            )?;
             index_vec.push(last_entry);
         }
 
         fn build_one_record_block<K: RecordKey<K>, D: BlockSet + 'static>(
             entry: &IndexEntry<K>,
             buf: &mut Vec<u8>,
+            record_block_size: usize,
             tx_log: &TxLog<D>,
             cache: &mut LruCache<BlockId, Arc<RecordBlock>>,
         ) -> Result<()> {
-            buf.resize(RECORD_BLOCK_SIZE, 0);
+            buf.resize(record_block_size, 0);
             let record_block = RecordBlock::from_buf(buf.clone());
 
             tx_log.append(BufRef::try_from(record_block.as_slice()).unwrap())?;
-            cache.put(entry.pos, Arc::new(record_block));
+            cache_record_block(cache, entry.pos, Arc::new(record_block));
             Ok(())
         }
 
         Ok((total_records, index_vec))
     }
 
+    /// Puts the flag and the value(s) of a record, whose key is put already,
+    /// to the record block, and adds the size to `inner_offset`.
+    fn put_record(
+        key: K,
+        value_ex: &ValueEx<V>,
+        value_format: ValueFormat,
+        block_buf: &mut Vec<u8>,
+        inner_offset: &mut usize,
+        event_listener: Option<&Arc<dyn TxEventListener<K, V>>>,
+    ) -> Result<()> {
+        let (v_size, max_record_size) = (
+            Self::v_size(value_format),
+            Self::max_record_size(value_format),
+        );
+        match value_ex {
+            ValueEx::Synced(v) => {
+                block_buf.push(RecordFlag::Synced as u8);
+                v.encode(value_format, block_buf);
+
+                if let Some(listener) = event_listener {
+                    listener.on_add_record(&(&key, v))?;
+                }
+                *inner_offset += 1 + v_size;
+            }
+            ValueEx::Unsynced(v) => {
+                block_buf.push(RecordFlag::Unsynced as u8);
+                v.encode(value_format, block_buf);
+
+                if let Some(listener) = event_listener {
+                    listener.on_add_record(&(&key, v))?;
+                }
+                *inner_offset += 1 + v_size;
+            }
+            ValueEx::SyncedAndUnsynced(sv, usv) => {
+                block_buf.push(RecordFlag::SyncedAndUnsynced as u8);
+                sv.encode(value_format, block_buf);
+                usv.encode(value_format, block_buf);
+
+                if let Some(listener) = event_listener {
+                    listener.on_add_record(&(&key, sv))?;
+                    listener.on_add_record(&(&key, usv))?;
+                }
+                *inner_offset += max_record_size;
+            }
+            ValueEx::SyncedAndDeleted(sv) => {
+                block_buf.push(RecordFlag::SyncedAndDeleted as u8);
+                sv.encode(value_format, block_buf);
+
+                if let Some(listener) = event_listener {
+                    listener.on_add_record(&(&key, sv))?;
+                }
+                *inner_offset += 1 + v_size;
+            }
+        }
+        Ok(())
+    }
+
     /// Builds the footer from the given index entries. The footer block will be appended
     /// to the SST log's end.
     fn build_footer<'a, D: BlockSet + 'static>(
         index_vec: Vec<IndexEntry<K>>,
         total_records: usize,
         sync_id: SyncId,
+        record_block_size: usize,
+        filter_nblocks: usize,
+        tombstone_nblocks: usize,
         tx_log: &'a TxLog<D>,
     ) -> Result<Footer<K>>
     where
@@ -516,6 +890,9 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
             index_vec.len() * Self::INDEX_ENTRY_SIZE + FOOTER_META_SIZE,
             BLOCK_SIZE,
         );
+        if index_vec.len() > u16::MAX as usize || footer_buf_len / BLOCK_SIZE > u16::MAX as usize {
+            return_errno_with_msg!(InvalidArgs, "too many record blocks in a SST");
+        }
         let mut append_buf = Vec::with_capacity(footer_buf_len);
         for entry in &index_vec {
             append_buf.extend_from_slice(&entry.pos.to_le_bytes());
@@ -527,7 +904,10 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
             num_index: index_vec.len() as _,
             index_nblocks: (footer_buf_len / BLOCK_SIZE) as _,
             total_records: total_records as _,
-            record_block_size: RECORD_BLOCK_SIZE as _,
+            record_block_size: record_block_size as _,
+            filter_nblocks: filter_nblocks as _,
+            tombstone_nblocks: tombstone_nblocks as _,
+            version: FOOTER_VERSION,
             sync_id,
         };
         append_buf[footer_buf_len - FOOTER_META_SIZE..].copy_from_slice(meta.as_bytes());
@@ -540,24 +920,42 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
     }
 
     /// Builds a SST from a `TxLog`, loads the footer and the index blocks.
+    /// The values are in `value_format`, the record blocks are cached
+    /// as configured in `config`.
     ///
     /// # Panics
     ///
     /// This method must be called within a TX. Otherwise, this method panics.
-    pub fn from_log<D: BlockSet + 'static>(tx_log: &Arc<TxLog<D>>) -> Result<Self> {
+    pub fn from_log<D: BlockSet + 'static>(
+        tx_log: &Arc<TxLog<D>>,
+        value_format: ValueFormat,
+        config: &Config,
+    ) -> Result<Self> {
         let nblocks = tx_log.nblocks();
 
         let mut rbuf = Buf::alloc(1)?;
         // Load footer block (last block)
         tx_log.read(nblocks - 1, rbuf.as_mut())?;
         let meta = FooterMeta::from_bytes(&rbuf.as_slice()[BLOCK_SIZE - FOOTER_META_SIZE..]);
+        if meta.version > FOOTER_VERSION {
+            return_errno_with_msg!(InvalidArgs, "unknown version of a SST footer");
+        }
 
         let mut rbuf = Buf::alloc(meta.index_nblocks as _)?;
         tx_log.read(nblocks - meta.index_nblocks as usize, rbuf.as_mut())?;
         let mut index = Vec::with_capacity(meta.num_index as _);
-        let cache_cap = Self::cache_capacity();
+        let record_block_size = meta.record_block_size as usize;
+        if record_block_size == 0 || record_block_size % BLOCK_SIZE != 0 {
+            return_errno_with_msg!(InvalidArgs, "invalid record block size of a SST");
+        }
+        let (index_nblocks, tombstone_nblocks) =
+            (meta.index_nblocks as usize, meta.tombstone_nblocks as usize);
+        if index_nblocks + tombstone_nblocks + meta.filter_nblocks as usize > nblocks {
+            return_errno_with_msg!(InvalidArgs, "invalid footer of a SST");
+        }
+        let cache_cap = Self::cache_capacity(record_block_size, config);
         let mut cache = LruCache::new(NonZeroUsize::new(cache_cap).unwrap());
-        let mut record_block = vec![0; RECORD_BLOCK_SIZE];
+        let mut record_block = vec![0; record_block_size];
         for i in 0..meta.num_index as _ {
             let buf =
                 &rbuf.as_slice()[i * Self::INDEX_ENTRY_SIZE..(i + 1) * Self::INDEX_ENTRY_SIZE];
@@ -568,27 +966,59 @@ impl<K: RecordKey<K>, V: RecordValue> SSTable<K, V> {
                 K::from_bytes(&buf[Self::INDEX_ENTRY_SIZE - Self::K_SIZE..Self::INDEX_ENTRY_SIZE]);
 
             tx_log.read(pos, BufMut::try_from(&mut record_block[..]).unwrap())?;
-            let _ = cache.put(pos, Arc::new(RecordBlock::from_buf(record_block.clone())));
+            cache_record_block(
+                &mut cache,
+                pos,
+                Arc::new(RecordBlock::from_buf(record_block.clone())),
+            );
 
             index.push(IndexEntry { pos, first, last })
         }
 
-        let cache = if CONFIG.get().two_level_caching {
+        let cache = if tx_log.is_two_level_cached() {
             Some(Mutex::new(cache))
         } else {
             None
         };
 
+        let tombstones = if tombstone_nblocks > 0 {
+            let mut tbuf = Buf::alloc(tombstone_nblocks)?;
+            tx_log.read(nblocks - index_nblocks - tombstone_nblocks, tbuf.as_mut())?;
+            RangeTombstones::decode(tbuf.as_slice())?
+        } else {
+            RangeTombstones::new()
+        };
+        let range = Self::calc_range(&index, &tombstones)
+            .ok_or(Error::with_msg(InvalidArgs, "invalid empty SST"))?;
+
         let footer = Footer { meta, index };
         Ok(Self {
             id: tx_log.id(),
             footer,
+            range,
+            tombstones,
+            value_format,
             cache,
+            filter: Mutex::new(None),
             phantom: PhantomData,
         })
     }
 }
 
+/// Puts a record block into the cache of a `SSTable`, the block evicted
+/// for the capacity is recorded to `CACHE_STATS`.
+fn cache_record_block(
+    cache: &mut LruCache<BlockId, Arc<RecordBlock>>,
+    pos: BlockId,
+    record_block: Arc<RecordBlock>,
+) {
+    if let Some((evicted_pos, _)) = cache.push(pos, record_block)
+        && evicted_pos != pos
+    {
+        CACHE_STATS.record_eviction(CacheTier::SstBlock);
+    }
+}
+
 impl<K: RecordKey<K>> IndexEntry<K> {
     pub fn range(&self) -> RangeInclusive<K> {
         self.first..=self.last
@@ -606,7 +1036,7 @@ impl<K: RecordKey<K>> IndexEntry<K> {
 
 impl RecordBlock {
     pub fn from_buf(buf: Vec<u8>) -> Self {
-        debug_assert_eq!(buf.len(), RECORD_BLOCK_SIZE);
+        debug_assert!(!buf.is_empty() && buf.len() % BLOCK_SIZE == 0);
         Self { buf }
     }
 
@@ -619,6 +1049,90 @@ impl RecordBlock {
     }
 }
 
+impl<K: RecordKey<K>, V: RecordValue> ExtentTail<K, V> {
+    /// Starts an extent from a record whose flag is put at `flag_offset`
+    /// of the record block. Returns `None` if no successor may follow it.
+    pub fn start(
+        key: K,
+        value_ex: &ValueEx<V>,
+        flag_offset: usize,
+        value_format: ValueFormat,
+    ) -> Option<Self> {
+        V::successor_size(value_format)?;
+        let (value, synced) = single_value(value_ex)?;
+        Some(Self {
+            key,
+            value,
+            synced,
+            flag_offset,
+            count: 0,
+        })
+    }
+
+    /// Extends the extent by the record if it succeeds the last one, i.e.,
+    /// appends its successor to `buf`, which ends with the extent. Returns
+    /// the number of bytes appended, or `None` if it doesn't succeed.
+    pub fn extend(
+        &mut self,
+        key: K,
+        value_ex: &ValueEx<V>,
+        value_format: ValueFormat,
+        buf: &mut Vec<u8>,
+    ) -> Option<usize> {
+        let (value, synced) = single_value(value_ex)?;
+        if synced != self.synced
+            || key != self.key + 1
+            || self.count == u16::MAX
+            || !value.succeeds(&self.value, value_format)
+        {
+            return None;
+        }
+
+        let len = buf.len();
+        let count_offset = self.flag_offset + 1 + V::encoded_size(value_format);
+        if self.count == 0 {
+            // Turn the first record into the head of an extent
+            let flag = if synced {
+                RecordFlag::SyncedExtent
+            } else {
+                RecordFlag::UnsyncedExtent
+            };
+            buf[self.flag_offset] = flag as u8;
+            debug_assert_eq!(count_offset, len);
+            buf.resize(len + EXTENT_COUNT_SIZE, 0);
+        }
+        self.count += 1;
+        buf[count_offset..count_offset + EXTENT_COUNT_SIZE]
+            .copy_from_slice(&self.count.to_le_bytes());
+        value.encode_successor(value_format, buf);
+
+        self.key = key;
+        self.value = value;
+        Some(buf.len() - len)
+    }
+}
+
+/// Returns the value of a record and whether it's synced, or `None` if
+/// it has none or more than one value.
+fn single_value<V: RecordValue>(value_ex: &ValueEx<V>) -> Option<(V, bool)> {
+    match value_ex {
+        ValueEx::Synced(v) => Some((*v, true)),
+        ValueEx::Unsynced(v) => Some((*v, false)),
+        _ => None,
+    }
+}
+
+impl<K: RecordKey<K>, V: RecordValue> ExtentCursor<K, V> {
+    /// Splits the next record off the extent given the successor in `buf`.
+    /// Returns the record and whether the extent is used up.
+    pub fn split(&mut self, value_format: ValueFormat, buf: &[u8]) -> ((K, V), bool) {
+        self.key = self.key + 1;
+        self.value = V::decode_successor(&self.value, value_format, buf);
+        self.remaining -= 1;
+        ((self.key, self.value), self.remaining == 0)
+    }
+}
+
 impl<K: RecordKey<K>> QueryAccessor<K> {
     pub fn hit_target(&self, target: &K) -> bool {
         match self {
@@ -626,17 +1140,65 @@ impl<K: RecordKey<K>> QueryAccessor<K> {
             QueryAccessor::Range(range) => range.contains(target),
         }
     }
+
+    /// Whether any target is within the non-empty `range`.
+    pub fn hit_any(&self, range: &RangeInclusive<K>) -> bool {
+        debug_assert!(!range.is_empty());
+        match self {
+            QueryAccessor::Point(k) => range.contains(k),
+            QueryAccessor::Range(target) => {
+                !(target.end() < range.start() || target.start() > range.end())
+            }
+        }
+    }
+}
+
+impl<K: RecordKey<K>, V: RecordValue> ScanAccessor<'_, K, V> {
+    /// Returns the record of a single value as it's scanned, or `None` if
+    /// it's unsynced and discarded.
+    fn single_value_ex(&self, key: K, v: V, synced: bool) -> Option<ValueEx<V>> {
+        if synced || self.all_synced {
+            Some(ValueEx::Synced(v))
+        } else if self.discard_unsynced {
+            if let Some(listener) = self.event_listener {
+                listener.on_drop_record(&(key, v)).unwrap();
+            }
+            None
+        } else {
+            Some(ValueEx::Unsynced(v))
+        }
+    }
 }
 
 impl<K: RecordKey<K>, V: RecordValue> Iterator for BlockQueryIter<'_, K, V> {
-    type Item = (K, Option<V>);
+    type Item = (K, Option<Lookup<V>>);
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut offset = self.offset;
         let buf_slice = &self.block.buf;
-        let (k_size, v_size) = (SSTable::<K, V>::K_SIZE, SSTable::<K, V>::V_SIZE);
+        let value_format = self.value_format;
+        let (k_size, v_size) = (
+            SSTable::<K, V>::K_SIZE,
+            SSTable::<K, V>::v_size(value_format),
+        );
 
-        if offset + SSTable::<K, V>::MAX_RECORD_SIZE > RECORD_BLOCK_SIZE {
+        // Split the successors off the extent being iterated first
+        if let Some(extent) = self.extent.as_mut() {
+            let successor_size = V::successor_size(value_format).unwrap();
+            let ((key, value), is_end) =
+                extent.split(value_format, &buf_slice[offset..offset + successor_size]);
+            if is_end {
+                self.extent = None;
+            }
+            self.offset = offset + successor_size;
+            let lookup_opt = self
+                .accessor
+                .hit_target(&key)
+                .then_some(Lookup::Found(value));
+            return Some((key, lookup_opt));
+        }
+
+        if offset + SSTable::<K, V>::max_record_size(value_format) > buf_slice.len() {
             return None;
         }
 
@@ -650,10 +1212,35 @@ impl<K: RecordKey<K>, V: RecordValue> Iterator for BlockQueryIter<'_, K, V> {
         }
 
         let hit_target = self.accessor.hit_target(&key);
-        let value_opt = match flag {
+        let lookup_opt = match flag {
+            RecordFlag::SyncedExtent | RecordFlag::UnsyncedExtent => {
+                let value = V::decode(value_format, &buf_slice[offset..offset + v_size]);
+                offset += v_size;
+                let count = u16::from_le_bytes(
+                    buf_slice[offset..offset + EXTENT_COUNT_SIZE]
+                        .try_into()
+                        .unwrap(),
+                ) as usize;
+                offset += EXTENT_COUNT_SIZE;
+                // The successors are skipped as a whole if none is the target
+                if count > 0 && self.accessor.hit_any(&(key + 1..=key + count)) {
+                    let _ = self.extent.insert(ExtentCursor {
+                        key,
+                        value,
+                        synced: flag == RecordFlag::SyncedExtent,
+                        remaining: count,
+                    });
+                } else {
+                    offset += count * V::successor_size(value_format).unwrap_or(0);
+                }
+                hit_target.then_some(Lookup::Found(value))
+            }
             RecordFlag::Synced | RecordFlag::Unsynced => {
                 let v_opt = if hit_target {
-                    Some(V::from_bytes(&buf_slice[offset..offset + v_size]))
+                    Some(Lookup::Found(V::decode(
+                        value_format,
+                        &buf_slice[offset..offset + v_size],
+                    )))
                 } else {
                     None
                 };
@@ -662,20 +1249,25 @@ impl<K: RecordKey<K>, V: RecordValue> Iterator for BlockQueryIter<'_, K, V> {
             }
             RecordFlag::SyncedAndUnsynced => {
                 let v_opt = if hit_target {
-                    Some(V::from_bytes(
+                    Some(Lookup::Found(V::decode(
+                        value_format,
                         &buf_slice[offset + v_size..offset + 2 * v_size],
-                    ))
+                    )))
                 } else {
                     None
                 };
                 offset += 2 * v_size;
                 v_opt
             }
+            RecordFlag::SyncedAndDeleted => {
+                offset += v_size;
+                hit_target.then_some(Lookup::Deleted)
+            }
             _ => unreachable!(),
         };
 
         self.offset = offset;
-        Some((key, value_opt))
+        Some((key, lookup_opt))
     }
 }
 
@@ -685,7 +1277,12 @@ impl<K: RecordKey<K>, V: RecordValue> Iterator for BlockScanIter<'_, K, V> {
     fn next(&mut self) -> Option<Self::Item> {
         let mut offset = self.offset;
         let buf_slice = &self.block.buf;
-        let (k_size, v_size) = (SSTable::<K, V>::K_SIZE, SSTable::<K, V>::V_SIZE);
+        let value_format = self.accessor.value_format;
+        let (k_size, v_size) = (
+            SSTable::<K, V>::K_SIZE,
+            SSTable::<K, V>::v_size(value_format),
+        );
+        let max_record_size = SSTable::<K, V>::max_record_size(value_format);
         let (all_synced, discard_unsynced, event_listener) = (
             self.accessor.all_synced,
             self.accessor.discard_unsynced,
@@ -693,7 +1290,23 @@ impl<K: RecordKey<K>, V: RecordValue> Iterator for BlockScanIter<'_, K, V> {
         );
 
         let (key, value_ex) = loop {
-            if offset + SSTable::<K, V>::MAX_RECORD_SIZE > RECORD_BLOCK_SIZE {
+            // Split the successors off the extent being iterated first
+            if let Some(extent) = self.extent.as_mut() {
+                let successor_size = V::successor_size(value_format).unwrap();
+                let synced = extent.synced;
+                let ((key, v), is_end) =
+                    extent.split(value_format, &buf_slice[offset..offset + successor_size]);
+                offset += successor_size;
+                if is_end {
+                    self.extent = None;
+                }
+                match self.accessor.single_value_ex(key, v, synced) {
+                    Some(v_ex) => break (key, v_ex),
+                    None => continue,
+                }
+            }
+
+            if offset + max_record_size > buf_slice.len() {
                 return None;
             }
 
@@ -707,29 +1320,44 @@ impl<K: RecordKey<K>, V: RecordValue> Iterator for BlockScanIter<'_, K, V> {
             }
 
             let v_ex = match flag {
-                RecordFlag::Synced => {
-                    let v = V::from_bytes(&buf_slice[offset..offset + v_size]);
+                RecordFlag::Synced | RecordFlag::Unsynced => {
+                    let v = V::decode(value_format, &buf_slice[offset..offset + v_size]);
                     offset += v_size;
-                    ValueEx::Synced(v)
+                    match self
+                        .accessor
+                        .single_value_ex(key, v, flag == RecordFlag::Synced)
+                    {
+                        Some(v_ex) => v_ex,
+                        None => continue,
+                    }
                 }
-                RecordFlag::Unsynced => {
-                    let v = V::from_bytes(&buf_slice[offset..offset + v_size]);
+                RecordFlag::SyncedExtent | RecordFlag::UnsyncedExtent => {
+                    let v = V::decode(value_format, &buf_slice[offset..offset + v_size]);
                     offset += v_size;
-                    if all_synced {
-                        ValueEx::Synced(v)
-                    } else if discard_unsynced {
-                        if let Some(listener) = event_listener {
-                            listener.on_drop_record(&(key, v)).unwrap();
-                        }
-                        continue;
-                    } else {
-                        ValueEx::Unsynced(v)
+                    let count = u16::from_le_bytes(
+                        buf_slice[offset..offset + EXTENT_COUNT_SIZE]
+                            .try_into()
+                            .unwrap(),
+                    ) as usize;
+                    offset += EXTENT_COUNT_SIZE;
+                    let synced = flag == RecordFlag::SyncedExtent;
+                    if count > 0 {
+                        let _ = self.extent.insert(ExtentCursor {
+                            key,
+                            value: v,
+                            synced,
+                            remaining: count,
+                        });
+                    }
+                    match self.accessor.single_value_ex(key, v, synced) {
+                        Some(v_ex) => v_ex,
+                        None => continue,
                     }
                 }
                 RecordFlag::SyncedAndUnsynced => {
-                    let sv = V::from_bytes(&buf_slice[offset..offset + v_size]);
+                    let sv = V::decode(value_format, &buf_slice[offset..offset + v_size]);
                     offset += v_size;
-                    let usv = V::from_bytes(&buf_slice[offset..offset + v_size]);
+                    let usv = V::decode(value_format, &buf_slice[offset..offset + v_size]);
                     offset += v_size;
                     if all_synced {
                         if let Some(listener) = event_listener {
@@ -745,6 +1373,21 @@ impl<K: RecordKey<K>, V: RecordValue> Iterator for BlockScanIter<'_, K, V> {
                         ValueEx::SyncedAndUnsynced(sv, usv)
                     }
                 }
+                RecordFlag::SyncedAndDeleted => {
+                    let sv = V::decode(value_format, &buf_slice[offset..offset + v_size]);
+                    offset += v_size;
+                    if all_synced {
+                        // The deletion is synced
+                        if let Some(listener) = event_listener {
+                            listener.on_drop_record(&(key, sv)).unwrap();
+                        }
+                        continue;
+                    } else if discard_unsynced {
+                        ValueEx::Synced(sv)
+                    } else {
+                        ValueEx::SyncedAndDeleted(sv)
+                    }
+                }
                 _ => unreachable!(),
             };
             break (key, v_ex);
@@ -760,7 +1403,7 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> Iterator for SstIte
 
     fn next(&mut self) -> Option<Self::Item> {
         // Iterate over the current record block first
-        if let Some(next) = self.curr_rb_iter.as_mut().unwrap().next() {
+        if let Some(next) = self.curr_rb_iter.as_mut()?.next() {
             return Some(next);
         }
 
@@ -783,6 +1426,7 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> Iterator for SstIte
             block: next_rb,
             offset: 0,
             accessor: curr_rb_iter.accessor,
+            extent: None,
         };
         let next = next_rb_iter.next()?;
 
@@ -796,13 +1440,8 @@ impl<K: Debug, V> Debug for SSTable<K, V> {
         f.debug_struct("SSTable")
             .field("id", &self.id)
             .field("footer", &self.footer.meta)
-            .field(
-                "range",
-                &RangeInclusive::new(
-                    &self.footer.index[0].first,
-                    &self.footer.index[self.footer.meta.num_index as usize - 1].last,
-                ),
-            )
+            .field("range", &self.range)
+            .field("num_tombstones", &self.tombstones.len())
             .finish()
     }
 }
@@ -814,6 +1453,9 @@ enum RecordFlag {
     Synced = 7,
     Unsynced = 11,
     SyncedAndUnsynced = 19,
+    SyncedAndDeleted = 23,
+    SyncedExtent = 29,
+    UnsyncedExtent = 31,
     Invalid,
 }
 
@@ -823,6 +1465,9 @@ impl From<u8> for RecordFlag {
             7 => RecordFlag::Synced,
             11 => RecordFlag::Unsynced,
             19 => RecordFlag::SyncedAndUnsynced,
+            23 => RecordFlag::SyncedAndDeleted,
+            29 => RecordFlag::SyncedExtent,
+            31 => RecordFlag::UnsyncedExtent,
             _ => RecordFlag::Invalid,
         }
     }
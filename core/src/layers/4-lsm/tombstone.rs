@@ -0,0 +1,234 @@
+//! Range tombstones of `TxLsmTree`.
+use super::RecordKey;
+use crate::prelude::*;
+
+use core::mem::size_of;
+use core::ops::{Range, RangeInclusive};
+
+/// Range tombstones of a `MemTable` or a `SSTable`.
+///
+/// A range tombstone deletes all the records within its key range from the
+/// older `MemTable`s and `SSTable`s, while the records of its own table are
+/// always newer than it. The tombstones are kept as disjoint sorted ranges,
+/// the synced ones and the unsynced ones separately.
+#[derive(Clone, Debug, Default)]
+pub(super) struct RangeTombstones<K> {
+    synced: Vec<Range<K>>,
+    unsynced: Vec<Range<K>>,
+}
+
+/// The result of looking up a key in a `MemTable` or a `SSTable`.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Lookup<V> {
+    /// The most recent value of the key.
+    Found(V),
+    /// The key is deleted, the older tables must not be looked up.
+    Deleted,
+    /// The key is absent, the older tables are looked up then.
+    Missing,
+}
+
+impl<K> RangeTombstones<K> {
+    /// Return the number of (merged) tombstones.
+    pub fn len(&self) -> usize {
+        self.synced.len() + self.unsynced.len()
+    }
+
+    /// Whether there is no tombstone.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: RecordKey<K>> RangeTombstones<K> {
+    /// The size of an encoded tombstone, i.e., the start key,
+    /// the end key and the sync flag.
+    const ENCODED_SIZE: usize = 2 * size_of::<K>() + 1;
+
+    /// Creates an empty set of range tombstones.
+    pub fn new() -> Self {
+        Self {
+            synced: Vec::new(),
+            unsynced: Vec::new(),
+        }
+    }
+
+    /// Inserts a range tombstone, merged with the overlapped ones.
+    pub fn insert(&mut self, range: Range<K>, synced: bool) {
+        if range.is_empty() {
+            return;
+        }
+        let ranges = if synced {
+            &mut self.synced
+        } else {
+            &mut self.unsynced
+        };
+
+        // The overlapped or adjacent ranges are in `lo..hi`
+        let lo = ranges.partition_point(|r| r.end < range.start);
+        let hi = ranges.partition_point(|r| r.start <= range.end);
+        let merged = if lo < hi {
+            range.start.min(ranges[lo].start)..range.end.max(ranges[hi - 1].end)
+        } else {
+            range
+        };
+        let _ = ranges.splice(lo..hi, [merged]);
+    }
+
+    /// Extends with all the tombstones of `other`.
+    pub fn extend(&mut self, other: &Self) {
+        for (range, synced) in other.iter() {
+            self.insert(range.clone(), synced);
+        }
+    }
+
+    /// Whether the key is deleted by any tombstone.
+    pub fn covers(&self, key: &K) -> bool {
+        self.covering(key).is_some()
+    }
+
+    /// Return whether the key is deleted by a synced tombstone (`Some(true)`)
+    /// or by unsynced ones only (`Some(false)`). Return `None` if the key
+    /// is not deleted.
+    pub fn covering(&self, key: &K) -> Option<bool> {
+        let find = |ranges: &[Range<K>]| {
+            let nth = ranges.partition_point(|r| r.end <= *key);
+            ranges.get(nth).is_some_and(|r| r.start <= *key)
+        };
+        if find(&self.synced) {
+            Some(true)
+        } else if find(&self.unsynced) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Return the tombstones within the given range.
+    pub fn clip(&self, range: &RangeInclusive<K>) -> Self {
+        let (start, end) = (*range.start(), *range.end() + 1);
+        let clip = |ranges: &[Range<K>]| {
+            ranges
+                .iter()
+                .filter(|r| r.start < end && r.end > start)
+                .map(|r| r.start.max(start)..r.end.min(end))
+                .collect()
+        };
+        Self {
+            synced: clip(&self.synced),
+            unsynced: clip(&self.unsynced),
+        }
+    }
+
+    /// Return the range of keys covered by all the tombstones.
+    pub fn span(&self) -> Option<RangeInclusive<K>> {
+        let first = [self.synced.first(), self.unsynced.first()]
+            .into_iter()
+            .flatten()
+            .map(|r| r.start)
+            .min()?;
+        let last = [self.synced.last(), self.unsynced.last()]
+            .into_iter()
+            .flatten()
+            .map(|r| r.start + (r.end - r.start - 1))
+            .max()?;
+        Some(first..=last)
+    }
+
+    /// Mark all the tombstones as synced.
+    pub fn sync(&mut self) {
+        for range in core::mem::take(&mut self.unsynced) {
+            self.insert(range, true);
+        }
+    }
+
+    /// Discard all the unsynced tombstones.
+    pub fn discard_unsynced(&mut self) {
+        self.unsynced.clear();
+    }
+
+    /// Return an iterator over the tombstones and whether each is synced.
+    pub fn iter(&self) -> impl Iterator<Item = (&Range<K>, bool)> {
+        self.synced
+            .iter()
+            .map(|range| (range, true))
+            .chain(self.unsynced.iter().map(|range| (range, false)))
+    }
+
+    /// Encodes the tombstones to a buffer of whole blocks,
+    /// return an empty one if there is no tombstone.
+    ///
+    /// ```text
+    /// | num_tombstones (u32) | start K | end K | synced (u8) | ... |
+    /// ```
+    pub fn encode(&self) -> Vec<u8> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let len = size_of::<u32>() + self.len() * Self::ENCODED_SIZE;
+        let mut buf = Vec::with_capacity(align_up(len, BLOCK_SIZE));
+        buf.extend_from_slice(&(self.len() as u32).to_le_bytes());
+        for (range, synced) in self.iter() {
+            buf.extend_from_slice(range.start.as_bytes());
+            buf.extend_from_slice(range.end.as_bytes());
+            buf.push(synced as u8);
+        }
+        buf.resize(align_up(len, BLOCK_SIZE), 0);
+        buf
+    }
+
+    /// Decodes the tombstones from an encoded buffer.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        let mut tombstones = Self::new();
+        if buf.len() < size_of::<u32>() {
+            return Ok(tombstones);
+        }
+        let num = u32::from_le_bytes(buf[..size_of::<u32>()].try_into().unwrap()) as usize;
+        if size_of::<u32>() + num * Self::ENCODED_SIZE > buf.len() {
+            return_errno_with_msg!(InvalidArgs, "invalid range tombstones");
+        }
+
+        let k_size = size_of::<K>();
+        for entry in buf[size_of::<u32>()..]
+            .chunks_exact(Self::ENCODED_SIZE)
+            .take(num)
+        {
+            let start = K::from_bytes(&entry[..k_size]);
+            let end = K::from_bytes(&entry[k_size..2 * k_size]);
+            tombstones.insert(start..end, entry[2 * k_size] != 0);
+        }
+        Ok(tombstones)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_tombstones_fns() -> Result<()> {
+        let mut tombstones = RangeTombstones::<usize>::new();
+        tombstones.insert(10..20, true);
+        tombstones.insert(30..40, false);
+        tombstones.insert(15..25, true);
+        tombstones.insert(25..30, false);
+        assert_eq!(tombstones.len(), 2);
+        assert_eq!(tombstones.covering(&5), None);
+        assert_eq!(tombstones.covering(&24), Some(true));
+        assert_eq!(tombstones.covering(&25), Some(false));
+        assert_eq!(tombstones.covering(&40), None);
+        assert_eq!(tombstones.span(), Some(10..=39));
+
+        let clipped = tombstones.clip(&(20..=29));
+        assert_eq!(clipped.span(), Some(20..=29));
+        assert!(clipped.covers(&20) && !clipped.covers(&30));
+
+        let decoded = RangeTombstones::<usize>::decode(&tombstones.encode())?;
+        assert!(decoded.iter().eq(tombstones.iter()));
+
+        tombstones.sync();
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(tombstones.covering(&35), Some(true));
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Ordered iteration over `TxLsmTree`.
+use super::mem_table::MemTable;
+use super::sstable::SSTable;
+use super::tombstone::RangeTombstones;
+use super::tx_lsm_tree::TreeInner;
+use super::{RecordKey, RecordValue, SyncId};
+use crate::layers::bio::BlockSet;
+use crate::layers::log::TxLogId;
+use crate::prelude::*;
+
+use alloc::collections::{BinaryHeap, VecDeque};
+use core::cmp::Reverse;
+
+/// An ordered iterator over the most recent records of a `TxLsmTree`,
+/// created by `TxLsmTree::iter()`.
+///
+/// It iterates a snapshot taken on creation: the records of the `MemTable`s
+/// are copied, while the `SSTable`s are pinned and read lazily, one record
+/// block at a time in a read TX. The sources are merged by a min-heap of
+/// their next keys, where the newer source goes first on the same key.
+///
+/// A pinned SST may be compacted away meanwhile, its log is deleted once
+/// the last iterator pinning it is dropped.
+pub struct TreeIter<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> {
+    tree: Arc<TreeInner<K, V, D>>,
+    /// The sources from newer to older.
+    sources: Vec<Source<K, V>>,
+    /// The IDs of the pinned SSTs.
+    pinned_ids: Vec<TxLogId>,
+    /// The next key and the index of each non-exhausted source.
+    heap: BinaryHeap<Reverse<(K, usize)>>,
+    sync_id: SyncId,
+    last_key: Option<K>,
+    is_failed: bool,
+}
+
+/// A snapshot of a `MemTable`, the most recent values of which are copied.
+pub(super) struct MemTableSnapshot<K, V> {
+    records: Vec<(K, Option<V>)>,
+    tombstones: RangeTombstones<K>,
+}
+
+/// A source of records in the snapshot of a `TxLsmTree`.
+struct Source<K, V> {
+    /// The loaded records, a `None` value is deleted.
+    records: VecDeque<(K, Option<V>)>,
+    kind: SourceKind<K, V>,
+}
+
+enum SourceKind<K, V> {
+    MemTable(RangeTombstones<K>),
+    SSTable {
+        sst: Arc<SSTable<K, V>>,
+        next_block: usize,
+    },
+}
+
+impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeIter<K, V, D> {
+    /// Creates an iterator over a snapshot of the given tree.
+    pub(super) fn new(tree: Arc<TreeInner<K, V, D>>) -> Self {
+        let (memtables, ssts, sync_id) = tree.pin_snapshot();
+        let pinned_ids = ssts.iter().map(|(id, _)| *id).collect();
+        let sources: Vec<_> = memtables
+            .into_iter()
+            .map(|memtable| Source {
+                records: memtable.records.into(),
+                kind: SourceKind::MemTable(memtable.tombstones),
+            })
+            .chain(ssts.into_iter().map(|(_, sst)| Source {
+                records: VecDeque::new(),
+                kind: SourceKind::SSTable { sst, next_block: 0 },
+            }))
+            .collect();
+
+        let heap = sources
+            .iter()
+            .enumerate()
+            .filter_map(|(nth, source)| source.next_key().map(|key| Reverse((key, nth))))
+            .collect();
+        Self {
+            tree,
+            sources,
+            pinned_ids,
+            heap,
+            sync_id,
+            last_key: None,
+            is_failed: false,
+        }
+    }
+
+    /// Loads the next record block of the `nth` source, which is a `SSTable`.
+    fn load_next_block(&mut self, nth: usize) -> Result<()> {
+        let Source {
+            records,
+            kind: SourceKind::SSTable { sst, next_block },
+        } = &mut self.sources[nth]
+        else {
+            unreachable!();
+        };
+
+        let block_records = self
+            .tree
+            .read_record_block(sst, *next_block, self.sync_id)?;
+        *next_block += 1;
+        records.extend(
+            block_records
+                .into_iter()
+                .map(|(key, value_ex)| (key, value_ex.get().copied())),
+        );
+        Ok(())
+    }
+
+    fn push_next_key(&mut self, nth: usize) {
+        if let Some(key) = self.sources[nth].next_key() {
+            self.heap.push(Reverse((key, nth)));
+        }
+    }
+}
+
+impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> Iterator for TreeIter<K, V, D> {
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_failed {
+            return None;
+        }
+
+        loop {
+            let Reverse((key, nth)) = self.heap.pop()?;
+            // The key of an unloaded source is the first key of its next
+            // record block, which is no greater than its next record's
+            if self.sources[nth].records.is_empty() {
+                if let Err(e) = self.load_next_block(nth) {
+                    self.is_failed = true;
+                    return Some(Err(e));
+                }
+                self.push_next_key(nth);
+                continue;
+            }
+
+            let (key, value) = self.sources[nth].records.pop_front().unwrap();
+            self.push_next_key(nth);
+            // The older records of a visited key
+            if self.last_key.is_some_and(|last_key| key <= last_key) {
+                continue;
+            }
+            let _ = self.last_key.insert(key);
+
+            if self.sources[..nth]
+                .iter()
+                .any(|newer_source| newer_source.is_deleted(&key))
+            {
+                continue;
+            }
+            if let Some(value) = value {
+                return Some(Ok((key, value)));
+            }
+        }
+    }
+}
+
+impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> Drop for TreeIter<K, V, D> {
+    fn drop(&mut self) {
+        // The retired logs left by a failure are deleted on recovery
+        let _ = self.tree.unpin_ssts(&self.pinned_ids);
+    }
+}
+
+impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> Debug for TreeIter<K, V, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TreeIter")
+            .field("num_sources", &self.sources.len())
+            .field("sync_id", &self.sync_id)
+            .field("last_key", &self.last_key)
+            .finish()
+    }
+}
+
+impl<K: RecordKey<K>, V: RecordValue> MemTableSnapshot<K, V> {
+    /// Takes a snapshot of the given `MemTable`.
+    pub fn new(memtable: &MemTable<K, V>) -> Self {
+        Self {
+            records: memtable
+                .iter()
+                .map(|(key, value_ex)| (key, value_ex.get().copied()))
+                .collect(),
+            tombstones: memtable.tombstones().clone(),
+        }
+    }
+}
+
+impl<K: RecordKey<K>, V: RecordValue> Source<K, V> {
+    /// Return the key of the next record, or the first key of the next
+    /// record block if no record is loaded. Return `None` if exhausted.
+    fn next_key(&self) -> Option<K> {
+        if let Some((key, _)) = self.records.front() {
+            return Some(*key);
+        }
+        match &self.kind {
+            SourceKind::MemTable(_) => None,
+            SourceKind::SSTable { sst, next_block } => (*next_block < sst.num_record_blocks())
+                .then(|| sst.record_block_first_key(*next_block)),
+        }
+    }
+
+    /// Whether the key is deleted by the range tombstones of this source,
+    /// i.e., the records of the key in older sources are hidden.
+    fn is_deleted(&self, key: &K) -> bool {
+        match &self.kind {
+            SourceKind::MemTable(tombstones) => tombstones.covers(key),
+            SourceKind::SSTable { sst, .. } => sst.is_deleted(key),
+        }
+    }
+}
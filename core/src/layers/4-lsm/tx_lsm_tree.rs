@@ -1,25 +1,31 @@
 //! Transactional LSM-Tree.
 //!
-//! API: `format()`, `recover()`, `get()`, `put()`, `get_range()`, `sync()`
+//! API: `format()`, `recover()`, `get()`, `put()`, `put_batch_unlogged()`, `delete_range()`,
+//! `get_range()`, `sync()`, `checkpoint()`, `bulk_load()`
 //!
-//! Responsible for managing two `MemTable`s, WAL and SSTs as `TxLog`s
+//! Responsible for managing two `MemTable`s, WAL, checkpoints and SSTs as `TxLog`s
 //! backed by a `TxLogStore`. All operations are executed based
 //! on internal transactions.
-use super::compaction::Compactor;
+use super::checkpoint::{Checkpoint, BUCKET_CHECKPOINT};
+use super::compaction::{CompactionInput, CompactionPolicy, CompactionPolicyKind, Compactor};
 use super::mem_table::{MemTableManager, ValueEx};
 use super::range_query_ctx::RangeQueryCtx;
-use super::sstable::SSTable;
-use super::wal::{WalAppendTx, BUCKET_WAL};
+use super::sstable::{SSTable, MAX_RECORD_BLOCK_SIZE, RECORD_BLOCK_SIZE};
+use super::tombstone::{Lookup, RangeTombstones};
+use super::tree_iter::{MemTableSnapshot, TreeIter};
+use super::wal::{WalAppendTx, WalEntry, BUCKET_WAL};
 use crate::layers::bio::BlockSet;
-use crate::layers::disk::{SharedState, SharedStateRef};
+use crate::layers::disk::{Config, SharedState, SharedStateRef};
 use crate::layers::log::{TxLogId, TxLogStore};
-use crate::os::{spawn, BTreeMap, RwLock};
+use crate::os::{spawn, BTreeMap, Clock, CvarMutex, HashMap, HashSet, Mutex, RealClock, RwLock};
 use crate::tx::Tx;
-use crate::{prelude::*, CostL2Type, CONFIG, COST_L2};
+use crate::{prelude::*, CostL2Type, COST_L2};
 use core::default;
 use core::hash::Hash;
-use core::ops::{Add, RangeInclusive, Sub};
-use core::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use core::mem::size_of;
+use core::ops::{Add, Range, RangeInclusive, Sub};
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
 use pod::Pod;
 
 /// Monotonic incrementing sync ID.
@@ -41,8 +47,40 @@ pub(super) struct TreeInner<K: RecordKey<K>, V, D> {
     shared_state: SharedStateRef,
     listener_factory: Arc<dyn TxEventListenerFactory<K, V>>,
     master_sync_id: MasterSyncId,
+    compaction_scheduler: RwLock<Option<Arc<dyn CompactionScheduler>>>,
+    compaction_policy: Arc<dyn CompactionPolicy<K, V>>,
+    // Serialize the major compactions, each of which takes several TXs
+    major_compaction_lock: CvarMutex<()>,
+    // The SSTs pinned by `TreeIter`s, whose logs outlive the compactions
+    sst_pins: Mutex<SstPins>,
+    // The time of the last checkpoint (or the format/recovery)
+    last_checkpoint: Mutex<Duration>,
+    // Whether any record is put bypassing the WAL since the last checkpoint
+    has_unlogged_records: AtomicBool,
+    // Serialize the syncs (and the checkpoints) against the puts, so that no
+    // record is appended to the WAL between its sync and its replacement
+    wal_lock: RwLock<()>,
+    // The configuration of the disk, shared by its trees
+    config: Arc<Config>,
+    params: LsmParams,
 }
 
+/// The SSTs pinned by the `TreeIter`s of a tree.
+///
+/// The log of a SST compacted away is moved to `BUCKET_RETIRED` in the
+/// compaction TX, and deleted once no iterator pins it. The retired logs
+/// left by a crash are deleted on recovery.
+#[derive(Default)]
+struct SstPins {
+    // The number of iterators pinning each SST
+    counts: HashMap<TxLogId, usize>,
+    // The retired SSTs still pinned
+    retired: HashSet<TxLogId>,
+}
+
+/// The bucket of the logs of the retired SSTs, see `SstPins`.
+const BUCKET_RETIRED: &str = "RETIRED";
+
 /// Levels in a `TxLsmTree`.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum LsmLevel {
@@ -56,8 +94,37 @@ pub enum LsmLevel {
 
 /// Manager of all `SSTable`s from every level in a `TxLsmTree`.
 #[derive(Debug)]
-struct SstManager<K, V> {
+pub(super) struct SstManager<K, V> {
     level_ssts: Vec<BTreeMap<TxLogId, Arc<SSTable<K, V>>>>,
+    level0_ratio: u16,
+    level_ratio: u16,
+}
+
+/// Tunable parameters of a `TxLsmTree`.
+///
+/// The parameters can differ across recoveries, existing SSTs are still
+/// readable since each records its own record block size.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug, PartialEq, Eq)]
+pub struct LsmParams {
+    /// The capacity (in records) of each `MemTable` and `SSTable`.
+    pub memtable_capacity: u64,
+    /// The size (in bytes) of the record blocks of `SSTable`s,
+    /// a multiple of `BLOCK_SIZE`.
+    pub sst_block_size: u32,
+    /// The number of SSTs at L0 that triggers a major compaction.
+    pub level0_ratio: u16,
+    /// The size ratio between adjacent levels from L1 on, i.e., a major
+    /// compaction is triggered once Li has `level_ratio^i` SSTs.
+    pub level_ratio: u16,
+    /// The compaction policy, a `CompactionPolicyKind` as `u32`.
+    /// Unlike the others, it must not differ across recoveries.
+    pub compaction_policy: u32,
+    /// The format of the values in WALs, checkpoints and SSTs, see
+    /// `ValueFormat`. Like the compaction policy, it must not differ across
+    /// recoveries. Zero for the parameters persisted before it's introduced.
+    pub value_format: ValueFormat,
+    pub reserved: u16,
 }
 
 /// A factory of per-transaction event listeners.
@@ -100,9 +167,21 @@ pub enum TxType {
     Migration,
 }
 
+/// A scheduler that decides when `TxLsmTree` performs its major compactions.
+///
+/// A major compaction deferred by the scheduler is retried upon the next
+/// minor compaction or `TxLsmTree::manual_compaction()`. A level can't be
+/// deferred once it reaches `MAX_DEFERRED_RATIO` times its capacity.
+pub trait CompactionScheduler: Send + Sync {
+    /// Whether to defer the required major compaction from `from_level`.
+    fn should_defer(&self, from_level: LsmLevel) -> bool;
+}
+
 /// A trusted store that stores the master sync ID.
 pub trait SyncIdStore {
-    /// Read the current master sync ID from the store.
+    /// Read the current master sync ID from the store. It may fail with
+    /// `NotFound` if none has been written, e.g., the built-in store of
+    /// a disk formatted before it's introduced.
     fn read(&self) -> Result<SyncId>;
 
     /// Write the given master sync ID to the store.
@@ -121,7 +200,61 @@ pub trait RecordKey<K>:
 {
 }
 /// A trait that represents the value for a record in a `TxLsmTree`.
-pub trait RecordValue: Pod + Debug + Send + Sync + 'static {}
+///
+/// Values are stored in WALs, checkpoints and SSTs in the format given by
+/// `LsmParams::value_format`. A value type may encode itself differently
+/// per format, by default it's stored as its `Pod` layout in any format.
+///
+/// A value type may also support extents in SSTs, i.e., a run of records
+/// of consecutive keys, whose values succeed the previous ones (see
+/// `succeeds()`), is stored as the first record followed by the bytes of
+/// its successors not derived from their predecessors.
+pub trait RecordValue: Pod + Debug + Send + Sync + 'static {
+    /// Returns the size (in bytes) of a value encoded in `format`.
+    fn encoded_size(_format: ValueFormat) -> usize {
+        size_of::<Self>()
+    }
+
+    /// Appends the value encoded in `format` to `buf`.
+    fn encode(&self, _format: ValueFormat, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    /// Decodes a value in `format` from `buf`, which has exactly
+    /// `encoded_size(format)` bytes.
+    fn decode(_format: ValueFormat, buf: &[u8]) -> Self {
+        Self::from_bytes(buf)
+    }
+
+    /// Returns the size (in bytes) of a value encoded in `format` as the
+    /// successor of its predecessor in an extent, or `None` if the values
+    /// are never stored in extents in `format`.
+    fn successor_size(_format: ValueFormat) -> Option<usize> {
+        None
+    }
+
+    /// Whether the value (of the next key) succeeds `prev` in `format`, i.e.,
+    /// it's derived from `prev` except for the bytes of `encode_successor()`.
+    fn succeeds(&self, _prev: &Self, _format: ValueFormat) -> bool {
+        false
+    }
+
+    /// Appends the bytes of the value not derived from its predecessor,
+    /// encoded in `format`, to `buf`.
+    fn encode_successor(&self, format: ValueFormat, buf: &mut Vec<u8>) {
+        self.encode(format, buf)
+    }
+
+    /// Decodes the successor of `prev` in `format` from `buf`, which has
+    /// exactly `successor_size(format)` bytes.
+    fn decode_successor(_prev: &Self, format: ValueFormat, buf: &[u8]) -> Self {
+        Self::decode(format, buf)
+    }
+}
+
+/// The format of the values of a `TxLsmTree`, which is only interpreted
+/// by the `RecordValue`, e.g., to store a compact layout of it.
+pub type ValueFormat = u16;
 
 /// Represent any type that includes a key and a value.
 pub trait AsKV<K, V> {
@@ -137,9 +270,14 @@ pub(super) trait AsKVex<K, V> {
     fn value_ex(&self) -> &ValueEx<V>;
 }
 
-/// Capacity of each `MemTable` and `SSTable`.
+/// Default capacity of each `MemTable` and `SSTable`.
 pub(super) const MEMTABLE_CAPACITY: usize = 2097152; // 96 MiB MemTable, cover 8 GiB data // TBD
-pub(super) const SSTABLE_CAPACITY: usize = MEMTABLE_CAPACITY;
+/// The maximum number of times of its capacity a level can grow to while
+/// its major compaction is deferred by the `CompactionScheduler`.
+pub const MAX_DEFERRED_RATIO: usize = 4;
+/// The maximum length of an uncompleted range whose keys are probed
+/// one by one in Bloom filters of SSTs.
+const MAX_FILTER_PROBES_PER_RANGE: usize = 64;
 
 impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TxLsmTree<K, V, D> {
     /// Format a `TxLsmTree` from a given `TxLogStore`.
@@ -149,6 +287,8 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TxLsmTree<K, V, D>
         on_drop_record_in_memtable: Option<Arc<dyn Fn(&dyn AsKV<K, V>)>>,
         sync_id_store: Option<Arc<dyn SyncIdStore>>,
         shared_state: Arc<SharedState>,
+        config: Arc<Config>,
+        params: LsmParams,
     ) -> Result<Self> {
         let inner = TreeInner::format(
             tx_log_store,
@@ -156,6 +296,8 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TxLsmTree<K, V, D>
             on_drop_record_in_memtable,
             sync_id_store,
             shared_state,
+            config,
+            params,
         )?;
         Ok(Self(Arc::new(inner)))
     }
@@ -167,6 +309,8 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TxLsmTree<K, V, D>
         on_drop_record_in_memtable: Option<Arc<dyn Fn(&dyn AsKV<K, V>)>>,
         sync_id_store: Option<Arc<dyn SyncIdStore>>,
         shared_state: Arc<SharedState>,
+        config: Arc<Config>,
+        params: LsmParams,
     ) -> Result<Self> {
         let inner = TreeInner::recover(
             tx_log_store,
@@ -174,6 +318,8 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TxLsmTree<K, V, D>
             on_drop_record_in_memtable,
             sync_id_store,
             shared_state,
+            config,
+            params,
         )?;
         Ok(Self(Arc::new(inner)))
     }
@@ -188,33 +334,200 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TxLsmTree<K, V, D>
         self.0.get_range(range_query_ctx)
     }
 
+    /// Gets the target values of multiple (possibly non-contiguous) keys
+    /// in one pass. The `nth` value is `None` if the `nth` key is not found.
+    pub fn get_multi(&self, keys: &[K]) -> Result<Vec<Option<V>>> {
+        self.0.get_multi(keys)
+    }
+
+    /// Returns the ID of the last sync of the tree. The records put from now
+    /// on are committed by the next sync, whose ID is one greater.
+    pub fn sync_id(&self) -> SyncId {
+        self.0.master_sync_id.id()
+    }
+
+    /// Returns an ordered iterator over the most recent records of the tree.
+    ///
+    /// The iterator sees a snapshot of the tree taken on creation, the later
+    /// puts and deletes are invisible to it. The compactions go on meanwhile,
+    /// only the logs of the SSTs it pins are kept until it is dropped.
+    pub fn iter(&self) -> TreeIter<K, V, D> {
+        TreeIter::new(self.0.clone())
+    }
+
+    /// Returns the records written since the last compaction, i.e., those
+    /// still in the mutable `MemTable`.
+    ///
+    /// Right after recovery, these are exactly the synced records replayed
+    /// from the checkpoint and the WAL, which bounds the window of records
+    /// that may have been persisted without their counterparts in another tree.
+    pub fn recent_records(&self) -> Vec<(K, V)> {
+        self.0.memtable_manager.mutable_records()
+    }
+
+    /// Returns the memory (in bytes) taken by the caches of the SSTs.
+    pub fn cached_bytes(&self) -> usize {
+        let sst_manager = self.0.sst_manager.read();
+        LsmLevel::iter()
+            .flat_map(|(level, _)| sst_manager.list_level(level))
+            .map(|(_, sst)| sst.cached_bytes())
+            .sum()
+    }
+
+    /// Sets the scheduler of major compactions, replacing the previous one.
+    pub fn set_compaction_scheduler(&self, scheduler: Arc<dyn CompactionScheduler>) {
+        let _ = self.0.compaction_scheduler.write().insert(scheduler);
+    }
+
+    /// Sets whether the records are put without being logged in the WAL,
+    /// e.g., for an ephemeral disk that is never recovered.
+    pub fn set_ephemeral(&self, ephemeral: bool) {
+        self.0.wal_append_tx.set_ephemeral(ephemeral);
+    }
+
     /// Puts a key-value record to the tree.
     pub fn put(&self, key: K, value: V) -> Result<()> {
+        self.put_batch(&[(key, value)])
+    }
+
+    /// Puts a batch of key-value records to the tree, in order.
+    ///
+    /// The records are appended to the WAL as a group, which saves
+    /// the per-record WAL overhead of `put()`.
+    pub fn put_batch(&self, records: &[(K, V)]) -> Result<()> {
+        self.do_put_batch(records, true)
+    }
+
+    /// Puts a batch of key-value records to the `MemTable` only, bypassing
+    /// the WAL, e.g., the records of temporary data.
+    ///
+    /// The records are lost on a crash before the next `sync()`, which
+    /// checkpoints the mutable `MemTable` to persist them, unless their
+    /// `MemTable` is flushed into an SST beforehand.
+    pub fn put_batch_unlogged(&self, records: &[(K, V)]) -> Result<()> {
+        self.0.has_unlogged_records.store(true, Ordering::Release);
+        self.do_put_batch(records, false)
+    }
+
+    fn do_put_batch(&self, records: &[(K, V)], logged: bool) -> Result<()> {
+        let inner = &self.0;
+        let _wal_guard = inner.wal_lock.read();
+        let mut records = records;
+        while !records.is_empty() {
+            // Only the records fitting in the mutable `MemTable` are logged,
+            // so the WAL committed on the switch doesn't hold the rest ones
+            let num_fit = inner
+                .memtable_manager
+                .remaining_capacity()
+                .clamp(1, records.len());
+            let (batch, rest) = records.split_at(num_fit);
+            if logged {
+                let timer = if inner.config.stat_cost {
+                    Some(COST_L2.time(CostL2Type::WAL))
+                } else {
+                    None
+                };
+                // Write the records to WAL
+                inner.wal_append_tx.append_batch(batch)?;
+                drop(timer);
+            }
+
+            let timer = if inner.config.stat_cost {
+                Some(COST_L2.time(CostL2Type::MemTable))
+            } else {
+                None
+            };
+            // Put the records into `MemTable` until it is at capacity
+            let num_put = batch
+                .iter()
+                .position(|(key, value)| inner.memtable_manager.put(*key, *value))
+                .map(|nth| nth + 1);
+            drop(timer);
+
+            let Some(num_put) = num_put else {
+                records = rest;
+                continue;
+            };
+            self.switch_memtable()?;
+            // The logged ones not put yet, only if filled up by the concurrent
+            // puts, are appended again to the new WAL, since the committed WAL
+            // is discarded after the flush
+            records = &records[num_put..];
+        }
+        Ok(())
+    }
+
+    /// Deletes all the records within the range of keys, with a single
+    /// range tombstone rather than one deletion per key.
+    pub fn delete_range(&self, range: Range<K>) -> Result<()> {
+        if range.is_empty() {
+            return Ok(());
+        }
         let inner = &self.0;
-        let record = (key, value);
-        let timer = if CONFIG.get().stat_cost {
+        let _wal_guard = inner.wal_lock.read();
+        let timer = if inner.config.stat_cost {
             Some(COST_L2.time(CostL2Type::WAL))
         } else {
             None
         };
-        // Write the record to WAL
-        inner.wal_append_tx.append(&record)?;
+        inner.wal_append_tx.append_range_delete(&range)?;
         drop(timer);
 
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if inner.config.stat_cost {
             Some(COST_L2.time(CostL2Type::MemTable))
         } else {
             None
         };
-        // Put the record into `MemTable`
-        let at_capacity = inner.memtable_manager.put(key, value);
+        let at_capacity = inner.memtable_manager.delete_range(range);
         drop(timer);
 
-        if !at_capacity {
+        if at_capacity {
+            self.switch_memtable()?;
+        }
+        Ok(())
+    }
+
+    /// Loads a batch of records sorted by keys into new SSTs at L0 directly,
+    /// bypassing the WAL and the `MemTable`s. The records are durable once
+    /// it returns, without a sync.
+    ///
+    /// The records whose keys are in the `MemTable`s (including the deleted
+    /// ones) are put as usual instead, since the `MemTable`s are searched
+    /// before any SST. It must not be called concurrently with the puts.
+    /// Unlike compactions, it doesn't wait for background GC, which is
+    /// excluded by the caller if required.
+    pub fn bulk_load(&self, records: &[(K, V)]) -> Result<()> {
+        debug_assert!(records.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        let inner = &self.0;
+        let (fresh, shadowed): (Vec<_>, Vec<_>) = records
+            .iter()
+            .copied()
+            .partition(|(key, _)| matches!(inner.memtable_manager.get(key), Lookup::Missing));
+        if !shadowed.is_empty() {
+            self.put_batch(&shadowed)?;
+        }
+        if fresh.is_empty() {
             return Ok(());
         }
 
-        let timer = if CONFIG.get().stat_cost {
+        inner.shared_state.start_compaction();
+        let res = fresh
+            .chunks(inner.params.memtable_capacity as usize)
+            .try_for_each(|chunk| inner.do_bulk_load_tx(chunk))
+            .and_then(|_| {
+                if inner.require_scheduled_major_compaction(LsmLevel::L0) {
+                    inner.do_major_compaction(LsmLevel::L1)?;
+                }
+                Ok(())
+            });
+        inner.shared_state.notify_compaction_finished();
+        res
+    }
+
+    /// Switches the full `MemTable` and triggers compaction.
+    fn switch_memtable(&self) -> Result<()> {
+        let inner = &self.0;
+        let timer = if inner.config.stat_cost {
             Some(COST_L2.time(CostL2Type::WAL))
         } else {
             None
@@ -224,17 +537,18 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TxLsmTree<K, V, D>
         let wal_id = inner.wal_append_tx.commit()?;
         drop(timer);
 
-        // Wait asynchronous compaction to finish
+        // Wait for the immutable `MemTable` to be flushed, then switch
         // TODO: Error handling for compaction: try twice or become read-only
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if inner.config.stat_cost {
             Some(COST_L2.time(CostL2Type::Compaction))
         } else {
             None
         };
-        inner.compactor.wait_compaction()?;
+        inner.compactor.wait_flush()?;
         drop(timer);
 
-        inner.memtable_manager.switch().unwrap();
+        inner.compactor.begin_flush();
+        inner.memtable_manager.switch()?;
 
         // Trigger compaction when `MemTable` is at capacity
         self.do_compaction_tx(wal_id)
@@ -246,17 +560,29 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TxLsmTree<K, V, D>
 
         inner.compactor.wait_compaction()?;
 
-        inner.memtable_manager.switch().unwrap();
+        inner.compactor.begin_flush();
+        inner.memtable_manager.switch()?;
 
         self.do_compaction_tx(wal_id)?;
         Ok(())
     }
 
     /// Persist all in-memory data of `TxLsmTree` to the backed storage.
+    ///
+    /// The mutable `MemTable` is also checkpointed once it is due by
+    /// `Config::checkpoint_interval` or `Config::checkpoint_dirty_bytes`.
     pub fn sync(&self) -> Result<()> {
         self.0.sync()
     }
 
+    /// Syncs the tree, then checkpoints the mutable `MemTable` to replace
+    /// the WAL, so that recovery only replays the WAL after the checkpoint.
+    ///
+    /// Like `sync()`, the concurrent puts are held off until it returns.
+    pub fn checkpoint(&self) -> Result<()> {
+        self.0.checkpoint()
+    }
+
     pub fn manual_compaction(&self) -> Result<()> {
         #[cfg(not(feature = "linux"))]
         debug!("Manual compaction started");
@@ -308,31 +634,38 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TxLsmTree<K, V, D>
 
     /// Do a compaction TX.
     /// The given `wal_id` is used to identify the WAL for discarding.
+    /// Its flush of the immutable `MemTable` is marked by
+    /// `Compactor::begin_flush()` before switching.
     fn do_compaction_tx(&self, wal_id: TxLogId) -> Result<()> {
         let inner = self.0.clone();
-        let stat_cost = CONFIG.get().stat_cost;
+        let stat_cost = inner.config.stat_cost;
+        // Major compactions of the last round may still be running
+        let last_handle = inner.compactor.take_handle();
         let handle = spawn(move || -> Result<()> {
             let timer = if stat_cost {
                 Some(COST_L2.time(CostL2Type::Compaction))
             } else {
                 None
             };
-            // Wait for background GC to finish
-            #[cfg(not(feature = "linux"))]
-            debug!("Compaction TX: waiting for background GC to finish");
-            inner.shared_state.wait_for_background_gc();
-            inner.shared_state.start_compaction();
-            // Do major compaction first if necessary
-            if inner
-                .sst_manager
-                .read()
-                .require_major_compaction(LsmLevel::L0)
-            {
+            let res = last_handle
+                .map_or(Ok(()), |handle| handle.join().unwrap())
+                .and_then(|_| {
+                    // Wait for background GC to finish
+                    #[cfg(not(feature = "linux"))]
+                    debug!("Compaction TX: waiting for background GC to finish");
+                    inner.shared_state.wait_for_background_gc();
+                    inner.shared_state.start_compaction();
+
+                    // Do minor compaction first to release the immutable `MemTable`
+                    inner.do_minor_compaction(wal_id)
+                });
+            inner.compactor.finish_flush(res.is_ok());
+            res?;
+
+            // Then do major compaction if necessary and not deferred
+            if inner.require_scheduled_major_compaction(LsmLevel::L0) {
                 inner.do_major_compaction(LsmLevel::L1)?;
             }
-
-            // Do minor compaction
-            inner.do_minor_compaction(wal_id)?;
             drop(timer);
             // Notify background GC to proceed
             inner.shared_state.notify_compaction_finished();
@@ -352,21 +685,33 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
         on_drop_record_in_memtable: Option<Arc<dyn Fn(&dyn AsKV<K, V>)>>,
         sync_id_store: Option<Arc<dyn SyncIdStore>>,
         shared_state: Arc<SharedState>,
+        config: Arc<Config>,
+        params: LsmParams,
     ) -> Result<Self> {
+        params.validate()?;
         let sync_id: SyncId = 0;
         Ok(Self {
             memtable_manager: MemTableManager::new(
                 sync_id,
-                MEMTABLE_CAPACITY,
+                params.memtable_capacity as _,
                 on_drop_record_in_memtable,
             ),
-            sst_manager: RwLock::new(SstManager::new()),
-            wal_append_tx: WalAppendTx::new(&tx_log_store, sync_id),
+            sst_manager: RwLock::new(SstManager::new(&params)),
+            wal_append_tx: WalAppendTx::new(&tx_log_store, sync_id, params.value_format, &config),
             compactor: Compactor::new(),
             tx_log_store,
             listener_factory,
             shared_state,
             master_sync_id: MasterSyncId::new(sync_id_store, sync_id)?,
+            compaction_scheduler: RwLock::new(None),
+            compaction_policy: params.compaction_policy_kind()?.build(),
+            major_compaction_lock: CvarMutex::new(()),
+            sst_pins: Mutex::new(SstPins::default()),
+            last_checkpoint: Mutex::new(RealClock.now()),
+            has_unlogged_records: AtomicBool::new(false),
+            wal_lock: RwLock::new(()),
+            config,
+            params,
         })
     }
 
@@ -376,29 +721,50 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
         on_drop_record_in_memtable: Option<Arc<dyn Fn(&dyn AsKV<K, V>)>>,
         sync_id_store: Option<Arc<dyn SyncIdStore>>,
         shared_state: Arc<SharedState>,
+        config: Arc<Config>,
+        params: LsmParams,
     ) -> Result<Self> {
-        let (synced_records, wal_sync_id) = Self::recover_from_wal(&tx_log_store)?;
-        let (sst_manager, ssts_sync_id) = Self::recover_sst_manager(&tx_log_store)?;
-
-        let max_sync_id = wal_sync_id.max(ssts_sync_id);
-        let master_sync_id = MasterSyncId::new(sync_id_store, max_sync_id)?;
+        params.validate()?;
+        let checkpoint = Self::recover_from_checkpoint(&tx_log_store, params.value_format)?;
+        let (synced_entries, wal_sync_id) =
+            Self::recover_from_wal(&tx_log_store, params.value_format)?;
+        let (sst_manager, ssts_sync_id) =
+            Self::recover_sst_manager(&tx_log_store, &params, &config)?;
+
+        let checkpoint_sync_id = checkpoint.as_ref().map_or(0, |ckpt| ckpt.sync_id());
+        let max_sync_id = wal_sync_id.max(ssts_sync_id).max(checkpoint_sync_id);
+        let master_sync_id = MasterSyncId::recover(sync_id_store, max_sync_id)?;
         let sync_id = master_sync_id.id();
 
         let memtable_manager = Self::recover_memtable_manager(
             sync_id,
-            synced_records.into_iter(),
+            params.memtable_capacity as _,
+            // The WAL only contains the entries after the checkpoint
+            checkpoint
+                .into_iter()
+                .flat_map(Checkpoint::into_entries)
+                .chain(synced_entries),
             on_drop_record_in_memtable,
         );
 
         let recov_self = Self {
             memtable_manager,
             sst_manager: RwLock::new(sst_manager),
-            wal_append_tx: WalAppendTx::new(&tx_log_store, sync_id),
+            wal_append_tx: WalAppendTx::new(&tx_log_store, sync_id, params.value_format, &config),
             compactor: Compactor::new(),
             tx_log_store,
             listener_factory,
             shared_state,
             master_sync_id,
+            compaction_scheduler: RwLock::new(None),
+            compaction_policy: params.compaction_policy_kind()?.build(),
+            major_compaction_lock: CvarMutex::new(()),
+            sst_pins: Mutex::new(SstPins::default()),
+            last_checkpoint: Mutex::new(RealClock.now()),
+            has_unlogged_records: AtomicBool::new(false),
+            wal_lock: RwLock::new(()),
+            config,
+            params,
         };
 
         recov_self.do_migration_tx()?;
@@ -408,8 +774,36 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
         Ok(recov_self)
     }
 
-    /// Recover the synced records and the maximum sync ID from the latest WAL.
-    fn recover_from_wal(tx_log_store: &Arc<TxLogStore<D>>) -> Result<(Vec<(K, V)>, SyncId)> {
+    /// Recover the latest checkpoint of the mutable `MemTable`, if any.
+    fn recover_from_checkpoint(
+        tx_log_store: &Arc<TxLogStore<D>>,
+        value_format: ValueFormat,
+    ) -> Result<Option<Checkpoint<K, V>>> {
+        let mut tx = tx_log_store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            let ckpt_res = tx_log_store.open_log_in(BUCKET_CHECKPOINT);
+            if let Err(e) = &ckpt_res
+                && e.errno() == NotFound
+            {
+                return Ok(None);
+            }
+            let ckpt = ckpt_res?;
+            Checkpoint::from_log(&ckpt, value_format).map(Some)
+        });
+        if res.is_ok() {
+            tx.commit()?;
+        } else {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "recover from checkpoint failed");
+        }
+        res
+    }
+
+    /// Recover the synced entries and the maximum sync ID from the latest WAL.
+    fn recover_from_wal(
+        tx_log_store: &Arc<TxLogStore<D>>,
+        value_format: ValueFormat,
+    ) -> Result<(Vec<WalEntry<K, V>>, SyncId)> {
         let mut tx = tx_log_store.new_tx();
         let res: Result<_> = tx.context(|| {
             let wal_res = tx_log_store.open_log_in(BUCKET_WAL);
@@ -420,7 +814,7 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
             }
             let wal = wal_res?;
             // Only synced records count, all unsynced are discarded
-            WalAppendTx::collect_synced_records_and_sync_id::<K, V>(&wal)
+            WalAppendTx::collect_synced_entries_and_sync_id::<K, V>(&wal, value_format)
         });
         if res.is_ok() {
             tx.commit()?;
@@ -431,16 +825,19 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
         res
     }
 
-    /// Recover `MemTable` from the given synced records.
+    /// Recover `MemTable` from the given synced entries, in order.
     fn recover_memtable_manager(
         sync_id: SyncId,
-        synced_records: impl Iterator<Item = (K, V)>,
+        capacity: usize,
+        synced_entries: impl Iterator<Item = WalEntry<K, V>>,
         on_drop_record_in_memtable: Option<Arc<dyn Fn(&dyn AsKV<K, V>)>>,
     ) -> MemTableManager<K, V> {
-        let memtable_manager =
-            MemTableManager::new(sync_id, MEMTABLE_CAPACITY, on_drop_record_in_memtable);
-        synced_records.into_iter().for_each(|(k, v)| {
-            let _ = memtable_manager.put(k, v);
+        let memtable_manager = MemTableManager::new(sync_id, capacity, on_drop_record_in_memtable);
+        synced_entries.for_each(|entry| {
+            let _ = match entry {
+                WalEntry::Record(k, v) => memtable_manager.put(k, v),
+                WalEntry::RangeDelete(range) => memtable_manager.delete_range(range),
+            };
         });
         memtable_manager
     }
@@ -449,11 +846,21 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
     /// Return the recovered `SstManager` and the maximum sync ID present.
     fn recover_sst_manager(
         tx_log_store: &Arc<TxLogStore<D>>,
+        params: &LsmParams,
+        config: &Config,
     ) -> Result<(SstManager<K, V>, SyncId)> {
-        let mut manager = SstManager::new();
+        let mut manager = SstManager::new(params);
         let mut max_sync_id: SyncId = 0;
         let mut tx = tx_log_store.new_tx();
         let res: Result<_> = tx.context(|| {
+            // The retired SSTs are pinned by no iterator after a restart
+            let retired_ids = tx_log_store.list_logs_in(BUCKET_RETIRED);
+            if !retired_ids.as_ref().is_err_and(|e| e.errno() == NotFound) {
+                for id in retired_ids? {
+                    tx_log_store.delete_log(id)?;
+                }
+            }
+
             for (level, bucket) in LsmLevel::iter() {
                 let log_ids = tx_log_store.list_logs_in(bucket);
                 if let Err(e) = &log_ids
@@ -464,9 +871,9 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
 
                 for id in log_ids? {
                     let log = tx_log_store.open_log(id, false)?;
-                    let sst = SSTable::<K, V>::from_log(&log)?;
+                    let sst = SSTable::<K, V>::from_log(&log, params.value_format, config)?;
                     max_sync_id = max_sync_id.max(sst.sync_id());
-                    manager.insert(SSTable::from_log(&log)?, level);
+                    manager.insert(sst, level);
                 }
             }
             Ok(())
@@ -482,13 +889,15 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
 
     pub fn get(&self, key: &K) -> Result<V> {
         // 1. Search from MemTables
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if self.config.stat_cost {
             Some(COST_L2.time(CostL2Type::MemTable))
         } else {
             None
         };
-        if let Some(value) = self.memtable_manager.get(key) {
-            return Ok(value);
+        match self.memtable_manager.get(key) {
+            Lookup::Found(value) => return Ok(value),
+            Lookup::Deleted => return_errno_with_msg!(NotFound, "target key deleted"),
+            Lookup::Missing => {}
         }
         drop(timer);
 
@@ -499,7 +908,7 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
     }
 
     pub fn get_range(&self, range_query_ctx: &mut RangeQueryCtx<K, V>) -> Result<()> {
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if self.config.stat_cost {
             Some(COST_L2.time(CostL2Type::MemTable))
         } else {
             None
@@ -515,29 +924,80 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
         Ok(())
     }
 
+    pub fn get_multi(&self, keys: &[K]) -> Result<Vec<Option<V>>> {
+        // 1. Search from MemTables
+        let timer = if self.config.stat_cost {
+            Some(COST_L2.time(CostL2Type::MemTable))
+        } else {
+            None
+        };
+        let mut lookups: Vec<Lookup<V>> = keys
+            .iter()
+            .map(|key| self.memtable_manager.get(key))
+            .collect();
+        drop(timer);
+
+        // 2. Search from SSTs (do Read TX)
+        if lookups
+            .iter()
+            .any(|lookup| matches!(lookup, Lookup::Missing))
+        {
+            self.do_read_multi_tx(keys, &mut lookups)?;
+        }
+
+        let values = lookups
+            .into_iter()
+            .map(|lookup| match lookup {
+                Lookup::Found(value) => Some(value),
+                Lookup::Deleted | Lookup::Missing => None,
+            })
+            .collect();
+        Ok(values)
+    }
+
     pub fn sync(&self) -> Result<()> {
+        let _wal_guard = self.wal_lock.write();
+        let wal_nblocks = self.do_sync()?;
+        // The records bypassing the WAL are only persisted by a checkpoint
+        if self.has_unlogged_records.load(Ordering::Acquire) || self.require_checkpoint(wal_nblocks)
+        {
+            self.do_checkpoint_tx()?;
+        }
+        Ok(())
+    }
+
+    pub fn checkpoint(&self) -> Result<()> {
+        let _wal_guard = self.wal_lock.write();
+        let _ = self.do_sync()?;
+        self.do_checkpoint_tx()
+    }
+
+    /// Syncs the WAL and the mutable `MemTable`,
+    /// return the number of blocks of the synced WAL.
+    fn do_sync(&self) -> Result<usize> {
         let master_sync_id = self.master_sync_id.id() + 1;
 
-        // Wait asynchronous compaction to finish
+        // Wait for the immutable `MemTable` to be flushed, the records in
+        // which are only persisted by the committed WAL until then
         // TODO: Error handling for compaction: try twice or become read-only
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if self.config.stat_cost {
             Some(COST_L2.time(CostL2Type::Compaction))
         } else {
             None
         };
-        self.compactor.wait_compaction()?;
+        self.compactor.wait_flush()?;
         drop(timer);
 
         // TODO: Error handling for WAL: try twice or become read-only
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if self.config.stat_cost {
             Some(COST_L2.time(CostL2Type::WAL))
         } else {
             None
         };
-        self.wal_append_tx.sync(master_sync_id)?;
+        let wal_nblocks = self.wal_append_tx.sync(master_sync_id)?;
         drop(timer);
 
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if self.config.stat_cost {
             Some(COST_L2.time(CostL2Type::MemTable))
         } else {
             None
@@ -547,7 +1007,19 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
 
         // TODO: Error handling: try twice or ignore
         self.master_sync_id.increment()?;
-        Ok(())
+        Ok(wal_nblocks)
+    }
+
+    /// Whether a checkpoint is due by `Config::checkpoint_interval`
+    /// or `Config::checkpoint_dirty_bytes`, given the size of the WAL.
+    fn require_checkpoint(&self, wal_nblocks: usize) -> bool {
+        let config = &self.config;
+        config
+            .checkpoint_dirty_bytes
+            .is_some_and(|dirty_bytes| wal_nblocks * BLOCK_SIZE >= dirty_bytes)
+            || config.checkpoint_interval.is_some_and(|interval| {
+                RealClock.now().saturating_sub(*self.last_checkpoint.lock()) >= interval
+            })
     }
 
     /// TXs in `TxLsmTree`
@@ -555,7 +1027,7 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
     /// Read TX.
     fn do_read_tx(&self, key: &K) -> Result<V> {
         let mut tx = self.tx_log_store.new_tx();
-        let stat_cost = CONFIG.get().stat_cost;
+        let stat_cost = self.config.stat_cost;
 
         let read_res: Result<_> = tx.context(|| {
             // Search each level from top to bottom (newer to older)
@@ -568,12 +1040,10 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
 
             for (level, _bucket) in LsmLevel::iter() {
                 for (_id, sst) in sst_manager.list_level(level) {
-                    if !sst.is_within_range(key) {
-                        continue;
-                    }
-
-                    if let Ok(target_value) = sst.access_point(key, &self.tx_log_store) {
-                        return Ok(target_value);
+                    match self.lookup_sst(sst, key)? {
+                        Lookup::Found(target_value) => return Ok(target_value),
+                        Lookup::Deleted => return_errno_with_msg!(NotFound, "target key deleted"),
+                        Lookup::Missing => {}
                     }
                 }
             }
@@ -591,11 +1061,61 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
         read_res
     }
 
+    /// Read Multi TX. Fills the missing `lookups` of `keys` within one TX,
+    /// so that each SST is visited at most once.
+    fn do_read_multi_tx(&self, keys: &[K], lookups: &mut [Lookup<V>]) -> Result<()> {
+        let mut tx = self.tx_log_store.new_tx();
+        let stat_cost = self.config.stat_cost;
+
+        let read_res: Result<_> = tx.context(|| {
+            // Search each level from top to bottom (newer to older)
+            let timer = if stat_cost {
+                Some(COST_L2.time(CostL2Type::SSTableLookup))
+            } else {
+                None
+            };
+            let sst_manager = self.sst_manager.read();
+            let mut num_uncompleted = lookups
+                .iter()
+                .filter(|lookup| matches!(lookup, Lookup::Missing))
+                .count();
+
+            for (level, _bucket) in LsmLevel::iter() {
+                for (_id, sst) in sst_manager.list_level(level) {
+                    for (key, lookup) in keys.iter().zip(lookups.iter_mut()) {
+                        if !matches!(lookup, Lookup::Missing) {
+                            continue;
+                        }
+
+                        *lookup = self.lookup_sst(sst, key)?;
+                        if !matches!(lookup, Lookup::Missing) {
+                            num_uncompleted -= 1;
+                        }
+                    }
+
+                    if num_uncompleted == 0 {
+                        return Ok(());
+                    }
+                }
+            }
+            drop(timer);
+            Ok(())
+        });
+        if read_res.is_err() {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "read TX failed")
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
     /// Read Range TX.
     fn do_read_range_tx(&self, range_query_ctx: &mut RangeQueryCtx<K, V>) -> Result<()> {
         debug_assert!(!range_query_ctx.is_completed());
         let mut tx = self.tx_log_store.new_tx();
-        let stat_cost = CONFIG.get().stat_cost;
+        let stat_cost = self.config.stat_cost;
 
         let read_res: Result<_> = tx.context(|| {
             // Search each level from top to bottom (newer to older)
@@ -611,7 +1131,11 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
                         continue;
                     }
 
-                    sst.access_range(range_query_ctx, &self.tx_log_store)?;
+                    if self.probe_filter_in_range(sst, range_query_ctx)? {
+                        sst.access_range(range_query_ctx, &self.tx_log_store)?;
+                    }
+                    // The records of the SST are newer than its tombstones
+                    sst.complete_deleted(range_query_ctx);
 
                     if range_query_ctx.is_completed() {
                         return Ok(());
@@ -631,8 +1155,183 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
         read_res
     }
 
+    /// Takes a snapshot of the tree, which consists of the `MemTable`s and
+    /// the `SSTable`s from newer to older and the master sync ID. The logs
+    /// of the SSTs are pinned until `unpin_ssts()`.
+    pub(super) fn pin_snapshot(
+        &self,
+    ) -> (
+        Vec<MemTableSnapshot<K, V>>,
+        Vec<(TxLogId, Arc<SSTable<K, V>>)>,
+        SyncId,
+    ) {
+        // The `MemTable`s go first, their records may be flushed to
+        // a new SST meanwhile, but never missed
+        let memtables = self.memtable_manager.snapshot();
+        // The SSTs are listed and pinned at once, so that a compaction
+        // either sees the pins or has removed the SSTs from the list
+        let mut sst_pins = self.sst_pins.lock();
+        let sst_manager = self.sst_manager.read();
+        let ssts: Vec<_> = LsmLevel::iter()
+            .flat_map(|(level, _)| sst_manager.list_level(level))
+            .map(|(&id, sst)| (id, sst.clone()))
+            .collect();
+        for (id, _) in ssts.iter() {
+            *sst_pins.counts.entry(*id).or_insert(0) += 1;
+        }
+        (memtables, ssts, self.master_sync_id.id())
+    }
+
+    /// Unpins the SSTs of a snapshot taken by `pin_snapshot()`, the logs
+    /// retired meanwhile are deleted once unpinned by all.
+    pub(super) fn unpin_ssts(&self, ids: &[TxLogId]) -> Result<()> {
+        let mut unpinned = Vec::new();
+        let mut sst_pins = self.sst_pins.lock();
+        for id in ids {
+            let count = sst_pins.counts.get_mut(id).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                sst_pins.counts.remove(id);
+                if sst_pins.retired.remove(id) {
+                    unpinned.push(*id);
+                }
+            }
+        }
+        drop(sst_pins);
+        self.delete_retired_logs(&unpinned)
+    }
+
+    /// Deletes the logs of the SSTs retired by a compaction TX, except
+    /// for those pinned, which are left to the last `unpin_ssts()`.
+    fn release_retired_ssts(&self, ids: impl Iterator<Item = TxLogId>) -> Result<()> {
+        let mut sst_pins = self.sst_pins.lock();
+        let unpinned: Vec<_> = ids
+            .filter(|id| {
+                if sst_pins.counts.contains_key(id) {
+                    sst_pins.retired.insert(*id);
+                    false
+                } else {
+                    true
+                }
+            })
+            .collect();
+        drop(sst_pins);
+        self.delete_retired_logs(&unpinned)
+    }
+
+    /// Deletes the retired logs in a TX.
+    fn delete_retired_logs(&self, ids: &[TxLogId]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut tx = self.tx_log_store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            for id in ids {
+                self.tx_log_store.delete_log(*id)?;
+            }
+            Ok(())
+        });
+        if res.is_err() {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "delete retired SSTs TX failed");
+        }
+        tx.commit()
+    }
+
+    /// Reads the records of the `nth` record block of the SST in a read TX.
+    pub(super) fn read_record_block(
+        &self,
+        sst: &SSTable<K, V>,
+        nth: usize,
+        sync_id: SyncId,
+    ) -> Result<Vec<(K, ValueEx<V>)>> {
+        let mut tx = self.tx_log_store.new_tx();
+        let read_res: Result<_> =
+            tx.context(|| sst.access_record_block(nth, sync_id, &self.tx_log_store));
+        if read_res.is_err() {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "read TX failed")
+        }
+
+        tx.commit()?;
+
+        read_res
+    }
+
+    /// Looks up the key in the SST. Its records are searched only if the
+    /// Bloom filter may contain the key, while its range tombstones are
+    /// checked anyway.
+    fn lookup_sst(&self, sst: &SSTable<K, V>, key: &K) -> Result<Lookup<V>> {
+        if !sst.is_within_range(key) {
+            return Ok(Lookup::Missing);
+        }
+
+        if self.probe_filter(sst, key)? {
+            match sst.access_point(key, &self.tx_log_store)? {
+                Lookup::Missing => COST_L2.record_filter_false_positive(),
+                lookup => return Ok(lookup),
+            }
+        }
+        if sst.is_deleted(key) {
+            Ok(Lookup::Deleted)
+        } else {
+            Ok(Lookup::Missing)
+        }
+    }
+
+    /// Whether the key may be in the SST according to its Bloom filter,
+    /// the probe is recorded to `COST_L2`.
+    fn probe_filter(&self, sst: &SSTable<K, V>, key: &K) -> Result<bool> {
+        let may_contain = sst.may_contain(key, &self.tx_log_store)?;
+        COST_L2.record_filter_probe(may_contain);
+        Ok(may_contain)
+    }
+
+    /// Whether any uncompleted key of the range query may be in the SST
+    /// according to its Bloom filter. Only short ranges are probed key by key,
+    /// longer ones are assumed to hit.
+    fn probe_filter_in_range(
+        &self,
+        sst: &SSTable<K, V>,
+        range_query_ctx: &RangeQueryCtx<K, V>,
+    ) -> Result<bool> {
+        let range = range_query_ctx.range_uncompleted().unwrap();
+        if *range.end() - *range.start() >= MAX_FILTER_PROBES_PER_RANGE {
+            return Ok(true);
+        }
+
+        for key in range_query_ctx.uncompleted_keys() {
+            if sst.is_within_range(&key) && sst.may_contain(&key, &self.tx_log_store)? {
+                COST_L2.record_filter_probe(true);
+                return Ok(true);
+            }
+        }
+        COST_L2.record_filter_probe(false);
+        Ok(false)
+    }
+
+    /// Check whether a major compaction from `from_level` is required
+    /// and not deferred by the `CompactionScheduler`.
+    fn require_scheduled_major_compaction(&self, from_level: LsmLevel) -> bool {
+        let sst_manager = self.sst_manager.read();
+        if !sst_manager.require_major_compaction(from_level) {
+            return false;
+        }
+        if sst_manager.exceed_deferral_limit(from_level) {
+            return true;
+        }
+        drop(sst_manager);
+
+        !self
+            .compaction_scheduler
+            .read()
+            .as_ref()
+            .is_some_and(|scheduler| scheduler.should_defer(from_level))
+    }
+
     /// Minor Compaction TX { to_level: LsmLevel::L0 }.
     fn do_minor_compaction(&self, wal_id: TxLogId) -> Result<()> {
+        trace_span!("minor_compaction", wal_id);
         let mut tx = self.tx_log_store.new_tx();
         // Prepare TX listener
         let tx_type = TxType::Compaction {
@@ -655,8 +1354,19 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
             let records_iter = immutable_memtable.iter();
             let sync_id = immutable_memtable.sync_id();
 
-            let sst = SSTable::build(records_iter, sync_id, &tx_log, Some(&event_listener))?;
+            let sst = SSTable::build(
+                records_iter,
+                |_| immutable_memtable.tombstones().clone(),
+                sync_id,
+                self.params.sst_block_size as _,
+                self.params.value_format,
+                &self.config,
+                &tx_log,
+                Some(&event_listener),
+            )?;
             self.tx_log_store.delete_log(wal_id)?;
+            // The checkpoints of the flushed `MemTable` are obsolete
+            self.delete_checkpoints()?;
             Ok(sst)
         });
         let new_sst = res.map_err(|_| {
@@ -682,77 +1392,251 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
         Ok(())
     }
 
-    /// Major Compaction TX { to_level: LsmLevel::L1~LsmLevel::L5 }.
-    fn do_major_compaction(&self, to_level: LsmLevel) -> Result<()> {
-        let from_level = to_level.upper_level();
+    /// Bulk Load TX { to_level: LsmLevel::L0 }, builds a new SST from the
+    /// sorted records, which are regarded as synced.
+    fn do_bulk_load_tx(&self, records: &[(K, V)]) -> Result<()> {
+        trace_span!("bulk_load", nrecords = records.len());
         let mut tx = self.tx_log_store.new_tx();
-
-        // Prepare TX listener
-        let tx_type = TxType::Compaction { to_level };
+        // Prepare TX listener, the records are added as a minor compaction does
+        let tx_type = TxType::Compaction {
+            to_level: LsmLevel::L0,
+        };
         let event_listener = self.listener_factory.new_event_listener(tx_type);
         event_listener.on_tx_begin(&mut tx).map_err(|_| {
             tx.abort();
-            Error::with_msg(
-                TxAborted,
-                "major compaction TX callback 'on_tx_begin' failed",
+            Error::with_msg(TxAborted, "bulk load TX callback 'on_tx_begin' failed")
+        })?;
+
+        let res: Result<_> = tx.context(|| {
+            let tx_log = self.tx_log_store.create_log(LsmLevel::L0.bucket())?;
+            let records_iter = records
+                .iter()
+                .map(|(key, value)| (*key, ValueEx::Synced(*value)));
+            SSTable::build(
+                records_iter,
+                |_| RangeTombstones::new(),
+                self.master_sync_id.id(),
+                self.params.sst_block_size as _,
+                self.params.value_format,
+                &self.config,
+                &tx_log,
+                Some(&event_listener),
             )
+        });
+        let new_sst = res.map_err(|_| {
+            tx.abort();
+            Error::with_msg(TxAborted, "bulk load TX failed")
         })?;
 
-        let master_sync_id = self.master_sync_id.id();
-        let tx_log_store = self.tx_log_store.clone();
-        let listener = event_listener.clone();
-        let res: Result<_> = tx.context(move || {
-            let (mut created_ssts, mut deleted_ssts) = (vec![], vec![]);
+        event_listener.on_tx_precommit(&mut tx).map_err(|_| {
+            tx.abort();
+            Error::with_msg(TxAborted, "bulk load TX callback 'on_tx_precommit' failed")
+        })?;
 
-            // Collect overlapped SSTs
-            let sst_manager = self.sst_manager.read();
-            let (upper_sst_id, upper_sst) = sst_manager
-                .list_level(from_level)
-                .last() // Choose the oldest SST from upper level
-                .map(|(id, sst)| (*id, sst.clone()))
-                .unwrap();
-            let lower_ssts: Vec<(TxLogId, Arc<SSTable<K, V>>)> = {
-                let mut ssts = sst_manager
-                    .find_overlapped_ssts(&upper_sst.range(), to_level)
-                    .map(|(id, sst)| (*id, sst.clone()))
-                    .collect::<Vec<_>>();
-                ssts.sort_by_key(|(_, sst)| *sst.range().start());
-                ssts
-            };
-            drop(sst_manager);
+        tx.commit()?;
+        event_listener.on_tx_commit();
 
-            // If there are no overlapped SSTs, just move the upper SST to the lower level
-            if lower_ssts.is_empty() {
-                tx_log_store.move_log(upper_sst_id, from_level.bucket(), to_level.bucket())?;
-                self.sst_manager
-                    .write()
-                    .move_sst(upper_sst_id, from_level, to_level);
-                return Ok((created_ssts, deleted_ssts));
+        self.sst_manager.write().insert(new_sst, LsmLevel::L0);
+        Ok(())
+    }
+
+    /// Checkpoint TX.
+    ///
+    /// The synced contents of the mutable `MemTable` are persisted as a new
+    /// checkpoint, which replaces the last checkpoint and the synced WAL in
+    /// the same TX. The later records are appended to a new WAL.
+    ///
+    /// It must be called with `wal_lock` held for write right after the sync,
+    /// so that no record is appended to the synced WAL meanwhile.
+    fn do_checkpoint_tx(&self) -> Result<()> {
+        let wal_id = self.wal_append_tx.log_id();
+        // Taken before the snapshot, so that the later unlogged records
+        // are left to the next checkpoint
+        let has_unlogged_records = self.has_unlogged_records.swap(false, Ordering::AcqRel);
+        // Nothing is put since the last checkpoint (or compaction)
+        if wal_id.is_none() && !has_unlogged_records {
+            return Ok(());
+        }
+        let checkpoint = Checkpoint::new(&self.memtable_manager.mutable_memtable());
+
+        let mut tx = self.tx_log_store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            self.delete_checkpoints()?;
+            let tx_log = self.tx_log_store.create_log(BUCKET_CHECKPOINT)?;
+            checkpoint.write(&tx_log, self.params.value_format)?;
+            match wal_id {
+                Some(wal_id) => self.tx_log_store.delete_log(wal_id),
+                None => Ok(()),
             }
+        });
+        if res.is_err() {
+            tx.abort();
+            self.has_unlogged_records
+                .fetch_or(has_unlogged_records, Ordering::AcqRel);
+            return_errno_with_msg!(TxAborted, "checkpoint TX failed");
+        }
+        tx.commit().inspect_err(|_| {
+            self.has_unlogged_records
+                .fetch_or(has_unlogged_records, Ordering::AcqRel);
+        })?;
 
-            let upper_records_iter =
-                upper_sst.iter(master_sync_id, false, &tx_log_store, Some(&listener));
+        self.wal_append_tx.detach();
+        *self.last_checkpoint.lock() = RealClock.now();
 
-            let lower_records_iter = lower_ssts.iter().flat_map(|(_, sst)| {
-                sst.iter(master_sync_id, false, &tx_log_store, Some(&listener))
-            });
+        #[cfg(not(feature = "linux"))]
+        debug!("[SwornDisk TxLsmTree] Checkpoint completed");
+        Ok(())
+    }
+
+    /// Deletes all the checkpoints, must be called within a TX.
+    fn delete_checkpoints(&self) -> Result<()> {
+        let ckpt_ids = match self.tx_log_store.list_logs_in(BUCKET_CHECKPOINT) {
+            Err(e) if e.errno() == NotFound => return Ok(()),
+            res => res?,
+        };
+        for id in ckpt_ids {
+            self.tx_log_store.delete_log(id)?;
+        }
+        Ok(())
+    }
+
+    /// Major Compaction { to_level: LsmLevel::L1~LsmLevel::L5 }.
+    ///
+    /// The picked SSTs are compacted partition by partition, one TX for each
+    /// key-range partition, so that each TX only rewrites a part of the SSTs
+    /// and the new SSTs are visible as soon as their partition is done.
+    /// An upper SST straddling partitions is deleted in the TX of its last
+    /// partition, the other TXs only compact its records within the partition.
+    fn do_major_compaction(&self, to_level: LsmLevel) -> Result<()> {
+        let from_level = to_level.upper_level();
+        trace_span!("major_compaction", from = ?from_level, to = ?to_level);
+        let major_compaction_guard = self.major_compaction_lock.lock().unwrap();
+        // The upper level may have been compacted by others meanwhile
+        if self
+            .sst_manager
+            .read()
+            .list_level(from_level)
+            .next()
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        let input = self
+            .compaction_policy
+            .pick(&self.sst_manager.read(), from_level);
+        if input.upper_runs.len() == 1 && input.lower_runs.is_empty() {
+            self.do_major_compaction_tx(&input, None, to_level)?;
+        } else {
+            for partition in Compactor::partition(&input) {
+                self.do_major_compaction_tx(&input, Some(partition), to_level)?;
+            }
+        }
+        drop(major_compaction_guard);
+
+        #[cfg(not(feature = "linux"))]
+        debug!("[SwornDisk TxLsmTree] Major Compaction completed");
+
+        // Continue to do major compaction if necessary
+        if to_level != LsmLevel::L5 && self.sst_manager.read().require_major_compaction(to_level) {
+            self.do_major_compaction(to_level.lower_level())?;
+        }
+        Ok(())
+    }
+
+    /// Major Compaction TX { to_level: LsmLevel::L1~LsmLevel::L5 } of the
+    /// picked SSTs within the given partition. If no partition is given,
+    /// the single upper run is just moved to the lower level.
+    fn do_major_compaction_tx(
+        &self,
+        input: &CompactionInput<K, V>,
+        partition: Option<RangeInclusive<K>>,
+        to_level: LsmLevel,
+    ) -> Result<()> {
+        let from_level = to_level.upper_level();
+        let mut tx = self.tx_log_store.new_tx();
+
+        // Prepare TX listener
+        let tx_type = TxType::Compaction { to_level };
+        let event_listener = self.listener_factory.new_event_listener(tx_type);
+        event_listener.on_tx_begin(&mut tx).map_err(|_| {
+            tx.abort();
+            Error::with_msg(
+                TxAborted,
+                "major compaction TX callback 'on_tx_begin' failed",
+            )
+        })?;
+
+        let master_sync_id = self.master_sync_id.id();
+        let tx_log_store = self.tx_log_store.clone();
+        let listener = event_listener.clone();
+        let res: Result<_> = tx.context(move || {
+            let (mut created_ssts, mut deleted_ssts) = (vec![], vec![]);
+
+            // If there is a single run and no overlapped SSTs, just move the run to the lower level
+            let Some(partition) = partition else {
+                let mut sst_manager = self.sst_manager.write();
+                for (upper_sst_id, _) in &input.upper_runs[0] {
+                    tx_log_store.move_log(*upper_sst_id, from_level.bucket(), to_level.bucket())?;
+                    sst_manager.move_sst(*upper_sst_id, from_level, to_level);
+                }
+                return Ok((created_ssts, deleted_ssts));
+            };
+
+            let runs = input
+                .upper_runs
+                .iter()
+                .chain(input.lower_runs.iter())
+                .map(|run| {
+                    let ssts = run.iter().filter(|(_, sst)| sst.overlap_with(&partition));
+                    let mut tombstones = RangeTombstones::new();
+                    for (_, sst) in ssts.clone() {
+                        tombstones.extend(&sst.tombstones(master_sync_id, false).clip(&partition));
+                    }
+                    let records = Box::new(ssts.flat_map(|(_, sst)| {
+                        sst.iter_range(
+                            &partition,
+                            master_sync_id,
+                            false,
+                            &tx_log_store,
+                            Some(&listener),
+                        )
+                    })) as Box<dyn Iterator<Item = _> + '_>;
+                    (records, tombstones)
+                })
+                .collect();
 
             // Compact records then build new SSTs
             created_ssts = Compactor::compact_records_and_build_ssts(
-                upper_records_iter,
-                lower_records_iter,
+                runs,
+                &partition,
                 &tx_log_store,
                 &listener,
                 to_level,
                 master_sync_id,
+                &self.params,
+                &self.config,
             )?;
 
-            // Delete the old SSTs
-            for (id, level) in core::iter::once((upper_sst_id, from_level))
-                .chain(lower_ssts.into_iter().map(|(id, _)| (id, to_level)))
+            // Delete the old SSTs ending within the partition
+            for ((id, sst), level) in input
+                .upper_runs
+                .iter()
+                .flatten()
+                .map(|entry| (entry, from_level))
+                .chain(
+                    input
+                        .lower_runs
+                        .iter()
+                        .flatten()
+                        .map(|entry| (entry, to_level)),
+                )
             {
-                tx_log_store.delete_log(id)?;
-                deleted_ssts.push((id, level));
+                if partition.contains(sst.range().end()) {
+                    // Retired rather than deleted, in case it's pinned
+                    tx_log_store.move_log(*id, level.bucket(), BUCKET_RETIRED)?;
+                    deleted_ssts.push((*id, level));
+                }
             }
             Ok((created_ssts, deleted_ssts))
         });
@@ -772,19 +1656,12 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
         tx.commit()?;
         event_listener.on_tx_commit();
 
+        let retired_ids: Vec<_> = deleted_ssts.iter().map(|(id, _)| *id).collect();
         self.update_sst_manager(
             created_ssts.into_iter().map(|sst| (sst, to_level)),
             deleted_ssts.into_iter(),
         );
-
-        #[cfg(not(feature = "linux"))]
-        debug!("[SwornDisk TxLsmTree] Major Compaction completed");
-
-        // Continue to do major compaction if necessary
-        if self.sst_manager.read().require_major_compaction(to_level) {
-            self.do_major_compaction(to_level.lower_level())?;
-        }
-        Ok(())
+        self.release_retired_ssts(retired_ids.into_iter())
     }
 
     /// Migration TX, primarily to discard all unsynced records in SSTs.
@@ -811,22 +1688,31 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
                 // Iterate SSTs whose sync ID is equal to the
                 // master sync ID, who may have unsynced records
                 for (&id, sst) in ssts.filter(|(_, sst)| sst.sync_id() == master_sync_id) {
-                    // Collect synced records only
+                    // Collect synced records and tombstones only
                     let mut synced_records_iter = sst
                         .iter(master_sync_id, true, &tx_log_store, Some(&listener))
                         .peekable();
+                    let synced_tombstones = sst.tombstones(master_sync_id, true);
 
-                    if synced_records_iter.peek().is_some() {
+                    if synced_records_iter.peek().is_some() || !synced_tombstones.is_empty() {
                         // Create new migrated SST
                         let new_log = tx_log_store.create_log(bucket)?;
-                        let new_sst =
-                            SSTable::build(synced_records_iter, master_sync_id, &new_log, None)?;
+                        let new_sst = SSTable::build(
+                            synced_records_iter,
+                            |_| synced_tombstones,
+                            master_sync_id,
+                            self.params.sst_block_size as _,
+                            self.params.value_format,
+                            &self.config,
+                            &new_log,
+                            None,
+                        )?;
                         created_ssts.push((new_sst, level));
                         continue;
                     }
 
-                    // Delete the old SST
-                    tx_log_store.delete_log(id)?;
+                    // Retire the old SST, in case it's pinned
+                    tx_log_store.move_log(id, bucket, BUCKET_RETIRED)?;
                     deleted_ssts.push((id, level));
                 }
             }
@@ -846,8 +1732,9 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
         tx.commit()?;
         event_listener.on_tx_commit();
 
+        let retired_ids: Vec<_> = deleted_ssts.iter().map(|(id, _)| *id).collect();
         self.update_sst_manager(created_ssts.into_iter(), deleted_ssts.into_iter());
-        Ok(())
+        self.release_retired_ssts(retired_ids.into_iter())
     }
 
     fn update_sst_manager(
@@ -868,12 +1755,12 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> TreeInner<K, V, D>
 impl MasterSyncId {
     /// Create a new instance of `MasterSyncId`.
     /// Load the master sync ID from the given store if present.
-    /// If the store is not present, use the default sync ID instead.
+    /// If the store is not present, or no sync ID has been written to it,
+    /// use the default sync ID instead.
     pub fn new(store: Option<Arc<dyn SyncIdStore>>, default: SyncId) -> Result<Self> {
-        let id: SyncId = if let Some(store) = &store {
-            store.read()?
-        } else {
-            default
+        let id: SyncId = match Self::read_store(&store)? {
+            Some(id) => id,
+            None => default,
         };
         Ok(Self {
             id: AtomicU64::new(id),
@@ -881,6 +1768,33 @@ impl MasterSyncId {
         })
     }
 
+    /// Recover the master sync ID from the given store if present, which
+    /// is checked against the sync ID recovered from the disk.
+    ///
+    /// The disk is rolled back to an older state if the store is ahead of
+    /// the recovered sync ID, since the store is only written after the WAL is synced.
+    pub fn recover(store: Option<Arc<dyn SyncIdStore>>, recovered: SyncId) -> Result<Self> {
+        if let Some(id) = Self::read_store(&store)?
+            && id > recovered
+        {
+            return_errno_with_msg!(RolledBack, "disk image is older than the sync ID store");
+        }
+        Self::new(store, recovered)
+    }
+
+    /// Read the master sync ID from the given store if present,
+    /// `None` if no sync ID has been written to it.
+    fn read_store(store: &Option<Arc<dyn SyncIdStore>>) -> Result<Option<SyncId>> {
+        let Some(store) = store else {
+            return Ok(None);
+        };
+        match store.read() {
+            Ok(id) => Ok(Some(id)),
+            Err(e) if e.errno() == NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Get the current master sync ID.
     pub fn id(&self) -> SyncId {
         self.id.load(Ordering::Acquire)
@@ -928,10 +1842,47 @@ impl<K: RecordKey<K>, V: RecordValue, D: BlockSet + 'static> Clone for TxLsmTree
     }
 }
 
-impl LsmLevel {
-    const LEVEL0_RATIO: u16 = 1;
-    const LEVELI_RATIO: u16 = 10;
+impl LsmParams {
+    /// Checks whether the parameters are valid.
+    pub fn validate(&self) -> Result<()> {
+        if self.memtable_capacity == 0 {
+            return_errno_with_msg!(InvalidArgs, "MemTable capacity must be positive");
+        }
+        let sst_block_size = self.sst_block_size as usize;
+        if sst_block_size == 0
+            || sst_block_size % BLOCK_SIZE != 0
+            || sst_block_size > MAX_RECORD_BLOCK_SIZE
+        {
+            return_errno_with_msg!(InvalidArgs, "invalid SST block size");
+        }
+        if self.level0_ratio == 0 || self.level_ratio < 2 {
+            return_errno_with_msg!(InvalidArgs, "invalid LSM level ratios");
+        }
+        let _ = self.compaction_policy_kind()?;
+        Ok(())
+    }
 
+    /// Returns the compaction policy.
+    pub fn compaction_policy_kind(&self) -> Result<CompactionPolicyKind> {
+        CompactionPolicyKind::try_from(u64::from(self.compaction_policy))
+    }
+}
+
+impl Default for LsmParams {
+    fn default() -> Self {
+        Self {
+            memtable_capacity: MEMTABLE_CAPACITY as _,
+            sst_block_size: RECORD_BLOCK_SIZE as _,
+            level0_ratio: 1,
+            level_ratio: 10,
+            compaction_policy: CompactionPolicyKind::Leveled as _,
+            value_format: 0,
+            reserved: 0,
+        }
+    }
+}
+
+impl LsmLevel {
     const MAX_NUM_LEVELS: usize = 6;
     const LEVEL_BUCKETS: [(LsmLevel, &'static str); Self::MAX_NUM_LEVELS] = [
         (LsmLevel::L0, LsmLevel::L0.bucket()),
@@ -983,11 +1934,15 @@ impl From<u8> for LsmLevel {
 }
 
 impl<K: RecordKey<K>, V: RecordValue> SstManager<K, V> {
-    pub fn new() -> Self {
+    pub fn new(params: &LsmParams) -> Self {
         let level_ssts = (0..LsmLevel::MAX_NUM_LEVELS)
             .map(|_| BTreeMap::new())
             .collect();
-        Self { level_ssts }
+        Self {
+            level_ssts,
+            level0_ratio: params.level0_ratio,
+            level_ratio: params.level_ratio,
+        }
     }
 
     /// List all SSTs of a given level from newer to older.
@@ -1028,12 +1983,24 @@ impl<K: RecordKey<K>, V: RecordValue> SstManager<K, V> {
     /// Check whether a major compaction is required from `from_level` to its lower level.
     pub fn require_major_compaction(&self, from_level: LsmLevel) -> bool {
         debug_assert!(from_level != LsmLevel::L5);
+        self.level_ssts[from_level as usize].len() >= self.level_capacity(from_level)
+    }
 
-        if from_level == LsmLevel::L0 {
-            return self.level_ssts[from_level as usize].len() >= LsmLevel::LEVEL0_RATIO as _;
-        }
+    /// Check whether `from_level` has grown too large to defer its major compaction.
+    pub fn exceed_deferral_limit(&self, from_level: LsmLevel) -> bool {
+        debug_assert!(from_level != LsmLevel::L5);
         self.level_ssts[from_level as usize].len()
-            >= LsmLevel::LEVELI_RATIO.pow(from_level as _) as _
+            >= self
+                .level_capacity(from_level)
+                .saturating_mul(MAX_DEFERRED_RATIO)
+    }
+
+    /// The number of SSTs that triggers a major compaction from `level`.
+    fn level_capacity(&self, level: LsmLevel) -> usize {
+        if level == LsmLevel::L0 {
+            return self.level0_ratio as _;
+        }
+        (self.level_ratio as usize).saturating_pow(level as _)
     }
 
     pub fn require_major_compaction_force(&self, from_level: LsmLevel) -> bool {
@@ -1089,7 +2056,7 @@ mod tests {
     use crate::{
         layers::{
             bio::{Buf, MemDisk},
-            disk::SharedState,
+            disk::{CacheTier, CacheTierSnapshot, SharedState, CACHE_STATS, STATS_ENABLED},
             log::TxLogStore,
             lsm::wal::BUCKET_WAL,
         },
@@ -1130,7 +2097,30 @@ mod tests {
     }
 
     impl RecordKey<BlockId> for BlockId {}
-    impl RecordValue for Value {}
+    // The values of consecutive HBAs are stored as extents in SSTs
+    impl RecordValue for Value {
+        fn successor_size(_format: ValueFormat) -> Option<usize> {
+            Some(size_of::<Key>() + size_of::<Mac>())
+        }
+
+        fn succeeds(&self, prev: &Self, _format: ValueFormat) -> bool {
+            self.hba == prev.hba + 1
+        }
+
+        fn encode_successor(&self, _format: ValueFormat, buf: &mut Vec<u8>) {
+            buf.extend_from_slice(self.key.as_bytes());
+            buf.extend_from_slice(self.mac.as_bytes());
+        }
+
+        fn decode_successor(prev: &Self, _format: ValueFormat, buf: &[u8]) -> Self {
+            let (key, mac) = buf.split_at(size_of::<Key>());
+            Self {
+                hba: prev.hba + 1,
+                key: Key::from_bytes(key),
+                mac: Mac::from_bytes(mac),
+            }
+        }
+    }
 
     #[test]
     fn tx_lsm_tree_fns() -> Result<()> {
@@ -1143,6 +2133,8 @@ mod tests {
             None,
             None,
             Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            LsmParams::default(),
         )?;
 
         // Put sufficient records which can trigger compaction before a sync command
@@ -1201,6 +2193,8 @@ mod tests {
             None,
             None,
             Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            LsmParams::default(),
         )?;
 
         assert!(tx_lsm_tree.get(&(600 + cap)).is_err());
@@ -1211,6 +2205,1012 @@ mod tests {
         let res = range_query_ctx.into_results();
         assert_eq!(res[0].1.hba, 500);
         assert_eq!(res[cnt - 1].1.hba, 500 + cnt - 1);
+
+        let values = tx_lsm_tree.get_multi(&[3, 500, 42, 600 + cap])?;
+        assert_eq!(values[0].unwrap().hba, 3);
+        assert_eq!(values[1].unwrap().hba, 500);
+        assert_eq!(values[2].unwrap().hba, 42);
+        assert!(values[3].is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_params() -> Result<()> {
+        assert!(LsmParams {
+            sst_block_size: BLOCK_SIZE as u32 + 1,
+            ..LsmParams::default()
+        }
+        .validate()
+        .is_err());
+        assert!(LsmParams {
+            level_ratio: 1,
+            ..LsmParams::default()
+        }
+        .validate()
+        .is_err());
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        // Tiny MemTables and record blocks to go through several levels
+        let params = LsmParams {
+            memtable_capacity: 1024,
+            sst_block_size: BLOCK_SIZE as _,
+            level0_ratio: 1,
+            level_ratio: 2,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store.clone(),
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+
+        let num_records = 16 * params.memtable_capacity as usize;
+        for i in 0..num_records {
+            let value = Value {
+                hba: i as BlockId,
+                key: Key::random(),
+                mac: Mac::random(),
+            };
+            tx_lsm_tree.put(i as BlockId, value)?;
+        }
+        tx_lsm_tree.sync()?;
+        assert_eq!(tx_lsm_tree.get(&1000)?.hba, 1000);
+
+        // Existing SSTs are still readable with other parameters
+        drop(tx_lsm_tree);
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::recover(
+            tx_log_store,
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            LsmParams::default(),
+        )?;
+        let keys: Vec<BlockId> = (0..num_records).step_by(777).collect();
+        let values = tx_lsm_tree.get_multi(&keys)?;
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(value.unwrap().hba, *key);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_put_batch() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 1000,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store.clone(),
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+
+        // A batch filling the `MemTable` more than twice
+        let records: Vec<_> = (0..2500)
+            .map(|i| {
+                let value = Value {
+                    hba: i as BlockId,
+                    key: Key::random(),
+                    mac: Mac::random(),
+                };
+                (i as BlockId, value)
+            })
+            .collect();
+        tx_lsm_tree.put_batch(&records)?;
+        tx_lsm_tree.sync()?;
+        tx_lsm_tree.0.compactor.wait_compaction()?;
+
+        // The records left in the `MemTable` are recovered from the WAL
+        drop(tx_lsm_tree);
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::recover(
+            tx_log_store,
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        let keys: Vec<BlockId> = (0..2500).step_by(7).chain([2499]).collect();
+        let values = tx_lsm_tree.get_multi(&keys)?;
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(value.unwrap().hba, *key);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_bulk_load() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 1000,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store.clone(),
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        let new_value = |hba: BlockId| Value {
+            hba,
+            key: Key::random(),
+            mac: Mac::random(),
+        };
+
+        // Older records in the `MemTable`, one of which is deleted
+        tx_lsm_tree.put(10, new_value(0))?;
+        tx_lsm_tree.put(11, new_value(0))?;
+        tx_lsm_tree.delete_range(11..12)?;
+        // A bulk filling more than two SSTs
+        let records: Vec<_> = (0..2500)
+            .map(|i| (i as BlockId, new_value(i as BlockId)))
+            .collect();
+        tx_lsm_tree.bulk_load(&records)?;
+        assert_eq!(tx_lsm_tree.get(&10)?.hba, 10);
+        assert_eq!(tx_lsm_tree.get(&11)?.hba, 11);
+        assert_eq!(tx_lsm_tree.0.memtable_manager.mutable_records().len(), 2);
+
+        // The SSTs are durable without a sync
+        drop(tx_lsm_tree);
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::recover(
+            tx_log_store,
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        let keys: Vec<BlockId> = (0..2500).step_by(7).chain([2499]).collect();
+        let values = tx_lsm_tree.get_multi(&keys)?;
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(value.unwrap().hba, *key);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_extents() -> Result<()> {
+        let params = LsmParams {
+            memtable_capacity: 1000,
+            sst_block_size: BLOCK_SIZE as _,
+            ..LsmParams::default()
+        };
+        let format = || -> Result<TxLsmTree<BlockId, Value, MemDisk>> {
+            let mem_disk = MemDisk::create(64 * 1024)?;
+            TxLsmTree::format(
+                Arc::new(TxLogStore::format(mem_disk, Key::random())?),
+                Arc::new(Factory),
+                None,
+                None,
+                Arc::new(SharedState::new()),
+                Arc::new(Config::default()),
+                params,
+            )
+        };
+        let new_value = |hba: BlockId| Value {
+            hba,
+            key: Key::random(),
+            mac: Mac::random(),
+        };
+        let num_record_blocks = |tree: &TxLsmTree<BlockId, Value, MemDisk>| {
+            let sst_manager = tree.0.sst_manager.read();
+            LsmLevel::iter()
+                .flat_map(|(level, _)| sst_manager.list_level(level))
+                .map(|(_, sst)| sst.num_record_blocks())
+                .sum::<usize>()
+        };
+
+        // The records of consecutive HBAs take fewer record blocks
+        let sequential = format()?;
+        let records: Vec<_> = (0..2000).map(|i| (i, new_value(1000 + i))).collect();
+        sequential.bulk_load(&records)?;
+        let scattered = format()?;
+        let records: Vec<_> = (0..2000).map(|i| (i, new_value(2 * i))).collect();
+        scattered.bulk_load(&records)?;
+        assert!(num_record_blocks(&sequential) < num_record_blocks(&scattered));
+
+        // The extents are split on point and range queries
+        for key in [0, 1, 500, 999, 1000, 1999] {
+            assert_eq!(sequential.get(&key)?.hba, 1000 + key);
+        }
+        let mut range_query_ctx = RangeQueryCtx::new(700, 600);
+        sequential.get_range(&mut range_query_ctx)?;
+        assert!(range_query_ctx
+            .into_results()
+            .iter()
+            .all(|(key, value)| value.hba == 1000 + key));
+
+        // Overwrites and deletes in the middle of the extents are compacted
+        for i in 300..310 {
+            sequential.put(i, new_value(i))?;
+        }
+        sequential.delete_range(1200..1300)?;
+        sequential.force_compaction()?;
+        let records: Vec<_> = sequential
+            .iter()
+            .map(|record| record.map(|(key, value)| (key, value.hba)))
+            .collect::<Result<_>>()?;
+        let expected: Vec<_> = (0..1200)
+            .chain(1300..2000)
+            .map(|i| match i {
+                300..310 => (i, i),
+                _ => (i, 1000 + i),
+            })
+            .collect();
+        assert_eq!(records, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_tiered_compaction() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 256,
+            sst_block_size: BLOCK_SIZE as _,
+            level_ratio: 2,
+            compaction_policy: CompactionPolicyKind::Tiered as _,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store.clone(),
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+
+        // Overwrite the keys in rounds, so that runs of different levels overlap
+        let (num_keys, num_rounds) = (1024, 4);
+        for round in 0..num_rounds {
+            for i in 0..num_keys {
+                let value = Value {
+                    hba: (round * num_keys + i) as BlockId,
+                    key: Key::random(),
+                    mac: Mac::random(),
+                };
+                tx_lsm_tree.put(i as BlockId, value)?;
+            }
+        }
+        tx_lsm_tree.sync()?;
+
+        let check = |tx_lsm_tree: &TxLsmTree<BlockId, Value, MemDisk>| -> Result<()> {
+            let latest = (num_rounds - 1) * num_keys;
+            let keys: Vec<BlockId> = (0..num_keys).step_by(7).collect();
+            let values = tx_lsm_tree.get_multi(&keys)?;
+            for (key, value) in keys.iter().zip(values) {
+                assert_eq!(value.unwrap().hba, latest + key);
+            }
+
+            let cnt = 64;
+            let mut range_query_ctx = RangeQueryCtx::new(100, cnt);
+            tx_lsm_tree.get_range(&mut range_query_ctx)?;
+            for (key, value) in range_query_ctx.into_results() {
+                assert_eq!(value.hba, latest + key);
+            }
+            Ok(())
+        };
+        check(&tx_lsm_tree)?;
+
+        drop(tx_lsm_tree);
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::recover(
+            tx_log_store,
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        check(&tx_lsm_tree)
+    }
+
+    #[test]
+    fn tx_lsm_tree_tiered_compaction_bounded() -> Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Clone)]
+        struct DropCounter(Arc<AtomicUsize>);
+        impl<K, V> TxEventListenerFactory<K, V> for DropCounter {
+            fn new_event_listener(&self, _tx_type: TxType) -> Arc<dyn TxEventListener<K, V>> {
+                Arc::new(self.clone())
+            }
+        }
+        impl<K, V> TxEventListener<K, V> for DropCounter {
+            fn on_add_record(&self, _record: &dyn AsKV<K, V>) -> Result<()> {
+                Ok(())
+            }
+            fn on_drop_record(&self, _record: &dyn AsKV<K, V>) -> Result<()> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            fn on_tx_begin(&self, _tx: &mut Tx) -> Result<()> {
+                Ok(())
+            }
+            fn on_tx_precommit(&self, _tx: &mut Tx) -> Result<()> {
+                Ok(())
+            }
+            fn on_tx_commit(&self) {}
+        }
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 256,
+            sst_block_size: BLOCK_SIZE as _,
+            level_ratio: 2,
+            compaction_policy: CompactionPolicyKind::Tiered as _,
+            ..LsmParams::default()
+        };
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let on_drop_record_in_memtable = {
+            let dropped = dropped.clone();
+            move |_: &dyn AsKV<BlockId, Value>| {
+                dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store,
+            Arc::new(DropCounter(dropped.clone())),
+            Some(Arc::new(on_drop_record_in_memtable)),
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+
+        // Overwrite the keys until the last level is compacted several times
+        let (num_keys, num_rounds) = (1024, 32);
+        let sst_capacity = params.memtable_capacity as usize;
+        // A single run, with some SSTs partially filled at the partitions
+        let max_last_level_ssts = 2 * num_keys / sst_capacity;
+        let max_ssts = (1 + 2 + 4 + 8 + 16) + max_last_level_ssts;
+        for round in 0..num_rounds {
+            for i in 0..num_keys {
+                let value = Value {
+                    hba: (round * num_keys + i) as BlockId,
+                    key: Key::random(),
+                    mac: Mac::random(),
+                };
+                tx_lsm_tree.put(i as BlockId, value)?;
+            }
+            tx_lsm_tree.0.compactor.wait_compaction()?;
+
+            // The last level is a single run, so the SSTs and the records
+            // not dropped (i.e., the allocated blocks) are bounded
+            let sst_manager = tx_lsm_tree.0.sst_manager.read();
+            assert!(sst_manager.list_level(LsmLevel::L5).count() <= max_last_level_ssts);
+            let num_ssts: usize = LsmLevel::iter()
+                .map(|(level, _)| sst_manager.list_level(level).count())
+                .sum();
+            assert!(num_ssts <= max_ssts);
+            drop(sst_manager);
+            let num_allocated = (round + 1) * num_keys - dropped.load(Ordering::Relaxed);
+            assert!(num_allocated <= (max_ssts + 2) * sst_capacity);
+        }
+
+        let values = tx_lsm_tree.get_multi(&(0..num_keys as BlockId).collect::<Vec<_>>())?;
+        let latest = ((num_rounds - 1) * num_keys) as BlockId;
+        for (key, value) in values.into_iter().enumerate() {
+            assert_eq!(value.unwrap().hba, latest + key as BlockId);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_partitioned_compaction() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 256,
+            sst_block_size: BLOCK_SIZE as _,
+            level_ratio: 2,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store,
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+
+        // Each upper SST overlaps with many lower SSTs in later rounds
+        let (num_keys, num_rounds) = (4096, 3);
+        for round in 0..num_rounds {
+            for i in (0..num_keys).step_by(num_rounds - round) {
+                let value = Value {
+                    hba: (round * num_keys + i) as BlockId,
+                    key: Key::random(),
+                    mac: Mac::random(),
+                };
+                tx_lsm_tree.put(i as BlockId, value)?;
+            }
+        }
+        tx_lsm_tree.sync()?;
+
+        let latest = (num_rounds - 1) * num_keys;
+        let keys: Vec<BlockId> = (0..num_keys).step_by(13).collect();
+        let values = tx_lsm_tree.get_multi(&keys)?;
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(value.unwrap().hba, latest + key);
+        }
+
+        // SSTs below L0 are still disjoint
+        tx_lsm_tree.0.compactor.wait_compaction()?;
+        let sst_manager = tx_lsm_tree.0.sst_manager.read();
+        for (level, _) in LsmLevel::iter().skip(1) {
+            let mut ranges: Vec<_> = sst_manager
+                .list_level(level)
+                .map(|(_, sst)| sst.range())
+                .collect();
+            ranges.sort_by_key(|range| *range.start());
+            assert!(ranges
+                .windows(2)
+                .all(|pair| pair[0].end() < pair[1].start()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_bloom_filter() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 1024,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store.clone(),
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+
+        // Only even keys exist
+        let num_records = 4 * params.memtable_capacity as usize;
+        for i in (0..2 * num_records).step_by(2) {
+            let value = Value {
+                hba: i as BlockId,
+                key: Key::random(),
+                mac: Mac::random(),
+            };
+            tx_lsm_tree.put(i as BlockId, value)?;
+        }
+        tx_lsm_tree.sync()?;
+
+        // Filters are loaded lazily after recovery
+        drop(tx_lsm_tree);
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::recover(
+            tx_log_store,
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+
+        let negatives = COST_L2.get_filter_stats().negatives;
+        let absent_keys: Vec<BlockId> = (1..num_records).step_by(2).collect();
+        for key in &absent_keys {
+            assert!(tx_lsm_tree.get(key).is_err());
+        }
+        // Most probes of absent keys are skipped by filters
+        let skipped = COST_L2.get_filter_stats().negatives - negatives;
+        if STATS_ENABLED {
+            assert!(skipped as usize >= absent_keys.len() * 9 / 10);
+        }
+
+        let values = tx_lsm_tree.get_multi(&[0, 1, 1000, 1001])?;
+        assert_eq!(values[0].unwrap().hba, 0);
+        assert!(values[1].is_none());
+        assert_eq!(values[2].unwrap().hba, 1000);
+        assert!(values[3].is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_iter() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 256,
+            sst_block_size: BLOCK_SIZE as _,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store,
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        let put = |keys: Range<BlockId>, round: usize| {
+            for i in keys {
+                let value = Value {
+                    hba: round * 10000 + i,
+                    key: Key::random(),
+                    mac: Mac::random(),
+                };
+                tx_lsm_tree.put(i, value)?;
+            }
+            Ok::<_, Error>(())
+        };
+
+        // Records of different versions spread over `SSTable`s and `MemTable`s
+        put(0..1000, 0)?;
+        tx_lsm_tree.sync()?;
+        tx_lsm_tree.0.compactor.wait_compaction()?;
+        put(500..1200, 1)?;
+        tx_lsm_tree.delete_range(100..200)?;
+        put(150..160, 2)?;
+
+        let iter = tx_lsm_tree.iter();
+        // Invisible to the iterator
+        tx_lsm_tree.delete_range(0..10)?;
+        let records: Vec<_> = iter.collect::<Result<_>>()?;
+        let expected: Vec<_> = (0..100)
+            .chain(150..160)
+            .chain(200..1200)
+            .map(|i| {
+                let round = match i {
+                    150..160 => 2,
+                    500.. => 1,
+                    _ => 0,
+                };
+                (i, round * 10000 + i)
+            })
+            .collect();
+        assert!(records
+            .iter()
+            .map(|(key, value)| (*key, value.hba))
+            .eq(expected));
+
+        let mut iter = tx_lsm_tree.iter();
+        assert_eq!(iter.next().unwrap()?.0, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_iter_during_compaction() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 256,
+            sst_block_size: BLOCK_SIZE as _,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store.clone(),
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        let put = |keys: Range<BlockId>, round: usize| {
+            for i in keys {
+                let value = Value {
+                    hba: round * 10000 + i,
+                    key: Key::random(),
+                    mac: Mac::random(),
+                };
+                tx_lsm_tree.put(i, value)?;
+            }
+            Ok::<_, Error>(())
+        };
+        let num_logs_in = |bucket: &str| {
+            let mut tx = tx_log_store.new_tx();
+            let res = tx.context(|| tx_log_store.list_logs_in(bucket).map_or(0, |ids| ids.len()));
+            tx.commit().map(|_| res)
+        };
+
+        put(0..1000, 0)?;
+        tx_lsm_tree.sync()?;
+        tx_lsm_tree.0.compactor.wait_compaction()?;
+        let iter = tx_lsm_tree.iter();
+
+        // Neither the writes nor the compactions wait for the iterator
+        put(0..1000, 1)?;
+        tx_lsm_tree.sync()?;
+        tx_lsm_tree.force_compaction()?;
+        assert!(num_logs_in(BUCKET_RETIRED)? > 0);
+
+        // The retired SSTs are still readable by the iterator
+        let records: Vec<_> = iter.collect::<Result<_>>()?;
+        assert!(records
+            .iter()
+            .map(|(key, value)| (*key, value.hba))
+            .eq((0..1000).map(|i| (i, i))));
+        assert_eq!(num_logs_in(BUCKET_RETIRED)?, 0);
+
+        let records: Vec<_> = tx_lsm_tree.iter().collect::<Result<_>>()?;
+        assert!(records
+            .iter()
+            .map(|(key, value)| (*key, value.hba))
+            .eq((0..1000).map(|i| (i, 10000 + i))));
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_cache_tiers() -> Result<()> {
+        let keys: Vec<BlockId> = (0..2048).step_by(3).collect();
+        // Returns the statistics of both tiers on looking up the keys twice
+        let lookup = |two_level_caching: bool| -> Result<(CacheTierSnapshot, CacheTierSnapshot)> {
+            let nblocks = 64 * 1024;
+            let mem_disk = MemDisk::create(nblocks)?;
+            let tx_log_store = Arc::new(TxLogStore::format_with_two_level_caching(
+                mem_disk,
+                Key::random(),
+                two_level_caching,
+            )?);
+            let params = LsmParams {
+                memtable_capacity: 1024,
+                sst_block_size: BLOCK_SIZE as _,
+                ..LsmParams::default()
+            };
+            let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+                tx_log_store,
+                Arc::new(Factory),
+                None,
+                None,
+                Arc::new(SharedState::new()),
+                Arc::new(Config::default()),
+                params,
+            )?;
+
+            // Two SSTs are flushed, the rest stay in the `MemTable`
+            for i in 0..3000 {
+                let value = Value {
+                    hba: i as BlockId,
+                    key: Key::random(),
+                    mac: Mac::random(),
+                };
+                tx_lsm_tree.put(i as BlockId, value)?;
+            }
+            tx_lsm_tree.sync()?;
+            tx_lsm_tree.0.compactor.wait_compaction()?;
+
+            let before =
+                [CacheTier::SstBlock, CacheTier::LogBlock].map(|tier| CACHE_STATS.get_stats(tier));
+            for _ in 0..2 {
+                for key in &keys {
+                    assert_eq!(tx_lsm_tree.get(key)?.hba, *key);
+                }
+            }
+            Ok((
+                CACHE_STATS.get_stats(CacheTier::SstBlock).since(&before[0]),
+                CACHE_STATS.get_stats(CacheTier::LogBlock).since(&before[1]),
+            ))
+        };
+        let hit_rate = |stats: &CacheTierSnapshot| {
+            stats.hits as f64 / (stats.hits + stats.misses).max(1) as f64
+        };
+
+        // With two tiers, the record blocks are cached in the upper tier on
+        // building the SSTs, the lookups are served without going down to the
+        // log blocks. With one tier, the log blocks are decrypted from the lower
+        // tier, which caches the data blocks along with the MHT nodes
+        let (two_tier_sst, _) = lookup(true)?;
+        let (_, one_tier_log) = lookup(false)?;
+        if STATS_ENABLED {
+            assert!(two_tier_sst.hits >= 2 * keys.len() as u64);
+            assert!(one_tier_log.hits >= 2 * keys.len() as u64);
+            assert!(hit_rate(&two_tier_sst) >= hit_rate(&one_tier_log));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_delete_range() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 256,
+            sst_block_size: BLOCK_SIZE as _,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store.clone(),
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        let put = |tx_lsm_tree: &TxLsmTree<BlockId, Value, MemDisk>, keys: Range<BlockId>| {
+            for i in keys {
+                let value = Value {
+                    hba: i as BlockId,
+                    key: Key::random(),
+                    mac: Mac::random(),
+                };
+                tx_lsm_tree.put(i, value)?;
+            }
+            Ok::<_, Error>(())
+        };
+
+        // Records of the deleted range reside in both `SSTable`s and `MemTable`
+        put(&tx_lsm_tree, 0..1000)?;
+        tx_lsm_tree.sync()?;
+        tx_lsm_tree.delete_range(100..600)?;
+        put(&tx_lsm_tree, 200..201)?;
+        assert!(tx_lsm_tree.get(&150).is_err());
+        assert_eq!(tx_lsm_tree.get(&200)?.hba, 200);
+        assert_eq!(tx_lsm_tree.get(&600)?.hba, 600);
+
+        let mut range_query_ctx = RangeQueryCtx::new(96, 8);
+        let _ = tx_lsm_tree.get_range(&mut range_query_ctx);
+        assert_eq!(range_query_ctx.deleted_keys(), &[100, 101, 102, 103]);
+
+        // Push the tombstones down the levels
+        tx_lsm_tree.sync()?;
+        put(&tx_lsm_tree, 1000..2000)?;
+        tx_lsm_tree.sync()?;
+        tx_lsm_tree.0.compactor.wait_compaction()?;
+        tx_lsm_tree.force_compaction()?;
+
+        // Unsynced tombstones are discarded on recovery
+        tx_lsm_tree.delete_range(600..700)?;
+        drop(tx_lsm_tree);
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::recover(
+            tx_log_store,
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        let values = tx_lsm_tree.get_multi(&[99, 100, 200, 599, 650, 1500])?;
+        assert_eq!(values[0].unwrap().hba, 99);
+        assert!(values[1].is_none());
+        assert_eq!(values[2].unwrap().hba, 200);
+        assert!(values[3].is_none());
+        assert_eq!(values[4].unwrap().hba, 650);
+        assert_eq!(values[5].unwrap().hba, 1500);
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_checkpoint() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 1000,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store.clone(),
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        let put = |tx_lsm_tree: &TxLsmTree<BlockId, Value, MemDisk>, keys: Range<BlockId>| {
+            for i in keys {
+                let value = Value {
+                    hba: i as BlockId,
+                    key: Key::random(),
+                    mac: Mac::random(),
+                };
+                tx_lsm_tree.put(i, value)?;
+            }
+            Ok::<_, Error>(())
+        };
+        let num_logs_in = |bucket: &str| {
+            let mut tx = tx_log_store.new_tx();
+            let res = tx.context(|| tx_log_store.list_logs_in(bucket).map_or(0, |ids| ids.len()));
+            tx.commit().map(|_| res)
+        };
+
+        // The checkpoint replaces the WAL
+        put(&tx_lsm_tree, 0..500)?;
+        tx_lsm_tree.delete_range(100..200)?;
+        tx_lsm_tree.checkpoint()?;
+        assert_eq!(num_logs_in(BUCKET_WAL)?, 0);
+        assert_eq!(num_logs_in(BUCKET_CHECKPOINT)?, 1);
+
+        // Only the WAL after the checkpoint is replayed
+        put(&tx_lsm_tree, 150..160)?;
+        put(&tx_lsm_tree, 500..600)?;
+        tx_lsm_tree.sync()?;
+        put(&tx_lsm_tree, 600..700)?;
+        drop(tx_lsm_tree);
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::recover(
+            tx_log_store.clone(),
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        assert_eq!(tx_lsm_tree.get(&99)?.hba, 99);
+        assert!(tx_lsm_tree.get(&100).is_err());
+        assert_eq!(tx_lsm_tree.get(&150)?.hba, 150);
+        assert!(tx_lsm_tree.get(&160).is_err());
+        assert_eq!(tx_lsm_tree.get(&599)?.hba, 599);
+        assert!(tx_lsm_tree.get(&600).is_err());
+
+        // A new checkpoint replaces the last one
+        tx_lsm_tree.checkpoint()?;
+        assert_eq!(num_logs_in(BUCKET_CHECKPOINT)?, 1);
+
+        // The checkpoints are deleted once the `MemTable` is flushed
+        put(&tx_lsm_tree, 1000..2000)?;
+        tx_lsm_tree.sync()?;
+        tx_lsm_tree.0.compactor.wait_compaction()?;
+        assert_eq!(num_logs_in(BUCKET_CHECKPOINT)?, 0);
+        assert_eq!(tx_lsm_tree.get(&599)?.hba, 599);
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_concurrent_checkpoint() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 1000,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store.clone(),
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+
+        // The puts racing with the checkpoints never find the WAL detached
+        // under them, nor are they lost with the replaced WAL
+        let num_put = 3000;
+        let putter = {
+            let tx_lsm_tree = tx_lsm_tree.clone();
+            std::thread::spawn(move || -> Result<()> {
+                for i in 0..num_put {
+                    let value = Value {
+                        hba: i as BlockId,
+                        key: Key::random(),
+                        mac: Mac::random(),
+                    };
+                    tx_lsm_tree.put(i as BlockId, value)?;
+                }
+                Ok(())
+            })
+        };
+        for _ in 0..20 {
+            tx_lsm_tree.checkpoint()?;
+        }
+        putter.join().unwrap()?;
+        tx_lsm_tree.sync()?;
+        drop(tx_lsm_tree);
+
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::recover(
+            tx_log_store,
+            Arc::new(Factory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+        for i in 0..num_put {
+            assert_eq!(tx_lsm_tree.get(&(i as BlockId))?.hba, i as BlockId);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tx_lsm_tree_listener_error() -> Result<()> {
+        struct FailingFactory;
+        struct FailingListener;
+
+        impl<K, V> TxEventListenerFactory<K, V> for FailingFactory {
+            fn new_event_listener(&self, _tx_type: TxType) -> Arc<dyn TxEventListener<K, V>> {
+                Arc::new(FailingListener)
+            }
+        }
+        impl<K, V> TxEventListener<K, V> for FailingListener {
+            fn on_add_record(&self, _record: &dyn AsKV<K, V>) -> Result<()> {
+                Ok(())
+            }
+            fn on_drop_record(&self, _record: &dyn AsKV<K, V>) -> Result<()> {
+                Ok(())
+            }
+            fn on_tx_begin(&self, _tx: &mut Tx) -> Result<()> {
+                Ok(())
+            }
+            fn on_tx_precommit(&self, _tx: &mut Tx) -> Result<()> {
+                Err(Error::with_msg(IoFailed, "precommit failed"))
+            }
+            fn on_tx_commit(&self) {}
+        }
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let tx_log_store = Arc::new(TxLogStore::format(mem_disk, Key::random())?);
+        let params = LsmParams {
+            memtable_capacity: 100,
+            ..LsmParams::default()
+        };
+        let tx_lsm_tree: TxLsmTree<BlockId, Value, MemDisk> = TxLsmTree::format(
+            tx_log_store,
+            Arc::new(FailingFactory),
+            None,
+            None,
+            Arc::new(SharedState::new()),
+            Arc::new(Config::default()),
+            params,
+        )?;
+
+        // The failed minor compaction is aborted and reported by the sync
+        for i in 0..100 {
+            let value = Value {
+                hba: i as BlockId,
+                key: Key::random(),
+                mac: Mac::random(),
+            };
+            tx_lsm_tree.put(i as BlockId, value)?;
+        }
+        assert_eq!(tx_lsm_tree.sync().unwrap_err().errno(), TxAborted);
+        assert_eq!(
+            tx_lsm_tree
+                .0
+                .sst_manager
+                .read()
+                .list_level(LsmLevel::L0)
+                .count(),
+            0
+        );
+        assert_eq!(tx_lsm_tree.get(&50)?.hba, 50);
         Ok(())
     }
 }
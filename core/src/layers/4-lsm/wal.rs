@@ -1,14 +1,17 @@
 //! Transactions in WriteAhead Log.
-use super::{AsKV, SyncId};
+use super::{RecordValue, SyncId, ValueFormat};
 use crate::layers::bio::{BlockId, BlockSet, Buf, BufRef};
+use crate::layers::disk::Config;
 use crate::layers::log::{TxLog, TxLogId, TxLogStore};
-use crate::os::Mutex;
+use crate::os::{Clock, Mutex, RealClock};
 use crate::prelude::*;
 use crate::tx::Tx;
 
 use core::cell::{RefCell, RefMut};
 use core::fmt::Debug;
 use core::mem::size_of;
+use core::ops::Range;
+use core::time::Duration;
 use pod::Pod;
 
 /// The bucket name of WAL.
@@ -19,6 +22,15 @@ pub(super) const BUCKET_WAL: &str = "WAL";
 /// A `WalAppendTx` is used to append records, sync and discard WALs.
 /// A WAL is storing, managing key-value records which are going to
 /// put in `MemTable`. It's space is backed by a `TxLog` (L3).
+///
+/// Besides records, a WAL also logs range deletes in order with them.
+/// Neither is logged if the WAL TX is ephemeral (see `set_ephemeral()`),
+/// while the WAL is still created to be discarded after the flush.
+///
+/// Appended records are buffered and committed to the log as a group,
+/// i.e., encrypted and appended as one multi-block frame, once the group
+/// reaches `Config::wal_group_blocks` or its oldest record is older than
+/// `Config::wal_group_timeout`. Commits and syncs flush the group anyway.
 #[derive(Clone)]
 pub(super) struct WalAppendTx<D> {
     inner: Arc<Mutex<WalTxInner<D>>>,
@@ -31,52 +43,128 @@ struct WalTxInner<D> {
     log_id: Option<TxLogId>,
     /// Store current sync ID as the first record of WAL.
     sync_id: SyncId,
+    /// The format of the appended values.
+    value_format: ValueFormat,
     /// A buffer to cache appended records.
     record_buf: Vec<u8>,
+    /// The time the oldest record in `record_buf` was appended.
+    group_start: Option<Duration>,
+    /// The capacity (in bytes) of a group of records.
+    group_cap: usize,
+    /// The timeout of a group, i.e., `Config::wal_group_timeout`.
+    group_timeout: Option<Duration>,
     /// Store for WALs.
     tx_log_store: Arc<TxLogStore<D>>,
+    /// Whether records and range deletes are not logged.
+    ephemeral: bool,
 }
 
-impl<D: BlockSet + 'static> WalAppendTx<D> {
-    const BUF_CAP: usize = 1024 * BLOCK_SIZE;
+/// An entry collected from a WAL or a checkpoint.
+#[derive(Debug)]
+pub(super) enum WalEntry<K, V> {
+    Record(K, V),
+    RangeDelete(Range<K>),
+}
 
-    /// Prepare a new WAL TX.
-    pub fn new(store: &Arc<TxLogStore<D>>, sync_id: SyncId) -> Self {
+impl<D: BlockSet + 'static> WalAppendTx<D> {
+    const MAX_RECORD_SIZE: usize = 49;
+
+    /// Prepare a new WAL TX, whose values are appended in `value_format`
+    /// and grouped as configured in `config`.
+    pub fn new(
+        store: &Arc<TxLogStore<D>>,
+        sync_id: SyncId,
+        value_format: ValueFormat,
+        config: &Config,
+    ) -> Self {
+        let group_cap = config.wal_group_blocks.max(1) * BLOCK_SIZE;
         Self {
             inner: Arc::new(Mutex::new(WalTxInner {
                 wal_tx_and_log: None,
                 log_id: None,
                 sync_id,
-                record_buf: Vec::with_capacity(Self::BUF_CAP),
+                value_format,
+                record_buf: Vec::with_capacity(group_cap),
+                group_start: None,
+                group_cap,
+                group_timeout: config.wal_group_timeout,
                 tx_log_store: store.clone(),
+                ephemeral: false,
             })),
         }
     }
 
+    /// Sets whether the appended records and range deletes are not logged,
+    /// e.g., for the trees of an ephemeral disk (see `Config::ephemeral`).
+    pub fn set_ephemeral(&self, ephemeral: bool) {
+        self.inner.lock().ephemeral = ephemeral;
+    }
+
     /// Append phase for an Append TX, mainly to append newly records to the WAL.
-    pub fn append<K: Pod, V: Pod>(&self, record: &dyn AsKV<K, V>) -> Result<()> {
+    /// The records are buffered in the current group at once.
+    pub fn append_batch<K: Pod, V: RecordValue>(&self, records: &[(K, V)]) -> Result<()> {
         let mut inner = self.inner.lock();
         if inner.wal_tx_and_log.is_none() {
             inner.prepare()?;
         }
+        if inner.ephemeral {
+            return Ok(());
+        }
 
-        {
-            let record_buf = &mut inner.record_buf;
-            record_buf.push(WalAppendFlag::Record as u8);
-            record_buf.extend_from_slice(record.key().as_bytes());
-            record_buf.extend_from_slice(record.value().as_bytes());
+        let group_cap = inner.group_cap;
+        for (key, value) in records {
+            let value_format = inner.value_format;
+            inner.push_entry(WalAppendFlag::Record, &[key.as_bytes()]);
+            value.encode(value_format, &mut inner.record_buf);
+
+            if inner.record_buf.len() > group_cap - Self::MAX_RECORD_SIZE {
+                self.flush_group(&mut inner)?;
+            }
         }
 
-        const MAX_RECORD_SIZE: usize = 49;
-        if inner.record_buf.len() <= Self::BUF_CAP - MAX_RECORD_SIZE {
+        self.flush_expired_group(&mut inner)
+    }
+
+    /// Append phase for an Append TX, to append a range delete to the WAL.
+    pub fn append_range_delete<K: Pod>(&self, range: &Range<K>) -> Result<()> {
+        let mut inner = self.inner.lock();
+        if inner.wal_tx_and_log.is_none() {
+            inner.prepare()?;
+        }
+        if inner.ephemeral {
             return Ok(());
         }
 
+        inner.push_entry(
+            WalAppendFlag::RangeDelete,
+            &[range.start.as_bytes(), range.end.as_bytes()],
+        );
+        if inner.record_buf.len() > inner.group_cap - Self::MAX_RECORD_SIZE {
+            self.flush_group(&mut inner)?;
+        }
+
+        self.flush_expired_group(&mut inner)
+    }
+
+    /// Flushes the buffered group if its oldest record is older
+    /// than `Config::wal_group_timeout`.
+    fn flush_expired_group(&self, inner: &mut WalTxInner<D>) -> Result<()> {
+        if let Some(timeout) = inner.group_timeout
+            && let Some(start) = inner.group_start
+            && RealClock.now().saturating_sub(start) >= timeout
+        {
+            self.flush_group(inner)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the buffered group of records to the backed log.
+    fn flush_group(&self, inner: &mut WalTxInner<D>) -> Result<()> {
         inner.align_record_buf();
         let (wal_tx, wal_log) = inner.wal_tx_and_log.as_ref().unwrap();
         self.flush_buf(&inner.record_buf, wal_tx.borrow_mut(), wal_log)?;
         inner.record_buf.clear();
-
+        inner.group_start = None;
         Ok(())
     }
 
@@ -101,6 +189,7 @@ impl<D: BlockSet + 'static> WalAppendTx<D> {
             self.flush_buf(&inner.record_buf, wal_tx.borrow_mut(), &wal_log)?;
             inner.record_buf.clear();
         }
+        inner.group_start = None;
 
         drop(wal_log);
         let mut wal_tx = wal_tx.borrow_mut();
@@ -109,8 +198,8 @@ impl<D: BlockSet + 'static> WalAppendTx<D> {
     }
 
     /// Appends current sync ID to WAL then commit the TX to ensure WAL's persistency.
-    /// Save the log ID for later appending.
-    pub fn sync(&self, sync_id: SyncId) -> Result<()> {
+    /// Save the log ID for later appending. Return the number of blocks of the WAL.
+    pub fn sync(&self, sync_id: SyncId) -> Result<usize> {
         let mut inner = self.inner.lock();
         if inner.wal_tx_and_log.is_none() {
             inner.prepare()?;
@@ -123,10 +212,30 @@ impl<D: BlockSet + 'static> WalAppendTx<D> {
         let (wal_tx, wal_log) = inner.wal_tx_and_log.take().unwrap();
         self.flush_buf(&inner.record_buf, wal_tx.borrow_mut(), &wal_log)?;
         inner.record_buf.clear();
+        inner.group_start = None;
 
+        let nblocks = wal_log.nblocks();
         drop(wal_log);
         let mut wal_tx = wal_tx.borrow_mut();
-        wal_tx.commit()
+        wal_tx.commit()?;
+        Ok(nblocks)
+    }
+
+    /// Return the log ID of the current WAL, `None` if it's not created yet.
+    pub fn log_id(&self) -> Option<TxLogId> {
+        self.inner.lock().log_id
+    }
+
+    /// Detaches the current WAL, so the later records are appended to a new
+    /// WAL. Used once the WAL is replaced by a checkpoint.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if current WAL's TX exists, i.e., the WAL is not synced.
+    pub fn detach(&self) {
+        let mut inner = self.inner.lock();
+        assert!(inner.wal_tx_and_log.is_none());
+        inner.log_id = None;
     }
 
     /// Flushes the buffer to the backed log.
@@ -138,6 +247,7 @@ impl<D: BlockSet + 'static> WalAppendTx<D> {
     ) -> Result<()> {
         debug_assert!(!record_buf.is_empty() && record_buf.len() % BLOCK_SIZE == 0);
         let res = wal_tx.context(|| {
+            crate::chaos_point!(WalAppend);
             let buf = BufRef::try_from(record_buf).unwrap();
             log.append(buf)
         });
@@ -147,12 +257,14 @@ impl<D: BlockSet + 'static> WalAppendTx<D> {
         res
     }
 
-    /// Collects the synced records only and the maximum sync ID in the WAL.
-    pub fn collect_synced_records_and_sync_id<K: Pod, V: Pod>(
+    /// Collects the synced entries only (in order) and the maximum sync ID in the WAL,
+    /// whose values are in `value_format`.
+    pub fn collect_synced_entries_and_sync_id<K: Pod, V: RecordValue>(
         wal: &TxLog<D>,
-    ) -> Result<(Vec<(K, V)>, SyncId)> {
+        value_format: ValueFormat,
+    ) -> Result<(Vec<WalEntry<K, V>>, SyncId)> {
         let nblocks = wal.nblocks();
-        let mut records = Vec::new();
+        let mut entries = Vec::new();
 
         // TODO: Allocate separate buffers for large WAL
         let mut buf = Buf::alloc(nblocks)?;
@@ -160,7 +272,7 @@ impl<D: BlockSet + 'static> WalAppendTx<D> {
         let buf_slice = buf.as_slice();
 
         let k_size = size_of::<K>();
-        let v_size = size_of::<V>();
+        let v_size = V::encoded_size(value_format);
         let total_bytes = nblocks * BLOCK_SIZE;
         let mut offset = 0;
         let (mut max_sync_id, mut synced_len) = (None, 0);
@@ -180,13 +292,22 @@ impl<D: BlockSet + 'static> WalAppendTx<D> {
                 WalAppendFlag::Record => {
                     let record = {
                         let k = K::from_bytes(&buf_slice[offset..offset + k_size]);
-                        let v =
-                            V::from_bytes(&buf_slice[offset + k_size..offset + k_size + v_size]);
+                        let v = V::decode(
+                            value_format,
+                            &buf_slice[offset + k_size..offset + k_size + v_size],
+                        );
                         offset += k_size + v_size;
-                        (k, v)
+                        WalEntry::Record(k, v)
                     };
 
-                    records.push(record);
+                    entries.push(record);
+                }
+                WalAppendFlag::RangeDelete => {
+                    let start = K::from_bytes(&buf_slice[offset..offset + k_size]);
+                    let end = K::from_bytes(&buf_slice[offset + k_size..offset + 2 * k_size]);
+                    offset += 2 * k_size;
+
+                    entries.push(WalEntry::RangeDelete(start..end));
                 }
                 WalAppendFlag::Sync => {
                     let sync_id = SyncId::from_le_bytes(
@@ -197,14 +318,14 @@ impl<D: BlockSet + 'static> WalAppendTx<D> {
                     offset += size_of::<SyncId>();
 
                     let _ = max_sync_id.insert(sync_id);
-                    synced_len = records.len();
+                    synced_len = entries.len();
                 }
             }
         }
 
         if let Some(max_sync_id) = max_sync_id {
-            records.truncate(synced_len);
-            Ok((records, max_sync_id))
+            entries.truncate(synced_len);
+            Ok((entries, max_sync_id))
         } else {
             Ok((vec![], 0))
         }
@@ -243,18 +364,30 @@ impl<D: BlockSet + 'static> WalTxInner<D> {
         Ok(())
     }
 
+    /// Buffers an entry of the given flag and content in the current group.
+    fn push_entry(&mut self, flag: WalAppendFlag, content: &[&[u8]]) {
+        if self.group_timeout.is_some() {
+            let _ = self.group_start.get_or_insert_with(|| RealClock.now());
+        }
+        self.record_buf.push(flag as u8);
+        for bytes in content {
+            self.record_buf.extend_from_slice(bytes);
+        }
+    }
+
     fn align_record_buf(&mut self) {
         let aligned_len = align_up(self.record_buf.len(), BLOCK_SIZE);
         self.record_buf.resize(aligned_len, 0);
     }
 }
 
-/// Two content kinds in a WAL.
+/// Content kinds in a WAL.
 #[derive(PartialEq, Eq, Debug)]
 #[repr(u8)]
 enum WalAppendFlag {
     Record = 13,
     Sync = 23,
+    RangeDelete = 31,
 }
 
 impl TryFrom<u8> for WalAppendFlag {
@@ -264,6 +397,7 @@ impl TryFrom<u8> for WalAppendFlag {
         match value {
             13 => Ok(WalAppendFlag::Record),
             23 => Ok(WalAppendFlag::Sync),
+            31 => Ok(WalAppendFlag::RangeDelete),
             _ => Err(Error::new(InvalidArgs)),
         }
     }
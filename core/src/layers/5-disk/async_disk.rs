@@ -0,0 +1,287 @@
+//! Async facade of `SwornDisk`.
+//!
+//! `AsyncSwornDisk` submits block I/O requests to the request queue of
+//! `SwornDisk`, which is served by a background worker thread. A request
+//! wakes up its waiting task through the `on_complete` callback, so that
+//! async runtimes (e.g., tokio or smol) never block their worker threads
+//! on disk I/O.
+use super::bio::{BioReq, BioReqBuilder, BioResp, BioType, BlockBuf};
+use super::sworndisk::{Lba, SwornDisk};
+use crate::layers::bio::{AsyncBlockSet, BlockSet, Buf};
+use crate::os::{spawn, Condvar, CvarMutex, JoinHandle, Mutex};
+use crate::prelude::*;
+
+use core::future::Future;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::{Context, Poll, Waker};
+
+/// An async facade of `SwornDisk`.
+pub struct AsyncSwornDisk<D: BlockSet + 'static> {
+    disk: Arc<SwornDisk<D>>,
+    worker_state: Arc<BioWorkerState>,
+    worker_thread: Option<JoinHandle<()>>,
+}
+
+impl<D: BlockSet + 'static> AsyncSwornDisk<D> {
+    /// Wraps a `SwornDisk` and launches the background worker thread
+    /// that serves the queued requests.
+    pub fn new(disk: SwornDisk<D>) -> Self {
+        let disk = Arc::new(disk);
+        let worker_state = Arc::new(BioWorkerState::new());
+        let worker_thread = {
+            let disk = disk.clone();
+            let worker_state = worker_state.clone();
+            spawn(move || worker_state.run(&disk))
+        };
+        Self {
+            disk,
+            worker_state,
+            worker_thread: Some(worker_thread),
+        }
+    }
+
+    /// Read a specified number of blocks at a logical block address on the device.
+    /// The buffer is handed back when the read completes.
+    pub async fn read(&self, lba: Lba, buf: Buf) -> Result<Buf> {
+        self.check_rw_args(lba, buf.nblocks())?;
+        let buf = self.submit(BioType::Read, lba, Some(buf))?.await?;
+        Ok(buf.unwrap())
+    }
+
+    /// Write a specified number of blocks at a logical block address on the device.
+    /// The buffer is handed back when the write completes.
+    pub async fn write(&self, lba: Lba, buf: Buf) -> Result<Buf> {
+        self.check_rw_args(lba, buf.nblocks())?;
+        let buf = self.submit(BioType::Write, lba, Some(buf))?.await?;
+        Ok(buf.unwrap())
+    }
+
+    /// Sync all cached data in the device to the storage medium for durability.
+    pub async fn sync(&self) -> Result<()> {
+        let _ = self.submit(BioType::Sync, 0, None)?.await?;
+        Ok(())
+    }
+
+    /// Returns the total number of blocks in the device.
+    pub fn total_blocks(&self) -> usize {
+        self.disk.total_blocks()
+    }
+
+    /// Returns the wrapped `SwornDisk`.
+    pub fn disk(&self) -> &SwornDisk<D> {
+        &self.disk
+    }
+
+    fn check_rw_args(&self, lba: Lba, buf_nblocks: usize) -> Result<()> {
+        if lba + buf_nblocks > self.disk.total_blocks() {
+            return_errno_with_msg!(OutOfDisk, "read/write out of disk capacity");
+        }
+        Ok(())
+    }
+
+    /// Submit a block I/O request to the request queue of `SwornDisk`,
+    /// returns a future resolved upon its completion.
+    fn submit(&self, type_: BioType, lba: Lba, mut buf: Option<Buf>) -> Result<BioFuture> {
+        let mut builder = BioReqBuilder::new(type_);
+        if let Some(buf) = buf.as_mut() {
+            let slice = buf.as_mut_slice();
+            // Safety: the buffer is owned by the completion, which outlives the request.
+            let block_buf = unsafe {
+                BlockBuf::from_raw_parts(NonNull::new(slice.as_mut_ptr()).unwrap(), slice.len())
+            };
+            builder = builder.addr(lba).bufs(vec![block_buf]);
+        }
+
+        let completion = Arc::new(Mutex::new(BioCompletion {
+            resp: None,
+            buf,
+            waker: None,
+        }));
+        let bio_req = builder
+            .on_complete(wake_on_complete)
+            .ext(BioCompletionRef(completion.clone()))
+            .build();
+        self.disk.submit_bio(bio_req)?;
+        self.worker_state.notify_pending();
+
+        Ok(BioFuture { completion })
+    }
+}
+
+impl<D: BlockSet + 'static> AsyncBlockSet for AsyncSwornDisk<D> {
+    async fn read(&self, pos: BlockId, buf: Buf) -> Result<Buf> {
+        AsyncSwornDisk::read(self, pos as Lba, buf).await
+    }
+
+    async fn write(&self, pos: BlockId, buf: Buf) -> Result<Buf> {
+        AsyncSwornDisk::write(self, pos as Lba, buf).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.sync().await
+    }
+
+    fn nblocks(&self) -> usize {
+        self.total_blocks()
+    }
+}
+
+impl<D: BlockSet + 'static> Drop for AsyncSwornDisk<D> {
+    fn drop(&mut self) {
+        // The worker drains the request queue before it exits
+        self.worker_state.notify_shutdown();
+        if let Some(worker_thread) = self.worker_thread.take() {
+            let _ = worker_thread.join();
+        }
+    }
+}
+
+/// The completion state shared by a request and its future.
+struct BioCompletion {
+    resp: Option<BioResp>,
+    buf: Option<Buf>,
+    waker: Option<Waker>,
+}
+
+/// The extension object attached to a request to find its completion.
+struct BioCompletionRef(Arc<Mutex<BioCompletion>>);
+
+fn wake_on_complete(req: &BioReq, resp: &BioResp) {
+    let ext = req.ext();
+    let Some(completion) = ext.get::<BioCompletionRef>() else {
+        return;
+    };
+
+    let mut completion = completion.0.lock();
+    completion.resp = Some(resp.clone());
+    if let Some(waker) = completion.waker.take() {
+        waker.wake();
+    }
+}
+
+/// A future resolved upon the completion of a block I/O request,
+/// outputs the buffer of the request (if any).
+struct BioFuture {
+    completion: Arc<Mutex<BioCompletion>>,
+}
+
+impl Future for BioFuture {
+    type Output = Result<Option<Buf>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut completion = self.completion.lock();
+        match completion.resp.take() {
+            Some(resp) => Poll::Ready(resp.map(|_| completion.buf.take())),
+            None => {
+                completion.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The state of the background worker serving queued requests.
+struct BioWorkerState {
+    // (has pending requests, should shut down)
+    flags: CvarMutex<(bool, bool)>,
+    condvar: Condvar,
+}
+
+impl BioWorkerState {
+    fn new() -> Self {
+        Self {
+            flags: CvarMutex::new((false, false)),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn run<D: BlockSet + 'static>(&self, disk: &SwornDisk<D>) {
+        loop {
+            let should_shutdown = {
+                let mut flags = self.flags.lock().unwrap();
+                while !flags.0 && !flags.1 {
+                    flags = self.condvar.wait(flags).unwrap();
+                }
+                flags.0 = false;
+                flags.1
+            };
+
+            disk.handle_queued_bios();
+            if should_shutdown {
+                return;
+            }
+        }
+    }
+
+    fn notify_pending(&self) {
+        self.flags.lock().unwrap().0 = true;
+        self.condvar.notify_one();
+    }
+
+    fn notify_shutdown(&self) {
+        self.flags.lock().unwrap().1 = true;
+        self.condvar.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::bio::MemDisk;
+    use crate::os::AeadKey as Key;
+
+    use std::sync::Arc as StdArc;
+    use std::task::Wake;
+    use std::thread::{self, Thread};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: StdArc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let waker = Waker::from(StdArc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn async_sworndisk_fns() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+        let async_disk = AsyncSwornDisk::new(sworndisk);
+
+        block_on(async {
+            let num_rw = 16;
+            for i in 0..num_rw {
+                let mut wbuf = Buf::alloc(1)?;
+                wbuf.as_mut_slice().fill(i as u8);
+                async_disk.write(i as Lba, wbuf).await?;
+            }
+            async_disk.sync().await?;
+
+            let rbuf = async_disk.read(3 as Lba, Buf::alloc(2)?).await?;
+            assert_eq!(rbuf.as_slice()[0], 3u8);
+            assert_eq!(rbuf.as_slice()[BLOCK_SIZE], 4u8);
+
+            let rbuf = AsyncBlockSet::read(&async_disk, 15, Buf::alloc(1)?).await?;
+            assert_eq!(rbuf.as_slice()[0], 15u8);
+            assert!(async_disk
+                .read(async_disk.total_blocks() as Lba, Buf::alloc(1)?)
+                .await
+                .is_err());
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,154 @@
+//! The integrity audit of a whole `SwornDisk`, see `SwornDisk::audit()`.
+//!
+//! An audit authenticates the ciphertext of every written block with
+//! `SwornDisk::verify_range()`, checks that no data block is owned by more
+//! LBAs than its reference count (see `RefCountTable`), and takes the digest of the disk, so that an auditor can
+//! attest the integrity of an image (e.g., with the `sworndisk-audit` binary)
+//! without handing out any plaintext.
+use super::digest::DiskDigest;
+use super::sworndisk::{Hba, Lba, VerifyResult};
+use crate::prelude::*;
+
+use core::fmt::{self, Write};
+use core::ops::Range;
+
+/// The report of an integrity audit over the whole logical space.
+#[derive(Clone, Debug)]
+pub struct AuditReport {
+    /// The number of logical blocks audited.
+    pub total_blocks: usize,
+    /// The number of blocks passing the integrity check.
+    pub num_passed: usize,
+    /// The number of blocks failing the integrity check.
+    pub num_failed: usize,
+    /// The number of blocks never written.
+    pub num_holes: usize,
+    /// The number of blocks buffered in memory and not written to disk yet.
+    pub num_buffered: usize,
+    /// The LBAs failing the integrity check, as ascending ranges.
+    pub failed_lbas: Vec<Range<Lba>>,
+    /// The HBAs mapped by more LBAs than their reference counts, which are
+    /// one unless the blocks are shared, in ascending order.
+    pub shared_hbas: Vec<Hba>,
+    /// The digest of the disk.
+    pub digest: DiskDigest,
+}
+
+impl AuditReport {
+    pub(super) fn new(total_blocks: usize) -> Self {
+        Self {
+            total_blocks,
+            num_passed: 0,
+            num_failed: 0,
+            num_holes: 0,
+            num_buffered: 0,
+            failed_lbas: Vec::new(),
+            shared_hbas: Vec::new(),
+            digest: DiskDigest {
+                num_mapped: 0,
+                root: Default::default(),
+            },
+        }
+    }
+
+    /// Records the results of `verify_range()` starting from `lba`.
+    pub(super) fn record_verify_results(&mut self, lba: Lba, results: &[VerifyResult]) {
+        for (nth, result) in results.iter().enumerate() {
+            match result {
+                VerifyResult::Pass => self.num_passed += 1,
+                VerifyResult::Hole => self.num_holes += 1,
+                VerifyResult::Buffered => self.num_buffered += 1,
+                VerifyResult::Fail => {
+                    self.num_failed += 1;
+                    let failed_lba = lba + nth;
+                    match self.failed_lbas.last_mut() {
+                        Some(range) if range.end == failed_lba => range.end += 1,
+                        _ => self.failed_lbas.push(failed_lba..failed_lba + 1),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records the HBAs of all the mappings, finds the ones mapped by more
+    /// LBAs than `ref_count` of them.
+    pub(super) fn record_mappings(&mut self, mut hbas: Vec<Hba>, ref_count: impl Fn(Hba) -> usize) {
+        hbas.sort_unstable();
+        self.shared_hbas = hbas
+            .group_by(|hba1, hba2| hba1 == hba2)
+            .filter(|group| group.len() > ref_count(group[0]))
+            .map(|group| group[0])
+            .collect();
+    }
+
+    /// Returns whether the disk passes the audit.
+    pub fn is_clean(&self) -> bool {
+        self.num_failed == 0 && self.shared_hbas.is_empty()
+    }
+
+    /// Print the report as JSON format to `sink`.
+    pub fn print_json(&self, sink: &mut dyn Write) -> fmt::Result {
+        writeln!(sink, "{{")?;
+        writeln!(sink, "  \"clean\": {},", self.is_clean())?;
+        writeln!(sink, "  \"total_blocks\": {},", self.total_blocks)?;
+        writeln!(sink, "  \"passed\": {},", self.num_passed)?;
+        writeln!(sink, "  \"failed\": {},", self.num_failed)?;
+        writeln!(sink, "  \"holes\": {},", self.num_holes)?;
+        writeln!(sink, "  \"buffered\": {},", self.num_buffered)?;
+        write!(sink, "  \"failed_lbas\": [")?;
+        for (nth, range) in self.failed_lbas.iter().enumerate() {
+            let sep = if nth == 0 { "" } else { ", " };
+            write!(sink, "{sep}[{}, {}]", range.start, range.end)?;
+        }
+        writeln!(sink, "],")?;
+        write!(sink, "  \"shared_hbas\": [")?;
+        for (nth, hba) in self.shared_hbas.iter().enumerate() {
+            let sep = if nth == 0 { "" } else { ", " };
+            write!(sink, "{sep}{hba}")?;
+        }
+        writeln!(sink, "],")?;
+        writeln!(sink, "  \"digest\": {{")?;
+        writeln!(sink, "    \"num_mapped\": {},", self.digest.num_mapped)?;
+        write!(sink, "    \"root\": \"")?;
+        for byte in self.digest.root {
+            write!(sink, "{byte:02x}")?;
+        }
+        writeln!(sink, "\"")?;
+        writeln!(sink, "  }}")?;
+        writeln!(sink, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditReport;
+    use crate::layers::disk::VerifyResult;
+
+    #[test]
+    fn audit_report() {
+        use VerifyResult::{Buffered, Fail, Hole, Pass};
+
+        let mut report = AuditReport::new(8);
+        report.record_verify_results(0, &[Pass, Fail, Fail, Hole]);
+        report.record_verify_results(4, &[Fail, Buffered, Pass, Fail]);
+        assert_eq!(report.failed_lbas, vec![1..3, 4..5, 7..8]);
+        assert_eq!(report.num_failed, 4);
+        assert_eq!(
+            (report.num_passed, report.num_holes, report.num_buffered),
+            (2, 1, 1)
+        );
+
+        report.record_mappings(vec![5, 3, 5, 9, 5, 3], |_| 1);
+        assert_eq!(report.shared_hbas, vec![3, 5]);
+        assert!(!report.is_clean());
+
+        let mut json = String::new();
+        report.print_json(&mut json).unwrap();
+        assert!(json.contains("\"failed_lbas\": [[1, 3], [4, 5], [7, 8]],"));
+        assert!(json.contains("\"shared_hbas\": [3, 5],"));
+
+        // The mappings within the reference counts are fine
+        report.record_mappings(vec![5, 3, 5, 9, 5, 3], |hba| if hba == 5 { 3 } else { 1 });
+        assert_eq!(report.shared_hbas, vec![3]);
+    }
+}
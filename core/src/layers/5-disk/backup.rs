@@ -0,0 +1,394 @@
+//! Portable backup streams of the logical contents of `SwornDisk`.
+//!
+//! A backup stream is produced by `SwornDisk::export_stream()` and consumed
+//! by `SwornDisk::import_stream()`, e.g., to migrate a disk between hosts.
+//! The blocks are re-encrypted under a key derived from a caller-provided
+//! stream key, so the stream is independent of the root key, the crypto mode
+//! and the physical layout of either disk, and never exposes plaintext.
+//!
+//! An incremental stream only carries the blocks written and the ranges
+//! discarded since the sync of its base stream (see
+//! `SwornDisk::changed_blocks_since()`), which is applied on top of the disk
+//! restored from the base stream. The discards come before the extents.
+//!
+//! Stream format:
+//!
+//! ```text
+//! | Header | Frame (discard) ... | Frame (extent) ... | Frame (end) |
+//! Frame: | FrameHeader | Ciphertext (nblocks * BLOCK_SIZE) | MAC |
+//! ```
+//!
+//! Each frame is sealed by AEAD, with the header and the frame header as
+//! AAD and the sequence number of the frame as IV. Thus a reordered, dropped
+//! or truncated frame fails the authentication. A discard frame carries no
+//! block, its `nblocks` is flagged by `FrameHeader::DISCARD`. The end frame
+//! carries no block either, its `lba` is the total number of the exported
+//! blocks. The streams of version 1 have no discard frame.
+use super::sworndisk::Lba;
+use crate::layers::lsm::SyncId;
+use crate::os::{AeadBackendRef, AeadIv as Iv, AeadKey as Key, AeadMac as Mac};
+use crate::prelude::*;
+
+use core::mem::size_of;
+use core::ops::Range;
+use pod::Pod;
+
+/// The maximum number of blocks of an extent frame.
+pub(super) const MAX_FRAME_NBLOCKS: usize = 256;
+
+/// A sink of a backup stream.
+pub trait StreamWriter {
+    /// Writes the whole `buf` to the stream.
+    fn write_all(&mut self, buf: &[u8]) -> Result<()>;
+}
+
+/// A source of a backup stream.
+pub trait StreamReader {
+    /// Reads exactly `buf.len()` bytes from the stream.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()>;
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + ?Sized> StreamWriter for W {
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        std::io::Write::write_all(self, buf)
+            .map_err(|_| Error::with_msg(IoFailed, "failed to write backup stream"))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> StreamReader for R {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        std::io::Read::read_exact(self, buf)
+            .map_err(|_| Error::with_msg(IoFailed, "failed to read backup stream"))
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl StreamWriter for Vec<u8> {
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl StreamReader for &[u8] {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        if self.len() < buf.len() {
+            return_errno_with_msg!(IoFailed, "backup stream is truncated");
+        }
+        let (head, tail) = self.split_at(buf.len());
+        buf.copy_from_slice(head);
+        *self = tail;
+        Ok(())
+    }
+}
+
+/// The summary of an exported backup stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamSummary {
+    /// The number of exported blocks.
+    pub num_blocks: usize,
+    /// The sync ID of the exported snapshot, which is the base
+    /// of the next incremental stream.
+    pub sync_id: SyncId,
+}
+
+/// The header of a backup stream.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Debug)]
+struct StreamHeader {
+    magic: u64,
+    version: u64,
+    /// The number of blocks of the exported disk.
+    total_blocks: u64,
+    /// The sync ID of the base snapshot, zero for a full stream.
+    base_sync_id: SyncId,
+    /// The sync ID of the exported snapshot.
+    sync_id: SyncId,
+    /// The random salt to derive the key of frames from the stream key.
+    salt: Iv,
+    padding: [u8; 4],
+}
+
+/// The header of a frame, which is authenticated but not encrypted.
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Debug)]
+struct FrameHeader {
+    lba: u64,
+    nblocks: u64,
+}
+
+impl StreamHeader {
+    const MAGIC: u64 = 0x5357_4e44_424b_5550;
+    const VERSION: u64 = 2;
+    /// The oldest version which can be imported.
+    const MIN_VERSION: u64 = 1;
+}
+
+impl FrameHeader {
+    /// The flag of `nblocks` of a discard frame.
+    const DISCARD: u64 = 1 << 63;
+}
+
+/// A frame read from a backup stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Frame {
+    /// An extent of blocks at an LBA, read into the given buffer.
+    Extent(Lba, usize),
+    /// A range of blocks to be discarded.
+    Discard(Range<Lba>),
+}
+
+/// Seals and opens the frames of a backup stream.
+struct FrameCipher {
+    aead: AeadBackendRef,
+    key: Key,
+    header: StreamHeader,
+    next_seq: u64,
+}
+
+impl FrameCipher {
+    fn new(aead: AeadBackendRef, stream_key: &Key, header: StreamHeader) -> Result<Self> {
+        // The frame key is the keystream of zeros under the stream key and
+        // the salt, which differs among the streams sealed by the same key
+        let mut key = Key::new_zeroed();
+        let _ = aead.encrypt(
+            Key::new_zeroed().as_bytes(),
+            stream_key,
+            &header.salt,
+            &[],
+            key.as_bytes_mut(),
+        )?;
+        Ok(Self {
+            aead,
+            key,
+            header,
+            next_seq: 0,
+        })
+    }
+
+    fn next_iv_and_aad(&mut self, frame_header: &FrameHeader) -> (Iv, Vec<u8>) {
+        let mut iv = Iv::new_zeroed();
+        iv[..size_of::<u64>()].copy_from_slice(&self.next_seq.to_le_bytes());
+        self.next_seq += 1;
+        let mut aad = Vec::with_capacity(size_of::<StreamHeader>() + size_of::<FrameHeader>());
+        aad.extend_from_slice(self.header.as_bytes());
+        aad.extend_from_slice(frame_header.as_bytes());
+        (iv, aad)
+    }
+
+    fn seal(&mut self, frame_header: &FrameHeader, plain: &[u8], cipher: &mut [u8]) -> Result<Mac> {
+        let (iv, aad) = self.next_iv_and_aad(frame_header);
+        self.aead.encrypt(plain, &self.key, &iv, &aad, cipher)
+    }
+
+    fn open(
+        &mut self,
+        frame_header: &FrameHeader,
+        cipher: &[u8],
+        mac: &Mac,
+        plain: &mut [u8],
+    ) -> Result<()> {
+        let (iv, aad) = self.next_iv_and_aad(frame_header);
+        self.aead
+            .decrypt(cipher, &self.key, &iv, &aad, mac, plain)
+            .map_err(|_| Error::with_msg(MacMismatched, "backup stream is tampered"))
+    }
+}
+
+/// Writes the extents of a disk as a backup stream.
+pub(super) struct StreamExporter<'a, W: StreamWriter + ?Sized> {
+    writer: &'a mut W,
+    cipher: FrameCipher,
+    cipher_buf: Vec<u8>,
+    num_blocks: u64,
+}
+
+impl<'a, W: StreamWriter + ?Sized> StreamExporter<'a, W> {
+    /// Starts a stream of the snapshot at `sync_id` of a disk of `total_blocks`
+    /// blocks, sealed by `stream_key`. The stream is incremental to the one
+    /// at `base_sync_id` unless it's zero.
+    pub fn new(
+        writer: &'a mut W,
+        aead: AeadBackendRef,
+        stream_key: &Key,
+        total_blocks: usize,
+        base_sync_id: SyncId,
+        sync_id: SyncId,
+    ) -> Result<Self> {
+        let header = StreamHeader {
+            magic: StreamHeader::MAGIC,
+            version: StreamHeader::VERSION,
+            total_blocks: total_blocks as _,
+            base_sync_id,
+            sync_id,
+            salt: Iv::random(),
+            padding: [0; 4],
+        };
+        writer.write_all(header.as_bytes())?;
+        Ok(Self {
+            writer,
+            cipher: FrameCipher::new(aead, stream_key, header)?,
+            cipher_buf: Vec::new(),
+            num_blocks: 0,
+        })
+    }
+
+    /// Appends an extent of at most `MAX_FRAME_NBLOCKS` plaintext blocks at `lba`.
+    pub fn write_extent(&mut self, lba: Lba, plain: &[u8]) -> Result<()> {
+        let nblocks = plain.len() / BLOCK_SIZE;
+        debug_assert!(nblocks > 0 && nblocks <= MAX_FRAME_NBLOCKS);
+        debug_assert_eq!(plain.len() % BLOCK_SIZE, 0);
+        let frame_header = FrameHeader {
+            lba: lba as _,
+            nblocks: nblocks as _,
+        };
+        self.cipher_buf.resize(plain.len(), 0);
+        let mac = self
+            .cipher
+            .seal(&frame_header, plain, &mut self.cipher_buf)?;
+        self.writer.write_all(frame_header.as_bytes())?;
+        self.writer.write_all(&self.cipher_buf)?;
+        self.writer.write_all(mac.as_bytes())?;
+        self.num_blocks += nblocks as u64;
+        Ok(())
+    }
+
+    /// Appends a discard of `range`, which must precede all the extents.
+    pub fn write_discard(&mut self, range: Range<Lba>) -> Result<()> {
+        debug_assert!(!range.is_empty());
+        debug_assert_eq!(self.num_blocks, 0);
+        let frame_header = FrameHeader {
+            lba: range.start as _,
+            nblocks: range.len() as u64 | FrameHeader::DISCARD,
+        };
+        let mac = self.cipher.seal(&frame_header, &[], &mut [])?;
+        self.writer.write_all(frame_header.as_bytes())?;
+        self.writer.write_all(mac.as_bytes())
+    }
+
+    /// Ends the stream, returns the summary of it.
+    pub fn finish(mut self) -> Result<StreamSummary> {
+        let frame_header = FrameHeader {
+            lba: self.num_blocks,
+            nblocks: 0,
+        };
+        let mac = self.cipher.seal(&frame_header, &[], &mut [])?;
+        self.writer.write_all(frame_header.as_bytes())?;
+        self.writer.write_all(mac.as_bytes())?;
+        Ok(StreamSummary {
+            num_blocks: self.num_blocks as _,
+            sync_id: self.cipher.header.sync_id,
+        })
+    }
+}
+
+/// Reads the extents of a disk from a backup stream.
+pub(super) struct StreamImporter<'a, R: StreamReader + ?Sized> {
+    reader: &'a mut R,
+    cipher: FrameCipher,
+    cipher_buf: Vec<u8>,
+    num_blocks: u64,
+    is_ended: bool,
+}
+
+impl<'a, R: StreamReader + ?Sized> StreamImporter<'a, R> {
+    /// Starts reading a stream sealed by `stream_key`.
+    pub fn new(reader: &'a mut R, aead: AeadBackendRef, stream_key: &Key) -> Result<Self> {
+        let mut header = StreamHeader::new_zeroed();
+        reader.read_exact(header.as_bytes_mut())?;
+        if header.magic != StreamHeader::MAGIC {
+            return_errno_with_msg!(InvalidArgs, "not a backup stream");
+        }
+        if !(StreamHeader::MIN_VERSION..=StreamHeader::VERSION).contains(&header.version) {
+            return_errno_with_msg!(Unsupported, "unsupported version of backup stream");
+        }
+        Ok(Self {
+            reader,
+            cipher: FrameCipher::new(aead, stream_key, header)?,
+            cipher_buf: Vec::new(),
+            num_blocks: 0,
+            is_ended: false,
+        })
+    }
+
+    /// The number of blocks of the exported disk, which is not authenticated
+    /// until the first frame is read.
+    pub fn total_blocks(&self) -> usize {
+        self.cipher.header.total_blocks as _
+    }
+
+    /// Whether the stream is incremental, which is not authenticated
+    /// until the first frame is read either.
+    pub fn is_incremental(&self) -> bool {
+        self.cipher.header.base_sync_id != 0
+    }
+
+    /// Reads the next frame, the blocks of an extent are read into `plain`.
+    /// Returns `None` once the end of the stream is authenticated.
+    ///
+    /// The `plain` buffer must hold `MAX_FRAME_NBLOCKS` blocks.
+    pub fn next_frame(&mut self, plain: &mut [u8]) -> Result<Option<Frame>> {
+        debug_assert_eq!(plain.len(), MAX_FRAME_NBLOCKS * BLOCK_SIZE);
+        if self.is_ended {
+            return Ok(None);
+        }
+
+        let mut frame_header = FrameHeader::new_zeroed();
+        self.reader.read_exact(frame_header.as_bytes_mut())?;
+        if frame_header.nblocks & FrameHeader::DISCARD != 0 {
+            return self.open_discard(&frame_header).map(Some);
+        }
+        let nblocks = frame_header.nblocks as usize;
+        if nblocks > MAX_FRAME_NBLOCKS {
+            return_errno_with_msg!(InvalidArgs, "invalid frame of backup stream");
+        }
+        self.cipher_buf.resize(nblocks * BLOCK_SIZE, 0);
+        self.reader.read_exact(&mut self.cipher_buf)?;
+        let mut mac = Mac::new_zeroed();
+        self.reader.read_exact(mac.as_bytes_mut())?;
+
+        let plain = &mut plain[..nblocks * BLOCK_SIZE];
+        self.cipher
+            .open(&frame_header, &self.cipher_buf, &mac, plain)?;
+
+        if nblocks == 0 {
+            if frame_header.lba != self.num_blocks {
+                return_errno_with_msg!(MacMismatched, "backup stream is tampered");
+            }
+            self.is_ended = true;
+            return Ok(None);
+        }
+        let lba = frame_header.lba as Lba;
+        if lba + nblocks > self.total_blocks() {
+            return_errno_with_msg!(InvalidArgs, "invalid frame of backup stream");
+        }
+        self.num_blocks += nblocks as u64;
+        Ok(Some(Frame::Extent(lba, nblocks)))
+    }
+
+    fn open_discard(&mut self, frame_header: &FrameHeader) -> Result<Frame> {
+        let mut mac = Mac::new_zeroed();
+        self.reader.read_exact(mac.as_bytes_mut())?;
+        self.cipher.open(frame_header, &[], &mac, &mut [])?;
+
+        let lba = frame_header.lba as Lba;
+        let nblocks = (frame_header.nblocks & !FrameHeader::DISCARD) as usize;
+        // The discards precede the extents, and never appear in version 1
+        if self.num_blocks > 0
+            || self.cipher.header.version == StreamHeader::MIN_VERSION
+            || nblocks == 0
+            || lba + nblocks > self.total_blocks()
+        {
+            return_errno_with_msg!(InvalidArgs, "invalid frame of backup stream");
+        }
+        Ok(Frame::Discard(lba..lba + nblocks))
+    }
+
+    /// The number of imported blocks.
+    pub fn num_blocks(&self) -> usize {
+        self.num_blocks as _
+    }
+}
@@ -1,41 +1,74 @@
 //! Block I/O (BIO).
+use super::cost_stats::rdtsc;
+use super::disk_stats::DiskStats;
 use crate::os::{Mutex, MutexGuard};
 use crate::prelude::*;
 
+use alloc::collections::VecDeque;
 use anymap::hashbrown::AnyMap;
 use core::any::Any;
 use core::ptr::NonNull;
-use core::sync::atomic::{AtomicUsize, Ordering};
-use crossbeam_queue::SegQueue;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 /// A queue for managing block I/O requests (`BioReq`).
 /// It provides a concurrency-safe way to store and manage
 /// block I/O requests that need to be processed by a block device.
+///
+/// Like the request queue of a block device, adjacent requests can be merged
+/// on dequeue, see `dequeue_merged()`. The urgent reads (see `BioPriority`)
+/// are queued in a lane ahead of the other requests, and never merged.
 pub struct BioReqQueue {
-    queue: Mutex<SegQueue<BioReq>>,
+    queue: Mutex<VecDeque<BioReq>>,
     num_reqs: AtomicUsize,
+    /// The maximum number of blocks of a merged request, no merging if
+    /// it's no more than one.
+    merge_window: usize,
+    /// The statistics where the queue depths are recorded.
+    stats: Arc<DiskStats>,
 }
 
 impl BioReqQueue {
-    /// Create a new `BioReqQueue` instance.
+    /// Create a new `BioReqQueue` instance without request merging.
     pub fn new() -> Self {
+        Self::with_merge_window(0, Arc::new(DiskStats::default()))
+    }
+
+    /// Create a new `BioReqQueue` instance, which merges adjacent requests
+    /// into up to `merge_window` blocks, and records its depths to `stats`.
+    pub fn with_merge_window(merge_window: usize, stats: Arc<DiskStats>) -> Self {
         Self {
-            queue: Mutex::new(SegQueue::new()),
+            queue: Mutex::new(VecDeque::new()),
             num_reqs: AtomicUsize::new(0),
+            merge_window,
+            stats,
         }
     }
 
-    /// Enqueue a block I/O request.
+    /// Enqueue a block I/O request. An urgent read is queued behind the other
+    /// urgent ones only, thus it doesn't wait for the queued writes to the
+    /// same blocks, as in the request queue of a block device.
     pub fn enqueue(&self, req: BioReq) -> Result<()> {
         req.submit();
-        self.queue.lock().push(req);
-        self.num_reqs.fetch_add(1, Ordering::Release);
+        let mut queue = self.queue.lock();
+        if req.is_urgent_read() {
+            let pos = queue
+                .iter()
+                .position(|queued| !queued.is_urgent_read())
+                .unwrap_or(queue.len());
+            queue.insert(pos, req);
+        } else {
+            queue.push_back(req);
+        }
+        drop(queue);
+        let depth = self.num_reqs.fetch_add(1, Ordering::Release) + 1;
+        self.stats
+            .record_bio(|stats| stats.record_queue_depth(depth));
         Ok(())
     }
 
     /// Dequeue a block I/O request.
     pub fn dequeue(&self) -> Option<BioReq> {
-        if let Some(req) = self.queue.lock().pop() {
+        if let Some(req) = self.queue.lock().pop_front() {
             self.num_reqs.fetch_sub(1, Ordering::Release);
             Some(req)
         } else {
@@ -44,6 +77,32 @@ impl BioReqQueue {
         }
     }
 
+    /// Dequeue a block I/O request, along with the following requests that
+    /// can be merged with it.
+    ///
+    /// Requests are merged if they are reads (or non-FUA writes) at contiguous
+    /// addresses, queued one right after another, and the merged request
+    /// has no more blocks than the merge window. The requests are returned
+    /// in the order of addresses.
+    pub fn dequeue_merged(&self) -> Option<Vec<BioReq>> {
+        let mut queue = self.queue.lock();
+        let first = queue.pop_front()?;
+        let mut nblocks = first.nblocks();
+        let mut reqs = vec![first];
+        while let Some(next) = queue.front() {
+            let last = reqs.last().unwrap();
+            if !last.can_merge_with(next) || nblocks + next.nblocks() > self.merge_window {
+                break;
+            }
+            nblocks += next.nblocks();
+            reqs.push(queue.pop_front().unwrap());
+        }
+        drop(queue);
+
+        self.num_reqs.fetch_sub(reqs.len(), Ordering::Release);
+        Some(reqs)
+    }
+
     /// Returns the number of pending requests in this queue.
     pub fn num_reqs(&self) -> usize {
         self.num_reqs.load(Ordering::Acquire)
@@ -58,12 +117,16 @@ impl BioReqQueue {
 /// A block I/O request.
 pub struct BioReq {
     type_: BioType,
+    priority: BioPriority,
+    fua: bool,
     addr: BlockId,
     nblocks: u32,
     bufs: Mutex<Vec<BlockBuf>>,
     status: Mutex<BioStatus>,
     on_complete: Option<BioReqOnCompleteFn>,
     ext: Mutex<AnyMap>,
+    /// The time (in RDTSC cycles) when the request is submitted.
+    submitted_at: AtomicU64,
 }
 
 /// The type of a block request.
@@ -75,6 +138,30 @@ pub enum BioType {
     Write,
     /// A sync request.
     Sync,
+    /// A flush request, which is a write barrier weaker than a sync.
+    ///
+    /// The writes completed before the flush reach the device ahead of the
+    /// writes after it, but they are not committed (i.e., durable) until a
+    /// following sync or FUA write.
+    Flush,
+}
+
+/// The priority of a block request.
+///
+/// It is a hint for the request queue, e.g., the async path may serve a
+/// latency-sensitive sync ahead of the normal requests, as long as the order
+/// required by the sync semantics is kept.
+///
+/// A read of `High` priority is latency-critical (urgent): it's served ahead
+/// of the queued normal requests without being merged, preempts background GC
+/// at the next victim segment, and is counted apart in `BioStats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BioPriority {
+    /// A normal request.
+    #[default]
+    Normal,
+    /// A latency-sensitive request.
+    High,
 }
 
 /// A response from a block device.
@@ -98,6 +185,22 @@ impl BioReq {
         self.type_
     }
 
+    /// Returns the priority of the request.
+    pub fn priority(&self) -> BioPriority {
+        self.priority
+    }
+
+    /// Returns whether the request is a latency-critical read, see `BioPriority`.
+    pub fn is_urgent_read(&self) -> bool {
+        self.type_ == BioType::Read && self.priority == BioPriority::High
+    }
+
+    /// Returns whether the request is a FUA (force unit access) write,
+    /// whose blocks are durable upon completion.
+    pub fn is_fua(&self) -> bool {
+        self.fua
+    }
+
     /// Returns the starting address of requested blocks.
     ///
     /// The return value is meaningless if the request is not a read or write.
@@ -180,6 +283,17 @@ impl BioReq {
         }
     }
 
+    /// Returns whether the given request can be merged right after this one.
+    fn can_merge_with(&self, next: &BioReq) -> bool {
+        self.type_ == next.type_
+            && matches!(self.type_, BioType::Read | BioType::Write)
+            && !self.fua
+            && !next.fua
+            && !self.is_urgent_read()
+            && !next.is_urgent_read()
+            && self.addr + self.nblocks() == next.addr
+    }
+
     /// Mark the request as submitted.
     pub(super) fn submit(&self) {
         let mut status = self.status.lock();
@@ -187,12 +301,20 @@ impl BioReq {
             BioStatus::Init => *status = BioStatus::Submitted,
             _ => unreachable!(),
         }
+        self.submitted_at.store(rdtsc(), Ordering::Relaxed);
+    }
+
+    /// Returns the time (in RDTSC cycles) when the request is submitted.
+    pub(super) fn submitted_at(&self) -> u64 {
+        self.submitted_at.load(Ordering::Relaxed)
     }
 }
 
 /// A builder for `BioReq`.
 pub struct BioReqBuilder {
     type_: BioType,
+    priority: BioPriority,
+    fua: bool,
     addr: Option<BlockId>,
     bufs: Option<Vec<BlockBuf>>,
     on_complete: Option<BioReqOnCompleteFn>,
@@ -204,6 +326,8 @@ impl BioReqBuilder {
     pub fn new(type_: BioType) -> Self {
         Self {
             type_,
+            priority: BioPriority::default(),
+            fua: false,
             addr: None,
             bufs: None,
             on_complete: None,
@@ -211,6 +335,18 @@ impl BioReqBuilder {
         }
     }
 
+    /// Specify the priority of the request.
+    pub fn priority(mut self, priority: BioPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Mark the write request as FUA (force unit access).
+    pub fn fua(mut self, fua: bool) -> Self {
+        self.fua = fua;
+        self
+    }
+
     /// Specify the block address of the request.
     pub fn addr(mut self, addr: BlockId) -> Self {
         self.addr = Some(addr);
@@ -241,7 +377,11 @@ impl BioReqBuilder {
     /// Build the request.
     pub fn build(mut self) -> BioReq {
         let type_ = self.type_;
-        if type_ == BioType::Sync {
+        debug_assert!(
+            !self.fua || type_ == BioType::Write,
+            "fua is only meaningful for a write",
+        );
+        if type_ == BioType::Sync || type_ == BioType::Flush {
             debug_assert!(
                 self.addr.is_none(),
                 "addr is only meaningful for a read or write",
@@ -270,12 +410,15 @@ impl BioReqBuilder {
 
         BioReq {
             type_,
+            priority: self.priority,
+            fua: self.fua,
             addr,
             nblocks,
             bufs: Mutex::new(bufs),
             status: Mutex::new(BioStatus::Init),
             on_complete,
             ext: Mutex::new(ext),
+            submitted_at: AtomicU64::new(0),
         }
     }
 }
@@ -0,0 +1,433 @@
+//! Statistics of block I/O requests (`BioReq`).
+
+use super::bio::BioType;
+use super::cost_stats::{rdtsc, STATS_ENABLED};
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+/// The number of buckets in a latency histogram, the nth bucket counts the
+/// requests whose latency (in CPU cycles) is in `[2^(n-1), 2^n)`.
+pub const LATENCY_BUCKETS: usize = 48;
+
+/// The number of buckets in a batch size histogram, the nth bucket counts the
+/// physical I/Os whose number of records is in `[2^(n-1), 2^n)`.
+pub const BATCH_SIZE_BUCKETS: usize = 16;
+
+/// The time constant of the EWMA of the I/O rate, i.e., the samples older
+/// than it weigh less than `1/e`.
+const IO_RATE_TIME_CONSTANT: Duration = Duration::from_secs(1);
+
+/// Statistics of the block I/O requests of each type, and of the request queue.
+pub struct BioStats {
+    read: BioTypeStats,
+    /// The latency-critical reads, not counted in `read`.
+    urgent_read: BioTypeStats,
+    write: BioTypeStats,
+    sync: BioTypeStats,
+    flush: BioTypeStats,
+    /// The maximum depth of the request queue.
+    max_queue_depth: AtomicU64,
+    /// The sum of the queue depths seen by each enqueued request.
+    sum_queue_depth: AtomicU64,
+    num_enqueued: AtomicU64,
+    /// The physical reads of user data blocks, batched by contiguous HBAs.
+    read_batches: BatchStats,
+    /// The physical writes of user data blocks, batched by contiguous HBAs.
+    write_batches: BatchStats,
+    io_rate: IoRateEstimator,
+}
+
+/// An estimator of the rate of user data I/Os, i.e., the exponentially
+/// weighted moving average (EWMA) of the blocks read or written per second
+/// between two samples, weighted by the elapsed time.
+struct IoRateEstimator {
+    /// The estimated rate in blocks per second, as the bits of a `f64`.
+    rate: AtomicU64,
+    /// The number of blocks read or written at the last sample.
+    last_nblocks: AtomicU64,
+    /// The time of the last sample in nanoseconds, zero if never sampled.
+    last_sample_ns: AtomicU64,
+}
+
+/// Statistics of the number of records served by each physical I/O.
+struct BatchStats {
+    num_batches: AtomicU64,
+    num_records: AtomicU64,
+    size_hist: [AtomicU64; BATCH_SIZE_BUCKETS],
+}
+
+/// Statistics of block I/O requests of a type, timed in CPU cycles (RDTSC).
+struct BioTypeStats {
+    count: AtomicU64,
+    queued_cycles: AtomicU64,
+    service_cycles: AtomicU64,
+    latency_hist: [AtomicU64; LATENCY_BUCKETS],
+}
+
+/// A snapshot of the statistics of block I/O requests of a type.
+#[derive(Debug, Clone)]
+pub struct BioTypeSnapshot {
+    pub count: u64,
+    /// Total cycles between the submission and the start of service.
+    pub queued_cycles: u64,
+    /// Total cycles in service.
+    pub service_cycles: u64,
+    /// Histogram of the latency (queued plus in service), see `LATENCY_BUCKETS`.
+    pub latency_hist: [u64; LATENCY_BUCKETS],
+}
+
+/// A snapshot of the statistics of batched physical I/Os.
+#[derive(Debug, Clone)]
+pub struct BatchSnapshot {
+    /// The number of physical I/Os.
+    pub num_batches: u64,
+    /// The number of records (blocks) served by them.
+    pub num_records: u64,
+    /// Histogram of the records per physical I/O, see `BATCH_SIZE_BUCKETS`.
+    pub size_hist: [u64; BATCH_SIZE_BUCKETS],
+}
+
+/// A snapshot of `BioStats`.
+#[derive(Debug, Clone)]
+pub struct BioStatsSnapshot {
+    pub read: BioTypeSnapshot,
+    /// The latency-critical reads, not counted in `read`.
+    pub urgent_read: BioTypeSnapshot,
+    pub write: BioTypeSnapshot,
+    pub sync: BioTypeSnapshot,
+    pub flush: BioTypeSnapshot,
+    pub max_queue_depth: u64,
+    pub avg_queue_depth: f64,
+    pub read_batches: BatchSnapshot,
+    pub write_batches: BatchSnapshot,
+}
+
+impl BatchSnapshot {
+    /// Returns the average number of records per physical I/O.
+    pub fn avg_batch_size(&self) -> f64 {
+        if self.num_batches > 0 {
+            self.num_records as f64 / self.num_batches as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+impl BioTypeStats {
+    const fn new() -> Self {
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        Self {
+            count: AtomicU64::new(0),
+            queued_cycles: AtomicU64::new(0),
+            service_cycles: AtomicU64::new(0),
+            latency_hist: [ZERO; LATENCY_BUCKETS],
+        }
+    }
+
+    fn record(&self, queued_cycles: u64, service_cycles: u64) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.queued_cycles
+            .fetch_add(queued_cycles, Ordering::Relaxed);
+        self.service_cycles
+            .fetch_add(service_cycles, Ordering::Relaxed);
+        let latency = queued_cycles.saturating_add(service_cycles);
+        let bucket = ((u64::BITS - latency.leading_zeros()) as usize).min(LATENCY_BUCKETS - 1);
+        self.latency_hist[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> BioTypeSnapshot {
+        BioTypeSnapshot {
+            count: self.count.load(Ordering::Relaxed),
+            queued_cycles: self.queued_cycles.load(Ordering::Relaxed),
+            service_cycles: self.service_cycles.load(Ordering::Relaxed),
+            latency_hist: core::array::from_fn(|i| self.latency_hist[i].load(Ordering::Relaxed)),
+        }
+    }
+
+    fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.queued_cycles.store(0, Ordering::Relaxed);
+        self.service_cycles.store(0, Ordering::Relaxed);
+        self.latency_hist
+            .iter()
+            .for_each(|bucket| bucket.store(0, Ordering::Relaxed));
+    }
+}
+
+impl BatchStats {
+    const fn new() -> Self {
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        Self {
+            num_batches: AtomicU64::new(0),
+            num_records: AtomicU64::new(0),
+            size_hist: [ZERO; BATCH_SIZE_BUCKETS],
+        }
+    }
+
+    fn record(&self, nrecords: usize) {
+        let nrecords = nrecords as u64;
+        // Always counted, since the I/O rate is sampled from it
+        self.num_records.fetch_add(nrecords, Ordering::Relaxed);
+        if !STATS_ENABLED {
+            return;
+        }
+        self.num_batches.fetch_add(1, Ordering::Relaxed);
+        let bucket = ((u64::BITS - nrecords.leading_zeros()) as usize).min(BATCH_SIZE_BUCKETS - 1);
+        self.size_hist[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> BatchSnapshot {
+        BatchSnapshot {
+            num_batches: self.num_batches.load(Ordering::Relaxed),
+            num_records: self.num_records.load(Ordering::Relaxed),
+            size_hist: core::array::from_fn(|i| self.size_hist[i].load(Ordering::Relaxed)),
+        }
+    }
+
+    fn reset(&self) {
+        self.num_batches.store(0, Ordering::Relaxed);
+        self.num_records.store(0, Ordering::Relaxed);
+        self.size_hist
+            .iter()
+            .for_each(|bucket| bucket.store(0, Ordering::Relaxed));
+    }
+}
+
+impl BioStats {
+    /// Create a new `BioStats` instance
+    pub const fn new() -> Self {
+        Self {
+            read: BioTypeStats::new(),
+            urgent_read: BioTypeStats::new(),
+            write: BioTypeStats::new(),
+            sync: BioTypeStats::new(),
+            flush: BioTypeStats::new(),
+            max_queue_depth: AtomicU64::new(0),
+            sum_queue_depth: AtomicU64::new(0),
+            num_enqueued: AtomicU64::new(0),
+            read_batches: BatchStats::new(),
+            write_batches: BatchStats::new(),
+            io_rate: IoRateEstimator::new(),
+        }
+    }
+
+    fn of_type(&self, type_: BioType) -> &BioTypeStats {
+        match type_ {
+            BioType::Read => &self.read,
+            BioType::Write => &self.write,
+            BioType::Sync => &self.sync,
+            BioType::Flush => &self.flush,
+        }
+    }
+
+    /// Record the depth of the request queue upon an enqueue.
+    pub fn record_queue_depth(&self, depth: usize) {
+        if !STATS_ENABLED {
+            return;
+        }
+        self.max_queue_depth
+            .fetch_max(depth as u64, Ordering::Relaxed);
+        self.sum_queue_depth
+            .fetch_add(depth as u64, Ordering::Relaxed);
+        self.num_enqueued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a completed request, which was submitted at `submitted_at` and
+    /// started to be served at `started_at` (both in RDTSC cycles).
+    pub fn record_completion(&self, type_: BioType, submitted_at: u64, started_at: u64) {
+        if !STATS_ENABLED {
+            return;
+        }
+        let queued_cycles = started_at.saturating_sub(submitted_at);
+        let service_cycles = rdtsc().saturating_sub(started_at);
+        self.of_type(type_).record(queued_cycles, service_cycles);
+    }
+
+    /// Record a completed latency-critical read, see `record_completion()`.
+    pub fn record_urgent_read(&self, submitted_at: u64, started_at: u64) {
+        if !STATS_ENABLED {
+            return;
+        }
+        let queued_cycles = started_at.saturating_sub(submitted_at);
+        let service_cycles = rdtsc().saturating_sub(started_at);
+        self.urgent_read.record(queued_cycles, service_cycles);
+    }
+
+    /// Record a physical read of `nrecords` user data blocks.
+    pub fn record_read_batch(&self, nrecords: usize) {
+        self.read_batches.record(nrecords);
+    }
+
+    /// Record a physical write of `nrecords` user data blocks.
+    pub fn record_write_batch(&self, nrecords: usize) {
+        self.write_batches.record(nrecords);
+    }
+
+    /// Sample the number of user data blocks read or written so far at `now`,
+    /// then update and return the estimated I/O rate (in blocks per second).
+    pub fn sample_io_rate(&self, now: Duration) -> f64 {
+        let nblocks = self.read_batches.num_records.load(Ordering::Relaxed)
+            + self.write_batches.num_records.load(Ordering::Relaxed);
+        self.io_rate.sample(nblocks, now)
+    }
+
+    /// Returns the I/O rate (in blocks per second) estimated at the last sample.
+    pub fn io_rate(&self) -> f64 {
+        self.io_rate.rate()
+    }
+
+    pub fn get_stats(&self) -> BioStatsSnapshot {
+        let num_enqueued = self.num_enqueued.load(Ordering::Relaxed);
+        let avg_queue_depth = if num_enqueued > 0 {
+            self.sum_queue_depth.load(Ordering::Relaxed) as f64 / num_enqueued as f64
+        } else {
+            0.0
+        };
+        BioStatsSnapshot {
+            read: self.read.snapshot(),
+            urgent_read: self.urgent_read.snapshot(),
+            write: self.write.snapshot(),
+            sync: self.sync.snapshot(),
+            flush: self.flush.snapshot(),
+            max_queue_depth: self.max_queue_depth.load(Ordering::Relaxed),
+            avg_queue_depth,
+            read_batches: self.read_batches.snapshot(),
+            write_batches: self.write_batches.snapshot(),
+        }
+    }
+
+    /// Reset all statistics
+    pub fn reset(&self) {
+        self.read.reset();
+        self.urgent_read.reset();
+        self.write.reset();
+        self.sync.reset();
+        self.flush.reset();
+        self.max_queue_depth.store(0, Ordering::Relaxed);
+        self.sum_queue_depth.store(0, Ordering::Relaxed);
+        self.num_enqueued.store(0, Ordering::Relaxed);
+        self.read_batches.reset();
+        self.write_batches.reset();
+        self.io_rate.last_nblocks.store(0, Ordering::Relaxed);
+    }
+
+    /// Print statistics to `sink`
+    pub fn print(&self, sink: &mut dyn Write) -> fmt::Result {
+        let stats = self.get_stats();
+
+        writeln!(
+            sink,
+            "==================== BIO Statistics ===================="
+        )?;
+        writeln!(sink, "  (Unit: CPU cycles, measured via RDTSC)")?;
+        for (name, type_stats) in [
+            ("Read", &stats.read),
+            ("Urgent", &stats.urgent_read),
+            ("Write", &stats.write),
+            ("Sync", &stats.sync),
+            ("Flush", &stats.flush),
+        ] {
+            let count = type_stats.count.max(1);
+            writeln!(
+                sink,
+                "  {:<6} count: {:>10}, avg queued: {:>12}, avg service: {:>12}",
+                name,
+                type_stats.count,
+                type_stats.queued_cycles / count,
+                type_stats.service_cycles / count,
+            )?;
+            for (bucket, &n) in type_stats.latency_hist.iter().enumerate() {
+                if n > 0 {
+                    writeln!(sink, "    < 2^{:<2} cycles: {}", bucket, n)?;
+                }
+            }
+        }
+        writeln!(
+            sink,
+            "  Queue depth max: {}, avg: {:.2}",
+            stats.max_queue_depth, stats.avg_queue_depth
+        )?;
+        for (name, batches) in [
+            ("Read", &stats.read_batches),
+            ("Write", &stats.write_batches),
+        ] {
+            writeln!(
+                sink,
+                "  {:<6} batches: {:>10}, records: {:>10}, avg records per I/O: {:.2}",
+                name,
+                batches.num_batches,
+                batches.num_records,
+                batches.avg_batch_size(),
+            )?;
+            for (bucket, &n) in batches.size_hist.iter().enumerate() {
+                if n > 0 {
+                    writeln!(sink, "    < 2^{:<2} records: {}", bucket, n)?;
+                }
+            }
+        }
+        writeln!(
+            sink,
+            "========================================================"
+        )
+    }
+}
+
+impl IoRateEstimator {
+    const fn new() -> Self {
+        Self {
+            rate: AtomicU64::new(0),
+            last_nblocks: AtomicU64::new(0),
+            last_sample_ns: AtomicU64::new(0),
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        f64::from_bits(self.rate.load(Ordering::Relaxed))
+    }
+
+    fn sample(&self, nblocks: u64, now: Duration) -> f64 {
+        let now_ns = (now.as_nanos() as u64).max(1);
+        let last_sample_ns = self.last_sample_ns.swap(now_ns, Ordering::Relaxed);
+        let last_nblocks = self.last_nblocks.swap(nblocks, Ordering::Relaxed);
+        // The first sample is only the baseline of the following ones
+        if last_sample_ns == 0 || now_ns <= last_sample_ns {
+            return self.rate();
+        }
+
+        let elapsed = (now_ns - last_sample_ns) as f64 / 1e9;
+        let sampled_rate = nblocks.saturating_sub(last_nblocks) as f64 / elapsed;
+        // A longer interval weighs the sampled rate more
+        let weight = elapsed / (elapsed + IO_RATE_TIME_CONSTANT.as_secs_f64());
+        let rate = self.rate() + weight * (sampled_rate - self.rate());
+        self.rate.store(rate.to_bits(), Ordering::Relaxed);
+        rate
+    }
+}
+
+// Global BIO statistics
+pub static BIO_STATS: BioStats = BioStats::new();
+
+#[cfg(test)]
+mod tests {
+    use super::BioStats;
+    use core::time::Duration;
+
+    #[test]
+    fn io_rate_ewma() {
+        let stats = BioStats::new();
+        assert_eq!(stats.sample_io_rate(Duration::from_secs(1)), 0.0);
+
+        // 1000 blocks per second for a second, weighed a half
+        stats.record_write_batch(600);
+        stats.record_read_batch(400);
+        let rate = stats.sample_io_rate(Duration::from_secs(2));
+        assert!((rate - 500.0).abs() < 1e-6);
+        assert_eq!(stats.io_rate(), rate);
+
+        // Decays while idle
+        let idle_rate = stats.sample_io_rate(Duration::from_secs(3));
+        assert!((idle_rate - 250.0).abs() < 1e-6);
+    }
+}
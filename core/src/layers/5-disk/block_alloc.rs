@@ -1,15 +1,20 @@
 //! Block allocation.
-use super::segment::{self, recover_segment_table, Segment, SegmentId, SEGMENT_SIZE};
-use super::sworndisk::{Hba, CONFIG};
+use super::config::AllocPolicy;
+use super::config::Config;
+use super::segment::{
+    self, recover_segment_table, Segment, SegmentDiff, SegmentHeat, SegmentId, SEGMENT_SIZE,
+};
+use super::sworndisk::Hba;
 use crate::layers::bio::{BlockSet, Buf, BufRef, BID_SIZE};
-use crate::layers::log::{TxLog, TxLogStore};
-use crate::os::{BTreeMap, Condvar, CvarMutex, Mutex};
+use crate::layers::log::{TxLog, TxLogId, TxLogStore};
+use crate::os::{spawn, BTreeMap, Condvar, CvarMutex, Mutex};
 use crate::prelude::*;
 use crate::util::BitMap;
 
 use core::mem::size_of;
 use core::num::NonZeroUsize;
-use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use pod::Pod;
 use serde::{Deserialize, Serialize};
 
@@ -19,6 +24,10 @@ const BUCKET_BLOCK_VALIDITY_TABLE: &str = "BVT";
 const BUCKET_BLOCK_ALLOC_LOG: &str = "BAL";
 /// The bucket name of segment table.
 const BUCKET_SEGMENT_TABLE: &str = "SEG";
+/// The bucket name of segment diff log.
+const BUCKET_SEGMENT_DIFF_LOG: &str = "SEGD";
+/// The bucket name of segment heat log.
+const BUCKET_SEGMENT_HEAT_LOG: &str = "SEGH";
 
 /// Block validity table. Global allocator for `SwornDisk`,
 /// which manages validities of user data blocks.
@@ -31,6 +40,91 @@ pub(super) struct AllocTable {
     is_dirty: AtomicBool,
     cvar: Condvar,
     num_free: CvarMutex<usize>,
+    /// The total number of blocks allocated for user writes, used as the
+    /// sequence number of writes to measure the age of segments.
+    write_seq: AtomicU64,
+    /// The number of free blocks reserved for GC, which user writes can't use.
+    reserved_nblocks: usize,
+    alloc_policy: AllocPolicy,
+    /// See `Config::alloc_alignment`.
+    alloc_alignment: usize,
+    /// The statistics of the allocations large enough to be aligned.
+    align_stats: AlignStatsCounter,
+    /// Whether the table is never persisted, see `Config::ephemeral`.
+    ephemeral: bool,
+    /// The open segment of each allocation class, used by `AllocPolicy::SegmentFill`.
+    open_segments: Mutex<[Option<SegmentId>; AllocClass::COUNT]>,
+    /// The state of the lazy recovery, `None` if the table is recovered eagerly.
+    lazy_recovery: Option<LazyRecovery>,
+    /// The latest version of the persisted segment counters, either in the
+    /// `SEG` log or in a `SEGD` log. Bumped under the lock of `bitmap`.
+    segment_version: AtomicU64,
+    /// The `(valid_blocks, free_space)` of each segment as persisted, i.e.,
+    /// by the `SEG` log and the `SEGD` logs. `None` if GC is disabled.
+    logged_segments: Option<Mutex<Vec<(usize, usize)>>>,
+    /// The number of `SEGD` logs written since the last compaction.
+    num_segment_diff_logs: AtomicUsize,
+    /// The number of bytes of the `BAL` records written since the last
+    /// compaction, which recovery replays on top of the `BVT` log.
+    logged_diff_bytes: AtomicUsize,
+    /// Whether blocks are freed without any `BAL` record (e.g., by GC) since
+    /// the last compaction, so that the next one can't be skipped.
+    has_unlogged_frees: AtomicBool,
+}
+
+/// The state of the lazy recovery of `AllocTable`.
+struct LazyRecovery {
+    state: CvarMutex<LazyRecoveryState>,
+    cvar: Condvar,
+}
+
+struct LazyRecoveryState {
+    /// The result of the recovery, `None` if it's in progress.
+    result: Option<Result<()>>,
+    /// The allocations and deallocations happened during the recovery, in order.
+    deferred_diffs: Vec<(Hba, AllocDiff)>,
+}
+
+impl LazyRecovery {
+    fn new() -> Self {
+        Self {
+            state: CvarMutex::new(LazyRecoveryState {
+                result: None,
+                deferred_diffs: Vec::new(),
+            }),
+            cvar: Condvar::new(),
+        }
+    }
+}
+
+/// The class of block allocations. Each class fills its own open segment
+/// under `AllocPolicy::SegmentFill`, so that blocks of different classes
+/// are not mixed in a segment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum AllocClass {
+    /// Blocks of user writes.
+    User = 0,
+    /// Target blocks of GC migrations.
+    Gc = 1,
+}
+
+impl AllocClass {
+    const COUNT: usize = 2;
+}
+
+/// The statistics of aligned allocations, see `Config::alloc_alignment`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocAlignStats {
+    /// The allocations of at least `Config::alloc_alignment` blocks.
+    pub num_large: u64,
+    /// The ones of them in a contiguous extent starting on an aligned boundary.
+    pub num_aligned: u64,
+}
+
+#[derive(Default)]
+struct AlignStatsCounter {
+    num_large: AtomicU64,
+    num_aligned: AtomicU64,
 }
 
 /// Per-TX block allocator in `SwornDisk`, recording validities
@@ -54,13 +148,14 @@ pub(super) enum AllocDiff {
 const DIFF_RECORD_SIZE: usize = size_of::<AllocDiff>() + size_of::<Hba>();
 
 impl AllocTable {
-    /// Create a new `AllocTable` given the total number of blocks.
-    pub fn new(nblocks: NonZeroUsize) -> Self {
+    /// Create a new `AllocTable` given the total number of blocks
+    /// and the configuration of the disk.
+    pub fn new(nblocks: NonZeroUsize, config: &Config) -> Self {
         let total_blocks = nblocks.get();
         let bitmap = Arc::new(Mutex::new(BitMap::repeat(true, nblocks.get())));
 
         // Only create segment_table when GC is enabled
-        let segment_table = if CONFIG.get().enable_gc {
+        let segment_table = if config.enable_gc {
             let segment_nums = total_blocks / SEGMENT_SIZE;
             let mut table = Vec::with_capacity(segment_nums);
             for id in 0..segment_nums {
@@ -70,6 +165,7 @@ impl AllocTable {
         } else {
             None
         };
+        let logged_segments = Self::snapshot_segments(&segment_table).map(Mutex::new);
 
         Self {
             bitmap,
@@ -79,39 +175,45 @@ impl AllocTable {
             is_dirty: AtomicBool::new(false),
             cvar: Condvar::new(),
             num_free: CvarMutex::new(nblocks.get()),
+            write_seq: AtomicU64::new(0),
+            reserved_nblocks: Self::calc_reserved_nblocks(nblocks, config),
+            alloc_policy: config.alloc_policy,
+            alloc_alignment: config.alloc_alignment,
+            align_stats: AlignStatsCounter::default(),
+            ephemeral: config.ephemeral,
+            open_segments: Mutex::new([None; AllocClass::COUNT]),
+            lazy_recovery: None,
+            segment_version: AtomicU64::new(0),
+            logged_segments,
+            num_segment_diff_logs: AtomicUsize::new(0),
+            logged_diff_bytes: AtomicUsize::new(0),
+            has_unlogged_frees: AtomicBool::new(false),
         }
     }
 
-    /// Allocate a free slot for a new block, returns `None`
-    /// if there are no free slots.
-    pub fn alloc(&self) -> Option<Hba> {
-        let mut bitmap = self.bitmap.lock();
-        let next_avail = self.next_avail.load(Ordering::Acquire);
-
-        let hba = if let Some(hba) = bitmap.first_one(next_avail) {
-            hba
-        } else {
-            bitmap.first_one(0)?
-        };
-        bitmap.set(hba, false);
-
-        // Only update segment_table when GC is enabled
-        if let Some(ref segment_table) = self.segment_table {
-            let segment_id = hba / SEGMENT_SIZE;
-            segment_table[segment_id].mark_alloc();
+    /// Calculate the number of blocks reserved for GC by `Config::over_provisioning`.
+    fn calc_reserved_nblocks(nblocks: NonZeroUsize, config: &Config) -> usize {
+        if !config.enable_gc {
+            return 0;
         }
+        nblocks.get() * config.over_provisioning.min(100) / 100
+    }
 
-        self.next_avail.store(hba + 1, Ordering::Release);
-        Some(hba as Hba)
+    /// Allocate a free slot for a new block, returns `None` if there are
+    /// no free slots but the ones reserved for GC (see `alloc_batch()`).
+    pub fn alloc(&self) -> Option<Hba> {
+        let hbas = self.alloc_batch(NonZeroUsize::new(1).unwrap()).ok()?;
+        Some(hbas[0])
     }
 
     /// Allocate multiple free slots for a bunch of new blocks, returns `None`
     /// if there are no free slots for all.
     pub fn alloc_batch(&self, count: NonZeroUsize) -> Result<Vec<Hba>> {
+        self.wait_for_recovery()?;
         let cnt = count.get();
         let mut num_free = self.num_free.lock().unwrap();
-        if *num_free < cnt {
-            return Err(Error::with_msg(OutOfDisk, "no free slots"));
+        if *num_free < cnt + self.reserved_nblocks {
+            return Err(Error::with_msg(NoSpaceLeft, "no free slots"));
         }
         while *num_free < cnt {
             // TODO: May not be woken, may require manual triggering of a compaction in L4
@@ -124,12 +226,16 @@ impl AllocTable {
             return_errno_with_msg!(OutOfDisk, "allocate blocks failed");
         };
         debug_assert_eq!(hbas.len(), cnt);
+        self.record_alignment(&hbas);
 
         // Only update segment_table when GC is enabled
+        let write_seq = self.write_seq.fetch_add(cnt as u64, Ordering::Relaxed) + cnt as u64;
         if let Some(ref segment_table) = self.segment_table {
             hbas.iter().for_each(|hba| {
                 let segment_id = *hba / SEGMENT_SIZE;
                 segment_table[segment_id].mark_alloc();
+                segment_table[segment_id].set_last_write(write_seq);
+                segment_table[segment_id].add_writes(1);
             });
         }
 
@@ -144,8 +250,28 @@ impl AllocTable {
         let count = count.get();
         debug_assert!(count > 0);
         let mut bitmap = self.bitmap.lock();
+
+        if self.alloc_policy == AllocPolicy::SegmentFill
+            && let Some(hbas) = self.pick_from_open_segment(&bitmap, AllocClass::User, count, None)
+        {
+            hbas.iter().for_each(|hba| bitmap.set(*hba, false));
+            return Some(hbas);
+        }
+
+        // Fall back to the linear scan if the segments can't serve the allocation
         let mut next_avail = self.next_avail.load(Ordering::Acquire);
 
+        if self.alloc_policy == AllocPolicy::Linear
+            && self.alloc_alignment > 1
+            && count >= self.alloc_alignment
+            && let Some(start) = self.find_aligned_extent(&bitmap, next_avail, count)
+        {
+            let hbas: Vec<Hba> = (start..start + count).collect();
+            hbas.iter().for_each(|hba| bitmap.set(*hba, false));
+            self.next_avail.store(start + count, Ordering::Release);
+            return Some(hbas);
+        }
+
         if next_avail + count > self.nblocks.get() {
             next_avail = bitmap.first_one(0)?;
         }
@@ -163,137 +289,788 @@ impl AllocTable {
         Some(hbas)
     }
 
+    /// Find a free extent of `count` blocks starting on a boundary of
+    /// `alloc_alignment`, scanning from `from` and wrapping around once.
+    fn find_aligned_extent(&self, bitmap: &BitMap, from: usize, count: usize) -> Option<Hba> {
+        let (nblocks, align) = (self.nblocks.get(), self.alloc_alignment);
+        let mut pos = align_up(from, align);
+        let mut wrapped = false;
+        loop {
+            if wrapped && pos >= from {
+                return None;
+            }
+            let start = match bitmap.first_one(pos.min(nblocks - 1)) {
+                Some(start) if pos < nblocks => align_up(start, align),
+                _ => nblocks,
+            };
+            if start + count > nblocks {
+                if wrapped {
+                    return None;
+                }
+                wrapped = true;
+                pos = 0;
+                continue;
+            }
+            let end = bitmap.first_zero(start).unwrap_or(nblocks);
+            if end >= start + count {
+                return Some(start);
+            }
+            pos = align_up(end, align);
+        }
+    }
+
+    /// Record whether a large allocation is aligned.
+    fn record_alignment(&self, hbas: &[Hba]) {
+        let align = self.alloc_alignment;
+        if align <= 1 || hbas.len() < align {
+            return;
+        }
+        self.align_stats.num_large.fetch_add(1, Ordering::Relaxed);
+        let is_contiguous = hbas.windows(2).all(|pair| pair[1] == pair[0] + 1);
+        if hbas[0] % align == 0 && is_contiguous {
+            self.align_stats.num_aligned.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the statistics of aligned allocations.
+    pub fn align_stats(&self) -> AllocAlignStats {
+        AllocAlignStats {
+            num_large: self.align_stats.num_large.load(Ordering::Relaxed),
+            num_aligned: self.align_stats.num_aligned.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Allocate `count` physically contiguous free slots, returns `None` if
+    /// there is no free extent large enough or the allocation would use
+    /// the blocks reserved for GC.
+    pub fn alloc_contiguous(&self, count: NonZeroUsize) -> Option<Vec<Hba>> {
+        let cnt = count.get();
+        let mut num_free = self.num_free.lock().unwrap();
+        if *num_free < cnt + self.reserved_nblocks {
+            return None;
+        }
+
+        let mut bitmap = self.bitmap.lock();
+        let mut pos = 0;
+        let start = loop {
+            if pos >= self.nblocks.get() {
+                return None;
+            }
+            let start = bitmap.first_one(pos)?;
+            let end = bitmap.first_zero(start).unwrap_or(self.nblocks.get());
+            if end - start >= cnt {
+                break start;
+            }
+            pos = end;
+        };
+        let hbas: Vec<Hba> = (start..start + cnt).collect();
+        hbas.iter().for_each(|hba| bitmap.set(*hba, false));
+
+        // Only update segment_table when GC is enabled
+        if let Some(ref segment_table) = self.segment_table {
+            hbas.iter()
+                .for_each(|hba| segment_table[*hba / SEGMENT_SIZE].mark_alloc());
+        }
+
+        *num_free -= cnt;
+        let _ = self
+            .is_dirty
+            .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed);
+        Some(hbas)
+    }
+
+    /// Pick `count` free blocks for the allocation class under `AllocPolicy::SegmentFill`,
+    /// without marking them allocated.
+    ///
+    /// The open segment of the class is filled to completion before another
+    /// segment is opened. Clean segments are preferred to be opened, then
+    /// partially-empty ones. The segments open by other classes and the
+    /// `excluded` segment are never picked. Returns `None` if there are not
+    /// enough free blocks in the available segments.
+    fn pick_from_open_segment(
+        &self,
+        bitmap: &BitMap,
+        class: AllocClass,
+        count: usize,
+        excluded: Option<SegmentId>,
+    ) -> Option<Vec<Hba>> {
+        let nsegments = self.nblocks.get() / SEGMENT_SIZE;
+        let mut open_segments = self.open_segments.lock();
+        let mut open_segment = open_segments[class as usize].filter(|id| Some(*id) != excluded);
+        let mut last_segment = open_segments[class as usize];
+        let mut filled_segments = Vec::new();
+        let mut hbas = Vec::with_capacity(count);
+
+        while hbas.len() < count {
+            let segment_id = match open_segment {
+                Some(segment_id) => segment_id,
+                None => {
+                    let is_available = |segment_id: SegmentId| {
+                        Some(segment_id) != excluded
+                            && !filled_segments.contains(&segment_id)
+                            && !open_segments
+                                .iter()
+                                .enumerate()
+                                .any(|(c, open)| c != class as usize && *open == Some(segment_id))
+                    };
+                    Self::find_segment_to_open(bitmap, nsegments, last_segment, is_available)?
+                }
+            };
+
+            let end = (segment_id + 1) * SEGMENT_SIZE;
+            let mut pos = segment_id * SEGMENT_SIZE;
+            while hbas.len() < count
+                && pos < end
+                && let Some(hba) = bitmap.first_one(pos)
+                && hba < end
+            {
+                hbas.push(hba);
+                pos = hba + 1;
+            }
+
+            let is_filled = pos >= end || bitmap.first_one(pos).map_or(true, |hba| hba >= end);
+            if is_filled {
+                filled_segments.push(segment_id);
+                open_segment = None;
+            } else {
+                open_segment = Some(segment_id);
+            }
+            last_segment = Some(segment_id);
+        }
+
+        open_segments[class as usize] = open_segment;
+        Some(hbas)
+    }
+
+    /// Find a segment with free blocks to open, starting from the one next to
+    /// `last_segment`. Clean segments are preferred.
+    fn find_segment_to_open(
+        bitmap: &BitMap,
+        nsegments: usize,
+        last_segment: Option<SegmentId>,
+        is_available: impl Fn(SegmentId) -> bool,
+    ) -> Option<SegmentId> {
+        if nsegments == 0 {
+            return None;
+        }
+        let start = last_segment.map_or(0, |segment_id| segment_id + 1);
+        let candidates = (0..nsegments)
+            .map(|i| (start + i) % nsegments)
+            .filter(|segment_id| is_available(*segment_id));
+
+        let is_clean = |segment_id: &SegmentId| {
+            bitmap
+                .first_zero(segment_id * SEGMENT_SIZE)
+                .map_or(true, |hba| hba >= (segment_id + 1) * SEGMENT_SIZE)
+        };
+        let has_free = |segment_id: &SegmentId| {
+            bitmap
+                .first_one(segment_id * SEGMENT_SIZE)
+                .is_some_and(|hba| hba < (segment_id + 1) * SEGMENT_SIZE)
+        };
+        candidates
+            .clone()
+            .find(is_clean)
+            .or_else(|| candidates.clone().find(has_free))
+    }
+
+    /// Pick free blocks as the targets to migrate `count` valid blocks of the
+    /// victim segment to. Returns `None` if the alloc policy is not
+    /// `AllocPolicy::SegmentFill` or the segments can't serve the blocks.
+    ///
+    /// The targets are marked allocated later by `migrate_batch()`.
+    pub fn pick_migration_targets(&self, count: usize, victim: SegmentId) -> Option<Vec<Hba>> {
+        if self.alloc_policy != AllocPolicy::SegmentFill {
+            return None;
+        }
+        if count == 0 {
+            return Some(Vec::new());
+        }
+        let bitmap = self.bitmap.lock();
+        self.pick_from_open_segment(&bitmap, AllocClass::Gc, count, Some(victim))
+    }
+
     /// Recover the `AllocTable` from the latest `BVT` log and a bunch of `BAL` logs
     /// in the given store.
     pub fn recover<D: BlockSet + 'static>(
         nblocks: NonZeroUsize,
         store: &Arc<TxLogStore<D>>,
+        config: &Config,
     ) -> Result<Self> {
-        let total_blocks = nblocks.get();
-        let segment_nums = total_blocks / SEGMENT_SIZE;
-        let enable_gc = CONFIG.get().enable_gc;
-
-        // Only recover segment_table when GC is enabled
-        let recover_segment_table_from_log =
-            |bitmap: Arc<Mutex<BitMap>>| -> Result<Option<Vec<Segment>>> {
-                if !enable_gc {
-                    return Ok(None);
-                }
-                let seg_log_res = store.open_log_in(BUCKET_SEGMENT_TABLE);
-                let segment_table = match seg_log_res {
-                    Ok(seg_log) => {
-                        let mut buf = Buf::alloc(seg_log.nblocks())?;
-                        seg_log.read(0 as BlockId, buf.as_mut())?;
-                        recover_segment_table(segment_nums, buf.as_slice(), bitmap)?
-                    }
-                    Err(e) => {
-                        if e.errno() != NotFound {
-                            return Err(e);
-                        }
-                        (0..segment_nums)
-                            .map(|id| Segment::new(id, SEGMENT_SIZE, bitmap.clone()))
-                            .collect()
-                    }
-                };
-                Ok(Some(segment_table))
-            };
+        let mut tx = store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            let bal_log_ids = Self::list_bal_logs(store)?;
+            let bitmap = Self::recover_bitmap(nblocks, store, bal_log_ids)?;
+            let next_avail = bitmap.first_one(0).unwrap_or(0);
+            let num_free = bitmap.count_ones();
+            let bitmap_ref = Arc::new(Mutex::new(bitmap));
+            let (segment_table, segment_version) =
+                Self::recover_segment_table(nblocks, store, bitmap_ref.clone(), config.enable_gc)?;
+            if let Some(ref segment_table) = segment_table {
+                let bitmap = bitmap_ref.lock();
+                segment_table
+                    .iter()
+                    .for_each(|segment| segment.recount_free_space(&bitmap));
+            }
+            Ok(Self::from_recovered(
+                nblocks,
+                config,
+                bitmap_ref,
+                segment_table,
+                segment_version,
+                next_avail,
+                num_free,
+                None,
+            ))
+        });
+        let recov_self = res.map_err(|_| {
+            tx.abort();
+            Error::with_msg(TxAborted, "recover block validity table TX aborted")
+        })?;
+        tx.commit()?;
 
+        Ok(recov_self)
+    }
+
+    /// Recover the `AllocTable` lazily, it returns before the bitmap of block
+    /// validities is recovered, which is then recovered by a background thread.
+    ///
+    /// Allocations wait for the recovery to finish, while deallocations are
+    /// deferred and applied after the recovery. Only the `BAL` logs existing
+    /// on return are replayed, the newer ones are applied in memory already.
+    pub fn recover_lazily<D: BlockSet + 'static>(
+        nblocks: NonZeroUsize,
+        store: &Arc<TxLogStore<D>>,
+        config: &Config,
+    ) -> Result<Arc<Self>> {
         let mut tx = store.new_tx();
         let res: Result<_> = tx.context(|| {
-            // Recover the block validity table from `BVT` log first
-            let bvt_log_res = store.open_log_in(BUCKET_BLOCK_VALIDITY_TABLE);
-            let mut bitmap = match bvt_log_res {
-                Ok(bvt_log) => {
-                    let mut buf = Buf::alloc(bvt_log.nblocks())?;
-                    bvt_log.read(0 as BlockId, buf.as_mut())?;
-                    postcard::from_bytes(buf.as_slice()).map_err(|_| {
-                        Error::with_msg(InvalidArgs, "deserialize block validity table failed")
-                    })?
-                }
+            let bal_log_ids = Self::list_bal_logs(store)?;
+            // All blocks are regarded as allocated until recovered
+            let bitmap_ref = Arc::new(Mutex::new(BitMap::repeat(false, nblocks.get())));
+            let (segment_table, segment_version) =
+                Self::recover_segment_table(nblocks, store, bitmap_ref.clone(), config.enable_gc)?;
+            let table = Self::from_recovered(
+                nblocks,
+                config,
+                bitmap_ref,
+                segment_table,
+                segment_version,
+                0,
+                0,
+                Some(LazyRecovery::new()),
+            );
+            Ok((Arc::new(table), bal_log_ids))
+        });
+        let (recov_self, bal_log_ids) = res.map_err(|_| {
+            tx.abort();
+            Error::with_msg(TxAborted, "recover block validity table TX aborted")
+        })?;
+        tx.commit()?;
+
+        let table = recov_self.clone();
+        let store = store.clone();
+        let _ = spawn(move || {
+            let mut tx = store.new_tx();
+            let res: Result<_> = tx.context(|| Self::recover_bitmap(nblocks, &store, bal_log_ids));
+            let res = match res {
+                Ok(bitmap) => tx.commit().map(|_| bitmap),
                 Err(e) => {
-                    if e.errno() != NotFound {
-                        return Err(e);
-                    }
-                    BitMap::repeat(true, nblocks.get())
+                    tx.abort();
+                    Err(e)
                 }
             };
+            table.finish_recovery(res);
+        });
+        Ok(recov_self)
+    }
+
+    /// Grow the table to `nblocks` blocks, the added blocks are free. When GC
+    /// is enabled, the segments covering the added blocks are appended to the
+    /// segment table. The grown table is dirty, it's persisted by the next
+    /// `do_compaction()`.
+    ///
+    /// It's only used by `SwornDisk::grow_offline()` on a table just recovered.
+    pub fn grow(self, nblocks: NonZeroUsize, config: &Config) -> Self {
+        let old_nblocks = self.nblocks.get();
+        debug_assert!(nblocks.get() >= old_nblocks && self.lazy_recovery.is_none());
+        let num_free = *self.num_free.lock().unwrap() + nblocks.get() - old_nblocks;
+        let bitmap_ref = self.bitmap.clone();
+        bitmap_ref.lock().grow(nblocks.get(), true);
+
+        let segment_table = self.segment_table.map(|mut segment_table| {
+            let bitmap = bitmap_ref.lock();
+            for id in segment_table.len()..nblocks.get() / SEGMENT_SIZE {
+                let segment = Segment::new(id, SEGMENT_SIZE, bitmap_ref.clone());
+                // The tail blocks of the old table, which belong to no
+                // segment before, may be allocated
+                let segment_range = id * SEGMENT_SIZE..(id + 1) * SEGMENT_SIZE;
+                let num_allocated = segment_range.filter(|hba| !bitmap[*hba]).count();
+                if num_allocated > 0 {
+                    segment.mark_alloc_batch(num_allocated);
+                }
+                segment_table.push(segment);
+            }
+            segment_table
+        });
+
+        let table = Self::from_recovered(
+            nblocks,
+            config,
+            bitmap_ref,
+            segment_table,
+            self.segment_version.load(Ordering::Relaxed),
+            self.next_avail.load(Ordering::Relaxed),
+            num_free,
+            None,
+        );
+        table.is_dirty.store(true, Ordering::Relaxed);
+        table
+    }
+
+    /// Shrink the table to `nblocks` blocks, the dropped blocks must be free,
+    /// i.e., evacuated by `GcWorker::evacuate()` beforehand. The segments
+    /// beyond the shrunk table are dropped. The shrunk table is dirty, it's
+    /// persisted by the next `do_compaction()`.
+    ///
+    /// It's only used by `SwornDisk::shrink()` on a table just recovered.
+    pub fn shrink(self, nblocks: NonZeroUsize, config: &Config) -> Result<Self> {
+        debug_assert!(nblocks <= self.nblocks && self.lazy_recovery.is_none());
+        let mut bitmap = self.bitmap.lock();
+        if nblocks < self.nblocks && bitmap.first_zero(nblocks.get()).is_some() {
+            return_errno_with_msg!(InvalidArgs, "blocks to drop are not evacuated");
+        }
+        bitmap.truncate(nblocks.get());
+        let next_avail = bitmap.first_one(0).unwrap_or(0);
+        let num_free = bitmap.count_ones();
+        drop(bitmap);
+
+        let segment_table = self.segment_table.map(|mut segment_table| {
+            segment_table.truncate(nblocks.get() / SEGMENT_SIZE);
+            segment_table
+        });
+        let table = Self::from_recovered(
+            nblocks,
+            config,
+            self.bitmap,
+            segment_table,
+            self.segment_version.load(Ordering::Relaxed),
+            next_avail,
+            num_free,
+            None,
+        );
+        table.is_dirty.store(true, Ordering::Relaxed);
+        Ok(table)
+    }
+
+    fn from_recovered(
+        nblocks: NonZeroUsize,
+        config: &Config,
+        bitmap: Arc<Mutex<BitMap>>,
+        segment_table: Option<Vec<Segment>>,
+        segment_version: u64,
+        next_avail: usize,
+        num_free: usize,
+        lazy_recovery: Option<LazyRecovery>,
+    ) -> Self {
+        // Continue the sequence from the last writes of segments, so that
+        // their ages stay meaningful across recovery
+        let write_seq = segment_table.as_ref().map_or(0, |segment_table| {
+            segment_table
+                .iter()
+                .map(Segment::last_write)
+                .max()
+                .unwrap_or(0)
+        });
+        let logged_segments = Self::snapshot_segments(&segment_table).map(Mutex::new);
+        Self {
+            bitmap,
+            segment_table,
+            next_avail: AtomicUsize::new(next_avail),
+            nblocks,
+            is_dirty: AtomicBool::new(false),
+            cvar: Condvar::new(),
+            num_free: CvarMutex::new(num_free),
+            write_seq: AtomicU64::new(write_seq),
+            reserved_nblocks: Self::calc_reserved_nblocks(nblocks, config),
+            alloc_policy: config.alloc_policy,
+            alloc_alignment: config.alloc_alignment,
+            align_stats: AlignStatsCounter::default(),
+            ephemeral: config.ephemeral,
+            open_segments: Mutex::new([None; AllocClass::COUNT]),
+            lazy_recovery,
+            segment_version: AtomicU64::new(segment_version),
+            logged_segments,
+            num_segment_diff_logs: AtomicUsize::new(0),
+            logged_diff_bytes: AtomicUsize::new(0),
+            has_unlogged_frees: AtomicBool::new(false),
+        }
+    }
+
+    /// List the `BAL` logs in the store, from older to newer.
+    ///
+    /// # Panics
+    ///
+    /// This method must be called within a TX. Otherwise, this method panics.
+    fn list_bal_logs<D: BlockSet + 'static>(store: &Arc<TxLogStore<D>>) -> Result<Vec<TxLogId>> {
+        let bal_log_ids_res = store.list_logs_in(BUCKET_BLOCK_ALLOC_LOG);
+        if let Err(e) = &bal_log_ids_res
+            && e.errno() == NotFound
+        {
+            return Ok(Vec::new());
+        }
+        let mut bal_log_ids = bal_log_ids_res?;
+        bal_log_ids.sort();
+        Ok(bal_log_ids)
+    }
+
+    /// Recover the bitmap of block validities from the latest `BVT` log,
+    /// then apply each diff in the given `BAL` logs.
+    ///
+    /// # Panics
+    ///
+    /// This method must be called within a TX. Otherwise, this method panics.
+    fn recover_bitmap<D: BlockSet + 'static>(
+        nblocks: NonZeroUsize,
+        store: &Arc<TxLogStore<D>>,
+        bal_log_ids: Vec<TxLogId>,
+    ) -> Result<BitMap> {
+        // Recover the block validity table from `BVT` log first
+        let bvt_log_res = store.open_log_in(BUCKET_BLOCK_VALIDITY_TABLE);
+        let mut bitmap = match bvt_log_res {
+            Ok(bvt_log) => {
+                let mut buf = Buf::alloc(bvt_log.nblocks())?;
+                bvt_log.read(0 as BlockId, buf.as_mut())?;
+                postcard::from_bytes(buf.as_slice()).map_err(|_| {
+                    Error::with_msg(InvalidArgs, "deserialize block validity table failed")
+                })?
+            }
+            Err(e) => {
+                if e.errno() != NotFound {
+                    return Err(e);
+                }
+                BitMap::repeat(true, nblocks.get())
+            }
+        };
 
-            // Iterate each `BAL` log and apply each diff, from older to newer
-            let bal_log_ids_res = store.list_logs_in(BUCKET_BLOCK_ALLOC_LOG);
-            if let Err(e) = &bal_log_ids_res
+        // Iterate each `BAL` log and apply each diff, from older to newer
+        for bal_log_id in bal_log_ids {
+            let bal_log_res = store.open_log(bal_log_id, false);
+            if let Err(e) = &bal_log_res
                 && e.errno() == NotFound
             {
-                let next_avail = bitmap.first_one(0).unwrap_or(0);
-                let num_free = bitmap.count_ones();
-                let bitmap_ref = Arc::new(Mutex::new(bitmap));
-                let segment_table = recover_segment_table_from_log(bitmap_ref.clone())?;
-                return Ok(Self {
-                    bitmap: bitmap_ref,
-                    segment_table,
-                    next_avail: AtomicUsize::new(next_avail),
-                    nblocks,
-                    is_dirty: AtomicBool::new(false),
-                    cvar: Condvar::new(),
-                    num_free: CvarMutex::new(num_free),
-                });
-            }
-            let mut bal_log_ids = bal_log_ids_res?;
-            bal_log_ids.sort();
-
-            for bal_log_id in bal_log_ids {
-                let bal_log_res = store.open_log(bal_log_id, false);
-                if let Err(e) = &bal_log_res
-                    && e.errno() == NotFound
-                {
+                continue;
+            }
+            let bal_log = bal_log_res?;
+
+            let log_nblocks = bal_log.nblocks();
+            let mut buf = Buf::alloc(log_nblocks)?;
+            bal_log.read(0 as BlockId, buf.as_mut())?;
+            let buf_slice = buf.as_slice();
+            let mut offset = 0;
+            while offset <= log_nblocks * BLOCK_SIZE - DIFF_RECORD_SIZE {
+                let diff = AllocDiff::from(buf_slice[offset]);
+                offset += 1;
+                if diff == AllocDiff::Invalid {
                     continue;
                 }
-                let bal_log = bal_log_res?;
+                let bid = BlockId::from_bytes(&buf_slice[offset..offset + BID_SIZE]);
+                offset += BID_SIZE;
+                match diff {
+                    AllocDiff::Alloc => bitmap.set(bid, false),
+                    AllocDiff::Dealloc => bitmap.set(bid, true),
+                    _ => unreachable!(),
+                }
+            }
+        }
+        Ok(bitmap)
+    }
 
-                let log_nblocks = bal_log.nblocks();
-                let mut buf = Buf::alloc(log_nblocks)?;
-                bal_log.read(0 as BlockId, buf.as_mut())?;
+    /// Recover the segment table from the `SEG` log, then apply the newer
+    /// records in `SEGD` logs, only when GC is enabled.
+    /// Returns the table with the latest version of its counters.
+    ///
+    /// # Panics
+    ///
+    /// This method must be called within a TX. Otherwise, this method panics.
+    fn recover_segment_table<D: BlockSet + 'static>(
+        nblocks: NonZeroUsize,
+        store: &Arc<TxLogStore<D>>,
+        bitmap: Arc<Mutex<BitMap>>,
+        enable_gc: bool,
+    ) -> Result<(Option<Vec<Segment>>, u64)> {
+        if !enable_gc {
+            return Ok((None, 0));
+        }
+        let segment_nums = nblocks.get() / SEGMENT_SIZE;
+        let seg_log_res = store.open_log_in(BUCKET_SEGMENT_TABLE);
+        let (segment_table, table_version) = match seg_log_res {
+            Ok(seg_log) => {
+                let mut buf = Buf::alloc(seg_log.nblocks())?;
+                seg_log.read(0 as BlockId, buf.as_mut())?;
                 let buf_slice = buf.as_slice();
-                let mut offset = 0;
-                while offset <= log_nblocks * BLOCK_SIZE - DIFF_RECORD_SIZE {
-                    let diff = AllocDiff::from(buf_slice[offset]);
-                    offset += 1;
-                    if diff == AllocDiff::Invalid {
+                // The version follows the table, zero if absent
+                let version_offset = segment_nums * Segment::ser_size();
+                let version = buf_slice
+                    .get(version_offset..version_offset + size_of::<u64>())
+                    .map_or(0, u64::from_bytes);
+                let segment_table = recover_segment_table(segment_nums, buf_slice, bitmap)?;
+                // The write statistics follow the version, zero if absent
+                let heat_offset = version_offset + size_of::<u64>();
+                if let Some(heat_buf) = buf_slice
+                    .get(heat_offset..heat_offset + segment_nums * size_of::<SegmentHeat>())
+                {
+                    for (segment, record) in segment_table
+                        .iter()
+                        .zip(heat_buf.chunks_exact(size_of::<SegmentHeat>()))
+                    {
+                        let heat = SegmentHeat::from_bytes(record);
+                        if heat.version != 0 {
+                            segment.apply_heat(&heat);
+                        }
+                    }
+                }
+                (segment_table, version)
+            }
+            Err(e) => {
+                if e.errno() != NotFound {
+                    return Err(e);
+                }
+                let segment_table = (0..segment_nums)
+                    .map(|id| Segment::new(id, SEGMENT_SIZE, bitmap.clone()))
+                    .collect();
+                (segment_table, 0)
+            }
+        };
+
+        // Apply the records newer than the table, the largest version wins
+        let mut versions = vec![table_version; segment_nums];
+        let segd_log_ids = match store.list_logs_in(BUCKET_SEGMENT_DIFF_LOG) {
+            Ok(segd_log_ids) => segd_log_ids,
+            Err(e) if e.errno() == NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        for segd_log_id in segd_log_ids {
+            let segd_log = store.open_log(segd_log_id, false)?;
+            let mut buf = Buf::alloc(segd_log.nblocks())?;
+            segd_log.read(0 as BlockId, buf.as_mut())?;
+            for record in buf.as_slice().chunks_exact(size_of::<SegmentDiff>()) {
+                let diff = SegmentDiff::from_bytes(record);
+                if diff.version == 0 {
+                    continue;
+                }
+                let segment_id = diff.segment_id as SegmentId;
+                if segment_id >= segment_nums {
+                    return_errno_with_msg!(InvalidArgs, "invalid segment diff record");
+                }
+                if diff.version > versions[segment_id] {
+                    segment_table[segment_id].apply_diff(&diff);
+                    versions[segment_id] = diff.version;
+                }
+            }
+        }
+        let version = versions.into_iter().max().unwrap_or(table_version);
+
+        // The same for the write statistics
+        let mut versions = vec![table_version; segment_nums];
+        let segh_log_ids = match store.list_logs_in(BUCKET_SEGMENT_HEAT_LOG) {
+            Ok(segh_log_ids) => segh_log_ids,
+            Err(e) if e.errno() == NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        for segh_log_id in segh_log_ids {
+            let segh_log = store.open_log(segh_log_id, false)?;
+            let mut buf = Buf::alloc(segh_log.nblocks())?;
+            segh_log.read(0 as BlockId, buf.as_mut())?;
+            for record in buf.as_slice().chunks_exact(size_of::<SegmentHeat>()) {
+                let heat = SegmentHeat::from_bytes(record);
+                if heat.version == 0 {
+                    continue;
+                }
+                let segment_id = heat.segment_id as SegmentId;
+                if segment_id >= segment_nums {
+                    return_errno_with_msg!(InvalidArgs, "invalid segment heat record");
+                }
+                if heat.version > versions[segment_id] {
+                    segment_table[segment_id].apply_heat(&heat);
+                    versions[segment_id] = heat.version;
+                }
+            }
+        }
+        Ok((Some(segment_table), version))
+    }
+
+    /// Collect the counters and the write statistics of the segments changed
+    /// by the diffs of a TX, all of which are of a new version.
+    ///
+    /// The counters are the persisted ones advanced by the diffs, rather than
+    /// the ones in memory, which count the allocations of the TXs (and the
+    /// writes) in progress as well. Only called when GC is enabled.
+    fn collect_segment_diffs(
+        &self,
+        diffs: &BTreeMap<Hba, AllocDiff>,
+    ) -> Vec<(SegmentDiff, SegmentHeat)> {
+        let (Some(segment_table), Some(logged_segments)) =
+            (&self.segment_table, &self.logged_segments)
+        else {
+            return Vec::new();
+        };
+        // The versions follow the order of collections
+        let _bitmap = self.bitmap.lock();
+        let mut logged_segments = logged_segments.lock();
+        let segment_ids = Self::advance_segment_counters(&mut logged_segments, diffs, false);
+        if segment_ids.is_empty() {
+            return Vec::new();
+        }
+        let version = self.segment_version.fetch_add(1, Ordering::Relaxed) + 1;
+        segment_ids
+            .into_iter()
+            .map(|segment_id| {
+                let (valid_blocks, free_space) = logged_segments[segment_id];
+                let diff = SegmentDiff {
+                    version,
+                    segment_id: segment_id as _,
+                    valid_blocks: valid_blocks as _,
+                    free_space: free_space as _,
+                };
+                (diff, segment_table[segment_id].to_heat(version))
+            })
+            .collect()
+    }
+
+    /// Revert the persisted counters advanced by `collect_segment_diffs()`,
+    /// whose records fail to be persisted.
+    fn revert_segment_diffs(&self, diffs: &BTreeMap<Hba, AllocDiff>) {
+        if let Some(ref logged_segments) = self.logged_segments {
+            let _ = Self::advance_segment_counters(&mut logged_segments.lock(), diffs, true);
+        }
+    }
+
+    /// Advance the `(valid_blocks, free_space)` of the segments by the diffs,
+    /// or revert them if `revert`. Returns the IDs of the changed segments
+    /// in ascending order.
+    fn advance_segment_counters(
+        counters: &mut [(usize, usize)],
+        diffs: &BTreeMap<Hba, AllocDiff>,
+        revert: bool,
+    ) -> Vec<SegmentId> {
+        let sign = if revert { -1 } else { 1 };
+        let mut segment_ids: Vec<SegmentId> = Vec::new();
+        for (&hba, &diff) in diffs {
+            let segment_id = hba / SEGMENT_SIZE;
+            // The tail blocks belong to no segment
+            let Some((valid_blocks, free_space)) = counters.get_mut(segment_id) else {
+                continue;
+            };
+            let (valid_delta, free_delta) = match diff {
+                AllocDiff::Alloc => (0, -1),
+                AllocDiff::Dealloc => (-1, 1),
+                AllocDiff::Invalid => unreachable!(),
+            };
+            *valid_blocks = valid_blocks.saturating_add_signed(sign * valid_delta);
+            *free_space = free_space.saturating_add_signed(sign * free_delta);
+            if segment_ids.last() != Some(&segment_id) {
+                segment_ids.push(segment_id);
+            }
+        }
+        segment_ids
+    }
+
+    /// Returns the `(valid_blocks, free_space)` of each segment in memory,
+    /// `None` if GC is disabled.
+    fn snapshot_segments(segment_table: &Option<Vec<Segment>>) -> Option<Vec<(usize, usize)>> {
+        segment_table.as_ref().map(|segment_table| {
+            segment_table
+                .iter()
+                .map(|segment| (segment.num_valid_blocks(), segment.free_space()))
+                .collect()
+        })
+    }
+
+    /// Whether the `SEGD` logs are too many to be replayed on recovery,
+    /// so that they should be truncated by a compaction.
+    pub fn has_many_segment_diff_logs(&self) -> bool {
+        const MAX_SEGMENT_DIFF_LOGS: usize = 64;
+        self.num_segment_diff_logs.load(Ordering::Relaxed) >= MAX_SEGMENT_DIFF_LOGS
+    }
+
+    /// Install the bitmap recovered by the background thread of a lazy
+    /// recovery, then apply the deferred diffs and wake up the waiters.
+    fn finish_recovery(&self, recovered: Result<BitMap>) {
+        let lazy_recovery = self.lazy_recovery.as_ref().unwrap();
+        let mut state = lazy_recovery.state.lock().unwrap();
+        match recovered {
+            Ok(mut bitmap) => {
+                let mut num_free = self.num_free.lock().unwrap();
+                for (hba, diff) in state.deferred_diffs.drain(..) {
+                    let is_free = diff == AllocDiff::Dealloc;
+                    if bitmap[hba] == is_free {
                         continue;
                     }
-                    let bid = BlockId::from_bytes(&buf_slice[offset..offset + BID_SIZE]);
-                    offset += BID_SIZE;
-                    match diff {
-                        AllocDiff::Alloc => bitmap.set(bid, false),
-                        AllocDiff::Dealloc => bitmap.set(bid, true),
-                        _ => unreachable!(),
+                    bitmap.set(hba, is_free);
+                    if is_free && let Some(ref segment_table) = self.segment_table {
+                        segment_table[hba / SEGMENT_SIZE].mark_deallocated();
                     }
                 }
+                if let Some(ref segment_table) = self.segment_table {
+                    segment_table
+                        .iter()
+                        .for_each(|segment| segment.recount_free_space(&bitmap));
+                    let mut logged_segments = self.logged_segments.as_ref().unwrap().lock();
+                    for (counters, segment) in logged_segments.iter_mut().zip(segment_table) {
+                        counters.1 = segment.free_space();
+                    }
+                }
+                self.next_avail
+                    .store(bitmap.first_one(0).unwrap_or(0), Ordering::Release);
+                *num_free = bitmap.count_ones();
+                *self.bitmap.lock() = bitmap;
+                state.result = Some(Ok(()));
             }
-            let next_avail = bitmap.first_one(0).unwrap_or(0);
-            let num_free = bitmap.count_ones();
-            let bitmap_ref = Arc::new(Mutex::new(bitmap));
-            let segment_table = recover_segment_table_from_log(bitmap_ref.clone())?;
-            Ok(Self {
-                bitmap: bitmap_ref,
-                segment_table,
-                next_avail: AtomicUsize::new(next_avail),
-                nblocks,
-                is_dirty: AtomicBool::new(false),
-                cvar: Condvar::new(),
-                num_free: CvarMutex::new(num_free),
-            })
-        });
-        let recov_self = res.map_err(|_| {
-            tx.abort();
-            Error::with_msg(TxAborted, "recover block validity table TX aborted")
-        })?;
-        tx.commit()?;
+            Err(e) => {
+                #[cfg(not(feature = "linux"))]
+                error!("[AllocTable] Lazy recovery failed: {e:?}");
+                state.result = Some(Err(e));
+            }
+        }
+        lazy_recovery.cvar.notify_all();
+    }
 
-        Ok(recov_self)
+    /// Wait for the lazy recovery (if any) to finish, returns the error
+    /// if the recovery failed.
+    pub fn wait_for_recovery(&self) -> Result<()> {
+        let Some(lazy_recovery) = &self.lazy_recovery else {
+            return Ok(());
+        };
+        let mut state = lazy_recovery.state.lock().unwrap();
+        while state.result.is_none() {
+            state = lazy_recovery.cvar.wait(state).unwrap();
+        }
+        state.result.clone().unwrap()
+    }
+
+    /// Defer the diffs if the table is being recovered lazily, they are
+    /// applied once the recovery finishes. Returns whether deferred.
+    fn defer_diffs(&self, hbas: impl Iterator<Item = Hba>, diff: AllocDiff) -> bool {
+        let Some(lazy_recovery) = &self.lazy_recovery else {
+            return false;
+        };
+        let mut state = lazy_recovery.state.lock().unwrap();
+        if state.result.is_some() {
+            return false;
+        }
+        state.deferred_diffs.extend(hbas.map(|hba| (hba, diff)));
+        true
     }
 
     /// Persist the block validity table to `BVT` log. GC all existed `BAL` logs.
+    /// Nothing is persisted if the table is ephemeral.
     pub fn do_compaction<D: BlockSet + 'static>(&self, store: &Arc<TxLogStore<D>>) -> Result<()> {
+        if self.ephemeral {
+            return Ok(());
+        }
+        // The `BAL` logs being replayed can't be deleted
+        self.wait_for_recovery()?;
         if !self.is_dirty.load(Ordering::Relaxed) {
             return Ok(());
         }
@@ -306,27 +1083,33 @@ impl AllocTable {
             .map_err(|_| Error::with_msg(InvalidArgs, "serialize block validity table failed"))?
             .len();
         ser_buf.resize(align_up(ser_len, BLOCK_SIZE), 0);
-        drop(bitmap);
 
-        // Only serialize segment_table when GC is enabled
+        // Only serialize segment_table when GC is enabled, the whole table
+        // is of a new version which supersedes all the `SEGD` logs
+        let logged_segments = Self::snapshot_segments(&self.segment_table);
         let ser_seg_buf = if let Some(ref segment_table) = self.segment_table {
             let segment_table_len = segment_table.len();
             let mut buf = vec![0; Segment::ser_size() * segment_table_len];
-            let mut ser_len = 0;
             segment_table
                 .iter()
                 .enumerate()
                 .try_for_each(|(idx, segment)| {
                     let offset = idx * Segment::ser_size();
                     let segment_buf = &mut buf[offset..offset + Segment::ser_size()];
-                    ser_len += segment.to_slice(segment_buf)?;
+                    segment.to_slice(segment_buf)?;
                     Ok::<_, Error>(())
                 })?;
-            buf.resize(align_up(ser_len, BLOCK_SIZE), 0);
+            let version = self.segment_version.fetch_add(1, Ordering::Relaxed) + 1;
+            buf.extend_from_slice(version.as_bytes());
+            for segment in segment_table {
+                buf.extend_from_slice(segment.to_heat(version).as_bytes());
+            }
+            buf.resize(align_up(buf.len(), BLOCK_SIZE), 0);
             Some(buf)
         } else {
             None
         };
+        drop(bitmap);
 
         // Persist the serialized block validity table to `BVT` log
         // and GC any old `BVT` logs and `BAL` logs
@@ -345,6 +1128,16 @@ impl AllocTable {
                         store.delete_log(seg_log_id)?;
                     }
                 }
+                if let Ok(segd_log_ids) = store.list_logs_in(BUCKET_SEGMENT_DIFF_LOG) {
+                    for segd_log_id in segd_log_ids {
+                        store.delete_log(segd_log_id)?;
+                    }
+                }
+                if let Ok(segh_log_ids) = store.list_logs_in(BUCKET_SEGMENT_HEAT_LOG) {
+                    for segh_log_id in segh_log_ids {
+                        store.delete_log(segh_log_id)?;
+                    }
+                }
             }
 
             let bvt_log = store.create_log(BUCKET_BLOCK_VALIDITY_TABLE)?;
@@ -369,10 +1162,56 @@ impl AllocTable {
         }
         tx.commit()?;
 
+        if let (Some(logged), Some(snapshot)) = (&self.logged_segments, logged_segments) {
+            *logged.lock() = snapshot;
+        }
+        self.num_segment_diff_logs.store(0, Ordering::Relaxed);
         self.is_dirty.store(false, Ordering::Relaxed);
+        self.logged_diff_bytes.store(0, Ordering::Relaxed);
+        self.has_unlogged_frees.store(false, Ordering::Relaxed);
         Ok(())
     }
 
+    /// Mark the blocks allocated, if not yet. It's called on recovery with
+    /// the blocks of the records replayed from the WAL of the logical block
+    /// table, whose allocations are persisted by no `BAL` log, but by the
+    /// compaction of the table, which may be skipped on the last syncs.
+    ///
+    /// The segment counters are left as recovered, they're hints to GC only.
+    pub fn mark_allocated(&self, hbas: &[Hba]) {
+        if self.defer_diffs(hbas.iter().copied(), AllocDiff::Alloc) {
+            return;
+        }
+        let mut num_free = self.num_free.lock().unwrap();
+        let mut bitmap = self.bitmap.lock();
+        for &hba in hbas {
+            if bitmap[hba] {
+                bitmap.set(hba, false);
+                *num_free -= 1;
+            }
+        }
+        let _ = self
+            .is_dirty
+            .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    /// Returns the number of bytes of the `BAL` records written since
+    /// the last compaction.
+    pub fn logged_diff_bytes(&self) -> usize {
+        self.logged_diff_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the size (in bytes) of the bitmap persisted by a compaction.
+    pub fn compacted_size(&self) -> usize {
+        align_up(self.nblocks.get().div_ceil(8), BLOCK_SIZE)
+    }
+
+    /// Whether the next compaction can't be skipped, as blocks are freed
+    /// without any `BAL` record since the last one.
+    pub fn has_unlogged_frees(&self) -> bool {
+        self.has_unlogged_frees.load(Ordering::Relaxed)
+    }
+
     // Migrate a batch of blocks to another segment.
     // the blocks has been marked as allocated before, so the total num_free will not be decreased
     // Note: This function is only called when GC is enabled
@@ -393,8 +1232,18 @@ impl AllocTable {
 
     /// Mark a specific slot deallocated.
     pub fn set_deallocated(&self, nth: usize) {
+        if self.defer_diffs(core::iter::once(nth), AllocDiff::Dealloc) {
+            return;
+        }
         let mut num_free = self.num_free.lock().unwrap();
-        self.bitmap.lock().set(nth, true);
+        let mut bitmap = self.bitmap.lock();
+        // Freed already, e.g., by the replay of an overwrite whose
+        // allocation is not persisted
+        if bitmap[nth] {
+            return;
+        }
+        bitmap.set(nth, true);
+        drop(bitmap);
 
         // Only update segment_table when GC is enabled
         if let Some(ref segment_table) = self.segment_table {
@@ -414,6 +1263,7 @@ impl AllocTable {
     // Note: This function is only called when GC is enabled
     pub fn clear_segment(&self, segment_id: SegmentId, discard_count: usize) {
         *self.num_free.lock().unwrap() += discard_count;
+        self.has_unlogged_frees.store(true, Ordering::Relaxed);
         let mut bitmap = self.bitmap.lock();
         let begin_hba = segment_id * SEGMENT_SIZE;
         let end_hba = begin_hba + SEGMENT_SIZE;
@@ -425,10 +1275,69 @@ impl AllocTable {
         }
     }
 
+    /// Undo the migrations of the victim blocks whose records are not remapped
+    /// (e.g., the remapping fails midway), given as `(old_hba, new_hba)`.
+    /// The old blocks are still referenced, they're allocated again after
+    /// `clear_segment()`, while the new ones are free again.
+    /// Note: This function is only called when GC is enabled
+    pub fn undo_migrations(&self, blocks: &[(Hba, Hba)]) {
+        let mut bitmap = self.bitmap.lock();
+        for &(old_hba, new_hba) in blocks {
+            if bitmap[old_hba] {
+                bitmap.set(old_hba, false);
+                if let Some(ref segment_table) = self.segment_table {
+                    segment_table[old_hba / SEGMENT_SIZE].mark_alloc();
+                }
+            }
+            bitmap.set(new_hba, true);
+        }
+        let _ = self
+            .is_dirty
+            .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
     /// Get reference to segment_table for GC, returns None if GC is disabled
     pub fn get_segment_table_ref(&self) -> Option<&[Segment]> {
         self.segment_table.as_deref()
     }
+
+    /// Returns the total number of blocks.
+    pub fn nblocks(&self) -> usize {
+        self.nblocks.get()
+    }
+
+    /// Fence the free blocks in `range` off any allocation (e.g., as the
+    /// targets of GC migrations), returns the fenced blocks. Only the bitmap
+    /// is changed, the fenced blocks must be released by `unfence()` before
+    /// the table is persisted.
+    pub fn fence(&self, range: Range<Hba>) -> Vec<Hba> {
+        let mut bitmap = self.bitmap.lock();
+        let fenced: Vec<_> = range.filter(|hba| bitmap[*hba]).collect();
+        fenced.iter().for_each(|hba| bitmap.set(*hba, false));
+        fenced
+    }
+
+    /// Release the blocks fenced by `fence()`. The table is compacted on the
+    /// next sync, in case the fenced blocks are persisted as allocated.
+    pub fn unfence(&self, hbas: &[Hba]) {
+        let mut bitmap = self.bitmap.lock();
+        hbas.iter().for_each(|hba| bitmap.set(*hba, true));
+        self.is_dirty.store(true, Ordering::Relaxed);
+        self.has_unlogged_frees.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns the fraction of allocated blocks.
+    pub fn utilization(&self) -> f64 {
+        let num_free = *self.num_free.lock().unwrap();
+        1.0 - num_free as f64 / self.nblocks.get() as f64
+    }
+
+    /// Returns the total number of blocks allocated for user writes since the
+    /// table is created, which continues from the last writes of the segments
+    /// on recovery.
+    pub fn write_seq(&self) -> u64 {
+        self.write_seq.load(Ordering::Relaxed)
+    }
 }
 
 impl<D: BlockSet + 'static> BlockAlloc<D> {
@@ -453,8 +1362,20 @@ impl<D: BlockSet + 'static> BlockAlloc<D> {
         Ok(())
     }
 
+    /// Record a diff of `Alloc` of a block shared by several records (see
+    /// `RefCountTable`), which may be recorded once for each of them.
+    pub fn alloc_shared_block(&self, block_id: Hba) -> Result<()> {
+        self.diff_table.lock().insert(block_id, AllocDiff::Alloc);
+        Ok(())
+    }
+
     /// Record a diff of `Dealloc`.
     pub fn dealloc_block(&self, block_id: Hba) -> Result<()> {
+        // The block is dropped by shrinking the disk, after it's evacuated,
+        // only an out-of-date record refers to it
+        if block_id >= self.alloc_table.nblocks() {
+            return Ok(());
+        }
         let mut diff_table = self.diff_table.lock();
         let replaced = diff_table.insert(block_id, AllocDiff::Dealloc);
         debug_assert!(
@@ -475,17 +1396,25 @@ impl<D: BlockSet + 'static> BlockAlloc<D> {
     }
 
     /// Persist the metadata in diff table to the block validity diff log.
+    /// Nothing is persisted if the table is ephemeral.
     ///
     /// # Panics
     ///
     /// This method must be called within a TX. Otherwise, this method panics.
     pub fn update_diff_log(&self) -> Result<()> {
+        if self.alloc_table.ephemeral {
+            return Ok(());
+        }
         let diff_table = self.diff_table.lock();
+        self.update_segment_diff_log(&diff_table)?;
         if diff_table.is_empty() {
             return Ok(());
         }
 
         let diff_log = self.store.create_log(BUCKET_BLOCK_ALLOC_LOG)?;
+        self.alloc_table
+            .logged_diff_bytes
+            .fetch_add(diff_table.len() * DIFF_RECORD_SIZE, Ordering::Relaxed);
 
         const MAX_BUF_SIZE: usize = 1024 * BLOCK_SIZE;
         let mut diff_buf = Vec::with_capacity(MAX_BUF_SIZE);
@@ -507,10 +1436,54 @@ impl<D: BlockSet + 'static> BlockAlloc<D> {
         diff_log.append(BufRef::try_from(&diff_buf[..]).unwrap())
     }
 
+    /// Persist the counters of the segments changed by the diffs of this TX
+    /// to a `SEGD` log, and their write statistics to a `SEGH` log, so that
+    /// they survive a crash before the next `AllocTable::do_compaction()`.
+    ///
+    /// # Panics
+    ///
+    /// This method must be called within a TX. Otherwise, this method panics.
+    fn update_segment_diff_log(&self, diff_table: &BTreeMap<Hba, AllocDiff>) -> Result<()> {
+        let diffs = self.alloc_table.collect_segment_diffs(diff_table);
+        if diffs.is_empty() {
+            return Ok(());
+        }
+
+        let mut diff_buf = Vec::with_capacity(diffs.len() * size_of::<SegmentDiff>());
+        let mut heat_buf = Vec::with_capacity(diffs.len() * size_of::<SegmentHeat>());
+        for (diff, heat) in &diffs {
+            diff_buf.extend_from_slice(diff.as_bytes());
+            heat_buf.extend_from_slice(heat.as_bytes());
+        }
+        diff_buf.resize(align_up(diff_buf.len(), BLOCK_SIZE), 0);
+        heat_buf.resize(align_up(heat_buf.len(), BLOCK_SIZE), 0);
+        let res = self
+            .store
+            .create_log(BUCKET_SEGMENT_DIFF_LOG)
+            .and_then(|diff_log| diff_log.append(BufRef::try_from(&diff_buf[..]).unwrap()))
+            .and_then(|_| self.store.create_log(BUCKET_SEGMENT_HEAT_LOG))
+            .and_then(|heat_log| heat_log.append(BufRef::try_from(&heat_buf[..]).unwrap()));
+        if res.is_err() {
+            self.alloc_table.revert_segment_diffs(diff_table);
+            return res;
+        }
+        self.alloc_table
+            .num_segment_diff_logs
+            .fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
     /// Update the metadata in diff table to the in-memory block validity table.
     pub fn update_alloc_table(&self) {
         let diff_table = self.diff_table.lock();
         let alloc_table = &self.alloc_table;
+        let deallocs = diff_table
+            .iter()
+            .filter(|(_, block_diff)| **block_diff == AllocDiff::Dealloc)
+            .map(|(block_id, _)| *block_id);
+        if alloc_table.defer_diffs(deallocs, AllocDiff::Dealloc) {
+            return;
+        }
         let mut num_free = alloc_table.num_free.lock().unwrap();
         let mut bitmap = alloc_table.bitmap.lock();
         let mut num_dealloc = 0_usize;
@@ -522,6 +1495,14 @@ impl<D: BlockSet + 'static> BlockAlloc<D> {
                 AllocDiff::Dealloc => {
                     debug_assert!(!bitmap[*block_id]);
                     bitmap.set(*block_id, true);
+                    // As the counters persisted by the `SEGD` log of this TX
+                    if let Some(segment) = alloc_table
+                        .segment_table
+                        .as_ref()
+                        .and_then(|segment_table| segment_table.get(*block_id / SEGMENT_SIZE))
+                    {
+                        segment.mark_deallocated();
+                    }
                     num_dealloc += 1;
                 }
                 AllocDiff::Invalid => unreachable!(),
@@ -548,22 +1529,28 @@ impl From<u8> for AllocDiff {
 
 #[cfg(test)]
 mod tests {
+    use crate::layers::bio::MemDisk;
     use crate::layers::disk::{
-        block_alloc::AllocTable, config::Config, segment::SEGMENT_SIZE, sworndisk::CONFIG,
+        block_alloc::{AllocAlignStats, AllocTable, BlockAlloc},
+        config::{AllocPolicy, Config},
+        segment::SEGMENT_SIZE,
+        sworndisk::Hba,
     };
+    use crate::layers::log::TxLogStore;
+    use crate::prelude::*;
+    use crate::{AeadKey, RandomInit};
     use core::num::NonZeroUsize;
 
-    fn setup_gc_enabled() {
-        CONFIG.set(Config {
+    fn gc_config() -> Config {
+        Config {
             enable_gc: true,
             ..Default::default()
-        });
+        }
     }
 
     #[test]
     fn test_alloc_table() {
-        setup_gc_enabled();
-        let alloc_table = AllocTable::new(NonZeroUsize::new(1024).unwrap());
+        let alloc_table = AllocTable::new(NonZeroUsize::new(1024).unwrap(), &gc_config());
         let segment_table = alloc_table.segment_table.as_ref().unwrap();
         assert_eq!(alloc_table.alloc(), Some(0));
         assert_eq!(alloc_table.alloc(), Some(1));
@@ -580,8 +1567,7 @@ mod tests {
 
     #[test]
     fn test_alloc_table_batch() {
-        setup_gc_enabled();
-        let alloc_table = AllocTable::new(NonZeroUsize::new(1024).unwrap());
+        let alloc_table = AllocTable::new(NonZeroUsize::new(1024).unwrap(), &gc_config());
         let segment_table = alloc_table.segment_table.as_ref().unwrap();
         let hbas = alloc_table
             .alloc_batch(NonZeroUsize::new(1024).unwrap())
@@ -590,7 +1576,8 @@ mod tests {
         assert!(segment_table[0].num_valid_blocks() == 1024);
         assert_eq!(segment_table[0].free_space(), 0);
 
-        let alloc_table = AllocTable::new(NonZeroUsize::new(4 * SEGMENT_SIZE).unwrap());
+        let alloc_table =
+            AllocTable::new(NonZeroUsize::new(4 * SEGMENT_SIZE).unwrap(), &gc_config());
         let segment_table = alloc_table.segment_table.as_ref().unwrap();
         let hbas = alloc_table
             .alloc_batch(NonZeroUsize::new(SEGMENT_SIZE + 2).unwrap())
@@ -605,8 +1592,8 @@ mod tests {
         assert_eq!(segment_table[1].num_valid_blocks(), 1023);
         assert_eq!(segment_table[1].free_space(), 1023);
 
-        setup_gc_enabled();
-        let alloc_table = AllocTable::new(NonZeroUsize::new(200 * SEGMENT_SIZE).unwrap());
+        let alloc_table =
+            AllocTable::new(NonZeroUsize::new(200 * SEGMENT_SIZE).unwrap(), &gc_config());
         let segment_table = alloc_table.segment_table.as_ref().unwrap();
         let hbas = alloc_table
             .alloc_batch(NonZeroUsize::new(100 * SEGMENT_SIZE + 2).unwrap())
@@ -619,4 +1606,205 @@ mod tests {
         assert_eq!(segment_table[100].num_valid_blocks(), 1024);
         assert_eq!(segment_table[100].free_space(), 1022);
     }
+
+    #[test]
+    fn test_alloc_table_alignment() {
+        let config = Config {
+            alloc_alignment: 64,
+            ..Default::default()
+        };
+        let alloc_table = AllocTable::new(NonZeroUsize::new(1024).unwrap(), &config);
+        assert_eq!(alloc_table.alloc(), Some(0));
+        // A large allocation skips to the next aligned boundary
+        let hbas = alloc_table
+            .alloc_batch(NonZeroUsize::new(100).unwrap())
+            .unwrap();
+        assert_eq!(hbas, (64..164).collect::<Vec<_>>());
+        // A small one is not aligned
+        let hbas = alloc_table
+            .alloc_batch(NonZeroUsize::new(10).unwrap())
+            .unwrap();
+        assert_eq!(hbas[0], 164);
+        // No aligned extent is large enough, fall back to the linear scan
+        let hbas = alloc_table
+            .alloc_batch(NonZeroUsize::new(840).unwrap())
+            .unwrap();
+        assert_eq!(hbas.len(), 840);
+        assert_eq!(
+            alloc_table.align_stats(),
+            AllocAlignStats {
+                num_large: 2,
+                num_aligned: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_alloc_table_segment_fill() {
+        let mut alloc_table =
+            AllocTable::new(NonZeroUsize::new(4 * SEGMENT_SIZE).unwrap(), &gc_config());
+        alloc_table.alloc_policy = AllocPolicy::SegmentFill;
+
+        // User writes open segment 0
+        let hbas = alloc_table
+            .alloc_batch(NonZeroUsize::new(10).unwrap())
+            .unwrap();
+        assert_eq!(hbas, (0..10).collect::<Vec<_>>());
+
+        // GC migrations open another clean segment, never the victim
+        let targets = alloc_table.pick_migration_targets(5, 1).unwrap();
+        assert_eq!(
+            targets,
+            (2 * SEGMENT_SIZE..2 * SEGMENT_SIZE + 5).collect::<Vec<_>>()
+        );
+        alloc_table.migrate_batch(&targets);
+
+        // Free a block in the middle, it is filled before opening a new segment
+        alloc_table.set_deallocated(5);
+        let hbas = alloc_table
+            .alloc_batch(NonZeroUsize::new(SEGMENT_SIZE - 8).unwrap())
+            .unwrap();
+        assert_eq!(hbas[0], 5);
+        assert_eq!(
+            hbas[1..SEGMENT_SIZE - 9],
+            (10..SEGMENT_SIZE).collect::<Vec<_>>()
+        );
+        assert_eq!(*hbas.last().unwrap(), SEGMENT_SIZE);
+
+        // Segment 2 is open by GC, user writes go to segment 3 after segment 1 is filled
+        let hbas = alloc_table
+            .alloc_batch(NonZeroUsize::new(SEGMENT_SIZE).unwrap())
+            .unwrap();
+        assert_eq!(hbas[SEGMENT_SIZE - 2], 2 * SEGMENT_SIZE - 1);
+        assert_eq!(hbas[SEGMENT_SIZE - 1], 3 * SEGMENT_SIZE);
+    }
+
+    #[test]
+    fn test_alloc_table_reserve() {
+        let mut alloc_table =
+            AllocTable::new(NonZeroUsize::new(2 * SEGMENT_SIZE).unwrap(), &gc_config());
+        alloc_table.reserved_nblocks = SEGMENT_SIZE / 8;
+
+        let nblocks = 2 * SEGMENT_SIZE - SEGMENT_SIZE / 8;
+        let hbas = alloc_table
+            .alloc_batch(NonZeroUsize::new(nblocks).unwrap())
+            .unwrap();
+        assert_eq!(hbas.len(), nblocks);
+
+        // User allocations can't use the reserve
+        let err = alloc_table
+            .alloc_batch(NonZeroUsize::new(1).unwrap())
+            .unwrap_err();
+        assert_eq!(err.errno(), NoSpaceLeft);
+        assert_eq!(alloc_table.alloc(), None);
+
+        alloc_table.set_deallocated(0);
+        assert!(alloc_table
+            .alloc_batch(NonZeroUsize::new(1).unwrap())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_alloc_table_segment_diff_recovery() -> Result<()> {
+        let nblocks = NonZeroUsize::new(4 * SEGMENT_SIZE).unwrap();
+        let store = Arc::new(TxLogStore::format(
+            MemDisk::create(16 * SEGMENT_SIZE)?,
+            AeadKey::random(),
+        )?);
+        let alloc_table = Arc::new(AllocTable::new(nblocks, &gc_config()));
+
+        // Allocate and deallocate blocks, then persist the diffs in a TX
+        // as a compaction TX does
+        let persist_diffs = |allocs: &[Hba], deallocs: &[Hba]| -> Result<()> {
+            let block_alloc = BlockAlloc::new(alloc_table.clone(), store.clone());
+            for hba in allocs {
+                block_alloc.alloc_block(*hba)?;
+            }
+            for hba in deallocs {
+                alloc_table.set_deallocated(*hba);
+                block_alloc.dealloc_block(*hba)?;
+            }
+            let mut tx = store.new_tx();
+            tx.context(|| block_alloc.update_diff_log())?;
+            tx.commit()
+        };
+        let check_recovered = || -> Result<()> {
+            let recovered = AllocTable::recover(nblocks, &store, &gc_config())?;
+            let segment_table = alloc_table.get_segment_table_ref().unwrap();
+            let recovered_table = recovered.get_segment_table_ref().unwrap();
+            for (segment, recovered_segment) in segment_table.iter().zip(recovered_table) {
+                assert_eq!(
+                    recovered_segment.num_valid_blocks(),
+                    segment.num_valid_blocks()
+                );
+                assert_eq!(recovered_segment.free_space(), segment.free_space());
+                assert_eq!(recovered_segment.last_write(), segment.last_write());
+                assert_eq!(recovered_segment.write_count(), segment.write_count());
+                // The same as a fresh scan of the recovered bitmap
+                assert_eq!(
+                    recovered_segment.free_space(),
+                    recovered_segment.find_all_free_blocks().len()
+                );
+            }
+            assert_eq!(recovered.write_seq(), alloc_table.write_seq());
+            Ok(())
+        };
+
+        let hbas = alloc_table.alloc_batch(NonZeroUsize::new(SEGMENT_SIZE + 500).unwrap())?;
+        let deallocs: Vec<_> = hbas.iter().step_by(3).copied().collect();
+        persist_diffs(&hbas, &deallocs)?;
+        check_recovered()?;
+
+        // The counters persisted after the segment table supersede it
+        alloc_table.get_segment_table_ref().unwrap()[0].decay_write_count(0.5);
+        alloc_table.do_compaction(&store)?;
+        check_recovered()?;
+        let hbas = alloc_table.alloc_batch(NonZeroUsize::new(SEGMENT_SIZE).unwrap())?;
+        persist_diffs(&hbas, &hbas[..100])?;
+        check_recovered()?;
+        persist_diffs(&[], &hbas[100..200])?;
+        check_recovered()
+    }
+
+    #[test]
+    fn test_alloc_table_segment_diff_concurrent_crash() -> Result<()> {
+        let nblocks = NonZeroUsize::new(4 * SEGMENT_SIZE).unwrap();
+        let store = Arc::new(TxLogStore::format(
+            MemDisk::create(16 * SEGMENT_SIZE)?,
+            AeadKey::random(),
+        )?);
+        let alloc_table = Arc::new(AllocTable::new(nblocks, &gc_config()));
+
+        // A TX persists its allocations, while a concurrent write allocates
+        // blocks in the same segments whose TX never commits before a crash
+        let logged = alloc_table.alloc_batch(NonZeroUsize::new(SEGMENT_SIZE / 2).unwrap())?;
+        let in_flight = alloc_table.alloc_batch(NonZeroUsize::new(SEGMENT_SIZE).unwrap())?;
+        let block_alloc = BlockAlloc::new(alloc_table.clone(), store.clone());
+        for hba in &logged {
+            block_alloc.alloc_block(*hba)?;
+        }
+        let mut tx = store.new_tx();
+        tx.context(|| block_alloc.update_diff_log())?;
+        tx.commit()?;
+        store.sync()?;
+
+        let recovered = AllocTable::recover(nblocks, &store, &gc_config())?;
+        let segment_table = alloc_table.get_segment_table_ref().unwrap();
+        let recovered_table = recovered.get_segment_table_ref().unwrap();
+        for (segment, recovered_segment) in segment_table.iter().zip(recovered_table) {
+            // The counters agree with a scan of the recovered bitmap
+            let num_free = recovered_segment.find_all_free_blocks().len();
+            assert_eq!(recovered_segment.free_space(), num_free);
+            assert!(recovered_segment.num_valid_blocks() >= recovered_segment.nblocks() - num_free);
+            // The allocations in flight are not counted
+            let lower = segment.segment_id() * SEGMENT_SIZE;
+            let range = lower..lower + segment.nblocks();
+            let num_in_flight = in_flight.iter().filter(|hba| range.contains(hba)).count();
+            assert_eq!(
+                recovered_segment.free_space(),
+                segment.free_space() + num_in_flight
+            );
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,152 @@
+//! Statistics of the two cache tiers, shared by all the caches of a tier.
+
+use super::cost_stats::STATS_ENABLED;
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The tiers of caches.
+///
+/// The upper tier caches the decoded record blocks of SSTs, which is only
+/// used with `Config::two_level_caching`. The lower tier caches the decrypted
+/// blocks of TX logs, i.e., the MHT nodes, and the data blocks as well
+/// if the upper tier is not used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheTier {
+    /// Record blocks of SSTs, sized by `Config::sst_cache_size`.
+    SstBlock = 0,
+    /// Decrypted blocks of TX logs, sized by `Config::block_cache_size`.
+    LogBlock = 1,
+}
+
+/// Statistics of the cache tiers.
+pub struct CacheStats {
+    tiers: [TierStats; 2],
+}
+
+struct TierStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// A snapshot of the statistics of a cache tier.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheTierSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl CacheTier {
+    pub fn iter() -> impl Iterator<Item = (CacheTier, &'static str)> {
+        [(Self::SstBlock, "SST block"), (Self::LogBlock, "Log block")].into_iter()
+    }
+}
+
+impl CacheStats {
+    /// Create a new CacheStats instance
+    pub const fn new() -> Self {
+        Self {
+            tiers: [TierStats::new(), TierStats::new()],
+        }
+    }
+
+    /// Record a lookup of the tier, whether it hits or not.
+    pub fn record_lookup(&self, tier: CacheTier, hit: bool) {
+        if !STATS_ENABLED {
+            return;
+        }
+        let stats = &self.tiers[tier as usize];
+        if hit {
+            stats.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            stats.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record an entry evicted from the tier for its capacity.
+    pub fn record_eviction(&self, tier: CacheTier) {
+        if !STATS_ENABLED {
+            return;
+        }
+        self.tiers[tier as usize]
+            .evictions
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_stats(&self, tier: CacheTier) -> CacheTierSnapshot {
+        let stats = &self.tiers[tier as usize];
+        CacheTierSnapshot {
+            hits: stats.hits.load(Ordering::Relaxed),
+            misses: stats.misses.load(Ordering::Relaxed),
+            evictions: stats.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reset all statistics
+    pub fn reset(&self) {
+        for stats in &self.tiers {
+            stats.hits.store(0, Ordering::Relaxed);
+            stats.misses.store(0, Ordering::Relaxed);
+            stats.evictions.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Print statistics to `sink`
+    pub fn print(&self, sink: &mut dyn Write) -> fmt::Result {
+        writeln!(
+            sink,
+            "=================== Cache Statistics ==================="
+        )?;
+        for (tier, name) in CacheTier::iter() {
+            let stats = self.get_stats(tier);
+            writeln!(
+                sink,
+                "  {:<10} hits: {}, misses: {}, hit rate: {:.2}%, evictions: {}",
+                name,
+                stats.hits,
+                stats.misses,
+                stats.hit_rate() * 100.0,
+                stats.evictions
+            )?;
+        }
+        writeln!(
+            sink,
+            "========================================================"
+        )
+    }
+}
+
+impl TierStats {
+    const fn new() -> Self {
+        Self {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+}
+
+impl CacheTierSnapshot {
+    /// The fraction of the lookups that hit, zero if there is no lookup.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / lookups as f64
+    }
+
+    /// The statistics since the `earlier` snapshot.
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            hits: self.hits - earlier.hits,
+            misses: self.misses - earlier.misses,
+            evictions: self.evictions - earlier.evictions,
+        }
+    }
+}
+
+// Global cache statistics
+pub static CACHE_STATS: CacheStats = CacheStats::new();
@@ -1,39 +1,452 @@
-use super::gc::{GreedyVictimPolicy, VictimPolicy, VictimPolicyRef};
-use crate::os::Arc;
+use super::cache_stats::CacheTier;
+use super::corruption::CorruptionHandlerRef;
+use super::cost_stats::CostClock;
+use super::freshness::TrustedCounterRef;
+use super::gc::{
+    GenerationalVictimPolicy, GreedyVictimPolicy, LazyGreedyVictimPolicy, LoopScanVictimPolicy,
+    VictimPolicy, VictimPolicyRef, WindowGreedyVictimPolicy,
+};
+use super::mem_budget::{CacheRatios, MemBudget};
+use super::rate_limit::{BackgroundIoLimit, RateLimit};
+use crate::layers::lsm::{CompactionPolicyKind, LsmParams};
+use crate::os::{AeadBackendRef, Arc};
+use crate::prelude::*;
+use core::str::FromStr;
+use core::time::Duration;
 use core::usize;
 
+/// The base of the disk layout fractions in `Config`.
+pub const LAYOUT_FRACTION_BASE: usize = 1024;
+
 #[derive(Clone)]
 pub struct Config {
+    /// The total size (in bytes) of `DataBuf` and the cache tiers, split among
+    /// them by `cache_ratios` (see `MemBudget`), `usize::MAX` for the default
+    /// capacities.
     pub cache_size: usize,
+    /// The shares of `cache_size` taken by `DataBuf` and the cache tiers.
+    pub cache_ratios: CacheRatios,
+    /// Whether to cache in two tiers, i.e., the decoded record blocks of SSTs
+    /// above the decrypted blocks of TX logs. Only the latter is used if not.
+    /// See `CacheTier`.
     pub two_level_caching: bool,
+    /// The size (in bytes) of the cache tier of SST record blocks, taken out
+    /// of `cache_size`, its share of `cache_size` if `None`.
+    pub sst_cache_size: Option<usize>,
+    /// The size (in bytes) of the cache tier of decrypted TX log blocks, taken
+    /// out of `cache_size`, its share of `cache_size` if `None`.
+    pub block_cache_size: Option<usize>,
     pub delayed_reclamation: bool,
     pub stat_waf: bool,
     pub stat_cost: bool,
+    /// The clock source of the cost statistics, which are global, so the
+    /// clock is switched (and the timed costs are reset) by the disks with
+    /// `stat_cost` on. See `CostClock`.
+    pub cost_clock: CostClock,
+    /// Whether to persist the WAF and cost statistics on sync and
+    /// restore them on open, so that they accumulate across restarts.
+    pub persist_stats: bool,
+    /// Whether to record the statistics of the disk (see `SwornDisk::stats()`)
+    /// to the global ones as well, e.g., `WAF_STATS` and `BIO_STATS`.
+    pub aggregate_stats: bool,
     pub enable_gc: bool,
+    /// The percentage of the user data area reserved for GC (over-provisioning),
+    /// user writes fail with `NoSpaceLeft` rather than using the reserve.
+    /// Only takes effect if GC is enabled.
+    pub over_provisioning: usize,
+    /// Whether to defragment user data while the foreground is idle, i.e.,
+    /// migrate blocks so that logically adjacent LBAs are physically adjacent.
+    /// Only takes effect if GC is enabled.
+    pub enable_defrag: bool,
+    /// Whether GC re-encrypts the migrated blocks with fresh keys rather than
+    /// moving their ciphertext verbatim, so that a compromised key of a block
+    /// no longer decrypts it once migrated. The cost is tracked as
+    /// `CostL3Type::Encryption`. Only takes effect if GC is enabled, and only
+    /// under `BlockCryptoMode::RandomKey`, as all the blocks share the data
+    /// key under `BlockCryptoMode::PerBlockNonce`.
+    pub reencrypt_on_gc: bool,
     pub victim_policy: Option<VictimPolicyRef>,
+    /// The built-in victim policy, ignored if `victim_policy` is set.
+    pub victim_policy_kind: VictimPolicyKind,
     pub sync_atomicity: bool,
+    /// Whether `SwornDisk::open()` returns before the block validity table is
+    /// recovered, which is then recovered in the background to cut mount time.
+    /// Block allocations wait for the recovery, while reads don't.
+    pub lazy_recovery: bool,
+    /// Whether the disk is an encrypted swap area whose data need not survive
+    /// a restart, set by `SwornDisk::create_ephemeral()`. The keys are never
+    /// persisted, and neither the WAL records nor the block validity table
+    /// are written, so `sync()` only drains the buffered blocks. Such a disk
+    /// is reformatted rather than recovered on `SwornDisk::open()`.
+    pub ephemeral: bool,
+    /// The number of blocks from which a discard is issued at once, e.g.,
+    /// `SEGMENT_SIZE`. The smaller discards are queued and merged with the
+    /// adjacent ones, then issued once merged up to it, before their blocks
+    /// are accessed, on sync, or while idle. Zero issues every discard at once.
+    pub discard_granularity: usize,
+    /// When the block validity table is compacted, i.e., persisted as a whole
+    /// with its `BAL` logs deleted. Recovery replays the `BAL` logs written
+    /// since the last compaction.
+    pub bvt_compaction: BvtCompactionPolicy,
+    /// The fraction of the disk for the logical block table, in units of
+    /// `1 / LAYOUT_FRACTION_BASE`. Only takes effect on `SwornDisk::create()`.
+    pub index_fraction: usize,
+    /// The fraction of the disk for the reverse index table, in units of
+    /// `1 / LAYOUT_FRACTION_BASE`. No space is reserved if GC is disabled.
+    /// Only takes effect on `SwornDisk::create()`.
+    ///
+    /// The user data takes the rest of the disk.
+    pub reverse_index_fraction: usize,
+    /// How the reverse index table is stored in its part of the disk.
+    /// Only takes effect on `SwornDisk::create()`, and only if GC is enabled.
+    pub reverse_index_kind: ReverseIndexKind,
+    /// The capacity (in records) of each MemTable and SST of the logical
+    /// block table and the reverse index table, the default if `None`.
+    /// Only takes effect on `SwornDisk::create()`, as do the LSM parameters below.
+    pub memtable_capacity: Option<usize>,
+    /// The size (in bytes) of the record blocks of SSTs, a multiple of
+    /// `BLOCK_SIZE`, the default if `None`.
+    pub sst_block_size: Option<usize>,
+    /// The number of SSTs at L0 that triggers a major compaction,
+    /// the default if `None`.
+    pub lsm_level0_ratio: Option<u16>,
+    /// The size ratio between adjacent LSM levels from L1 on,
+    /// the default if `None`.
+    pub lsm_level_ratio: Option<u16>,
+    /// How the LSM trees compact their SSTs.
+    pub compaction_policy: CompactionPolicyKind,
+    /// The size (in blocks) of a group of WAL records of the LSM trees, which
+    /// are encrypted and appended to the WAL together once the group is full.
+    pub wal_group_blocks: usize,
+    /// The maximum time a WAL record waits in its group before the group is
+    /// appended, checked upon appends. Only bounded by the size if `None`.
+    pub wal_group_timeout: Option<Duration>,
+    /// Checkpoint the mutable MemTable of each LSM tree upon a sync once this
+    /// much time has passed since its last checkpoint, so that recovery only
+    /// replays the WAL after it. No timed checkpoint if `None`.
+    pub checkpoint_interval: Option<Duration>,
+    /// Checkpoint the mutable MemTable of each LSM tree upon a sync once its
+    /// WAL since the last checkpoint reaches this many bytes. No checkpoint
+    /// by the WAL size if `None`.
+    pub checkpoint_dirty_bytes: Option<usize>,
+    /// Whether the user data blocks are flushed to the device before their
+    /// records are inserted into the index, so that no record surviving a
+    /// crash refers to a block which never reached the device (the WAL and
+    /// the compactions may persist records ahead of the next sync).
+    ///
+    /// It costs a device flush per flush of the data buffer, per chunk of
+    /// direct writes and per batch of GC migration, which lowers the write
+    /// throughput, especially with a small data buffer or a slow flush.
+    /// Without it, such a record is only detected by the MAC check on reads.
+    pub ordered_data_writes: bool,
+    /// Writes of at least this many blocks bypass the data buffer and are
+    /// written to disk directly in bounded chunks. `usize::MAX` disables it.
+    pub direct_write_threshold: usize,
+    /// The maximum number of blocks of a request merged from the adjacent
+    /// queued read (or write) requests at contiguous LBAs. No merging if
+    /// it's no more than one.
+    pub bio_merge_window: usize,
+    /// The budget of the block I/O requests submitted by each tenant
+    /// (see `BioTenant`), no rate limiting if `None`. Only supported
+    /// with `std`, which keeps the time for the token buckets.
+    pub rate_limit: Option<RateLimit>,
+    /// The I/O budget shared by GC migration and defragmentation, so that
+    /// they don't starve the foreground I/Os on slow devices. Background
+    /// I/Os run at full speed if `None`. The bandwidth is only supported
+    /// with `std`, as `rate_limit` is.
+    pub background_io_limit: Option<BackgroundIoLimit>,
+    /// The time a foreground read or write may wait for background GC before
+    /// it's regarded as hung, upon which the states of GC, compaction and the
+    /// queues are dumped to the log. No hang detection if `None`. Only timed
+    /// with `std`, otherwise the wait is never regarded as hung.
+    pub hang_timeout: Option<Duration>,
+    /// Whether a hung read or write fails with `Errno::Timeout` after the
+    /// dump, rather than keeps waiting.
+    pub fail_on_hang: bool,
+    /// How free blocks are chosen for new writes.
+    pub alloc_policy: AllocPolicy,
+    /// The alignment (in blocks) of the allocations of at least this many
+    /// blocks, e.g., `SEGMENT_SIZE` or the stripe size of a `StripedDisk`.
+    /// Such a large write starts on an aligned boundary if there is an aligned
+    /// free extent large enough, which favors the sequential reads of the
+    /// device and fills segments more uniformly. No alignment if it's no more
+    /// than one. Only takes effect under `AllocPolicy::Linear`.
+    pub alloc_alignment: usize,
+    /// How user data blocks are encrypted, only takes effect on `SwornDisk::create()`.
+    pub crypto_mode: BlockCryptoMode,
+    /// Whether `SwornDisk::clone_range()` shares the host blocks of the cloned
+    /// records with reference counts, rather than copying the blocks. Only
+    /// takes effect on `SwornDisk::create()`.
+    ///
+    /// A shared block stays encrypted for the LBA it's written to, so the disk
+    /// is created without binding the blocks to their LBAs (`FEATURE_LBA_AAD`).
+    /// It can't be combined with GC, which migrates a block for a single LBA,
+    /// nor with `BlockCryptoMode::DerivedKey`, whose keys are derived from
+    /// the LBAs.
+    pub shared_blocks: bool,
+    /// The AEAD backend to protect user data blocks, the native one
+    /// (see `default_aead_backend()`) if `None`.
+    pub aead_backend: Option<AeadBackendRef>,
+    /// The trusted monotonic counter to detect rollback of the disk image.
+    /// No rollback detection if `None`.
+    pub trusted_counter: Option<TrustedCounterRef>,
+    /// The handler of the detected corruptions of user data, which are
+    /// only logged if `None`. Corruptions are detected with the `debug_crc`
+    /// feature only.
+    pub corruption_handler: Option<CorruptionHandlerRef>,
+}
+
+/// The crypto mode of user data blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BlockCryptoMode {
+    /// Each block is encrypted with a fresh random key and a zeroed IV.
+    RandomKey = 0,
+    /// Each block is encrypted with the per-disk data key and
+    /// a monotonic per-block nonce as its IV. Only the nonce is stored as
+    /// the secret of its record, in a compact layout.
+    PerBlockNonce = 1,
+    /// Each block is encrypted with a key derived from the per-disk data key,
+    /// its LBA and a monotonic per-block epoch, with a zeroed IV. Only the
+    /// epoch is stored as the secret of its record, in a compact layout.
+    DerivedKey = 2,
+}
+
+/// The policy to allocate free blocks for new writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocPolicy {
+    /// Scan the block validity table linearly from the last allocated block.
+    Linear,
+    /// Fill the open segment of each allocation class to completion before
+    /// opening another one, preferring clean segments (log-structured).
+    /// It leaves clean segment boundaries for GC.
+    SegmentFill,
+}
+
+/// The policy to compact the block validity table.
+///
+/// Skipping the compaction on a sync is safe: the `BAL` logs written by the
+/// compactions of the logical block table and the records replayed from its
+/// WAL recover the table, while the blocks freed by GC (which no `BAL` log
+/// records) force a compaction on the next sync.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BvtCompactionPolicy {
+    /// Compact on every sync.
+    EverySync,
+    /// Compact on every `n`-th sync.
+    EveryNSyncs(usize),
+    /// Compact on a sync once the `BAL` logs since the last compaction
+    /// reach this many bytes.
+    DirtyBytes(usize),
+    /// Compact by a background task while the foreground is idle, rather
+    /// than on syncs.
+    Background,
+    /// Compact on a sync once the `BAL` logs since the last compaction
+    /// outgrow the compacted table, i.e., once replaying them on recovery
+    /// costs more than reading the table.
+    Auto,
+}
+
+/// The storage of the reverse index table (HBA to LBA), which GC consults.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReverseIndexKind {
+    /// A `TxLsmTree`, as the logical block table.
+    Lsm,
+    /// An encrypted blob per segment, which holds the LBAs of all its blocks.
+    /// It's written once the segment is sealed or GC migrates blocks into it,
+    /// which saves the WAL appends and compactions of the LSM variant.
+    SegmentBlobs,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             cache_size: usize::MAX,
+            cache_ratios: CacheRatios::default(),
             two_level_caching: true,
+            sst_cache_size: None,
+            block_cache_size: None,
             delayed_reclamation: true,
             stat_waf: false,
             stat_cost: false,
+            cost_clock: CostClock::Rdtsc,
+            persist_stats: false,
+            aggregate_stats: true,
             enable_gc: false,
+            over_provisioning: 0,
+            enable_defrag: false,
+            reencrypt_on_gc: false,
             victim_policy: None,
+            victim_policy_kind: VictimPolicyKind::Greedy,
             sync_atomicity: true,
+            lazy_recovery: false,
+            ephemeral: false,
+            discard_granularity: 0,
+            bvt_compaction: BvtCompactionPolicy::Auto,
+            // 1/32 of the disk for each table
+            index_fraction: LAYOUT_FRACTION_BASE / 32,
+            reverse_index_fraction: LAYOUT_FRACTION_BASE / 32,
+            reverse_index_kind: ReverseIndexKind::Lsm,
+            memtable_capacity: None,
+            sst_block_size: None,
+            lsm_level0_ratio: None,
+            lsm_level_ratio: None,
+            compaction_policy: CompactionPolicyKind::Leveled,
+            // 4 MiB
+            wal_group_blocks: 1024,
+            wal_group_timeout: None,
+            ordered_data_writes: false,
+            checkpoint_interval: None,
+            checkpoint_dirty_bytes: None,
+            // 1 MiB
+            direct_write_threshold: 256,
+            // 256 KiB
+            bio_merge_window: 64,
+            rate_limit: None,
+            background_io_limit: None,
+            hang_timeout: None,
+            fail_on_hang: false,
+            alloc_policy: AllocPolicy::Linear,
+            alloc_alignment: 0,
+            crypto_mode: BlockCryptoMode::RandomKey,
+            shared_blocks: false,
+            aead_backend: None,
+            trusted_counter: None,
+            corruption_handler: None,
         }
     }
 }
 
 impl Config {
-    /// Get the victim policy, using GreedyVictimPolicy as default
+    /// Get the victim policy, built from `victim_policy_kind` if not set
     pub fn get_victim_policy(&self) -> VictimPolicyRef {
         self.victim_policy
             .clone()
-            .unwrap_or_else(|| Arc::new(GreedyVictimPolicy {}))
+            .unwrap_or_else(|| self.victim_policy_kind.build())
+    }
+
+    /// Get the size (in bytes) of the given cache tier, see `mem_budget()`.
+    pub fn cache_tier_size(&self, tier: CacheTier) -> usize {
+        self.mem_budget()
+            .map_or(self.cache_size, |budget| budget.tier(tier))
+    }
+
+    /// Get the budget of `DataBuf` and the cache tiers split from `cache_size`.
+    /// Returns `InvalidArgs` if the explicitly sized tiers exceed it.
+    pub fn mem_budget(&self) -> Result<MemBudget> {
+        if self.cache_size == usize::MAX {
+            return Ok(MemBudget {
+                sst_block: self.sst_cache_size.unwrap_or(usize::MAX),
+                log_block: self.block_cache_size.unwrap_or(usize::MAX),
+                ..MemBudget::UNLIMITED
+            });
+        }
+        let mut ratios = self.cache_ratios;
+        if !self.two_level_caching {
+            // The SST cache tier is not used
+            ratios.sst_block = 0;
+        }
+        MemBudget::split(
+            self.cache_size,
+            ratios,
+            self.sst_cache_size,
+            self.block_cache_size,
+        )
+    }
+
+    /// Get the parameters of the LSM trees, the defaults are taken for
+    /// the unset ones. Returns `InvalidArgs` if they are invalid.
+    pub fn lsm_params(&self) -> Result<LsmParams> {
+        let default = LsmParams::default();
+        let sst_block_size = match self.sst_block_size {
+            Some(size) => u32::try_from(size)
+                .map_err(|_| Error::with_msg(InvalidArgs, "invalid SST block size"))?,
+            None => default.sst_block_size,
+        };
+        let params = LsmParams {
+            memtable_capacity: self
+                .memtable_capacity
+                .map_or(default.memtable_capacity, |cap| cap as _),
+            sst_block_size,
+            level0_ratio: self.lsm_level0_ratio.unwrap_or(default.level0_ratio),
+            level_ratio: self.lsm_level_ratio.unwrap_or(default.level_ratio),
+            compaction_policy: self.compaction_policy as _,
+        };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
+/// The built-in victim policies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VictimPolicyKind {
+    /// `GreedyVictimPolicy`.
+    Greedy,
+    /// `LoopScanVictimPolicy`.
+    LoopScan,
+    /// `WindowGreedyVictimPolicy` over the `window` oldest segments.
+    WindowGreedy { window: usize },
+    /// `GenerationalVictimPolicy` with the minimum age (in blocks written)
+    /// of the old generation.
+    Generational { old_age: u64 },
+    /// `LazyGreedyVictimPolicy` excluding the `exclude` most recently
+    /// written segments.
+    LazyGreedy { exclude: usize },
+}
+
+impl VictimPolicyKind {
+    /// Build the victim policy of the kind.
+    pub fn build(&self) -> VictimPolicyRef {
+        match *self {
+            Self::Greedy => Arc::new(GreedyVictimPolicy {}),
+            Self::LoopScan => Arc::new(LoopScanVictimPolicy::new()),
+            Self::WindowGreedy { window } => Arc::new(WindowGreedyVictimPolicy::new(window)),
+            Self::Generational { old_age } => Arc::new(GenerationalVictimPolicy::new(old_age)),
+            Self::LazyGreedy { exclude } => Arc::new(LazyGreedyVictimPolicy::new(exclude)),
+        }
+    }
+}
+
+impl FromStr for VictimPolicyKind {
+    type Err = Error;
+
+    /// Parse from `greedy`, `loopscan`, `window:<window>`, `generational:<old_age>`
+    /// or `lazy:<exclude>`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let parse_arg = || {
+            arg.and_then(|arg| arg.parse::<u64>().ok())
+                .ok_or(Error::with_msg(
+                    InvalidArgs,
+                    "invalid victim policy argument",
+                ))
+        };
+        match name {
+            "greedy" => Ok(Self::Greedy),
+            "loopscan" => Ok(Self::LoopScan),
+            "window" => Ok(Self::WindowGreedy {
+                window: parse_arg()? as usize,
+            }),
+            "generational" => Ok(Self::Generational {
+                old_age: parse_arg()?,
+            }),
+            "lazy" => Ok(Self::LazyGreedy {
+                exclude: parse_arg()? as usize,
+            }),
+            _ => Err(Error::with_msg(InvalidArgs, "unknown victim policy")),
+        }
+    }
+}
+
+impl From<u64> for BlockCryptoMode {
+    fn from(value: u64) -> Self {
+        match value {
+            1 => BlockCryptoMode::PerBlockNonce,
+            2 => BlockCryptoMode::DerivedKey,
+            _ => BlockCryptoMode::RandomKey,
+        }
     }
 }
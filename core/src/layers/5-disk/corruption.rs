@@ -0,0 +1,28 @@
+//! Reports of corrupted user data detected by `SwornDisk`.
+//!
+//! With the `debug_crc` feature, each record keeps a CRC-32 of the plaintext
+//! of its block, taken on write and validated after decryption. A mismatch
+//! means the data is corrupted inside `SwornDisk` (rather than in the buffer
+//! of the caller), which is reported to the `CorruptionHandler` in `Config`.
+use super::sworndisk::Hba;
+use crate::os::Arc;
+
+/// A handler of the corruptions detected by `SwornDisk`, e.g., to log them
+/// or to stop the workload under debugging.
+pub trait CorruptionHandler: Send + Sync {
+    /// Called on each detected corruption, before the read returns an error.
+    fn on_corruption(&self, report: &CorruptionReport);
+}
+
+pub type CorruptionHandlerRef = Arc<dyn CorruptionHandler>;
+
+/// A corrupted user data block, whose plaintext mismatches its CRC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CorruptionReport {
+    /// The host block address of the corrupted block.
+    pub hba: Hba,
+    /// The CRC-32 of the plaintext taken on write.
+    pub expected_crc: u32,
+    /// The CRC-32 of the decrypted plaintext.
+    pub actual_crc: u32,
+}
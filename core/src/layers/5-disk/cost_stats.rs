@@ -1,16 +1,156 @@
 //! Cost statistics for read/write operations.
+//!
+//! The counters are updated on every block at high IOPS, so each of them is
+//! sharded by thread (see `ShardedCounter`) to avoid the contention on a
+//! single atomic, and the shards are summed on read.
+//!
+//! The counters are in the ticks of the selected `CostClock`, which are
+//! reported both as CPU cycles and as wall time where they are convertible.
+
+use crate::os::{Clock, CurrentThread, RealClock};
+use crate::prelude::*;
+
+use core::fmt::{self, Write};
+use core::hash::{Hash, Hasher};
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
 
-use core::sync::atomic::{AtomicU64, Ordering};
-use lazy_static::lazy_static;
+// ============================================================================
+// Cost Timing Statistics (L3: Disk Layer, L2: LSM Tree Layer)
+// Uses RDTSC for low-overhead timing by default (no OCall needed in SGX)
+// ============================================================================
 
+/// The clock source of the cost timers, see `Config::cost_clock`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CostClock {
+    /// The CPU timestamp counter (RDTSC) in cycles, the cheapest one and no
+    /// OCall is needed in SGX. The cycles are skewed across sockets and by
+    /// frequency scaling, and converted to wall time by the frequency
+    /// calibrated against the monotonic clock (see `tsc_frequency()`).
+    Rdtsc = 0,
+    /// The monotonic clock of the OS in nanoseconds. Only available with
+    /// `std`, otherwise it stays zero.
+    Monotonic = 1,
+    /// A counter of the timed sections, which reads no clock at all, so it's
+    /// safe where neither RDTSC nor the OS clock is usable (e.g., SGX1
+    /// enclaves). It counts rather than times, so the costs are the numbers
+    /// of operations, convertible to neither cycles nor wall time.
+    Counter = 2,
+}
 
+impl CostClock {
+    /// Read the current ticks of the clock.
+    #[inline]
+    fn now(self) -> u64 {
+        match self {
+            Self::Rdtsc => rdtsc(),
+            Self::Monotonic => RealClock.now().as_nanos() as u64,
+            Self::Counter => 0,
+        }
+    }
 
+    /// The ticks elapsed since `start`.
+    #[inline]
+    fn elapsed(self, start: u64) -> u64 {
+        match self {
+            Self::Counter => 1,
+            _ => self.now().saturating_sub(start),
+        }
+    }
 
+    /// Convert the ticks of the clock to CPU cycles, if possible.
+    pub fn to_cycles(self, ticks: u64) -> Option<u64> {
+        match self {
+            Self::Rdtsc => Some(ticks),
+            Self::Monotonic => {
+                let freq = tsc_frequency()?;
+                Some((ticks as u128 * freq as u128 / NANOS_PER_SEC) as u64)
+            }
+            Self::Counter => None,
+        }
+    }
 
-// ============================================================================
-// Cost Timing Statistics (L3: Disk Layer, L2: LSM Tree Layer)
-// Uses RDTSC for low-overhead timing (no OCall needed in SGX)
-// ============================================================================
+    /// Convert the ticks of the clock to wall time in nanoseconds, if possible.
+    pub fn to_nanos(self, ticks: u64) -> Option<u64> {
+        match self {
+            Self::Rdtsc => {
+                let freq = tsc_frequency()?;
+                Some((ticks as u128 * NANOS_PER_SEC / freq as u128) as u64)
+            }
+            Self::Monotonic if cfg!(feature = "std") => Some(ticks),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rdtsc => "rdtsc",
+            Self::Monotonic => "monotonic",
+            Self::Counter => "counter",
+        }
+    }
+
+    fn from_u8(val: u8) -> Self {
+        match val {
+            1 => Self::Monotonic,
+            2 => Self::Counter,
+            _ => Self::Rdtsc,
+        }
+    }
+}
+
+impl FromStr for CostClock {
+    type Err = Error;
+
+    /// Parse from `rdtsc`, `monotonic` or `counter`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rdtsc" => Ok(Self::Rdtsc),
+            "monotonic" => Ok(Self::Monotonic),
+            "counter" => Ok(Self::Counter),
+            _ => Err(Error::with_msg(InvalidArgs, "invalid cost clock")),
+        }
+    }
+}
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// The clock of the global cost timers
+static COST_CLOCK: AtomicU8 = AtomicU8::new(CostClock::Rdtsc as u8);
+
+/// Returns the clock of the global cost timers.
+pub fn cost_clock() -> CostClock {
+    CostClock::from_u8(COST_CLOCK.load(Ordering::Relaxed))
+}
+
+/// Set the clock of the global cost timers. The timed costs are reset if
+/// the clock changes, as the ticks of different clocks don't add up
+pub fn set_cost_clock(clock: CostClock) {
+    if COST_CLOCK.swap(clock as u8, Ordering::Relaxed) != clock as u8 {
+        COST_L3.reset();
+        COST_L2.reset_timers();
+    }
+}
+
+/// The frequency (in Hz) of the timestamp counter, calibrated against the
+/// monotonic clock once. `None` without `std` or RDTSC
+pub fn tsc_frequency() -> Option<u64> {
+    #[cfg(feature = "std")]
+    {
+        static FREQ: std::sync::OnceLock<u64> = std::sync::OnceLock::new();
+        let freq = *FREQ.get_or_init(|| {
+            let (start_cycles, start) = (rdtsc(), RealClock.now());
+            std::thread::sleep(core::time::Duration::from_millis(10));
+            let cycles = rdtsc().saturating_sub(start_cycles);
+            let nanos = (RealClock.now() - start).as_nanos().max(1);
+            (cycles as u128 * NANOS_PER_SEC / nanos) as u64
+        });
+        (freq > 0).then_some(freq)
+    }
+    #[cfg(not(feature = "std"))]
+    None
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum CostL3Type {
@@ -30,19 +170,19 @@ pub enum CostL2Type {
 
 /// L3 Layer (Disk Layer) cost statistics
 pub struct CostL3 {
-    logical_block_table: AtomicU64,
-    block_io: AtomicU64,
-    encryption: AtomicU64,
-    allocation: AtomicU64,
+    logical_block_table: ShardedCounter,
+    block_io: ShardedCounter,
+    encryption: ShardedCounter,
+    allocation: ShardedCounter,
 }
 
 impl CostL3 {
     pub const fn new() -> Self {
         Self {
-            logical_block_table: AtomicU64::new(0),
-            block_io: AtomicU64::new(0),
-            encryption: AtomicU64::new(0),
-            allocation: AtomicU64::new(0),
+            logical_block_table: ShardedCounter::new(),
+            block_io: ShardedCounter::new(),
+            encryption: ShardedCounter::new(),
+            allocation: ShardedCounter::new(),
         }
     }
 
@@ -57,10 +197,10 @@ impl CostL3 {
     }
 
     pub fn get_stats(&self) -> CostL3Stats {
-        let logical_block_table = self.logical_block_table.load(Ordering::Relaxed);
-        let block_io = self.block_io.load(Ordering::Relaxed);
-        let encryption = self.encryption.load(Ordering::Relaxed);
-        let allocation = self.allocation.load(Ordering::Relaxed);
+        let logical_block_table = self.logical_block_table.load();
+        let block_io = self.block_io.load();
+        let encryption = self.encryption.load();
+        let allocation = self.allocation.load();
         let total = logical_block_table + block_io + encryption + allocation;
 
         CostL3Stats {
@@ -69,38 +209,53 @@ impl CostL3 {
             encryption,
             allocation,
             total,
+            clock: cost_clock(),
         }
     }
 
     pub fn reset(&self) {
-        self.logical_block_table.store(0, Ordering::Relaxed);
-        self.block_io.store(0, Ordering::Relaxed);
-        self.encryption.store(0, Ordering::Relaxed);
-        self.allocation.store(0, Ordering::Relaxed);
+        self.logical_block_table.store(0);
+        self.block_io.store(0);
+        self.encryption.store(0);
+        self.allocation.store(0);
+    }
+
+    /// Restore statistics from a previous snapshot
+    pub fn restore(&self, stats: &CostL3Stats) {
+        self.logical_block_table.store(stats.logical_block_table);
+        self.block_io.store(stats.block_io);
+        self.encryption.store(stats.encryption);
+        self.allocation.store(stats.allocation);
     }
 
-    pub fn print(&self) {
+    pub fn print(&self, sink: &mut dyn Write) -> fmt::Result {
         let stats = self.get_stats();
-        stats.print();
+        stats.print(sink)
     }
 }
 
-
 /// L2 Layer (LSM Tree Layer) cost statistics
 pub struct CostL2 {
-    wal: AtomicU64,
-    memtable: AtomicU64,
-    compaction: AtomicU64,
-    sstable_lookup: AtomicU64,
+    wal: ShardedCounter,
+    memtable: ShardedCounter,
+    compaction: ShardedCounter,
+    sstable_lookup: ShardedCounter,
+    // Bloom filter probes of SSTs (counts, not cycles)
+    filter_negatives: ShardedCounter,
+    filter_positives: ShardedCounter,
+    filter_false_positives: ShardedCounter,
 }
 
 impl CostL2 {
     pub const fn new() -> Self {
         Self {
-            wal: AtomicU64::new(0),
-            memtable: AtomicU64::new(0),
-            compaction: AtomicU64::new(0),
-            sstable_lookup: AtomicU64::new(0),
+            wal: ShardedCounter::new(),
+            memtable: ShardedCounter::new(),
+            compaction: ShardedCounter::new(),
+            sstable_lookup: ShardedCounter::new(),
+            filter_negatives: ShardedCounter::new(),
+            filter_positives: ShardedCounter::new(),
+            filter_false_positives: ShardedCounter::new(),
         }
     }
 
@@ -115,10 +270,10 @@ impl CostL2 {
     }
 
     pub fn get_stats(&self) -> CostL2Stats {
-        let wal = self.wal.load(Ordering::Relaxed);
-        let memtable = self.memtable.load(Ordering::Relaxed);
-        let compaction = self.compaction.load(Ordering::Relaxed);
-        let sstable_lookup = self.sstable_lookup.load(Ordering::Relaxed);
+        let wal = self.wal.load();
+        let memtable = self.memtable.load();
+        let compaction = self.compaction.load();
+        let sstable_lookup = self.sstable_lookup.load();
         let total = wal + memtable + compaction + sstable_lookup;
 
         CostL2Stats {
@@ -127,25 +282,77 @@ impl CostL2 {
             compaction,
             sstable_lookup,
             total,
+            clock: cost_clock(),
         }
     }
 
     pub fn reset(&self) {
-        self.wal.store(0, Ordering::Relaxed);
-        self.memtable.store(0, Ordering::Relaxed);
-        self.compaction.store(0, Ordering::Relaxed);
-        self.sstable_lookup.store(0, Ordering::Relaxed);
+        self.reset_timers();
+        self.filter_negatives.store(0);
+        self.filter_positives.store(0);
+        self.filter_false_positives.store(0);
+    }
+
+    /// Reset the timed costs only, not the counts of Bloom filter probes
+    pub fn reset_timers(&self) {
+        self.wal.store(0);
+        self.memtable.store(0);
+        self.compaction.store(0);
+        self.sstable_lookup.store(0);
+    }
+
+    /// Restore statistics from a previous snapshot
+    pub fn restore(&self, stats: &CostL2Stats) {
+        self.wal.store(stats.wal);
+        self.memtable.store(stats.memtable);
+        self.compaction.store(stats.compaction);
+        self.sstable_lookup.store(stats.sstable_lookup);
+    }
+
+    /// Record a probe of the Bloom filter of a SST, a negative probe
+    /// skips the SST
+    pub fn record_filter_probe(&self, may_contain: bool) {
+        if !STATS_ENABLED {
+            return;
+        }
+        let target = if may_contain {
+            &self.filter_positives
+        } else {
+            &self.filter_negatives
+        };
+        target.add(1);
     }
 
-    pub fn print(&self) {
+    /// Record a positive probe of the Bloom filter that misses in the SST
+    pub fn record_filter_false_positive(&self) {
+        if !STATS_ENABLED {
+            return;
+        }
+        self.filter_false_positives.add(1);
+    }
+
+    pub fn get_filter_stats(&self) -> BloomFilterStats {
+        BloomFilterStats {
+            negatives: self.filter_negatives.load(),
+            positives: self.filter_positives.load(),
+            false_positives: self.filter_false_positives.load(),
+        }
+    }
+
+    pub fn print(&self, sink: &mut dyn Write) -> fmt::Result {
         let stats = self.get_stats();
-        stats.print();
+        stats.print(sink)?;
+        self.get_filter_stats().print(sink)
     }
 }
 
+/// Whether the statistics are recorded, i.e., the `stats` feature is enabled.
+/// Otherwise, all the global statistics are read as zeros.
+pub const STATS_ENABLED: bool = cfg!(feature = "stats");
+
 /// Read CPU timestamp counter (RDTSC) - no OCall needed, very fast
 #[inline]
-fn rdtsc() -> u64 {
+pub(super) fn rdtsc() -> u64 {
     #[cfg(target_arch = "x86_64")]
     unsafe {
         core::arch::x86_64::_rdtsc()
@@ -156,28 +363,165 @@ fn rdtsc() -> u64 {
     }
 }
 
+/// The number of shards of a `ShardedCounter`
+const NUM_SHARDS: usize = 16;
+
+/// A shard on its own cache line, so that the shards never false share
+#[repr(align(64))]
+struct CounterShard(AtomicU64);
+
+/// A counter sharded by thread. Each thread adds to its own shard (modulo
+/// `NUM_SHARDS`), and a read sums all the shards
+pub struct ShardedCounter {
+    shards: [CounterShard; NUM_SHARDS],
+}
+
+impl ShardedCounter {
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: CounterShard = CounterShard(AtomicU64::new(0));
+        Self {
+            shards: [ZERO; NUM_SHARDS],
+        }
+    }
+
+    pub fn add(&self, val: u64) {
+        self.add_to(shard_index(), val);
+    }
+
+    fn add_to(&self, shard: usize, val: u64) {
+        self.shards[shard].0.fetch_add(val, Ordering::Relaxed);
+    }
+
+    pub fn load(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.0.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Set the counter to `val`. It's not atomic with the concurrent adds,
+    /// which may be lost
+    pub fn store(&self, val: u64) {
+        for (nth, shard) in self.shards.iter().enumerate() {
+            let val = if nth == 0 { val } else { 0 };
+            shard.0.store(val, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The shard of the current thread, by the FNV-1a hash of its ID
+fn shard_index() -> usize {
+    struct FnvHasher(u64);
+
+    impl Hasher for FnvHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for byte in bytes {
+                self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100_0000_01b3);
+            }
+        }
+    }
+
+    let mut hasher = FnvHasher(0xcbf2_9ce4_8422_2325);
+    CurrentThread::id().hash(&mut hasher);
+    let hash = hasher.finish();
+    ((hash >> 32) ^ hash) as usize % NUM_SHARDS
+}
+
+/// The number of alive `CostTimersDisabled` guards
+static NUM_TIMER_DISABLES: AtomicUsize = AtomicUsize::new(0);
+
+/// Disable all the cost timers until the returned guard is dropped, e.g., to
+/// keep the timers from distorting a fine-grained measurement. The guards
+/// nest, and the timers started before are still recorded
+pub fn disable_cost_timers() -> CostTimersDisabled {
+    NUM_TIMER_DISABLES.fetch_add(1, Ordering::Relaxed);
+    CostTimersDisabled { _private: () }
+}
+
+/// A guard of disabled cost timers, see `disable_cost_timers()`
+pub struct CostTimersDisabled {
+    _private: (),
+}
+
+impl Drop for CostTimersDisabled {
+    fn drop(&mut self) {
+        NUM_TIMER_DISABLES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns whether the cost timers are recorded now
+pub fn cost_timers_enabled() -> bool {
+    STATS_ENABLED && NUM_TIMER_DISABLES.load(Ordering::Relaxed) == 0
+}
+
 pub struct CostTimer<'a> {
+    clock: CostClock,
     start: u64,
-    target: &'a AtomicU64,
+    // `None` if the timer is disabled
+    target: Option<&'a ShardedCounter>,
 }
 
 impl<'a> CostTimer<'a> {
-    pub fn new(target: &'a AtomicU64) -> Self {
+    pub fn new(target: &'a ShardedCounter) -> Self {
+        let clock = cost_clock();
+        if !cost_timers_enabled() {
+            return Self {
+                clock,
+                start: 0,
+                target: None,
+            };
+        }
         Self {
-            start: rdtsc(),
-            target,
+            clock,
+            start: clock.now(),
+            target: Some(target),
         }
     }
 }
 
 impl<'a> Drop for CostTimer<'a> {
     fn drop(&mut self) {
-        let elapsed_cycles = rdtsc().saturating_sub(self.start);
-        self.target.fetch_add(elapsed_cycles, Ordering::Relaxed);
+        let Some(target) = self.target else {
+            return;
+        };
+        target.add(self.clock.elapsed(self.start));
+    }
+}
+
+/// Print the unit of the costs measured by `clock`
+fn print_unit(sink: &mut dyn Write, clock: CostClock) -> fmt::Result {
+    match clock {
+        CostClock::Counter => writeln!(sink, "  (Unit: timed sections, counted without any clock)"),
+        _ => writeln!(
+            sink,
+            "  (Unit: CPU cycles and wall time, measured via {})",
+            clock.name()
+        ),
+    }
+}
+
+/// Print a cost in both CPU cycles and wall time (`-` if unknown),
+/// or in times if counted by `CostClock::Counter`
+fn print_cost(sink: &mut dyn Write, label: &str, ticks: u64, clock: CostClock) -> fmt::Result {
+    if clock == CostClock::Counter {
+        return write!(sink, "  {:<21}{:>15} times", label, ticks);
+    }
+    match clock.to_cycles(ticks) {
+        Some(cycles) => write!(sink, "  {:<21}{:>15} cycles", label, cycles)?,
+        None => write!(sink, "  {:<21}{:>15} cycles", label, "-")?,
+    }
+    match clock.to_nanos(ticks) {
+        Some(nanos) => write!(sink, " {:>15} ns", nanos),
+        None => write!(sink, " {:>15} ns", "-"),
     }
 }
 
-/// CPU cycles statistics (using RDTSC)
+/// Cost statistics in the ticks of `clock`
 #[derive(Debug, Clone)]
 pub struct CostL3Stats {
     pub logical_block_table: u64,
@@ -185,6 +529,8 @@ pub struct CostL3Stats {
     pub encryption: u64,
     pub allocation: u64,
     pub total: u64,
+    /// The clock measuring the costs.
+    pub clock: CostClock,
 }
 
 impl CostL3Stats {
@@ -202,31 +548,38 @@ impl CostL3Stats {
         }
     }
 
-    pub fn print(&self) {
+    pub fn print(&self, sink: &mut dyn Write) -> fmt::Result {
         let pct = self.get_percentage();
 
-        println!("=============== L3 (Disk Layer) Cost Statistics ===============");
-        println!("  (Unit: CPU cycles, measured via RDTSC)");
-        println!("  Logical Block Table: {:>15} cycles ({:>5.2}%)",
-                 self.logical_block_table,
-                 pct.logical_block_table);
-        println!("  Block I/O:           {:>15} cycles ({:>5.2}%)",
-                 self.block_io,
-                 pct.block_io);
-        println!("  Encryption:          {:>15} cycles ({:>5.2}%)",
-                 self.encryption,
-                 pct.encryption);
-        println!("  Allocation:          {:>15} cycles ({:>5.2}%)",
-                 self.allocation,
-                 pct.allocation);
-        println!("  {}", "-".repeat(63));
-        println!("  Total:               {:>15} cycles",
-                 self.total);
-        println!("================================================================");
-    }
-}
-
-/// CPU cycles statistics (using RDTSC)
+        writeln!(
+            sink,
+            "=============== L3 (Disk Layer) Cost Statistics ==============="
+        )?;
+        print_unit(sink, self.clock)?;
+        print_cost(
+            sink,
+            "Logical Block Table:",
+            self.logical_block_table,
+            self.clock,
+        )?;
+        writeln!(sink, " ({:>5.2}%)", pct.logical_block_table)?;
+        print_cost(sink, "Block I/O:", self.block_io, self.clock)?;
+        writeln!(sink, " ({:>5.2}%)", pct.block_io)?;
+        print_cost(sink, "Encryption:", self.encryption, self.clock)?;
+        writeln!(sink, " ({:>5.2}%)", pct.encryption)?;
+        print_cost(sink, "Allocation:", self.allocation, self.clock)?;
+        writeln!(sink, " ({:>5.2}%)", pct.allocation)?;
+        writeln!(sink, "  {:-<63}", "")?;
+        print_cost(sink, "Total:", self.total, self.clock)?;
+        writeln!(sink)?;
+        writeln!(
+            sink,
+            "================================================================"
+        )
+    }
+}
+
+/// Cost statistics in the ticks of `clock`
 #[derive(Debug, Clone)]
 pub struct CostL2Stats {
     pub wal: u64,
@@ -234,6 +587,8 @@ pub struct CostL2Stats {
     pub compaction: u64,
     pub sstable_lookup: u64,
     pub total: u64,
+    /// The clock measuring the costs.
+    pub clock: CostClock,
 }
 
 impl CostL2Stats {
@@ -251,27 +606,61 @@ impl CostL2Stats {
         }
     }
 
-    pub fn print(&self) {
+    pub fn print(&self, sink: &mut dyn Write) -> fmt::Result {
         let pct = self.get_percentage();
 
-        println!("============= L2 (LSM Tree Layer) Cost Statistics =============");
-        println!("  (Unit: CPU cycles, measured via RDTSC)");
-        println!("  WAL:                 {:>15} cycles ({:>5.2}%)",
-                 self.wal,
-                 pct.wal);
-        println!("  MemTable:            {:>15} cycles ({:>5.2}%)",
-                 self.memtable,
-                 pct.memtable);
-        println!("  Compaction:          {:>15} cycles ({:>5.2}%)",
-                 self.compaction,
-                 pct.compaction);
-        println!("  SSTable Lookup:      {:>15} cycles ({:>5.2}%)",
-                 self.sstable_lookup,
-                 pct.sstable_lookup);
-        println!("  {}", "-".repeat(63));
-        println!("  Total:               {:>15} cycles",
-                 self.total);
-        println!("================================================================");
+        writeln!(
+            sink,
+            "============= L2 (LSM Tree Layer) Cost Statistics ============="
+        )?;
+        print_unit(sink, self.clock)?;
+        print_cost(sink, "WAL:", self.wal, self.clock)?;
+        writeln!(sink, " ({:>5.2}%)", pct.wal)?;
+        print_cost(sink, "MemTable:", self.memtable, self.clock)?;
+        writeln!(sink, " ({:>5.2}%)", pct.memtable)?;
+        print_cost(sink, "Compaction:", self.compaction, self.clock)?;
+        writeln!(sink, " ({:>5.2}%)", pct.compaction)?;
+        print_cost(sink, "SSTable Lookup:", self.sstable_lookup, self.clock)?;
+        writeln!(sink, " ({:>5.2}%)", pct.sstable_lookup)?;
+        writeln!(sink, "  {:-<63}", "")?;
+        print_cost(sink, "Total:", self.total, self.clock)?;
+        writeln!(sink)?;
+        writeln!(
+            sink,
+            "================================================================"
+        )
+    }
+}
+
+/// Bloom filter statistics of SST lookups
+#[derive(Debug, Default, Clone)]
+pub struct BloomFilterStats {
+    /// Probes that skip the SST
+    pub negatives: u64,
+    /// Probes that may hit the SST
+    pub positives: u64,
+    /// Positive probes that turn out to miss
+    pub false_positives: u64,
+}
+
+impl BloomFilterStats {
+    /// The ratio of positive probes that turn out to miss
+    pub fn false_positive_rate(&self) -> f64 {
+        if self.positives == 0 {
+            return 0.0;
+        }
+        self.false_positives as f64 / self.positives as f64
+    }
+
+    pub fn print(&self, sink: &mut dyn Write) -> fmt::Result {
+        writeln!(
+            sink,
+            "  Bloom Filter:  {} skipped, {} passed, {} false positives ({:>5.2}%)",
+            self.negatives,
+            self.positives,
+            self.false_positives,
+            self.false_positive_rate() * 100.0
+        )
     }
 }
 
@@ -291,39 +680,134 @@ pub struct CostL2Percentage {
     pub sstable_lookup: f64,
 }
 
-lazy_static! {
-    pub static ref COST_L3: CostL3 = CostL3::new();
-    pub static ref COST_L2: CostL2 = CostL2::new();
+pub static COST_L3: CostL3 = CostL3::new();
+pub static COST_L2: CostL2 = CostL2::new();
+
+pub fn print_all_cost_stats(sink: &mut dyn Write) -> fmt::Result {
+    COST_L3.print(sink)?;
+    writeln!(sink)?;
+    COST_L2.print(sink)
 }
 
-pub fn print_all_cost_stats() {
-    COST_L3.print();
-    println!();
-    COST_L2.print();
+/// Print a total cost as a JSON field, `null` if it's not convertible
+fn print_total_json(sink: &mut dyn Write, name: &str, total: u64, clock: CostClock) -> fmt::Result {
+    write!(sink, "    \"{}\": {{ \"cycles\": ", name)?;
+    match clock.to_cycles(total) {
+        Some(cycles) => write!(sink, "{}", cycles)?,
+        None => write!(sink, "null")?,
+    }
+    write!(sink, ", \"nanos\": ")?;
+    match clock.to_nanos(total) {
+        Some(nanos) => write!(sink, "{} }}", nanos),
+        None => write!(sink, "null }}"),
+    }
 }
 
-/// Print cost statistics as JSON format for visualization
-pub fn print_cost_stats_json() {
+/// Print cost statistics as JSON format to `sink` for visualization
+pub fn print_cost_stats_json(sink: &mut dyn Write) -> fmt::Result {
     let l3_stats = COST_L3.get_stats();
     let l3_pct = l3_stats.get_percentage();
 
     let l2_stats = COST_L2.get_stats();
     let l2_pct = l2_stats.get_percentage();
 
-    println!("{{");
-    println!("  \"L3\": {{");
-    println!("    \"logical_block_table\": {:.2},", l3_pct.logical_block_table);
-    println!("    \"block_io\": {:.2},", l3_pct.block_io);
-    println!("    \"encryption\": {:.2},", l3_pct.encryption);
-    println!("    \"allocation\": {:.2}", l3_pct.allocation);
-    println!("  }},");
-    println!("  \"L2\": {{");
-    println!("    \"wal\": {:.2},", l2_pct.wal);
-    println!("    \"memtable\": {:.2},", l2_pct.memtable);
-    println!("    \"compaction\": {:.2},", l2_pct.compaction);
-    println!("    \"sstable_lookup\": {:.2}", l2_pct.sstable_lookup);
-    println!("  }}");
-    println!("}}");
+    writeln!(sink, "{{")?;
+    writeln!(sink, "  \"L3\": {{")?;
+    writeln!(
+        sink,
+        "    \"logical_block_table\": {:.2},",
+        l3_pct.logical_block_table
+    )?;
+    writeln!(sink, "    \"block_io\": {:.2},", l3_pct.block_io)?;
+    writeln!(sink, "    \"encryption\": {:.2},", l3_pct.encryption)?;
+    writeln!(sink, "    \"allocation\": {:.2}", l3_pct.allocation)?;
+    writeln!(sink, "  }},")?;
+    writeln!(sink, "  \"L2\": {{")?;
+    writeln!(sink, "    \"wal\": {:.2},", l2_pct.wal)?;
+    writeln!(sink, "    \"memtable\": {:.2},", l2_pct.memtable)?;
+    writeln!(sink, "    \"compaction\": {:.2},", l2_pct.compaction)?;
+    writeln!(sink, "    \"sstable_lookup\": {:.2}", l2_pct.sstable_lookup)?;
+    writeln!(sink, "  }},")?;
+    // The totals in both CPU cycles and wall time
+    writeln!(sink, "  \"clock\": \"{}\",", l3_stats.clock.name())?;
+    writeln!(sink, "  \"total\": {{")?;
+    print_total_json(sink, "L3", l3_stats.total, l3_stats.clock)?;
+    writeln!(sink, ",")?;
+    print_total_json(sink, "L2", l2_stats.total, l2_stats.clock)?;
+    writeln!(sink)?;
+    writeln!(sink, "  }}")?;
+    writeln!(sink, "}}")
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{
+        cost_timers_enabled, disable_cost_timers, tsc_frequency, CostClock, CostTimer,
+        ShardedCounter, NUM_SHARDS, STATS_ENABLED,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn sharded_counter() {
+        let counter = Arc::new(ShardedCounter::new());
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = counter.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counter.add(1);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(counter.load(), 8000);
+
+        for shard in 0..NUM_SHARDS {
+            counter.add_to(shard, 1);
+        }
+        counter.store(42);
+        assert_eq!(counter.load(), 42);
+    }
+
+    #[test]
+    fn disable_cost_timers_test() {
+        let counter = ShardedCounter::new();
+        {
+            let _disabled = disable_cost_timers();
+            assert!(!cost_timers_enabled());
+            let _timer = CostTimer::new(&counter);
+            let _nested = disable_cost_timers();
+        }
+        assert_eq!(counter.load(), 0);
+        assert_eq!(cost_timers_enabled(), STATS_ENABLED);
+    }
 
+    #[test]
+    fn cost_clock() {
+        assert_eq!(
+            "monotonic".parse::<CostClock>().unwrap(),
+            CostClock::Monotonic
+        );
+        assert!("tsc".parse::<CostClock>().is_err());
+
+        // Counts rather than times
+        let counter = CostClock::Counter;
+        assert_eq!(counter.elapsed(counter.now()), 1);
+        assert_eq!((counter.to_cycles(1), counter.to_nanos(1)), (None, None));
+
+        let monotonic = CostClock::Monotonic;
+        assert_eq!(monotonic.to_nanos(1000), Some(1000));
+        match tsc_frequency() {
+            Some(freq) => {
+                let rdtsc = CostClock::Rdtsc;
+                assert_eq!(rdtsc.to_cycles(freq), Some(freq));
+                assert_eq!(rdtsc.to_nanos(freq), Some(1_000_000_000));
+                assert_eq!(monotonic.to_cycles(1_000_000_000), Some(freq));
+            }
+            None => assert_eq!(monotonic.to_cycles(1000), None),
+        }
+    }
+}
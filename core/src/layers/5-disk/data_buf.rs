@@ -7,9 +7,15 @@ use crate::prelude::*;
 use core::ops::RangeInclusive;
 
 /// A buffer to cache data blocks before they are written to disk.
+///
+/// A flush takes a snapshot of the buffered blocks, which is moved aside
+/// and stays readable until it is written and indexed. Meanwhile, the
+/// buffer accepts new blocks and serves the reads of the other blocks.
 #[derive(Debug)]
 pub(super) struct DataBuf {
     buf: Mutex<BTreeMap<RecordKey, Arc<DataBlock>>>,
+    /// The snapshot being flushed, older than the blocks of `buf`.
+    flushing: Mutex<BTreeMap<RecordKey, Arc<DataBlock>>>,
     cap: usize,
     cvar: Condvar,
     is_full: CvarMutex<bool>,
@@ -23,6 +29,7 @@ impl DataBuf {
     pub fn new(cap: usize) -> Self {
         Self {
             buf: Mutex::new(BTreeMap::new()),
+            flushing: Mutex::new(BTreeMap::new()),
             cap,
             cvar: Condvar::new(),
             is_full: CvarMutex::new(false),
@@ -33,27 +40,29 @@ impl DataBuf {
     /// the content into `buf`.
     pub fn get(&self, key: RecordKey, buf: &mut BufMut) -> Option<()> {
         debug_assert_eq!(buf.nblocks(), 1);
-        if let Some(block) = self.buf.lock().get(&key) {
-            buf.as_mut_slice().copy_from_slice(block.as_slice());
-            Some(())
-        } else {
-            None
-        }
+        // Search in the buffer first, then in the older snapshot
+        let block = self.buf.lock().get(&key).cloned();
+        let block = match block {
+            Some(block) => block,
+            None => self.flushing.lock().get(&key).cloned()?,
+        };
+        buf.as_mut_slice().copy_from_slice(block.as_slice());
+        Some(())
     }
 
     /// Get the buffered data blocks which keys are within the given range.
     pub fn get_range(&self, range: RangeInclusive<RecordKey>) -> Vec<(RecordKey, Arc<DataBlock>)> {
-        self.buf
+        let mut blocks: BTreeMap<_, _> = self
+            .buf
             .lock()
-            .iter()
-            .filter_map(|(k, v)| {
-                if range.contains(k) {
-                    Some((*k, v.clone()))
-                } else {
-                    None
-                }
-            })
-            .collect()
+            .range(range.clone())
+            .map(|(k, v)| (*k, v.clone()))
+            .collect();
+        // The blocks of the older snapshot are overridden by the buffer's
+        for (k, v) in self.flushing.lock().range(range) {
+            let _ = blocks.entry(*k).or_insert_with(|| v.clone());
+        }
+        blocks.into_iter().collect()
     }
 
     /// Put the data block in `buf` into the buffer. Return
@@ -76,38 +85,89 @@ impl DataBuf {
         *is_full
     }
 
+    /// Remove the buffered data blocks which keys are within the given range.
+    ///
+    /// The snapshot being flushed is left as it is, the caller should
+    /// wait for the flush to complete.
+    pub fn remove_range(&self, range: RangeInclusive<RecordKey>) {
+        let mut is_full = self.is_full.lock().unwrap();
+        let mut data_buf = self.buf.lock();
+        data_buf.retain(|k, _| !range.contains(k));
+
+        if *is_full && data_buf.len() < self.cap {
+            *is_full = false;
+            self.cvar.notify_all();
+        }
+    }
+
     /// Return the number of data blocks of the buffer.
     pub fn nblocks(&self) -> usize {
         self.buf.lock().len()
     }
 
+    /// Return the memory (in bytes) taken by the data blocks, including
+    /// the snapshot being flushed.
+    pub fn mem_usage(&self) -> usize {
+        (self.nblocks() + self.flushing.lock().len()) * BLOCK_SIZE
+    }
+
     /// Return whether the buffer is full.
     pub fn at_capacity(&self) -> bool {
         self.nblocks() >= self.cap
     }
 
-    /// Return whether the buffer is empty.
+    /// Return whether the buffer is empty, including the snapshot being flushed.
     pub fn is_empty(&self) -> bool {
-        self.nblocks() == 0
+        self.nblocks() == 0 && self.flushing.lock().is_empty()
     }
 
-    /// Empty the buffer.
+    /// Empty the buffer, including the snapshot being flushed.
     pub fn clear(&self) {
         let mut is_full = self.is_full.lock().unwrap();
         self.buf.lock().clear();
+        self.flushing.lock().clear();
         if *is_full {
             *is_full = false;
             self.cvar.notify_all();
         }
     }
 
-    /// Return all the buffered data blocks.
-    pub fn all_blocks(&self) -> Vec<(RecordKey, Arc<DataBlock>)> {
-        self.buf
-            .lock()
-            .iter()
-            .map(|(k, v)| (*k, v.clone()))
-            .collect()
+    /// Move all the buffered data blocks to a snapshot to be flushed,
+    /// return the blocks of the snapshot. The buffer becomes empty.
+    ///
+    /// At most one snapshot exists at a time, it must be dropped by
+    /// `drop_snapshot()` or put back by `restore_snapshot()` before
+    /// the next one is taken.
+    pub fn take_snapshot(&self) -> Vec<(RecordKey, Arc<DataBlock>)> {
+        let mut is_full = self.is_full.lock().unwrap();
+        let mut flushing = self.flushing.lock();
+        debug_assert!(flushing.is_empty());
+        *flushing = core::mem::take(&mut *self.buf.lock());
+        if *is_full {
+            *is_full = false;
+            self.cvar.notify_all();
+        }
+        flushing.iter().map(|(k, v)| (*k, v.clone())).collect()
+    }
+
+    /// Drop the snapshot, whose blocks are written and indexed.
+    pub fn drop_snapshot(&self) {
+        self.flushing.lock().clear();
+    }
+
+    /// Put the blocks of the snapshot back to the buffer, on failure of the
+    /// flush. The blocks buffered since the snapshot is taken are newer and
+    /// thus kept.
+    pub fn restore_snapshot(&self) {
+        let mut is_full = self.is_full.lock().unwrap();
+        let snapshot = core::mem::take(&mut *self.flushing.lock());
+        let mut data_buf = self.buf.lock();
+        for (k, v) in snapshot {
+            let _ = data_buf.entry(k).or_insert(v);
+        }
+        if data_buf.len() >= self.cap {
+            *is_full = true;
+        }
     }
 }
 
@@ -131,3 +191,53 @@ impl Debug for DataBlock {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::bio::Buf;
+
+    fn put_block(data_buf: &DataBuf, lba: usize, byte: u8) {
+        let mut buf = Buf::alloc(1).unwrap();
+        buf.as_mut_slice().fill(byte);
+        let _ = data_buf.put(RecordKey { lba }, buf.as_ref());
+    }
+
+    fn get_block(data_buf: &DataBuf, lba: usize) -> Option<u8> {
+        let mut buf = Buf::alloc(1).unwrap();
+        data_buf
+            .get(RecordKey { lba }, &mut buf.as_mut())
+            .map(|_| buf.as_slice()[0])
+    }
+
+    #[test]
+    fn data_buf_snapshot() {
+        let data_buf = DataBuf::new(4);
+        put_block(&data_buf, 0, 1);
+        put_block(&data_buf, 1, 1);
+
+        let snapshot = data_buf.take_snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(data_buf.nblocks(), 0);
+        assert!(!data_buf.is_empty());
+        // The snapshot stays readable, while newer blocks override it
+        put_block(&data_buf, 1, 2);
+        put_block(&data_buf, 2, 2);
+        assert_eq!(get_block(&data_buf, 0), Some(1));
+        assert_eq!(get_block(&data_buf, 1), Some(2));
+        let range = data_buf.get_range(RecordKey { lba: 0 }..=RecordKey { lba: 3 });
+        let lbas: Vec<_> = range.iter().map(|(k, _)| k.lba).collect();
+        assert_eq!(lbas, vec![0, 1, 2]);
+        assert_eq!(range[1].1.as_slice()[0], 2);
+
+        // A failed flush puts the older blocks back
+        data_buf.restore_snapshot();
+        assert_eq!(data_buf.nblocks(), 3);
+        assert_eq!(get_block(&data_buf, 1), Some(2));
+
+        let _ = data_buf.take_snapshot();
+        data_buf.drop_snapshot();
+        assert!(data_buf.is_empty());
+        assert_eq!(get_block(&data_buf, 0), None);
+    }
+}
@@ -0,0 +1,160 @@
+//! Digest of the whole logical disk for external consistency tooling.
+//!
+//! The digest is a Merkle root over the per-block MACs of the logical block
+//! table. The LBA space is split into fixed-size buckets, the leaf digest of
+//! each bucket is cached and only recomputed after the bucket changes.
+use super::sworndisk::Lba;
+use crate::os::{AeadMac as Mac, Mutex, Sha256};
+use crate::prelude::*;
+use crate::util::{Hash as _, SHA256_SIZE};
+
+use core::ops::Range;
+
+/// The number of logical blocks covered by a leaf of the digest tree.
+pub const DIGEST_BUCKET_NBLOCKS: usize = 4096;
+
+/// Domain separators of the leaves and the inner nodes.
+const LEAF_TAG: u8 = 0x00;
+const NODE_TAG: u8 = 0x01;
+
+/// An authenticated digest of the whole logical disk, taken by
+/// `SwornDisk::digest()` at a sync point.
+///
+/// Two disks (or two sync points of a disk) with the same digest map
+/// the same LBAs to blocks with the same MACs, i.e., the same ciphertexts
+/// under the same keys.
+///
+/// The root is computed as follows, with all integers in little endian:
+///
+/// ```text
+/// leaf = SHA256(0x00 || (lba: u64 || mac) of each mapped LBA in the bucket, ascending)
+/// node = SHA256(0x01 || left || right)
+/// ```
+///
+/// The leaves are the buckets of `DIGEST_BUCKET_NBLOCKS` LBAs in order.
+/// A node without sibling is promoted to the upper level as is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiskDigest {
+    /// The number of mapped logical blocks.
+    pub num_mapped: usize,
+    /// The Merkle root.
+    pub root: [u8; SHA256_SIZE],
+}
+
+/// The cached leaves of the digest, invalidated on each change
+/// of the logical block table.
+pub(super) struct DigestTree {
+    buckets: Mutex<Vec<BucketDigest>>,
+    total_blocks: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+struct BucketDigest {
+    // `None` if the bucket has changed since the last computation
+    digest: Option<[u8; SHA256_SIZE]>,
+    num_mapped: usize,
+    // Bumped on each invalidation, which tells whether a digest
+    // computed without holding the lock is still up to date
+    version: u64,
+}
+
+impl DigestTree {
+    /// Creates a digest tree of `total_blocks` logical blocks,
+    /// all of the buckets are to be computed.
+    pub fn new(total_blocks: usize) -> Self {
+        let nbuckets = total_blocks.div_ceil(DIGEST_BUCKET_NBLOCKS);
+        Self {
+            buckets: Mutex::new(vec![BucketDigest::default(); nbuckets]),
+            total_blocks,
+        }
+    }
+
+    /// Invalidates the buckets covering the given LBA range.
+    pub fn invalidate(&self, lba: Lba, nblocks: usize) {
+        if nblocks == 0 {
+            return;
+        }
+        let first = lba / DIGEST_BUCKET_NBLOCKS;
+        let last = (lba + nblocks - 1) / DIGEST_BUCKET_NBLOCKS;
+        let mut buckets = self.buckets.lock();
+        let last = last.min(buckets.len().saturating_sub(1));
+        for bucket in buckets.iter_mut().take(last + 1).skip(first) {
+            bucket.digest = None;
+            bucket.version += 1;
+        }
+    }
+
+    /// Invalidates the buckets covering each of the given LBAs.
+    pub fn invalidate_lbas(&self, lbas: impl Iterator<Item = Lba>) {
+        let mut buckets = self.buckets.lock();
+        for lba in lbas {
+            if let Some(bucket) = buckets.get_mut(lba / DIGEST_BUCKET_NBLOCKS) {
+                bucket.digest = None;
+                bucket.version += 1;
+            }
+        }
+    }
+
+    /// Computes the digest, where the invalidated buckets are recomputed
+    /// from the records returned by `read_bucket`, i.e., the mapped LBAs
+    /// within the given range and their MACs.
+    pub fn digest(
+        &self,
+        mut read_bucket: impl FnMut(Range<Lba>) -> Result<Vec<(Lba, Mac)>>,
+    ) -> Result<DiskDigest> {
+        let mut leaves: Vec<_> = self.buckets.lock().clone();
+
+        for (nth, leaf) in leaves.iter_mut().enumerate() {
+            if leaf.digest.is_some() {
+                continue;
+            }
+            let start = nth * DIGEST_BUCKET_NBLOCKS;
+            let end = (start + DIGEST_BUCKET_NBLOCKS).min(self.total_blocks);
+            let mut records = read_bucket(start..end)?;
+            records.sort_unstable_by_key(|(lba, _)| *lba);
+
+            let mut hasher = Sha256::new();
+            hasher.update(&[LEAF_TAG]);
+            for (lba, mac) in records.iter() {
+                hasher.update(&(*lba as u64).to_le_bytes());
+                hasher.update(&mac[..]);
+            }
+            leaf.digest = Some(hasher.finalize());
+            leaf.num_mapped = records.len();
+
+            // Keep the leaf only if no change comes during the computation
+            let mut buckets = self.buckets.lock();
+            if buckets[nth].version == leaf.version {
+                buckets[nth] = *leaf;
+            }
+        }
+
+        Ok(DiskDigest {
+            num_mapped: leaves.iter().map(|leaf| leaf.num_mapped).sum(),
+            root: merkle_root(leaves.iter().map(|leaf| leaf.digest.unwrap()).collect()),
+        })
+    }
+}
+
+fn merkle_root(mut level: Vec<[u8; SHA256_SIZE]>) -> [u8; SHA256_SIZE] {
+    if level.is_empty() {
+        return Sha256::new().finalize();
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = Sha256::new();
+                    hasher.update(&[NODE_TAG]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    hasher.finalize()
+                }
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
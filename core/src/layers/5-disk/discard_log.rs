@@ -0,0 +1,280 @@
+//! The log of discards.
+//!
+//! A discard deletes the records of the discarded blocks from the logical
+//! block table, so they are no longer told apart from the never-written ones.
+//! To tell the blocks discarded since a sync (see
+//! `SwornDisk::changed_blocks_since()`), each discarded range is kept as a
+//! range tombstone stamped with the sync ID which commits it. A tombstone
+//! overrides the overlapped parts of the older ones.
+//!
+//! The tombstones issued between two syncs are appended to a new log in the
+//! `DSCD` bucket of `TxLogStore` on the latter sync, and replayed from older
+//! to newer on open. Once the logs hold twice as many entries as the live
+//! tombstones, they are replaced by a single log of the live ones.
+use super::sworndisk::Lba;
+use crate::layers::bio::{BlockSet, Buf, BufRef};
+use crate::layers::log::{TxLogId, TxLogStore};
+use crate::layers::lsm::SyncId;
+use crate::os::{BTreeMap, Mutex};
+use crate::prelude::*;
+
+use core::mem::size_of;
+use core::ops::Range;
+use pod::Pod;
+
+/// The bucket name of discard logs.
+const BUCKET_DISCARD_LOG: &str = "DSCD";
+
+/// A range tombstone in the discard logs, whose range is never empty
+/// unless it's the padding of a block.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug, PartialEq, Eq)]
+struct Tombstone {
+    start: u64,
+    end: u64,
+    sync_id: SyncId,
+}
+
+impl Tombstone {
+    /// The number of tombstones in a block of the logs.
+    const PER_BLOCK: usize = BLOCK_SIZE / size_of::<Tombstone>();
+}
+
+/// The range tombstones of the discards of a disk.
+pub(super) struct DiscardLog<D> {
+    store: Arc<TxLogStore<D>>,
+    state: Mutex<DiscardLogState>,
+    /// Whether the tombstones are never persisted, see `Config::ephemeral`.
+    ephemeral: bool,
+}
+
+struct DiscardLogState {
+    /// The live tombstones keyed by their starts, which never overlap.
+    /// Each maps to the end of its range and its sync ID.
+    tombstones: BTreeMap<Lba, (Lba, SyncId)>,
+    /// The tombstones issued since the last persistence, in order.
+    unlogged: Vec<Tombstone>,
+    /// The number of tombstones in the logs.
+    num_logged: usize,
+}
+
+impl<D: BlockSet + 'static> DiscardLog<D> {
+    /// Creates an empty `DiscardLog` on the given `TxLogStore`.
+    pub fn new(store: Arc<TxLogStore<D>>, ephemeral: bool) -> Self {
+        Self {
+            store,
+            state: Mutex::new(DiscardLogState {
+                tombstones: BTreeMap::new(),
+                unlogged: Vec::new(),
+                num_logged: 0,
+            }),
+            ephemeral,
+        }
+    }
+
+    /// Recovers the `DiscardLog` from the logs in the given `TxLogStore`.
+    pub fn recover(store: Arc<TxLogStore<D>>) -> Result<Self> {
+        let discard_log = Self::new(store, false);
+        let store = &discard_log.store;
+        let mut tx = store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            let mut state = discard_log.state.lock();
+            for log_id in Self::list_logs(store)? {
+                let log = store.open_log(log_id, false)?;
+                let mut buf = Buf::alloc(log.nblocks())?;
+                log.read(0 as BlockId, buf.as_mut())?;
+                for block in buf.as_slice().chunks(BLOCK_SIZE) {
+                    for entry in block[..Tombstone::PER_BLOCK * size_of::<Tombstone>()]
+                        .chunks(size_of::<Tombstone>())
+                    {
+                        let tombstone = Tombstone::from_bytes(entry);
+                        if tombstone.start >= tombstone.end {
+                            continue;
+                        }
+                        state.insert(
+                            tombstone.start as Lba..tombstone.end as Lba,
+                            tombstone.sync_id,
+                        );
+                        state.num_logged += 1;
+                    }
+                }
+            }
+            Ok(())
+        });
+        if res.is_err() {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "recover discard log TX aborted");
+        }
+        tx.commit()?;
+        Ok(discard_log)
+    }
+
+    /// Records a discard of `range`, which is committed by the sync of `sync_id`.
+    pub fn record(&self, range: Range<Lba>, sync_id: SyncId) {
+        debug_assert!(!range.is_empty());
+        let mut state = self.state.lock();
+        state.insert(range.clone(), sync_id);
+        if self.ephemeral {
+            return;
+        }
+        state.unlogged.push(Tombstone {
+            start: range.start as _,
+            end: range.end as _,
+            sync_id,
+        });
+    }
+
+    /// Returns the ranges discarded since the sync of `sync_id`, merged
+    /// and in ascending order.
+    pub fn discarded_since(&self, sync_id: SyncId) -> Vec<Range<Lba>> {
+        let state = self.state.lock();
+        let mut ranges: Vec<Range<Lba>> = Vec::new();
+        for (&start, &(end, discard_sync_id)) in state.tombstones.iter() {
+            if discard_sync_id <= sync_id {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+        ranges
+    }
+
+    /// Persists the tombstones issued since the last persistence, which
+    /// are durable once the `TxLogStore` is synced.
+    pub fn persist(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        if state.unlogged.is_empty() {
+            return Ok(());
+        }
+        let num_logged = state.num_logged + state.unlogged.len();
+        let is_rewritten = num_logged > 2 * state.tombstones.len() + Tombstone::PER_BLOCK;
+        let tombstones: Vec<_> = if is_rewritten {
+            state
+                .tombstones
+                .iter()
+                .map(|(&start, &(end, sync_id))| Tombstone {
+                    start: start as _,
+                    end: end as _,
+                    sync_id,
+                })
+                .collect()
+        } else {
+            state.unlogged.clone()
+        };
+
+        let nblocks = tombstones.len().div_ceil(Tombstone::PER_BLOCK).max(1);
+        let mut buf = Buf::alloc(nblocks)?;
+        for (block, chunk) in buf
+            .as_mut_slice()
+            .chunks_mut(BLOCK_SIZE)
+            .zip(tombstones.chunks(Tombstone::PER_BLOCK))
+        {
+            for (entry, tombstone) in block.chunks_mut(size_of::<Tombstone>()).zip(chunk) {
+                entry.copy_from_slice(tombstone.as_bytes());
+            }
+        }
+
+        let store = &self.store;
+        let mut tx = store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            if is_rewritten {
+                for log_id in Self::list_logs(store)? {
+                    store.delete_log(log_id)?;
+                }
+            }
+            let log = store.create_log(BUCKET_DISCARD_LOG)?;
+            log.append(BufRef::try_from(buf.as_slice()).unwrap())
+        });
+        if res.is_err() {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "persist discard log TX aborted");
+        }
+        tx.commit()?;
+
+        state.num_logged = if is_rewritten {
+            tombstones.len()
+        } else {
+            num_logged
+        };
+        state.unlogged.clear();
+        Ok(())
+    }
+
+    /// Lists the discard logs in the store, from older to newer.
+    ///
+    /// # Panics
+    ///
+    /// This method must be called within a TX. Otherwise, this method panics.
+    fn list_logs(store: &Arc<TxLogStore<D>>) -> Result<Vec<TxLogId>> {
+        let mut log_ids = match store.list_logs_in(BUCKET_DISCARD_LOG) {
+            Ok(log_ids) => log_ids,
+            Err(e) if e.errno() == NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        log_ids.sort();
+        Ok(log_ids)
+    }
+}
+
+impl DiscardLogState {
+    /// Inserts a tombstone of `range`, trimming or splitting the older
+    /// ones it overlaps.
+    fn insert(&mut self, range: Range<Lba>, sync_id: SyncId) {
+        let overlapped: Vec<_> = self
+            .tombstones
+            .range(..range.end)
+            .rev()
+            .take_while(|(_, &(end, _))| end > range.start)
+            .map(|(&start, &(end, sync_id))| (start, end, sync_id))
+            .collect();
+        for (start, end, old_sync_id) in overlapped {
+            self.tombstones.remove(&start);
+            if start < range.start {
+                self.tombstones.insert(start, (range.start, old_sync_id));
+            }
+            if end > range.end {
+                self.tombstones.insert(range.end, (end, old_sync_id));
+            }
+        }
+        self.tombstones.insert(range.start, (range.end, sync_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::bio::MemDisk;
+    use crate::os::AeadKey as Key;
+
+    #[test]
+    fn discard_log() -> Result<()> {
+        let mem_disk = MemDisk::create(4 * 1024)?;
+        let root_key = Key::random();
+        let store = Arc::new(TxLogStore::format(mem_disk.clone(), root_key.clone())?);
+        let discard_log = DiscardLog::new(store.clone(), false);
+        discard_log.record(10..20, 1);
+        discard_log.record(30..40, 2);
+        // Overrides the overlapped parts of both
+        discard_log.record(15..35, 3);
+        assert_eq!(discard_log.discarded_since(0), vec![10..40]);
+        assert_eq!(discard_log.discarded_since(1), vec![15..40]);
+        assert_eq!(discard_log.discarded_since(2), vec![15..35]);
+        assert!(discard_log.discarded_since(3).is_empty());
+
+        discard_log.persist()?;
+        discard_log.record(0..12, 4);
+        discard_log.persist()?;
+        store.sync()?;
+        drop(discard_log);
+        drop(store);
+
+        let store = Arc::new(TxLogStore::recover(mem_disk, root_key)?);
+        let discard_log = DiscardLog::recover(store)?;
+        assert_eq!(discard_log.discarded_since(0), vec![0..40]);
+        assert_eq!(discard_log.discarded_since(2), vec![0..12, 15..35]);
+        assert_eq!(discard_log.discarded_since(3), vec![0..12]);
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//! Deferred discards, see `Config::discard_granularity`.
+use super::sworndisk::Lba;
+use crate::os::{BTreeMap, Mutex};
+use crate::prelude::*;
+
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// A queue of the discards deferred to be issued in larger extents.
+///
+/// A queued discard is merged with the adjacent and overlapping ones, so that
+/// a guest file system trimming many small extents costs a few range deletions
+/// of the logical block table, rather than one per extent.
+pub(super) struct DiscardQueue {
+    /// The pending extents keyed by their starts, neither overlapping
+    /// nor adjacent to each other.
+    extents: Mutex<BTreeMap<Lba, Lba>>,
+    /// The number of pending extents, checked without the lock.
+    len: AtomicUsize,
+    num_requests: AtomicU64,
+    num_issued: AtomicU64,
+    num_blocks: AtomicU64,
+}
+
+/// The statistics of the discards of a disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiscardStats {
+    /// The number of discarded extents requested.
+    pub num_requests: u64,
+    /// The number of the (merged) extents issued to the logical block table.
+    pub num_issued: u64,
+    /// The number of blocks of the issued extents.
+    pub num_blocks: u64,
+    /// The number of extents pending in the queue.
+    pub num_pending: usize,
+}
+
+impl DiscardQueue {
+    pub fn new() -> Self {
+        Self {
+            extents: Mutex::new(BTreeMap::new()),
+            len: AtomicUsize::new(0),
+            num_requests: AtomicU64::new(0),
+            num_issued: AtomicU64::new(0),
+            num_blocks: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues an extent, merged with the pending ones. The merged extent is
+    /// taken out of the queue and returned if it reaches `min_nblocks`.
+    pub fn push(&self, extent: Range<Lba>, min_nblocks: usize) -> Option<Range<Lba>> {
+        debug_assert!(!extent.is_empty());
+        let mut extents = self.extents.lock();
+        let (mut start, mut end) = (extent.start, extent.end);
+        if let Some((&prev_start, &prev_end)) = extents.range(..=start).next_back()
+            && prev_end >= start
+        {
+            start = prev_start;
+            end = end.max(prev_end);
+        }
+        let merged: Vec<_> = extents
+            .range(start..=end)
+            .map(|(&start, &end)| (start, end))
+            .collect();
+        for (next_start, next_end) in merged {
+            extents.remove(&next_start);
+            end = end.max(next_end);
+        }
+
+        if end - start < min_nblocks {
+            extents.insert(start, end);
+        }
+        self.len.store(extents.len(), Ordering::Relaxed);
+        (end - start >= min_nblocks).then_some(start..end)
+    }
+
+    /// Takes the pending extents overlapping `range` out of the queue.
+    pub fn take_overlapping(&self, range: Range<Lba>) -> Vec<Range<Lba>> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let mut extents = self.extents.lock();
+        let mut overlapping: Vec<_> = extents
+            .range(..range.end)
+            .rev()
+            .take_while(|(_, &end)| end > range.start)
+            .map(|(&start, &end)| start..end)
+            .collect();
+        for extent in overlapping.iter() {
+            extents.remove(&extent.start);
+        }
+        self.len.store(extents.len(), Ordering::Relaxed);
+        overlapping.reverse();
+        overlapping
+    }
+
+    /// Takes all the pending extents out of the queue.
+    pub fn take_all(&self) -> Vec<Range<Lba>> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let mut extents = self.extents.lock();
+        self.len.store(0, Ordering::Relaxed);
+        core::mem::take(&mut *extents)
+            .into_iter()
+            .map(|(start, end)| start..end)
+            .collect()
+    }
+
+    /// Returns whether no extent is pending.
+    pub fn is_empty(&self) -> bool {
+        self.len.load(Ordering::Relaxed) == 0
+    }
+
+    /// Records the requests of discarding `nextents` extents.
+    pub fn record_requests(&self, nextents: usize) {
+        self.num_requests
+            .fetch_add(nextents as u64, Ordering::Relaxed);
+    }
+
+    /// Records an issued extent.
+    pub fn record_issued(&self, extent: &Range<Lba>) {
+        self.num_issued.fetch_add(1, Ordering::Relaxed);
+        self.num_blocks
+            .fetch_add(extent.len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> DiscardStats {
+        DiscardStats {
+            num_requests: self.num_requests.load(Ordering::Relaxed),
+            num_issued: self.num_issued.load(Ordering::Relaxed),
+            num_blocks: self.num_blocks.load(Ordering::Relaxed),
+            num_pending: self.len.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Merges the adjacent and overlapping extents, returns the merged ones in
+/// ascending order.
+pub(super) fn merge_extents(extents: &[Range<Lba>]) -> Vec<Range<Lba>> {
+    let mut extents: Vec<_> = extents
+        .iter()
+        .filter(|extent| !extent.is_empty())
+        .cloned()
+        .collect();
+    extents.sort_unstable_by_key(|extent| extent.start);
+    let mut merged: Vec<Range<Lba>> = Vec::with_capacity(extents.len());
+    for extent in extents {
+        match merged.last_mut() {
+            Some(last) if last.end >= extent.start => last.end = last.end.max(extent.end),
+            _ => merged.push(extent),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_extents, DiscardQueue};
+
+    #[test]
+    fn discard_queue() {
+        let queue = DiscardQueue::new();
+        assert!(queue.take_all().is_empty());
+        assert_eq!(queue.push(10..12, 8), None);
+        assert_eq!(queue.push(20..22, 8), None);
+        assert_eq!(queue.push(30..32, 8), None);
+        // Merged with the adjacent ones
+        assert_eq!(queue.push(12..14, 8), None);
+        assert_eq!(queue.push(18..20, 8), None);
+        assert_eq!(queue.stats().num_pending, 3);
+
+        assert_eq!(queue.take_overlapping(13..19), vec![10..14, 18..22]);
+        assert_eq!(queue.take_overlapping(32..40), vec![]);
+        // Reaches the granularity once merged
+        assert_eq!(queue.push(24..31, 8), Some(24..32));
+        assert!(queue.is_empty());
+
+        queue.push(5..6, 8);
+        queue.push(1..2, 8);
+        assert_eq!(queue.take_all(), vec![1..2, 5..6]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn merge_discard_extents() {
+        assert_eq!(
+            merge_extents(&[8..10, 0..2, 2..4, 9..12, 5..5, 6..7]),
+            vec![0..4, 6..7, 8..12]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Statistics of a `SwornDisk` instance.
+//!
+//! Each `SwornDisk` records its WAF, GC and block I/O statistics into its
+//! own `DiskStats`, so that concurrent instances (e.g., in tests) never see
+//! each other's numbers. The global statistics (`WAF_STATS`, `GC_STATS` and
+//! `BIO_STATS`) remain as aggregation points of all the instances that
+//! enable `Config::aggregate_stats`.
+use super::bio_stats::{BioStats, BIO_STATS};
+use super::gc_stats::{GcStats, GC_STATS};
+use super::waf_stats::{WafStats, WAF_STATS};
+
+/// The WAF, GC and block I/O statistics of a `SwornDisk` instance.
+pub struct DiskStats {
+    waf: WafStats,
+    gc: GcStats,
+    bio: BioStats,
+    /// Whether to record to the global statistics as well.
+    aggregate: bool,
+}
+
+impl DiskStats {
+    /// Create a new `DiskStats`, which also records to the global
+    /// statistics if `aggregate` is true.
+    pub const fn new(aggregate: bool) -> Self {
+        Self {
+            waf: WafStats::new(),
+            gc: GcStats::new(),
+            bio: BioStats::new(),
+            aggregate,
+        }
+    }
+
+    /// Returns the WAF statistics of the instance.
+    pub fn waf(&self) -> &WafStats {
+        &self.waf
+    }
+
+    /// Returns the GC statistics of the instance.
+    pub fn gc(&self) -> &GcStats {
+        &self.gc
+    }
+
+    /// Returns the block I/O statistics of the instance.
+    pub fn bio(&self) -> &BioStats {
+        &self.bio
+    }
+
+    /// Reset all statistics of the instance, the global ones are untouched.
+    pub fn reset(&self) {
+        self.waf.reset();
+        self.gc.reset();
+        self.bio.reset();
+    }
+
+    /// Record to the WAF statistics of the instance and the global one.
+    pub(super) fn record_waf(&self, record: impl Fn(&WafStats)) {
+        record(&self.waf);
+        if self.aggregate {
+            record(&WAF_STATS);
+        }
+    }
+
+    /// Record to the GC statistics of the instance and the global one.
+    pub(super) fn record_gc(&self, record: impl Fn(&GcStats)) {
+        record(&self.gc);
+        if self.aggregate {
+            record(&GC_STATS);
+        }
+    }
+
+    /// Record to the block I/O statistics of the instance and the global one.
+    pub(super) fn record_bio(&self, record: impl Fn(&BioStats)) {
+        record(&self.bio);
+        if self.aggregate {
+            record(&BIO_STATS);
+        }
+    }
+}
+
+impl Default for DiskStats {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod tests {
+    use super::DiskStats;
+    use crate::layers::disk::GC_STATS;
+
+    #[test]
+    fn disk_stats_isolated() {
+        let stats = DiskStats::new(false);
+        let other = DiskStats::new(false);
+        stats.record_gc(|gc| gc.record_missing_record());
+        stats.record_waf(|waf| waf.add_logical(4096));
+        assert_eq!(stats.gc().get_stats().missing_records, 1);
+        assert_eq!(stats.waf().get_logical(), 4096);
+        assert_eq!(other.gc().get_stats().missing_records, 0);
+        assert_eq!(other.waf().get_logical(), 0);
+
+        // Aggregated to the global one, which may be touched by other tests
+        let before = GC_STATS.get_stats().missing_records;
+        DiskStats::new(true).record_gc(|gc| gc.record_missing_record());
+        assert!(GC_STATS.get_stats().missing_records > before);
+    }
+}
@@ -0,0 +1,263 @@
+//! Notifications of the events of `SwornDisk` to upper layers.
+//!
+//! An upper layer, e.g., a cache keyed by HBA, subscribes with
+//! `SwornDisk::subscribe_events()` to learn when GC moves the blocks or
+//! the disk changes in the background. The events are queued and delivered
+//! in order on a dedicated callback thread, which is spawned on the first
+//! subscription, so that a slow subscriber never stalls I/O or GC.
+//!
+//! A cache of HBA-derived state, e.g., the mappings of `iter_mappings()`,
+//! must not outlive a GC round, since the freed HBAs are reused by the
+//! following writes. Such a cache subscribes with
+//! `SwornDisk::subscribe_events_sync()` instead, whose subscribers are called
+//! on the emitting thread. `DiskEvent::BlocksMigrated` is emitted once the
+//! remapping commits, and before GC lets the foreground I/O proceed, so the
+//! cache drops or refreshes the migrated LBAs before any freed HBA is reused.
+//! The caches inside `SwornDisk` are coherent by themselves: `DataBuf` is
+//! keyed by LBA, and a read racing with a remapping reads the block again at
+//! its new HBA.
+use super::sworndisk::{Hba, Lba};
+use crate::layers::lsm::SyncId;
+use crate::os::{spawn, Arc, Condvar, CvarMutex};
+use crate::prelude::*;
+
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A user data block moved by GC or defragmentation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockMigration {
+    pub lba: Lba,
+    pub old_hba: Hba,
+    pub new_hba: Hba,
+    /// Whether the block is re-encrypted with a fresh key on migration,
+    /// see `Config::reencrypt_on_gc`.
+    pub rekeyed: bool,
+}
+
+/// An event of `SwornDisk`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiskEvent {
+    /// The blocks are migrated, and the migration is committed.
+    BlocksMigrated(Vec<BlockMigration>),
+    /// The blocks in `lba..lba + nblocks` are discarded.
+    Discarded { lba: Lba, nblocks: usize },
+    /// The root key is re-wrapped with a new key provider.
+    RootKeyRewrapped,
+    /// A sync commits a new consistent snapshot of the disk, which is
+    /// identified by `sync_id` (see `SwornDisk::changed_blocks_since()`).
+    SnapshotCreated { sync_id: SyncId },
+}
+
+/// A subscriber of the events of `SwornDisk`.
+pub trait EventSubscriber: Send + Sync {
+    /// Called on the callback thread for each event, in the order of the
+    /// events. It may call back into `SwornDisk`.
+    ///
+    /// A synchronous subscriber is called on the emitting thread instead,
+    /// it must return quickly and must not do I/O on `SwornDisk`, which
+    /// waits for the ongoing GC.
+    fn on_event(&self, event: &DiskEvent);
+}
+
+pub type EventSubscriberRef = Arc<dyn EventSubscriber>;
+
+/// The ID of a subscription, see `SwornDisk::unsubscribe_events()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// The queue of events, drained by the callback thread.
+pub(super) struct EventBus {
+    shared: Arc<EventQueue>,
+    /// Whether there is any subscriber, to skip the events quickly if not.
+    has_subscribers: AtomicBool,
+}
+
+struct EventQueue {
+    state: CvarMutex<EventQueueState>,
+    cvar: Condvar,
+}
+
+struct EventQueueState {
+    events: VecDeque<DiskEvent>,
+    subscribers: Vec<(SubscriptionId, EventSubscriberRef)>,
+    sync_subscribers: Vec<(SubscriptionId, EventSubscriberRef)>,
+    next_id: u64,
+    is_started: bool,
+    is_closed: bool,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(EventQueue {
+                state: CvarMutex::new(EventQueueState {
+                    events: VecDeque::new(),
+                    subscribers: Vec::new(),
+                    sync_subscribers: Vec::new(),
+                    next_id: 0,
+                    is_started: false,
+                    is_closed: false,
+                }),
+                cvar: Condvar::new(),
+            }),
+            has_subscribers: AtomicBool::new(false),
+        }
+    }
+
+    /// Adds a subscriber, starts the callback thread if not yet.
+    pub fn subscribe(&self, subscriber: EventSubscriberRef) -> SubscriptionId {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.alloc_id();
+        state.subscribers.push((id, subscriber));
+        self.has_subscribers.store(true, Ordering::Release);
+
+        if !state.is_started {
+            state.is_started = true;
+            let shared = self.shared.clone();
+            let _ = spawn(move || shared.deliver_events());
+        }
+        id
+    }
+
+    /// Adds a synchronous subscriber, which is called by `emit()` itself.
+    pub fn subscribe_sync(&self, subscriber: EventSubscriberRef) -> SubscriptionId {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.alloc_id();
+        state.sync_subscribers.push((id, subscriber));
+        self.has_subscribers.store(true, Ordering::Release);
+        id
+    }
+
+    /// Removes a subscriber, returns whether it's subscribed. The events
+    /// being delivered may still reach it.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let num_subscribers = state.subscribers.len() + state.sync_subscribers.len();
+        state.subscribers.retain(|(sub_id, _)| *sub_id != id);
+        state.sync_subscribers.retain(|(sub_id, _)| *sub_id != id);
+        self.has_subscribers.store(
+            !state.subscribers.is_empty() || !state.sync_subscribers.is_empty(),
+            Ordering::Release,
+        );
+        state.subscribers.len() + state.sync_subscribers.len() < num_subscribers
+    }
+
+    /// Returns whether there is any subscriber.
+    pub fn is_subscribed(&self) -> bool {
+        self.has_subscribers.load(Ordering::Acquire)
+    }
+
+    /// Queues an event for the subscribers, it's dropped if there is none.
+    /// The synchronous subscribers have been called once it returns.
+    pub fn emit(&self, event: DiskEvent) {
+        if !self.is_subscribed() {
+            return;
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        let sync_subscribers: Vec<_> = state
+            .sync_subscribers
+            .iter()
+            .map(|(_, subscriber)| subscriber.clone())
+            .collect();
+        if !state.subscribers.is_empty() {
+            state.events.push_back(event.clone());
+            self.shared.cvar.notify_one();
+        }
+        drop(state);
+        for subscriber in sync_subscribers {
+            subscriber.on_event(&event);
+        }
+    }
+}
+
+impl Drop for EventBus {
+    // The callback thread exits once the queued events are delivered
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().is_closed = true;
+        self.shared.cvar.notify_one();
+    }
+}
+
+impl EventQueueState {
+    fn alloc_id(&mut self) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+}
+
+impl EventQueue {
+    /// The loop of the callback thread.
+    fn deliver_events(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let Some(event) = state.events.pop_front() else {
+                if state.is_closed {
+                    return;
+                }
+                state = self.cvar.wait(state).unwrap();
+                continue;
+            };
+            // Call the subscribers without the lock, so that they
+            // may subscribe or emit events themselves
+            let subscribers: Vec<_> = state
+                .subscribers
+                .iter()
+                .map(|(_, subscriber)| subscriber.clone())
+                .collect();
+            drop(state);
+            for subscriber in subscribers {
+                subscriber.on_event(&event);
+            }
+            state = self.state.lock().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiskEvent, EventBus, EventSubscriber};
+    use crate::os::Arc;
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    struct ChannelSubscriber(Mutex<Sender<DiskEvent>>);
+
+    impl EventSubscriber for ChannelSubscriber {
+        fn on_event(&self, event: &DiskEvent) {
+            let _ = self.0.lock().unwrap().send(event.clone());
+        }
+    }
+
+    #[test]
+    fn event_bus_delivery() {
+        let bus = EventBus::new();
+        // Dropped without subscribers
+        bus.emit(DiskEvent::RootKeyRewrapped);
+
+        let (sender, receiver) = channel();
+        let id = bus.subscribe(Arc::new(ChannelSubscriber(Mutex::new(sender))));
+        for lba in 0..3 {
+            bus.emit(DiskEvent::Discarded { lba, nblocks: 1 });
+        }
+        for lba in 0..3 {
+            let event = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(event, DiskEvent::Discarded { lba, nblocks: 1 });
+        }
+
+        assert!(bus.unsubscribe(id));
+        assert!(!bus.unsubscribe(id));
+        bus.emit(DiskEvent::RootKeyRewrapped);
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+
+        // Called before `emit()` returns
+        let (sender, receiver) = channel();
+        let id = bus.subscribe_sync(Arc::new(ChannelSubscriber(Mutex::new(sender))));
+        bus.emit(DiskEvent::RootKeyRewrapped);
+        assert_eq!(receiver.try_recv(), Ok(DiskEvent::RootKeyRewrapped));
+        assert!(bus.unsubscribe(id));
+        assert!(!bus.is_subscribed());
+    }
+}
@@ -0,0 +1,43 @@
+//! Freshness of `SwornDisk` against rollback attacks.
+//!
+//! Authenticated encryption keeps a disk image confidential and consistent,
+//! but a malicious host can still replace the whole image with an older,
+//! consistent one. With a `TrustedCounter`, each `sync()` advances a freshness
+//! counter sealed in the superblock, then advances the trusted counter to it.
+//! `open()` rejects the image whose sealed counter is behind the trusted one.
+use crate::os::Arc;
+use crate::prelude::*;
+
+/// A trusted monotonic counter, e.g., a TPM NV counter or a counter kept
+/// by a remote trusted service, which the host can't roll back.
+pub trait TrustedCounter: Send + Sync {
+    /// Read the current value of the counter.
+    fn read(&self) -> Result<u64>;
+
+    /// Advance the counter to the given value, which is never less than
+    /// the current one.
+    fn advance(&self, value: u64) -> Result<()>;
+}
+
+pub type TrustedCounterRef = Arc<dyn TrustedCounter>;
+
+/// Check the freshness counter sealed in the superblock against the trusted
+/// counter on `open()`.
+///
+/// The sealed counter may be ahead of the trusted one if the disk crashed
+/// between persisting the superblock and advancing the trusted counter,
+/// the trusted counter catches up in this case, unless the disk is opened read-only.
+pub(super) fn check_freshness(
+    sealed: u64,
+    counter: &dyn TrustedCounter,
+    read_only: bool,
+) -> Result<()> {
+    let trusted = counter.read()?;
+    if sealed < trusted {
+        return_errno_with_msg!(RolledBack, "disk image is older than the trusted counter");
+    }
+    if sealed > trusted && !read_only {
+        counter.advance(sealed)?;
+    }
+    Ok(())
+}
@@ -1,16 +1,27 @@
 use super::{
     block_alloc::{AllocTable, BlockAlloc},
+    config::Config,
     dealloc_block::DeallocTable,
+    digest::DigestTree,
+    disk_stats::DiskStats,
+    events::{BlockMigration, DiskEvent, EventBus},
+    rate_limit::BackgroundIoLimiter,
+    reverse_index::ReverseIndex,
     segment::{Segment, SegmentId},
-    sworndisk::{Hba, Lba, RecordKey, RecordValue},
+    superblock::DiskId,
+    sworndisk::{BlockAad, Hba, Lba, RecordKey, RecordValue},
 };
+#[cfg(feature = "sim")]
+use crate::os::{SimClock, WaitPoint};
 use crate::{
     layers::{
         disk::segment::SEGMENT_SIZE,
-        lsm::{RecordKey as RecordK, RecordValue as RecordV, TxLsmTree},
+        lsm::{
+            CompactionScheduler, LsmLevel, RecordKey as RecordK, RecordValue as RecordV, TxLsmTree,
+        },
     },
     tx::TxProvider,
-    BlockSet, Error,
+    BlockSet, CostL3Type, Errno, Error, RandomInit, COST_L3,
 };
 use crate::{
     layers::{
@@ -18,20 +29,24 @@ use crate::{
         log::TxLogStore,
     },
     prelude::Result,
-    Buf, BLOCK_SIZE,
+    Buf, BufMut, BufRef, BLOCK_SIZE,
 };
 use crate::{
-    os::{sleep, Arc, BTreeMap, Condvar, CvarMutex, Mutex, Vec},
+    os::{
+        AeadBackendRef, AeadIv as Iv, AeadKey as Key, AeadMac as Mac, Arc, BTreeMap,
+        BackgroundTask, Condvar, CvarMutex, Mutex, TaskContext, Vec,
+    },
     prelude,
 };
 use core::{
+    num::NonZeroUsize,
     ops::{Add, Sub},
     sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU64, AtomicUsize, Ordering},
     time::Duration,
     usize,
 };
 use hashbrown::{HashMap, HashSet};
-use log::debug;
+use log::{debug, warn};
 use pod::Pod;
 // Default gc interval time is 30 seconds
 const ACTIVE_GC_INTERVAL_TIME: core::time::Duration = core::time::Duration::from_secs(5);
@@ -39,6 +54,17 @@ const INACTIVE_GC_INTERVAL_TIME: core::time::Duration = core::time::Duration::fr
 const GC_WATERMARK: usize = 16;
 const ACTIVE_GC_THRESHOLD: f64 = 0.6;
 const INACTIVE_GC_THRESHOLD: f64 = 0.1;
+// The I/O rate (in blocks per second) at which the foreground is half active,
+// the interval and the threshold of GC are in the middle of their ranges
+const HALF_ACTIVE_IO_RATE: f64 = 1024.0;
+// The foreground is regarded as idle below this activity
+const IDLE_ACTIVITY: f64 = 0.05;
+// Number of LBAs scanned by a round of defragmentation
+const DEFRAG_WINDOW: usize = SEGMENT_SIZE;
+// The interval until the retry of a failed GC round, doubled on each
+// failure in a row up to the maximum
+const GC_RETRY_INTERVAL_TIME: Duration = Duration::from_millis(100);
+const MAX_GC_RETRY_INTERVAL_TIME: Duration = Duration::from_secs(60);
 
 #[repr(C)]
 #[derive(Clone, Copy, Pod, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
@@ -77,6 +103,10 @@ impl RecordV for ReverseValue {}
 // 1. Background GC will stop the world, I/O requests and lsm compaction will be blocked
 // 2. Background GC should wait until lsm compaction are done
 // TODO: 3. Should background GC wait for all I/O requests to finished?
+// 4. Major compactions are deferred while the foreground is latency-critical,
+//    and done by the GC worker when the foreground is idle
+// 5. Compactions triggered by the foreground take priority over background GC,
+//    no GC section begins while any of them is pending
 
 pub type SharedStateRef = Arc<SharedState>;
 pub struct SharedState {
@@ -84,6 +114,20 @@ pub struct SharedState {
     compaction_in_progress: CvarMutex<bool>,
     gc_condvar: Condvar,
     compaction_condvar: Condvar,
+    // The number of pending compactions prioritized over GC
+    prioritized_compactions: AtomicUsize,
+    latency_critical: AtomicBool,
+    // The number of latency-critical reads in service, which GC yields to
+    urgent_reads: AtomicUsize,
+    // The virtual clock of the background tasks of the disk, and the
+    // points counting the threads waiting for GC and compaction, in
+    // simulation mode
+    #[cfg(feature = "sim")]
+    sim_clock: Arc<SimClock>,
+    #[cfg(feature = "sim")]
+    gc_wait_point: WaitPoint,
+    #[cfg(feature = "sim")]
+    compaction_wait_point: WaitPoint,
 }
 
 impl SharedState {
@@ -93,6 +137,15 @@ impl SharedState {
             compaction_in_progress: CvarMutex::new(false),
             gc_condvar: Condvar::new(),
             compaction_condvar: Condvar::new(),
+            prioritized_compactions: AtomicUsize::new(0),
+            latency_critical: AtomicBool::new(false),
+            urgent_reads: AtomicUsize::new(0),
+            #[cfg(feature = "sim")]
+            sim_clock: Arc::new(SimClock::new()),
+            #[cfg(feature = "sim")]
+            gc_wait_point: WaitPoint::new(),
+            #[cfg(feature = "sim")]
+            compaction_wait_point: WaitPoint::new(),
         }
     }
 
@@ -102,20 +155,65 @@ impl SharedState {
         while *gc_in_progress {
             #[cfg(not(feature = "linux"))]
             debug!("Waiting for background GC to finish");
+            #[cfg(feature = "sim")]
+            self.gc_wait_point.enter();
             gc_in_progress = self.gc_condvar.wait(gc_in_progress).unwrap();
+            #[cfg(feature = "sim")]
+            self.gc_wait_point.leave();
+        }
+    }
+
+    // Wait for background GC like `wait_for_background_gc`, but for at most
+    // `timeout`. Returns whether GC is finished (or not in progress). Without
+    // `std`, the wait is untimed and always returns true
+    pub fn wait_for_background_gc_timeout(&self, timeout: Duration) -> bool {
+        #[cfg(feature = "std")]
+        {
+            let deadline = std::time::Instant::now() + timeout;
+            let mut gc_in_progress = self.gc_in_progress.lock().unwrap();
+            while *gc_in_progress {
+                let now = std::time::Instant::now();
+                if now >= deadline {
+                    return false;
+                }
+                gc_in_progress = self
+                    .gc_condvar
+                    .wait_timeout(gc_in_progress, deadline - now)
+                    .unwrap()
+                    .0;
+            }
+            true
+        }
+        #[cfg(not(feature = "std"))]
+        {
+            let _ = timeout;
+            self.wait_for_background_gc();
+            true
         }
     }
 
+    pub fn is_gc_in_progress(&self) -> bool {
+        *self.gc_in_progress.lock().unwrap()
+    }
+
+    pub fn is_compaction_in_progress(&self) -> bool {
+        *self.compaction_in_progress.lock().unwrap()
+    }
+
     // Background GC will call this function to wait for compaction finished
     pub fn wait_for_compaction(&self) {
         let mut compaction_in_progress = self.compaction_in_progress.lock().unwrap();
         while *compaction_in_progress {
             #[cfg(not(feature = "linux"))]
             debug!("Waiting for compaction to finish");
+            #[cfg(feature = "sim")]
+            self.compaction_wait_point.enter();
             compaction_in_progress = self
                 .compaction_condvar
                 .wait(compaction_in_progress)
                 .unwrap();
+            #[cfg(feature = "sim")]
+            self.compaction_wait_point.leave();
         }
     }
 
@@ -124,6 +222,49 @@ impl SharedState {
         *gc_in_progress = true;
     }
 
+    // Start GC and return a guard that notifies GC finished when dropped,
+    // even if GC fails or panics, so that foreground I/O is never blocked forever.
+    // GC sections exclude each other, e.g., a forced GC and the background one.
+    // It also waits for the prioritized compactions (see `prioritize_compaction`)
+    pub fn begin_gc(&self) -> GcGuard<'_> {
+        self.enter_gc();
+        GcGuard { shared_state: self }
+    }
+
+    // Suspend the GC section held by the caller while running `f`, e.g., to
+    // wait for the budget of background I/Os, so that the foreground isn't
+    // blocked meanwhile. The section is resumed the same as `begin_gc`
+    pub fn suspend_gc<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.notify_gc_finished();
+        let res = f();
+        self.enter_gc();
+        res
+    }
+
+    fn enter_gc(&self) {
+        let mut gc_in_progress = self.gc_in_progress.lock().unwrap();
+        while *gc_in_progress || self.prioritized_compactions.load(Ordering::Acquire) > 0 {
+            gc_in_progress = self.gc_condvar.wait(gc_in_progress).unwrap();
+        }
+        *gc_in_progress = true;
+    }
+
+    // Prioritize the compactions triggered by the foreground (e.g., to reclaim
+    // space on `OutOfDisk`) over background GC until the returned guard is
+    // dropped. An ongoing GC section is still waited for, but no new one begins,
+    // otherwise the GC rounds slipping in between the compactions and the
+    // retried writes may starve the writes.
+    //
+    // The holder must not begin a GC section itself
+    pub fn prioritize_compaction(&self) -> CompactionPriorityGuard<'_> {
+        self.prioritized_compactions.fetch_add(1, Ordering::AcqRel);
+        CompactionPriorityGuard { shared_state: self }
+    }
+
+    pub fn has_prioritized_compactions(&self) -> bool {
+        self.prioritized_compactions.load(Ordering::Acquire) > 0
+    }
+
     pub fn start_compaction(&self) {
         #[cfg(not(feature = "linux"))]
         debug!("Background compaction started");
@@ -144,6 +285,91 @@ impl SharedState {
         *compaction_in_progress = false;
         self.compaction_condvar.notify_all();
     }
+
+    // Foreground I/O requests mark whether they are latency-critical
+    pub fn set_latency_critical(&self, critical: bool) {
+        self.latency_critical.store(critical, Ordering::Release);
+    }
+
+    pub fn is_latency_critical(&self) -> bool {
+        self.latency_critical.load(Ordering::Acquire)
+    }
+
+    // Marks a latency-critical read in service until the returned guard is
+    // dropped, the ongoing GC section ends at the next victim segment
+    pub fn begin_urgent_read(&self) -> UrgentReadGuard<'_> {
+        self.urgent_reads.fetch_add(1, Ordering::AcqRel);
+        UrgentReadGuard { shared_state: self }
+    }
+
+    pub fn has_urgent_reads(&self) -> bool {
+        self.urgent_reads.load(Ordering::Acquire) > 0
+    }
+
+    // The virtual clock the background tasks of the disk sleep on
+    #[cfg(feature = "sim")]
+    pub fn sim_clock(&self) -> &Arc<SimClock> {
+        &self.sim_clock
+    }
+
+    // The point where threads wait for background GC
+    #[cfg(feature = "sim")]
+    pub fn gc_wait_point(&self) -> &WaitPoint {
+        &self.gc_wait_point
+    }
+
+    // The point where threads wait for compaction
+    #[cfg(feature = "sim")]
+    pub fn compaction_wait_point(&self) -> &WaitPoint {
+        &self.compaction_wait_point
+    }
+}
+
+/// A guard of a background GC section, see `SharedState::begin_gc`.
+pub struct GcGuard<'a> {
+    shared_state: &'a SharedState,
+}
+
+impl Drop for GcGuard<'_> {
+    fn drop(&mut self) {
+        self.shared_state.notify_gc_finished();
+    }
+}
+
+/// A guard of prioritized compactions, see `SharedState::prioritize_compaction`.
+pub struct CompactionPriorityGuard<'a> {
+    shared_state: &'a SharedState,
+}
+
+impl Drop for CompactionPriorityGuard<'_> {
+    fn drop(&mut self) {
+        let shared_state = self.shared_state;
+        // Decrement with the lock held, so that no GC misses the wakeup
+        let _gc_in_progress = shared_state.gc_in_progress.lock().unwrap();
+        shared_state
+            .prioritized_compactions
+            .fetch_sub(1, Ordering::AcqRel);
+        shared_state.gc_condvar.notify_all();
+    }
+}
+
+/// A guard of a latency-critical read, see `SharedState::begin_urgent_read`.
+pub struct UrgentReadGuard<'a> {
+    shared_state: &'a SharedState,
+}
+
+impl Drop for UrgentReadGuard<'_> {
+    fn drop(&mut self) {
+        self.shared_state
+            .urgent_reads
+            .fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl CompactionScheduler for SharedState {
+    fn should_defer(&self, _from_level: LsmLevel) -> bool {
+        self.is_latency_critical()
+    }
 }
 
 pub struct Victim {
@@ -151,8 +377,70 @@ pub struct Victim {
     blocks: Vec<Hba>,
 }
 
+impl Victim {
+    /// Creates a victim of the segment, with all the allocated blocks in it.
+    pub fn new(segment_id: SegmentId, blocks: Vec<Hba>) -> Self {
+        Self { segment_id, blocks }
+    }
+
+    pub fn segment_id(&self) -> SegmentId {
+        self.segment_id
+    }
+
+    /// Returns the allocated blocks of the victim segment.
+    pub fn blocks(&self) -> &[Hba] {
+        &self.blocks
+    }
+}
+
+/// Runtime feedback for victim policies, collected by the GC worker
+/// each time before picking a victim.
+pub struct GcContext<'a> {
+    /// The segment table of user data blocks.
+    pub segment_table: &'a [Segment],
+    /// The minimum fraction of invalid blocks in a victim segment.
+    pub threshold: f64,
+    /// The fraction of allocated blocks of the device.
+    pub utilization: f64,
+    /// The number of blocks written per second since the last GC round.
+    pub write_rate: f64,
+    /// The current write sequence number, i.e., the total number of blocks written.
+    pub write_seq: u64,
+}
+
+impl<'a> GcContext<'a> {
+    /// Returns the age of a segment, i.e., the number of blocks
+    /// written since the segment was last written.
+    pub fn segment_age(&self, segment_id: SegmentId) -> u64 {
+        self.write_seq
+            .saturating_sub(self.segment_table[segment_id].last_write())
+    }
+
+    /// Returns the heat of a segment, i.e., the number of blocks
+    /// invalidated in the segment since the last GC round.
+    pub fn segment_heat(&self, segment_id: SegmentId) -> usize {
+        self.segment_table[segment_id].recent_invalidations()
+    }
+
+    /// Returns the number of blocks written to a segment by user writes,
+    /// decayed over time (see `SwornDisk::decay_segment_heat()`). Unlike
+    /// `segment_heat()`, it's persisted and survives recovery.
+    pub fn segment_writes(&self, segment_id: SegmentId) -> u64 {
+        self.segment_table[segment_id].write_count()
+    }
+}
+
 pub trait VictimPolicy: Send + Sync {
     fn pick_victim(&self, segment_table: &[Segment], threshold: f64) -> Option<Victim>;
+
+    /// Picks a victim with the runtime feedback in `ctx`, which is called by
+    /// the GC worker. Policies that are aware of the device utilization, the
+    /// write rate or the age/heat of segments (e.g., cost-benefit) override it.
+    ///
+    /// It falls back to `pick_victim()` by default.
+    fn pick_victim_with_ctx(&self, ctx: &GcContext) -> Option<Victim> {
+        self.pick_victim(ctx.segment_table, ctx.threshold)
+    }
 }
 
 pub type VictimPolicyRef = Arc<dyn VictimPolicy>;
@@ -162,26 +450,129 @@ pub struct GreedyVictimPolicy {}
 impl VictimPolicy for GreedyVictimPolicy {
     // pick the segment with the maximum number of invalid blocks
     fn pick_victim(&self, segment_table: &[Segment], threshold: f64) -> Option<Victim> {
-        let mut max_num_invalid_blocks = 0;
-        let mut victim = None;
-        segment_table.iter().enumerate().for_each(|(i, segment)| {
-            let invalid_block_fraction =
-                segment.num_invalid_blocks() as f64 / segment.nblocks() as f64;
-            if invalid_block_fraction > threshold
-                && segment.num_invalid_blocks() > max_num_invalid_blocks
-            {
-                max_num_invalid_blocks = segment.num_invalid_blocks();
-                victim = Some(Victim {
-                    segment_id: i,
-                    blocks: vec![],
-                });
-            }
-        });
-        victim.map(|mut victim| {
-            let victim_segment = &segment_table[victim.segment_id];
-            victim.blocks = victim_segment.find_all_allocated_blocks();
-            victim
-        })
+        pick_greedy(segment_table.iter(), threshold)
+    }
+}
+
+// pick the segment with the maximum number of invalid blocks among the candidates
+fn pick_greedy<'a>(
+    candidates: impl Iterator<Item = &'a Segment>,
+    threshold: f64,
+) -> Option<Victim> {
+    let mut max_num_invalid_blocks = 0;
+    let mut victim_segment = None;
+    candidates.for_each(|segment| {
+        let invalid_block_fraction = segment.num_invalid_blocks() as f64 / segment.nblocks() as f64;
+        if invalid_block_fraction > threshold
+            && segment.num_invalid_blocks() > max_num_invalid_blocks
+        {
+            max_num_invalid_blocks = segment.num_invalid_blocks();
+            victim_segment = Some(segment);
+        }
+    });
+    victim_segment.map(|segment| Victim {
+        segment_id: segment.segment_id(),
+        blocks: segment.find_all_allocated_blocks(),
+    })
+}
+
+/// A greedy policy that only considers the `window` oldest segments,
+/// i.e., the segments not written for the longest time. The segments
+/// without any invalid block (e.g., the never written ones, which look the
+/// oldest) are never victims, thus they are not counted in the window.
+pub struct WindowGreedyVictimPolicy {
+    window: usize,
+}
+
+impl WindowGreedyVictimPolicy {
+    pub fn new(window: usize) -> Self {
+        Self { window }
+    }
+}
+
+impl VictimPolicy for WindowGreedyVictimPolicy {
+    // without the age of segments, fall back to greedy over all segments
+    fn pick_victim(&self, segment_table: &[Segment], threshold: f64) -> Option<Victim> {
+        pick_greedy(segment_table.iter(), threshold)
+    }
+
+    fn pick_victim_with_ctx(&self, ctx: &GcContext) -> Option<Victim> {
+        let mut segments: Vec<&Segment> = ctx
+            .segment_table
+            .iter()
+            .filter(|segment| segment.num_invalid_blocks() > 0)
+            .collect();
+        segments.sort_by_key(|segment| core::cmp::Reverse(ctx.segment_age(segment.segment_id())));
+        pick_greedy(segments.into_iter().take(self.window), ctx.threshold)
+    }
+}
+
+/// A generational policy that prefers the segments in the "old" generation,
+/// i.e., the segments not written within the last `old_age` blocks of writes.
+/// The data in old segments are cold, thus their invalid blocks are unlikely
+/// to grow any more. The young generation is considered only if no victim is
+/// found in the old one.
+pub struct GenerationalVictimPolicy {
+    old_age: u64,
+}
+
+impl GenerationalVictimPolicy {
+    pub fn new(old_age: u64) -> Self {
+        Self { old_age }
+    }
+}
+
+impl VictimPolicy for GenerationalVictimPolicy {
+    // without the age of segments, fall back to greedy over all segments
+    fn pick_victim(&self, segment_table: &[Segment], threshold: f64) -> Option<Victim> {
+        pick_greedy(segment_table.iter(), threshold)
+    }
+
+    fn pick_victim_with_ctx(&self, ctx: &GcContext) -> Option<Victim> {
+        let is_old = |segment: &&Segment| ctx.segment_age(segment.segment_id()) >= self.old_age;
+        pick_greedy(ctx.segment_table.iter().filter(is_old), ctx.threshold)
+            .or_else(|| pick_greedy(ctx.segment_table.iter(), ctx.threshold))
+    }
+}
+
+/// A "lazy" greedy policy that never picks the `exclude` most recently
+/// written segments. Their blocks are likely to be invalidated soon by the
+/// following writes, thus migrating them now is mostly wasted. Greedy over
+/// all segments is the fallback if no other segment qualifies.
+pub struct LazyGreedyVictimPolicy {
+    exclude: usize,
+}
+
+impl LazyGreedyVictimPolicy {
+    pub fn new(exclude: usize) -> Self {
+        Self { exclude }
+    }
+}
+
+impl VictimPolicy for LazyGreedyVictimPolicy {
+    // without the age of segments, fall back to greedy over all segments
+    fn pick_victim(&self, segment_table: &[Segment], threshold: f64) -> Option<Victim> {
+        pick_greedy(segment_table.iter(), threshold)
+    }
+
+    fn pick_victim_with_ctx(&self, ctx: &GcContext) -> Option<Victim> {
+        let mut youngest: Vec<SegmentId> = ctx
+            .segment_table
+            .iter()
+            .map(|segment| segment.segment_id())
+            .collect();
+        youngest.sort_by_key(|&segment_id| ctx.segment_age(segment_id));
+        let mut is_excluded = vec![false; ctx.segment_table.len()];
+        for &segment_id in youngest.iter().take(self.exclude) {
+            is_excluded[segment_id] = true;
+        }
+        // Filter rather than sort the candidates, so ties are broken as greedy does
+        let candidates = ctx
+            .segment_table
+            .iter()
+            .filter(|segment| !is_excluded[segment.segment_id()]);
+        pick_greedy(candidates, ctx.threshold)
+            .or_else(|| pick_greedy(ctx.segment_table.iter(), ctx.threshold))
     }
 }
 
@@ -223,29 +614,160 @@ impl VictimPolicy for LoopScanVictimPolicy {
 pub(super) struct GcWorker<D> {
     victim_policy: VictimPolicyRef,
     logical_block_table: TxLsmTree<RecordKey, RecordValue, D>,
-    reverse_index_table: TxLsmTree<ReverseKey, ReverseValue, D>,
+    reverse_index_table: ReverseIndex<D>,
     dealloc_table: Arc<DeallocTable>,
     block_validity_table: Arc<AllocTable>,
     tx_log_store: Arc<TxLogStore<D>>,
     tx_provider: Arc<TxProvider>,
     user_data_disk: Arc<D>,
     shared_state: SharedStateRef,
-    is_active: Arc<AtomicBool>,
+    // The activity of the foreground in [0, 1], derived from the estimated
+    // I/O rate at the start of each GC round, as the bits of a f64
+    activity: AtomicU64,
+    // The write sequence number and the interval of the last GC round,
+    // used to measure the write rate
+    last_write_seq: AtomicU64,
+    last_interval: Mutex<Duration>,
+    // The first LBA of the next window to defragment
+    defrag_cursor: AtomicUsize,
+    // The number of failed rounds in a row, which the retry backs off with
+    consecutive_failures: AtomicUsize,
+    // The AEAD backend to re-encrypt the migrated blocks,
+    // `None` if they are moved verbatim (see `Config::reencrypt_on_gc`)
+    aead: Option<AeadBackendRef>,
+    // The identity of the disk bound to the re-encrypted blocks,
+    // `None` if the disk is created without `FEATURE_LBA_AAD`
+    disk_id: Option<DiskId>,
+    // The digest of the logical block table, which changes along with
+    // the MACs of the re-encrypted blocks
+    digest_tree: Arc<DigestTree>,
+    // The limiter of the migration I/Os, shared with the other background
+    // tasks (see `Config::background_io_limit`)
+    io_limiter: Option<Arc<BackgroundIoLimiter>>,
+    // The configuration and the statistics of the disk
+    config: Arc<Config>,
+    stats: Arc<DiskStats>,
+    // The subscribers of the migrations (see `SwornDisk::subscribe_events()`)
+    events: Arc<EventBus>,
+}
+
+// A block migrated by GC, whose record is to be remapped
+pub(super) struct MigratedBlock {
+    lba: Lba,
+    old_hba: Hba,
+    new_hba: Hba,
+    // The fresh key and MAC of the block if it's re-encrypted
+    secret: Option<(Key, Mac)>,
+}
+
+// The migrated blocks whose records are not remapped yet, in the order of
+// remapping. Their migrations are undone on drop, e.g., once the remapping
+// fails midway, so that the old blocks still referenced are never reused.
+struct UnmappedBlocks<'a> {
+    alloc_table: &'a AllocTable,
+    // The old and new HBAs of the migrated blocks
+    blocks: Vec<(Hba, Hba)>,
+    // The number of blocks remapped or discarded so far
+    num_settled: usize,
+}
+
+impl<'a> UnmappedBlocks<'a> {
+    fn new(alloc_table: &'a AllocTable, migrated_blocks: &[MigratedBlock]) -> Self {
+        Self {
+            alloc_table,
+            blocks: migrated_blocks
+                .iter()
+                .map(|block| (block.old_hba, block.new_hba))
+                .collect(),
+            num_settled: 0,
+        }
+    }
+
+    // The next block is either remapped or discarded
+    fn settle_next(&mut self) {
+        self.num_settled += 1;
+    }
+}
+
+impl Drop for UnmappedBlocks<'_> {
+    fn drop(&mut self) {
+        let unmapped = &self.blocks[self.num_settled..];
+        if !unmapped.is_empty() {
+            self.alloc_table.undo_migrations(unmapped);
+        }
+    }
+}
+
+impl<D: BlockSet + 'static> BackgroundTask for GcWorker<D> {
+    fn name(&self) -> &'static str {
+        "gc"
+    }
+
+    // A round of background GC, the interval until the next round (and the
+    // threshold of this round) scales with the activity of the foreground.
+    // A failed round never stops the task, it's retried after a backoff
+    fn run(&self, ctx: &TaskContext) -> Result<Duration> {
+        let res = self.gc_round(ctx);
+        self.stats
+            .record_gc(|stats| stats.record_round(res.is_ok()));
+        Ok(self.next_interval(res))
+    }
 }
 
 impl<D: BlockSet + 'static> GcWorker<D> {
+    // A round of GC (and defragmentation), followed by the deferred major
+    // compactions if idle. Returns the interval until the next round
+    fn gc_round(&self, ctx: &TaskContext) -> Result<Duration> {
+        let io_rate = self.stats.bio().sample_io_rate(ctx.now());
+        self.set_activity(io_rate / (io_rate + HALF_ACTIVE_IO_RATE));
+
+        #[cfg(not(feature = "linux"))]
+        debug!("Background GC started");
+        let gc_guard = self.shared_state.begin_gc();
+        let res = self.background_gc().and_then(|_| {
+            // Defragment while the foreground is idle, it's excluded from
+            // foreground writes the same as GC
+            if !self.is_active()
+                && self.config.enable_defrag
+                && !self.shared_state.has_urgent_reads()
+            {
+                self.defragment()?;
+            }
+            Ok(())
+        });
+        // Notify foreground GC and foreground I/O Requests
+        drop(gc_guard);
+        res?;
+
+        // Do the deferred major compactions while the foreground is idle
+        if !self.is_active() {
+            self.compact_when_idle()?;
+        }
+        let interval = INACTIVE_GC_INTERVAL_TIME
+            + (ACTIVE_GC_INTERVAL_TIME - INACTIVE_GC_INTERVAL_TIME).mul_f64(self.activity());
+        *self.last_interval.lock() = interval;
+        Ok(interval)
+    }
+
     pub fn new(
         victim_policy: VictimPolicyRef,
         logical_block_table: TxLsmTree<RecordKey, RecordValue, D>,
-        reverse_index_table: TxLsmTree<ReverseKey, ReverseValue, D>,
+        reverse_index_table: ReverseIndex<D>,
         dealloc_table: Arc<DeallocTable>,
         tx_log_store: Arc<TxLogStore<D>>,
         block_validity_table: Arc<AllocTable>,
         user_data_disk: Arc<D>,
         shared_state: SharedStateRef,
-        last_active_time: Arc<AtomicBool>,
+        aead: Option<AeadBackendRef>,
+        disk_id: Option<DiskId>,
+        digest_tree: Arc<DigestTree>,
+        io_limiter: Option<Arc<BackgroundIoLimiter>>,
+        config: Arc<Config>,
+        stats: Arc<DiskStats>,
+        events: Arc<EventBus>,
     ) -> Self {
         let tx_provider = TxProvider::new();
+        let last_write_seq = AtomicU64::new(block_validity_table.write_seq());
         Self {
             victim_policy,
             logical_block_table,
@@ -256,25 +778,19 @@ impl<D: BlockSet + 'static> GcWorker<D> {
             user_data_disk,
             shared_state,
             tx_provider,
-            is_active: last_active_time,
-        }
-    }
-
-    pub fn run(&self) -> Result<()> {
-        loop {
-            #[cfg(not(feature = "linux"))]
-            debug!("Background GC started");
-            self.shared_state.start_gc();
-            self.background_gc()?;
-            // Notify foreground GC and foreground I/O Requests
-            self.shared_state.notify_gc_finished();
-            if self.is_active() {
-                self.is_active.store(false, Ordering::Release);
-                sleep(ACTIVE_GC_INTERVAL_TIME);
-            } else {
-                self.is_active.store(false, Ordering::Release);
-                sleep(INACTIVE_GC_INTERVAL_TIME);
-            }
+            // Regarded as fully active until sampled
+            activity: AtomicU64::new(1.0f64.to_bits()),
+            last_write_seq,
+            last_interval: Mutex::new(INACTIVE_GC_INTERVAL_TIME),
+            defrag_cursor: AtomicUsize::new(0),
+            consecutive_failures: AtomicUsize::new(0),
+            aead,
+            disk_id,
+            digest_tree,
+            io_limiter,
+            config,
+            stats,
+            events,
         }
     }
 
@@ -294,20 +810,36 @@ impl<D: BlockSet + 'static> GcWorker<D> {
     // }
 
     pub fn is_active(&self) -> bool {
-        self.is_active.load(Ordering::Acquire)
+        self.activity() >= IDLE_ACTIVITY
+    }
+
+    // The activity of the foreground in [0, 1], see `HALF_ACTIVE_IO_RATE`
+    pub fn activity(&self) -> f64 {
+        f64::from_bits(self.activity.load(Ordering::Acquire))
+    }
+
+    fn set_activity(&self, activity: f64) {
+        self.activity
+            .store(activity.clamp(0.0, 1.0).to_bits(), Ordering::Release);
+    }
+
+    // Do the major compactions of both tables if required, including the deferred ones
+    fn compact_when_idle(&self) -> Result<()> {
+        self.shared_state.wait_for_compaction();
+        self.logical_block_table.manual_compaction()?;
+        self.reverse_index_table.manual_compaction()
     }
     pub fn background_gc(&self) -> Result<()> {
         // FIXME: use a cross-platform time function
         #[cfg(feature = "std")]
         let start = std::time::Instant::now();
 
+        // The bitmap of block validities must be recovered
+        self.block_validity_table.wait_for_recovery()?;
         let mut segment_ids = Vec::with_capacity(GC_WATERMARK);
 
-        let threshold = if self.is_active() {
-            ACTIVE_GC_THRESHOLD
-        } else {
-            INACTIVE_GC_THRESHOLD
-        };
+        let threshold =
+            INACTIVE_GC_THRESHOLD + (ACTIVE_GC_THRESHOLD - INACTIVE_GC_THRESHOLD) * self.activity();
 
         // GC is only enabled when segment_table exists
         let segment_table = self
@@ -315,8 +847,29 @@ impl<D: BlockSet + 'static> GcWorker<D> {
             .get_segment_table_ref()
             .expect("segment_table must exist when GC is enabled");
 
+        let write_seq = self.block_validity_table.write_seq();
+        let last_write_seq = self.last_write_seq.swap(write_seq, Ordering::Relaxed);
+        let write_rate = (write_seq - last_write_seq) as f64
+            / self.last_interval.lock().as_secs_f64().max(f64::EPSILON);
+
         for _ in 0..GC_WATERMARK {
-            let victim = self.victim_policy.pick_victim(segment_table, threshold);
+            // Yield to the latency-critical reads, the rest victims are
+            // picked in the next round
+            if self.shared_state.has_urgent_reads() {
+                break;
+            }
+            self.wait_for_io_budget();
+            let ctx = GcContext {
+                segment_table,
+                threshold,
+                utilization: self.block_validity_table.utilization(),
+                write_rate,
+                write_seq,
+            };
+            let victim = {
+                crate::trace_span!("gc_pick_victim", threshold, write_rate);
+                self.victim_policy.pick_victim_with_ctx(&ctx)
+            };
 
             // Generally, the VictimPolicy will pick a victim segment that most needs GC
             // if it returned None, it means there is no segment needs GC, we can return
@@ -324,19 +877,31 @@ impl<D: BlockSet + 'static> GcWorker<D> {
                 break;
             };
             segment_ids.push(victim.segment_id);
+            crate::trace_span!(
+                "gc_victim",
+                segment = victim.segment_id,
+                nblocks = victim.blocks.len()
+            );
 
             let mut tx = self.tx_provider.new_tx();
             let ret: Result<_> = tx.context(|| {
-                let remapped_hbas = self.clean_and_migrate_data(victim)?;
-                self.remap_index_batch(remapped_hbas)?;
-                Ok(())
+                let migrated_blocks = self.clean_and_migrate_data(victim)?;
+                self.remap_index_batch(migrated_blocks)
             });
-            if ret.is_err() {
-                tx.abort();
-                return Err(ret.err().unwrap());
-            }
+            let migrations = match ret {
+                Ok(migrations) => migrations,
+                Err(e) => {
+                    tx.abort();
+                    return Err(e);
+                }
+            };
             tx.commit()?;
+            self.emit_migrations(migrations);
         }
+        // The heat of segments restarts from each GC round
+        segment_table
+            .iter()
+            .for_each(|segment| segment.reset_recent_invalidations());
 
         #[cfg(feature = "std")]
         {
@@ -357,33 +922,65 @@ impl<D: BlockSet + 'static> GcWorker<D> {
     // 1. update the hba of the records in lsm tree
     // 2. update the reverse index table, record the old hba of the migrated blocks and insert the new hba -> lba mapping
     // 3. insert the lba -> old hba mapping into the dealloc table to prevent double deallocation in compaction
-    pub fn remap_index_batch(&self, remapped_hbas: Vec<(Hba, Hba)>) -> Result<()> {
-        remapped_hbas
-            .into_iter()
-            .try_for_each(|(old_hba, new_hba)| {
-                // Get the lba of the old hba
-                // Safety: hba should exist in index table, otherwise it means system is inconsistent
-                let key = ReverseKey { hba: old_hba };
-                let lba = self
-                    .reverse_index_table
-                    .get(&key)
-                    .map(|value| value.lba)
-                    .expect("hba should exist in index table");
+    //
+    // The LBAs of the migrated blocks are resolved in `find_target_hbas`, where
+    // the missing reverse entries are rebuilt. A block is discarded only if its
+    // record no longer refers to it, i.e., it's overwritten or deleted.
+    // If the remapping fails midway, the migrations of the blocks whose
+    // records are not remapped yet are undone (see `UnmappedBlocks`).
+    //
+    // Returns the remapped blocks.
+    pub fn remap_index_batch(
+        &self,
+        migrated_blocks: Vec<MigratedBlock>,
+    ) -> Result<Vec<BlockMigration>> {
+        let mut migrations = Vec::with_capacity(migrated_blocks.len());
+        let mut unmapped = UnmappedBlocks::new(&self.block_validity_table, &migrated_blocks);
+        migrated_blocks.into_iter().try_for_each(
+            |MigratedBlock {
+                 lba,
+                 old_hba,
+                 new_hba,
+                 secret,
+             }| {
                 let record_key = RecordKey { lba };
 
                 // get mac and key of the old hba record
-                // Safety: hba should exist in lsm tree, otherwise it means system is inconsistent
-                let mut record_value = self
-                    .logical_block_table
-                    .get(&record_key)
-                    .expect("record key should exist in lsm tree");
+                let mut record_value = match self.logical_block_table.get(&record_key) {
+                    Ok(record_value) if record_value.hba == old_hba => record_value,
+                    Ok(_) => {
+                        // The block has been overwritten, it's no longer valid
+                        unmapped.settle_next();
+                        self.discard_migrated_block(old_hba, new_hba);
+                        return Ok(());
+                    }
+                    Err(e) if e.errno() == Errno::NotFound => {
+                        self.stats.record_gc(|stats| stats.record_missing_record());
+                        unmapped.settle_next();
+                        self.discard_migrated_block(old_hba, new_hba);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),
+                };
 
-                // Update the hba of the record but keep the key and mac unchanged
+                // Update the hba of the record, the key and mac are unchanged
+                // unless the block is re-encrypted
                 // This will trigger deallocation of the old hba in MemTable
                 record_value.hba = new_hba;
+                if let Some((key, mac)) = secret {
+                    record_value.key = key;
+                    record_value.mac = mac;
+                }
 
                 // write the record back to lsm tree
                 self.logical_block_table.put(record_key, record_value)?;
+                // The new block is referenced from now on, a missing reverse
+                // entry is repaired on open
+                unmapped.settle_next();
+                if secret.is_some() {
+                    self.digest_tree.invalidate(lba, 1);
+                }
+                crate::chaos_point!(GcRemap);
 
                 let reverse_index_key = ReverseKey { hba: new_hba };
 
@@ -392,16 +989,62 @@ impl<D: BlockSet + 'static> GcWorker<D> {
                 self.reverse_index_table
                     .put(reverse_index_key, reverse_index_value)?;
                 self.dealloc_table.mark_deallocated(old_hba);
+                migrations.push(BlockMigration {
+                    lba,
+                    old_hba,
+                    new_hba,
+                    rekeyed: secret.is_some(),
+                });
                 Ok::<_, Error>(())
-            })?;
-        Ok::<_, Error>(())
+            },
+        )?;
+        Ok(migrations)
+    }
+
+    // Compute the interval until the next round from the result of a round,
+    // the failed rounds are retried with exponential backoff
+    fn next_interval(&self, res: Result<Duration>) -> Duration {
+        match res {
+            Ok(interval) => {
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                interval
+            }
+            Err(_e) => {
+                let num_failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                let backoff = GC_RETRY_INTERVAL_TIME
+                    .saturating_mul(1u32 << (num_failures - 1).min(31))
+                    .min(MAX_GC_RETRY_INTERVAL_TIME);
+                #[cfg(not(feature = "linux"))]
+                warn!(
+                    "[GC] round failed ({} in a row): {:?}, retry in {:?}",
+                    num_failures, _e, backoff
+                );
+                backoff
+            }
+        }
+    }
+
+    // Notify the subscribers of the committed migrations, the synchronous
+    // ones drop their stale state before GC lets the foreground I/O proceed
+    fn emit_migrations(&self, migrations: Vec<BlockMigration>) {
+        if !migrations.is_empty() {
+            self.events.emit(DiskEvent::BlocksMigrated(migrations));
+        }
+    }
+
+    // Free the block migrated from `old_hba` to `new_hba`, since it can't be remapped.
+    // The old block has been freed along with the victim segment.
+    fn discard_migrated_block(&self, old_hba: Hba, new_hba: Hba) {
+        #[cfg(not(feature = "linux"))]
+        warn!("[GC] migrated block {old_hba} can't be remapped, discarded");
+        self.block_validity_table.set_deallocated(new_hba);
     }
 
     // Find valid blocks to migrate and invalid blocks to discard and free blocks to store
     pub fn find_target_hbas(
         &self,
         victim: Victim,
-    ) -> Result<(Vec<Hba>, Vec<(Lba, Hba)>, Vec<Hba>)> {
+    ) -> Result<(Vec<(Lba, RecordValue)>, Vec<(Lba, Hba)>, Vec<Hba>)> {
         // GC is only enabled when segment_table exists
         let segment_table = self
             .block_validity_table
@@ -409,24 +1052,72 @@ impl<D: BlockSet + 'static> GcWorker<D> {
             .expect("segment_table must exist when GC is enabled");
         let victim_segment = &segment_table[victim.segment_id];
 
-        let (valid_hbas, discard_hbas) = victim.blocks.into_iter().try_fold(
-            (Vec::new(), Vec::new()),
-            |(mut valid, mut discard), hba| {
-                // if victim hba is different from the hba that stored in logical block table,
-                // it means the block is already invalid but not deallocated by compaction,
-                // it should be discarded and be marked to avoid double free
-                //let lba = self.reverse_index_table.get_lba(&hba);
-                let reverse_index_key = ReverseKey { hba };
-                let lba = self.reverse_index_table.get(&reverse_index_key)?.lba;
-                let old_hba = self.logical_block_table.get(&RecordKey { lba })?.hba;
-                if hba == old_hba {
-                    valid.push(hba);
-                } else {
-                    discard.push((lba, hba));
+        // if victim hba is different from the hba that stored in logical block table,
+        // it means the block is already invalid but not deallocated by compaction,
+        // it should be discarded and be marked to avoid double free
+        let reverse_index_keys: Vec<_> = victim
+            .blocks
+            .iter()
+            .map(|&hba| ReverseKey { hba })
+            .collect();
+        let reverse_index_values = self.reverse_index_table.get_multi(&reverse_index_keys)?;
+
+        let mut valid_values = Vec::new();
+        let mut discard_hbas = Vec::new();
+        let mut blocks = Vec::with_capacity(victim.blocks.len());
+        let mut missing_hbas = HashSet::new();
+        for (hba, reverse_index_value) in victim.blocks.into_iter().zip(reverse_index_values) {
+            match reverse_index_value {
+                Some(value) => blocks.push((hba, RecordKey { lba: value.lba })),
+                None => {
+                    self.stats
+                        .record_gc(|stats| stats.record_missing_reverse_entry());
+                    missing_hbas.insert(hba);
                 }
-                Ok::<_, Error>((valid, discard))
-            },
-        )?;
+            }
+        }
+        // A block without a reverse entry may still be live, its entry is
+        // rebuilt from the logical block table before it's judged
+        if !missing_hbas.is_empty() {
+            for (hba, lba) in self.rebuild_reverse_entries(&missing_hbas)? {
+                missing_hbas.remove(&hba);
+                blocks.push((hba, RecordKey { lba }));
+            }
+            for hba in missing_hbas {
+                #[cfg(not(feature = "linux"))]
+                warn!("[GC] victim block {hba} not referenced by any lba, discarded");
+                discard_hbas.push((Lba::MAX, hba));
+            }
+        }
+        // Look up the scattered lbas in one pass
+        let lbas: Vec<_> = blocks.iter().map(|(_, key)| *key).collect();
+        let values = self.logical_block_table.get_multi(&lbas)?;
+
+        for ((hba, key), value) in blocks.into_iter().zip(values) {
+            let Some(value) = value else {
+                #[cfg(not(feature = "linux"))]
+                warn!(
+                    "[GC] victim block {hba} of lba {} not found in logical block table, discarded",
+                    key.lba
+                );
+                self.stats.record_gc(|stats| stats.record_missing_record());
+                discard_hbas.push((key.lba, hba));
+                continue;
+            };
+            if hba == value.hba {
+                valid_values.push((key.lba, value));
+            } else {
+                discard_hbas.push((key.lba, hba));
+            }
+        }
+
+        // Fill the open segment of GC migrations under `AllocPolicy::SegmentFill`
+        if let Some(target_hbas) = self
+            .block_validity_table
+            .pick_migration_targets(valid_values.len(), victim_segment.segment_id())
+        {
+            return Ok((valid_values, discard_hbas, target_hbas));
+        }
 
         let mut target_hbas = Vec::new();
         let mut found_enough_blocks = false;
@@ -436,7 +1127,7 @@ impl<D: BlockSet + 'static> GcWorker<D> {
             }
             let free_hbas = segment.find_all_free_blocks();
             for hba in free_hbas {
-                if target_hbas.len() >= valid_hbas.len() {
+                if target_hbas.len() >= valid_values.len() {
                     found_enough_blocks = true;
                     break;
                 }
@@ -446,11 +1137,35 @@ impl<D: BlockSet + 'static> GcWorker<D> {
                 break;
             }
         }
-        debug_assert_eq!(valid_hbas.len(), target_hbas.len());
-        Ok((valid_hbas, discard_hbas, target_hbas))
+        debug_assert_eq!(valid_values.len(), target_hbas.len());
+        Ok((valid_values, discard_hbas, target_hbas))
+    }
+
+    // Scan the logical block table for the records referring to `hbas`,
+    // re-insert their reverse entries. Returns the found blocks and their LBAs.
+    fn rebuild_reverse_entries(&self, hbas: &HashSet<Hba>) -> Result<Vec<(Hba, Lba)>> {
+        let mut found = Vec::new();
+        for record in self.logical_block_table.iter() {
+            let (key, value) = record?;
+            if hbas.contains(&value.hba) {
+                found.push((value.hba, key.lba));
+                if found.len() == hbas.len() {
+                    break;
+                }
+            }
+        }
+        for &(hba, lba) in found.iter() {
+            self.reverse_index_table
+                .put(ReverseKey { hba }, ReverseValue { lba })?;
+        }
+        #[cfg(not(feature = "linux"))]
+        if !found.is_empty() {
+            warn!("[GC] rebuilt {} reverse index entries", found.len());
+        }
+        Ok(found)
     }
 
-    pub fn clean_and_migrate_data(&self, victim: Victim) -> Result<Vec<(Hba, Hba)>> {
+    pub fn clean_and_migrate_data(&self, victim: Victim) -> Result<Vec<MigratedBlock>> {
         // GC is only enabled when segment_table exists
         let segment_table = self
             .block_validity_table
@@ -459,26 +1174,37 @@ impl<D: BlockSet + 'static> GcWorker<D> {
         let victim_segment = &segment_table[victim.segment_id];
 
         //        let start = Instant::now();
-        let (valid_hbas, discard_hbas, free_hbas) = self.find_target_hbas(victim)?;
+        let (valid_values, discard_hbas, free_hbas) = self.find_target_hbas(victim)?;
+        let valid_hbas: Vec<Hba> = valid_values.iter().map(|(_, value)| value.hba).collect();
+        crate::trace_span!(
+            "gc_migrate",
+            segment = victim_segment.segment_id(),
+            nvalid = valid_hbas.len(),
+            ndiscard = discard_hbas.len(),
+            first_target_hba = ?free_hbas.first()
+        );
         let mut victim_data = Buf::alloc(victim_segment.nblocks())?;
         let offset = victim_segment.segment_id() * SEGMENT_SIZE;
-        self.user_data_disk.read(offset, victim_data.as_mut())?;
+        self.read_data(offset, victim_data.as_mut_slice())?;
         // let duration = start.elapsed();
         // debug!("Find target hbas took {:?}", duration);
 
         // let start = Instant::now();
         let target_hba_batches = free_hbas.group_by(|hba1, hba2| hba2.saturating_sub(*hba1) == 1);
-        let mut victim_hba_iter = valid_hbas.iter();
+        let mut victim_value_iter = valid_values.iter();
+        let mut secrets = Vec::new();
         for target_hba_batch in target_hba_batches {
             let batch_len = target_hba_batch.len();
             let mut write_buf = Buf::alloc(batch_len)?;
+            let mut batch_values = Vec::with_capacity(batch_len);
 
             // read enough blocks to fill the batch
             for i in 0..batch_len {
-                let Some(victim_hba) = victim_hba_iter.next() else {
+                let Some(&(lba, victim_value)) = victim_value_iter.next() else {
                     break;
                 };
-                let start = (victim_hba % SEGMENT_SIZE) * BLOCK_SIZE;
+                batch_values.push((lba, victim_value));
+                let start = (victim_value.hba % SEGMENT_SIZE) * BLOCK_SIZE;
                 let end = start + BLOCK_SIZE;
 
                 let des_start = i * BLOCK_SIZE;
@@ -486,9 +1212,17 @@ impl<D: BlockSet + 'static> GcWorker<D> {
                 write_buf.as_mut_slice()[des_start..des_end]
                     .copy_from_slice(&victim_data.as_slice()[start..end]);
             }
+            if let Some(batch_secrets) =
+                self.reencrypt_blocks(&batch_values, write_buf.as_mut_slice())?
+            {
+                secrets.extend(batch_secrets);
+            }
 
-            self.user_data_disk
-                .write(*target_hba_batch.first().unwrap(), write_buf.as_ref())?;
+            self.write_data(*target_hba_batch.first().unwrap(), write_buf.as_slice())?;
+        }
+        // The migrated blocks must be durable before they are remapped
+        if self.config.ordered_data_writes {
+            self.user_data_disk.flush()?;
         }
         // let duration = start.elapsed();
         // debug!("Write data to disk took {:?}", duration);
@@ -497,31 +1231,340 @@ impl<D: BlockSet + 'static> GcWorker<D> {
         self.block_validity_table
             .clear_segment(victim_segment.segment_id(), discard_hbas.len());
 
-        Ok(valid_hbas.into_iter().zip(free_hbas).collect())
+        let mut secrets = secrets.into_iter();
+        Ok(valid_values
+            .into_iter()
+            .zip(free_hbas)
+            .map(|((lba, value), new_hba)| MigratedBlock {
+                lba,
+                old_hba: value.hba,
+                new_hba,
+                secret: secrets.next(),
+            })
+            .collect())
     }
 
-    // TODO: Support more rules
-    fn trigger_gc(&self, victim: Option<&Victim>) -> bool {
-        if victim.is_none() {
-            return false;
-        }
-        #[cfg(not(feature = "linux"))]
-        debug!(
-            "Triggered background GC, victim segment: {}",
-            victim.unwrap().segment_id
-        );
-        true
+    // Re-encrypt the blocks of the LBAs and records in `cipher` in place with
+    // fresh random keys, returns their new keys and MACs. Returns `None` if the
+    // migrated blocks are moved verbatim.
+    fn reencrypt_blocks(
+        &self,
+        values: &[(Lba, RecordValue)],
+        cipher: &mut [u8],
+    ) -> Result<Option<Vec<(Key, Mac)>>> {
+        let Some(aead) = &self.aead else {
+            return Ok(None);
+        };
+        let _timer = if self.config.stat_cost {
+            Some(COST_L3.time(CostL3Type::Encryption))
+        } else {
+            None
+        };
+
+        let mut plain = Buf::alloc(1)?;
+        let secrets = values
+            .iter()
+            .zip(cipher.chunks_mut(BLOCK_SIZE))
+            .map(|((lba, value), block)| {
+                let iv = Iv::new_zeroed();
+                let aad = BlockAad::new(*lba, self.disk_id.as_ref());
+                aead.decrypt(
+                    block,
+                    &value.key,
+                    &iv,
+                    aad.as_slice(),
+                    &value.mac,
+                    plain.as_mut_slice(),
+                )?;
+                let key = Key::random();
+                let mac = aead.encrypt(plain.as_slice(), &key, &iv, aad.as_slice(), block)?;
+                Ok((key, mac))
+            })
+            .collect::<Result<Vec<_>>>();
+        plain.as_mut_slice().fill(0);
+        secrets.map(Some)
     }
 
-    // fn clean_segment(&self, segment: &Segment) -> Result<()> {
-    //     segment.clear_segment();
-    //     self.block_validity_table.set_deallocated(nth);
-    // }
-}
+    // A round of defragmentation driven by LBA order rather than invalid blocks.
+    // It scans the next window of LBAs, each run of adjacent LBAs whose blocks are
+    // scattered is migrated to a contiguous free extent. Returns the number of
+    // migrated blocks.
+    pub fn defragment(&self) -> Result<usize> {
+        self.block_validity_table.wait_for_recovery()?;
+        self.wait_for_io_budget();
+        let nblocks = self.user_data_disk.nblocks();
+        let window_start = self.defrag_cursor.load(Ordering::Relaxed);
+        let window_end = (window_start + DEFRAG_WINDOW).min(nblocks);
+        let next_cursor = if window_end >= nblocks { 0 } else { window_end };
+        self.defrag_cursor.store(next_cursor, Ordering::Relaxed);
+
+        let keys: Vec<RecordKey> = (window_start..window_end)
+            .map(|lba| RecordKey { lba })
+            .collect();
+        let values = self.logical_block_table.get_multi(&keys)?;
+        let records: Vec<(Lba, RecordValue)> = keys
+            .into_iter()
+            .zip(values)
+            .filter_map(|(key, value)| value.map(|value| (key.lba, value)))
+            .collect();
+
+        let mut num_migrated = 0;
+        for run in records.group_by(|(lba1, _), (lba2, _)| *lba2 == lba1 + 1) {
+            let is_fragmented = run
+                .windows(2)
+                .any(|pair| pair[1].1.hba != pair[0].1.hba + 1);
+            if !is_fragmented {
+                continue;
+            }
+            if self.shared_state.has_urgent_reads() {
+                break;
+            }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+            let mut tx = self.tx_provider.new_tx();
+            let ret: Result<_> = tx.context(|| self.migrate_run(run));
+            let migrations = match ret {
+                Ok(migrations) => migrations,
+                Err(e) => {
+                    tx.abort();
+                    return Err(e);
+                }
+            };
+            tx.commit()?;
+            // Stop if there is no free extent large enough
+            if migrations.is_empty() {
+                break;
+            }
+            num_migrated += migrations.len();
+            self.emit_migrations(migrations);
+        }
+
+        #[cfg(not(feature = "linux"))]
+        if num_migrated > 0 {
+            debug!(
+                "Defragmented {} blocks in lba range [{}, {})",
+                num_migrated, window_start, window_end
+            );
+        }
+        Ok(num_migrated)
+    }
+
+    // Migrate all the allocated blocks at or beyond `limit` to the blocks
+    // before, so that the user data can be shrunk to `limit` blocks. The
+    // segment across `limit` is migrated as a whole. It fails with
+    // `NoSpaceLeft` before any migration if the blocks don't fit.
+    //
+    // The free blocks beyond `limit` are fenced off during the evacuation,
+    // so that they're never the targets of the migrations. The caller must
+    // exclude the foreground I/O and the other GC sections.
+    //
+    // Returns the number of migrated blocks.
+    pub fn evacuate(&self, limit: Hba) -> Result<usize> {
+        self.block_validity_table.wait_for_recovery()?;
+        let segment_table = self
+            .block_validity_table
+            .get_segment_table_ref()
+            .expect("segment_table must exist when GC is enabled");
+        let nblocks = self.block_validity_table.nblocks();
+        let first_segment = limit / SEGMENT_SIZE;
+        let tail_segments = &segment_table[first_segment.min(segment_table.len())..];
+        // The blocks beyond the last segment are never allocated with GC
+        let num_to_migrate: usize = tail_segments
+            .iter()
+            .map(|segment| segment.find_all_allocated_blocks().len())
+            .sum();
+        let num_free: usize = segment_table[..first_segment.min(segment_table.len())]
+            .iter()
+            .map(|segment| segment.find_all_free_blocks().len())
+            .sum();
+        if num_to_migrate > num_free {
+            return Err(Error::with_msg(
+                Errno::NoSpaceLeft,
+                "live data doesn't fit in the shrunk disk",
+            ));
+        }
+
+        let mut fenced: HashSet<Hba> = self
+            .block_validity_table
+            .fence(limit.min(nblocks)..nblocks)
+            .into_iter()
+            .collect();
+        let res = tail_segments.iter().try_fold(0, |num_migrated, segment| {
+            let blocks: Vec<_> = segment
+                .find_all_allocated_blocks()
+                .into_iter()
+                .filter(|hba| !fenced.contains(hba))
+                .collect();
+            if blocks.is_empty() {
+                return Ok(num_migrated);
+            }
+
+            let victim = Victim::new(segment.segment_id(), blocks);
+            let mut tx = self.tx_provider.new_tx();
+            let ret: Result<_> = tx.context(|| {
+                let migrated_blocks = self.clean_and_migrate_data(victim)?;
+                self.remap_index_batch(migrated_blocks)
+            });
+            let migrations = match ret {
+                Ok(migrations) => migrations,
+                Err(e) => {
+                    tx.abort();
+                    return Err(e);
+                }
+            };
+            tx.commit()?;
+            // The victim segment is freed as a whole, fence it off again
+            let segment_start = segment.segment_id() * SEGMENT_SIZE;
+            let fence_range = segment_start.max(limit)..segment_start + SEGMENT_SIZE;
+            fenced.extend(self.block_validity_table.fence(fence_range));
+            let num_migrated = num_migrated + migrations.len();
+            self.emit_migrations(migrations);
+            Ok(num_migrated)
+        });
+        let fenced: Vec<_> = fenced.into_iter().collect();
+        self.block_validity_table.unfence(&fenced);
+        res
+    }
+
+    // Migrate the blocks of a run of adjacent LBAs to a contiguous free extent.
+    // The blocks are copied as ciphertext unless re-encrypted, only their HBAs
+    // (and their keys and MACs if re-encrypted) are updated in the index.
+    // The old blocks are deallocated once their out-of-date records are dropped,
+    // the same as being overwritten by user writes.
+    //
+    // Returns the migrated blocks, none if there is no free extent large enough.
+    fn migrate_run(&self, run: &[(Lba, RecordValue)]) -> Result<Vec<BlockMigration>> {
+        let Some(target_hbas) = self
+            .block_validity_table
+            .alloc_contiguous(NonZeroUsize::new(run.len()).unwrap())
+        else {
+            return Ok(Vec::new());
+        };
+
+        // Read the scattered blocks, the physically adjacent ones at a time
+        let mut buf = Buf::alloc(run.len())?;
+        let mut offset = 0;
+        for hba_batch in run.group_by(|(_, value1), (_, value2)| value2.hba == value1.hba + 1) {
+            let batch_len = hba_batch.len();
+            let batch_buf =
+                &mut buf.as_mut_slice()[offset * BLOCK_SIZE..(offset + batch_len) * BLOCK_SIZE];
+            self.read_data(hba_batch[0].1.hba, batch_buf)?;
+            offset += batch_len;
+        }
+        let mut secrets = self
+            .reencrypt_blocks(run, buf.as_mut_slice())?
+            .map(|secrets| secrets.into_iter());
+        self.write_data(target_hbas[0], buf.as_slice())?;
+        if self.config.ordered_data_writes {
+            self.user_data_disk.flush()?;
+        }
+
+        let (records, reverse_records): (Vec<_>, Vec<_>) = run
+            .iter()
+            .zip(target_hbas)
+            .map(|((lba, value), new_hba)| {
+                let mut record_value = *value;
+                record_value.hba = new_hba;
+                if let Some((key, mac)) = secrets.as_mut().and_then(|secrets| secrets.next()) {
+                    record_value.key = key;
+                    record_value.mac = mac;
+                }
+                (
+                    (RecordKey { lba: *lba }, record_value),
+                    (ReverseKey { hba: new_hba }, ReverseValue { lba: *lba }),
+                )
+            })
+            .unzip();
+        self.logical_block_table.put_batch(&records)?;
+        if self.aead.is_some() {
+            self.digest_tree
+                .invalidate_lbas(run.iter().map(|(lba, _)| *lba));
+        }
+        self.reverse_index_table.put_batch(&reverse_records)?;
+        let migrations = run
+            .iter()
+            .zip(records)
+            .map(|((lba, value), (_, new_value))| BlockMigration {
+                lba: *lba,
+                old_hba: value.hba,
+                new_hba: new_value.hba,
+                rekeyed: self.aead.is_some(),
+            })
+            .collect();
+        Ok(migrations)
+    }
+
+    // Wait for the budget of background I/Os between the migration batches,
+    // with the GC section of the caller suspended rather than sleeping in it,
+    // so the foreground isn't blocked. Nothing is migrated during the wait,
+    // the victims (or the defragmented runs) are picked afterwards
+    fn wait_for_io_budget(&self) {
+        if let Some(io_limiter) = &self.io_limiter
+            && io_limiter.is_in_debt()
+        {
+            self.shared_state
+                .suspend_gc(|| io_limiter.wait_for_budget());
+        }
+    }
+
+    // Read the user data blocks at `hba` into `buf`, charged to the budget
+    // of background I/Os in chunks of at most its outstanding bytes
+    fn read_data(&self, hba: Hba, buf: &mut [u8]) -> Result<()> {
+        let Some(io_limiter) = &self.io_limiter else {
+            return self.user_data_disk.read(hba, BufMut::try_from(buf)?);
+        };
+        let chunk_nblocks = io_limiter.max_io_nblocks();
+        for (nth, chunk) in buf
+            .chunks_mut(chunk_nblocks.saturating_mul(BLOCK_SIZE))
+            .enumerate()
+        {
+            io_limiter.run_io(chunk.len() / BLOCK_SIZE, || {
+                self.user_data_disk
+                    .read(hba + nth * chunk_nblocks, BufMut::try_from(chunk)?)
+            })?;
+        }
+        Ok(())
+    }
+
+    // Write the user data blocks in `buf` at `hba`, the same as `read_data()`
+    fn write_data(&self, hba: Hba, buf: &[u8]) -> Result<()> {
+        let Some(io_limiter) = &self.io_limiter else {
+            return self.user_data_disk.write(hba, BufRef::try_from(buf)?);
+        };
+        let chunk_nblocks = io_limiter.max_io_nblocks();
+        for (nth, chunk) in buf
+            .chunks(chunk_nblocks.saturating_mul(BLOCK_SIZE))
+            .enumerate()
+        {
+            io_limiter.run_io(chunk.len() / BLOCK_SIZE, || {
+                self.user_data_disk
+                    .write(hba + nth * chunk_nblocks, BufRef::try_from(chunk)?)
+            })?;
+        }
+        Ok(())
+    }
+
+    // TODO: Support more rules
+    fn trigger_gc(&self, victim: Option<&Victim>) -> bool {
+        if victim.is_none() {
+            return false;
+        }
+        #[cfg(not(feature = "linux"))]
+        debug!(
+            "Triggered background GC, victim segment: {}",
+            victim.unwrap().segment_id
+        );
+        true
+    }
+
+    // fn clean_segment(&self, segment: &Segment) -> Result<()> {
+    //     segment.clear_segment();
+    //     self.block_validity_table.set_deallocated(nth);
+    // }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     use crate::os::Rng;
     use crate::util::Rng as UtilRng;
     use crate::BlockId;
@@ -530,16 +1573,24 @@ mod tests {
             bio::MemDisk,
             disk::{
                 block_alloc::{AllocTable, BlockAlloc},
-                config::Config,
-                gc::{GreedyVictimPolicy, VictimPolicy},
+                config::{BlockCryptoMode, Config},
+                gc::{
+                    GcContext, GenerationalVictimPolicy, GreedyVictimPolicy,
+                    LazyGreedyVictimPolicy, Victim, VictimPolicy, WindowGreedyVictimPolicy,
+                },
+                rate_limit::BackgroundIoLimit,
                 segment::{Segment, SEGMENT_SIZE},
+                sworndisk::EmptyFactory,
             },
             log::TxLogStore,
-            lsm::{AsKV, SyncIdStore, TxEventListener, TxEventListenerFactory, TxLsmTree, TxType},
+            lsm::{
+                AsKV, LsmParams, SyncIdStore, TxEventListener, TxEventListenerFactory, TxLsmTree,
+                TxType,
+            },
         },
         tx::Tx,
         util::BitMap,
-        AeadKey, RandomInit, SwornDisk,
+        AeadKey, RandomInit, SwornDisk, STATS_ENABLED,
     };
     use core::num::NonZeroUsize;
     use spin::Mutex;
@@ -563,6 +1614,49 @@ mod tests {
         BlockId::from_le_bytes(rnd_pos_bytes) % (total_nblocks - buf_nblocks)
     }
 
+    fn gen_rnd_f64() -> f64 {
+        let mut rnd_bytes = [0u8; 8];
+        Rng::new(&[]).fill_bytes(&mut rnd_bytes).unwrap();
+        (u64::from_le_bytes(rnd_bytes) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Generates zipfian positions in `[0, nblocks)`, lower positions are hotter,
+    /// so that GC tests can exercise hot/cold data separation.
+    struct ZipfPosGenerator {
+        nblocks: usize,
+        theta: f64,
+        zetan: f64,
+        eta: f64,
+    }
+
+    impl ZipfPosGenerator {
+        fn new(nblocks: usize, theta: f64) -> Self {
+            let zeta = |n: usize| -> f64 { (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum() };
+            let zetan = zeta(nblocks);
+            let eta = (1.0 - (2.0 / nblocks as f64).powf(1.0 - theta)) / (1.0 - zeta(2) / zetan);
+            Self {
+                nblocks,
+                theta,
+                zetan,
+                eta,
+            }
+        }
+
+        fn next_pos(&self) -> BlockId {
+            let u = gen_rnd_f64();
+            let uz = u * self.zetan;
+            if uz < 1.0 {
+                return 0;
+            }
+            if uz < 1.0 + 0.5f64.powf(self.theta) {
+                return 1;
+            }
+            let alpha = 1.0 / (1.0 - self.theta);
+            let pos = (self.nblocks as f64 * (self.eta * u - self.eta + 1.0).powf(alpha)) as usize;
+            pos.min(self.nblocks - 1)
+        }
+    }
+
     // I/O request will wait for background GC to finish
     #[test]
     fn io_and_gc_test() {
@@ -585,6 +1679,66 @@ mod tests {
         assert!(finished.load(Ordering::Acquire));
     }
 
+    // I/O requests won't be blocked forever if background GC panics
+    #[test]
+    fn gc_guard_test() {
+        let shared_state = Arc::new(SharedState::new());
+        let state_clone = shared_state.clone();
+        let gc_thread = std::thread::spawn(move || {
+            let _gc_guard = state_clone.begin_gc();
+            panic!("GC failed");
+        });
+        assert!(gc_thread.join().is_err());
+        shared_state.wait_for_background_gc();
+    }
+
+    // I/O requests aren't blocked while GC is suspended to wait for the budget
+    #[test]
+    fn suspend_gc_test() {
+        let shared_state = Arc::new(SharedState::new());
+        let gc_guard = shared_state.begin_gc();
+        let io_done = shared_state.suspend_gc(|| {
+            let state_clone = shared_state.clone();
+            std::thread::spawn(move || state_clone.wait_for_background_gc())
+                .join()
+                .is_ok()
+        });
+        assert!(io_done);
+        // The section is resumed until the guard is dropped
+        assert!(shared_state.is_gc_in_progress());
+        drop(gc_guard);
+        assert!(!shared_state.is_gc_in_progress());
+    }
+
+    // No GC begins while a compaction is prioritized
+    #[test]
+    fn prioritized_compaction_test() {
+        let shared_state = Arc::new(SharedState::new());
+        let priority = shared_state.prioritize_compaction();
+        assert!(shared_state.has_prioritized_compactions());
+
+        let began = Arc::new(AtomicBool::new(false));
+        let gc_thread = std::thread::spawn({
+            let shared_state = shared_state.clone();
+            let began = began.clone();
+            move || {
+                let _gc_guard = shared_state.begin_gc();
+                began.store(true, Ordering::Release);
+            }
+        });
+        // The prioritized compaction proceeds without GC
+        std::thread::sleep(Duration::from_millis(50));
+        shared_state.wait_for_background_gc();
+        shared_state.start_compaction();
+        assert!(!began.load(Ordering::Acquire));
+        shared_state.notify_compaction_finished();
+
+        drop(priority);
+        gc_thread.join().unwrap();
+        assert!(began.load(Ordering::Acquire));
+        assert!(!shared_state.has_prioritized_compactions());
+    }
+
     #[test]
     fn gc_waits_for_compaction_test() {
         // init_logger();
@@ -683,6 +1837,44 @@ mod tests {
         assert_eq!(finished.load(Ordering::Acquire), 2);
     }
 
+    // The same interleaving as `compaction_gc_io_test`, driven by wait points
+    // instead of real sleeps
+    #[cfg(feature = "sim")]
+    #[test]
+    fn compaction_gc_io_sim_test() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let shared_state = Arc::new(SharedState::new());
+        shared_state.start_compaction();
+
+        let gc_thread = std::thread::spawn({
+            let finished = Arc::clone(&finished);
+            let shared_state = Arc::clone(&shared_state);
+            move || {
+                shared_state.wait_for_compaction();
+                assert_eq!(finished.load(Ordering::Acquire), 1);
+                shared_state.start_gc();
+                finished.store(2, Ordering::Release);
+                // Keep GC in progress until the I/O request waits for it
+                shared_state.gc_wait_point().wait_for_waiters(1);
+                finished.store(3, Ordering::Release);
+                shared_state.notify_gc_finished();
+            }
+        });
+
+        // GC waits for compaction
+        shared_state.compaction_wait_point().wait_for_waiters(1);
+        finished.store(1, Ordering::Release);
+        shared_state.notify_compaction_finished();
+
+        // I/O waits for GC
+        while finished.load(Ordering::Acquire) < 2 {
+            std::thread::yield_now();
+        }
+        shared_state.wait_for_background_gc();
+        assert_eq!(finished.load(Ordering::Acquire), 3);
+        gc_thread.join().unwrap();
+    }
+
     #[test]
     fn greedy_victim_policy_test() {
         let bitmap = Arc::new(Mutex::new(BitMap::repeat(true, 3 * 1024)));
@@ -701,6 +1893,107 @@ mod tests {
         assert_eq!(victim.unwrap().segment_id, 1);
     }
 
+    #[test]
+    fn victim_policy_with_ctx_test() {
+        // Picks the oldest segment with any invalid block
+        struct OldestVictimPolicy;
+        impl VictimPolicy for OldestVictimPolicy {
+            fn pick_victim(&self, _segment_table: &[Segment], _threshold: f64) -> Option<Victim> {
+                None
+            }
+
+            fn pick_victim_with_ctx(&self, ctx: &GcContext) -> Option<Victim> {
+                ctx.segment_table
+                    .iter()
+                    .filter(|segment| segment.num_invalid_blocks() > 0)
+                    .max_by_key(|segment| ctx.segment_age(segment.segment_id()))
+                    .map(|segment| {
+                        Victim::new(segment.segment_id(), segment.find_all_allocated_blocks())
+                    })
+            }
+        }
+
+        let bitmap = Arc::new(Mutex::new(BitMap::repeat(true, 3 * 1024)));
+        let segment_table = vec![
+            Segment::new(0, 1024, bitmap.clone()),
+            Segment::new(1, 1024, bitmap.clone()),
+            Segment::new(2, 1024, bitmap.clone()),
+        ];
+        segment_table[0].set_last_write(10);
+        segment_table[1].set_last_write(20);
+        for _ in 0..2 {
+            segment_table[0].mark_alloc();
+            segment_table[0].mark_deallocated();
+        }
+        for _ in 0..3 {
+            segment_table[1].mark_alloc();
+            segment_table[1].mark_deallocated();
+        }
+        let ctx = GcContext {
+            segment_table: &segment_table,
+            threshold: 0.,
+            utilization: 0.,
+            write_rate: 0.,
+            write_seq: 30,
+        };
+        assert_eq!(ctx.segment_age(0), 20);
+        assert_eq!(ctx.segment_heat(1), 3);
+
+        // The default method falls back to `pick_victim()`
+        let victim = GreedyVictimPolicy {}.pick_victim_with_ctx(&ctx);
+        assert_eq!(victim.unwrap().segment_id(), 1);
+        let victim = OldestVictimPolicy.pick_victim_with_ctx(&ctx);
+        assert_eq!(victim.unwrap().segment_id(), 0);
+    }
+
+    #[test]
+    fn window_and_generational_policy_test() {
+        let bitmap = Arc::new(Mutex::new(BitMap::repeat(true, 4 * 1024)));
+        let segment_table = vec![
+            Segment::new(0, 1024, bitmap.clone()),
+            Segment::new(1, 1024, bitmap.clone()),
+            Segment::new(2, 1024, bitmap.clone()),
+            Segment::new(3, 1024, bitmap.clone()),
+        ];
+        // Segment 2 is the youngest, with the most invalid blocks.
+        // Segment 3 is never written, which looks the oldest
+        for (segment_id, num_invalid) in [(0, 1), (1, 2), (2, 3)] {
+            segment_table[segment_id].set_last_write(segment_id as u64 * 100);
+            for _ in 0..num_invalid {
+                segment_table[segment_id].mark_alloc();
+                segment_table[segment_id].mark_deallocated();
+            }
+        }
+        let ctx = GcContext {
+            segment_table: &segment_table,
+            threshold: 0.,
+            utilization: 0.,
+            write_rate: 0.,
+            write_seq: 300,
+        };
+
+        let policy = WindowGreedyVictimPolicy::new(2);
+        assert_eq!(policy.pick_victim_with_ctx(&ctx).unwrap().segment_id(), 1);
+        let policy = WindowGreedyVictimPolicy::new(3);
+        assert_eq!(policy.pick_victim_with_ctx(&ctx).unwrap().segment_id(), 2);
+
+        let policy = GenerationalVictimPolicy::new(250);
+        assert_eq!(policy.pick_victim_with_ctx(&ctx).unwrap().segment_id(), 0);
+        // No segment is old enough, fall back to the young generation
+        let policy = GenerationalVictimPolicy::new(1000);
+        assert_eq!(policy.pick_victim_with_ctx(&ctx).unwrap().segment_id(), 2);
+
+        let policy = LazyGreedyVictimPolicy::new(0);
+        assert_eq!(policy.pick_victim_with_ctx(&ctx).unwrap().segment_id(), 2);
+        let policy = LazyGreedyVictimPolicy::new(1);
+        assert_eq!(policy.pick_victim_with_ctx(&ctx).unwrap().segment_id(), 1);
+        let policy = LazyGreedyVictimPolicy::new(2);
+        assert_eq!(policy.pick_victim_with_ctx(&ctx).unwrap().segment_id(), 0);
+        // All the segments with invalid blocks are excluded, fall back to greedy
+        let policy = LazyGreedyVictimPolicy::new(3);
+        assert_eq!(policy.pick_victim_with_ctx(&ctx).unwrap().segment_id(), 2);
+    }
+
     #[test]
     fn threshold_test() {
         let bitmap = Arc::new(Mutex::new(BitMap::repeat(true, 3 * 1024)));
@@ -760,6 +2053,86 @@ mod tests {
         assert_eq!(read_buf.as_slice(), content);
     }
 
+    // GC yields to the latency-critical reads at the next victim segment
+    #[test]
+    fn gc_yields_to_urgent_reads() {
+        use crate::layers::disk::{DiskEvent, EventSubscriber};
+
+        struct MigrationCounter(AtomicUsize);
+        impl EventSubscriber for MigrationCounter {
+            fn on_event(&self, event: &DiskEvent) {
+                if let DiskEvent::BlocksMigrated(migrations) = event {
+                    self.0.fetch_add(migrations.len(), Ordering::Relaxed);
+                }
+            }
+        }
+
+        init_logger();
+        let nblocks = 256 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        let config = Some(Config {
+            enable_gc: true,
+            ..Default::default()
+        });
+        let disk = SwornDisk::create(mem_disk, AeadKey::random(), None, config).unwrap();
+        let gc_worker = disk
+            .create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+            .unwrap();
+        let counter = Arc::new(MigrationCounter(AtomicUsize::new(0)));
+        disk.subscribe_events_sync(counter.clone());
+
+        let mut buf = Buf::alloc(1).unwrap();
+        buf.as_mut_slice().fill(1);
+        for _ in 0..300 {
+            disk.write(0, buf.as_ref()).unwrap();
+            disk.sync().unwrap();
+        }
+        let urgent_guard = gc_worker.shared_state.begin_urgent_read();
+        gc_worker.background_gc().unwrap();
+        assert_eq!(counter.0.load(Ordering::Relaxed), 0);
+        drop(urgent_guard);
+        gc_worker.background_gc().unwrap();
+        assert!(counter.0.load(Ordering::Relaxed) > 0);
+
+        let mut read_buf = Buf::alloc(1).unwrap();
+        disk.read_urgent(0, read_buf.as_mut()).unwrap();
+        assert_eq!(read_buf.as_slice(), buf.as_slice());
+    }
+
+    #[test]
+    fn rate_limited_data_migration() {
+        init_logger();
+        let nblocks = 256 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        // Three quarters of a segment per second, in chunks of 64 blocks
+        let config = Some(Config {
+            enable_gc: true,
+            background_io_limit: Some(BackgroundIoLimit {
+                bandwidth: (SEGMENT_SIZE * BLOCK_SIZE * 3 / 4) as u64,
+                max_outstanding: 64 * BLOCK_SIZE,
+            }),
+            ..Default::default()
+        });
+        let disk = SwornDisk::create(mem_disk, AeadKey::random(), None, config).unwrap();
+        let gc_worker = disk
+            .create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+            .unwrap();
+
+        let mut buf = Buf::alloc(1).unwrap();
+        buf.as_mut_slice().fill(1);
+        for _ in 0..300 {
+            disk.write(0, buf.as_ref()).unwrap();
+            disk.sync().unwrap();
+        }
+        gc_worker.background_gc().unwrap();
+
+        // Reading the whole victim segment exceeds the burst of a second
+        assert!(disk.background_io_stats().unwrap().num_throttled > 0);
+        let mut read_buf = Buf::alloc(1).unwrap();
+        disk.read(0, read_buf.as_mut()).unwrap();
+        assert_eq!(read_buf.as_slice(), buf.as_slice());
+    }
+
     #[test]
     fn batch_data_migration() {
         init_logger();
@@ -813,6 +2186,155 @@ mod tests {
         // after gc, the block at offset 0 should be migrated to another segment
     }
 
+    #[test]
+    fn derived_key_data_migration() {
+        init_logger();
+        let nblocks = 64 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        let root_key = AeadKey::random();
+        let config = Some(Config {
+            enable_gc: true,
+            crypto_mode: BlockCryptoMode::DerivedKey,
+            ..Default::default()
+        });
+        let disk = SwornDisk::create(mem_disk.clone(), root_key, None, config).unwrap();
+        let gc_worker = disk
+            .create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+            .unwrap();
+        // The derived keys are bound to the LBAs, the blocks are moved verbatim
+        assert!(gc_worker.aead.is_none());
+
+        let num_lbas = 64;
+        let mut buf = Buf::alloc(1).unwrap();
+        for lba in 0..num_lbas {
+            buf.as_mut_slice().fill(lba as u8);
+            disk.write(lba, buf.as_ref()).unwrap();
+        }
+        disk.sync().unwrap();
+        let old_values: Vec<_> = (0..num_lbas)
+            .map(|lba| {
+                gc_worker
+                    .logical_block_table
+                    .get(&RecordKey { lba })
+                    .unwrap()
+            })
+            .collect();
+
+        let segment_table = gc_worker
+            .block_validity_table
+            .get_segment_table_ref()
+            .unwrap();
+        let segment_id = old_values[0].hba / SEGMENT_SIZE;
+        let victim = Victim {
+            segment_id,
+            blocks: segment_table[segment_id].find_all_allocated_blocks(),
+        };
+        let mut tx = gc_worker.tx_provider.new_tx();
+        let migrations = tx
+            .context(|| {
+                let migrated_blocks = gc_worker.clean_and_migrate_data(victim)?;
+                gc_worker.remap_index_batch(migrated_blocks)
+            })
+            .unwrap();
+        tx.commit().unwrap();
+        assert_eq!(migrations.len(), num_lbas);
+        disk.sync().unwrap();
+
+        let mut read_buf = Buf::alloc(1).unwrap();
+        for (lba, old_value) in old_values.iter().enumerate() {
+            let value = gc_worker
+                .logical_block_table
+                .get(&RecordKey { lba })
+                .unwrap();
+            assert_ne!(value.hba, old_value.hba, "block {} is not migrated", lba);
+            assert_eq!(value.key, old_value.key);
+            disk.read(lba, read_buf.as_mut()).unwrap();
+            assert!(read_buf.as_slice().iter().all(|&byte| byte == lba as u8));
+        }
+
+        // The migrated records are recovered from their compact format
+        drop(gc_worker);
+        drop(disk);
+        let disk = SwornDisk::open(mem_disk, root_key, None, None).unwrap();
+        for lba in 0..num_lbas {
+            disk.read(lba, read_buf.as_mut()).unwrap();
+            assert!(read_buf.as_slice().iter().all(|&byte| byte == lba as u8));
+        }
+    }
+
+    #[test]
+    fn reencrypted_data_migration() {
+        init_logger();
+        let nblocks = 64 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        let config = Some(Config {
+            enable_gc: true,
+            reencrypt_on_gc: true,
+            ..Default::default()
+        });
+        let disk = SwornDisk::create(mem_disk, AeadKey::random(), None, config).unwrap();
+        let gc_worker = disk
+            .create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+            .unwrap();
+        assert!(gc_worker.aead.is_some());
+
+        let num_lbas = 64;
+        let mut buf = Buf::alloc(1).unwrap();
+        for lba in 0..num_lbas {
+            buf.as_mut_slice().fill(lba as u8);
+            disk.write(lba, buf.as_ref()).unwrap();
+        }
+        disk.sync().unwrap();
+        let old_values: Vec<_> = (0..num_lbas)
+            .map(|lba| {
+                gc_worker
+                    .logical_block_table
+                    .get(&RecordKey { lba })
+                    .unwrap()
+            })
+            .collect();
+
+        // Migrate the segment of the blocks as a victim
+        let segment_table = gc_worker
+            .block_validity_table
+            .get_segment_table_ref()
+            .unwrap();
+        let segment_id = old_values[0].hba / SEGMENT_SIZE;
+        let victim = Victim {
+            segment_id,
+            blocks: segment_table[segment_id].find_all_allocated_blocks(),
+        };
+        let mut tx = gc_worker.tx_provider.new_tx();
+        let migrations = tx
+            .context(|| {
+                let migrated_blocks = gc_worker.clean_and_migrate_data(victim)?;
+                gc_worker.remap_index_batch(migrated_blocks)
+            })
+            .unwrap();
+        tx.commit().unwrap();
+        assert_eq!(migrations.len(), num_lbas);
+        assert!(migrations.iter().all(|migration| migration.rekeyed));
+
+        let mut read_buf = Buf::alloc(1).unwrap();
+        for (lba, old_value) in old_values.into_iter().enumerate() {
+            let value = gc_worker
+                .logical_block_table
+                .get(&RecordKey { lba })
+                .unwrap();
+            if old_value.hba / SEGMENT_SIZE != segment_id {
+                continue;
+            }
+            assert_ne!(value.hba, old_value.hba, "block {} is not migrated", lba);
+            assert_ne!(
+                value.key, old_value.key,
+                "block {} is not re-encrypted",
+                lba
+            );
+            disk.read(lba, read_buf.as_mut()).unwrap();
+            assert!(read_buf.as_slice().iter().all(|&byte| byte == lba as u8));
+        }
+    }
+
     #[test]
     fn multi_segment_migration() {
         init_logger();
@@ -843,4 +2365,590 @@ mod tests {
 
         gc_worker.background_gc().unwrap();
     }
+
+    #[test]
+    fn skewed_workload_migration() {
+        init_logger();
+        let nblocks = 64 * SEGMENT_SIZE;
+        let policies: Vec<VictimPolicyRef> = vec![
+            Arc::new(GreedyVictimPolicy {}),
+            Arc::new(LoopScanVictimPolicy::new()),
+        ];
+
+        for policy in policies {
+            let mem_disk = MemDisk::create(nblocks * 5 / 4).unwrap();
+            let config = Some(Config {
+                enable_gc: true,
+                ..Default::default()
+            });
+            let disk = SwornDisk::create(mem_disk, AeadKey::random(), None, config).unwrap();
+            let gc_worker = disk.create_gc_worker(policy).unwrap();
+
+            let pos_gen = ZipfPosGenerator::new(nblocks, 0.99);
+            let mut latest = BTreeMap::new();
+            let mut buf = Buf::alloc(1).unwrap();
+            for i in 0..2 * nblocks {
+                let block_id = pos_gen.next_pos();
+                buf.as_mut_slice().fill(i as u8);
+                disk.write(block_id, buf.as_ref()).unwrap();
+                latest.insert(block_id, i as u8);
+            }
+            disk.sync().unwrap();
+
+            gc_worker.background_gc().unwrap();
+
+            let mut read_buf = Buf::alloc(1).unwrap();
+            for (block_id, content) in latest {
+                disk.read(block_id, read_buf.as_mut()).unwrap();
+                assert_eq!(
+                    read_buf.as_slice()[0],
+                    content,
+                    "block {} is lost",
+                    block_id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lba_order_defragmentation() {
+        init_logger();
+        let nblocks = 256 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        let root_key = AeadKey::random();
+
+        let config = Some(Config {
+            enable_gc: true,
+            ..Default::default()
+        });
+        let disk = SwornDisk::create(mem_disk, root_key, None, config).unwrap();
+        let gc_worker = disk
+            .create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+            .unwrap();
+
+        // Interleave the writes, so that adjacent LBAs are physically scattered
+        let num_rw = 64;
+        for lbas in [(0..num_rw).step_by(2), (1..num_rw).step_by(2)] {
+            for i in lbas {
+                let mut buf = Buf::alloc(1).unwrap();
+                buf.as_mut_slice().fill(i as u8);
+                disk.write(i, buf.as_ref()).unwrap();
+            }
+            disk.sync().unwrap();
+        }
+        let get_hbas = || {
+            (0..num_rw)
+                .map(|lba| {
+                    gc_worker
+                        .logical_block_table
+                        .get(&RecordKey { lba })
+                        .unwrap()
+                        .hba
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(get_hbas().windows(2).any(|pair| pair[1] != pair[0] + 1));
+
+        assert_eq!(gc_worker.defragment().unwrap(), num_rw);
+        let hbas = get_hbas();
+        assert!(hbas.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        for (lba, hba) in hbas.into_iter().enumerate() {
+            let reverse_value = gc_worker
+                .reverse_index_table
+                .get(&ReverseKey { hba })
+                .unwrap();
+            assert_eq!(reverse_value.lba, lba);
+        }
+
+        disk.sync().unwrap();
+        let mut read_buf = Buf::alloc(num_rw).unwrap();
+        disk.read(0, read_buf.as_mut()).unwrap();
+        for (i, block) in read_buf.as_slice().chunks(BLOCK_SIZE).enumerate() {
+            assert_eq!(block, [i as u8; BLOCK_SIZE], "block {} is not migrated", i);
+        }
+    }
+
+    // A synchronous subscriber caching the mappings sees the migrations before
+    // GC finishes, and the reads racing with the migration are never stale
+    #[test]
+    fn cache_coherence_during_migration() {
+        use crate::layers::disk::{DiskEvent, EventSubscriber};
+
+        struct MappingCache(Mutex<HashMap<Lba, Hba>>);
+        impl EventSubscriber for MappingCache {
+            fn on_event(&self, event: &DiskEvent) {
+                let DiskEvent::BlocksMigrated(migrations) = event else {
+                    return;
+                };
+                let mut mappings = self.0.lock();
+                for migration in migrations {
+                    let hba = mappings.get_mut(&migration.lba).unwrap();
+                    assert_eq!(*hba, migration.old_hba);
+                    *hba = migration.new_hba;
+                }
+            }
+        }
+
+        init_logger();
+        let nblocks = 256 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        let config = Some(Config {
+            enable_gc: true,
+            ..Default::default()
+        });
+        let disk = SwornDisk::create(mem_disk, AeadKey::random(), None, config).unwrap();
+        let gc_worker = disk
+            .create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+            .unwrap();
+
+        // The cold blocks stay valid in the first segment, to be migrated
+        let (num_cold, num_hot) = (16, 16);
+        let write_block = |lba: Lba| {
+            let mut buf = Buf::alloc(1).unwrap();
+            buf.as_mut_slice().fill(lba as u8);
+            disk.write(lba, buf.as_ref()).unwrap();
+        };
+        (0..num_cold).for_each(write_block);
+        disk.sync().unwrap();
+        for _ in 0..SEGMENT_SIZE / num_hot + 1 {
+            (num_cold..num_cold + num_hot).for_each(write_block);
+            disk.sync().unwrap();
+        }
+
+        let cache = Arc::new(MappingCache(Mutex::new(
+            disk.iter_mappings()
+                .unwrap()
+                .collect::<Result<HashMap<_, _>>>()
+                .unwrap(),
+        )));
+        disk.subscribe_events_sync(cache.clone());
+
+        let finished = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let mut buf = Buf::alloc(1).unwrap();
+                    while !finished.load(Ordering::Acquire) {
+                        let lba = gen_rnd_pos(num_cold + num_hot + 1, 1);
+                        disk.read(lba, buf.as_mut()).unwrap();
+                        assert_eq!(buf.as_slice(), [lba as u8; BLOCK_SIZE]);
+                    }
+                });
+            }
+            gc_worker.background_gc().unwrap();
+            finished.store(true, Ordering::Release);
+        });
+
+        // Refreshed once GC returns, without waiting for any delivery
+        let mappings: HashMap<_, _> = disk
+            .iter_mappings()
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(*cache.0.lock(), mappings);
+        assert!((0..num_cold).all(|lba| mappings[&lba] >= SEGMENT_SIZE));
+    }
+
+    #[test]
+    fn gc_retry_backoff() {
+        let nblocks = 64 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        let config = Some(Config {
+            enable_gc: true,
+            ..Default::default()
+        });
+        let disk = SwornDisk::create(mem_disk, AeadKey::random(), None, config).unwrap();
+        let gc_worker = disk
+            .create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+            .unwrap();
+
+        let failed = || Err(Error::with_msg(Errno::IoFailed, "transient failure"));
+        for nth in 0..4 {
+            assert_eq!(
+                gc_worker.next_interval(failed()),
+                GC_RETRY_INTERVAL_TIME * (1 << nth)
+            );
+        }
+        for _ in 0..32 {
+            gc_worker.next_interval(failed());
+        }
+        assert_eq!(
+            gc_worker.next_interval(failed()),
+            MAX_GC_RETRY_INTERVAL_TIME
+        );
+
+        // Restarts from the base once a round succeeds
+        let interval = Duration::from_secs(1);
+        assert_eq!(gc_worker.next_interval(Ok(interval)), interval);
+        assert_eq!(gc_worker.next_interval(failed()), GC_RETRY_INTERVAL_TIME);
+    }
+
+    #[cfg(feature = "chaos")]
+    #[test]
+    fn failed_remapping_undone() {
+        use crate::util::{fail_hook, set_chaos_hook, ChaosPoint};
+
+        let nblocks = 64 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        let config = Some(Config {
+            enable_gc: true,
+            ..Default::default()
+        });
+        let disk = SwornDisk::create(mem_disk, AeadKey::random(), None, config).unwrap();
+        let gc_worker = disk
+            .create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+            .unwrap();
+
+        let num_lbas = 64;
+        let mut buf = Buf::alloc(1).unwrap();
+        for lba in 0..num_lbas {
+            buf.as_mut_slice().fill(lba as u8);
+            disk.write(lba, buf.as_ref()).unwrap();
+        }
+        disk.sync().unwrap();
+
+        let segment_table = gc_worker
+            .block_validity_table
+            .get_segment_table_ref()
+            .unwrap();
+        let hba = gc_worker
+            .logical_block_table
+            .get(&RecordKey { lba: 0 })
+            .unwrap()
+            .hba;
+        let segment_id = hba / SEGMENT_SIZE;
+        let allocated = segment_table[segment_id].find_all_allocated_blocks();
+        let victim = Victim {
+            segment_id,
+            blocks: allocated.clone(),
+        };
+
+        // Fail after the record of the first block is remapped
+        let guard = set_chaos_hook(ChaosPoint::GcRemap, fail_hook(Errno::IoFailed, 1));
+        let mut tx = gc_worker.tx_provider.new_tx();
+        let res = tx.context(|| {
+            let migrated_blocks = gc_worker.clean_and_migrate_data(victim)?;
+            gc_worker.remap_index_batch(migrated_blocks)
+        });
+        assert_eq!(res.unwrap_err().errno(), Errno::IoFailed);
+        tx.abort();
+        drop(guard);
+
+        // The blocks not remapped are still allocated in the victim segment
+        assert_eq!(
+            segment_table[segment_id].find_all_allocated_blocks(),
+            allocated[1..]
+        );
+        let mut read_buf = Buf::alloc(1).unwrap();
+        for lba in 0..num_lbas {
+            disk.read(lba, read_buf.as_mut()).unwrap();
+            assert!(read_buf.as_slice().iter().all(|&byte| byte == lba as u8));
+        }
+    }
+
+    #[test]
+    fn missing_reverse_index_entries() {
+        init_logger();
+        let nblocks = 256 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        let root_key = AeadKey::random();
+
+        let config = Some(Config {
+            enable_gc: true,
+            ..Default::default()
+        });
+        let disk = SwornDisk::create(mem_disk, root_key, None, config).unwrap();
+        let gc_worker = disk
+            .create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+            .unwrap();
+
+        let mut buf = Buf::alloc(1).unwrap();
+        buf.as_mut_slice().fill(1);
+        for _ in 0..300 {
+            disk.write(0, buf.as_ref()).unwrap();
+            disk.sync().unwrap();
+        }
+
+        // Lose all the reverse index entries
+        let reverse_index_disk = MemDisk::create(16 * SEGMENT_SIZE).unwrap();
+        let reverse_index_table = ReverseIndex::Lsm(
+            TxLsmTree::format(
+                Arc::new(TxLogStore::format(reverse_index_disk, root_key).unwrap()),
+                Arc::new(EmptyFactory),
+                None,
+                None,
+                gc_worker.shared_state.clone(),
+                gc_worker.config.clone(),
+                LsmParams::default(),
+            )
+            .unwrap(),
+        );
+        let gc_worker = GcWorker::new(
+            gc_worker.victim_policy.clone(),
+            gc_worker.logical_block_table.clone(),
+            reverse_index_table,
+            gc_worker.dealloc_table.clone(),
+            gc_worker.tx_log_store.clone(),
+            gc_worker.block_validity_table.clone(),
+            gc_worker.user_data_disk.clone(),
+            gc_worker.shared_state.clone(),
+            gc_worker.aead.clone(),
+            gc_worker.disk_id,
+            gc_worker.digest_tree.clone(),
+            gc_worker.io_limiter.clone(),
+            gc_worker.config.clone(),
+            gc_worker.stats.clone(),
+            gc_worker.events.clone(),
+        );
+
+        gc_worker.background_gc().unwrap();
+        if STATS_ENABLED {
+            assert!(disk.stats().gc().get_stats().missing_reverse_entries >= 300);
+        }
+        // The live block is found in the logical block table and kept
+        let mut read_buf = Buf::alloc(1).unwrap();
+        disk.read(0, read_buf.as_mut()).unwrap();
+        assert!(read_buf.as_slice().iter().all(|&byte| byte == 1));
+    }
+
+    #[test]
+    fn dropped_reverse_entry_survives_gc() {
+        init_logger();
+        let nblocks = 64 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        let config = Some(Config {
+            enable_gc: true,
+            ..Default::default()
+        });
+        let disk = SwornDisk::create(mem_disk, AeadKey::random(), None, config).unwrap();
+        let gc_worker = disk
+            .create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+            .unwrap();
+
+        let num_lbas = 64;
+        let mut buf = Buf::alloc(1).unwrap();
+        for lba in 0..num_lbas {
+            buf.as_mut_slice().fill(lba as u8);
+            disk.write(lba, buf.as_ref()).unwrap();
+        }
+        disk.sync().unwrap();
+
+        // Drop the reverse entry of a live block
+        let lost_lba = 7;
+        let lost_hba = gc_worker
+            .logical_block_table
+            .get(&RecordKey { lba: lost_lba })
+            .unwrap()
+            .hba;
+        let ReverseIndex::Lsm(reverse_index_table) = &gc_worker.reverse_index_table else {
+            unreachable!("the reverse index is a TxLsmTree by default");
+        };
+        reverse_index_table
+            .delete_range(ReverseKey { hba: lost_hba }..ReverseKey { hba: lost_hba + 1 })
+            .unwrap();
+        assert!(gc_worker
+            .reverse_index_table
+            .get(&ReverseKey { hba: lost_hba })
+            .is_err());
+
+        let segment_table = gc_worker
+            .block_validity_table
+            .get_segment_table_ref()
+            .unwrap();
+        let segment_id = lost_hba / SEGMENT_SIZE;
+        let victim = Victim {
+            segment_id,
+            blocks: segment_table[segment_id].find_all_allocated_blocks(),
+        };
+        let mut tx = gc_worker.tx_provider.new_tx();
+        let migrations = tx
+            .context(|| {
+                let migrated_blocks = gc_worker.clean_and_migrate_data(victim)?;
+                gc_worker.remap_index_batch(migrated_blocks)
+            })
+            .unwrap();
+        tx.commit().unwrap();
+
+        // The block is migrated rather than freed under its record
+        assert!(migrations
+            .iter()
+            .any(|migration| migration.lba == lost_lba && migration.old_hba == lost_hba));
+        let new_hba = gc_worker
+            .logical_block_table
+            .get(&RecordKey { lba: lost_lba })
+            .unwrap()
+            .hba;
+        assert_ne!(new_hba / SEGMENT_SIZE, segment_id);
+        assert_eq!(
+            gc_worker
+                .reverse_index_table
+                .get(&ReverseKey { hba: new_hba })
+                .unwrap()
+                .lba,
+            lost_lba
+        );
+
+        // Overwrite the freed segment, the data of all LBAs survive
+        buf.as_mut_slice().fill(u8::MAX);
+        for lba in num_lbas..num_lbas + SEGMENT_SIZE {
+            disk.write(lba, buf.as_ref()).unwrap();
+        }
+        disk.sync().unwrap();
+        let mut read_buf = Buf::alloc(1).unwrap();
+        for lba in 0..num_lbas {
+            disk.read(lba, read_buf.as_mut()).unwrap();
+            assert!(read_buf.as_slice().iter().all(|&byte| byte == lba as u8));
+        }
+    }
+
+    /// Fill a block of the stress test, which carries its LBA, a sequence number
+    /// and a checksum of the whole block.
+    fn fill_stress_block(block: &mut [u8], lba: Lba, seq: u64) {
+        block[..8].copy_from_slice(&(lba as u64).to_le_bytes());
+        block[8..16].copy_from_slice(&seq.to_le_bytes());
+        for (i, byte) in block[24..].iter_mut().enumerate() {
+            *byte = (seq as usize).wrapping_mul(31).wrapping_add(i) as u8;
+        }
+        let checksum = stress_block_checksum(block);
+        block[16..24].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    fn stress_block_checksum(block: &[u8]) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        block[..16].hash(&mut hasher);
+        block[24..].hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Check a block of the stress test, return its sequence number,
+    /// or `None` if the block has never been written.
+    fn check_stress_block(block: &[u8], lba: Lba) -> Option<u64> {
+        if block.iter().all(|&byte| byte == 0) {
+            return None;
+        }
+        let checksum = u64::from_le_bytes(block[16..24].try_into().unwrap());
+        assert_eq!(
+            checksum,
+            stress_block_checksum(block),
+            "lba {lba} is corrupted"
+        );
+        let block_lba = u64::from_le_bytes(block[..8].try_into().unwrap());
+        assert_eq!(block_lba as usize, lba, "lba {lba} is misdirected");
+        Some(u64::from_le_bytes(block[8..16].try_into().unwrap()))
+    }
+
+    // Many writers, a reader and forced GC rounds hammer a small disk at the same time,
+    // run it with `cargo test -- --ignored gc_stress_test`. The duration (in seconds)
+    // can be set by the environment variable `SWORNDISK_STRESS_SECS`.
+    #[test]
+    #[ignore]
+    fn gc_stress_test() {
+        let nblocks = 64 * SEGMENT_SIZE;
+        let mem_disk = MemDisk::create(nblocks).unwrap();
+        let root_key = AeadKey::random();
+        let config = Some(Config {
+            enable_gc: true,
+            ..Default::default()
+        });
+        let disk = Arc::new(SwornDisk::create(mem_disk, root_key, None, config).unwrap());
+        let gc_worker = Arc::new(
+            disk.create_gc_worker(Arc::new(GreedyVictimPolicy {}))
+                .unwrap(),
+        );
+        let secs = std::env::var("SWORNDISK_STRESS_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(10);
+        let deadline = std::time::Instant::now() + Duration::from_secs(secs);
+
+        // Half of the user data blocks are live, so that GC is constantly required
+        let nwriters = 4;
+        let num_lbas = disk.total_blocks() / 2 / nwriters * nwriters;
+        let lbas_per_writer = num_lbas / nwriters;
+        let writers: Vec<_> = (0..nwriters)
+            .map(|nth| {
+                let disk = disk.clone();
+                std::thread::spawn(move || {
+                    let start_lba = nth * lbas_per_writer;
+                    let mut last_seqs = vec![0u64; lbas_per_writer];
+                    let mut seq = 0u64;
+                    let mut buf = Buf::alloc(1).unwrap();
+                    while std::time::Instant::now() < deadline {
+                        seq += 1;
+                        let offset = gen_rnd_pos(lbas_per_writer + 1, 1);
+                        let lba = start_lba + offset;
+                        fill_stress_block(buf.as_mut_slice(), lba, seq);
+                        disk.write(lba, buf.as_ref()).unwrap();
+                        last_seqs[offset] = seq;
+
+                        if seq % 64 == 0 {
+                            disk.sync().unwrap();
+                            // A writer always reads its own latest writes
+                            let offset = gen_rnd_pos(lbas_per_writer + 1, 1);
+                            let _ = disk
+                                .read_with_holes(start_lba + offset, buf.as_mut())
+                                .unwrap();
+                            let read_seq = check_stress_block(buf.as_slice(), start_lba + offset);
+                            assert_eq!(read_seq.unwrap_or(0), last_seqs[offset]);
+                        }
+                        // Vary the interleaving with other threads
+                        if seq % 7 == 0 {
+                            std::thread::yield_now();
+                        }
+                    }
+                    disk.sync().unwrap();
+                    last_seqs
+                })
+            })
+            .collect();
+
+        let reader = {
+            let disk = disk.clone();
+            std::thread::spawn(move || {
+                let nblocks = 16;
+                let mut buf = Buf::alloc(nblocks).unwrap();
+                while std::time::Instant::now() < deadline {
+                    let lba = gen_rnd_pos(num_lbas, nblocks);
+                    let _ = disk.read_with_holes(lba, buf.as_mut()).unwrap();
+                    for (i, block) in buf.as_slice().chunks(BLOCK_SIZE).enumerate() {
+                        check_stress_block(block, lba + i);
+                    }
+                }
+            })
+        };
+
+        let gc_thread = {
+            let gc_worker = gc_worker.clone();
+            std::thread::spawn(move || {
+                let mut num_rounds = 0;
+                while std::time::Instant::now() < deadline {
+                    gc_worker.shared_state.wait_for_compaction();
+                    let gc_guard = gc_worker.shared_state.begin_gc();
+                    gc_worker.background_gc().unwrap();
+                    drop(gc_guard);
+                    num_rounds += 1;
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                num_rounds
+            })
+        };
+
+        let last_seqs: Vec<_> = writers
+            .into_iter()
+            .map(|writer| writer.join().unwrap())
+            .collect();
+        reader.join().unwrap();
+        assert!(gc_thread.join().unwrap() > 0);
+
+        // Every block holds the latest write of its writer
+        let mut buf = Buf::alloc(1).unwrap();
+        for (nth, seqs) in last_seqs.into_iter().enumerate() {
+            for (offset, seq) in seqs.into_iter().enumerate() {
+                let lba = nth * lbas_per_writer + offset;
+                let _ = disk.read_with_holes(lba, buf.as_mut()).unwrap();
+                assert_eq!(check_stress_block(buf.as_slice(), lba).unwrap_or(0), seq);
+            }
+        }
+    }
 }
@@ -0,0 +1,419 @@
+//! A simulator of GC victim policies.
+//!
+//! It replays a trace of user writes and discards against a segment table
+//! without any I/O, and counts the blocks each `VictimPolicy` migrates, i.e.,
+//! its write amplification, in a fraction of the time of a disk benchmark.
+//!
+//! The user writes fill an open segment sequentially, as do the migrated
+//! blocks (see `AllocPolicy::SegmentFill`). Once the clean segments drop
+//! below `GcSimConfig::reserved_segments`, GC picks victims until enough
+//! segments are cleaned.
+use super::gc::{GcContext, VictimPolicy};
+use super::segment::{Segment, SegmentId, SEGMENT_SIZE};
+use super::sworndisk::{Hba, Lba};
+use crate::os::{HashMap, Mutex};
+use crate::prelude::*;
+use crate::util::BitMap;
+
+/// An operation of a trace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceOp {
+    Write(Lba),
+    Discard(Lba),
+}
+
+/// The configuration of a simulation.
+#[derive(Clone, Copy, Debug)]
+pub struct GcSimConfig {
+    /// The number of segments of the simulated device.
+    pub nsegments: usize,
+    /// GC runs once the clean segments drop below it.
+    pub reserved_segments: usize,
+    /// The minimum fraction of invalid blocks in a victim segment.
+    pub threshold: f64,
+}
+
+impl Default for GcSimConfig {
+    fn default() -> Self {
+        Self {
+            nsegments: 64,
+            reserved_segments: 2,
+            threshold: 0.0,
+        }
+    }
+}
+
+/// The result of a simulation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcSimReport {
+    /// The number of blocks written by the user.
+    pub user_writes: u64,
+    /// The number of blocks migrated by GC.
+    pub migrated_blocks: u64,
+    /// The number of victims cleaned.
+    pub num_victims: u64,
+    /// Whether the replay stopped early for no segment could be cleaned.
+    pub out_of_space: bool,
+}
+
+impl GcSimReport {
+    /// The theoretical write amplification factor, i.e., the blocks written
+    /// to the device per block written by the user.
+    pub fn waf(&self) -> f64 {
+        if self.user_writes == 0 {
+            return 1.0;
+        }
+        (self.user_writes + self.migrated_blocks) as f64 / self.user_writes as f64
+    }
+}
+
+/// Replays `trace` on a simulated device with the victim policy.
+pub fn simulate_gc(
+    policy: &dyn VictimPolicy,
+    config: &GcSimConfig,
+    trace: impl IntoIterator<Item = TraceOp>,
+) -> GcSimReport {
+    let mut sim = GcSim::new(config);
+    for op in trace {
+        let res = match op {
+            TraceOp::Write(lba) => sim.write(policy, lba),
+            TraceOp::Discard(lba) => {
+                sim.discard(lba);
+                Ok(())
+            }
+        };
+        if res.is_err() {
+            sim.report.out_of_space = true;
+            break;
+        }
+    }
+    sim.report
+}
+
+struct GcSim {
+    config: GcSimConfig,
+    bitmap: Arc<Mutex<BitMap>>,
+    segments: Vec<Segment>,
+    mappings: HashMap<Lba, Hba>,
+    // The LBA of each allocated HBA
+    reverse_mappings: Vec<Option<Lba>>,
+    // The open segments of the user writes and of GC, with their next offsets
+    user_open: Option<(SegmentId, usize)>,
+    gc_open: Option<(SegmentId, usize)>,
+    report: GcSimReport,
+}
+
+impl GcSim {
+    fn new(config: &GcSimConfig) -> Self {
+        let nblocks = config.nsegments * SEGMENT_SIZE;
+        let bitmap = Arc::new(Mutex::new(BitMap::repeat(true, nblocks)));
+        let segments = (0..config.nsegments)
+            .map(|id| Segment::new(id, SEGMENT_SIZE, bitmap.clone()))
+            .collect();
+        Self {
+            config: *config,
+            bitmap,
+            segments,
+            mappings: HashMap::new(),
+            reverse_mappings: vec![None; nblocks],
+            user_open: None,
+            gc_open: None,
+            report: GcSimReport::default(),
+        }
+    }
+
+    fn write(&mut self, policy: &dyn VictimPolicy, lba: Lba) -> Result<()> {
+        if self.user_open.is_none() {
+            self.collect_garbage(policy)?;
+        }
+        let hba = Self::alloc(&mut self.user_open, &self.segments, &self.bitmap)?;
+        self.report.user_writes += 1;
+        self.segments[hba / SEGMENT_SIZE].set_last_write(self.report.user_writes);
+        self.discard(lba);
+        self.map(lba, hba);
+        Ok(())
+    }
+
+    fn discard(&mut self, lba: Lba) {
+        if let Some(old_hba) = self.mappings.remove(&lba) {
+            self.reverse_mappings[old_hba] = None;
+            self.bitmap.lock().set_bit(old_hba);
+            self.segments[old_hba / SEGMENT_SIZE].mark_deallocated();
+        }
+    }
+
+    fn map(&mut self, lba: Lba, hba: Hba) {
+        let _ = self.mappings.insert(lba, hba);
+        self.reverse_mappings[hba] = Some(lba);
+    }
+
+    /// Allocates the next block of the open segment, a clean segment
+    /// is opened if there is none.
+    fn alloc(
+        open: &mut Option<(SegmentId, usize)>,
+        segments: &[Segment],
+        bitmap: &Mutex<BitMap>,
+    ) -> Result<Hba> {
+        let (segment_id, offset) = match *open {
+            Some(open) => open,
+            None => {
+                let segment = segments
+                    .iter()
+                    .find(|segment| Self::is_clean(segment))
+                    .ok_or_else(|| Error::with_msg(NoSpaceLeft, "no clean segment"))?;
+                segment.clear_segment();
+                (segment.segment_id(), 0)
+            }
+        };
+        *open = (offset + 1 < SEGMENT_SIZE).then_some((segment_id, offset + 1));
+
+        let hba = segment_id * SEGMENT_SIZE + offset;
+        bitmap.lock().clear_bit(hba);
+        segments[segment_id].mark_alloc();
+        Ok(hba)
+    }
+
+    fn is_clean(segment: &Segment) -> bool {
+        segment.free_space() == segment.nblocks()
+    }
+
+    /// Cleans victims until enough clean segments are reserved,
+    /// or no victim is found.
+    fn collect_garbage(&mut self, policy: &dyn VictimPolicy) -> Result<()> {
+        loop {
+            let num_clean = self.segments.iter().filter(|s| Self::is_clean(s)).count();
+            if num_clean >= self.config.reserved_segments {
+                break;
+            }
+            let num_live = self.mappings.len();
+            let ctx = GcContext {
+                segment_table: &self.segments,
+                threshold: self.config.threshold,
+                utilization: num_live as f64 / (self.segments.len() * SEGMENT_SIZE) as f64,
+                write_rate: 0.0,
+                write_seq: self.report.user_writes,
+            };
+            let Some(victim) = policy.pick_victim_with_ctx(&ctx) else {
+                break;
+            };
+            self.clean(victim.segment_id(), victim.blocks())?;
+        }
+        self.segments
+            .iter()
+            .for_each(|segment| segment.reset_recent_invalidations());
+        Ok(())
+    }
+
+    /// Migrates the live blocks of the victim to the open segment of GC,
+    /// then the victim becomes clean.
+    fn clean(&mut self, victim_id: SegmentId, blocks: &[Hba]) -> Result<()> {
+        // A victim being filled is closed beforehand
+        for open in [&mut self.user_open, &mut self.gc_open] {
+            if open.is_some_and(|(segment_id, _)| segment_id == victim_id) {
+                *open = None;
+            }
+        }
+
+        for &old_hba in blocks {
+            let Some(lba) = self.reverse_mappings[old_hba].take() else {
+                continue;
+            };
+            let new_hba = Self::alloc(&mut self.gc_open, &self.segments, &self.bitmap)?;
+            self.bitmap.lock().set_bit(old_hba);
+            self.map(lba, new_hba);
+            self.report.migrated_blocks += 1;
+        }
+
+        let victim = &self.segments[victim_id];
+        let mut bitmap = self.bitmap.lock();
+        for hba in victim_id * SEGMENT_SIZE..(victim_id + 1) * SEGMENT_SIZE {
+            bitmap.set_bit(hba);
+        }
+        victim.clear_segment();
+        self.report.num_victims += 1;
+        Ok(())
+    }
+}
+
+/// A minimal xorshift generator for the synthetic traces.
+struct TraceRng(u64);
+
+impl TraceRng {
+    fn new(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Generates a trace that writes each of `nlbas` LBAs once, then
+/// overwrites LBAs uniformly at random `nwrites` times.
+pub fn uniform_trace(nlbas: usize, nwrites: usize, seed: u64) -> Vec<TraceOp> {
+    let mut rng = TraceRng::new(seed);
+    (0..nlbas)
+        .map(TraceOp::Write)
+        .chain((0..nwrites).map(|_| TraceOp::Write(rng.below(nlbas))))
+        .collect()
+}
+
+/// Generates a trace that writes each of `nlbas` LBAs once, then overwrites
+/// LBAs `nwrites` times, where `hot_access` of the writes go to the first
+/// `hot_fraction` of the LBAs (e.g., 0.8 and 0.2).
+pub fn hot_cold_trace(
+    nlbas: usize,
+    nwrites: usize,
+    hot_fraction: f64,
+    hot_access: f64,
+    seed: u64,
+) -> Vec<TraceOp> {
+    let mut rng = TraceRng::new(seed);
+    let nhot = ((nlbas as f64 * hot_fraction) as usize).clamp(1, nlbas);
+    let overwrites: Vec<_> = (0..nwrites)
+        .map(|_| {
+            let is_hot = (rng.next() % 10000) as f64 / 10000.0 < hot_access;
+            if is_hot || nhot == nlbas {
+                TraceOp::Write(rng.below(nhot))
+            } else {
+                TraceOp::Write(nhot + rng.below(nlbas - nhot))
+            }
+        })
+        .collect();
+    (0..nlbas).map(TraceOp::Write).chain(overwrites).collect()
+}
+
+/// Parses a recorded trace, one operation per line, i.e., `w <lba>` for
+/// a write and `d <lba>` for a discard. Empty lines and lines starting
+/// with `#` are skipped.
+pub fn parse_trace(text: &str) -> Result<Vec<TraceOp>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (op, lba) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| Error::with_msg(InvalidArgs, "invalid trace line"))?;
+            let lba = lba
+                .trim()
+                .parse::<Lba>()
+                .map_err(|_| Error::with_msg(InvalidArgs, "invalid LBA of trace"))?;
+            match op {
+                "w" | "W" => Ok(TraceOp::Write(lba)),
+                "d" | "D" => Ok(TraceOp::Discard(lba)),
+                _ => Err(Error::with_msg(InvalidArgs, "invalid operation of trace")),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::disk::VictimPolicyKind;
+
+    #[test]
+    fn gc_sim_sequential() {
+        // Overwriting whole segments leaves nothing to migrate
+        let config = GcSimConfig {
+            nsegments: 8,
+            ..Default::default()
+        };
+        let nlbas = 4 * SEGMENT_SIZE;
+        let trace = (0..4 * nlbas).map(|nth| TraceOp::Write(nth % nlbas));
+        let report = simulate_gc(VictimPolicyKind::Greedy.build().as_ref(), &config, trace);
+        assert!(!report.out_of_space);
+        assert_eq!(report.user_writes, 4 * nlbas as u64);
+        assert_eq!(report.migrated_blocks, 0);
+        assert_eq!(report.waf(), 1.0);
+
+        // Full of live blocks
+        let trace = (0..8 * SEGMENT_SIZE + 1).map(TraceOp::Write);
+        let report = simulate_gc(VictimPolicyKind::Greedy.build().as_ref(), &config, trace);
+        assert!(report.out_of_space);
+    }
+
+    #[test]
+    fn gc_sim_policies() {
+        let config = GcSimConfig::default();
+        // 75% utilization
+        let nlbas = config.nsegments * SEGMENT_SIZE * 3 / 4;
+        let trace = hot_cold_trace(nlbas, 4 * nlbas, 0.2, 0.8, 0x5eed);
+
+        for kind in [
+            VictimPolicyKind::Greedy,
+            VictimPolicyKind::LoopScan,
+            VictimPolicyKind::WindowGreedy { window: 16 },
+            VictimPolicyKind::Generational {
+                old_age: nlbas as u64,
+            },
+            VictimPolicyKind::LazyGreedy { exclude: 4 },
+        ] {
+            let policy = kind.build();
+            let report = simulate_gc(policy.as_ref(), &config, trace.iter().copied());
+            println!(
+                "{kind:?}: migrated {} blocks of {} victims, WAF {:.3}",
+                report.migrated_blocks,
+                report.num_victims,
+                report.waf()
+            );
+            assert!(!report.out_of_space, "{kind:?} runs out of space");
+            assert_eq!(report.user_writes, trace.len() as u64);
+            assert!(report.migrated_blocks > 0);
+            assert!(report.waf() > 1.0);
+        }
+    }
+
+    #[test]
+    fn gc_sim_lazy_greedy() {
+        let config = GcSimConfig::default();
+        let nlbas = config.nsegments * SEGMENT_SIZE * 3 / 4;
+        let trace = hot_cold_trace(nlbas, 4 * nlbas, 0.2, 0.8, 0x5eed);
+        let simulate = |kind: VictimPolicyKind| {
+            simulate_gc(kind.build().as_ref(), &config, trace.iter().copied())
+        };
+
+        let greedy = simulate(VictimPolicyKind::Greedy);
+        // Excluding none is the same as greedy
+        assert_eq!(
+            simulate(VictimPolicyKind::LazyGreedy { exclude: 0 }),
+            greedy
+        );
+        for exclude in [2, 4, 8, 16] {
+            let lazy = simulate(VictimPolicyKind::LazyGreedy { exclude });
+            println!(
+                "LazyGreedy {{ exclude: {exclude} }}: WAF {:.3}, Greedy: WAF {:.3}",
+                lazy.waf(),
+                greedy.waf()
+            );
+            assert!(!lazy.out_of_space);
+            assert_eq!(lazy.user_writes, greedy.user_writes);
+        }
+    }
+
+    #[test]
+    fn gc_sim_parse_trace() -> Result<()> {
+        let trace = parse_trace("# recorded\nw 1\n\n  d 1\nW 2\n")?;
+        assert_eq!(
+            trace,
+            [TraceOp::Write(1), TraceOp::Discard(1), TraceOp::Write(2)]
+        );
+        assert!(parse_trace("x 1").is_err());
+        assert!(parse_trace("w").is_err());
+
+        let config = GcSimConfig {
+            nsegments: 4,
+            ..Default::default()
+        };
+        let report = simulate_gc(VictimPolicyKind::Greedy.build().as_ref(), &config, trace);
+        assert_eq!(report.user_writes, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! Statistics of background GC, including the anomalies of the indexes.
+
+use super::cost_stats::STATS_ENABLED;
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Statistics of background GC.
+///
+/// The anomalies are inconsistencies between the logical block table and
+/// the reverse index found by GC. They are tolerated by discarding the
+/// affected blocks, but should never happen on a consistent disk.
+pub struct GcStats {
+    num_rounds: AtomicU64,
+    num_failed_rounds: AtomicU64,
+    /// The failed rounds since the last succeeded one, which GC retries
+    /// with exponential backoff.
+    consecutive_failed_rounds: AtomicU64,
+    /// Victim blocks without an entry in the reverse index.
+    missing_reverse_entries: AtomicU64,
+    /// Victim blocks whose LBA has no record in the logical block table.
+    missing_records: AtomicU64,
+}
+
+/// A snapshot of `GcStats`.
+#[derive(Debug, Clone, Default)]
+pub struct GcStatsSnapshot {
+    pub num_rounds: u64,
+    pub num_failed_rounds: u64,
+    pub consecutive_failed_rounds: u64,
+    pub missing_reverse_entries: u64,
+    pub missing_records: u64,
+}
+
+impl GcStats {
+    /// Create a new GcStats instance
+    pub const fn new() -> Self {
+        Self {
+            num_rounds: AtomicU64::new(0),
+            num_failed_rounds: AtomicU64::new(0),
+            consecutive_failed_rounds: AtomicU64::new(0),
+            missing_reverse_entries: AtomicU64::new(0),
+            missing_records: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a finished GC round, whether it succeeded or not.
+    pub fn record_round(&self, succeeded: bool) {
+        if !STATS_ENABLED {
+            return;
+        }
+        self.num_rounds.fetch_add(1, Ordering::Relaxed);
+        if succeeded {
+            self.consecutive_failed_rounds.store(0, Ordering::Relaxed);
+        } else {
+            self.num_failed_rounds.fetch_add(1, Ordering::Relaxed);
+            self.consecutive_failed_rounds
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record a victim block missing in the reverse index.
+    pub fn record_missing_reverse_entry(&self) {
+        if !STATS_ENABLED {
+            return;
+        }
+        self.missing_reverse_entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a victim block missing in the logical block table.
+    pub fn record_missing_record(&self) {
+        if !STATS_ENABLED {
+            return;
+        }
+        self.missing_records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_stats(&self) -> GcStatsSnapshot {
+        GcStatsSnapshot {
+            num_rounds: self.num_rounds.load(Ordering::Relaxed),
+            num_failed_rounds: self.num_failed_rounds.load(Ordering::Relaxed),
+            consecutive_failed_rounds: self.consecutive_failed_rounds.load(Ordering::Relaxed),
+            missing_reverse_entries: self.missing_reverse_entries.load(Ordering::Relaxed),
+            missing_records: self.missing_records.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reset all statistics
+    pub fn reset(&self) {
+        self.num_rounds.store(0, Ordering::Relaxed);
+        self.num_failed_rounds.store(0, Ordering::Relaxed);
+        self.consecutive_failed_rounds.store(0, Ordering::Relaxed);
+        self.missing_reverse_entries.store(0, Ordering::Relaxed);
+        self.missing_records.store(0, Ordering::Relaxed);
+    }
+
+    /// Print statistics to `sink`
+    pub fn print(&self, sink: &mut dyn Write) -> fmt::Result {
+        let stats = self.get_stats();
+
+        writeln!(
+            sink,
+            "==================== GC Statistics ====================="
+        )?;
+        writeln!(
+            sink,
+            "  Rounds: {}, failed: {} ({} in a row)",
+            stats.num_rounds, stats.num_failed_rounds, stats.consecutive_failed_rounds
+        )?;
+        writeln!(
+            sink,
+            "  Missing reverse entries: {}, missing records: {}",
+            stats.missing_reverse_entries, stats.missing_records
+        )?;
+        writeln!(
+            sink,
+            "========================================================"
+        )
+    }
+}
+
+// Global GC statistics
+pub static GC_STATS: GcStats = GcStats::new();
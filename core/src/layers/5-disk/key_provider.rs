@@ -0,0 +1,70 @@
+//! Root key providers.
+//!
+//! A `RootKeyProvider` wraps and unwraps the root key of `SwornDisk`, so that
+//! the root key can be protected by SGX sealing, a TPM, or an external KMS.
+//! Only the wrapped root key is stored in the superblock of `SwornDisk`.
+use crate::os::{Aead, AeadIv as Iv, AeadKey as Key, AeadMac as Mac};
+use crate::prelude::*;
+
+use core::mem::size_of;
+use pod::Pod;
+
+/// A provider of the root key.
+pub trait RootKeyProvider: Send + Sync {
+    /// Wrap the root key into an opaque blob that can be stored in untrusted storage.
+    fn wrap_key(&self, key: &Key) -> Result<Vec<u8>>;
+
+    /// Unwrap the root key from the blob returned by `wrap_key()`.
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<Key>;
+}
+
+/// A `RootKeyProvider` that wraps the root key with a key encryption key (KEK)
+/// using authenticated encryption.
+///
+/// Wrapped layout: `| Iv | Mac | Encrypted root key |`.
+pub struct KekKeyProvider {
+    kek: Key,
+}
+
+impl KekKeyProvider {
+    const IV_SIZE: usize = size_of::<Iv>();
+    const MAC_SIZE: usize = size_of::<Mac>();
+    const KEY_SIZE: usize = size_of::<Key>();
+
+    /// Create a `KekKeyProvider` with the given key encryption key.
+    pub fn new(kek: Key) -> Self {
+        Self { kek }
+    }
+}
+
+impl RootKeyProvider for KekKeyProvider {
+    fn wrap_key(&self, key: &Key) -> Result<Vec<u8>> {
+        let iv = Iv::random();
+        let mut cipher = [0u8; Self::KEY_SIZE];
+        let mac = Aead::new().encrypt(key.as_bytes(), &self.kek, &iv, &[], &mut cipher)?;
+
+        let mut wrapped = Vec::with_capacity(Self::IV_SIZE + Self::MAC_SIZE + Self::KEY_SIZE);
+        wrapped.extend_from_slice(iv.as_bytes());
+        wrapped.extend_from_slice(mac.as_bytes());
+        wrapped.extend_from_slice(&cipher);
+        Ok(wrapped)
+    }
+
+    fn unwrap_key(&self, wrapped: &[u8]) -> Result<Key> {
+        if wrapped.len() != Self::IV_SIZE + Self::MAC_SIZE + Self::KEY_SIZE {
+            return_errno_with_msg!(InvalidArgs, "invalid size of the wrapped root key");
+        }
+        let iv = Iv::from_bytes(&wrapped[..Self::IV_SIZE]);
+        let mac = Mac::from_bytes(&wrapped[Self::IV_SIZE..Self::IV_SIZE + Self::MAC_SIZE]);
+        let mut key = Key::new_zeroed();
+        Aead::new().decrypt(
+            &wrapped[Self::IV_SIZE + Self::MAC_SIZE..],
+            &self.kek,
+            &iv,
+            &[],
+            &mac,
+            &mut key,
+        )?;
+        Ok(key)
+    }
+}
@@ -0,0 +1,200 @@
+//! Layout of the underlying disk of `SwornDisk`.
+use super::config::{Config, LAYOUT_FRACTION_BASE};
+use super::superblock::SUPERBLOCK_NBLOCKS;
+use crate::layers::bio::BlockId;
+use crate::prelude::*;
+
+use core::ops::Range;
+use pod::Pod;
+
+/// The layout of the underlying disk, i.e., the sizes of the subdisks.
+///
+/// ```text
+/// ----------------------------------------------------------------------------
+/// | User data | Logical block table | Reverse index table (GC) | Superblock |
+/// ----------------------------------------------------------------------------
+/// ```
+/// The layout is computed from `Config` on `SwornDisk::create()` and persisted
+/// in the superblock, so that opening a disk never depends on the `Config`.
+/// The reverse index table takes no space if GC is disabled.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug, PartialEq, Eq)]
+pub(super) struct DiskLayout {
+    data_nblocks: u64,
+    index_nblocks: u64,
+    reverse_index_nblocks: u64,
+}
+
+impl DiskLayout {
+    /// Computes the layout of a disk of `total_nblocks` blocks from the fractions
+    /// given in `config`. The user data takes the rest of the disk.
+    pub fn new(total_nblocks: usize, config: &Config) -> Result<Self> {
+        let reverse_index_fraction = if config.enable_gc {
+            config.reverse_index_fraction
+        } else {
+            0
+        };
+        if config.index_fraction == 0
+            || (config.enable_gc && reverse_index_fraction == 0)
+            || config.index_fraction + reverse_index_fraction >= LAYOUT_FRACTION_BASE
+        {
+            return_errno_with_msg!(InvalidArgs, "invalid fractions of disk layout");
+        }
+
+        // The last blocks are reserved for the superblock
+        let nblocks = total_nblocks.saturating_sub(SUPERBLOCK_NBLOCKS);
+        let index_nblocks = nblocks * config.index_fraction / LAYOUT_FRACTION_BASE;
+        let reverse_index_nblocks = nblocks * reverse_index_fraction / LAYOUT_FRACTION_BASE;
+        let data_nblocks = nblocks - index_nblocks - reverse_index_nblocks;
+        if data_nblocks == 0
+            || index_nblocks == 0
+            || (config.enable_gc && reverse_index_nblocks == 0)
+        {
+            return_errno_with_msg!(InvalidArgs, "disk is too small for the layout");
+        }
+
+        Ok(Self {
+            data_nblocks: data_nblocks as _,
+            index_nblocks: index_nblocks as _,
+            reverse_index_nblocks: reverse_index_nblocks as _,
+        })
+    }
+
+    /// Checks whether the layout fits in a disk of `total_nblocks` blocks, whose
+    /// superblock takes `superblock_nblocks` blocks, see `Superblock::nblocks()`.
+    pub fn check(&self, total_nblocks: usize, superblock_nblocks: usize) -> Result<()> {
+        if self.data_nblocks == 0
+            || self.reverse_index_range().end + superblock_nblocks > total_nblocks
+        {
+            return_errno_with_msg!(InvalidArgs, "disk layout mismatches the disk");
+        }
+        Ok(())
+    }
+
+    /// Returns the layout of the disk grown to `total_nblocks` blocks. All the
+    /// added blocks go to the user data, and the index tables, which keep their
+    /// sizes, are moved towards the end.
+    ///
+    /// The index tables must be moved beyond the end of the old layout, so that
+    /// they're copied without overwriting the old ones, i.e., the disk must grow
+    /// by no less than the sizes of the index tables and the superblock.
+    pub fn grow(&self, total_nblocks: usize) -> Result<Self> {
+        let old_end = self.reverse_index_range().end + SUPERBLOCK_NBLOCKS;
+        let tables_nblocks = (self.index_nblocks + self.reverse_index_nblocks) as usize;
+        let data_nblocks = total_nblocks.saturating_sub(tables_nblocks + SUPERBLOCK_NBLOCKS);
+        if data_nblocks < old_end {
+            return_errno_with_msg!(
+                InvalidArgs,
+                "disk is not grown enough to move the index tables"
+            );
+        }
+
+        Ok(Self {
+            data_nblocks: data_nblocks as _,
+            ..*self
+        })
+    }
+
+    /// Returns the layout of the disk shrunk to `total_nblocks` blocks. All the
+    /// removed blocks are taken from the user data, and the index tables, which
+    /// keep their sizes, are moved towards the start.
+    ///
+    /// The index tables and the superblock must be moved within the user data
+    /// of the old layout, so that they're copied without overwriting the old
+    /// index tables, i.e., the disk must shrink by no less than their sizes.
+    pub fn shrink(&self, total_nblocks: usize) -> Result<Self> {
+        let tables_nblocks = (self.index_nblocks + self.reverse_index_nblocks) as usize;
+        let data_nblocks = total_nblocks.saturating_sub(tables_nblocks + SUPERBLOCK_NBLOCKS);
+        if data_nblocks == 0 {
+            return_errno_with_msg!(InvalidArgs, "disk is too small for the index tables");
+        }
+        if total_nblocks > self.data_nblocks as usize {
+            return_errno_with_msg!(
+                InvalidArgs,
+                "disk is not shrunk enough to move the index tables"
+            );
+        }
+
+        Ok(Self {
+            data_nblocks: data_nblocks as _,
+            ..*self
+        })
+    }
+
+    /// Returns the range of the user data subdisk.
+    pub fn data_range(&self) -> Range<BlockId> {
+        0..self.data_nblocks as usize
+    }
+
+    /// Returns the range of the logical block table subdisk.
+    pub fn index_range(&self) -> Range<BlockId> {
+        let start = self.data_range().end;
+        start..start + self.index_nblocks as usize
+    }
+
+    /// Returns the range of the reverse index table subdisk,
+    /// which is empty if GC is disabled on creation.
+    pub fn reverse_index_range(&self) -> Range<BlockId> {
+        let start = self.index_range().end;
+        start..start + self.reverse_index_nblocks as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disk_layout() -> Result<()> {
+        let total_nblocks = 64 * 1024 + SUPERBLOCK_NBLOCKS;
+        let config = Config {
+            enable_gc: true,
+            ..Default::default()
+        };
+        let layout = DiskLayout::new(total_nblocks, &config)?;
+        assert_eq!(layout.index_range(), 61440..63488);
+        assert_eq!(layout.reverse_index_range(), 63488..65536);
+        layout.check(total_nblocks, SUPERBLOCK_NBLOCKS)?;
+        assert!(layout.check(total_nblocks - 1, SUPERBLOCK_NBLOCKS).is_err());
+        // The legacy superblock takes a single block
+        layout.check(total_nblocks - 1, 1)?;
+
+        // The space of reverse index table goes to user data if GC is disabled
+        let config = Config::default();
+        let layout = DiskLayout::new(total_nblocks, &config)?;
+        assert_eq!(layout.data_range(), 0..63488);
+        assert!(layout.reverse_index_range().is_empty());
+
+        let config = Config {
+            index_fraction: LAYOUT_FRACTION_BASE,
+            ..Default::default()
+        };
+        assert!(DiskLayout::new(total_nblocks, &config).is_err());
+        assert!(DiskLayout::new(16, &Config::default()).is_err());
+
+        // The index tables are moved beyond the old end on growth
+        let config = Config {
+            enable_gc: true,
+            ..Default::default()
+        };
+        let layout = DiskLayout::new(total_nblocks, &config)?;
+        assert!(layout.grow(total_nblocks + 2048).is_err());
+        let grown = layout.grow(2 * total_nblocks)?;
+        grown.check(2 * total_nblocks, SUPERBLOCK_NBLOCKS)?;
+        assert_eq!(grown.data_range(), 0..126978);
+        assert_eq!(grown.index_range(), 126978..129026);
+        assert_eq!(grown.reverse_index_range().len(), 2048);
+
+        // The index tables are moved within the old user data on shrinking
+        assert!(layout.shrink(total_nblocks - 2048).is_err());
+        assert!(layout.shrink(4096).is_err());
+        let shrunk = layout.shrink(32 * 1024)?;
+        shrunk.check(32 * 1024, SUPERBLOCK_NBLOCKS)?;
+        assert_eq!(shrunk.data_range(), 0..28670);
+        assert_eq!(
+            shrunk,
+            grown.shrink(total_nblocks - 4096)?.shrink(32 * 1024)?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! Memory budget of the buffers and caches of `SwornDisk`.
+//!
+//! `Config::cache_size` is the total budget, which is split among the data
+//! buffer and the two cache tiers (see `CacheTier`) by `CacheRatios`. A tier
+//! sized explicitly (`Config::sst_cache_size` or `Config::block_cache_size`)
+//! takes its size out of the total first, the rest is split among the others.
+use super::cache_stats::CacheTier;
+use crate::prelude::*;
+
+/// The relative shares of `Config::cache_size` taken by each consumer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheRatios {
+    /// The share of `DataBuf`, which buffers the written user data blocks.
+    pub data_buf: u32,
+    /// The share of the cache tier of SST record blocks, ignored without
+    /// `Config::two_level_caching`.
+    pub sst_block: u32,
+    /// The share of the cache tier of decrypted TX log blocks.
+    pub log_block: u32,
+}
+
+impl Default for CacheRatios {
+    fn default() -> Self {
+        Self {
+            data_buf: 1,
+            sst_block: 2,
+            log_block: 1,
+        }
+    }
+}
+
+/// The budget (in bytes) of each consumer, `usize::MAX` for the default
+/// capacity of the consumer when `Config::cache_size` is unlimited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemBudget {
+    pub data_buf: usize,
+    pub sst_block: usize,
+    pub log_block: usize,
+}
+
+/// The memory (in bytes) in use by each consumer, along with its budget.
+///
+/// The caches of the TX logs opened by an ongoing TX are not counted
+/// until the TX commits.
+#[derive(Clone, Copy, Debug)]
+pub struct MemUsage {
+    pub data_buf: usize,
+    pub sst_block: usize,
+    pub log_block: usize,
+    pub budget: MemBudget,
+}
+
+impl MemBudget {
+    /// The budget of the default capacities.
+    pub const UNLIMITED: Self = Self {
+        data_buf: usize::MAX,
+        sst_block: usize::MAX,
+        log_block: usize::MAX,
+    };
+
+    /// Splits `total` bytes by the ratios, except for the explicitly sized
+    /// cache tiers. Returns `InvalidArgs` if the explicit sizes exceed the
+    /// total, or nothing is left to split by.
+    pub fn split(
+        total: usize,
+        ratios: CacheRatios,
+        sst_block: Option<usize>,
+        log_block: Option<usize>,
+    ) -> Result<Self> {
+        let fixed = sst_block
+            .unwrap_or(0)
+            .checked_add(log_block.unwrap_or(0))
+            .filter(|fixed| *fixed <= total)
+            .ok_or_else(|| Error::with_msg(InvalidArgs, "cache tier sizes exceed cache size"))?;
+
+        let weights = [
+            ratios.data_buf as u128,
+            sst_block.map_or(ratios.sst_block as u128, |_| 0),
+            log_block.map_or(ratios.log_block as u128, |_| 0),
+        ];
+        let sum: u128 = weights.iter().sum();
+        if sum == 0 {
+            return_errno_with_msg!(InvalidArgs, "cache ratios are all zero");
+        }
+        let rest = (total - fixed) as u128;
+        let share = |weight: u128| (rest * weight / sum) as usize;
+
+        Ok(Self {
+            data_buf: share(weights[0]),
+            sst_block: sst_block.unwrap_or_else(|| share(weights[1])),
+            log_block: log_block.unwrap_or_else(|| share(weights[2])),
+        })
+    }
+
+    /// Returns the budget of the cache tier.
+    pub fn tier(&self, tier: CacheTier) -> usize {
+        match tier {
+            CacheTier::SstBlock => self.sst_block,
+            CacheTier::LogBlock => self.log_block,
+        }
+    }
+
+    /// Returns the capacity (in blocks) of `DataBuf`, `default_cap` if unlimited.
+    pub fn data_buf_cap(&self, default_cap: usize) -> usize {
+        if self.data_buf == usize::MAX {
+            return default_cap;
+        }
+        (self.data_buf / BLOCK_SIZE).max(MIN_DATA_BUF_CAP)
+    }
+}
+
+impl MemUsage {
+    /// Returns the total memory in use.
+    pub fn total(&self) -> usize {
+        self.data_buf + self.sst_block + self.log_block
+    }
+}
+
+/// The minimum capacity (in blocks) of `DataBuf`, so that writes
+/// are still batched under a tiny budget.
+const MIN_DATA_BUF_CAP: usize = 16;
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheRatios, MemBudget};
+    use crate::layers::disk::CacheTier;
+
+    #[test]
+    fn mem_budget_split() {
+        let mib = 1024 * 1024;
+        let ratios = CacheRatios::default();
+        let budget = MemBudget::split(64 * mib, ratios, None, None).unwrap();
+        assert_eq!(budget.data_buf, 16 * mib);
+        assert_eq!(budget.tier(CacheTier::SstBlock), 32 * mib);
+        assert_eq!(budget.tier(CacheTier::LogBlock), 16 * mib);
+        assert_eq!(budget.data_buf_cap(1024), 4096);
+
+        // An explicit size is taken out of the total first
+        let budget = MemBudget::split(64 * mib, ratios, None, Some(40 * mib)).unwrap();
+        assert_eq!(budget.log_block, 40 * mib);
+        assert_eq!(budget.data_buf + budget.sst_block, 24 * mib);
+        assert_eq!(budget.sst_block, 16 * mib);
+
+        assert!(MemBudget::split(64 * mib, ratios, Some(32 * mib), Some(33 * mib)).is_err());
+        let no_ratios = CacheRatios {
+            data_buf: 0,
+            sst_block: 0,
+            log_block: 0,
+        };
+        assert!(MemBudget::split(64 * mib, no_ratios, None, None).is_err());
+
+        // Never below the minimum capacity
+        let budget = MemBudget::split(mib, ratios, None, None).unwrap();
+        assert_eq!(budget.data_buf_cap(1024), 64);
+        assert_eq!(MemBudget::UNLIMITED.data_buf_cap(1024), 1024);
+    }
+}
@@ -31,24 +31,87 @@
 //! }
 //! ```
 
+#[cfg(feature = "async")]
+mod async_disk;
+mod audit;
+mod backup;
 mod bio;
+mod bio_stats;
 mod block_alloc;
+mod cache_stats;
 mod config;
+mod corruption;
 mod cost_stats;
 mod data_buf;
 mod dealloc_block;
+mod digest;
+mod discard_log;
+mod discard_queue;
+mod disk_stats;
+mod events;
+mod freshness;
 mod gc;
+mod gc_sim;
+mod gc_stats;
+mod key_provider;
+mod layout;
+mod mem_budget;
+mod rate_limit;
+mod ref_count;
+mod reverse_index;
 mod segment;
+mod stats_export;
+mod stats_log;
+mod superblock;
 mod sworndisk;
+mod sync_id_log;
 mod waf_stats;
 
-pub use self::config::Config;
+#[cfg(feature = "async")]
+pub use self::async_disk::AsyncSwornDisk;
+pub use self::audit::AuditReport;
+pub use self::backup::{StreamReader, StreamSummary, StreamWriter};
+pub use self::bio::BioPriority;
+pub use self::bio_stats::{
+    BatchSnapshot, BioStats, BioStatsSnapshot, BioTypeSnapshot, BATCH_SIZE_BUCKETS, BIO_STATS,
+    LATENCY_BUCKETS,
+};
+pub use self::block_alloc::AllocAlignStats;
+pub use self::cache_stats::{CacheStats, CacheTier, CacheTierSnapshot, CACHE_STATS};
+pub use self::config::{
+    AllocPolicy, BlockCryptoMode, BvtCompactionPolicy, Config, ReverseIndexKind, VictimPolicyKind,
+    LAYOUT_FRACTION_BASE,
+};
+pub use self::corruption::{CorruptionHandler, CorruptionHandlerRef, CorruptionReport};
 pub use self::cost_stats::{
-    print_all_cost_stats, print_cost_stats_json, CostL2Type, CostL3Type, COST_L2, COST_L3,
+    cost_clock, cost_timers_enabled, disable_cost_timers, print_all_cost_stats,
+    print_cost_stats_json, set_cost_clock, tsc_frequency, BloomFilterStats, CostClock, CostL2Type,
+    CostL3Type, CostTimersDisabled, COST_L2, COST_L3, STATS_ENABLED,
+};
+pub use self::digest::{DiskDigest, DIGEST_BUCKET_NBLOCKS};
+pub use self::discard_queue::DiscardStats;
+pub use self::disk_stats::DiskStats;
+pub use self::events::{
+    BlockMigration, DiskEvent, EventSubscriber, EventSubscriberRef, SubscriptionId,
 };
+pub use self::freshness::{TrustedCounter, TrustedCounterRef};
 pub use self::gc::{
-    GreedyVictimPolicy, LoopScanVictimPolicy, ReverseKey, ReverseValue, SharedState,
-    SharedStateRef, VictimPolicy,
+    CompactionPriorityGuard, GcContext, GcGuard, GenerationalVictimPolicy, GreedyVictimPolicy,
+    LazyGreedyVictimPolicy, LoopScanVictimPolicy, ReverseKey, ReverseValue, SharedState,
+    SharedStateRef, UrgentReadGuard, Victim, VictimPolicy, WindowGreedyVictimPolicy,
+};
+pub use self::gc_sim::{
+    hot_cold_trace, parse_trace, simulate_gc, uniform_trace, GcSimConfig, GcSimReport, TraceOp,
+};
+pub use self::gc_stats::{GcStats, GcStatsSnapshot, GC_STATS};
+pub use self::key_provider::{KekKeyProvider, RootKeyProvider};
+pub use self::mem_budget::{CacheRatios, MemBudget, MemUsage};
+pub use self::rate_limit::{BackgroundIoLimit, BioTenant, RateLimit, RateLimitStats};
+pub use self::segment::{FragmentationReport, Segment, SegmentUsage, INVALID_HIST_BUCKETS};
+pub use self::stats_export::{
+    CacheExport, CacheTierExport, CostExport, GcExport, QueueExport, QueueTypeExport, StatsExport,
+    WafExport,
 };
-pub use self::sworndisk::{SwornDisk, CONFIG};
+pub use self::sworndisk::{BulkWriter, SwornDisk, VerifyResult, CONFIG};
+pub use self::sync_id_log::TxLogSyncIdStore;
 pub use self::waf_stats::{WafStats, WAF_STATS};
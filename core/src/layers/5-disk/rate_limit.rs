@@ -0,0 +1,552 @@
+//! Rate limiting of block I/O requests.
+//!
+//! Each tenant has a token bucket of IOPS and one of bandwidth, a request
+//! consumes one I/O token and as many byte tokens as its size. The buckets
+//! are refilled at the configured rates, and hold up to one second of
+//! budget for bursts. A request is delayed while any bucket is in debt,
+//! so requests larger than the budget still make progress. Asynchronous
+//! requests are parked instead, rather than blocking their submitters.
+//!
+//! Rate limiting requires a clock that keeps the time (see
+//! `Clock::keeps_time`), the limiters can't be built without one.
+//!
+//! The I/Os of background tasks (e.g., GC migration and defragmentation) are
+//! limited by a `BackgroundIoLimiter` shared among them, which caps both
+//! their bandwidth and their outstanding bytes. Their bandwidth debts are
+//! paid off between the migration batches, out of the GC sections.
+use super::bio::BioReq;
+use crate::os::{Arc, Clock, Condvar, CvarMutex, HashMap, HashSet, Mutex, RealClock};
+use crate::prelude::*;
+
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+/// The budget of block I/O requests of each tenant.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The maximum number of requests per second, zero means unlimited.
+    pub iops: u64,
+    /// The maximum number of bytes per second, zero means unlimited.
+    pub bandwidth: u64,
+}
+
+/// The tenant of a block I/O request, attached as an extension of `BioReq`
+/// (see `BioReqBuilder::ext`). Each tenant is rate limited independently,
+/// the requests without a tenant belong to `BioTenant::default()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BioTenant(pub u32);
+
+/// The I/O budget of the background tasks, shared by all of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BackgroundIoLimit {
+    /// The maximum number of bytes read or written per second,
+    /// zero means unlimited.
+    pub bandwidth: u64,
+    /// The maximum number of bytes of the outstanding I/Os, zero means
+    /// unlimited. Larger I/Os are split into chunks of at most this size.
+    pub max_outstanding: usize,
+}
+
+/// A snapshot of the statistics of throttled requests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RateLimitStats {
+    /// The number of throttled requests.
+    pub num_throttled: u64,
+    /// The total time that the requests are delayed.
+    pub throttled_time: Duration,
+}
+
+/// A token-bucket rate limiter of block I/O requests.
+pub(super) struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<BioTenant, TokenBuckets>>,
+    clock: Arc<dyn Clock>,
+    /// The asynchronous requests parked until they're within the budget,
+    /// in the order of submission, along with the time they're parked.
+    parked: Mutex<VecDeque<(Duration, BioReq)>>,
+    num_throttled: AtomicU64,
+    throttled_nanos: AtomicU64,
+}
+
+/// The token buckets of a tenant, tokens below zero are debts.
+struct TokenBuckets {
+    io_tokens: f64,
+    byte_tokens: f64,
+    last_refill: Duration,
+}
+
+impl RateLimiter {
+    /// Creates a `RateLimiter` on the real time.
+    pub fn new(limit: RateLimit) -> Result<Self> {
+        Self::with_clock(limit, Arc::new(RealClock))
+    }
+
+    /// Creates a `RateLimiter` on the given clock, which must keep the time
+    /// unless the limit is unlimited. Otherwise the debts are never paid off.
+    pub fn with_clock(limit: RateLimit, clock: Arc<dyn Clock>) -> Result<Self> {
+        if limit != RateLimit::default() && !clock.keeps_time() {
+            return_errno_with_msg!(Unsupported, "rate limiting requires a clock");
+        }
+        Ok(Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+            clock,
+            parked: Mutex::new(VecDeque::new()),
+            num_throttled: AtomicU64::new(0),
+            throttled_nanos: AtomicU64::new(0),
+        })
+    }
+
+    /// Waits until the request is within the budget of its tenant.
+    /// Sync and flush requests are never throttled.
+    pub fn throttle_bio_req(&self, req: &BioReq) {
+        if req.nblocks() == 0 {
+            return;
+        }
+        self.throttle(Self::tenant_of(req), req.nblocks() * BLOCK_SIZE);
+    }
+
+    /// Waits until a request of `nbytes` is within the budget of the tenant,
+    /// then consumes the budget. Returns the delayed time.
+    pub fn throttle(&self, tenant: BioTenant, nbytes: usize) -> Duration {
+        let mut waited = Duration::ZERO;
+        while let Some(wait) = self.try_acquire(tenant, nbytes) {
+            self.clock.sleep(wait, &|| false);
+            waited += wait;
+        }
+
+        if !waited.is_zero() {
+            self.record_throttled(waited);
+        }
+        waited
+    }
+
+    /// Waits until the tenant is no longer in debt, without consuming the
+    /// budget. Returns the delayed time.
+    pub fn wait_for_budget(&self, tenant: BioTenant) -> Duration {
+        let mut waited = Duration::ZERO;
+        while let Some(wait) = self.debt_wait(tenant) {
+            self.clock.sleep(wait, &|| false);
+            waited += wait;
+        }
+
+        if !waited.is_zero() {
+            self.record_throttled(waited);
+        }
+        waited
+    }
+
+    /// Consumes the budget of a request of `nbytes` right away, even if
+    /// the tenant is in debt. The debt is paid off by the later requests.
+    pub fn charge(&self, tenant: BioTenant, nbytes: usize) {
+        self.with_buckets(tenant, |buckets| buckets.consume(&self.limit, nbytes));
+    }
+
+    /// Returns the time to wait until the debts of the tenant are paid off, if any.
+    pub fn debt_wait(&self, tenant: BioTenant) -> Option<Duration> {
+        self.with_buckets(tenant, |buckets| buckets.debt_wait(&self.limit))
+    }
+
+    /// Admits an asynchronous request if it's within the budget of its
+    /// tenant, otherwise parks it without blocking the submitter, until
+    /// it's taken by `unpark()`. A request is also parked if its tenant
+    /// has parked ones, so that the requests of a tenant stay in order.
+    pub fn admit_or_park(&self, req: BioReq) -> Option<BioReq> {
+        let tenant = Self::tenant_of(&req);
+        let mut parked = self.parked.lock();
+        if !parked
+            .iter()
+            .any(|(_, parked_req)| Self::tenant_of(parked_req) == tenant)
+            && self.try_acquire_bio_req(&req).is_none()
+        {
+            return Some(req);
+        }
+        parked.push_back((self.clock.now(), req));
+        None
+    }
+
+    /// Takes the parked requests that are within the budget now, in order.
+    pub fn unpark(&self) -> Vec<BioReq> {
+        let mut parked = self.parked.lock();
+        if parked.is_empty() {
+            return Vec::new();
+        }
+
+        let now = self.clock.now();
+        let mut in_debt = HashSet::new();
+        let mut admitted = Vec::new();
+        for (parked_at, req) in core::mem::take(&mut *parked) {
+            let tenant = Self::tenant_of(&req);
+            if in_debt.contains(&tenant) || self.try_acquire_bio_req(&req).is_some() {
+                let _ = in_debt.insert(tenant);
+                parked.push_back((parked_at, req));
+                continue;
+            }
+            self.record_throttled(now.saturating_sub(parked_at));
+            admitted.push(req);
+        }
+        admitted
+    }
+
+    /// Consumes the budget of the request if its tenant isn't in debt,
+    /// otherwise returns the time to wait.
+    fn try_acquire_bio_req(&self, req: &BioReq) -> Option<Duration> {
+        if req.nblocks() == 0 {
+            return None;
+        }
+        self.try_acquire(Self::tenant_of(req), req.nblocks() * BLOCK_SIZE)
+    }
+
+    /// Consumes the budget of a request of `nbytes` if the tenant
+    /// isn't in debt, otherwise returns the time to wait.
+    fn try_acquire(&self, tenant: BioTenant, nbytes: usize) -> Option<Duration> {
+        self.with_buckets(tenant, |buckets| {
+            let wait = buckets.debt_wait(&self.limit);
+            if wait.is_none() {
+                buckets.consume(&self.limit, nbytes);
+            }
+            wait
+        })
+    }
+
+    /// Runs `f` on the refilled buckets of the tenant.
+    fn with_buckets<T>(&self, tenant: BioTenant, f: impl FnOnce(&mut TokenBuckets) -> T) -> T {
+        let now = self.clock.now();
+        let mut buckets = self.buckets.lock();
+        let buckets = buckets
+            .entry(tenant)
+            .or_insert_with(|| TokenBuckets::new(&self.limit, now));
+        buckets.refill(&self.limit, now);
+        f(buckets)
+    }
+
+    fn tenant_of(req: &BioReq) -> BioTenant {
+        req.ext().get::<BioTenant>().copied().unwrap_or_default()
+    }
+
+    fn record_throttled(&self, delay: Duration) {
+        self.num_throttled.fetch_add(1, Ordering::Relaxed);
+        self.throttled_nanos
+            .fetch_add(delay.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the statistics of throttled requests.
+    pub fn stats(&self) -> RateLimitStats {
+        RateLimitStats {
+            num_throttled: self.num_throttled.load(Ordering::Relaxed),
+            throttled_time: Duration::from_nanos(self.throttled_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// A limiter of the I/Os of background tasks, see `BackgroundIoLimit`.
+pub(super) struct BackgroundIoLimiter {
+    limit: BackgroundIoLimit,
+    rate_limiter: RateLimiter,
+    /// The bytes of the outstanding I/Os.
+    outstanding: CvarMutex<usize>,
+    condvar: Condvar,
+}
+
+impl BackgroundIoLimiter {
+    /// Creates a `BackgroundIoLimiter` on the real time.
+    pub fn new(limit: BackgroundIoLimit) -> Result<Self> {
+        Self::with_clock(limit, Arc::new(RealClock))
+    }
+
+    /// Creates a `BackgroundIoLimiter` on the given clock, which must
+    /// keep the time if the bandwidth is limited.
+    pub fn with_clock(limit: BackgroundIoLimit, clock: Arc<dyn Clock>) -> Result<Self> {
+        let rate_limit = RateLimit {
+            iops: 0,
+            bandwidth: limit.bandwidth,
+        };
+        Ok(Self {
+            limit,
+            rate_limiter: RateLimiter::with_clock(rate_limit, clock)?,
+            outstanding: CvarMutex::new(0),
+            condvar: Condvar::new(),
+        })
+    }
+
+    /// Returns the maximum number of blocks of an I/O.
+    pub fn max_io_nblocks(&self) -> usize {
+        if self.limit.max_outstanding == 0 {
+            return usize::MAX;
+        }
+        (self.limit.max_outstanding / BLOCK_SIZE).max(1)
+    }
+
+    /// Runs an I/O of `nblocks` blocks, charging its bytes to the bandwidth.
+    ///
+    /// The bandwidth may fall into debt, which is paid off by
+    /// `wait_for_budget()` rather than delaying the I/O, so that the caller
+    /// doesn't sleep amid a GC section. The caller is only delayed while the
+    /// I/O would exceed the outstanding bytes. An I/O larger than the maximum
+    /// outstanding bytes waits to run alone.
+    pub fn run_io<T>(&self, nblocks: usize, io: impl FnOnce() -> Result<T>) -> Result<T> {
+        let nbytes = nblocks * BLOCK_SIZE;
+        self.rate_limiter.charge(BioTenant::default(), nbytes);
+        if self.limit.max_outstanding == 0 {
+            return io();
+        }
+
+        let reserved = nbytes.min(self.limit.max_outstanding);
+        let mut outstanding = self.outstanding.lock().unwrap();
+        while *outstanding + reserved > self.limit.max_outstanding {
+            outstanding = self.condvar.wait(outstanding).unwrap();
+        }
+        *outstanding += reserved;
+        drop(outstanding);
+
+        let res = io();
+        *self.outstanding.lock().unwrap() -= reserved;
+        self.condvar.notify_all();
+        res
+    }
+
+    /// Returns whether the bandwidth is in debt, i.e., the next
+    /// `wait_for_budget()` sleeps.
+    pub fn is_in_debt(&self) -> bool {
+        self.rate_limiter.debt_wait(BioTenant::default()).is_some()
+    }
+
+    /// Waits until the bandwidth is no longer in debt. Returns the delayed time.
+    pub fn wait_for_budget(&self) -> Duration {
+        self.rate_limiter.wait_for_budget(BioTenant::default())
+    }
+
+    /// Returns the statistics of the waits for the bandwidth.
+    pub fn stats(&self) -> RateLimitStats {
+        self.rate_limiter.stats()
+    }
+}
+
+impl TokenBuckets {
+    fn new(limit: &RateLimit, now: Duration) -> Self {
+        Self {
+            io_tokens: limit.iops as f64,
+            byte_tokens: limit.bandwidth as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Refill the buckets by the elapsed time, up to one second of budget.
+    fn refill(&mut self, limit: &RateLimit, now: Duration) {
+        let elapsed = now.saturating_sub(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.io_tokens = (self.io_tokens + elapsed * limit.iops as f64).min(limit.iops as f64);
+        self.byte_tokens =
+            (self.byte_tokens + elapsed * limit.bandwidth as f64).min(limit.bandwidth as f64);
+    }
+
+    /// Returns the time to wait until the debts are paid off, if any.
+    fn debt_wait(&self, limit: &RateLimit) -> Option<Duration> {
+        let wait_for = |tokens: f64, rate: u64| {
+            if rate == 0 || tokens >= 0.0 {
+                0.0
+            } else {
+                -tokens / rate as f64
+            }
+        };
+        let wait =
+            wait_for(self.io_tokens, limit.iops).max(wait_for(self.byte_tokens, limit.bandwidth));
+        (wait > 0.0).then(|| Duration::from_secs_f64(wait))
+    }
+
+    fn consume(&mut self, limit: &RateLimit, nbytes: usize) {
+        if limit.iops > 0 {
+            self.io_tokens -= 1.0;
+        }
+        if limit.bandwidth > 0 {
+            self.byte_tokens -= nbytes as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bio::{BioReqBuilder, BioType, BlockBuf};
+    use super::*;
+    use crate::layers::bio::{BlockId, Buf};
+
+    use core::ptr::NonNull;
+
+    /// A clock whose time only advances by sleeping.
+    struct SleepClock(Mutex<Duration>);
+
+    impl Clock for SleepClock {
+        fn sleep(&self, dur: Duration, _is_interrupted: &dyn Fn() -> bool) {
+            *self.0.lock() += dur;
+        }
+
+        fn now(&self) -> Duration {
+            *self.0.lock()
+        }
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let clock = Arc::new(SleepClock(Mutex::new(Duration::ZERO)));
+        let limit = RateLimit {
+            iops: 10,
+            bandwidth: 8 * BLOCK_SIZE as u64,
+        };
+        let limiter = RateLimiter::with_clock(limit, clock.clone()).unwrap();
+
+        // The burst of one second is never throttled
+        for _ in 0..8 {
+            assert!(limiter.throttle(BioTenant(0), BLOCK_SIZE).is_zero());
+        }
+        // Then the bandwidth is the bottleneck
+        let waited = limiter.throttle(BioTenant(0), BLOCK_SIZE);
+        assert!(waited.is_zero());
+        let waited = limiter.throttle(BioTenant(0), BLOCK_SIZE);
+        assert_eq!(waited.as_millis(), 125);
+
+        // A huge request is admitted, then blocks the followers for a while
+        assert!(limiter.throttle(BioTenant(0), 16 * BLOCK_SIZE).as_millis() > 0);
+        assert_eq!(limiter.throttle(BioTenant(0), 0).as_secs(), 2);
+
+        // Tenants are isolated
+        assert!(limiter.throttle(BioTenant(1), BLOCK_SIZE).is_zero());
+        let stats = limiter.stats();
+        assert_eq!(stats.num_throttled, 3);
+        assert!(stats.throttled_time >= Duration::from_secs(2));
+        assert!(clock.now() >= stats.throttled_time);
+    }
+
+    #[test]
+    fn rate_limiter_parks_async_reqs() {
+        let clock = Arc::new(SleepClock(Mutex::new(Duration::ZERO)));
+        let limit = RateLimit {
+            iops: 2,
+            bandwidth: 0,
+        };
+        let limiter = RateLimiter::with_clock(limit, clock.clone()).unwrap();
+        let mut buf = Buf::alloc(1).unwrap();
+        let mut new_req = |tenant| {
+            let block_buf = unsafe {
+                BlockBuf::from_raw_parts(
+                    NonNull::new(buf.as_mut_slice().as_mut_ptr()).unwrap(),
+                    BLOCK_SIZE,
+                )
+            };
+            BioReqBuilder::new(BioType::Write)
+                .addr(0 as BlockId)
+                .bufs(vec![block_buf])
+                .ext(BioTenant(tenant))
+                .build()
+        };
+
+        // The burst, then the debt is paid off by parking
+        for _ in 0..3 {
+            assert!(limiter.admit_or_park(new_req(0)).is_some());
+        }
+        assert!(limiter.admit_or_park(new_req(0)).is_none());
+        assert!(limiter.admit_or_park(new_req(0)).is_none());
+        // The submitters never sleep, and other tenants aren't blocked
+        assert_eq!(clock.now(), Duration::ZERO);
+        assert!(limiter.admit_or_park(new_req(1)).is_some());
+        assert!(limiter.unpark().is_empty());
+
+        // Unparked in order as the budget is refilled
+        for _ in 0..2 {
+            *clock.0.lock() += Duration::from_millis(500);
+            assert_eq!(limiter.unpark().len(), 1);
+        }
+        assert!(limiter.parked.lock().is_empty());
+        let stats = limiter.stats();
+        assert_eq!(stats.num_throttled, 2);
+        assert_eq!(stats.throttled_time, Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn rate_limiter_requires_clock() {
+        /// A clock that never advances, e.g., the real time without `std`.
+        struct StoppedClock;
+
+        impl Clock for StoppedClock {
+            fn sleep(&self, _dur: Duration, _is_interrupted: &dyn Fn() -> bool) {}
+
+            fn now(&self) -> Duration {
+                Duration::ZERO
+            }
+
+            fn keeps_time(&self) -> bool {
+                false
+            }
+        }
+
+        let limit = RateLimit {
+            iops: 10,
+            bandwidth: 0,
+        };
+        assert!(RateLimiter::with_clock(limit, Arc::new(StoppedClock)).is_err());
+        assert!(RateLimiter::with_clock(RateLimit::default(), Arc::new(StoppedClock)).is_ok());
+        let limit = BackgroundIoLimit {
+            bandwidth: 0,
+            max_outstanding: BLOCK_SIZE,
+        };
+        assert!(BackgroundIoLimiter::with_clock(limit, Arc::new(StoppedClock)).is_ok());
+    }
+
+    #[test]
+    fn background_io_limiter() {
+        let clock = Arc::new(SleepClock(Mutex::new(Duration::ZERO)));
+        let limit = BackgroundIoLimit {
+            bandwidth: 4 * BLOCK_SIZE as u64,
+            max_outstanding: 2 * BLOCK_SIZE,
+        };
+        let limiter = Arc::new(BackgroundIoLimiter::with_clock(limit, clock.clone()).unwrap());
+        assert_eq!(limiter.max_io_nblocks(), 2);
+
+        // A second of budget, then the I/Os run into debt without waiting
+        for _ in 0..2 {
+            limiter.run_io(2, || Ok(())).unwrap();
+        }
+        assert!(!limiter.is_in_debt());
+        assert_eq!(
+            limiter.run_io(1, || Ok(clock.now())).unwrap(),
+            Duration::ZERO
+        );
+        assert!(limiter.is_in_debt());
+        // The debt is paid off by the waiter
+        assert_eq!(limiter.wait_for_budget(), Duration::from_millis(250));
+        assert_eq!(clock.now(), Duration::from_millis(250));
+        assert!(limiter.wait_for_budget().is_zero());
+        assert_eq!(limiter.stats().num_throttled, 1);
+
+        // The outstanding I/Os are capped
+        let unlimited = BackgroundIoLimit {
+            bandwidth: 0,
+            max_outstanding: 2 * BLOCK_SIZE,
+        };
+        let limiter = Arc::new(BackgroundIoLimiter::new(unlimited).unwrap());
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (finish_tx, finish_rx) = std::sync::mpsc::channel::<()>();
+        let handle = {
+            let limiter = limiter.clone();
+            std::thread::spawn(move || {
+                limiter.run_io(8, || {
+                    started_tx.send(()).unwrap();
+                    finish_rx.recv().unwrap();
+                    Ok(())
+                })
+            })
+        };
+        started_rx.recv().unwrap();
+        let is_done = Arc::new(core::sync::atomic::AtomicBool::new(false));
+        let waiter = {
+            let (limiter, is_done) = (limiter.clone(), is_done.clone());
+            std::thread::spawn(move || limiter.run_io(1, || Ok(is_done.load(Ordering::Acquire))))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        is_done.store(true, Ordering::Release);
+        finish_tx.send(()).unwrap();
+        handle.join().unwrap().unwrap();
+        // The waiter only runs after the oversized I/O finishes
+        assert!(waiter.join().unwrap().unwrap());
+    }
+}
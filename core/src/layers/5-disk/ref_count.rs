@@ -0,0 +1,326 @@
+//! The reference counts of the shared user data blocks.
+//!
+//! On the disks with `FEATURE_SHARED_BLOCKS`, `SwornDisk::clone_range()`
+//! shares the host blocks of the cloned records rather than copying them, so
+//! a host block may be referred to by the records of several LBAs. Each block
+//! ever shared is counted until its last record is dropped, only then is it
+//! deallocated. The other blocks are not counted, each is owned by its record.
+//!
+//! The changes of the counts are appended to the logs in the `REFC` bucket of
+//! `TxLogStore` and replayed from older to newer on open. An increment is
+//! logged before the cloned records are inserted, while a decrement is logged
+//! on the sync after the drop of its record. Thus a crash leaves a count too
+//! high (which leaks the block) rather than too low (which frees a block in
+//! use). Once the logs hold twice as many entries as the counted blocks, they
+//! are replaced by a single log of the counts.
+use super::sworndisk::Hba;
+use crate::layers::bio::{BlockSet, Buf, BufRef};
+use crate::layers::log::{TxLogId, TxLogStore};
+use crate::os::{BTreeMap, Mutex};
+use crate::prelude::*;
+
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+use pod::Pod;
+
+/// The bucket name of reference count logs.
+const BUCKET_REF_COUNT_LOG: &str = "REFC";
+
+/// A change of the count of a block in the logs, whose delta is never zero
+/// unless it's the padding of a block.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug, PartialEq, Eq)]
+struct RefCountDelta {
+    hba: u64,
+    delta: i64,
+}
+
+impl RefCountDelta {
+    /// The number of deltas in a block of the logs.
+    const PER_BLOCK: usize = BLOCK_SIZE / size_of::<RefCountDelta>();
+}
+
+/// The reference counts of the shared blocks of a disk.
+pub(super) struct RefCountTable<D> {
+    store: Arc<TxLogStore<D>>,
+    state: Mutex<RefCountState>,
+    /// Whether the counts are never persisted, see `Config::ephemeral`.
+    ephemeral: bool,
+    /// Whether the WAL of the logical block table is being replayed. The
+    /// drops replayed meanwhile happened before the last sync, thus their
+    /// decrements are logged already.
+    replaying: AtomicBool,
+}
+
+struct RefCountState {
+    /// The number of records referring to each counted block.
+    counts: BTreeMap<Hba, usize>,
+    /// The decrements since the last persistence, in order.
+    unlogged: Vec<RefCountDelta>,
+    /// The number of deltas in the logs.
+    num_logged: usize,
+}
+
+impl<D: BlockSet + 'static> RefCountTable<D> {
+    /// Creates an empty `RefCountTable` on the given `TxLogStore`.
+    pub fn new(store: Arc<TxLogStore<D>>, ephemeral: bool) -> Self {
+        Self {
+            store,
+            state: Mutex::new(RefCountState {
+                counts: BTreeMap::new(),
+                unlogged: Vec::new(),
+                num_logged: 0,
+            }),
+            ephemeral,
+            replaying: AtomicBool::new(false),
+        }
+    }
+
+    /// Recovers the `RefCountTable` from the logs in the given `TxLogStore`.
+    /// The drops of the counted blocks are ignored until `finish_replay()`.
+    pub fn recover(store: Arc<TxLogStore<D>>) -> Result<Self> {
+        let table = Self::new(store, false);
+        table.replaying.store(true, Ordering::Release);
+        let store = &table.store;
+        let mut tx = store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            let mut state = table.state.lock();
+            for log_id in Self::list_logs(store)? {
+                let log = store.open_log(log_id, false)?;
+                let mut buf = Buf::alloc(log.nblocks())?;
+                log.read(0 as BlockId, buf.as_mut())?;
+                for block in buf.as_slice().chunks(BLOCK_SIZE) {
+                    for entry in block[..RefCountDelta::PER_BLOCK * size_of::<RefCountDelta>()]
+                        .chunks(size_of::<RefCountDelta>())
+                    {
+                        let delta = RefCountDelta::from_bytes(entry);
+                        if delta.delta == 0 {
+                            continue;
+                        }
+                        state.apply(&delta);
+                        state.num_logged += 1;
+                    }
+                }
+            }
+            Ok(())
+        });
+        if res.is_err() {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "recover reference count log TX aborted");
+        }
+        tx.commit()?;
+        Ok(table)
+    }
+
+    /// Marks the replay of the WAL of the logical block table finished.
+    pub fn finish_replay(&self) {
+        self.replaying.store(false, Ordering::Release);
+    }
+
+    /// Returns whether the block of `hba` is counted, i.e., it's ever shared
+    /// and referred to by some records.
+    pub fn is_counted(&self, hba: Hba) -> bool {
+        self.state.lock().counts.contains_key(&hba)
+    }
+
+    /// Shares the blocks of `hbas` with one more record each. The increments
+    /// are logged before it returns.
+    pub fn share(&self, hbas: &[Hba]) -> Result<()> {
+        if hbas.is_empty() {
+            return Ok(());
+        }
+        let mut state = self.state.lock();
+        let mut deltas = Vec::with_capacity(hbas.len());
+        for &hba in hbas {
+            // A block counted for the first time is referred to by its owner as well
+            let delta = RefCountDelta {
+                hba: hba as _,
+                delta: if state.counts.contains_key(&hba) {
+                    1
+                } else {
+                    2
+                },
+            };
+            state.apply(&delta);
+            deltas.push(delta);
+        }
+        if self.ephemeral {
+            return Ok(());
+        }
+        if let Err(e) = self.append_deltas(&deltas, false) {
+            for delta in deltas.iter() {
+                state.apply(&RefCountDelta {
+                    hba: delta.hba,
+                    delta: -delta.delta,
+                });
+            }
+            return Err(e);
+        }
+        state.num_logged += deltas.len();
+        Ok(())
+    }
+
+    /// Releases a record of the block of `hba`, returns whether the block
+    /// is to be deallocated, i.e., it's not counted or its last record is
+    /// released.
+    pub fn release(&self, hba: Hba) -> bool {
+        let mut state = self.state.lock();
+        if !state.counts.contains_key(&hba) {
+            return true;
+        }
+        if self.replaying.load(Ordering::Acquire) {
+            return false;
+        }
+        let delta = RefCountDelta {
+            hba: hba as _,
+            delta: -1,
+        };
+        state.apply(&delta);
+        if !self.ephemeral {
+            state.unlogged.push(delta);
+        }
+        !state.counts.contains_key(&hba)
+    }
+
+    /// Returns the number of records referring to the block of `hba`,
+    /// which is one if the block is not counted.
+    pub fn count(&self, hba: Hba) -> usize {
+        self.state.lock().counts.get(&hba).copied().unwrap_or(1)
+    }
+
+    /// Persists the decrements since the last persistence, which are
+    /// durable once the `TxLogStore` is synced.
+    pub fn persist(&self) -> Result<()> {
+        let mut state = self.state.lock();
+        if state.unlogged.is_empty() {
+            return Ok(());
+        }
+        let num_logged = state.num_logged + state.unlogged.len();
+        let is_rewritten = num_logged > 2 * state.counts.len() + RefCountDelta::PER_BLOCK;
+        let deltas: Vec<_> = if is_rewritten {
+            state
+                .counts
+                .iter()
+                .map(|(&hba, &count)| RefCountDelta {
+                    hba: hba as _,
+                    delta: count as _,
+                })
+                .collect()
+        } else {
+            state.unlogged.clone()
+        };
+        self.append_deltas(&deltas, is_rewritten)?;
+
+        state.num_logged = if is_rewritten {
+            deltas.len()
+        } else {
+            num_logged
+        };
+        state.unlogged.clear();
+        Ok(())
+    }
+
+    /// Appends the deltas to the newest log, or to a new log replacing
+    /// all the others if `is_rewritten`.
+    fn append_deltas(&self, deltas: &[RefCountDelta], is_rewritten: bool) -> Result<()> {
+        let nblocks = deltas.len().div_ceil(RefCountDelta::PER_BLOCK).max(1);
+        let mut buf = Buf::alloc(nblocks)?;
+        for (block, chunk) in buf
+            .as_mut_slice()
+            .chunks_mut(BLOCK_SIZE)
+            .zip(deltas.chunks(RefCountDelta::PER_BLOCK))
+        {
+            for (entry, delta) in block.chunks_mut(size_of::<RefCountDelta>()).zip(chunk) {
+                entry.copy_from_slice(delta.as_bytes());
+            }
+        }
+
+        let store = &self.store;
+        let mut tx = store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            let log_ids = Self::list_logs(store)?;
+            let log = match log_ids.last() {
+                Some(&log_id) if !is_rewritten => store.open_log(log_id, true)?,
+                _ => {
+                    for &log_id in log_ids.iter() {
+                        store.delete_log(log_id)?;
+                    }
+                    store.create_log(BUCKET_REF_COUNT_LOG)?
+                }
+            };
+            log.append(BufRef::try_from(buf.as_slice()).unwrap())
+        });
+        if res.is_err() {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "persist reference count log TX aborted");
+        }
+        tx.commit()
+    }
+
+    /// Lists the reference count logs in the store, from older to newer.
+    ///
+    /// # Panics
+    ///
+    /// This method must be called within a TX. Otherwise, this method panics.
+    fn list_logs(store: &Arc<TxLogStore<D>>) -> Result<Vec<TxLogId>> {
+        let mut log_ids = match store.list_logs_in(BUCKET_REF_COUNT_LOG) {
+            Ok(log_ids) => log_ids,
+            Err(e) if e.errno() == NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        log_ids.sort();
+        Ok(log_ids)
+    }
+}
+
+impl RefCountState {
+    /// Applies a delta to the count of its block, the block is no longer
+    /// counted once its count drops to zero.
+    fn apply(&mut self, delta: &RefCountDelta) {
+        let hba = delta.hba as Hba;
+        let count = self.counts.entry(hba).or_insert(0);
+        *count = count.saturating_add_signed(delta.delta as isize);
+        if *count == 0 {
+            self.counts.remove(&hba);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::bio::MemDisk;
+    use crate::os::AeadKey as Key;
+
+    #[test]
+    fn ref_count_table() -> Result<()> {
+        let mem_disk = MemDisk::create(4 * 1024)?;
+        let root_key = Key::random();
+        let store = Arc::new(TxLogStore::format(mem_disk.clone(), root_key.clone())?);
+        let table = RefCountTable::new(store.clone(), false);
+        assert!(table.release(1));
+
+        table.share(&[1, 2])?;
+        table.share(&[1])?;
+        assert_eq!((table.count(1), table.count(2), table.count(3)), (3, 2, 1));
+        assert!(!table.release(1));
+        assert!(!table.release(2));
+        // Still counted with its last record
+        assert!(table.is_counted(2));
+        table.persist()?;
+        store.sync()?;
+        drop(table);
+        drop(store);
+
+        let store = Arc::new(TxLogStore::recover(mem_disk, root_key)?);
+        let table = RefCountTable::recover(store)?;
+        assert_eq!((table.count(1), table.count(2)), (2, 1));
+        // The replayed drops are logged already
+        assert!(!table.release(2));
+        assert_eq!(table.count(2), 1);
+        table.finish_replay();
+        assert!(table.release(2));
+        assert!(!table.is_counted(2));
+        Ok(())
+    }
+}
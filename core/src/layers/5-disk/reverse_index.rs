@@ -0,0 +1,382 @@
+//! The reverse index of user data blocks, i.e., the map from HBA to LBA,
+//! which GC consults to remap the migrated blocks. Only kept if GC is enabled.
+//!
+//! It's either a second `TxLsmTree` (`ReverseIndexKind::Lsm`), or a reverse
+//! map per segment (`ReverseIndexKind::SegmentBlobs`), i.e., the LBAs of all
+//! blocks in the segment, persisted as one small (encrypted) `TxLog` in its
+//! own bucket. The latter saves all the compactions of the second tree: the
+//! blob of a segment is written once the segment is sealed (its last block is
+//! mapped), rewritten once GC migrates blocks into it, and the blobs of the
+//! other segments changed since are written on sync (or once too many). The
+//! entries lost by a crash before then are repaired from the WAL of the
+//! logical block table on open.
+//!
+//! Either may keep the stale entries of the freed blocks, which GC detects
+//! by cross-checking the logical block table.
+use super::gc::{ReverseKey, ReverseValue};
+use super::segment::{SegmentId, SEGMENT_SIZE};
+use super::sworndisk::{Hba, Lba};
+use crate::layers::bio::{BlockSet, Buf, BufRef};
+use crate::layers::log::TxLogStore;
+use crate::layers::lsm::{CompactionScheduler, TxLsmTree};
+use crate::os::{CvarMutex, HashMap};
+use crate::prelude::*;
+
+use core::mem::size_of;
+use pod::Pod;
+
+/// The prefix of the bucket names of the reverse maps of segments.
+const BUCKET_REVERSE_MAP_PREFIX: &str = "RMAP";
+/// The LBA of the blocks without entries in a reverse map.
+const NO_LBA: Lba = Lba::MAX;
+/// The maximum number of reverse maps cached in memory, besides the ones
+/// changed since last persisted.
+const MAX_CACHED_MAPS: usize = 64;
+/// The maximum number of reverse maps changed since last persisted, beyond
+/// which all of them are persisted without waiting for a sync.
+const MAX_DIRTY_MAPS: usize = 64;
+
+/// The reverse index of user data blocks.
+pub(super) enum ReverseIndex<D> {
+    Lsm(TxLsmTree<ReverseKey, ReverseValue, D>),
+    SegmentBlobs(Arc<SegmentReverseMaps<D>>),
+}
+
+impl<D: BlockSet + 'static> ReverseIndex<D> {
+    /// Returns the entry of a block, or `NotFound`.
+    pub fn get(&self, key: &ReverseKey) -> Result<ReverseValue> {
+        match self {
+            Self::Lsm(tree) => tree.get(key),
+            Self::SegmentBlobs(maps) => maps
+                .get(key.hba)?
+                .map(|lba| ReverseValue { lba })
+                .ok_or(Error::with_msg(NotFound, "reverse entry not found")),
+        }
+    }
+
+    /// Returns the entries of the blocks, `None` for the missing ones.
+    pub fn get_multi(&self, keys: &[ReverseKey]) -> Result<Vec<Option<ReverseValue>>> {
+        match self {
+            Self::Lsm(tree) => tree.get_multi(keys),
+            Self::SegmentBlobs(maps) => keys
+                .iter()
+                .map(|key| Ok(maps.get(key.hba)?.map(|lba| ReverseValue { lba })))
+                .collect(),
+        }
+    }
+
+    /// Inserts or replaces the entry of a block.
+    pub fn put(&self, key: ReverseKey, value: ReverseValue) -> Result<()> {
+        match self {
+            Self::Lsm(tree) => tree.put(key, value),
+            Self::SegmentBlobs(maps) => maps.put_batch(&[(key, value)]),
+        }
+    }
+
+    /// Inserts or replaces the entries of the blocks.
+    pub fn put_batch(&self, records: &[(ReverseKey, ReverseValue)]) -> Result<()> {
+        match self {
+            Self::Lsm(tree) => tree.put_batch(records),
+            Self::SegmentBlobs(maps) => maps.put_batch(records),
+        }
+    }
+
+    /// Loads the entries of the blocks in bulk, sorted by HBA. They're
+    /// durable once returned, without a sync.
+    pub fn bulk_load(&self, records: &[(ReverseKey, ReverseValue)]) -> Result<()> {
+        match self {
+            Self::Lsm(tree) => tree.bulk_load(records),
+            Self::SegmentBlobs(maps) => {
+                maps.put_batch(records)?;
+                maps.persist_dirty_maps()
+            }
+        }
+    }
+
+    /// Persists the entries put so far, they're durable once returned.
+    pub fn sync(&self) -> Result<()> {
+        match self {
+            Self::Lsm(tree) => tree.sync(),
+            Self::SegmentBlobs(maps) => maps.sync(),
+        }
+    }
+
+    /// Does the major compactions if required, a no-op for the reverse maps.
+    pub fn manual_compaction(&self) -> Result<()> {
+        match self {
+            Self::Lsm(tree) => tree.manual_compaction(),
+            Self::SegmentBlobs(_) => Ok(()),
+        }
+    }
+
+    /// Sets the scheduler of major compactions, a no-op for the reverse maps.
+    pub fn set_compaction_scheduler(&self, scheduler: Arc<dyn CompactionScheduler>) {
+        if let Self::Lsm(tree) = self {
+            tree.set_compaction_scheduler(scheduler);
+        }
+    }
+
+    /// Sets whether the records are not logged, a no-op for the reverse maps.
+    pub fn set_ephemeral(&self, ephemeral: bool) {
+        if let Self::Lsm(tree) = self {
+            tree.set_ephemeral(ephemeral);
+        }
+    }
+
+    /// Returns the memory (in bytes) taken by the caches.
+    pub fn cached_bytes(&self) -> usize {
+        match self {
+            Self::Lsm(tree) => tree.cached_bytes(),
+            Self::SegmentBlobs(maps) => maps.cached_bytes(),
+        }
+    }
+}
+
+impl<D> Clone for ReverseIndex<D> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Lsm(tree) => Self::Lsm(tree.clone()),
+            Self::SegmentBlobs(maps) => Self::SegmentBlobs(maps.clone()),
+        }
+    }
+}
+
+/// The reverse maps of segments, each persisted as a blob in a `TxLogStore`.
+pub(super) struct SegmentReverseMaps<D> {
+    store: Arc<TxLogStore<D>>,
+    /// The reverse maps loaded or changed, by their segments.
+    maps: CvarMutex<HashMap<SegmentId, ReverseMap>>,
+}
+
+struct ReverseMap {
+    lbas: Vec<Lba>,
+    /// Whether it's changed since last persisted.
+    is_dirty: bool,
+}
+
+impl<D: BlockSet + 'static> SegmentReverseMaps<D> {
+    /// Creates the reverse maps in the given store, the persisted ones
+    /// (if any) are loaded on demand.
+    pub fn new(store: Arc<TxLogStore<D>>) -> Self {
+        Self {
+            store,
+            maps: CvarMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the LBA of a block, `None` if it has no entry.
+    pub fn get(&self, hba: Hba) -> Result<Option<Lba>> {
+        let segment_id = hba / SEGMENT_SIZE;
+        let mut maps = self.maps.lock().unwrap();
+        let map = self.load_map(&mut maps, segment_id)?;
+        let lba = map.lbas[hba % SEGMENT_SIZE];
+        Ok((lba != NO_LBA).then_some(lba))
+    }
+
+    /// Puts the entries of the blocks, then persists the maps of the
+    /// segments sealed by them, or all the changed maps if too many.
+    pub fn put_batch(&self, records: &[(ReverseKey, ReverseValue)]) -> Result<()> {
+        let mut maps = self.maps.lock().unwrap();
+        let mut sealed = Vec::new();
+        for (key, value) in records {
+            let segment_id = key.hba / SEGMENT_SIZE;
+            let map = self.load_map(&mut maps, segment_id)?;
+            map.lbas[key.hba % SEGMENT_SIZE] = value.lba;
+            map.is_dirty = true;
+            if key.hba % SEGMENT_SIZE == SEGMENT_SIZE - 1 {
+                sealed.push(segment_id);
+            }
+        }
+        let dirty = dirty_segments(&maps);
+        if dirty.len() > MAX_DIRTY_MAPS {
+            return self.persist_maps(&mut maps, &dirty);
+        }
+        self.persist_maps(&mut maps, &sealed)
+    }
+
+    /// Persists the maps changed since last persisted, then syncs the store.
+    pub fn sync(&self) -> Result<()> {
+        self.persist_dirty_maps()?;
+        self.store.sync()
+    }
+
+    /// Persists the maps changed since last persisted.
+    pub fn persist_dirty_maps(&self) -> Result<()> {
+        let mut maps = self.maps.lock().unwrap();
+        let dirty = dirty_segments(&maps);
+        self.persist_maps(&mut maps, &dirty)
+    }
+
+    /// Returns the memory (in bytes) taken by the maps in memory.
+    pub fn cached_bytes(&self) -> usize {
+        self.maps.lock().unwrap().len() * SEGMENT_SIZE * size_of::<Lba>()
+    }
+
+    /// Returns the map of a segment, which is loaded from its blob (if any)
+    /// unless in memory. A clean map is evicted to make room if needed.
+    fn load_map<'a>(
+        &self,
+        maps: &'a mut HashMap<SegmentId, ReverseMap>,
+        segment_id: SegmentId,
+    ) -> Result<&'a mut ReverseMap> {
+        if !maps.contains_key(&segment_id) {
+            let lbas = self.read_blob(segment_id)?;
+            let num_clean = maps.values().filter(|map| !map.is_dirty).count();
+            if num_clean >= MAX_CACHED_MAPS {
+                let victim = maps
+                    .iter()
+                    .find(|(_, map)| !map.is_dirty)
+                    .map(|(segment_id, _)| *segment_id)
+                    .unwrap();
+                maps.remove(&victim);
+            }
+            maps.insert(
+                segment_id,
+                ReverseMap {
+                    lbas,
+                    is_dirty: false,
+                },
+            );
+        }
+        Ok(maps.get_mut(&segment_id).unwrap())
+    }
+
+    /// Reads the blob of a segment, all blocks are without entries if absent.
+    fn read_blob(&self, segment_id: SegmentId) -> Result<Vec<Lba>> {
+        let store = &self.store;
+        let mut tx = store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            let blob_log = match store.open_log_in(&bucket_of(segment_id)) {
+                Ok(blob_log) => blob_log,
+                Err(e) if e.errno() == NotFound => return Ok(vec![NO_LBA; SEGMENT_SIZE]),
+                Err(e) => return Err(e),
+            };
+            let mut buf = Buf::alloc(blob_log.nblocks())?;
+            blob_log.read(0 as BlockId, buf.as_mut())?;
+            let lbas: Vec<_> = buf
+                .as_slice()
+                .chunks_exact(size_of::<Lba>())
+                .take(SEGMENT_SIZE)
+                .map(Lba::from_bytes)
+                .collect();
+            if lbas.len() != SEGMENT_SIZE {
+                return_errno_with_msg!(InvalidArgs, "invalid reverse map blob");
+            }
+            Ok(lbas)
+        });
+        let lbas = match res {
+            Ok(lbas) => lbas,
+            Err(e) => {
+                tx.abort();
+                return Err(e);
+            }
+        };
+        tx.commit()?;
+        Ok(lbas)
+    }
+
+    /// Persists the maps of the given segments in a TX, each replaces
+    /// the older blob (if any) of its segment.
+    fn persist_maps(
+        &self,
+        maps: &mut HashMap<SegmentId, ReverseMap>,
+        segment_ids: &[SegmentId],
+    ) -> Result<()> {
+        if segment_ids.is_empty() {
+            return Ok(());
+        }
+        let store = &self.store;
+        let mut tx = store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            let mut buf = Vec::with_capacity(SEGMENT_SIZE * size_of::<Lba>());
+            for segment_id in segment_ids {
+                let bucket = bucket_of(*segment_id);
+                if let Ok(blob_log_ids) = store.list_logs_in(&bucket) {
+                    for blob_log_id in blob_log_ids {
+                        store.delete_log(blob_log_id)?;
+                    }
+                }
+                buf.clear();
+                for lba in &maps[segment_id].lbas {
+                    buf.extend_from_slice(lba.as_bytes());
+                }
+                buf.resize(align_up(buf.len(), BLOCK_SIZE), 0);
+                let blob_log = store.create_log(&bucket)?;
+                blob_log.append(BufRef::try_from(&buf[..]).unwrap())?;
+            }
+            Ok(())
+        });
+        if res.is_err() {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "persist reverse maps TX aborted");
+        }
+        tx.commit()?;
+
+        for segment_id in segment_ids {
+            maps.get_mut(segment_id).unwrap().is_dirty = false;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the segments whose maps are changed since last persisted.
+fn dirty_segments(maps: &HashMap<SegmentId, ReverseMap>) -> Vec<SegmentId> {
+    maps.iter()
+        .filter(|(_, map)| map.is_dirty)
+        .map(|(segment_id, _)| *segment_id)
+        .collect()
+}
+
+/// Returns the bucket name of the reverse map of a segment.
+fn bucket_of(segment_id: SegmentId) -> String {
+    BUCKET_REVERSE_MAP_PREFIX.to_string() + &segment_id.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::bio::MemDisk;
+    use crate::AeadKey;
+
+    #[test]
+    fn segment_reverse_maps() -> Result<()> {
+        let store = Arc::new(TxLogStore::format(
+            MemDisk::create(16 * SEGMENT_SIZE)?,
+            AeadKey::random(),
+        )?);
+        let maps = SegmentReverseMaps::new(store.clone());
+        assert_eq!(maps.get(0)?, None);
+
+        // Segment 0 is sealed by its last block, segment 1 is still open
+        let records: Vec<_> = (0..SEGMENT_SIZE + 10)
+            .map(|hba| (ReverseKey { hba }, ReverseValue { lba: hba * 2 }))
+            .collect();
+        maps.put_batch(&records)?;
+        assert_eq!(maps.get(5)?, Some(10));
+        {
+            let cached = maps.maps.lock().unwrap();
+            assert!(!cached[&0].is_dirty);
+            assert!(cached[&1].is_dirty);
+        }
+
+        maps.sync()?;
+        let reopened = SegmentReverseMaps::new(store.clone());
+        assert_eq!(
+            reopened.get(SEGMENT_SIZE - 1)?,
+            Some((SEGMENT_SIZE - 1) * 2)
+        );
+        assert_eq!(
+            reopened.get(SEGMENT_SIZE + 9)?,
+            Some((SEGMENT_SIZE + 9) * 2)
+        );
+        assert_eq!(reopened.get(SEGMENT_SIZE + 10)?, None);
+
+        // Rewritten by a migration into the segment
+        reopened.put_batch(&[(ReverseKey { hba: 3 }, ReverseValue { lba: 7 })])?;
+        reopened.sync()?;
+        let reopened = SegmentReverseMaps::new(store);
+        assert_eq!(reopened.get(3)?, Some(7));
+        assert_eq!(reopened.get(4)?, Some(8));
+        Ok(())
+    }
+}
@@ -5,7 +5,8 @@ use crate::os::{BTreeMap, Mutex};
 use crate::util::BitMap;
 use crate::{prelude::*, BlockSet, Errno};
 use core::mem::size_of;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use pod::Pod;
 // Each segment contains 1024 blocks
 pub const SEGMENT_SIZE: usize = 1024;
 pub type SegmentId = usize;
@@ -25,6 +26,14 @@ pub struct Segment {
     bitmap: Arc<Mutex<BitMap>>,
     nblocks: usize,
     free_space: AtomicUsize,
+    // Statistics for victim policies, persisted along with the counters:
+    // the write sequence number when the segment was last written by user writes
+    last_write: AtomicU64,
+    // the number of blocks written by user writes, decayed by `decay_write_count()`
+    write_count: AtomicU64,
+    // Runtime statistics, not persisted:
+    // the number of blocks invalidated since last reset, i.e., the heat of the segment
+    recent_invalidations: AtomicUsize,
 }
 
 impl Segment {
@@ -35,6 +44,9 @@ impl Segment {
             nblocks,
             free_space: AtomicUsize::new(nblocks),
             segment_id,
+            last_write: AtomicU64::new(0),
+            write_count: AtomicU64::new(0),
+            recent_invalidations: AtomicUsize::new(0),
         }
     }
     pub fn segment_id(&self) -> SegmentId {
@@ -55,6 +67,15 @@ impl Segment {
         self.free_space.load(Ordering::Acquire)
     }
 
+    // Recount free_space from the bitmap, the source of truth of free blocks, e.g., on recovery
+    pub fn recount_free_space(&self, bitmap: &BitMap) {
+        let lower = self.segment_id * SEGMENT_SIZE;
+        let free_space = (lower..lower + self.nblocks)
+            .filter(|&block_id| bitmap.test_bit(block_id))
+            .count();
+        self.free_space.store(free_space, Ordering::Release);
+    }
+
     pub fn num_invalid_blocks(&self) -> usize {
         self.nblocks - self.num_valid_blocks()
     }
@@ -71,12 +92,53 @@ impl Segment {
         //  debug!("mark_deallocated: {}", self.segment_id);
         self.free_space.fetch_add(1, Ordering::Release);
         self.valid_block.fetch_sub(1, Ordering::Release);
+        self.recent_invalidations.fetch_add(1, Ordering::Relaxed);
     }
 
     pub fn mark_deallocated_batch(&self, nblocks: usize) {
         //   debug!("mark_deallocated_batch: {}", self.segment_id);
         self.free_space.fetch_add(nblocks, Ordering::Release);
         self.valid_block.fetch_sub(nblocks, Ordering::Release);
+        self.recent_invalidations
+            .fetch_add(nblocks, Ordering::Relaxed);
+    }
+
+    // The write sequence number when the segment was last written by user writes
+    pub fn last_write(&self) -> u64 {
+        self.last_write.load(Ordering::Relaxed)
+    }
+
+    pub fn set_last_write(&self, write_seq: u64) {
+        self.last_write.fetch_max(write_seq, Ordering::Relaxed);
+    }
+
+    // The number of blocks written by user writes, decayed over time
+    pub fn write_count(&self) -> u64 {
+        self.write_count.load(Ordering::Relaxed)
+    }
+
+    pub fn add_writes(&self, nblocks: u64) {
+        self.write_count.fetch_add(nblocks, Ordering::Relaxed);
+    }
+
+    // Scale the write count by `factor` (in `[0, 1]`), so that old writes weigh less.
+    // It's persisted with the next TX changing the segment or the next compaction
+    pub fn decay_write_count(&self, factor: f64) {
+        let factor = factor.clamp(0.0, 1.0);
+        let _ = self
+            .write_count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                Some((count as f64 * factor) as u64)
+            });
+    }
+
+    // The number of blocks invalidated since last reset
+    pub fn recent_invalidations(&self) -> usize {
+        self.recent_invalidations.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_recent_invalidations(&self) {
+        self.recent_invalidations.store(0, Ordering::Relaxed);
     }
 
     // All blocks that have been marked as allocated
@@ -112,6 +174,71 @@ impl Segment {
         self.valid_block.store(self.nblocks, Ordering::Release);
         self.free_space.store(self.nblocks, Ordering::Release);
     }
+
+    // The counters of the segment as a record of the given version
+    pub fn to_diff(&self, version: u64) -> SegmentDiff {
+        SegmentDiff {
+            version,
+            segment_id: self.segment_id as _,
+            valid_blocks: self.num_valid_blocks() as _,
+            free_space: self.free_space() as _,
+        }
+    }
+
+    // Apply the counters of a recovered record
+    pub fn apply_diff(&self, diff: &SegmentDiff) {
+        debug_assert_eq!(diff.segment_id as SegmentId, self.segment_id);
+        self.valid_block
+            .store(diff.valid_blocks as _, Ordering::Release);
+        self.free_space
+            .store(diff.free_space as _, Ordering::Release);
+    }
+
+    // The write statistics of the segment as a record of the given version
+    pub fn to_heat(&self, version: u64) -> SegmentHeat {
+        SegmentHeat {
+            version,
+            segment_id: self.segment_id as _,
+            last_write: self.last_write(),
+            write_count: self.write_count(),
+        }
+    }
+
+    // Apply the write statistics of a recovered record
+    pub fn apply_heat(&self, heat: &SegmentHeat) {
+        debug_assert_eq!(heat.segment_id as SegmentId, self.segment_id);
+        self.last_write.store(heat.last_write, Ordering::Relaxed);
+        self.write_count.store(heat.write_count, Ordering::Relaxed);
+    }
+}
+
+/// A record of the counters of a segment, persisted in `SEGD` logs between
+/// two persistences of the whole segment table.
+///
+/// The records hold absolute counters rather than deltas, and the one of the
+/// largest version wins on recovery, regardless of the order of logs.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug, PartialEq, Eq)]
+pub struct SegmentDiff {
+    /// Zero is reserved for the padding of the log.
+    pub version: u64,
+    pub segment_id: u64,
+    pub valid_blocks: u64,
+    pub free_space: u64,
+}
+
+/// A record of the write statistics of a segment, persisted in `SEGH` logs
+/// along with each `SegmentDiff` of the same version, and following the
+/// version of the whole segment table. Absent in the disks created before,
+/// whose statistics start from zero.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug, PartialEq, Eq)]
+pub struct SegmentHeat {
+    /// Zero is reserved for the padding of the log.
+    pub version: u64,
+    pub segment_id: u64,
+    pub last_write: u64,
+    pub write_count: u64,
 }
 
 impl Segment {
@@ -141,6 +268,9 @@ impl Segment {
             bitmap,
             nblocks,
             segment_id,
+            last_write: AtomicU64::new(0),
+            write_count: AtomicU64::new(0),
+            recent_invalidations: AtomicUsize::new(0),
         })
     }
 
@@ -164,6 +294,114 @@ pub fn recover_segment_table(
     Ok(segment_table)
 }
 
+/// The usage of a segment, a row of the `FragmentationReport`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SegmentUsage {
+    pub segment_id: SegmentId,
+    pub nblocks: usize,
+    /// Blocks holding live data.
+    pub live_blocks: usize,
+    /// Deallocated blocks, which are reclaimable by GC.
+    pub invalid_blocks: usize,
+    /// Blocks never written since the segment is created or cleaned.
+    pub empty_blocks: usize,
+}
+
+impl SegmentUsage {
+    fn from_segment(segment: &Segment) -> Self {
+        let nblocks = segment.nblocks();
+        // The counters are hints which may drift, e.g., by the allocations
+        // replayed on recovery, so they are clamped to the segment
+        let free_blocks = segment.free_space().min(nblocks);
+        let live_blocks = nblocks - free_blocks;
+        let invalid_blocks = nblocks
+            .saturating_sub(segment.num_valid_blocks())
+            .min(free_blocks);
+        Self {
+            segment_id: segment.segment_id(),
+            nblocks,
+            live_blocks,
+            invalid_blocks,
+            empty_blocks: free_blocks - invalid_blocks,
+        }
+    }
+
+    /// Returns the fraction of live blocks.
+    pub fn utilization(&self) -> f64 {
+        self.live_blocks as f64 / self.nblocks as f64
+    }
+
+    /// Returns the fraction of invalid blocks, which GC compares with its threshold.
+    pub fn invalid_fraction(&self) -> f64 {
+        self.invalid_blocks as f64 / self.nblocks as f64
+    }
+}
+
+/// Number of buckets in the histogram of invalid-block fractions,
+/// the i-th bucket counts segments whose fraction is in `[i / N, (i + 1) / N)`.
+pub const INVALID_HIST_BUCKETS: usize = 10;
+
+/// A report of the space usage and fragmentation of all segments.
+#[derive(Clone, Debug)]
+pub struct FragmentationReport {
+    /// The usage of each segment, indexed by `SegmentId`.
+    pub segments: Vec<SegmentUsage>,
+    /// The histogram of invalid-block fractions of segments.
+    pub invalid_hist: [usize; INVALID_HIST_BUCKETS],
+    /// The GC threshold that the estimation below is based on.
+    pub threshold: f64,
+    /// Number of segments that would be picked as victims at the threshold.
+    pub reclaimable_segments: usize,
+    /// Number of blocks that would be reclaimed at the threshold.
+    pub reclaimable_blocks: usize,
+}
+
+impl FragmentationReport {
+    /// Builds the report from the segment table, a segment is reclaimable
+    /// if its invalid-block fraction exceeds the GC threshold.
+    pub fn new(segment_table: &[Segment], threshold: f64) -> Self {
+        let mut report = Self {
+            segments: Vec::with_capacity(segment_table.len()),
+            invalid_hist: [0; INVALID_HIST_BUCKETS],
+            threshold,
+            reclaimable_segments: 0,
+            reclaimable_blocks: 0,
+        };
+        for segment in segment_table {
+            let usage = SegmentUsage::from_segment(segment);
+            let invalid_fraction = usage.invalid_fraction();
+            let bucket = ((invalid_fraction * INVALID_HIST_BUCKETS as f64) as usize)
+                .min(INVALID_HIST_BUCKETS - 1);
+            report.invalid_hist[bucket] += 1;
+            if invalid_fraction > threshold {
+                report.reclaimable_segments += 1;
+                report.reclaimable_blocks += usage.invalid_blocks;
+            }
+            report.segments.push(usage);
+        }
+        report
+    }
+
+    /// Returns the total number of live blocks.
+    pub fn live_blocks(&self) -> usize {
+        self.segments.iter().map(|usage| usage.live_blocks).sum()
+    }
+
+    /// Returns the total number of invalid blocks.
+    pub fn invalid_blocks(&self) -> usize {
+        self.segments.iter().map(|usage| usage.invalid_blocks).sum()
+    }
+
+    /// Returns the fraction of live blocks of the whole disk.
+    pub fn utilization(&self) -> f64 {
+        let nblocks: usize = self.segments.iter().map(|usage| usage.nblocks).sum();
+        if nblocks == 0 {
+            return 0.0;
+        }
+        self.live_blocks() as f64 / nblocks as f64
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -246,6 +484,23 @@ mod tests {
         assert_eq!(recovered_segment.free_space(), 1023);
     }
 
+    #[test]
+    fn segment_heat() {
+        let bitmap = Arc::new(Mutex::new(BitMap::repeat(true, 1024)));
+        let segment = Segment::new(0, 1024, bitmap.clone());
+        segment.set_last_write(10);
+        segment.add_writes(100);
+        segment.decay_write_count(0.5);
+        assert_eq!(segment.write_count(), 50);
+        segment.decay_write_count(2.0);
+        assert_eq!(segment.write_count(), 50);
+
+        let recovered = Segment::new(0, 1024, bitmap);
+        recovered.apply_heat(&segment.to_heat(1));
+        assert_eq!(recovered.last_write(), 10);
+        assert_eq!(recovered.write_count(), 50);
+    }
+
     #[test]
     fn recover_multi_segments() {
         let bitmap = Arc::new(Mutex::new(BitMap::repeat(true, 3 * 1024)));
@@ -275,4 +530,61 @@ mod tests {
         assert_eq!(recovered_segments[2].num_valid_blocks(), 1024);
         assert_eq!(recovered_segments[2].free_space(), 1020);
     }
+
+    #[test]
+    fn fragmentation_report() {
+        let bitmap = Arc::new(Mutex::new(BitMap::repeat(true, 3 * 1024)));
+        let segments = vec![
+            Segment::new(0, 1024, bitmap.clone()),
+            Segment::new(1, 1024, bitmap.clone()),
+            Segment::new(2, 1024, bitmap.clone()),
+        ];
+        // Segment 0: fully written, 700 blocks invalidated
+        segments[0].mark_alloc_batch(1024);
+        segments[0].mark_deallocated_batch(700);
+        // Segment 1: 512 blocks written, 100 blocks invalidated
+        segments[1].mark_alloc_batch(512);
+        segments[1].mark_deallocated_batch(100);
+        // Segment 2: empty
+
+        let report = FragmentationReport::new(&segments, 0.6);
+        assert_eq!(
+            report.segments[0],
+            SegmentUsage {
+                segment_id: 0,
+                nblocks: 1024,
+                live_blocks: 324,
+                invalid_blocks: 700,
+                empty_blocks: 0,
+            }
+        );
+        assert_eq!(report.segments[1].live_blocks, 412);
+        assert_eq!(report.segments[1].empty_blocks, 512);
+        assert_eq!(report.segments[2].empty_blocks, 1024);
+        assert_eq!(report.invalid_hist[0], 2);
+        assert_eq!(report.invalid_hist[6], 1);
+        assert_eq!(report.reclaimable_segments, 1);
+        assert_eq!(report.reclaimable_blocks, 700);
+        assert_eq!(report.invalid_blocks(), 800);
+        assert_eq!(report.live_blocks(), 736);
+
+        let report = FragmentationReport::new(&segments, 0.05);
+        assert_eq!(report.reclaimable_segments, 2);
+        assert_eq!(report.reclaimable_blocks, 800);
+
+        // The drifted counters, e.g., a deallocation of a block whose
+        // allocation is not counted, never underflow
+        segments[2].mark_deallocated();
+        let report = FragmentationReport::new(&segments, 0.6);
+        assert_eq!(
+            report.segments[2],
+            SegmentUsage {
+                segment_id: 2,
+                nblocks: 1024,
+                live_blocks: 0,
+                invalid_blocks: 1,
+                empty_blocks: 1023,
+            }
+        );
+    }
 }
@@ -0,0 +1,435 @@
+//! A unified export of the statistics for plotting.
+//!
+//! A `StatsExport` snapshots the WAF, cost, GC, cache and block I/O queue
+//! statistics at a point in time. The difference between two snapshots (see
+//! `StatsExport::since()`) gives the statistics of a phase in between, e.g.,
+//! a benchmark task, so that a harness emits per-phase statistics without
+//! resetting the counters. The snapshots are serialized via `serde` to JSON,
+//! or to CSV with one row per phase and one column per (flattened) field.
+use super::bio_stats::{BioStatsSnapshot, BioTypeSnapshot, BIO_STATS};
+use super::cache_stats::{CacheTier, CacheTierSnapshot, CACHE_STATS};
+use super::cost_stats::{COST_L2, COST_L3};
+use super::disk_stats::DiskStats;
+use super::gc_stats::{GcStatsSnapshot, GC_STATS};
+use super::waf_stats::{WafStats, WAF_STATS};
+use crate::prelude::*;
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// A snapshot of all the statistics, or the difference between two.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct StatsExport {
+    pub waf: WafExport,
+    pub cost: CostExport,
+    pub gc: GcExport,
+    pub cache: CacheExport,
+    pub queue: QueueExport,
+}
+
+/// The WAF statistics.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct WafExport {
+    pub logical_bytes: u64,
+    pub physical_bytes: u64,
+    pub waf: f64,
+}
+
+/// The cost statistics, in the ticks of `clock` (see `CostClock`), and the
+/// probes of Bloom filters.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CostExport {
+    pub clock: &'static str,
+    pub logical_block_table: u64,
+    pub block_io: u64,
+    pub encryption: u64,
+    pub allocation: u64,
+    pub wal: u64,
+    pub memtable: u64,
+    pub compaction: u64,
+    pub sstable_lookup: u64,
+    pub filter_negatives: u64,
+    pub filter_positives: u64,
+    pub filter_false_positives: u64,
+}
+
+/// The GC statistics.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GcExport {
+    pub rounds: u64,
+    pub failed_rounds: u64,
+    /// A gauge, taken from the later snapshot in a difference.
+    pub consecutive_failed_rounds: u64,
+    pub missing_reverse_entries: u64,
+    pub missing_records: u64,
+}
+
+/// The statistics of the cache tiers.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CacheExport {
+    pub sst_block: CacheTierExport,
+    pub log_block: CacheTierExport,
+}
+
+/// The statistics of a cache tier.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CacheTierExport {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub hit_rate: f64,
+}
+
+/// The statistics of the block I/O queue.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct QueueExport {
+    pub read: QueueTypeExport,
+    /// The latency-critical reads, not counted in `read`.
+    pub urgent_read: QueueTypeExport,
+    pub write: QueueTypeExport,
+    pub sync: QueueTypeExport,
+    pub flush: QueueTypeExport,
+    /// A gauge, taken from the later snapshot in a difference.
+    pub max_depth: u64,
+    /// The average since the creation (or reset) of the statistics, taken
+    /// from the later snapshot in a difference.
+    pub avg_depth: f64,
+    pub read_batches: u64,
+    pub read_batch_records: u64,
+    pub write_batches: u64,
+    pub write_batch_records: u64,
+}
+
+/// The statistics of the block I/O requests of a type.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct QueueTypeExport {
+    pub count: u64,
+    pub queued_cycles: u64,
+    pub service_cycles: u64,
+}
+
+impl StatsExport {
+    /// Takes a snapshot of the statistics of a `SwornDisk` instance, with
+    /// the global cost and cache statistics, which no instance owns.
+    pub fn take(stats: &DiskStats) -> Self {
+        Self::take_from(stats.waf(), stats.gc().get_stats(), stats.bio().get_stats())
+    }
+
+    /// Takes a snapshot of the global statistics.
+    pub fn take_global() -> Self {
+        Self::take_from(&WAF_STATS, GC_STATS.get_stats(), BIO_STATS.get_stats())
+    }
+
+    fn take_from(waf: &WafStats, gc: GcStatsSnapshot, bio: BioStatsSnapshot) -> Self {
+        let (l3, l2) = (COST_L3.get_stats(), COST_L2.get_stats());
+        let filter = COST_L2.get_filter_stats();
+        Self {
+            waf: WafExport {
+                logical_bytes: waf.get_logical(),
+                physical_bytes: waf.get_physical(),
+                waf: waf.waf(),
+            },
+            cost: CostExport {
+                clock: l3.clock.name(),
+                logical_block_table: l3.logical_block_table,
+                block_io: l3.block_io,
+                encryption: l3.encryption,
+                allocation: l3.allocation,
+                wal: l2.wal,
+                memtable: l2.memtable,
+                compaction: l2.compaction,
+                sstable_lookup: l2.sstable_lookup,
+                filter_negatives: filter.negatives,
+                filter_positives: filter.positives,
+                filter_false_positives: filter.false_positives,
+            },
+            gc: GcExport {
+                rounds: gc.num_rounds,
+                failed_rounds: gc.num_failed_rounds,
+                consecutive_failed_rounds: gc.consecutive_failed_rounds,
+                missing_reverse_entries: gc.missing_reverse_entries,
+                missing_records: gc.missing_records,
+            },
+            cache: CacheExport {
+                sst_block: CACHE_STATS.get_stats(CacheTier::SstBlock).into(),
+                log_block: CACHE_STATS.get_stats(CacheTier::LogBlock).into(),
+            },
+            queue: QueueExport {
+                read: (&bio.read).into(),
+                urgent_read: (&bio.urgent_read).into(),
+                write: (&bio.write).into(),
+                sync: (&bio.sync).into(),
+                flush: (&bio.flush).into(),
+                max_depth: bio.max_queue_depth,
+                avg_depth: bio.avg_queue_depth,
+                read_batches: bio.read_batches.num_batches,
+                read_batch_records: bio.read_batches.num_records,
+                write_batches: bio.write_batches.num_batches,
+                write_batch_records: bio.write_batches.num_records,
+            },
+        }
+    }
+
+    /// The statistics since the `earlier` snapshot. The counters are
+    /// subtracted (saturating at zero if reset in between), and the ratios
+    /// are recomputed from the differences.
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            waf: self.waf.since(&earlier.waf),
+            cost: self.cost.since(&earlier.cost),
+            gc: self.gc.since(&earlier.gc),
+            cache: CacheExport {
+                sst_block: self.cache.sst_block.since(&earlier.cache.sst_block),
+                log_block: self.cache.log_block.since(&earlier.cache.log_block),
+            },
+            queue: self.queue.since(&earlier.queue),
+        }
+    }
+
+    /// Serializes to JSON.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|_| serialize_error())
+    }
+
+    /// Serializes the statistics of the named phases to a JSON array of
+    /// `{ "phase": <name>, "stats": <stats> }`.
+    pub fn phases_to_json(phases: &[(&str, StatsExport)]) -> Result<String> {
+        let phases: Vec<Value> = phases
+            .iter()
+            .map(|(name, stats)| {
+                let stats = serde_json::to_value(stats).map_err(|_| serialize_error())?;
+                Ok(serde_json::json!({ "phase": name, "stats": stats }))
+            })
+            .collect::<Result<_>>()?;
+        serde_json::to_string_pretty(&phases).map_err(|_| serialize_error())
+    }
+
+    /// Serializes the statistics of the named phases to CSV, with a header
+    /// of `phase` and the flattened field names, e.g., `cache.sst_block.hits`.
+    pub fn phases_to_csv(phases: &[(&str, StatsExport)]) -> Result<String> {
+        let mut csv = String::new();
+        for (nth, (name, stats)) in phases.iter().enumerate() {
+            let value = serde_json::to_value(stats).map_err(|_| serialize_error())?;
+            let mut fields = Vec::new();
+            flatten("", &value, &mut fields);
+            if nth == 0 {
+                csv.push_str("phase");
+                for (key, _) in fields.iter() {
+                    csv.push(',');
+                    csv.push_str(key);
+                }
+                csv.push('\n');
+            }
+            csv.push_str(&csv_field(name));
+            for (_, field) in fields.iter() {
+                csv.push(',');
+                csv.push_str(field);
+            }
+            csv.push('\n');
+        }
+        Ok(csv)
+    }
+}
+
+impl WafExport {
+    fn since(&self, earlier: &Self) -> Self {
+        let logical_bytes = self.logical_bytes.saturating_sub(earlier.logical_bytes);
+        let physical_bytes = self.physical_bytes.saturating_sub(earlier.physical_bytes);
+        let waf = if logical_bytes > 0 {
+            physical_bytes as f64 / logical_bytes as f64
+        } else {
+            0.0
+        };
+        Self {
+            logical_bytes,
+            physical_bytes,
+            waf,
+        }
+    }
+}
+
+impl CostExport {
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            clock: self.clock,
+            logical_block_table: self
+                .logical_block_table
+                .saturating_sub(earlier.logical_block_table),
+            block_io: self.block_io.saturating_sub(earlier.block_io),
+            encryption: self.encryption.saturating_sub(earlier.encryption),
+            allocation: self.allocation.saturating_sub(earlier.allocation),
+            wal: self.wal.saturating_sub(earlier.wal),
+            memtable: self.memtable.saturating_sub(earlier.memtable),
+            compaction: self.compaction.saturating_sub(earlier.compaction),
+            sstable_lookup: self.sstable_lookup.saturating_sub(earlier.sstable_lookup),
+            filter_negatives: self
+                .filter_negatives
+                .saturating_sub(earlier.filter_negatives),
+            filter_positives: self
+                .filter_positives
+                .saturating_sub(earlier.filter_positives),
+            filter_false_positives: self
+                .filter_false_positives
+                .saturating_sub(earlier.filter_false_positives),
+        }
+    }
+}
+
+impl GcExport {
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            rounds: self.rounds.saturating_sub(earlier.rounds),
+            failed_rounds: self.failed_rounds.saturating_sub(earlier.failed_rounds),
+            consecutive_failed_rounds: self.consecutive_failed_rounds,
+            missing_reverse_entries: self
+                .missing_reverse_entries
+                .saturating_sub(earlier.missing_reverse_entries),
+            missing_records: self.missing_records.saturating_sub(earlier.missing_records),
+        }
+    }
+}
+
+impl CacheTierExport {
+    fn since(&self, earlier: &Self) -> Self {
+        CacheTierSnapshot {
+            hits: self.hits.saturating_sub(earlier.hits),
+            misses: self.misses.saturating_sub(earlier.misses),
+            evictions: self.evictions.saturating_sub(earlier.evictions),
+        }
+        .into()
+    }
+}
+
+impl From<CacheTierSnapshot> for CacheTierExport {
+    fn from(snapshot: CacheTierSnapshot) -> Self {
+        Self {
+            hits: snapshot.hits,
+            misses: snapshot.misses,
+            evictions: snapshot.evictions,
+            hit_rate: snapshot.hit_rate(),
+        }
+    }
+}
+
+impl QueueExport {
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            read: self.read.since(&earlier.read),
+            urgent_read: self.urgent_read.since(&earlier.urgent_read),
+            write: self.write.since(&earlier.write),
+            sync: self.sync.since(&earlier.sync),
+            flush: self.flush.since(&earlier.flush),
+            max_depth: self.max_depth,
+            avg_depth: self.avg_depth,
+            read_batches: self.read_batches.saturating_sub(earlier.read_batches),
+            read_batch_records: self
+                .read_batch_records
+                .saturating_sub(earlier.read_batch_records),
+            write_batches: self.write_batches.saturating_sub(earlier.write_batches),
+            write_batch_records: self
+                .write_batch_records
+                .saturating_sub(earlier.write_batch_records),
+        }
+    }
+}
+
+impl QueueTypeExport {
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            count: self.count.saturating_sub(earlier.count),
+            queued_cycles: self.queued_cycles.saturating_sub(earlier.queued_cycles),
+            service_cycles: self.service_cycles.saturating_sub(earlier.service_cycles),
+        }
+    }
+}
+
+impl From<&BioTypeSnapshot> for QueueTypeExport {
+    fn from(snapshot: &BioTypeSnapshot) -> Self {
+        Self {
+            count: snapshot.count,
+            queued_cycles: snapshot.queued_cycles,
+            service_cycles: snapshot.service_cycles,
+        }
+    }
+}
+
+fn serialize_error() -> Error {
+    Error::with_msg(InvalidArgs, "failed to serialize statistics")
+}
+
+/// Flattens a JSON value into the fields named by their dotted paths.
+fn flatten(path: &str, value: &Value, fields: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    alloc::format!("{path}.{key}")
+                };
+                flatten(&path, value, fields);
+            }
+        }
+        Value::String(s) => fields.push((path.to_string(), csv_field(s))),
+        _ => fields.push((path.to_string(), value.to_string())),
+    }
+}
+
+/// Quotes a CSV field if needed.
+fn csv_field(s: &str) -> String {
+    if !s.contains([',', '"', '\n']) {
+        return s.to_string();
+    }
+    alloc::format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatsExport;
+
+    #[test]
+    fn stats_export_diff() {
+        let mut earlier = StatsExport::default();
+        earlier.waf.logical_bytes = 100;
+        earlier.waf.physical_bytes = 150;
+        earlier.cache.sst_block.hits = 10;
+        earlier.gc.consecutive_failed_rounds = 2;
+        let mut later = earlier.clone();
+        later.waf.logical_bytes = 300;
+        later.waf.physical_bytes = 750;
+        later.cache.sst_block.hits = 40;
+        later.cache.sst_block.misses = 10;
+        later.gc.rounds = 3;
+        later.gc.consecutive_failed_rounds = 0;
+
+        let diff = later.since(&earlier);
+        assert_eq!(
+            (
+                diff.waf.logical_bytes,
+                diff.waf.physical_bytes,
+                diff.waf.waf
+            ),
+            (200, 600, 3.0)
+        );
+        assert_eq!(diff.cache.sst_block.hits, 30);
+        assert_eq!(diff.cache.sst_block.hit_rate, 0.75);
+        assert_eq!((diff.gc.rounds, diff.gc.consecutive_failed_rounds), (3, 0));
+        // Saturates if reset in between
+        assert_eq!(earlier.since(&later).waf.logical_bytes, 0);
+
+        let json = diff.to_json().unwrap();
+        assert!(json.contains("\"logical_bytes\": 200"));
+
+        let csv = StatsExport::phases_to_csv(&[("load", later), ("run, 1", diff)]).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let header: Vec<_> = lines[0].split(',').collect();
+        assert_eq!(header[0], "phase");
+        let nth = header
+            .iter()
+            .position(|key| *key == "waf.logical_bytes")
+            .unwrap();
+        assert_eq!(lines[1].split(',').nth(nth), Some("300"));
+        assert!(lines[2].starts_with("\"run, 1\","));
+    }
+}
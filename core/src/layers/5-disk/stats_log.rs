@@ -0,0 +1,175 @@
+//! Persistent statistics.
+//!
+//! The WAF and cost statistics are in-memory counters, which reset on every
+//! restart. If `Config::persist_stats` is on, a snapshot of the statistics
+//! (the WAF of the disk itself and the global cost statistics) is
+//! persisted to the `STAT` bucket of `TxLogStore` on each sync, and restored
+//! on open, so that the statistics accumulate across restarts. Without the
+//! `stats` feature, there is nothing to persist or restore.
+//!
+//! The costs are persisted in the ticks of their `CostClock`, so they only
+//! accumulate correctly across restarts with the same `Config::cost_clock`.
+use super::cost_stats::{cost_clock, CostL2Stats, CostL3Stats, COST_L2, COST_L3, STATS_ENABLED};
+use super::disk_stats::DiskStats;
+use crate::layers::bio::{BlockSet, Buf, BufRef};
+use crate::layers::log::TxLogStore;
+use crate::prelude::*;
+
+use core::mem::size_of;
+use pod::Pod;
+
+/// The bucket name of statistics snapshots.
+const BUCKET_STATS: &str = "STAT";
+
+/// A snapshot of the statistics.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug)]
+struct StatsSnapshot {
+    waf_logical: u64,
+    waf_physical: u64,
+    /// Logical block table, block I/O, encryption and allocation.
+    cost_l3: [u64; 4],
+    /// WAL, MemTable, compaction and SSTable lookup.
+    cost_l2: [u64; 4],
+}
+
+impl StatsSnapshot {
+    /// Takes a snapshot of the statistics.
+    fn take(stats: &DiskStats) -> Self {
+        let l3 = COST_L3.get_stats();
+        let l2 = COST_L2.get_stats();
+        Self {
+            waf_logical: stats.waf().get_logical(),
+            waf_physical: stats.waf().get_physical(),
+            cost_l3: [
+                l3.logical_block_table,
+                l3.block_io,
+                l3.encryption,
+                l3.allocation,
+            ],
+            cost_l2: [l2.wal, l2.memtable, l2.compaction, l2.sstable_lookup],
+        }
+    }
+
+    /// Restores the statistics from the snapshot.
+    fn restore(&self, stats: &DiskStats) {
+        stats.record_waf(|waf| waf.restore(self.waf_logical, self.waf_physical));
+        let [logical_block_table, block_io, encryption, allocation] = self.cost_l3;
+        COST_L3.restore(&CostL3Stats {
+            logical_block_table,
+            block_io,
+            encryption,
+            allocation,
+            total: self.cost_l3.iter().sum(),
+            clock: cost_clock(),
+        });
+        let [wal, memtable, compaction, sstable_lookup] = self.cost_l2;
+        COST_L2.restore(&CostL2Stats {
+            wal,
+            memtable,
+            compaction,
+            sstable_lookup,
+            total: self.cost_l2.iter().sum(),
+            clock: cost_clock(),
+        });
+    }
+}
+
+/// Persists a snapshot of the statistics to the `STAT` bucket,
+/// replacing the older snapshot (if any).
+pub(super) fn persist_stats<D: BlockSet + 'static>(
+    store: &Arc<TxLogStore<D>>,
+    stats: &DiskStats,
+) -> Result<()> {
+    if !STATS_ENABLED {
+        return Ok(());
+    }
+    let mut buf = Buf::alloc(1)?;
+    buf.as_mut_slice()[..size_of::<StatsSnapshot>()]
+        .copy_from_slice(StatsSnapshot::take(stats).as_bytes());
+
+    let mut tx = store.new_tx();
+    let res: Result<_> = tx.context(|| {
+        if let Ok(stats_log_ids) = store.list_logs_in(BUCKET_STATS) {
+            for stats_log_id in stats_log_ids {
+                store.delete_log(stats_log_id)?;
+            }
+        }
+        let stats_log = store.create_log(BUCKET_STATS)?;
+        stats_log.append(BufRef::try_from(buf.as_slice()).unwrap())
+    });
+    if res.is_err() {
+        tx.abort();
+        return_errno_with_msg!(TxAborted, "persist statistics TX aborted");
+    }
+    tx.commit()
+}
+
+/// Restores the statistics from the latest snapshot in the `STAT` bucket.
+/// Nothing is restored if there is no snapshot.
+pub(super) fn restore_stats<D: BlockSet + 'static>(
+    store: &Arc<TxLogStore<D>>,
+    stats: &DiskStats,
+) -> Result<()> {
+    if !STATS_ENABLED {
+        return Ok(());
+    }
+    let mut tx = store.new_tx();
+    let res: Result<_> = tx.context(|| {
+        let stats_log = match store.open_log_in(BUCKET_STATS) {
+            Ok(stats_log) => stats_log,
+            Err(e) if e.errno() == NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut buf = Buf::alloc(1)?;
+        stats_log.read(0 as BlockId, buf.as_mut())?;
+        Ok(Some(StatsSnapshot::from_bytes(
+            &buf.as_slice()[..size_of::<StatsSnapshot>()],
+        )))
+    });
+    let snapshot = match res {
+        Ok(snapshot) => snapshot,
+        Err(_) => {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "restore statistics TX aborted");
+        }
+    };
+    tx.commit()?;
+
+    if let Some(snapshot) = snapshot {
+        snapshot.restore(stats);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::bio::MemDisk;
+    use crate::os::AeadKey as Key;
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats_persist_and_restore() -> Result<()> {
+        let mem_disk = MemDisk::create(4 * 1024)?;
+        let root_key = Key::random();
+        let store = Arc::new(TxLogStore::format(mem_disk.clone(), root_key.clone())?);
+        // Nothing to restore on a fresh store
+        let stats = DiskStats::new(false);
+        restore_stats(&store, &stats)?;
+
+        stats.waf().restore(4096, 3 * 4096);
+        persist_stats(&store, &stats)?;
+        stats.waf().restore(8192, 5 * 4096);
+        persist_stats(&store, &stats)?;
+        store.sync()?;
+        drop(store);
+
+        let stats = DiskStats::new(false);
+        let store = Arc::new(TxLogStore::recover(mem_disk, root_key)?);
+        restore_stats(&store, &stats)?;
+        assert_eq!(stats.waf().get_logical(), 8192);
+        assert_eq!(stats.waf().get_physical(), 5 * 4096);
+        Ok(())
+    }
+}
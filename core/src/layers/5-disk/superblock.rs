@@ -0,0 +1,651 @@
+//! Superblock of `SwornDisk`.
+//!
+//! The superblock resides in the last two blocks of the underlying disk and
+//! records the per-disk metadata that must be known before any other
+//! structure can be opened, e.g., the format version, the crypto mode of
+//! user data blocks, the disk layout, the LSM parameters, the freshness
+//! counter and the wrapped root key.
+//!
+//! The two blocks are shadow copies written alternately, each update
+//! overwrites the older copy with a greater generation. The newest valid
+//! copy is taken on open, so a torn update falls back to the previous one.
+//!
+//! Disks of `LEGACY_FORMAT_VERSION` keep a single copy in the last block,
+//! which is probed if no shadow copy is found. Their superblocks are still
+//! persisted there, since the blocks before it are taken by their layouts.
+use super::config::BlockCryptoMode;
+use super::layout::DiskLayout;
+use crate::layers::bio::{BlockSet, Buf};
+use crate::layers::lsm::LsmParams;
+use crate::os::{Aead, AeadIv as Iv, AeadKey as Key, AeadMac as Mac};
+use crate::prelude::*;
+use crate::util::{sha256, SHA256_SIZE};
+
+use core::mem::size_of;
+use pod::Pod;
+
+/// Superblock of `SwornDisk`.
+///
+/// On-disk layout of each copy (one block):
+/// ```text
+/// ---------------------------------------------------------------------------------
+/// | Checksum | Generation | WrappedKey | Iv | Mac | Encrypted `SuperblockMeta` | Padding |
+/// ---------------------------------------------------------------------------------
+/// ```
+/// The checksum is the SHA-256 of the rest of the block, which tells a torn
+/// copy without the root key. The generation and the wrapped root key are
+/// stored in plaintext since they are required to obtain the root key, they
+/// are authenticated as the associated data.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Superblock {
+    meta: SuperblockMeta,
+    wrapped_root_key: WrappedKey,
+    generation: u64,
+}
+
+/// The secret part of the superblock, encrypted with the root key.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug)]
+struct SuperblockMeta {
+    magic: u64,
+    /// The version of the on-disk format, see `FORMAT_VERSION`.
+    version: u64,
+    /// The features which the disk is created with, see `FEATURE_GC`.
+    features: u64,
+    crypto_mode: u64,
+    /// The per-disk data key used in `BlockCryptoMode::PerBlockNonce`
+    /// and `BlockCryptoMode::DerivedKey`.
+    data_key: Key,
+    /// Nonces (or epochs) below this limit may have been used, new ones must start from it.
+    nonce_limit: u64,
+    /// The freshness counter to detect rollback, see `TrustedCounter`.
+    freshness: u64,
+    layout: DiskLayout,
+    /// The parameters of the logical block table and the reverse index table.
+    lsm_params: LsmParams,
+}
+const MAGIC_NUMBER: u64 = 0x5357_4f52_4e44_534b;
+
+/// The version of the on-disk format, bumped on each incompatible change.
+/// Disks of newer versions are refused.
+const FORMAT_VERSION: u64 = 1;
+/// The version of the disks created before the shadow copies, whose superblock
+/// is a single copy in the last block, see `LegacySuperblockMeta`.
+pub(super) const LEGACY_FORMAT_VERSION: u64 = 0;
+
+/// The secret part of a superblock of `LEGACY_FORMAT_VERSION`, which records
+/// neither the version nor the features. Its `LsmParams` are laid out the
+/// same as the current ones, with a zero value format.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug)]
+struct LegacySuperblockMeta {
+    magic: u64,
+    crypto_mode: u64,
+    /// The size (in bytes) of the blocks, which is always `BLOCK_SIZE`.
+    block_size: u64,
+    data_key: Key,
+    nonce_limit: u64,
+    freshness: u64,
+    layout: DiskLayout,
+    lsm_params: LsmParams,
+}
+
+/// The disk is created with GC, i.e., the reverse index table is reserved.
+pub const FEATURE_GC: u64 = 1 << 0;
+/// The reverse index table is stored as per-segment blobs rather than
+/// a `TxLsmTree`, see `ReverseIndexKind::SegmentBlobs`.
+pub const FEATURE_SEGMENT_REVERSE_INDEX: u64 = 1 << 1;
+/// The user data blocks are bound to their LBAs and the disk by the
+/// associated data of AEAD, see `BlockAad`. Disks without it are read
+/// with empty associated data.
+pub const FEATURE_LBA_AAD: u64 = 1 << 2;
+/// The records of the logical block table only store the low 8 bytes of
+/// the per-block secret, i.e., the nonce (or epoch), see `RecordValue`.
+/// Disks in `BlockCryptoMode::PerBlockNonce` without it store full-size ones.
+pub const FEATURE_COMPACT_RECORDS: u64 = 1 << 3;
+/// The records of the logical block table store the sync IDs which commit
+/// them, and the discards are logged with theirs (see `DiscardLog`), so
+/// that the blocks changed since a sync can be told.
+pub const FEATURE_SYNC_ID_RECORDS: u64 = 1 << 4;
+/// The user data blocks may be shared by the records of several LBAs, whose
+/// reference counts are logged (see `RefCountTable`). Such a disk is created
+/// without `FEATURE_LBA_AAD` and GC, see `Config::shared_blocks`.
+pub const FEATURE_SHARED_BLOCKS: u64 = 1 << 5;
+/// The records of the logical block table written by a sequential run are
+/// stored as extents in SSTs, i.e., only the MAC (and the key in
+/// `BlockCryptoMode::RandomKey`) of each record but the first one.
+pub const FEATURE_EXTENT_RECORDS: u64 = 1 << 6;
+/// The features known by this version, disks with unknown ones are refused.
+const SUPPORTED_FEATURES: u64 = FEATURE_GC
+    | FEATURE_SEGMENT_REVERSE_INDEX
+    | FEATURE_LBA_AAD
+    | FEATURE_COMPACT_RECORDS
+    | FEATURE_SYNC_ID_RECORDS
+    | FEATURE_SHARED_BLOCKS
+    | FEATURE_EXTENT_RECORDS;
+
+/// The identity of a disk, which is bound to its user data blocks.
+pub(super) type DiskId = [u8; DISK_ID_SIZE];
+/// The size (in bytes) of `DiskId`.
+pub(super) const DISK_ID_SIZE: usize = 16;
+
+/// The number of blocks of the superblock, i.e., the two shadow copies.
+pub const SUPERBLOCK_NBLOCKS: usize = 2;
+
+/// The root key wrapped by a `RootKeyProvider`.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug)]
+pub(super) struct WrappedKey {
+    len: u64,
+    blob: [u8; WRAPPED_KEY_MAX_SIZE],
+}
+/// The maximum size of a wrapped root key.
+pub const WRAPPED_KEY_MAX_SIZE: usize = 1024;
+
+impl Superblock {
+    const META_SIZE: usize = size_of::<SuperblockMeta>();
+    const LEGACY_META_SIZE: usize = size_of::<LegacySuperblockMeta>();
+    const WRAPPED_KEY_SIZE: usize = size_of::<WrappedKey>();
+    const IV_SIZE: usize = size_of::<Iv>();
+    const MAC_SIZE: usize = size_of::<Mac>();
+
+    const GENERATION_OFFSET: usize = SHA256_SIZE;
+    const WRAPPED_KEY_OFFSET: usize = Self::GENERATION_OFFSET + size_of::<u64>();
+    const IV_OFFSET: usize = Self::WRAPPED_KEY_OFFSET + Self::WRAPPED_KEY_SIZE;
+    const MAC_OFFSET: usize = Self::IV_OFFSET + Self::IV_SIZE;
+    const META_OFFSET: usize = Self::MAC_OFFSET + Self::MAC_SIZE;
+
+    /// Creates a new `Superblock` with the given features, crypto mode,
+    /// disk layout and LSM parameters.
+    pub fn new(
+        features: u64,
+        crypto_mode: BlockCryptoMode,
+        layout: DiskLayout,
+        lsm_params: LsmParams,
+    ) -> Self {
+        Self {
+            meta: SuperblockMeta {
+                magic: MAGIC_NUMBER,
+                version: FORMAT_VERSION,
+                features,
+                crypto_mode: crypto_mode as u64,
+                data_key: Key::random(),
+                nonce_limit: 0,
+                freshness: 0,
+                layout,
+                lsm_params,
+            },
+            wrapped_root_key: WrappedKey::new_zeroed(),
+            generation: 0,
+        }
+    }
+
+    /// Returns the number of blocks taken by the superblock at the end of
+    /// the disk, which is one for the disks of `LEGACY_FORMAT_VERSION`.
+    pub fn nblocks(&self) -> usize {
+        if self.meta.version == LEGACY_FORMAT_VERSION {
+            1
+        } else {
+            SUPERBLOCK_NBLOCKS
+        }
+    }
+
+    /// Returns whether the disk is created with the given feature.
+    pub fn has_feature(&self, feature: u64) -> bool {
+        self.meta.features & feature == feature
+    }
+
+    /// Clears a feature, e.g., to emulate a disk created by an older version.
+    /// The caller should persist the superblock afterwards.
+    #[cfg(test)]
+    pub fn clear_feature(&mut self, feature: u64) {
+        self.meta.features &= !feature;
+    }
+
+    /// Returns the crypto mode of user data blocks.
+    pub fn crypto_mode(&self) -> BlockCryptoMode {
+        BlockCryptoMode::from(self.meta.crypto_mode)
+    }
+
+    /// Returns the disk layout.
+    pub fn layout(&self) -> &DiskLayout {
+        &self.meta.layout
+    }
+
+    /// Sets the disk layout, the caller should persist the superblock afterwards.
+    pub fn set_layout(&mut self, layout: DiskLayout) {
+        self.meta.layout = layout;
+    }
+
+    /// Returns the parameters of the LSM trees.
+    pub fn lsm_params(&self) -> &LsmParams {
+        &self.meta.lsm_params
+    }
+
+    /// Sets the format version, e.g., to emulate a disk created by an older
+    /// version. The caller should persist the superblock afterwards.
+    #[cfg(test)]
+    pub fn set_version(&mut self, version: u64) {
+        self.meta.version = version;
+    }
+
+    /// Sets the parameters of the LSM trees, the caller should persist the
+    /// superblock afterwards.
+    #[cfg(test)]
+    pub fn set_lsm_params(&mut self, lsm_params: LsmParams) {
+        self.meta.lsm_params = lsm_params;
+    }
+
+    /// Returns the per-disk data key.
+    pub fn data_key(&self) -> &Key {
+        &self.meta.data_key
+    }
+
+    /// Returns the identity of the disk, derived from the per-disk data key,
+    /// which is random and never changes during the lifetime of the disk.
+    pub fn disk_id(&self) -> DiskId {
+        let mut disk_id = [0u8; DISK_ID_SIZE];
+        disk_id.copy_from_slice(&sha256(self.meta.data_key.as_bytes())[..DISK_ID_SIZE]);
+        disk_id
+    }
+
+    /// Returns the persisted nonce limit.
+    pub fn nonce_limit(&self) -> u64 {
+        self.meta.nonce_limit
+    }
+
+    /// Sets the nonce limit, the caller should persist the superblock afterwards.
+    pub fn set_nonce_limit(&mut self, nonce_limit: u64) {
+        self.meta.nonce_limit = nonce_limit;
+    }
+
+    /// Returns the sealed freshness counter.
+    pub fn freshness(&self) -> u64 {
+        self.meta.freshness
+    }
+
+    /// Sets the freshness counter, the caller should persist the superblock afterwards.
+    pub fn set_freshness(&mut self, freshness: u64) {
+        self.meta.freshness = freshness;
+    }
+
+    /// Sets the wrapped root key, the caller should persist the superblock afterwards.
+    pub fn set_wrapped_root_key(&mut self, wrapped: &[u8]) -> Result<()> {
+        self.wrapped_root_key = WrappedKey::from_slice(wrapped)?;
+        Ok(())
+    }
+
+    /// Reads the wrapped root key of the newest copy on the disk (or the
+    /// copy of `LEGACY_FORMAT_VERSION` if none), no root key is required.
+    pub fn read_wrapped_root_key<D: BlockSet>(disk: &D) -> Result<Vec<u8>> {
+        let mut blocks = Buf::alloc(SUPERBLOCK_NBLOCKS)?;
+        disk.read(0, blocks.as_mut())?;
+        let newest = blocks
+            .as_slice()
+            .chunks(BLOCK_SIZE)
+            .filter(|block| Self::is_intact(block))
+            .max_by_key(|block| Self::generation_of(block));
+        let wrapped_root_key = match newest {
+            Some(newest) => WrappedKey::from_bytes(
+                &newest
+                    [Self::WRAPPED_KEY_OFFSET..Self::WRAPPED_KEY_OFFSET + Self::WRAPPED_KEY_SIZE],
+            ),
+            None => WrappedKey::from_bytes(
+                &Self::legacy_block(blocks.as_slice())[..Self::WRAPPED_KEY_SIZE],
+            ),
+        };
+        if wrapped_root_key.is_empty() {
+            return_errno_with_msg!(NotFound, "root key is not wrapped");
+        }
+        Ok(wrapped_root_key.as_slice().to_vec())
+    }
+
+    /// Reads the newest valid copy of the `Superblock` on the disk
+    /// with the given root key. The copy of `LEGACY_FORMAT_VERSION`
+    /// is probed if there is none.
+    pub fn open<D: BlockSet>(disk: &D, root_key: &Key) -> Result<Self> {
+        let mut blocks = Buf::alloc(SUPERBLOCK_NBLOCKS)?;
+        disk.read(0, blocks.as_mut())?;
+        let superblock = blocks
+            .as_slice()
+            .chunks(BLOCK_SIZE)
+            .filter_map(|block| Self::decode(block, root_key))
+            .max_by_key(|superblock| superblock.generation)
+            .or_else(|| Self::decode_legacy(Self::legacy_block(blocks.as_slice()), root_key))
+            .ok_or_else(|| Error::with_msg(InvalidArgs, "open superblock failed"))?;
+
+        if superblock.meta.version > FORMAT_VERSION {
+            return_errno_with_msg!(Unsupported, "superblock of a newer format version");
+        }
+        if superblock.meta.features & !SUPPORTED_FEATURES != 0 {
+            return_errno_with_msg!(Unsupported, "superblock with unknown features");
+        }
+        if superblock.meta.version == LEGACY_FORMAT_VERSION
+            && superblock.crypto_mode() == BlockCryptoMode::DerivedKey
+        {
+            return_errno_with_msg!(Unsupported, "derived keys of the legacy format");
+        }
+        Ok(superblock)
+    }
+
+    /// Returns the block of the copy of `LEGACY_FORMAT_VERSION`, i.e., the last one.
+    fn legacy_block(blocks: &[u8]) -> &[u8] {
+        &blocks[(SUPERBLOCK_NBLOCKS - 1) * BLOCK_SIZE..SUPERBLOCK_NBLOCKS * BLOCK_SIZE]
+    }
+
+    /// Decodes the copy of `LEGACY_FORMAT_VERSION`, `None` if it is forged,
+    /// encrypted with another root key or not of the legacy format at all.
+    ///
+    /// On-disk layout (one block):
+    /// ```text
+    /// ---------------------------------------------------------------------
+    /// | WrappedKey | Iv | Mac | Encrypted `LegacySuperblockMeta` | Padding |
+    /// ---------------------------------------------------------------------
+    /// ```
+    /// The disk is taken as created with GC if the reverse index table is
+    /// reserved, and without any other feature.
+    fn decode_legacy(block: &[u8], root_key: &Key) -> Option<Self> {
+        let (wrapped_key_bytes, rest) = block.split_at(Self::WRAPPED_KEY_SIZE);
+        let (iv, rest) = rest.split_at(Self::IV_SIZE);
+        let (mac, rest) = rest.split_at(Self::MAC_SIZE);
+        let mut plain = [0u8; Self::LEGACY_META_SIZE];
+        Aead::new()
+            .decrypt(
+                &rest[..Self::LEGACY_META_SIZE],
+                root_key,
+                &Iv::from_bytes(iv),
+                wrapped_key_bytes,
+                &Mac::from_bytes(mac),
+                &mut plain,
+            )
+            .ok()?;
+
+        let legacy_meta = LegacySuperblockMeta::from_bytes(&plain);
+        if legacy_meta.magic != MAGIC_NUMBER {
+            return None;
+        }
+        let features = if legacy_meta.layout.reverse_index_range().is_empty() {
+            0
+        } else {
+            FEATURE_GC
+        };
+        Some(Self {
+            meta: SuperblockMeta {
+                magic: MAGIC_NUMBER,
+                version: LEGACY_FORMAT_VERSION,
+                features,
+                crypto_mode: legacy_meta.crypto_mode,
+                data_key: legacy_meta.data_key,
+                nonce_limit: legacy_meta.nonce_limit,
+                freshness: legacy_meta.freshness,
+                layout: legacy_meta.layout,
+                lsm_params: legacy_meta.lsm_params,
+            },
+            wrapped_root_key: WrappedKey::from_bytes(wrapped_key_bytes),
+            generation: 0,
+        })
+    }
+
+    /// Decodes a copy of the `Superblock`, `None` if it is torn, forged,
+    /// or encrypted with another root key.
+    fn decode(block: &[u8], root_key: &Key) -> Option<Self> {
+        if !Self::is_intact(block) {
+            return None;
+        }
+        let iv = Iv::from_bytes(&block[Self::IV_OFFSET..Self::MAC_OFFSET]);
+        let mac = Mac::from_bytes(&block[Self::MAC_OFFSET..Self::META_OFFSET]);
+        let mut plain = [0u8; Self::META_SIZE];
+        Aead::new()
+            .decrypt(
+                &block[Self::META_OFFSET..Self::META_OFFSET + Self::META_SIZE],
+                root_key,
+                &iv,
+                &block[Self::GENERATION_OFFSET..Self::IV_OFFSET],
+                &mac,
+                &mut plain,
+            )
+            .ok()?;
+
+        let meta = SuperblockMeta::from_bytes(&plain);
+        if meta.magic != MAGIC_NUMBER {
+            return None;
+        }
+        Some(Self {
+            meta,
+            wrapped_root_key: WrappedKey::from_bytes(
+                &block[Self::WRAPPED_KEY_OFFSET..Self::IV_OFFSET],
+            ),
+            generation: Self::generation_of(block),
+        })
+    }
+
+    fn is_intact(block: &[u8]) -> bool {
+        sha256(&block[SHA256_SIZE..]) == block[..SHA256_SIZE]
+    }
+
+    fn generation_of(block: &[u8]) -> u64 {
+        u64::from_le_bytes(
+            block[Self::GENERATION_OFFSET..Self::WRAPPED_KEY_OFFSET]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    /// Persists the `Superblock` on a newly created disk with the given
+    /// root key. The stale copies left on the disk (e.g., of a previous
+    /// `SwornDisk` with the same root key) are erased beforehand.
+    ///
+    /// A superblock of `LEGACY_FORMAT_VERSION` (e.g., of a disk relaid out
+    /// by `SwornDisk::grow_offline()`) is upgraded to shadow copies.
+    pub fn format<D: BlockSet>(&mut self, disk: &D, root_key: &Key) -> Result<()> {
+        if self.meta.version == LEGACY_FORMAT_VERSION {
+            self.meta.version = FORMAT_VERSION;
+        }
+        let zeroed = Buf::alloc(SUPERBLOCK_NBLOCKS)?;
+        disk.write(0, zeroed.as_ref())?;
+        self.persist(disk, root_key)
+    }
+
+    /// Persists the `Superblock` on the disk with the given root key.
+    ///
+    /// The older copy is overwritten with the next generation, so that
+    /// the newer one survives if the write is torn. The single copy of
+    /// `LEGACY_FORMAT_VERSION` is overwritten in place instead.
+    pub fn persist<D: BlockSet>(&mut self, disk: &D, root_key: &Key) -> Result<()> {
+        if self.meta.version == LEGACY_FORMAT_VERSION {
+            return self.persist_legacy(disk, root_key);
+        }
+        self.generation += 1;
+        let slot = (self.generation % SUPERBLOCK_NBLOCKS as u64) as BlockId;
+
+        let mut block = Buf::alloc(1)?;
+        let block_slice = block.as_mut_slice();
+        block_slice[Self::GENERATION_OFFSET..Self::WRAPPED_KEY_OFFSET]
+            .copy_from_slice(&self.generation.to_le_bytes());
+        block_slice[Self::WRAPPED_KEY_OFFSET..Self::IV_OFFSET]
+            .copy_from_slice(self.wrapped_root_key.as_bytes());
+
+        let iv = Iv::random();
+        let mut cipher = [0u8; Self::META_SIZE];
+        let mac = Aead::new().encrypt(
+            self.meta.as_bytes(),
+            root_key,
+            &iv,
+            &block_slice[Self::GENERATION_OFFSET..Self::IV_OFFSET],
+            &mut cipher,
+        )?;
+        block_slice[Self::META_OFFSET..Self::META_OFFSET + Self::META_SIZE]
+            .copy_from_slice(&cipher);
+        block_slice[Self::IV_OFFSET..Self::MAC_OFFSET].copy_from_slice(iv.as_bytes());
+        block_slice[Self::MAC_OFFSET..Self::META_OFFSET].copy_from_slice(mac.as_bytes());
+        let checksum = sha256(&block_slice[SHA256_SIZE..]);
+        block_slice[..SHA256_SIZE].copy_from_slice(&checksum);
+
+        disk.write(slot, block.as_ref())?;
+        disk.flush()
+    }
+
+    /// Persists the `Superblock` of `LEGACY_FORMAT_VERSION` in its last block,
+    /// see `decode_legacy()`.
+    fn persist_legacy<D: BlockSet>(&self, disk: &D, root_key: &Key) -> Result<()> {
+        let legacy_meta = LegacySuperblockMeta {
+            magic: MAGIC_NUMBER,
+            crypto_mode: self.meta.crypto_mode,
+            block_size: BLOCK_SIZE as _,
+            data_key: self.meta.data_key,
+            nonce_limit: self.meta.nonce_limit,
+            freshness: self.meta.freshness,
+            layout: self.meta.layout,
+            lsm_params: self.meta.lsm_params,
+        };
+        let iv_offset = Self::WRAPPED_KEY_SIZE;
+        let mac_offset = iv_offset + Self::IV_SIZE;
+        let meta_offset = mac_offset + Self::MAC_SIZE;
+        let mut block = Buf::alloc(1)?;
+        let block_slice = block.as_mut_slice();
+        block_slice[..iv_offset].copy_from_slice(self.wrapped_root_key.as_bytes());
+
+        let iv = Iv::random();
+        let mut cipher = [0u8; Self::LEGACY_META_SIZE];
+        let mac = Aead::new().encrypt(
+            legacy_meta.as_bytes(),
+            root_key,
+            &iv,
+            self.wrapped_root_key.as_bytes(),
+            &mut cipher,
+        )?;
+        block_slice[meta_offset..meta_offset + Self::LEGACY_META_SIZE].copy_from_slice(&cipher);
+        block_slice[iv_offset..mac_offset].copy_from_slice(iv.as_bytes());
+        block_slice[mac_offset..meta_offset].copy_from_slice(mac.as_bytes());
+
+        disk.write((SUPERBLOCK_NBLOCKS - 1) as BlockId, block.as_ref())?;
+        disk.flush()
+    }
+}
+
+impl WrappedKey {
+    fn from_slice(wrapped: &[u8]) -> Result<Self> {
+        if wrapped.is_empty() || wrapped.len() > WRAPPED_KEY_MAX_SIZE {
+            return_errno_with_msg!(InvalidArgs, "invalid size of the wrapped root key");
+        }
+        let mut wrapped_key = Self::new_zeroed();
+        wrapped_key.len = wrapped.len() as u64;
+        wrapped_key.blob[..wrapped.len()].copy_from_slice(wrapped);
+        Ok(wrapped_key)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0 || self.len as usize > WRAPPED_KEY_MAX_SIZE
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.blob[..self.len as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::bio::MemDisk;
+    use crate::layers::disk::Config;
+
+    fn new_superblock(features: u64) -> Result<Superblock> {
+        let layout = DiskLayout::new(64 * 1024, &Config::default())?;
+        Ok(Superblock::new(
+            features,
+            BlockCryptoMode::RandomKey,
+            layout,
+            LsmParams::default(),
+        ))
+    }
+
+    #[test]
+    fn superblock_shadow_copies() -> Result<()> {
+        let disk = MemDisk::create(SUPERBLOCK_NBLOCKS)?;
+        let root_key = Key::random();
+
+        // A stale copy of a higher generation is erased on format
+        let mut stale = new_superblock(0)?;
+        stale.generation = 100;
+        stale.persist(&disk, &root_key)?;
+        let mut superblock = new_superblock(FEATURE_GC)?;
+        superblock.format(&disk, &root_key)?;
+        let opened = Superblock::open(&disk, &root_key)?;
+        assert!(opened.has_feature(FEATURE_GC));
+        assert_eq!(opened.generation, superblock.generation);
+
+        for nonce_limit in 1..=3 {
+            superblock.set_nonce_limit(nonce_limit);
+            superblock.persist(&disk, &root_key)?;
+        }
+        assert_eq!(Superblock::open(&disk, &root_key)?.nonce_limit(), 3);
+
+        // A torn update falls back to the previous copy
+        let slot = (superblock.generation % SUPERBLOCK_NBLOCKS as u64) as BlockId;
+        let mut block = Buf::alloc(1)?;
+        disk.read(slot, block.as_mut())?;
+        block.as_mut_slice()[BLOCK_SIZE / 2] ^= 1;
+        disk.write(slot, block.as_ref())?;
+        assert_eq!(Superblock::open(&disk, &root_key)?.nonce_limit(), 2);
+
+        // No valid copy at all
+        let other = (slot as usize + 1) % SUPERBLOCK_NBLOCKS;
+        disk.write(other, block.as_ref())?;
+        let res = Superblock::open(&disk, &root_key);
+        assert_eq!(res.err().unwrap().errno(), InvalidArgs);
+        Ok(())
+    }
+
+    #[test]
+    fn superblock_legacy_format() -> Result<()> {
+        let disk = MemDisk::create(SUPERBLOCK_NBLOCKS)?;
+        let root_key = Key::random();
+
+        // The single copy in the last block is probed
+        let mut superblock = new_superblock(0)?;
+        superblock.meta.version = LEGACY_FORMAT_VERSION;
+        superblock.set_wrapped_root_key(&[7u8; 32])?;
+        superblock.persist(&disk, &root_key)?;
+        let mut opened = Superblock::open(&disk, &root_key)?;
+        assert_eq!(opened.nblocks(), 1);
+        assert_eq!(opened.layout(), superblock.layout());
+        assert_eq!(Superblock::read_wrapped_root_key(&disk)?, vec![7u8; 32]);
+
+        // It's updated in place, the block before it is untouched
+        opened.set_nonce_limit(42);
+        opened.persist(&disk, &root_key)?;
+        assert_eq!(Superblock::open(&disk, &root_key)?.nonce_limit(), 42);
+        let mut block = Buf::alloc(1)?;
+        disk.read(0, block.as_mut())?;
+        assert!(block.as_slice().iter().all(|byte| *byte == 0));
+
+        // Formatting upgrades it to shadow copies
+        opened.format(&disk, &root_key)?;
+        let opened = Superblock::open(&disk, &root_key)?;
+        assert_eq!(opened.nblocks(), SUPERBLOCK_NBLOCKS);
+        assert_eq!(opened.nonce_limit(), 42);
+        Ok(())
+    }
+
+    #[test]
+    fn superblock_unknown_format() -> Result<()> {
+        let disk = MemDisk::create(SUPERBLOCK_NBLOCKS)?;
+        let root_key = Key::random();
+
+        let mut superblock = new_superblock(1 << 63)?;
+        superblock.format(&disk, &root_key)?;
+        let res = Superblock::open(&disk, &root_key);
+        assert_eq!(res.err().unwrap().errno(), Unsupported);
+
+        let mut superblock = new_superblock(0)?;
+        superblock.meta.version = FORMAT_VERSION + 1;
+        superblock.format(&disk, &root_key)?;
+        let res = Superblock::open(&disk, &root_key);
+        assert_eq!(res.err().unwrap().errno(), Unsupported);
+
+        // The wrong root key
+        let res = Superblock::open(&disk, &Key::random());
+        assert_eq!(res.err().unwrap().errno(), InvalidArgs);
+        Ok(())
+    }
+}
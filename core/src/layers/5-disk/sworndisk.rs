@@ -7,32 +7,65 @@
 //! are stored; an untrusted disk storing user data, a `BlockAlloc` for managing data blocks'
 //! allocation metadata. `TxLsmTree` and `BlockAlloc` are manipulated
 //! based on internal transactions.
-use super::bio::{BioReq, BioReqQueue, BioResp, BioType};
-use super::block_alloc::{AllocTable, BlockAlloc};
+use super::audit::AuditReport;
+use super::backup::{
+    Frame, StreamExporter, StreamImporter, StreamReader, StreamSummary, StreamWriter,
+    MAX_FRAME_NBLOCKS,
+};
+use super::bio::{BioReq, BioReqQueue, BioResp, BioType, BlockBuf};
+use super::block_alloc::{AllocAlignStats, AllocTable, BlockAlloc};
+#[cfg(feature = "debug_crc")]
+use super::corruption::{CorruptionHandlerRef, CorruptionReport};
+use super::cost_stats::{rdtsc, set_cost_clock};
 use super::data_buf::DataBuf;
 use super::dealloc_block::DeallocTable;
+use super::digest::{DigestTree, DiskDigest};
+use super::discard_log::DiscardLog;
+use super::discard_queue::{merge_extents, DiscardQueue, DiscardStats};
+use super::disk_stats::DiskStats;
+use super::events::{DiskEvent, EventBus, EventSubscriberRef, SubscriptionId};
+use super::freshness::{check_freshness, TrustedCounterRef};
 use super::gc::{
-    GcWorker, ReverseKey, ReverseValue, SharedStateRef, VictimPolicy, VictimPolicyRef,
+    GcGuard, GcWorker, ReverseKey, ReverseValue, SharedStateRef, VictimPolicy, VictimPolicyRef,
+};
+use super::key_provider::RootKeyProvider;
+use super::layout::DiskLayout;
+use super::mem_budget::{MemBudget, MemUsage};
+use super::rate_limit::{BackgroundIoLimiter, RateLimitStats, RateLimiter};
+use super::ref_count::RefCountTable;
+use super::reverse_index::{ReverseIndex, SegmentReverseMaps};
+use super::segment::FragmentationReport;
+use super::stats_log::{persist_stats, restore_stats};
+use super::superblock::{
+    DiskId, Superblock, DISK_ID_SIZE, FEATURE_COMPACT_RECORDS, FEATURE_EXTENT_RECORDS, FEATURE_GC,
+    FEATURE_LBA_AAD, FEATURE_SEGMENT_REVERSE_INDEX, FEATURE_SHARED_BLOCKS, FEATURE_SYNC_ID_RECORDS,
+    SUPERBLOCK_NBLOCKS,
 };
-use crate::layers::bio::{BlockId, BlockSet, Buf, BufMut, BufRef, BLOCK_SIZE};
-use crate::layers::disk::config::Config;
+use super::sync_id_log::sync_id_store_or_default;
+use crate::layers::bio::{BlockId, BlockSet, Buf, BufMut, BufRef, OverlayDisk, BLOCK_SIZE};
+use crate::layers::disk::config::{BlockCryptoMode, BvtCompactionPolicy, Config, ReverseIndexKind};
 use crate::layers::disk::gc::{GreedyVictimPolicy, SharedState};
-use crate::layers::disk::WAF_STATS;
 use crate::layers::log::TxLogStore;
 use crate::layers::lsm::{
-    AsKV, LsmLevel, RangeQueryCtx, RecordKey as RecordK, RecordValue as RecordV, SyncIdStore,
-    TxEventListener, TxEventListenerFactory, TxLsmTree, TxType,
+    AsKV, LsmLevel, LsmParams, RangeQueryCtx, RecordKey as RecordK, RecordValue as RecordV, SyncId,
+    SyncIdStore, TxEventListener, TxEventListenerFactory, TxLsmTree, TxType, ValueFormat,
+};
+use crate::os::{
+    default_aead_backend, AeadBackendRef, AeadIv as Iv, AeadKey as Key, AeadMac as Mac, BTreeMap,
+    BackgroundTask, Condvar, CvarMutex, RwLock, RwLockWriteGuard, TaskContext, Weak,
 };
-use crate::os::{Aead, AeadIv as Iv, AeadKey as Key, AeadMac as Mac, BTreeMap, Condvar, RwLock};
 use crate::prelude::*;
 use crate::tx::Tx;
 
-use crate::os::{spawn, Arc};
+#[cfg(feature = "sim")]
+use crate::os::SimClock;
+use crate::os::{Arc, TaskPriority, TaskScheduler};
 use crate::{CostL3Type, COST_L2, COST_L3};
 use core::cell::UnsafeCell;
+use core::mem::size_of;
 use core::num::NonZeroUsize;
-use core::ops::{Add, Sub};
-use core::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU64, Ordering};
+use core::ops::{Add, Range, Sub};
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU64, AtomicUsize, Ordering};
 use lazy_static::lazy_static;
 use pod::Pod;
 use spin::Mutex;
@@ -61,13 +94,13 @@ impl ConfigCell {
         if !self.initialized.swap(true, Ordering::SeqCst) {
             unsafe {
                 *self.value.get() = config;
-                println!(
+                info!(
                     "CONFIG set successfully: cache_size={}",
                     (*self.value.get()).cache_size
                 );
             }
         } else {
-            println!("CONFIG already initialized, ignoring new config");
+            warn!("CONFIG already initialized, ignoring new config");
         }
     }
 
@@ -80,7 +113,9 @@ unsafe impl Send for ConfigCell {}
 unsafe impl Sync for ConfigCell {}
 
 lazy_static! {
-    /// The capacity of the data buffer.
+    /// The configuration of the first `SwornDisk` created or opened, which
+    /// is only read by the caches of `TxLogStore`. The disk layer and its
+    /// `TxLsmTree`s (with their WALs and SSTs) read the config of each instance.
     pub static ref CONFIG: ConfigCell = ConfigCell::new(Config::default());
 }
 
@@ -96,7 +131,7 @@ struct DiskInner<D: BlockSet> {
     /// A `TxLsmTree` to store metadata of the logical blocks.
     logical_block_table: TxLsmTree<RecordKey, RecordValue, D>,
     /// A reverse index table that map HBA to LBA.
-    reverse_index_table: Option<TxLsmTree<ReverseKey, ReverseValue, D>>,
+    reverse_index_table: Option<ReverseIndex<D>>,
     /// A reverse index table that map HBA to LBA.
     dealloc_table: Arc<DeallocTable>,
     /// The underlying disk where user data is stored.
@@ -107,16 +142,66 @@ struct DiskInner<D: BlockSet> {
     tx_log_store: Arc<TxLogStore<D>>,
     /// A buffer to cache data blocks.
     data_buf: DataBuf,
+    /// The budget of `DataBuf` and the caches, see `Config::cache_size`.
+    mem_budget: MemBudget,
+    /// The cached digest of the logical block table.
+    digest_tree: Arc<DigestTree>,
+    /// The discards deferred to be merged, see `Config::discard_granularity`.
+    discard_queue: DiscardQueue,
+    /// The range tombstones of the issued discards, `None` if the disk is
+    /// created without `FEATURE_SYNC_ID_RECORDS`.
+    discard_log: Option<DiscardLog<D>>,
+    /// The reference counts of the shared user data blocks, `None` if the
+    /// disk is created without `FEATURE_SHARED_BLOCKS`.
+    ref_counts: Option<Arc<RefCountTable<D>>>,
+    /// Serializes the flushes of `DataBuf` and the writes bypassing it,
+    /// so that the records of a snapshot never override newer ones.
+    flush_lock: CvarMutex<()>,
     /// Root encryption key.
     root_key: Key,
+    /// The superblock of `SwornDisk`.
+    superblock: Mutex<Superblock>,
+    /// The underlying disk where the superblock is stored.
+    superblock_disk: D,
+    /// The crypto mode of user data blocks.
+    crypto_mode: BlockCryptoMode,
+    /// The per-disk data key used in `BlockCryptoMode::PerBlockNonce`
+    /// and `BlockCryptoMode::DerivedKey`.
+    data_key: Key,
+    /// The identity of the disk bound to user data blocks, `None` if the
+    /// disk is created without `FEATURE_LBA_AAD`.
+    disk_id: Option<DiskId>,
+    /// The AEAD backend to protect user data blocks.
+    aead: AeadBackendRef,
+    /// The trusted counter to advance the freshness on each sync.
+    trusted_counter: Option<TrustedCounterRef>,
+    /// The rate limiter of submitted block I/O requests.
+    rate_limiter: Option<RateLimiter>,
+    /// The limiter of the I/Os of GC and defragmentation.
+    background_io_limiter: Option<Arc<BackgroundIoLimiter>>,
+    /// The next per-block nonce used in `BlockCryptoMode::PerBlockNonce`,
+    /// or the next per-block epoch used in `BlockCryptoMode::DerivedKey`.
+    next_nonce: AtomicU64,
+    /// Whether `SwornDisk` is opened read-only.
+    read_only: bool,
     /// Whether `SwornDisk` is dropped.
     is_dropped: AtomicBool,
     /// Scope lock for control write and sync operation.
     write_sync_region: RwLock<()>,
+    /// Coalesces concurrent sync operations into group commits.
+    sync_group: SyncGroup,
+    /// The number of syncs since the last compaction of the block validity table.
+    syncs_since_bvt_compaction: AtomicUsize,
     /// Shared state for background GC.
     shared_state: SharedStateRef,
-    /// Whether the disk is active.
-    is_active: Arc<AtomicBool>,
+    /// Scheduler of background tasks, e.g., GC.
+    scheduler: TaskScheduler,
+    /// The configuration of this instance.
+    config: Arc<Config>,
+    /// The statistics of this instance.
+    stats: Arc<DiskStats>,
+    /// The subscribers of the events of this instance.
+    events: Arc<EventBus>,
 }
 
 impl<D: BlockSet + 'static> SwornDisk<D> {
@@ -134,9 +219,204 @@ impl<D: BlockSet + 'static> SwornDisk<D> {
         self.inner.readv(lba, bufs)
     }
 
+    /// Read a specified number of blocks at a logical block address on the device,
+    /// returns the sub-ranges of never-written blocks (holes), which are zero-filled.
+    ///
+    /// This allows the caller (e.g., a file system) to distinguish sparse regions
+    /// from the data that happens to be zeros.
+    pub fn read_with_holes(&self, lba: Lba, buf: BufMut) -> Result<Vec<Range<Lba>>> {
+        self.check_rw_args(lba, buf.nblocks())?;
+        self.inner.read_with_holes(lba, buf)
+    }
+
+    /// Read a specified number of blocks at a logical block address on the
+    /// device as a latency-critical read, e.g., of an interactive workload
+    /// sharing the disk with bulk jobs.
+    ///
+    /// Background GC yields to it at the next victim segment, and its latency
+    /// is counted apart in `BioStats`. The queued requests of `BioPriority::High`
+    /// are read the same way.
+    pub fn read_urgent(&self, lba: Lba, buf: BufMut) -> Result<()> {
+        self.check_rw_args(lba, buf.nblocks())?;
+        self.inner.read_urgent(lba, buf)
+    }
+
+    /// Read multiple blocks at scattered logical block addresses on the device.
+    /// The block at `lbas[nth]` will be read into the `nth` block of `buf`.
+    pub fn read_scattered(&self, lbas: &[Lba], buf: BufMut) -> Result<()> {
+        if lbas.len() != buf.nblocks() {
+            return_errno_with_msg!(InvalidArgs, "number of lbas mismatches the buffer");
+        }
+        for &lba in lbas {
+            self.check_rw_args(lba, 1)?;
+        }
+        self.inner.read_scattered(lbas, buf)
+    }
+
+    /// Read `buf.len()` bytes at the byte offset `offset` on the device,
+    /// neither of which needs to be aligned to `BLOCK_SIZE`.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        self.check_byte_args(offset, buf.len())?;
+        self.inner.read_at(offset, buf)
+    }
+
+    /// Verify the integrity of a specified number of blocks at a logical block
+    /// address on the device, returns the result of each block.
+    ///
+    /// The ciphertext of each block is re-read from disk and authenticated,
+    /// no plaintext is handed out and no corrupted block is repaired. Thus it
+    /// suits external auditors who attest the storage integrity.
+    pub fn verify_range(&self, lba: Lba, nblocks: usize) -> Result<Vec<VerifyResult>> {
+        self.check_rw_args(lba, nblocks)?;
+        self.inner.verify_range(lba, nblocks)
+    }
+
+    /// Returns an iterator over the mappings from LBAs to HBAs of all
+    /// the written blocks, in the ascending order of LBAs.
+    ///
+    /// The mappings are a snapshot taken on return (see `TxLsmTree::iter()`),
+    /// the blocks buffered in `DataBuf` are written beforehand. The writes,
+    /// syncs and compactions go on while the iterator is held.
+    pub fn iter_mappings(&self) -> Result<impl Iterator<Item = Result<(Lba, Hba)>>> {
+        self.inner.iter_mappings()
+    }
+
+    /// Returns the digest of the whole logical disk, i.e., a Merkle root over
+    /// the per-block MACs (see `DiskDigest`), for external consistency tooling.
+    ///
+    /// The device is synced beforehand, so the digest reflects the durable
+    /// state at this sync point. The digest is recomputed incrementally,
+    /// only the buckets of LBAs changed since the last call are re-read.
+    pub fn digest(&self) -> Result<DiskDigest> {
+        self.inner.digest()
+    }
+
+    /// Returns the ID of the last sync of the device.
+    pub fn sync_id(&self) -> SyncId {
+        self.inner.logical_block_table.sync_id()
+    }
+
+    /// Audit the integrity of the whole logical space, returns the report.
+    ///
+    /// Each block is verified as in `verify_range()`, and the mappings are
+    /// checked against data blocks owned by more than one LBA. It's meant
+    /// for a disk opened by `open_readonly()`, which audits the durable state.
+    pub fn audit(&self) -> Result<AuditReport> {
+        let total_blocks = self.total_blocks();
+        let mut report = AuditReport::new(total_blocks);
+        for lba in (0..total_blocks).step_by(AUDIT_CHUNK_NBLOCKS) {
+            let nblocks = AUDIT_CHUNK_NBLOCKS.min(total_blocks - lba);
+            report.record_verify_results(lba, &self.verify_range(lba, nblocks)?);
+        }
+        let hbas = self
+            .iter_mappings()?
+            .map(|mapping| mapping.map(|(_, hba)| hba))
+            .collect::<Result<Vec<_>>>()?;
+        let ref_counts = &self.inner.ref_counts;
+        report.record_mappings(hbas, |hba| {
+            ref_counts
+                .as_ref()
+                .map_or(1, |ref_counts| ref_counts.count(hba))
+        });
+        report.digest = self.digest()?;
+        Ok(report)
+    }
+
+    /// Returns the LBAs of the blocks written or discarded since the sync
+    /// of `sync_id`, in ascending order, including the buffered ones.
+    ///
+    /// Each record is stamped with the sync ID which commits it, so is each
+    /// range tombstone of the discards (see `DiscardLog`). It fails with
+    /// `Unsupported` if the disk is created without `FEATURE_SYNC_ID_RECORDS`.
+    pub fn changed_blocks_since(&self, sync_id: SyncId) -> Result<Vec<Lba>> {
+        self.inner.changed_blocks_since(sync_id)
+    }
+
+    /// Exports a consistent snapshot of the logical contents of the device
+    /// into a backup stream sealed by `stream_key`, returns the summary of
+    /// the stream. The holes are not exported.
+    ///
+    /// The device is synced beforehand, and the writes are blocked until
+    /// the export completes. See `backup` for the stream format.
+    pub fn export_stream<W: StreamWriter + ?Sized>(
+        &self,
+        writer: &mut W,
+        stream_key: &Key,
+    ) -> Result<StreamSummary> {
+        self.inner.export_stream(writer, stream_key, 0)
+    }
+
+    /// Exports the blocks written since the snapshot of a previous stream,
+    /// whose summary gives `base_sync_id`, as an incremental backup stream.
+    ///
+    /// The ranges discarded since then are exported as discard frames, so
+    /// that they are discarded from the base on import. It fails with
+    /// `Unsupported` as `changed_blocks_since()` does.
+    pub fn export_stream_since<W: StreamWriter + ?Sized>(
+        &self,
+        writer: &mut W,
+        stream_key: &Key,
+        base_sync_id: SyncId,
+    ) -> Result<StreamSummary> {
+        if base_sync_id == 0 {
+            return_errno_with_msg!(InvalidArgs, "base sync ID of incremental stream is zero");
+        }
+        self.inner.export_stream(writer, stream_key, base_sync_id)
+    }
+
+    /// Restores the logical contents of the device from a backup stream
+    /// sealed by `stream_key`, returns the number of imported blocks.
+    ///
+    /// The whole device is discarded first for a full stream, while an
+    /// incremental stream is applied on top of the current contents, which
+    /// are supposed to be restored from its base. The device is synced only
+    /// after the end of the stream is authenticated. On error, the contents
+    /// of the device are unspecified until the next successful import.
+    pub fn import_stream<R: StreamReader + ?Sized>(
+        &self,
+        reader: &mut R,
+        stream_key: &Key,
+    ) -> Result<usize> {
+        self.check_writable()?;
+        let mut importer = StreamImporter::new(reader, self.inner.aead.clone(), stream_key)?;
+        let total_blocks = self.total_blocks();
+        if importer.total_blocks() > total_blocks {
+            return_errno_with_msg!(InvalidArgs, "device is smaller than the exported one");
+        }
+
+        if !importer.is_incremental() {
+            self.discard(0, total_blocks)?;
+        }
+        let mut buf = Buf::alloc(MAX_FRAME_NBLOCKS)?;
+        let res = self.import_extents(&mut importer, &mut buf);
+        buf.as_mut_slice().fill(0);
+        res?;
+
+        self.sync()?;
+        Ok(importer.num_blocks())
+    }
+
+    fn import_extents<R: StreamReader + ?Sized>(
+        &self,
+        importer: &mut StreamImporter<R>,
+        buf: &mut Buf,
+    ) -> Result<()> {
+        while let Some(frame) = importer.next_frame(buf.as_mut_slice())? {
+            match frame {
+                Frame::Extent(lba, nblocks) => self.write(
+                    lba,
+                    BufRef::try_from(&buf.as_slice()[..nblocks * BLOCK_SIZE])?,
+                )?,
+                Frame::Discard(range) => self.discard(range.start, range.len())?,
+            }
+        }
+        Ok(())
+    }
+
     /// Write a specified number of blocks at a logical block address on the device.
     /// The block contents reside in a single contiguous buffer.
     pub fn write(&self, lba: Lba, buf: BufRef) -> Result<()> {
+        self.check_writable()?;
         self.check_rw_args(lba, buf.nblocks())?;
         let _rguard = self.inner.write_sync_region.read();
         self.inner.write(lba, buf)
@@ -145,28 +425,184 @@ impl<D: BlockSet + 'static> SwornDisk<D> {
     /// Write multiple blocks at a logical block address on the device.
     /// The block contents reside in several scattered buffers.
     pub fn writev(&self, lba: Lba, bufs: &[BufRef]) -> Result<()> {
+        self.check_writable()?;
         self.check_rw_args(lba, bufs.iter().fold(0, |acc, buf| acc + buf.nblocks()))?;
         let _rguard = self.inner.write_sync_region.read();
         self.inner.writev(lba, bufs)
     }
 
+    /// Write the bytes of `buf` at the byte offset `offset` on the device,
+    /// neither of which needs to be aligned to `BLOCK_SIZE`.
+    ///
+    /// The partially covered blocks at both ends are read, modified and
+    /// written back with the other writes excluded, so each of them is
+    /// updated atomically regardless of concurrent writers. The fully covered
+    /// blocks in between are written as a normal write. The write as a whole
+    /// is not atomic across blocks.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        self.check_byte_args(offset, buf.len())?;
+        self.inner.write_at(offset, buf)
+    }
+
+    /// Clone `nblocks` blocks from `src_lba` to `dst_lba`, as if they were
+    /// copied block by block. The two ranges may overlap.
+    ///
+    /// On a disk created with `Config::shared_blocks`, the cloned records
+    /// share the data blocks of the source with reference counts, without
+    /// any data I/O, and the holes of the source are discarded from the
+    /// destination. Otherwise, the data blocks are bound to their LBAs (and
+    /// GC migrates a block for a single LBA), so the blocks are copied, i.e.,
+    /// re-encrypted and written as normal writes, and the holes are written
+    /// as zero-filled blocks.
+    pub fn clone_range(&self, src_lba: Lba, dst_lba: Lba, nblocks: usize) -> Result<()> {
+        self.check_writable()?;
+        self.check_rw_args(src_lba, nblocks)?;
+        self.check_rw_args(dst_lba, nblocks)?;
+        let _rguard = self.inner.write_sync_region.read();
+        self.inner.clone_range(src_lba, dst_lba, nblocks)
+    }
+
+    /// Discard (TRIM) a specified number of blocks at a logical block address
+    /// on the device, the discarded blocks are read as holes afterwards.
+    ///
+    /// The mappings of the blocks are removed by a single range deletion of
+    /// the logical block table, whatever the size of the extent is. If
+    /// `Config::discard_granularity` is set, the smaller extents are queued
+    /// and merged with the adjacent ones before the deletion.
+    pub fn discard(&self, lba: Lba, nblocks: usize) -> Result<()> {
+        self.check_writable()?;
+        self.check_rw_args(lba, nblocks)?;
+        let _rguard = self.inner.write_sync_region.read();
+        self.inner.discard(lba, nblocks)
+    }
+
+    /// Discard a batch of extents, e.g., those of a file system trim.
+    /// The adjacent and overlapping extents are merged beforehand.
+    pub fn discard_batch(&self, extents: &[Range<Lba>]) -> Result<()> {
+        self.check_writable()?;
+        for extent in extents {
+            self.check_rw_args(extent.start, extent.end.saturating_sub(extent.start))?;
+        }
+        let _rguard = self.inner.write_sync_region.read();
+        self.inner.discard_batch(extents)
+    }
+
+    /// Returns the statistics of the discards, including the pending ones.
+    pub fn discard_stats(&self) -> DiscardStats {
+        self.inner.discard_queue.stats()
+    }
+
+    /// Returns a writer to bulk load blocks in ascending LBA order, e.g., to
+    /// create a filesystem image. The blocks are written to sequentially
+    /// allocated HBAs, and their records are loaded into SSTs directly,
+    /// bypassing `DataBuf`, the WAL and the `MemTable`.
+    ///
+    /// The other writes, syncs and GC wait until the writer is dropped, so
+    /// do the reads if GC is enabled. See `BulkWriter` for the durability.
+    pub fn bulk_writer(&self) -> Result<BulkWriter<'_, D>> {
+        self.check_writable()?;
+        BulkWriter::new(self)
+    }
+
     /// Sync all cached data in the device to the storage medium for durability.
+    ///
+    /// Concurrent syncs are coalesced into a single commit, which releases
+    /// all the callers upon its completion.
     pub fn sync(&self) -> Result<()> {
-        let _wguard = self.inner.write_sync_region.write();
-        // TODO: Error handling the sync operation
-        self.inner.sync().unwrap();
+        self.check_writable()?;
+        self.inner.group_sync()?;
 
         #[cfg(not(feature = "linux"))]
         trace!("[SwornDisk] Sync completed. {self:?}");
         Ok(())
     }
 
+    /// Write a specified number of blocks at a logical block address on the
+    /// device with FUA (force unit access) semantics, i.e., the blocks are
+    /// durable once it returns, without draining the other buffered blocks.
+    pub fn write_fua(&self, lba: Lba, buf: BufRef) -> Result<()> {
+        self.check_writable()?;
+        self.check_rw_args(lba, buf.nblocks())?;
+        self.inner.write_fua(lba, &[buf])
+    }
+
+    /// Write a specified number of blocks at a logical block address on the
+    /// device with relaxed durability, e.g., for temporary data such as
+    /// browser caches or tmpfs spills.
+    ///
+    /// The blocks bypass `DataBuf` and go to disk directly, while their
+    /// records are kept in the `MemTable` without being journaled in the WAL.
+    /// They are persisted by the next `sync()`, the relaxed writes since
+    /// the last `sync()` may be lost on a crash.
+    pub fn write_relaxed(&self, lba: Lba, buf: BufRef) -> Result<()> {
+        self.check_writable()?;
+        self.check_rw_args(lba, buf.nblocks())?;
+        let _rguard = self.inner.write_sync_region.read();
+        self.inner.write_relaxed(lba, buf)
+    }
+
+    /// Write barrier, which is weaker than `sync()`. The blocks written before
+    /// it reach the device ahead of the blocks written after it, they are
+    /// committed by the next `sync()` or `write_fua()`.
+    pub fn flush(&self) -> Result<()> {
+        self.check_writable()?;
+        self.inner.flush()
+    }
+
+    /// Marks whether the foreground I/O is latency-critical. Major compactions
+    /// of the index tables are deferred while it is, and done by the GC worker
+    /// once the disk turns idle.
+    pub fn set_latency_critical(&self, critical: bool) {
+        self.inner.shared_state.set_latency_critical(critical);
+    }
+
+    /// Returns the statistics of the allocations aligned by `Config::alloc_alignment`.
+    pub fn alloc_align_stats(&self) -> AllocAlignStats {
+        self.inner.block_validity_table.align_stats()
+    }
+
+    /// Returns the space usage of each segment, the histogram of invalid-block
+    /// fractions and the estimated reclaimable space at the given GC threshold.
+    ///
+    /// Segments are tracked only when GC is enabled.
+    pub fn fragmentation_report(&self, threshold: f64) -> Result<FragmentationReport> {
+        let Some(segment_table) = self.inner.block_validity_table.get_segment_table_ref() else {
+            return_errno_with_msg!(
+                Unsupported,
+                "segment table does not exist when GC is disabled"
+            );
+        };
+        Ok(FragmentationReport::new(segment_table, threshold))
+    }
+
+    /// Scales the write counters of all segments by `factor` (in `[0, 1]`),
+    /// so that the heat seen by victim policies follows the recent writes.
+    /// Call it periodically, e.g., `0.5` per period for a half-life of one period.
+    ///
+    /// Segments are tracked only when GC is enabled.
+    pub fn decay_segment_heat(&self, factor: f64) -> Result<()> {
+        let Some(segment_table) = self.inner.block_validity_table.get_segment_table_ref() else {
+            return_errno_with_msg!(
+                Unsupported,
+                "segment table does not exist when GC is disabled"
+            );
+        };
+        segment_table
+            .iter()
+            .for_each(|segment| segment.decay_write_count(factor));
+        Ok(())
+    }
+
     /// Returns the total number of blocks in the device.
     pub fn total_blocks(&self) -> usize {
         self.inner.user_data_disk.nblocks()
     }
 
     /// Creates a new `SwornDisk` on the given disk, with the root encryption key.
+    ///
+    /// If no `SyncIdStore` is given, the master sync IDs are stored in
+    /// the disk itself (see `TxLogSyncIdStore`).
     pub fn create(
         disk: D,
         root_key: Key,
@@ -174,33 +610,95 @@ impl<D: BlockSet + 'static> SwornDisk<D> {
         config: Option<Config>,
     ) -> Result<Self> {
         let cfg = config.unwrap_or_default();
+        if cfg.shared_blocks && (cfg.enable_gc || cfg.crypto_mode == BlockCryptoMode::DerivedKey) {
+            return_errno_with_msg!(
+                InvalidArgs,
+                "shared blocks can't be combined with GC or derived keys"
+            );
+        }
+        // The disk and its trees read this config rather than the global one
+        let config = Arc::new(cfg.clone());
         CONFIG.set(cfg.clone());
+        if cfg.stat_cost {
+            set_cost_clock(cfg.cost_clock);
+        }
         let enable_gc = cfg.enable_gc;
-
-        let data_disk = Self::subdisk_for_data(&disk)?;
-        let lsm_tree_disk = Self::subdisk_for_logical_block_table(&disk)?;
-        let reverse_index_disk = Self::subdisk_for_reverse_index_table(&disk)?;
+        let mem_budget = cfg.mem_budget()?;
+        let stats = Arc::new(DiskStats::new(cfg.aggregate_stats));
+
+        let layout = DiskLayout::new(disk.nblocks(), &cfg)?;
+        let lsm_params = cfg.lsm_params()?;
+        let data_disk = Self::subdisk_for_data(&disk, &layout)?;
+        let lsm_tree_disk = Self::subdisk_for_logical_block_table(&disk, &layout)?;
+        let superblock_disk = Self::subdisk_for_superblock(&disk)?;
+        let segment_reverse_index =
+            enable_gc && cfg.reverse_index_kind == ReverseIndexKind::SegmentBlobs;
+        let mut features = FEATURE_SYNC_ID_RECORDS | FEATURE_EXTENT_RECORDS;
+        // A shared block is encrypted for the LBA it's written to only
+        if cfg.shared_blocks {
+            features |= FEATURE_SHARED_BLOCKS;
+        } else {
+            features |= FEATURE_LBA_AAD;
+        }
+        if enable_gc {
+            features |= FEATURE_GC;
+        }
+        if segment_reverse_index {
+            features |= FEATURE_SEGMENT_REVERSE_INDEX;
+        }
+        // Only the nonce (or epoch) of each block is kept as the secret of its record
+        let compact_records = cfg.crypto_mode != BlockCryptoMode::RandomKey;
+        if compact_records {
+            features |= FEATURE_COMPACT_RECORDS;
+        }
+        let lsm_params = LsmParams {
+            value_format: RecordValue::value_format(compact_records, true, true),
+            ..lsm_params
+        };
+        let mut superblock = Superblock::new(features, cfg.crypto_mode, layout, lsm_params);
+        if let Some(counter) = &cfg.trusted_counter {
+            superblock.set_freshness(counter.read()?);
+        }
+        if cfg.ephemeral {
+            // Never persist the keys, and erase any stale superblock
+            let zeroed = Buf::alloc(SUPERBLOCK_NBLOCKS)?;
+            superblock_disk.write(0, zeroed.as_ref())?;
+        } else {
+            superblock.format(&superblock_disk, &root_key)?;
+        }
         let tx_log_store = Arc::new(TxLogStore::format(lsm_tree_disk, root_key.clone())?);
         let block_validity_table = Arc::new(AllocTable::new(
             NonZeroUsize::new(data_disk.nblocks()).unwrap(),
+            &cfg,
         ));
 
         let shared_state = Arc::new(SharedState::new());
 
         let (dealloc_table, reverse_index_table) = if enable_gc {
+            let reverse_index_disk = Self::subdisk_for_reverse_index_table(&disk, &layout)?;
             let reverse_index_tx_log_store =
                 Arc::new(TxLogStore::format(reverse_index_disk, root_key.clone())?);
             (
                 Arc::new(DeallocTable::new(
                     NonZeroUsize::new(data_disk.nblocks()).unwrap(),
                 )),
-                Some(TxLsmTree::format(
-                    reverse_index_tx_log_store,
-                    Arc::new(EmptyFactory),
-                    None,
-                    sync_id_store.clone(),
-                    shared_state.clone(),
-                )?),
+                Some(if segment_reverse_index {
+                    ReverseIndex::SegmentBlobs(Arc::new(SegmentReverseMaps::new(
+                        reverse_index_tx_log_store,
+                    )))
+                } else {
+                    ReverseIndex::Lsm(TxLsmTree::format(
+                        reverse_index_tx_log_store.clone(),
+                        Arc::new(EmptyFactory),
+                        None,
+                        // The given store only tracks the logical block table,
+                        // whose syncs the reverse index doesn't follow one by one
+                        Some(sync_id_store_or_default(&None, &reverse_index_tx_log_store)),
+                        shared_state.clone(),
+                        config.clone(),
+                        lsm_params,
+                    )?)
+                }),
             )
         } else {
             (
@@ -211,53 +709,120 @@ impl<D: BlockSet + 'static> SwornDisk<D> {
             )
         };
 
+        let ref_counts = cfg
+            .shared_blocks
+            .then(|| Arc::new(RefCountTable::new(tx_log_store.clone(), cfg.ephemeral)));
         let listener_factory = Arc::new(TxLsmTreeListenerFactory::new(
             tx_log_store.clone(),
             block_validity_table.clone(),
             dealloc_table.clone(),
+            ref_counts.clone(),
+            enable_gc,
         ));
 
         let logical_block_table = {
             let table = block_validity_table.clone();
             let dealloc_table = dealloc_table.clone();
+            let ref_counts = ref_counts.clone();
             let on_drop_record_in_memtable = move |record: &dyn AsKV<RecordKey, RecordValue>| {
                 // Deallocate the host block while the corresponding record is dropped in `MemTable`
                 // Only check dealloc_table when GC is enabled to avoid unnecessary mutex operations
-                if CONFIG.get().enable_gc && dealloc_table.has_deallocated(record.value().hba) {
+                if enable_gc && dealloc_table.has_deallocated(record.value().hba) {
                     dealloc_table.finish_deallocated(record.value().hba);
                     return;
                 }
+                // A shared block is only deallocated with its last record
+                if let Some(ref_counts) = &ref_counts
+                    && !ref_counts.release(record.value().hba)
+                {
+                    return;
+                }
                 table.set_deallocated(record.value().hba);
             };
             TxLsmTree::format(
                 tx_log_store.clone(),
                 listener_factory,
                 Some(Arc::new(on_drop_record_in_memtable)),
-                sync_id_store,
+                Some(sync_id_store_or_default(&sync_id_store, &tx_log_store)),
                 shared_state.clone(),
+                config.clone(),
+                lsm_params,
             )?
         };
 
+        // Defer major compactions while the foreground is latency-critical
+        logical_block_table.set_compaction_scheduler(shared_state.clone());
+        if let Some(reverse_index_table) = reverse_index_table.as_ref() {
+            reverse_index_table.set_compaction_scheduler(shared_state.clone());
+        }
+        // Only this disk's trees skip the WALs, not the others in the process
+        logical_block_table.set_ephemeral(cfg.ephemeral);
+        if let Some(reverse_index_table) = reverse_index_table.as_ref() {
+            reverse_index_table.set_ephemeral(cfg.ephemeral);
+        }
+
+        let discard_log = DiscardLog::new(tx_log_store.clone(), cfg.ephemeral);
+        let digest_tree = Arc::new(DigestTree::new(data_disk.nblocks()));
+        let scheduler = new_scheduler(&shared_state);
         let inner = Arc::new(DiskInner {
-            bio_req_queue: BioReqQueue::new(),
+            bio_req_queue: BioReqQueue::with_merge_window(cfg.bio_merge_window, stats.clone()),
             logical_block_table,
             reverse_index_table,
             dealloc_table,
             user_data_disk: Arc::new(data_disk),
             block_validity_table,
             tx_log_store,
-            data_buf: DataBuf::new(DATA_BUF_CAP),
+            data_buf: DataBuf::new(mem_budget.data_buf_cap(DATA_BUF_CAP)),
+            mem_budget,
+            digest_tree,
+            discard_queue: DiscardQueue::new(),
+            discard_log: Some(discard_log),
+            ref_counts,
+            flush_lock: CvarMutex::new(()),
             root_key,
+            crypto_mode: superblock.crypto_mode(),
+            data_key: *superblock.data_key(),
+            disk_id: superblock
+                .has_feature(FEATURE_LBA_AAD)
+                .then(|| superblock.disk_id()),
+            aead: cfg
+                .aead_backend
+                .clone()
+                .unwrap_or_else(default_aead_backend),
+            trusted_counter: cfg.trusted_counter.clone(),
+            rate_limiter: cfg.rate_limit.map(RateLimiter::new).transpose()?,
+            background_io_limiter: cfg
+                .background_io_limit
+                .map(|limit| BackgroundIoLimiter::new(limit).map(Arc::new))
+                .transpose()?,
+            next_nonce: AtomicU64::new(superblock.nonce_limit()),
+            superblock: Mutex::new(superblock),
+            superblock_disk,
+            read_only: false,
             is_dropped: AtomicBool::new(false),
             write_sync_region: RwLock::new(()),
+            sync_group: SyncGroup::new(),
+            syncs_since_bvt_compaction: AtomicUsize::new(0),
             shared_state,
-            is_active: Arc::new(AtomicBool::new(true)),
+            scheduler,
+            config,
+            stats,
+            events: Arc::new(EventBus::new()),
         });
 
         if enable_gc {
             let policy = cfg.get_victim_policy();
             let gc_worker = inner.create_gc_worker(policy)?;
-            spawn(move || gc_worker.run());
+            inner
+                .scheduler
+                .add_task(Arc::new(gc_worker), TaskPriority::Normal);
+            inner.scheduler.start();
+        }
+        if cfg.bvt_compaction == BvtCompactionPolicy::Background {
+            DiskInner::start_bvt_compactor(&inner);
+        }
+        if cfg.discard_granularity > 0 {
+            DiskInner::start_discard_issuer(&inner);
         }
 
         let new_self = Self { inner };
@@ -268,40 +833,308 @@ impl<D: BlockSet + 'static> SwornDisk<D> {
         Ok(new_self)
     }
 
+    /// Creates a new `SwornDisk` on the given disk as an encrypted swap area,
+    /// whose data need not survive a restart (see `Config::ephemeral`).
+    ///
+    /// The root key is random and only kept in memory, so are the other keys.
+    /// Neither the WAL records nor the block validity table are persisted,
+    /// and `sync()` doesn't make anything durable.
+    pub fn create_ephemeral(disk: D, config: Option<Config>) -> Result<Self> {
+        let config = Config {
+            ephemeral: true,
+            ..config.unwrap_or_default()
+        };
+        Self::create(disk, Key::random(), None, Some(config))
+    }
+
     /// Opens the `SwornDisk` on the given disk, with the root encryption key.
+    ///
+    /// If `Config::ephemeral` is set, the disk is reformatted with
+    /// `create_ephemeral()` instead, ignoring the root key.
+    ///
+    /// Fails with `RolledBack` if the disk is older than the given
+    /// `SyncIdStore` or `Config::trusted_counter`.
     pub fn open(
         disk: D,
         root_key: Key,
         sync_id_store: Option<Arc<dyn SyncIdStore>>,
         config: Option<Config>,
+    ) -> Result<Self> {
+        Self::do_open(disk, root_key, sync_id_store, config, false)
+    }
+
+    /// Opens the `SwornDisk` on the given disk read-only, with the root encryption key.
+    ///
+    /// The underlying disk is guaranteed not to be modified. No GC is started,
+    /// all writes and syncs fail with `PermissionDenied`. The recovery (e.g.,
+    /// replaying the WAL and discarding unsynced records) is done within
+    /// a volatile `OverlayDisk`, thus no TX log is created on the underlying disk.
+    /// The trusted counter (if any) is checked against but never advanced.
+    pub fn open_readonly(
+        disk: D,
+        root_key: Key,
+        sync_id_store: Option<Arc<dyn SyncIdStore>>,
+        config: Option<Config>,
+    ) -> Result<SwornDisk<OverlayDisk<D>>> {
+        SwornDisk::do_open(
+            OverlayDisk::new(disk),
+            root_key,
+            sync_id_store,
+            config,
+            true,
+        )
+    }
+
+    /// Grows the `SwornDisk` on the given disk offline, whose first `old_nblocks`
+    /// blocks hold the disk before it's extended (e.g., the host file is enlarged).
+    ///
+    /// All the added blocks go to the user data, which are free for new writes
+    /// once opened, while no user data is migrated. The index tables are copied
+    /// to the end of the grown disk, and the block validity table is extended
+    /// and persisted there. The new superblock is written last, so that the old
+    /// disk stays intact if it crashes before. Then the old superblock is erased.
+    ///
+    /// It's offline only: the disk must not be opened during the growth, since
+    /// the subdisks of an opened `SwornDisk` are fixed. Close the disk, grow it
+    /// and open it again. The disk must grow by no less than the sizes of the
+    /// index tables and the superblock, see `DiskLayout::grow()`.
+    pub fn grow_offline(disk: D, root_key: Key, old_nblocks: usize) -> Result<()> {
+        if old_nblocks > disk.nblocks() {
+            return_errno_with_msg!(InvalidArgs, "disk can't be shrunk");
+        }
+        if old_nblocks == disk.nblocks() {
+            return Ok(());
+        }
+
+        let old_disk = disk.subset(0..old_nblocks)?;
+        let old_superblock_disk = Self::subdisk_for_superblock(&old_disk)?;
+        let mut superblock = Superblock::open(&old_superblock_disk, &root_key)?;
+        let old_layout = *superblock.layout();
+        old_layout.check(old_nblocks, superblock.nblocks())?;
+        let layout = old_layout.grow(disk.nblocks())?;
+
+        Self::move_index_tables(&disk, root_key, &old_layout, &layout, &superblock)?;
+
+        // Switch to the new layout
+        superblock.set_layout(layout);
+        superblock.format(&Self::subdisk_for_superblock(&disk)?, &root_key)?;
+        let zeroed = Buf::alloc(SUPERBLOCK_NBLOCKS)?;
+        old_superblock_disk.write(0, zeroed.as_ref())?;
+        disk.flush()
+    }
+
+    /// Shrinks the `SwornDisk` on the given disk to its first `new_nblocks`
+    /// blocks, e.g., to compact the image before archiving it.
+    ///
+    /// All the removed blocks are taken from the user data, whose blocks in
+    /// the trailing region are evacuated by GC migrations beforehand. It fails
+    /// with `NoSpaceLeft` before any migration if the live data doesn't fit.
+    /// Then the index tables are copied to the end of the shrunk disk, and the
+    /// block validity table is truncated there. The new superblock is written
+    /// last, so that the old disk stays intact (with the data evacuated) if it
+    /// crashes before. The disk can be truncated to `new_nblocks` afterwards.
+    ///
+    /// The disk must not be opened during the shrinking, it's opened with
+    /// `sync_id_store` and `config` to evacuate the data. GC must be enabled on
+    /// creation. The disk must shrink by no less than the sizes of the index
+    /// tables and the superblock, see `DiskLayout::shrink()`.
+    pub fn shrink(
+        disk: D,
+        root_key: Key,
+        new_nblocks: usize,
+        sync_id_store: Option<Arc<dyn SyncIdStore>>,
+        config: Option<Config>,
+    ) -> Result<()> {
+        if new_nblocks > disk.nblocks() {
+            return_errno_with_msg!(InvalidArgs, "disk can't be grown by shrinking");
+        }
+        if new_nblocks == disk.nblocks() {
+            return Ok(());
+        }
+
+        let old_superblock_disk = Self::subdisk_for_superblock(&disk)?;
+        let superblock = Superblock::open(&old_superblock_disk, &root_key)?;
+        if !superblock.has_feature(FEATURE_GC) {
+            return_errno_with_msg!(InvalidArgs, "GC is not enabled on creation");
+        }
+        let old_layout = *superblock.layout();
+        old_layout.check(disk.nblocks(), superblock.nblocks())?;
+        let layout = old_layout.shrink(new_nblocks)?;
+
+        // Evacuate the trailing user data, the migrations are synced
+        let cfg = Config {
+            enable_gc: true,
+            ..config.unwrap_or_default()
+        };
+        let sworndisk = Self::open(
+            disk.subset(0..disk.nblocks())?,
+            root_key,
+            sync_id_store,
+            Some(cfg),
+        )?;
+        sworndisk.inner.evacuate(layout.data_range().end)?;
+        sworndisk.sync()?;
+        drop(sworndisk);
+
+        // The superblock may be updated when opened, e.g., the nonce limit
+        let mut superblock = Superblock::open(&old_superblock_disk, &root_key)?;
+        Self::move_index_tables(&disk, root_key, &old_layout, &layout, &superblock)?;
+
+        // Switch to the new layout
+        let new_disk = disk.subset(0..new_nblocks)?;
+        superblock.set_layout(layout);
+        superblock.format(&Self::subdisk_for_superblock(&new_disk)?, &root_key)?;
+        let zeroed = Buf::alloc(SUPERBLOCK_NBLOCKS)?;
+        old_superblock_disk.write(0, zeroed.as_ref())?;
+        disk.flush()
+    }
+
+    // Copies the index tables of `old_layout` to their places in `layout` on
+    // the same disk, the old ones are untouched. Then the block validity table
+    // in the copied store is resized to the user data of `layout`.
+    fn move_index_tables(
+        disk: &D,
+        root_key: Key,
+        old_layout: &DiskLayout,
+        layout: &DiskLayout,
+        superblock: &Superblock,
+    ) -> Result<()> {
+        const COPY_NBLOCKS: usize = 256;
+        let tables_range = old_layout.index_range().start..old_layout.reverse_index_range().end;
+        let new_tables_start = layout.index_range().start;
+        for pos in tables_range.clone().step_by(COPY_NBLOCKS) {
+            let mut buf = Buf::alloc(COPY_NBLOCKS.min(tables_range.end - pos))?;
+            disk.read(pos, buf.as_mut())?;
+            disk.write(new_tables_start + (pos - tables_range.start), buf.as_ref())?;
+        }
+        disk.flush()?;
+
+        let cfg = Config {
+            enable_gc: superblock.has_feature(FEATURE_GC),
+            ..Default::default()
+        };
+        let tx_log_store = Arc::new(TxLogStore::recover(
+            Self::subdisk_for_logical_block_table(disk, layout)?,
+            root_key,
+        )?);
+        let old_nblocks = NonZeroUsize::new(old_layout.data_range().len()).unwrap();
+        let nblocks = NonZeroUsize::new(layout.data_range().len()).unwrap();
+        let block_validity_table = AllocTable::recover(old_nblocks, &tx_log_store, &cfg)?;
+        let block_validity_table = if nblocks >= old_nblocks {
+            block_validity_table.grow(nblocks, &cfg)
+        } else {
+            block_validity_table.shrink(nblocks, &cfg)?
+        };
+        block_validity_table.do_compaction(&tx_log_store)?;
+        tx_log_store.sync()?;
+        disk.flush()
+    }
+
+    fn do_open(
+        disk: D,
+        root_key: Key,
+        sync_id_store: Option<Arc<dyn SyncIdStore>>,
+        config: Option<Config>,
+        read_only: bool,
     ) -> Result<Self> {
         let cfg = config.unwrap_or_default();
+        if cfg.ephemeral {
+            if read_only {
+                return_errno_with_msg!(InvalidArgs, "ephemeral disk can't be opened read-only");
+            }
+            // Nothing of an ephemeral disk survives the restart
+            return Self::create_ephemeral(disk, Some(cfg));
+        }
+        let config = Arc::new(cfg.clone());
         CONFIG.set(cfg.clone());
+        if cfg.stat_cost {
+            set_cost_clock(cfg.cost_clock);
+        }
         let enable_gc = cfg.enable_gc;
+        let mem_budget = cfg.mem_budget()?;
+        let stats = Arc::new(DiskStats::new(cfg.aggregate_stats));
 
-        let data_disk = Self::subdisk_for_data(&disk)?;
-        let lsm_tree_disk = Self::subdisk_for_logical_block_table(&disk)?;
+        let superblock_disk = Self::subdisk_for_superblock(&disk)?;
+        let superblock = Superblock::open(&superblock_disk, &root_key)?;
+        if enable_gc && !superblock.has_feature(FEATURE_GC) {
+            return_errno_with_msg!(InvalidArgs, "GC is not enabled on creation");
+        }
+        if let Some(counter) = &cfg.trusted_counter {
+            check_freshness(superblock.freshness(), counter.as_ref(), read_only)?;
+        }
+        let layout = *superblock.layout();
+        layout.check(disk.nblocks(), superblock.nblocks())?;
+        // The records are decoded in the format recorded in the superblock, so
+        // the disks in `BlockCryptoMode::PerBlockNonce` created before its
+        // secrets are stored compactly keep their full-size records
+        let lsm_params = *superblock.lsm_params();
+        if superblock.has_feature(FEATURE_COMPACT_RECORDS)
+            && superblock.crypto_mode() == BlockCryptoMode::RandomKey
+        {
+            return_errno_with_msg!(InvalidArgs, "random keys can't be stored compactly");
+        }
+        if lsm_params.value_format
+            != RecordValue::value_format(
+                superblock.has_feature(FEATURE_COMPACT_RECORDS),
+                superblock.has_feature(FEATURE_SYNC_ID_RECORDS),
+                superblock.has_feature(FEATURE_EXTENT_RECORDS),
+            )
+        {
+            return_errno_with_msg!(InvalidArgs, "inconsistent format of records");
+        }
+        let data_disk = Self::subdisk_for_data(&disk, &layout)?;
+        let lsm_tree_disk = Self::subdisk_for_logical_block_table(&disk, &layout)?;
 
         let tx_log_store = Arc::new(TxLogStore::recover(lsm_tree_disk, root_key)?);
-        let block_validity_table = Arc::new(AllocTable::recover(
-            NonZeroUsize::new(data_disk.nblocks()).unwrap(),
-            &tx_log_store,
-        )?);
+        if cfg.persist_stats {
+            restore_stats(&tx_log_store, &stats)?;
+        }
+        let discard_log = if superblock.has_feature(FEATURE_SYNC_ID_RECORDS) {
+            Some(DiscardLog::recover(tx_log_store.clone())?)
+        } else {
+            None
+        };
+        let block_validity_table = if cfg.lazy_recovery {
+            AllocTable::recover_lazily(
+                NonZeroUsize::new(data_disk.nblocks()).unwrap(),
+                &tx_log_store,
+                &cfg,
+            )?
+        } else {
+            Arc::new(AllocTable::recover(
+                NonZeroUsize::new(data_disk.nblocks()).unwrap(),
+                &tx_log_store,
+                &cfg,
+            )?)
+        };
 
         let shared_state = Arc::new(SharedState::new());
 
         let (dealloc_table, reverse_index_table) = if enable_gc {
+            let reverse_index_disk = Self::subdisk_for_reverse_index_table(&disk, &layout)?;
+            let reverse_index_tx_log_store =
+                Arc::new(TxLogStore::recover(reverse_index_disk, root_key)?);
             (
                 Arc::new(DeallocTable::new(
                     NonZeroUsize::new(data_disk.nblocks()).unwrap(),
                 )),
-                Some(TxLsmTree::format(
-                    tx_log_store.clone(),
-                    Arc::new(EmptyFactory),
-                    None,
-                    sync_id_store.clone(),
-                    shared_state.clone(),
-                )?),
+                Some(if superblock.has_feature(FEATURE_SEGMENT_REVERSE_INDEX) {
+                    ReverseIndex::SegmentBlobs(Arc::new(SegmentReverseMaps::new(
+                        reverse_index_tx_log_store,
+                    )))
+                } else {
+                    ReverseIndex::Lsm(TxLsmTree::recover(
+                        reverse_index_tx_log_store.clone(),
+                        Arc::new(EmptyFactory),
+                        None,
+                        // The given store only tracks the logical block table,
+                        // whose syncs the reverse index doesn't follow one by one
+                        Some(sync_id_store_or_default(&None, &reverse_index_tx_log_store)),
+                        shared_state.clone(),
+                        config.clone(),
+                        lsm_params,
+                    )?)
+                }),
             )
         } else {
             (
@@ -311,53 +1144,130 @@ impl<D: BlockSet + 'static> SwornDisk<D> {
                 None,
             )
         };
+        // Recovered before the WAL is replayed, see `RefCountTable::release()`
+        let ref_counts = if superblock.has_feature(FEATURE_SHARED_BLOCKS) {
+            Some(Arc::new(RefCountTable::recover(tx_log_store.clone())?))
+        } else {
+            None
+        };
         let listener_factory = Arc::new(TxLsmTreeListenerFactory::new(
             tx_log_store.clone(),
             block_validity_table.clone(),
             dealloc_table.clone(),
+            ref_counts.clone(),
+            enable_gc,
         ));
 
         let logical_block_table = {
             let table = block_validity_table.clone();
             let rit = dealloc_table.clone();
+            let ref_counts = ref_counts.clone();
             let on_drop_record_in_memtable = move |record: &dyn AsKV<RecordKey, RecordValue>| {
                 // Deallocate the host block while the corresponding record is dropped in `MemTable`
                 // Only check dealloc_table when GC is enabled to avoid unnecessary mutex operations
-                if CONFIG.get().enable_gc && rit.has_deallocated(record.value().hba) {
+                if enable_gc && rit.has_deallocated(record.value().hba) {
                     rit.finish_deallocated(record.value().hba);
                     return;
                 }
+                // A shared block is only deallocated with its last record
+                if let Some(ref_counts) = &ref_counts
+                    && !ref_counts.release(record.value().hba)
+                {
+                    return;
+                }
                 table.set_deallocated(record.value().hba);
             };
             TxLsmTree::recover(
                 tx_log_store.clone(),
                 listener_factory,
                 Some(Arc::new(on_drop_record_in_memtable)),
-                sync_id_store,
+                Some(sync_id_store_or_default(&sync_id_store, &tx_log_store)),
                 shared_state.clone(),
+                config.clone(),
+                lsm_params,
             )?
         };
+        // The blocks of the replayed records may be allocated after the last
+        // compaction of the block validity table (see `Config::bvt_compaction`)
+        let replayed_hbas: Vec<_> = logical_block_table
+            .recent_records()
+            .into_iter()
+            .map(|(_, value)| value.hba)
+            .collect();
+        block_validity_table.mark_allocated(&replayed_hbas);
+        if let Some(ref_counts) = &ref_counts {
+            ref_counts.finish_replay();
+        }
 
+        // Defer major compactions while the foreground is latency-critical
+        logical_block_table.set_compaction_scheduler(shared_state.clone());
+        if let Some(reverse_index_table) = reverse_index_table.as_ref() {
+            reverse_index_table.set_compaction_scheduler(shared_state.clone());
+        }
+
+        let digest_tree = Arc::new(DigestTree::new(data_disk.nblocks()));
+        let scheduler = new_scheduler(&shared_state);
         let inner = Arc::new(DiskInner {
-            bio_req_queue: BioReqQueue::new(),
+            bio_req_queue: BioReqQueue::with_merge_window(cfg.bio_merge_window, stats.clone()),
             logical_block_table,
             reverse_index_table,
             dealloc_table,
             user_data_disk: Arc::new(data_disk),
             block_validity_table,
-            data_buf: DataBuf::new(DATA_BUF_CAP),
+            data_buf: DataBuf::new(mem_budget.data_buf_cap(DATA_BUF_CAP)),
+            mem_budget,
+            digest_tree,
+            discard_queue: DiscardQueue::new(),
+            discard_log,
+            ref_counts,
+            flush_lock: CvarMutex::new(()),
             tx_log_store,
             root_key,
+            crypto_mode: superblock.crypto_mode(),
+            data_key: *superblock.data_key(),
+            disk_id: superblock
+                .has_feature(FEATURE_LBA_AAD)
+                .then(|| superblock.disk_id()),
+            aead: cfg
+                .aead_backend
+                .clone()
+                .unwrap_or_else(default_aead_backend),
+            trusted_counter: cfg.trusted_counter.clone(),
+            rate_limiter: cfg.rate_limit.map(RateLimiter::new).transpose()?,
+            background_io_limiter: cfg
+                .background_io_limit
+                .map(|limit| BackgroundIoLimiter::new(limit).map(Arc::new))
+                .transpose()?,
+            next_nonce: AtomicU64::new(superblock.nonce_limit()),
+            superblock: Mutex::new(superblock),
+            superblock_disk,
+            read_only,
             is_dropped: AtomicBool::new(false),
             write_sync_region: RwLock::new(()),
+            sync_group: SyncGroup::new(),
+            syncs_since_bvt_compaction: AtomicUsize::new(0),
             shared_state,
-            is_active: Arc::new(AtomicBool::new(true)),
+            scheduler,
+            config,
+            stats,
+            events: Arc::new(EventBus::new()),
         });
 
-        if enable_gc {
+        if enable_gc && !read_only {
+            // Repair the reverse index before GC ever consults it
+            inner.repair_reverse_index()?;
             let policy = cfg.get_victim_policy();
             let gc_worker = inner.create_gc_worker(policy)?;
-            spawn(move || gc_worker.run());
+            inner
+                .scheduler
+                .add_task(Arc::new(gc_worker), TaskPriority::Normal);
+            inner.scheduler.start();
+        }
+        if cfg.bvt_compaction == BvtCompactionPolicy::Background && !read_only {
+            DiskInner::start_bvt_compactor(&inner);
+        }
+        if cfg.discard_granularity > 0 && !read_only {
+            DiskInner::start_discard_issuer(&inner);
         }
 
         let opened_self = Self { inner };
@@ -367,12 +1277,164 @@ impl<D: BlockSet + 'static> SwornDisk<D> {
         Ok(opened_self)
     }
 
+    /// Creates a new `SwornDisk` on the given disk, with a fresh root key
+    /// protected by the given key provider.
+    ///
+    /// The wrapped root key is stored in the superblock.
+    pub fn create_with_key_provider(
+        disk: D,
+        key_provider: &dyn RootKeyProvider,
+        sync_id_store: Option<Arc<dyn SyncIdStore>>,
+        config: Option<Config>,
+    ) -> Result<Self> {
+        let root_key = Key::random();
+        let wrapped_root_key = key_provider.wrap_key(&root_key)?;
+        let new_self = Self::create(disk, root_key, sync_id_store, config)?;
+        new_self.inner.update_wrapped_root_key(&wrapped_root_key)?;
+        Ok(new_self)
+    }
+
+    /// Opens the `SwornDisk` on the given disk, with the root key
+    /// unwrapped from the superblock by the given key provider.
+    pub fn open_with_key_provider(
+        disk: D,
+        key_provider: &dyn RootKeyProvider,
+        sync_id_store: Option<Arc<dyn SyncIdStore>>,
+        config: Option<Config>,
+    ) -> Result<Self> {
+        let superblock_disk = Self::subdisk_for_superblock(&disk)?;
+        let wrapped_root_key = Superblock::read_wrapped_root_key(&superblock_disk)?;
+        let root_key = key_provider.unwrap_key(&wrapped_root_key)?;
+        Self::open(disk, root_key, sync_id_store, config)
+    }
+
+    /// Re-wraps the root key with the given key provider and
+    /// updates the wrapped root key in the superblock.
+    ///
+    /// The root key itself is unchanged.
+    pub fn rewrap_root_key(&self, key_provider: &dyn RootKeyProvider) -> Result<()> {
+        self.check_writable()?;
+        let wrapped_root_key = key_provider.wrap_key(&self.inner.root_key)?;
+        self.inner.update_wrapped_root_key(&wrapped_root_key)?;
+        self.inner.events.emit(DiskEvent::RootKeyRewrapped);
+        Ok(())
+    }
+
     /// Submit a new block I/O request and wait its completion (Synchronous).
+    ///
+    /// The submitter is delayed if its tenant exceeds `Config::rate_limit`.
     pub fn submit_bio_sync(&self, bio_req: BioReq) -> BioResp {
+        self.inner.throttle_bio_req(&bio_req);
         bio_req.submit();
         self.inner.handle_bio_req(&bio_req)
     }
-    // TODO: Support handling request asynchronously
+
+    /// Submit a new block I/O request to the request queue (Asynchronous).
+    ///
+    /// The request is handled later by `handle_queued_bios()`, the submitter
+    /// is notified through the `on_complete` callback of the request.
+    /// If its tenant exceeds `Config::rate_limit`, the request is parked
+    /// rather than delaying the submitter, until `handle_queued_bios()`
+    /// finds it within the budget.
+    pub fn submit_bio(&self, bio_req: BioReq) -> Result<()> {
+        let Some(bio_req) = self.inner.admit_or_park_bio_req(bio_req) else {
+            return Ok(());
+        };
+        self.inner.bio_req_queue.enqueue(bio_req)
+    }
+
+    /// Returns the memory in use by `DataBuf` and the caches of the index
+    /// tables and the TX logs, along with their budgets split from
+    /// `Config::cache_size`.
+    pub fn mem_usage(&self) -> MemUsage {
+        let inner = &self.inner;
+        let sst_block = inner.logical_block_table.cached_bytes()
+            + inner
+                .reverse_index_table
+                .as_ref()
+                .map_or(0, |table| table.cached_bytes());
+        MemUsage {
+            data_buf: inner.data_buf.mem_usage(),
+            sst_block,
+            log_block: inner.tx_log_store.cached_bytes(),
+            budget: inner.mem_budget,
+        }
+    }
+
+    /// Returns the statistics of the requests throttled by `Config::rate_limit`,
+    /// `None` if rate limiting is disabled.
+    pub fn rate_limit_stats(&self) -> Option<RateLimitStats> {
+        self.inner
+            .rate_limiter
+            .as_ref()
+            .map(|rate_limiter| rate_limiter.stats())
+    }
+
+    /// Returns the statistics of the GC and defragmentation I/Os throttled by
+    /// `Config::background_io_limit`, `None` if it's disabled.
+    pub fn background_io_stats(&self) -> Option<RateLimitStats> {
+        self.inner
+            .background_io_limiter
+            .as_ref()
+            .map(|limiter| limiter.stats())
+    }
+
+    /// Subscribes to the events of this instance, e.g., the blocks migrated
+    /// by GC. The events are delivered in order on a dedicated thread.
+    pub fn subscribe_events(&self, subscriber: EventSubscriberRef) -> SubscriptionId {
+        self.inner.events.subscribe(subscriber)
+    }
+
+    /// Subscribes to the events of this instance synchronously, the
+    /// subscriber is called on the thread emitting the event, e.g., before
+    /// GC finishes for the migrated blocks. It suits the caches of HBA-derived
+    /// state, see the `events` module.
+    pub fn subscribe_events_sync(&self, subscriber: EventSubscriberRef) -> SubscriptionId {
+        self.inner.events.subscribe_sync(subscriber)
+    }
+
+    /// Unsubscribes from the events, returns whether it's subscribed.
+    pub fn unsubscribe_events(&self, id: SubscriptionId) -> bool {
+        self.inner.events.unsubscribe(id)
+    }
+
+    /// Returns the WAF, GC and block I/O statistics of this instance.
+    pub fn stats(&self) -> &DiskStats {
+        &self.inner.stats
+    }
+
+    /// Returns the virtual clock the background tasks of this instance
+    /// sleep on in simulation mode.
+    #[cfg(feature = "sim")]
+    pub fn sim_clock(&self) -> &Arc<SimClock> {
+        self.inner.shared_state.sim_clock()
+    }
+
+    /// Handle all pending block I/O requests in the request queue,
+    /// returns the number of handled requests.
+    ///
+    /// Adjacent requests are merged as configured by `Config::bio_merge_window`.
+    /// The parked requests within the budget of `Config::rate_limit` now
+    /// are queued first.
+    pub fn handle_queued_bios(&self) -> usize {
+        for bio_req in self.inner.unpark_bio_reqs() {
+            let _ = self.inner.bio_req_queue.enqueue(bio_req);
+        }
+        let mut nreqs = 0;
+        while let Some(bio_reqs) = self.inner.bio_req_queue.dequeue_merged() {
+            let _ = self.inner.handle_merged_bio_reqs(&bio_reqs);
+            nreqs += bio_reqs.len();
+        }
+        nreqs
+    }
+
+    /// Check whether the disk can be written, i.e., it's not opened read-only.
+    fn check_writable(&self) -> Result<()> {
+        if self.inner.read_only {
+            return_errno_with_msg!(PermissionDenied, "sworndisk is opened read-only");
+        }
+        Ok(())
+    }
 
     /// Check whether the arguments are valid for read/write operations.
     fn check_rw_args(&self, lba: Lba, buf_nblocks: usize) -> Result<()> {
@@ -386,16 +1448,31 @@ impl<D: BlockSet + 'static> SwornDisk<D> {
         }
     }
 
-    fn subdisk_for_data(disk: &D) -> Result<D> {
-        disk.subset(0..disk.nblocks() * 15 / 16) // TBD
+    fn check_byte_args(&self, offset: usize, len: usize) -> Result<()> {
+        let capacity = self.inner.user_data_disk.nblocks() * BLOCK_SIZE;
+        match offset.checked_add(len) {
+            Some(end) if end <= capacity => Ok(()),
+            _ => Err(Error::with_msg(
+                OutOfDisk,
+                "read/write out of disk capacity",
+            )),
+        }
+    }
+
+    fn subdisk_for_data(disk: &D, layout: &DiskLayout) -> Result<D> {
+        disk.subset(layout.data_range())
+    }
+
+    fn subdisk_for_logical_block_table(disk: &D, layout: &DiskLayout) -> Result<D> {
+        disk.subset(layout.index_range())
     }
 
-    fn subdisk_for_logical_block_table(disk: &D) -> Result<D> {
-        disk.subset(disk.nblocks() * 15 / 16..disk.nblocks() * 31 / 32) // TBD
+    fn subdisk_for_reverse_index_table(disk: &D, layout: &DiskLayout) -> Result<D> {
+        disk.subset(layout.reverse_index_range())
     }
 
-    fn subdisk_for_reverse_index_table(disk: &D) -> Result<D> {
-        disk.subset(disk.nblocks() * 31 / 32..disk.nblocks()) // TBD
+    fn subdisk_for_superblock(disk: &D) -> Result<D> {
+        disk.subset(disk.nblocks().saturating_sub(SUPERBLOCK_NBLOCKS)..disk.nblocks())
     }
 
     // Create a gc worker but not launch, just for test
@@ -410,64 +1487,114 @@ impl<D: BlockSet + 'static> SwornDisk<D> {
 
 /// Capacity of the user data blocks buffer.
 const DATA_BUF_CAP: usize = 1024;
+/// Number of blocks copied at a time by `clone_range()`.
+const CLONE_CHUNK_NBLOCKS: usize = 256;
+/// Number of blocks read at a time by `read_at()`.
+const READ_AT_CHUNK_NBLOCKS: usize = 256;
+/// Number of blocks verified at a time by `audit()`.
+const AUDIT_CHUNK_NBLOCKS: usize = 1024;
+/// The number of blocks encrypted and written at a time by `BulkWriter`.
+const BULK_WRITE_NBLOCKS: usize = 1024;
+/// The number of records `BulkWriter` buffers before loading them into SSTs,
+/// i.e., 1 GiB of data blocks.
+const BULK_LOAD_NRECORDS: usize = 256 * 1024;
+/// The interval between the runs of `BvtCompactor`.
+const BVT_COMPACTION_INTERVAL: core::time::Duration = core::time::Duration::from_secs(1);
+/// The interval between the runs of `DiscardIssuer`.
+const DISCARD_ISSUE_INTERVAL: core::time::Duration = core::time::Duration::from_millis(100);
+/// The tick of the scheduler of background tasks.
+const SCHEDULER_TICK: core::time::Duration = core::time::Duration::from_millis(10);
+
+/// Creates the scheduler of background tasks, which runs on the `SimClock`
+/// of the disk in simulation mode.
+fn new_scheduler(shared_state: &SharedState) -> TaskScheduler {
+    #[cfg(feature = "sim")]
+    return TaskScheduler::with_clock(SCHEDULER_TICK, shared_state.sim_clock().clone());
+    #[cfg(not(feature = "sim"))]
+    {
+        let _ = shared_state;
+        TaskScheduler::new(SCHEDULER_TICK)
+    }
+}
+/// Number of nonces reserved each time the nonce limit in superblock is exhausted.
+const NONCE_RESERVE: u64 = 1 << 20;
 
 impl<D: BlockSet + 'static> DiskInner<D> {
     /// Read a specified number of blocks at a logical block address on the device.
     /// The block contents will be read into a single contiguous buffer.
     pub fn read(&self, lba: Lba, buf: BufMut) -> Result<()> {
-        let nblocks = buf.nblocks();
-
-        let res = if nblocks == 1 {
-            self.read_one_block(lba, buf)
-        } else {
-            self.read_multi_blocks(lba, &mut [buf])
-        };
+        let _holes = self.read_with_holes(lba, buf)?;
+        Ok(())
+    }
 
-        // Allow empty read
-        if let Err(e) = &res
-            && e.errno() == NotFound
-        {
-            #[cfg(not(feature = "linux"))]
-            warn!("[SwornDisk] read contains empty read on lba {lba}");
-            return Ok(());
-        }
+    /// Read blocks as a latency-critical read, which background GC yields to.
+    pub fn read_urgent(&self, lba: Lba, buf: BufMut) -> Result<()> {
+        let started_at = rdtsc();
+        let urgent_guard = self.shared_state.begin_urgent_read();
+        let res = self.read(lba, buf);
+        drop(urgent_guard);
+        self.stats
+            .record_bio(|stats| stats.record_urgent_read(started_at, started_at));
         res
     }
 
     /// Read multiple blocks at a logical block address on the device.
     /// The block contents will be read into several scattered buffers.
     pub fn readv<'a>(&self, lba: Lba, bufs: &'a mut [BufMut<'a>]) -> Result<()> {
-        let res = self.read_multi_blocks(lba, bufs);
-
-        // Allow empty read
-        if let Err(e) = &res
-            && e.errno() == NotFound
-        {
-            #[cfg(not(feature = "linux"))]
-            warn!("[SwornDisk] readv contains empty read on lba {lba}");
-            return Ok(());
+        trace_span!("readv", lba, nbufs = bufs.len());
+        let nblocks = bufs.iter().map(|buf| buf.nblocks()).sum::<usize>();
+        self.issue_discards_in(lba..lba + nblocks)?;
+        let _holes = self.read_multi_blocks(lba, bufs)?;
+        Ok(())
+    }
+
+    /// Read a specified number of blocks at a logical block address on the device,
+    /// returns the sub-ranges of holes (never-written blocks), which are zero-filled.
+    pub fn read_with_holes(&self, lba: Lba, buf: BufMut) -> Result<Vec<Range<Lba>>> {
+        let nblocks = buf.nblocks();
+        trace_span!("read", lba, nblocks);
+
+        self.issue_discards_in(lba..lba + nblocks)?;
+        let holes = if nblocks == 1 {
+            self.read_one_block(lba, buf)?
+        } else {
+            self.read_multi_blocks(lba, &mut [buf])?
+        };
+
+        #[cfg(not(feature = "linux"))]
+        if !holes.is_empty() {
+            debug!("[SwornDisk] read contains holes {holes:?}");
         }
-        res
+        Ok(holes)
     }
 
-    fn read_one_block(&self, lba: Lba, mut buf: BufMut) -> Result<()> {
+    // The pending discards overlapping the block must be issued beforehand
+    fn read_one_block(&self, lba: Lba, mut buf: BufMut) -> Result<Vec<Range<Lba>>> {
         debug_assert_eq!(buf.nblocks(), 1);
         // Search in `DataBuf` first
         if self.data_buf.get(RecordKey { lba }, &mut buf).is_some() {
-            return Ok(());
+            return Ok(Vec::new());
         }
 
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if self.config.stat_cost {
             Some(COST_L3.time(CostL3Type::LogicalBlockTable))
         } else {
             None
         };
-        self.wait_for_background_gc();
+        self.wait_for_background_gc()?;
         // Search in `TxLsmTree` then
-        let value = self.logical_block_table.get(&RecordKey { lba })?;
+        let value = match self.logical_block_table.get(&RecordKey { lba }) {
+            Ok(value) => value,
+            // Never-written block is a hole
+            Err(e) if e.errno() == NotFound => {
+                buf.as_mut_slice().fill(0);
+                return Ok(vec![lba..lba + 1]);
+            }
+            Err(e) => return Err(e),
+        };
         drop(timer);
 
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if self.config.stat_cost {
             Some(COST_L3.time(CostL3Type::BlockIO))
         } else {
             None
@@ -475,26 +1602,25 @@ impl<D: BlockSet + 'static> DiskInner<D> {
         let mut cipher = Buf::alloc(1)?;
         self.user_data_disk.read(value.hba, cipher.as_mut())?;
         drop(timer);
+        self.stats.record_bio(|stats| stats.record_read_batch(1));
 
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if self.config.stat_cost {
             Some(COST_L3.time(CostL3Type::Encryption))
         } else {
             None
         };
-        Aead::new().decrypt(
-            cipher.as_slice(),
-            &value.key,
-            &Iv::new_zeroed(),
-            &[],
-            &value.mac,
-            buf.as_mut_slice(),
-        )?;
+        self.decrypt_or_reread_block(lba, &value, cipher.as_slice(), buf.as_mut_slice())?;
         drop(timer);
 
-        Ok(())
+        Ok(Vec::new())
     }
 
-    fn read_multi_blocks<'a>(&self, lba: Lba, bufs: &'a mut [BufMut<'a>]) -> Result<()> {
+    // The pending discards overlapping the blocks must be issued beforehand
+    fn read_multi_blocks<'a>(
+        &self,
+        lba: Lba,
+        bufs: &'a mut [BufMut<'a>],
+    ) -> Result<Vec<Range<Lba>>> {
         let mut buf_vec = BufMutVec::from_bufs(bufs);
         let nblocks = buf_vec.nblocks();
 
@@ -512,22 +1638,35 @@ impl<D: BlockSet + 'static> DiskInner<D> {
             range_query_ctx.mark_completed(key);
         }
         if range_query_ctx.is_completed() {
-            return Ok(());
+            return Ok(Vec::new());
         }
-        self.wait_for_background_gc();
+        self.wait_for_background_gc()?;
 
-        let timer = if CONFIG.get().stat_cost {
+        let timer = if self.config.stat_cost {
             Some(COST_L3.time(CostL3Type::LogicalBlockTable))
         } else {
             None
         };
         // Search in `TxLsmTree` then
-        self.logical_block_table.get_range(&mut range_query_ctx)?;
+        match self.logical_block_table.get_range(&mut range_query_ctx) {
+            Err(e) if e.errno() != NotFound => return Err(e),
+            _ => {}
+        }
         drop(timer);
-        // Allow empty read
-        debug_assert!(range_query_ctx.is_completed());
 
-        let mut res = range_query_ctx.into_results();
+        // Never-written and discarded blocks are holes
+        let mut hole_lbas: Vec<_> = range_query_ctx
+            .uncompleted_keys()
+            .into_iter()
+            .chain(range_query_ctx.deleted_keys().iter().copied())
+            .map(|key| key.lba)
+            .collect();
+        hole_lbas.sort_unstable();
+        for &hole_lba in hole_lbas.iter() {
+            buf_vec.nth_buf_mut_slice(hole_lba - lba).fill(0);
+        }
+
+        let mut res = range_query_ctx.into_completed_results();
         let record_batches = {
             res.sort_by(|(_, v1), (_, v2)| v1.hba.cmp(&v2.hba));
             res.group_by(|(_, v1), (_, v2)| v2.hba - v1.hba == 1)
@@ -537,7 +1676,7 @@ impl<D: BlockSet + 'static> DiskInner<D> {
         let mut cipher_buf = Buf::alloc(nblocks)?;
         let cipher_slice = cipher_buf.as_mut_slice();
         for record_batch in record_batches {
-            let timer = if CONFIG.get().stat_cost {
+            let timer = if self.config.stat_cost {
                 Some(COST_L3.time(CostL3Type::BlockIO))
             } else {
                 None
@@ -547,34 +1686,348 @@ impl<D: BlockSet + 'static> DiskInner<D> {
                 BufMut::try_from(&mut cipher_slice[..record_batch.len() * BLOCK_SIZE]).unwrap(),
             )?;
             drop(timer);
+            self.stats
+                .record_bio(|stats| stats.record_read_batch(record_batch.len()));
 
-            let timer = if CONFIG.get().stat_cost {
+            let timer = if self.config.stat_cost {
                 Some(COST_L3.time(CostL3Type::Encryption))
             } else {
                 None
             };
             for (nth, (key, value)) in record_batch.iter().enumerate() {
-                Aead::new().decrypt(
+                self.decrypt_or_reread_block(
+                    key.lba,
+                    value,
                     &cipher_slice[nth * BLOCK_SIZE..(nth + 1) * BLOCK_SIZE],
-                    &value.key,
-                    &Iv::new_zeroed(),
-                    &[],
-                    &value.mac,
                     buf_vec.nth_buf_mut_slice(key.lba - lba),
                 )?;
             }
             drop(timer);
         }
 
+        Ok(lbas_to_ranges(&hole_lbas))
+    }
+
+    /// Read multiple blocks at scattered logical block addresses on the device.
+    /// The block at `lbas[nth]` will be read into the `nth` block of `buf`.
+    pub fn read_scattered(&self, lbas: &[Lba], mut buf: BufMut) -> Result<()> {
+        if let (Some(&min), Some(&max)) = (lbas.iter().min(), lbas.iter().max()) {
+            self.issue_discards_in(min..max + 1)?;
+        }
+        let buf_slice = buf.as_mut_slice();
+
+        // Search in `DataBuf` first
+        let mut uncompleted = Vec::with_capacity(lbas.len());
+        for (nth, &lba) in lbas.iter().enumerate() {
+            let mut block_buf =
+                BufMut::try_from(&mut buf_slice[nth * BLOCK_SIZE..(nth + 1) * BLOCK_SIZE]).unwrap();
+            if self
+                .data_buf
+                .get(RecordKey { lba }, &mut block_buf)
+                .is_none()
+            {
+                uncompleted.push(nth);
+            }
+        }
+        if uncompleted.is_empty() {
+            return Ok(());
+        }
+        self.wait_for_background_gc()?;
+
+        let timer = if self.config.stat_cost {
+            Some(COST_L3.time(CostL3Type::LogicalBlockTable))
+        } else {
+            None
+        };
+        // Search in `TxLsmTree` then, all the scattered lbas in one pass
+        let keys: Vec<_> = uncompleted
+            .iter()
+            .map(|&nth| RecordKey { lba: lbas[nth] })
+            .collect();
+        let values = self.logical_block_table.get_multi(&keys)?;
+        drop(timer);
+
+        let mut res = Vec::with_capacity(uncompleted.len());
+        for (nth, value) in uncompleted.into_iter().zip(values) {
+            match value {
+                Some(value) => res.push((nth, value)),
+                // Never-written block is a hole
+                None => buf_slice[nth * BLOCK_SIZE..(nth + 1) * BLOCK_SIZE].fill(0),
+            }
+        }
+        let record_batches = {
+            res.sort_by(|(_, v1), (_, v2)| v1.hba.cmp(&v2.hba));
+            res.group_by(|(_, v1), (_, v2)| v2.hba - v1.hba == 1)
+        };
+
+        // Perform disk read in batches and decryption
+        let mut cipher_buf = Buf::alloc(lbas.len())?;
+        let cipher_slice = cipher_buf.as_mut_slice();
+        for record_batch in record_batches {
+            let timer = if self.config.stat_cost {
+                Some(COST_L3.time(CostL3Type::BlockIO))
+            } else {
+                None
+            };
+            self.user_data_disk.read(
+                record_batch.first().unwrap().1.hba,
+                BufMut::try_from(&mut cipher_slice[..record_batch.len() * BLOCK_SIZE]).unwrap(),
+            )?;
+            drop(timer);
+            self.stats
+                .record_bio(|stats| stats.record_read_batch(record_batch.len()));
+
+            let timer = if self.config.stat_cost {
+                Some(COST_L3.time(CostL3Type::Encryption))
+            } else {
+                None
+            };
+            for (i, (nth, value)) in record_batch.iter().enumerate() {
+                self.decrypt_or_reread_block(
+                    lbas[*nth],
+                    value,
+                    &cipher_slice[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE],
+                    &mut buf_slice[nth * BLOCK_SIZE..(nth + 1) * BLOCK_SIZE],
+                )?;
+            }
+            drop(timer);
+        }
+
+        Ok(())
+    }
+
+    /// Iterate the mappings of the logical block table, after the buffered
+    /// blocks get their mappings.
+    pub fn iter_mappings(&self) -> Result<impl Iterator<Item = Result<(Lba, Hba)>>> {
+        let _wguard = self.write_sync_region.write();
+        self.issue_discards(self.discard_queue.take_all())?;
+        if !self.data_buf.is_empty() {
+            // flush_data_buf will wait for background GC to finish
+            self.flush_data_buf()?;
+        }
+
+        let mappings = self
+            .logical_block_table
+            .iter()
+            .map(|record| record.map(|(key, value)| (key.lba, value.hba)));
+        Ok(mappings)
+    }
+
+    /// Sync the device, then compute the digest of the logical block table.
+    pub fn digest(&self) -> Result<DiskDigest> {
+        let _wguard = self.write_sync_region.write();
+        if !self.read_only {
+            self.sync()?;
+        }
+        self.wait_for_background_gc()?;
+
+        self.digest_tree.digest(|range| {
+            let mut range_query_ctx = RangeQueryCtx::<RecordKey, RecordValue>::new(
+                RecordKey { lba: range.start },
+                range.len(),
+            );
+            match self.logical_block_table.get_range(&mut range_query_ctx) {
+                Err(e) if e.errno() != NotFound => return Err(e),
+                _ => {}
+            }
+            Ok(range_query_ctx
+                .into_completed_results()
+                .into_iter()
+                .map(|(key, value)| (key.lba, value.mac))
+                .collect())
+        })
+    }
+
+    pub fn changed_blocks_since(&self, sync_id: SyncId) -> Result<Vec<Lba>> {
+        let discard_log = self.tracked_discard_log()?;
+        let _wguard = self.write_sync_region.write();
+        self.issue_discards(self.discard_queue.take_all())?;
+        if !self.data_buf.is_empty() {
+            self.flush_data_buf()?;
+        }
+        let mut lbas = self
+            .logical_block_table
+            .iter()
+            .filter_map(|record| match record {
+                Ok((key, value)) => (value.sync_id > sync_id).then_some(Ok(key.lba)),
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        lbas.extend(discard_log.discarded_since(sync_id).into_iter().flatten());
+        lbas.sort_unstable();
+        lbas.dedup();
+        Ok(lbas)
+    }
+
+    /// Returns the `DiscardLog`, which exists as long as the records are
+    /// stamped with their sync IDs.
+    fn tracked_discard_log(&self) -> Result<&DiscardLog<D>> {
+        self.discard_log.as_ref().ok_or(Error::with_msg(
+            Unsupported,
+            "changes are not tracked without FEATURE_SYNC_ID_RECORDS",
+        ))
+    }
+
+    /// Sync the device, then export the ranges discarded and the mapped
+    /// blocks written since `base_sync_id` (if not zero) in frames.
+    pub fn export_stream<W: StreamWriter + ?Sized>(
+        &self,
+        writer: &mut W,
+        stream_key: &Key,
+        base_sync_id: SyncId,
+    ) -> Result<StreamSummary> {
+        if base_sync_id > 0 {
+            // Fail before the sync
+            let _ = self.tracked_discard_log()?;
+        }
+        let _wguard = self.write_sync_region.write();
+        if !self.read_only {
+            self.sync()?;
+        }
+
+        let mut exporter = StreamExporter::new(
+            writer,
+            self.aead.clone(),
+            stream_key,
+            self.user_data_disk.nblocks(),
+            base_sync_id,
+            self.logical_block_table.sync_id(),
+        )?;
+        let mut buf = Buf::alloc(MAX_FRAME_NBLOCKS)?;
+        let res = self.export_extents(&mut exporter, base_sync_id, &mut buf);
+        buf.as_mut_slice().fill(0);
+        res?;
+
+        exporter.finish()
+    }
+
+    fn export_extents<W: StreamWriter + ?Sized>(
+        &self,
+        exporter: &mut StreamExporter<W>,
+        base_sync_id: SyncId,
+        buf: &mut Buf,
+    ) -> Result<()> {
+        if base_sync_id > 0 {
+            // The blocks written after the discards are exported afterwards
+            for range in self.tracked_discard_log()?.discarded_since(base_sync_id) {
+                exporter.write_discard(range)?;
+            }
+        }
+
+        // Group the changed LBAs into extents
+        let mut extent: Option<Range<Lba>> = None;
+        for record in self.logical_block_table.iter() {
+            let (key, value) = record?;
+            if base_sync_id > 0 && value.sync_id <= base_sync_id {
+                continue;
+            }
+            let lba = key.lba;
+            match extent.as_mut() {
+                Some(range) if range.end == lba && range.len() < MAX_FRAME_NBLOCKS => {
+                    range.end += 1;
+                }
+                _ => {
+                    if let Some(range) = extent.replace(lba..lba + 1) {
+                        self.export_extent(exporter, range, buf)?;
+                    }
+                }
+            }
+        }
+        if let Some(range) = extent {
+            self.export_extent(exporter, range, buf)?;
+        }
         Ok(())
     }
 
+    fn export_extent<W: StreamWriter + ?Sized>(
+        &self,
+        exporter: &mut StreamExporter<W>,
+        range: Range<Lba>,
+        buf: &mut Buf,
+    ) -> Result<()> {
+        let plain = &mut buf.as_mut_slice()[..range.len() * BLOCK_SIZE];
+        self.read(range.start, BufMut::try_from(&mut plain[..])?)?;
+        exporter.write_extent(range.start, plain)
+    }
+
+    /// Verify the integrity of a specified number of blocks at a logical block
+    /// address on the device, returns the result of each block.
+    pub fn verify_range(&self, lba: Lba, nblocks: usize) -> Result<Vec<VerifyResult>> {
+        let mut results = vec![VerifyResult::Hole; nblocks];
+        if nblocks == 0 {
+            return Ok(results);
+        }
+        self.issue_discards_in(lba..lba + nblocks)?;
+        let mut range_query_ctx =
+            RangeQueryCtx::<RecordKey, RecordValue>::new(RecordKey { lba }, nblocks);
+
+        // Blocks in `DataBuf` are not on disk yet
+        for (key, _) in self
+            .data_buf
+            .get_range(range_query_ctx.range_uncompleted().unwrap())
+        {
+            results[key.lba - lba] = VerifyResult::Buffered;
+            range_query_ctx.mark_completed(key);
+        }
+        if range_query_ctx.is_completed() {
+            return Ok(results);
+        }
+        self.wait_for_background_gc()?;
+
+        match self.logical_block_table.get_range(&mut range_query_ctx) {
+            Err(e) if e.errno() != NotFound => return Err(e),
+            _ => {}
+        }
+
+        let mut res = range_query_ctx.into_completed_results();
+        let record_batches = {
+            res.sort_by(|(_, v1), (_, v2)| v1.hba.cmp(&v2.hba));
+            res.group_by(|(_, v1), (_, v2)| v2.hba - v1.hba == 1)
+        };
+
+        // Authenticate the ciphertext in batches, the plaintext is discarded
+        let mut cipher_buf = Buf::alloc(nblocks)?;
+        let cipher_slice = cipher_buf.as_mut_slice();
+        let mut plain = Buf::alloc(1)?;
+        for record_batch in record_batches {
+            self.user_data_disk.read(
+                record_batch.first().unwrap().1.hba,
+                BufMut::try_from(&mut cipher_slice[..record_batch.len() * BLOCK_SIZE]).unwrap(),
+            )?;
+            for (nth, (key, value)) in record_batch.iter().enumerate() {
+                let cipher = &cipher_slice[nth * BLOCK_SIZE..(nth + 1) * BLOCK_SIZE];
+                results[key.lba - lba] =
+                    match self.decrypt_block(key.lba, value, cipher, plain.as_mut_slice()) {
+                        Ok(()) => VerifyResult::Pass,
+                        Err(_) => VerifyResult::Fail,
+                    };
+            }
+        }
+        plain.as_mut_slice().fill(0);
+
+        Ok(results)
+    }
+
     /// Write a specified number of blocks at a logical block address on the device.
     /// The block contents reside in a single contiguous buffer.
-    pub fn write(&self, mut lba: Lba, buf: BufRef) -> Result<()> {
+    pub fn write(&self, lba: Lba, buf: BufRef) -> Result<()> {
+        trace_span!("write", lba, nblocks = buf.nblocks());
+        // The pending discards must not delete the newly written blocks
+        self.issue_discards_in(lba..lba + buf.nblocks())?;
+        self.write_after_discards(lba, buf)
+    }
+
+    /// Write blocks whose overlapping pending discards are issued already.
+    fn write_after_discards(&self, mut lba: Lba, buf: BufRef) -> Result<()> {
         // WAF Statistics: count all user write calls as logical writes
-        if CONFIG.get().stat_waf {
-            WAF_STATS.add_logical(buf.as_slice().len() as u64);
+        if self.config.stat_waf {
+            self.stats
+                .record_waf(|waf| waf.add_logical(buf.as_slice().len() as u64));
+        }
+
+        // Huge writes bypass `DataBuf` and go to disk directly
+        if buf.nblocks() >= self.config.direct_write_threshold {
+            return self.write_direct(lba, buf, false);
         }
 
         // Write block contents to `DataBuf` directly
@@ -602,619 +2055,4677 @@ impl<D: BlockSet + 'static> DiskInner<D> {
         Ok(())
     }
 
-    fn flush_data_buf(&self) -> Result<()> {
-        self.wait_for_background_gc();
-
-        let mut ret = self.write_blocks_from_data_buf();
+    /// Clone blocks in chunks, the chunks are cloned backwards if the
+    /// destination overlaps the tail of the source.
+    ///
+    /// On the disks with `FEATURE_SHARED_BLOCKS`, the records of the source are
+    /// remapped to the destination, sharing their host blocks, and the holes
+    /// of the source are discarded from the destination. Otherwise, the blocks
+    /// are copied, as their ciphertext is bound to the LBAs.
+    pub fn clone_range(&self, src_lba: Lba, dst_lba: Lba, nblocks: usize) -> Result<()> {
+        if nblocks == 0 || src_lba == dst_lba {
+            return Ok(());
+        }
+        let Some(ref_counts) = &self.ref_counts else {
+            return self.copy_range(src_lba, dst_lba, nblocks);
+        };
 
-        if let Err(e) = ret.as_ref() {
-            if e.errno() == OutOfDisk {
-                self.logical_block_table.manual_compaction()?;
-                // try write again
-                ret = self.write_blocks_from_data_buf();
+        // The records of the pending discards and the buffered blocks
+        // must be in the logical block table beforehand
+        self.issue_discards_in(src_lba..src_lba + nblocks)?;
+        self.issue_discards_in(dst_lba..dst_lba + nblocks)?;
+        self.flush_data_buf()?;
 
-                if let Err(e) = ret.as_ref() {
-                    if e.errno() == OutOfDisk {
-                        self.logical_block_table.force_compaction()?;
-                        // try write again
-                        ret = self.write_blocks_from_data_buf();
+        let nchunks = nblocks.div_ceil(CLONE_CHUNK_NBLOCKS);
+        let backwards = dst_lba > src_lba && dst_lba < src_lba + nblocks;
+        // The records are committed by the next sync
+        let sync_id = self.logical_block_table.sync_id() + 1;
+        for i in 0..nchunks {
+            let nth = if backwards { nchunks - 1 - i } else { i };
+            let offset = nth * CLONE_CHUNK_NBLOCKS;
+            let chunk_nblocks = (nblocks - offset).min(CLONE_CHUNK_NBLOCKS);
+            let keys: Vec<_> = (0..chunk_nblocks)
+                .map(|i| RecordKey {
+                    lba: src_lba + offset + i,
+                })
+                .collect();
+
+            let mut holes = Vec::new();
+            {
+                // No record of the source is dropped (thus its block is
+                // deallocated) before the block is shared
+                let _flush_guard = self.flush_lock.lock().unwrap();
+                let mut records = Vec::with_capacity(chunk_nblocks);
+                for (i, value) in self
+                    .logical_block_table
+                    .get_multi(&keys)?
+                    .into_iter()
+                    .enumerate()
+                {
+                    let key = RecordKey {
+                        lba: dst_lba + offset + i,
+                    };
+                    match value {
+                        Some(value) => records.push((key, RecordValue { sync_id, ..value })),
+                        None => holes.push(key.lba),
                     }
                 }
+                let hbas: Vec<_> = records.iter().map(|(_, value)| value.hba).collect();
+                ref_counts.share(&hbas)?;
+                self.logical_block_table.put_batch(&records)?;
+                self.digest_tree
+                    .invalidate_lbas(records.iter().map(|(key, _)| key.lba));
+            }
+            // The holes of the source are cloned as discards
+            for range in lbas_to_ranges(&holes) {
+                self.do_discard(range)?;
             }
         }
+        self.scheduler.mark_active();
+        Ok(())
+    }
 
-        let records = ret?;
+    /// Clone blocks by copying them in chunks, as `clone_range()` does.
+    fn copy_range(&self, src_lba: Lba, dst_lba: Lba, nblocks: usize) -> Result<()> {
+        let nchunks = nblocks.div_ceil(CLONE_CHUNK_NBLOCKS);
+        let backwards = dst_lba > src_lba && dst_lba < src_lba + nblocks;
+        let mut buf = Buf::alloc(nblocks.min(CLONE_CHUNK_NBLOCKS))?;
+        for i in 0..nchunks {
+            let nth = if backwards { nchunks - 1 - i } else { i };
+            let offset = nth * CLONE_CHUNK_NBLOCKS;
+            let chunk_nblocks = (nblocks - offset).min(CLONE_CHUNK_NBLOCKS);
+            let chunk = &mut buf.as_mut_slice()[..chunk_nblocks * BLOCK_SIZE];
+
+            // Holes are read as zero-filled blocks, which overwrite the destination
+            self.read(src_lba + offset, BufMut::try_from(&mut chunk[..])?)?;
+            self.write(dst_lba + offset, BufRef::try_from(&chunk[..])?)?;
+        }
+        buf.as_mut_slice().fill(0);
+        Ok(())
+    }
 
-        let timer = if CONFIG.get().stat_cost {
-            Some(COST_L3.time(CostL3Type::LogicalBlockTable))
-        } else {
-            None
-        };
-        // Insert new records of data blocks to `TxLsmTree`
-        for (key, value) in records.iter() {
-            if !CONFIG.get().delayed_reclamation {
-                // ignore this error
-                let _ = self.logical_block_table.get(&key);
-            }
-            // TODO: Error handling: Should dealloc the written blocks
-            self.logical_block_table.put(key.clone(), value.clone())?;
-            if let Some(reverse_index_table) = &self.reverse_index_table {
-                let reverse_index_key = ReverseKey { hba: value.hba };
-                let reverse_index_value = ReverseValue { lba: key.lba };
-                reverse_index_table.put(reverse_index_key, reverse_index_value)?;
-            }
+    /// Discard blocks, the extents smaller than `Config::discard_granularity`
+    /// are queued to be merged with the adjacent ones.
+    pub fn discard(&self, lba: Lba, nblocks: usize) -> Result<()> {
+        if nblocks == 0 {
+            return Ok(());
         }
+        self.discard_queue.record_requests(1);
+        self.discard_extent(lba..lba + nblocks)
+    }
 
-        drop(timer);
-        self.is_active.store(true, Ordering::Release);
-        self.data_buf.clear();
+    /// Discard a batch of extents, which are merged beforehand.
+    pub fn discard_batch(&self, extents: &[Range<Lba>]) -> Result<()> {
+        let nrequests = extents.iter().filter(|extent| !extent.is_empty()).count();
+        self.discard_queue.record_requests(nrequests);
+        for extent in merge_extents(extents) {
+            self.discard_extent(extent)?;
+        }
         Ok(())
     }
 
-    fn write_blocks_from_data_buf(&self) -> Result<Vec<(RecordKey, RecordValue)>> {
-        let data_blocks = self.data_buf.all_blocks();
+    fn discard_extent(&self, extent: Range<Lba>) -> Result<()> {
+        let granularity = self.config.discard_granularity;
+        if granularity == 0 {
+            return self.do_discard(extent);
+        }
 
-        let num_write = data_blocks.len();
-        let mut records = Vec::with_capacity(num_write);
-        if num_write == 0 {
-            return Ok(records);
+        // The buffered blocks are dropped at once, so they are never flushed
+        {
+            let _flush_guard = self.flush_lock.lock().unwrap();
+            self.data_buf.remove_range(
+                RecordKey { lba: extent.start }..=RecordKey {
+                    lba: extent.end - 1,
+                },
+            );
         }
-        let timer = if CONFIG.get().stat_cost {
-            Some(COST_L3.time(CostL3Type::Allocation))
-        } else {
-            None
-        };
-        // Allocate slots for data blocks
-        let hbas = self
-            .block_validity_table
-            .alloc_batch(NonZeroUsize::new(num_write).unwrap())?;
-        debug_assert_eq!(hbas.len(), num_write);
-        drop(timer);
-        let hba_batches = hbas.group_by(|hba1, hba2| hba2 - hba1 == 1);
+        self.scheduler.mark_active();
+        match self.discard_queue.push(extent, granularity) {
+            Some(merged) => self.do_discard(merged),
+            None => Ok(()),
+        }
+    }
 
-        // Perform encryption and batch disk write
-        let mut cipher_buf = Buf::alloc(num_write)?;
-        let mut cipher_slice = cipher_buf.as_mut_slice();
-        let mut nth = 0;
-        for hba_batch in hba_batches {
-            let timer = if CONFIG.get().stat_cost {
-                Some(COST_L3.time(CostL3Type::Encryption))
-            } else {
-                None
-            };
-            for (i, &hba) in hba_batch.iter().enumerate() {
-                let (lba, data_block) = &data_blocks[nth];
-                let key = Key::random();
-                let mac = Aead::new().encrypt(
-                    data_block.as_slice(),
-                    &key,
-                    &Iv::new_zeroed(),
-                    &[],
-                    &mut cipher_slice[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE],
-                )?;
+    /// Discard blocks by dropping the buffered ones and deleting the range
+    /// of their records from the logical block table.
+    fn do_discard(&self, extent: Range<Lba>) -> Result<()> {
+        let (lba, nblocks) = (extent.start, extent.len());
+        // Wait for the flushing snapshot, which may contain the blocks
+        let _flush_guard = self.flush_lock.lock().unwrap();
+        self.data_buf.remove_range(
+            RecordKey { lba }..=RecordKey {
+                lba: lba + nblocks - 1,
+            },
+        );
+        // The dropped records are deallocated by the logical block table,
+        // which must not race with background GC
+        self.wait_for_background_gc()?;
+        self.logical_block_table
+            .delete_range(RecordKey { lba }..RecordKey { lba: lba + nblocks })?;
+        if let Some(discard_log) = &self.discard_log {
+            // Committed by the next sync, as the records written meanwhile
+            discard_log.record(extent.clone(), self.logical_block_table.sync_id() + 1);
+        }
+        self.digest_tree.invalidate(lba, nblocks);
+        self.scheduler.mark_active();
+        self.discard_queue.record_issued(&extent);
+        self.events.emit(DiskEvent::Discarded { lba, nblocks });
+        Ok(())
+    }
 
-                records.push((*lba, RecordValue { hba, key, mac }));
-                nth += 1;
-            }
-            drop(timer);
+    /// Issue the pending discards overlapping `range`, before the blocks
+    /// within are accessed.
+    fn issue_discards_in(&self, range: Range<Lba>) -> Result<()> {
+        if self.discard_queue.is_empty() {
+            return Ok(());
+        }
+        // Excludes the syncs, as a discard does
+        let _rguard = self.write_sync_region.read();
+        self.issue_overlapping_discards(range)
+    }
 
-            let timer = if CONFIG.get().stat_cost {
-                Some(COST_L3.time(CostL3Type::BlockIO))
-            } else {
-                None
-            };
-            self.user_data_disk.write(
-                *hba_batch.first().unwrap(),
-                BufRef::try_from(&cipher_slice[..hba_batch.len() * BLOCK_SIZE]).unwrap(),
-            )?;
-            drop(timer);
-            cipher_slice = &mut cipher_slice[hba_batch.len() * BLOCK_SIZE..];
+    /// Issue the pending discards overlapping `range` as `issue_discards_in()`
+    /// does, while the caller holds `write_sync_region` already.
+    fn issue_overlapping_discards(&self, range: Range<Lba>) -> Result<()> {
+        if self.discard_queue.is_empty() {
+            return Ok(());
         }
+        self.issue_discards(self.discard_queue.take_overlapping(range))
+    }
 
-        Ok(records)
+    /// Issue the discards taken out of `DiscardQueue`.
+    fn issue_discards(&self, extents: Vec<Range<Lba>>) -> Result<()> {
+        for extent in extents {
+            self.do_discard(extent)?;
+        }
+        Ok(())
     }
 
-    /// Sync all cached data in the device to the storage medium for durability.
-    pub fn sync(&self) -> Result<()> {
-        // flush_data_buf will wait for background GC to finish
-        self.flush_data_buf()?;
-        debug_assert!(self.data_buf.is_empty());
+    /// Write a huge buffer to disk directly in chunks bounded by the capacity
+    /// of `DataBuf`, each chunk is encrypted, written and indexed as a whole.
+    /// The records bypass the WAL if `relaxed`.
+    fn write_direct(&self, lba: Lba, buf: BufRef, relaxed: bool) -> Result<()> {
+        // Drop the stale buffered blocks, or they would override
+        // the newly written ones on next flush
+        let _flush_guard = self.flush_lock.lock().unwrap();
+        self.data_buf.remove_range(
+            RecordKey { lba }..=RecordKey {
+                lba: lba + buf.nblocks() - 1,
+            },
+        );
 
-        if CONFIG.get().sync_atomicity {
-            self.logical_block_table.sync()?;
+        let mut lba = lba;
+        for chunk in buf.as_slice().chunks(DATA_BUF_CAP * BLOCK_SIZE) {
+            let data_blocks: Vec<_> = chunk
+                .chunks(BLOCK_SIZE)
+                .enumerate()
+                .map(|(i, block)| (RecordKey { lba: lba + i }, block))
+                .collect();
+            // write_and_index_blocks will wait for background GC to finish
+            self.write_and_index_blocks(&data_blocks, relaxed)?;
+            lba += data_blocks.len();
         }
+        self.scheduler.mark_active();
+        Ok(())
+    }
 
-        let timer = if CONFIG.get().stat_cost {
-            Some(COST_L3.time(CostL3Type::Allocation))
-        } else {
-            None
-        };
-        // XXX: May impact performance when there comes frequent syncs
-        self.block_validity_table
-            .do_compaction(&self.tx_log_store)?;
-        drop(timer);
+    /// Write blocks with FUA (force unit access) semantics, the written blocks
+    /// are durable once it returns.
+    ///
+    /// The blocks bypass `DataBuf` and their records are forced through the
+    /// WAL, the other blocks buffered in `DataBuf` are left as they are.
+    pub fn write_fua(&self, mut lba: Lba, bufs: &[BufRef]) -> Result<()> {
+        let nblocks = bufs.iter().map(|buf| buf.nblocks()).sum::<usize>();
+        self.issue_discards_in(lba..lba + nblocks)?;
+        let _wguard = self.write_sync_region.write();
+        for buf in bufs {
+            if self.config.stat_waf {
+                self.stats
+                    .record_waf(|waf| waf.add_logical(buf.as_slice().len() as u64));
+            }
+            self.write_direct(lba, *buf, false)?;
+            lba += buf.nblocks();
+        }
+        self.commit_records()
+    }
 
-        self.tx_log_store.sync()?;
+    /// Write blocks with relaxed durability, see `SwornDisk::write_relaxed()`.
+    pub fn write_relaxed(&self, lba: Lba, buf: BufRef) -> Result<()> {
+        trace_span!("write_relaxed", lba, nblocks = buf.nblocks());
+        self.issue_discards_in(lba..lba + buf.nblocks())?;
+        if self.config.stat_waf {
+            self.stats
+                .record_waf(|waf| waf.add_logical(buf.as_slice().len() as u64));
+        }
+        self.write_direct(lba, buf, true)
+    }
 
-        let timer = if CONFIG.get().stat_cost {
-            Some(COST_L3.time(CostL3Type::BlockIO))
-        } else {
-            None
-        };
-        self.user_data_disk.flush()?;
-        drop(timer);
+    /// Read bytes at a byte offset, the covering blocks are read in chunks.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        let total_nblocks = (offset % BLOCK_SIZE + buf.len()).div_ceil(BLOCK_SIZE);
+        let mut blocks = Buf::alloc(total_nblocks.clamp(1, READ_AT_CHUNK_NBLOCKS))?;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_block = pos % BLOCK_SIZE;
+            let nblocks = (in_block + buf.len() - done)
+                .div_ceil(BLOCK_SIZE)
+                .min(READ_AT_CHUNK_NBLOCKS);
+            let len = (nblocks * BLOCK_SIZE - in_block).min(buf.len() - done);
+
+            let chunk = &mut blocks.as_mut_slice()[..nblocks * BLOCK_SIZE];
+            self.read(pos / BLOCK_SIZE, BufMut::try_from(chunk)?)?;
+            buf[done..done + len].copy_from_slice(&blocks.as_slice()[in_block..in_block + len]);
+            done += len;
+        }
         Ok(())
     }
 
-    /// Handle one block I/O request. Mark the request completed when finished,
-    /// return any error that occurs.
-    pub fn handle_bio_req(&self, req: &BioReq) -> BioResp {
-        let res = match req.type_() {
-            BioType::Read => self.do_read(&req),
-            BioType::Write => self.do_write(&req),
-            BioType::Sync => self.do_sync(&req),
-        };
+    /// Write bytes at a byte offset, the partially covered blocks are
+    /// read-modify-written one by one.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_block = pos % BLOCK_SIZE;
+            if in_block != 0 || buf.len() - done < BLOCK_SIZE {
+                let len = (BLOCK_SIZE - in_block).min(buf.len() - done);
+                self.write_partial_block(pos / BLOCK_SIZE, in_block, &buf[done..done + len])?;
+                done += len;
+            } else {
+                let len = align_down(buf.len() - done, BLOCK_SIZE);
+                let lba = pos / BLOCK_SIZE;
+                let _rguard = self.write_sync_region.read();
+                self.issue_overlapping_discards(lba..lba + len / BLOCK_SIZE)?;
+                self.write_after_discards(lba, BufRef::try_from(&buf[done..done + len])?)?;
+                done += len;
+            }
+        }
+        Ok(())
+    }
 
-        req.complete(res.clone());
-        res
+    /// Read-modify-write the bytes at `offset` within a block, the other
+    /// writes are excluded in between so that none of them is lost.
+    fn write_partial_block(&self, lba: Lba, offset: usize, bytes: &[u8]) -> Result<()> {
+        let mut block = Buf::alloc(1)?;
+        let _wguard = self.write_sync_region.write();
+        // The region is held, so the discards are issued without relocking it
+        self.issue_overlapping_discards(lba..lba + 1)?;
+        self.read_one_block(lba, block.as_mut())?;
+        block.as_mut_slice()[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.write_after_discards(lba, block.as_ref())
     }
 
-    pub fn create_gc_worker(&self, policy_ref: VictimPolicyRef) -> Result<GcWorker<D>> {
-        // Safety: `reverse_index_table` is not None when enable_gc is true
-        let gc_worker = GcWorker::new(
-            policy_ref,
-            self.logical_block_table.clone(),
-            self.reverse_index_table.clone().unwrap(),
-            self.dealloc_table.clone(),
-            self.tx_log_store.clone(),
-            self.block_validity_table.clone(),
-            self.user_data_disk.clone(),
-            self.shared_state.clone(),
-            self.is_active.clone(),
-        );
-        Ok(gc_worker)
+    /// Write barrier, the blocks buffered in `DataBuf` are written to the
+    /// device before any following write, but not committed.
+    pub fn flush(&self) -> Result<()> {
+        let _wguard = self.write_sync_region.write();
+        // flush_data_buf will wait for background GC to finish
+        self.flush_data_buf()?;
+        self.user_data_disk.flush()
     }
 
-    /// Handle a read I/O request.
-    fn do_read(&self, req: &BioReq) -> BioResp {
-        debug_assert_eq!(req.type_(), BioType::Read);
+    /// Commit the records of the blocks written to the device so far,
+    /// without draining `DataBuf`.
+    fn commit_records(&self) -> Result<()> {
+        if self.config.ephemeral {
+            return Ok(());
+        }
+        // A tombstone never lags behind the deletion of the records
+        if let Some(discard_log) = &self.discard_log {
+            discard_log.persist()?;
+        }
+        // The blocks must be durable before their records
+        self.user_data_disk.flush()?;
+        self.logical_block_table.sync()?;
+        // A decrement never leads the drop of its record
+        if let Some(ref_counts) = &self.ref_counts {
+            ref_counts.persist()?;
+        }
+        self.maybe_compact_bvt()?;
+        self.tx_log_store.sync()
+    }
 
-        let lba = req.addr() as Lba;
-        let mut req_bufs = req.take_bufs();
-        let mut bufs = {
-            let mut bufs = Vec::with_capacity(req.nbufs());
-            for buf in req_bufs.iter_mut() {
-                bufs.push(BufMut::try_from(buf.as_mut_slice())?);
+    /// Write and index a snapshot of the blocks in `DataBuf`. The snapshot
+    /// stays readable until indexed, while the buffer accepts new blocks and
+    /// serves reads of the others without waiting for the flush.
+    fn flush_data_buf(&self) -> Result<()> {
+        let _flush_guard = self.flush_lock.lock().unwrap();
+        let data_blocks = self.data_buf.take_snapshot();
+        trace_span!("flush_data_buf", nblocks = data_blocks.len());
+        let data_blocks: Vec<_> = data_blocks
+            .iter()
+            .map(|(key, data_block)| (*key, data_block.as_slice()))
+            .collect();
+        if let Err(e) = self.write_and_index_blocks(&data_blocks, false) {
+            // Keep the blocks buffered for the next flush
+            self.data_buf.restore_snapshot();
+            return Err(e);
+        }
+
+        self.scheduler.mark_active();
+        self.data_buf.drop_snapshot();
+        Ok(())
+    }
+
+    /// Write the data blocks to disk, then insert their records into
+    /// the logical block table (and the reverse index table). The records
+    /// are put to the logical block table bypassing the WAL if `relaxed`.
+    fn write_and_index_blocks(
+        &self,
+        data_blocks: &[(RecordKey, &[u8])],
+        relaxed: bool,
+    ) -> Result<()> {
+        self.wait_for_background_gc()?;
+
+        let mut ret = self.write_blocks(data_blocks);
+
+        if let Err(e) = ret.as_ref() {
+            // The blocks of the dropped records are deallocated by compactions,
+            // which may free enough blocks beyond the reserve for GC
+            if matches!(e.errno(), OutOfDisk | NoSpaceLeft) {
+                // Keep background GC from slipping in between the compactions
+                // and the retried writes, which may starve the writes
+                let _priority = self.shared_state.prioritize_compaction();
+                self.logical_block_table.manual_compaction()?;
+                // try write again
+                ret = self.write_blocks(data_blocks);
+
+                if let Err(e) = ret.as_ref() {
+                    if matches!(e.errno(), OutOfDisk | NoSpaceLeft) {
+                        self.logical_block_table.force_compaction()?;
+                        // try write again
+                        ret = self.write_blocks(data_blocks);
+                    }
+                }
             }
-            bufs
-        };
+        }
 
-        if bufs.len() == 1 {
-            let buf = bufs.remove(0);
-            return self.read(lba, buf);
+        let records = ret?;
+
+        if self.config.ordered_data_writes && !records.is_empty() {
+            let timer = if self.config.stat_cost {
+                Some(COST_L3.time(CostL3Type::BlockIO))
+            } else {
+                None
+            };
+            // The data blocks must be durable before any record referring to them
+            self.user_data_disk.flush()?;
+            drop(timer);
         }
+        crate::chaos_point!(DataWritten);
 
-        self.readv(lba, &mut bufs)
+        let timer = if self.config.stat_cost {
+            Some(COST_L3.time(CostL3Type::LogicalBlockTable))
+        } else {
+            None
+        };
+        if !self.config.delayed_reclamation {
+            for (key, _) in records.iter() {
+                // ignore this error
+                let _ = self.logical_block_table.get(&key);
+            }
+        }
+        // Insert new records of data blocks to `TxLsmTree`, as a WAL group
+        // TODO: Error handling: Should dealloc the written blocks
+        if relaxed {
+            self.logical_block_table.put_batch_unlogged(&records)?;
+        } else {
+            self.logical_block_table.put_batch(&records)?;
+        }
+        self.digest_tree
+            .invalidate_lbas(records.iter().map(|(key, _)| key.lba));
+        if let Some(reverse_index_table) = &self.reverse_index_table {
+            let reverse_records: Vec<_> = records
+                .iter()
+                .map(|(key, value)| (ReverseKey { hba: value.hba }, ReverseValue { lba: key.lba }))
+                .collect();
+            reverse_index_table.put_batch(&reverse_records)?;
+        }
+
+        drop(timer);
+        Ok(())
     }
 
-    /// Handle a write I/O request.
-    fn do_write(&self, req: &BioReq) -> BioResp {
-        debug_assert_eq!(req.type_(), BioType::Write);
+    fn write_blocks(
+        &self,
+        data_blocks: &[(RecordKey, &[u8])],
+    ) -> Result<Vec<(RecordKey, RecordValue)>> {
+        let num_write = data_blocks.len();
+        let mut records = Vec::with_capacity(num_write);
+        if num_write == 0 {
+            return Ok(records);
+        }
+        // The records are committed by the next sync
+        let sync_id = self.logical_block_table.sync_id() + 1;
+        let timer = if self.config.stat_cost {
+            Some(COST_L3.time(CostL3Type::Allocation))
+        } else {
+            None
+        };
+        // Allocate slots for data blocks
+        let hbas = self
+            .block_validity_table
+            .alloc_batch(NonZeroUsize::new(num_write).unwrap())?;
+        debug_assert_eq!(hbas.len(), num_write);
+        drop(timer);
+        let hba_batches = hbas.group_by(|hba1, hba2| hba2 - hba1 == 1);
 
-        let lba = req.addr() as Lba;
-        let req_bufs = req.take_bufs();
-        let bufs = {
-            let mut bufs = Vec::with_capacity(req.nbufs());
-            for buf in req_bufs.iter() {
-                bufs.push(BufRef::try_from(buf.as_slice())?);
+        let first_nonce = match self.crypto_mode {
+            BlockCryptoMode::RandomKey => 0,
+            BlockCryptoMode::PerBlockNonce | BlockCryptoMode::DerivedKey => {
+                self.alloc_nonces(num_write)?
             }
-            bufs
         };
 
-        self.writev(lba, &bufs)
+        // Perform encryption and batch disk write
+        let mut cipher_buf = Buf::alloc(num_write)?;
+        let mut cipher_slice = cipher_buf.as_mut_slice();
+        let mut nth = 0;
+        for hba_batch in hba_batches {
+            let timer = if self.config.stat_cost {
+                Some(COST_L3.time(CostL3Type::Encryption))
+            } else {
+                None
+            };
+            let batch_blocks = &data_blocks[nth..nth + hba_batch.len()];
+            let secrets_and_macs = self.encrypt_blocks(
+                first_nonce + nth as u64,
+                batch_blocks,
+                &mut cipher_slice[..hba_batch.len() * BLOCK_SIZE],
+            )?;
+            #[cfg_attr(not(feature = "debug_crc"), allow(unused_variables))]
+            for ((&hba, (lba, block)), (key, mac)) in hba_batch
+                .iter()
+                .zip(batch_blocks.iter())
+                .zip(secrets_and_macs.into_iter())
+            {
+                records.push((
+                    *lba,
+                    RecordValue {
+                        hba,
+                        key,
+                        mac,
+                        sync_id,
+                        #[cfg(feature = "debug_crc")]
+                        crc: crate::util::crc32(block) as u64,
+                    },
+                ));
+            }
+            nth += hba_batch.len();
+            drop(timer);
+
+            let timer = if self.config.stat_cost {
+                Some(COST_L3.time(CostL3Type::BlockIO))
+            } else {
+                None
+            };
+            self.user_data_disk.write(
+                *hba_batch.first().unwrap(),
+                BufRef::try_from(&cipher_slice[..hba_batch.len() * BLOCK_SIZE]).unwrap(),
+            )?;
+            drop(timer);
+            self.stats
+                .record_bio(|stats| stats.record_write_batch(hba_batch.len()));
+            cipher_slice = &mut cipher_slice[hba_batch.len() * BLOCK_SIZE..];
+        }
+
+        Ok(records)
     }
 
-    /// Handle a sync I/O request.
-    fn do_sync(&self, req: &BioReq) -> BioResp {
-        debug_assert_eq!(req.type_(), BioType::Sync);
-        self.sync()
+    /// Encrypt consecutive user data blocks in a batch according to the
+    /// crypto mode, the nth block uses the nonce (or epoch) `first_nonce + n`.
+    /// Returns the per-block secrets (key, nonce or epoch) to be stored in
+    /// `RecordValue` and the MACs.
+    fn encrypt_blocks(
+        &self,
+        first_nonce: u64,
+        data_blocks: &[(RecordKey, &[u8])],
+        cipher: &mut [u8],
+    ) -> Result<Vec<(Key, Mac)>> {
+        let nblocks = data_blocks.len();
+        let nonce_secret = |nth: usize| {
+            let mut secret = Key::new_zeroed();
+            secret[..size_of::<u64>()].copy_from_slice(&(first_nonce + nth as u64).to_le_bytes());
+            secret
+        };
+        let (secrets, ivs): (Vec<Key>, Vec<Iv>) = match self.crypto_mode {
+            BlockCryptoMode::RandomKey => (0..nblocks)
+                .map(|_| (Key::random(), Iv::new_zeroed()))
+                .unzip(),
+            BlockCryptoMode::PerBlockNonce => (0..nblocks)
+                .map(|nth| {
+                    let secret = nonce_secret(nth);
+                    let iv = Iv::from_bytes(&secret[..size_of::<Iv>()]);
+                    (secret, iv)
+                })
+                .unzip(),
+            BlockCryptoMode::DerivedKey => (0..nblocks)
+                .map(|nth| (nonce_secret(nth), Iv::new_zeroed()))
+                .unzip(),
+        };
+        let derived_keys = match self.crypto_mode {
+            BlockCryptoMode::DerivedKey => data_blocks
+                .iter()
+                .enumerate()
+                .map(|(nth, (key, _))| self.derive_block_key(key.lba, first_nonce + nth as u64))
+                .collect::<Result<Vec<_>>>()?,
+            _ => Vec::new(),
+        };
+        let keys: Vec<&Key> = match self.crypto_mode {
+            BlockCryptoMode::RandomKey => secrets.iter().collect(),
+            BlockCryptoMode::PerBlockNonce => vec![&self.data_key; nblocks],
+            BlockCryptoMode::DerivedKey => derived_keys.iter().collect(),
+        };
+
+        let plains: Vec<&[u8]> = data_blocks.iter().map(|(_, block)| *block).collect();
+        let block_aads: Vec<BlockAad> = data_blocks
+            .iter()
+            .map(|(key, _)| self.block_aad(key.lba))
+            .collect();
+        let aads: Vec<&[u8]> = block_aads.iter().map(BlockAad::as_slice).collect();
+        let mut ciphers: Vec<&mut [u8]> = cipher.chunks_mut(BLOCK_SIZE).collect();
+        let mut macs = vec![Mac::new_zeroed(); nblocks];
+        self.aead
+            .encrypt_batch(&plains, &keys, &ivs, &aads, &mut ciphers, &mut macs)?;
+        Ok(secrets.into_iter().zip(macs).collect())
     }
 
-    // TODO: Currently, Background GC will block foreground I/O requests, but background gc will be launched when some foreground I/O requests remain running.
-    // this might cause some issue
+    /// Returns the associated data of the user data block of `lba`.
+    fn block_aad(&self, lba: Lba) -> BlockAad {
+        BlockAad::new(lba, self.disk_id.as_ref())
+    }
 
-    // GcWorker will touch block_validity_table, logical_block_table and reverse_index_table and user_data_disk.
-    // In the stop the world manner. to maximize the concurrency, we should call this function after accessing data_buf and before accessing these data structures.
-    // To simplify the implementation, we should only call this function in some fn related to accessing these data structures directly.
-    // E.g. fn flush_data_buf(), read_one_block(), read_multi_blocks()
-    #[inline]
-    fn wait_for_background_gc(&self) {
-        // Fast path: skip waiting if GC is disabled
-        if !CONFIG.get().enable_gc {
-            return;
-        }
-        self.shared_state.wait_for_background_gc();
+    /// Decrypt the user data block of `lba` with its record according to
+    /// the crypto mode.
+    fn decrypt_block(
+        &self,
+        lba: Lba,
+        value: &RecordValue,
+        cipher: &[u8],
+        plain: &mut [u8],
+    ) -> Result<()> {
+        self.do_decrypt_block(lba, value, cipher, plain)?;
+        #[cfg(feature = "debug_crc")]
+        check_plain_crc(value, plain, self.config.corruption_handler.as_ref())?;
+        Ok(())
     }
-}
 
-impl<D: BlockSet> Drop for SwornDisk<D> {
-    fn drop(&mut self) {
-        self.inner.is_dropped.store(true, Ordering::Release);
+    fn do_decrypt_block(
+        &self,
+        lba: Lba,
+        value: &RecordValue,
+        cipher: &[u8],
+        plain: &mut [u8],
+    ) -> Result<()> {
+        let aad = self.block_aad(lba);
+        match self.crypto_mode {
+            BlockCryptoMode::RandomKey => self.aead.decrypt(
+                cipher,
+                &value.key,
+                &Iv::new_zeroed(),
+                aad.as_slice(),
+                &value.mac,
+                plain,
+            ),
+            BlockCryptoMode::PerBlockNonce => {
+                let iv = Iv::from_bytes(&value.key[..size_of::<Iv>()]);
+                self.aead.decrypt(
+                    cipher,
+                    &self.data_key,
+                    &iv,
+                    aad.as_slice(),
+                    &value.mac,
+                    plain,
+                )
+            }
+            BlockCryptoMode::DerivedKey => {
+                let epoch = u64::from_bytes(&value.key[..size_of::<u64>()]);
+                let key = self.derive_block_key(lba, epoch)?;
+                self.aead.decrypt(
+                    cipher,
+                    &key,
+                    &Iv::new_zeroed(),
+                    aad.as_slice(),
+                    &value.mac,
+                    plain,
+                )
+            }
+        }
     }
-}
 
-impl<D: BlockSet + 'static> Debug for SwornDisk<D> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("SwornDisk")
-            .field("user_data_nblocks", &self.inner.user_data_disk.nblocks())
-            .field("logical_block_table", &self.inner.logical_block_table)
-            .finish()
+    /// Derive the key of a user data block under `BlockCryptoMode::DerivedKey`
+    /// from the data key, its LBA and the epoch it's written in.
+    ///
+    /// The AEAD keystream under the data key and the IV made of (the low
+    /// 48 bits of) the epoch and the LBA serves as the PRF, so the derived
+    /// key never repeats as long as the epochs don't wrap. The key doesn't
+    /// depend on where the block resides, so GC moves it verbatim.
+    fn derive_block_key(&self, lba: Lba, epoch: u64) -> Result<Key> {
+        const HALF_IV_SIZE: usize = size_of::<Iv>() / 2;
+        let mut iv = Iv::new_zeroed();
+        iv[..HALF_IV_SIZE].copy_from_slice(&epoch.to_le_bytes()[..HALF_IV_SIZE]);
+        iv[HALF_IV_SIZE..].copy_from_slice(&(lba as u64).to_le_bytes()[..HALF_IV_SIZE]);
+        let mut key = Key::new_zeroed();
+        let _ = self.aead.encrypt(
+            Key::new_zeroed().as_bytes(),
+            &self.data_key,
+            &iv,
+            &[],
+            key.as_bytes_mut(),
+        )?;
+        Ok(key)
     }
-}
 
-/// A wrapper for `[BufMut]` used in `readv()`.
-struct BufMutVec<'a> {
-    bufs: &'a mut [BufMut<'a>],
-    nblocks: usize,
-}
+    /// Decrypt a user data block, if the block fails the integrity check,
+    /// retry with a redundant copy from the underlying disk (if any), which
+    /// also repairs the corrupted copy.
+    fn decrypt_or_repair_block(
+        &self,
+        lba: Lba,
+        value: &RecordValue,
+        cipher: &[u8],
+        plain: &mut [u8],
+    ) -> Result<()> {
+        let err = match self.decrypt_block(lba, value, cipher, plain) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
 
-impl<'a> BufMutVec<'a> {
-    pub fn from_bufs(bufs: &'a mut [BufMut<'a>]) -> Self {
-        debug_assert!(bufs.len() > 0);
-        let nblocks = bufs
-            .iter()
-            .map(|buf| buf.nblocks())
-            .fold(0_usize, |sum, nblocks| sum.saturating_add(nblocks));
-        Self { bufs, nblocks }
+        // The redundant copy is verified before it repairs the corrupted one
+        let mut redundant_cipher = Buf::alloc(1)?;
+        if self
+            .user_data_disk
+            .read_redundant(value.hba, redundant_cipher.as_mut())
+            .is_err()
+        {
+            return Err(err);
+        }
+        self.decrypt_block(lba, value, redundant_cipher.as_slice(), plain)?;
+        self.user_data_disk
+            .repair(value.hba, redundant_cipher.as_ref())?;
+        #[cfg(not(feature = "linux"))]
+        warn!("[SwornDisk] corrupted block {} is repaired", value.hba);
+        Ok(())
     }
 
-    pub fn nblocks(&self) -> usize {
-        self.nblocks
+    /// Decrypt the block of `lba` read with its record `value`. If it fails
+    /// as GC has remapped the block since `value` was looked up, the old block
+    /// may have been freed and reused, the block is read again at its new HBA.
+    fn decrypt_or_reread_block(
+        &self,
+        lba: Lba,
+        value: &RecordValue,
+        cipher: &[u8],
+        plain: &mut [u8],
+    ) -> Result<()> {
+        let err = match self.decrypt_or_repair_block(lba, value, cipher, plain) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+
+        self.wait_for_background_gc()?;
+        let new_value = match self.logical_block_table.get(&RecordKey { lba }) {
+            Ok(new_value) if new_value.hba != value.hba => new_value,
+            _ => return Err(err),
+        };
+        #[cfg(not(feature = "linux"))]
+        debug!(
+            "[SwornDisk] block {lba} is remapped from {} to {} during read, read again",
+            value.hba, new_value.hba
+        );
+        let mut new_cipher = Buf::alloc(1)?;
+        self.user_data_disk
+            .read(new_value.hba, new_cipher.as_mut())?;
+        self.stats.record_bio(|stats| stats.record_read_batch(1));
+        self.decrypt_or_repair_block(lba, &new_value, new_cipher.as_slice(), plain)
     }
 
-    pub fn nth_buf_mut_slice(&mut self, mut nth: usize) -> &mut [u8] {
-        debug_assert!(nth < self.nblocks);
-        for buf in self.bufs.iter_mut() {
-            let nblocks = buf.nblocks();
-            if nth >= buf.nblocks() {
-                nth -= nblocks;
-            } else {
-                return &mut buf.as_mut_slice()[nth * BLOCK_SIZE..(nth + 1) * BLOCK_SIZE];
+    /// Allocate `count` consecutive per-block nonces, returns the first one.
+    ///
+    /// Nonces are reserved in the superblock ahead of use, so that no nonce
+    /// is reused under the same data key even if the disk crashes.
+    fn alloc_nonces(&self, count: usize) -> Result<u64> {
+        let first_nonce = self.next_nonce.fetch_add(count as u64, Ordering::AcqRel);
+        let end_nonce = first_nonce + count as u64;
+
+        let mut superblock = self.superblock.lock();
+        if end_nonce > superblock.nonce_limit() {
+            let mut new_superblock = *superblock;
+            new_superblock.set_nonce_limit(end_nonce + NONCE_RESERVE);
+            // The data key of an ephemeral disk never outlives the memory
+            if !self.config.ephemeral {
+                new_superblock.persist(&self.superblock_disk, &self.root_key)?;
             }
+            *superblock = new_superblock;
         }
-        &mut []
+        Ok(first_nonce)
     }
-}
 
-// SAFETY: `SwornDisk` is concurrency-safe.
-unsafe impl<D: BlockSet> Send for DiskInner<D> {}
-unsafe impl<D: BlockSet> Sync for DiskInner<D> {}
+    /// Update the wrapped root key in the superblock.
+    fn update_wrapped_root_key(&self, wrapped_root_key: &[u8]) -> Result<()> {
+        if self.config.ephemeral {
+            return_errno_with_msg!(
+                Unsupported,
+                "the root key of ephemeral disk is never persisted"
+            );
+        }
+        let mut superblock = self.superblock.lock();
+        let mut new_superblock = *superblock;
+        new_superblock.set_wrapped_root_key(wrapped_root_key)?;
+        new_superblock.persist(&self.superblock_disk, &self.root_key)?;
+        *superblock = new_superblock;
+        Ok(())
+    }
 
-/// Listener factory for `TxLsmTree`.
-struct TxLsmTreeListenerFactory<D> {
-    store: Arc<TxLogStore<D>>,
-    alloc_table: Arc<AllocTable>,
-    dealloc_table: Arc<DeallocTable>,
+    /// Sync all cached data in the device in a group commit, which may be
+    /// shared with other concurrent syncs.
+    fn group_sync(&self) -> Result<()> {
+        self.sync_group.sync(|| {
+            let _wguard = self.write_sync_region.write();
+            self.sync()
+        })
+    }
+
+    /// Sync all cached data in the device to the storage medium for durability.
+    pub fn sync(&self) -> Result<()> {
+        trace_span!("sync");
+        self.issue_discards(self.discard_queue.take_all())?;
+        // flush_data_buf will wait for background GC to finish
+        self.flush_data_buf()?;
+        debug_assert!(self.data_buf.is_empty());
+        if self.config.ephemeral {
+            return Ok(());
+        }
+
+        // A tombstone never lags behind the deletion of the records
+        if let Some(discard_log) = &self.discard_log {
+            discard_log.persist()?;
+        }
+        if self.config.sync_atomicity {
+            // Sync the reverse index first, so that every synced logical
+            // record has its reverse entry synced as well
+            if let Some(reverse_index_table) = &self.reverse_index_table {
+                reverse_index_table.sync()?;
+            }
+            self.logical_block_table.sync()?;
+        }
+        // A decrement never leads the drop of its record
+        if let Some(ref_counts) = &self.ref_counts {
+            ref_counts.persist()?;
+        }
+
+        let timer = if self.config.stat_cost {
+            Some(COST_L3.time(CostL3Type::Allocation))
+        } else {
+            None
+        };
+        self.maybe_compact_bvt()?;
+        drop(timer);
+
+        if self.config.persist_stats {
+            persist_stats(&self.tx_log_store, &self.stats)?;
+        }
+
+        self.tx_log_store.sync()?;
+
+        let timer = if self.config.stat_cost {
+            Some(COST_L3.time(CostL3Type::BlockIO))
+        } else {
+            None
+        };
+        self.user_data_disk.flush()?;
+        drop(timer);
+
+        self.advance_freshness()?;
+        self.events.emit(DiskEvent::SnapshotCreated {
+            sync_id: self.logical_block_table.sync_id(),
+        });
+        Ok(())
+    }
+
+    /// Compact the block validity table on a sync if it's due by
+    /// `Config::bvt_compaction`.
+    fn maybe_compact_bvt(&self) -> Result<()> {
+        let table = &self.block_validity_table;
+        let nsyncs = self
+            .syncs_since_bvt_compaction
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        let is_due = table.has_unlogged_frees()
+            || table.has_many_segment_diff_logs()
+            || match self.config.bvt_compaction {
+                BvtCompactionPolicy::EverySync => true,
+                BvtCompactionPolicy::EveryNSyncs(n) => nsyncs >= n,
+                BvtCompactionPolicy::DirtyBytes(nbytes) => table.logged_diff_bytes() >= nbytes,
+                BvtCompactionPolicy::Background => false,
+                BvtCompactionPolicy::Auto => table.logged_diff_bytes() >= table.compacted_size(),
+            };
+        if !is_due {
+            return Ok(());
+        }
+        self.compact_bvt()
+    }
+
+    /// Compact the block validity table, i.e., persist it as a whole
+    /// and delete its `BAL` logs.
+    fn compact_bvt(&self) -> Result<()> {
+        self.block_validity_table
+            .do_compaction(&self.tx_log_store)?;
+        self.syncs_since_bvt_compaction.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Register `BvtCompactor` to the scheduler and start it.
+    fn start_bvt_compactor(inner: &Arc<Self>) {
+        inner.scheduler.add_task(
+            Arc::new(BvtCompactor {
+                disk: Arc::downgrade(inner),
+            }),
+            TaskPriority::Idle,
+        );
+        inner.scheduler.start();
+    }
+
+    /// Register `DiscardIssuer` to the scheduler and start it.
+    fn start_discard_issuer(inner: &Arc<Self>) {
+        inner.scheduler.add_task(
+            Arc::new(DiscardIssuer {
+                disk: Arc::downgrade(inner),
+            }),
+            TaskPriority::Idle,
+        );
+        inner.scheduler.start();
+    }
+
+    /// Cross-check the most recent records of the logical block table against
+    /// the reverse index, re-insert the reverse entries that are missing or stale.
+    ///
+    /// A crash between the two puts in `flush_data_buf` (or between the syncs
+    /// of the two trees) leaves logical records without reverse entries.
+    /// Only the records replayed from the WAL can be affected, the older ones
+    /// have been compacted along with their reverse entries.
+    ///
+    /// Return the number of repaired reverse entries.
+    fn repair_reverse_index(&self) -> Result<usize> {
+        let Some(reverse_index_table) = &self.reverse_index_table else {
+            return Ok(0);
+        };
+
+        let records = self.logical_block_table.recent_records();
+        let reverse_keys: Vec<_> = records
+            .iter()
+            .map(|(_, value)| ReverseKey { hba: value.hba })
+            .collect();
+        let reverse_values = reverse_index_table.get_multi(&reverse_keys)?;
+
+        let mut num_repaired = 0;
+        for ((key, _), (reverse_key, reverse_value)) in records
+            .iter()
+            .zip(reverse_keys.into_iter().zip(reverse_values))
+        {
+            if reverse_value.is_some_and(|value| value.lba == key.lba) {
+                continue;
+            }
+            reverse_index_table.put(reverse_key, ReverseValue { lba: key.lba })?;
+            num_repaired += 1;
+        }
+
+        if num_repaired > 0 {
+            reverse_index_table.sync()?;
+            #[cfg(not(feature = "linux"))]
+            warn!("[SwornDisk] Repaired {num_repaired} reverse index entries");
+        }
+        Ok(num_repaired)
+    }
+
+    /// Advance the freshness counter sealed in the superblock and then the
+    /// trusted counter, once all the synced data are durable.
+    fn advance_freshness(&self) -> Result<()> {
+        let Some(counter) = &self.trusted_counter else {
+            return Ok(());
+        };
+        let mut superblock = self.superblock.lock();
+        let mut new_superblock = *superblock;
+        new_superblock.set_freshness(superblock.freshness() + 1);
+        new_superblock.persist(&self.superblock_disk, &self.root_key)?;
+        *superblock = new_superblock;
+        counter.advance(superblock.freshness())
+    }
+
+    /// Delay the submission of a block I/O request until it's within the budget.
+    fn throttle_bio_req(&self, req: &BioReq) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.throttle_bio_req(req);
+        }
+    }
+
+    /// Admit an asynchronous block I/O request if it's within the budget,
+    /// otherwise park it until `unpark_bio_reqs()` takes it.
+    fn admit_or_park_bio_req(&self, req: BioReq) -> Option<BioReq> {
+        match &self.rate_limiter {
+            Some(rate_limiter) => rate_limiter.admit_or_park(req),
+            None => Some(req),
+        }
+    }
+
+    /// Take the parked block I/O requests that are within the budget now.
+    fn unpark_bio_reqs(&self) -> Vec<BioReq> {
+        self.rate_limiter
+            .as_ref()
+            .map_or_else(Vec::new, |rate_limiter| rate_limiter.unpark())
+    }
+
+    /// Handle one block I/O request. Mark the request completed when finished,
+    /// return any error that occurs.
+    pub fn handle_bio_req(&self, req: &BioReq) -> BioResp {
+        let started_at = rdtsc();
+        let urgent_guard = req
+            .is_urgent_read()
+            .then(|| self.shared_state.begin_urgent_read());
+        let res = match req.type_() {
+            BioType::Write | BioType::Sync | BioType::Flush if self.read_only => Err(
+                Error::with_msg(PermissionDenied, "sworndisk is opened read-only"),
+            ),
+            BioType::Read => self.do_read(&req),
+            BioType::Write => self.do_write(&req),
+            BioType::Sync => self.do_sync(&req),
+            BioType::Flush => self.do_flush(&req),
+        };
+        drop(urgent_guard);
+        self.stats.record_bio(|stats| {
+            if req.is_urgent_read() {
+                stats.record_urgent_read(req.submitted_at(), started_at)
+            } else {
+                stats.record_completion(req.type_(), req.submitted_at(), started_at)
+            }
+        });
+
+        req.complete(res.clone());
+        res
+    }
+
+    /// Handle the block I/O requests merged by `BioReqQueue::dequeue_merged()`
+    /// as one request. All the requests are completed with the same response.
+    pub fn handle_merged_bio_reqs(&self, reqs: &[BioReq]) -> BioResp {
+        if reqs.len() == 1 {
+            return self.handle_bio_req(&reqs[0]);
+        }
+
+        let started_at = rdtsc();
+        let type_ = reqs[0].type_();
+        let lba = reqs[0].addr() as Lba;
+        let mut req_bufs: Vec<_> = reqs.iter().flat_map(|req| req.take_bufs()).collect();
+        let res = match type_ {
+            BioType::Write if self.read_only => Err(Error::with_msg(
+                PermissionDenied,
+                "sworndisk is opened read-only",
+            )),
+            BioType::Read => self.do_merged_read(lba, &mut req_bufs),
+            BioType::Write => self.do_merged_write(lba, &req_bufs),
+            _ => unreachable!("only reads and writes are merged"),
+        };
+
+        for req in reqs {
+            self.stats
+                .record_bio(|stats| stats.record_completion(type_, req.submitted_at(), started_at));
+            req.complete(res.clone());
+        }
+        res
+    }
+
+    /// Migrates the user data blocks at or beyond `limit` to the ones before,
+    /// see `GcWorker::evacuate()`. The background GC is stopped for good, so
+    /// that it never migrates the blocks back.
+    fn evacuate(&self, limit: Hba) -> Result<usize> {
+        self.scheduler.shutdown();
+        let gc_worker = self.create_gc_worker(self.config.get_victim_policy())?;
+        let num_migrated = {
+            let _gc_guard = self.shared_state.begin_gc();
+            gc_worker.evacuate(limit)?
+        };
+        // Compact the out-of-date records of the evacuated blocks out of the
+        // WAL and the `MemTable`, so that they're never replayed once shrunk
+        self.logical_block_table.force_compaction()?;
+        Ok(num_migrated)
+    }
+
+    pub fn create_gc_worker(&self, policy_ref: VictimPolicyRef) -> Result<GcWorker<D>> {
+        // Safety: `reverse_index_table` is not None when enable_gc is true
+        let gc_worker = GcWorker::new(
+            policy_ref,
+            self.logical_block_table.clone(),
+            self.reverse_index_table.clone().unwrap(),
+            self.dealloc_table.clone(),
+            self.tx_log_store.clone(),
+            self.block_validity_table.clone(),
+            self.user_data_disk.clone(),
+            self.shared_state.clone(),
+            (self.config.reencrypt_on_gc && self.crypto_mode == BlockCryptoMode::RandomKey)
+                .then(|| self.aead.clone()),
+            self.disk_id,
+            self.digest_tree.clone(),
+            self.background_io_limiter.clone(),
+            self.config.clone(),
+            self.stats.clone(),
+            self.events.clone(),
+        );
+        Ok(gc_worker)
+    }
+
+    /// Handle a read I/O request.
+    fn do_read(&self, req: &BioReq) -> BioResp {
+        debug_assert_eq!(req.type_(), BioType::Read);
+
+        let lba = req.addr() as Lba;
+        let mut req_bufs = req.take_bufs();
+        let mut bufs = {
+            let mut bufs = Vec::with_capacity(req.nbufs());
+            for buf in req_bufs.iter_mut() {
+                bufs.push(BufMut::try_from(buf.as_mut_slice())?);
+            }
+            bufs
+        };
+
+        if bufs.len() == 1 {
+            let buf = bufs.remove(0);
+            return self.read(lba, buf);
+        }
+
+        self.readv(lba, &mut bufs)
+    }
+
+    /// Handle a write I/O request.
+    fn do_write(&self, req: &BioReq) -> BioResp {
+        debug_assert_eq!(req.type_(), BioType::Write);
+
+        let lba = req.addr() as Lba;
+        let req_bufs = req.take_bufs();
+        let bufs = {
+            let mut bufs = Vec::with_capacity(req.nbufs());
+            for buf in req_bufs.iter() {
+                bufs.push(BufRef::try_from(buf.as_slice())?);
+            }
+            bufs
+        };
+
+        if req.is_fua() {
+            self.write_fua(lba, &bufs)
+        } else {
+            self.writev(lba, &bufs)
+        }
+    }
+
+    /// Handle the buffers of merged read I/O requests.
+    fn do_merged_read(&self, lba: Lba, req_bufs: &mut [BlockBuf]) -> BioResp {
+        let mut bufs = Vec::with_capacity(req_bufs.len());
+        for buf in req_bufs.iter_mut() {
+            bufs.push(BufMut::try_from(buf.as_mut_slice())?);
+        }
+        self.readv(lba, &mut bufs)
+    }
+
+    /// Handle the buffers of merged write I/O requests.
+    fn do_merged_write(&self, lba: Lba, req_bufs: &[BlockBuf]) -> BioResp {
+        let mut bufs = Vec::with_capacity(req_bufs.len());
+        for buf in req_bufs.iter() {
+            bufs.push(BufRef::try_from(buf.as_slice())?);
+        }
+        self.writev(lba, &bufs)
+    }
+
+    /// Handle a sync I/O request.
+    fn do_sync(&self, req: &BioReq) -> BioResp {
+        debug_assert_eq!(req.type_(), BioType::Sync);
+        self.group_sync()
+    }
+
+    /// Handle a flush I/O request.
+    fn do_flush(&self, req: &BioReq) -> BioResp {
+        debug_assert_eq!(req.type_(), BioType::Flush);
+        self.flush()
+    }
+
+    // TODO: Currently, Background GC will block foreground I/O requests, but background gc will be launched when some foreground I/O requests remain running.
+    // this might cause some issue
+
+    // GcWorker will touch block_validity_table, logical_block_table and reverse_index_table and user_data_disk.
+    // In the stop the world manner. to maximize the concurrency, we should call this function after accessing data_buf and before accessing these data structures.
+    // To simplify the implementation, we should only call this function in some fn related to accessing these data structures directly.
+    // E.g. fn flush_data_buf(), read_one_block(), read_multi_blocks()
+    //
+    // A wait longer than `Config::hang_timeout` is reported as a hang, and
+    // fails with `Timeout` if `Config::fail_on_hang` is set.
+    #[inline]
+    fn wait_for_background_gc(&self) -> Result<()> {
+        // Fast path: skip waiting if GC is disabled
+        if !self.config.enable_gc {
+            return Ok(());
+        }
+        let Some(timeout) = self.config.hang_timeout else {
+            self.shared_state.wait_for_background_gc();
+            return Ok(());
+        };
+        if self.shared_state.wait_for_background_gc_timeout(timeout) {
+            return Ok(());
+        }
+        self.report_hang(timeout);
+        if self.config.fail_on_hang {
+            return_errno_with_msg!(Timeout, "wait for background GC timed out");
+        }
+        self.shared_state.wait_for_background_gc();
+        Ok(())
+    }
+
+    // Dump the states which a hung foreground I/O may wait on to the log
+    #[cold]
+    fn report_hang(&self, _timeout: core::time::Duration) {
+        #[cfg(not(feature = "linux"))]
+        {
+            let gc_stats = self.stats.gc().get_stats();
+            error!(
+                "[SwornDisk] I/O waited for background GC over {:?}: gc_in_progress: {}, \
+                 compaction_in_progress: {}, latency_critical: {}, queued bios: {}, \
+                 buffered blocks: {}, gc rounds: {} (failed: {}, {} in a row)",
+                _timeout,
+                self.shared_state.is_gc_in_progress(),
+                self.shared_state.is_compaction_in_progress(),
+                self.shared_state.is_latency_critical(),
+                self.bio_req_queue.num_reqs(),
+                self.data_buf.nblocks(),
+                gc_stats.num_rounds,
+                gc_stats.num_failed_rounds,
+                gc_stats.consecutive_failed_rounds,
+            );
+        }
+    }
+}
+
+impl<D: BlockSet> Drop for SwornDisk<D> {
+    fn drop(&mut self) {
+        self.inner.is_dropped.store(true, Ordering::Release);
+    }
+}
+
+impl<D: BlockSet + 'static> Debug for SwornDisk<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SwornDisk")
+            .field("user_data_nblocks", &self.inner.user_data_disk.nblocks())
+            .field("logical_block_table", &self.inner.logical_block_table)
+            .finish()
+    }
+}
+
+/// The background task compacting the block validity table while the
+/// foreground is idle, see `BvtCompactionPolicy::Background`.
+struct BvtCompactor<D: BlockSet> {
+    disk: Weak<DiskInner<D>>,
+}
+
+impl<D: BlockSet + 'static> BackgroundTask for BvtCompactor<D> {
+    fn name(&self) -> &'static str {
+        "bvt_compaction"
+    }
+
+    // The task is removed from the scheduler once the disk is dropped
+    fn run(&self, _ctx: &TaskContext) -> Result<core::time::Duration> {
+        let Some(disk) = self.disk.upgrade() else {
+            return_errno_with_msg!(NotFound, "disk is dropped");
+        };
+        let table = &disk.block_validity_table;
+        if table.logged_diff_bytes() > 0
+            || table.has_unlogged_frees()
+            || table.has_many_segment_diff_logs()
+        {
+            // Excludes the writes and syncs, as a sync does
+            let _wguard = disk.write_sync_region.write();
+            disk.compact_bvt()?;
+            disk.tx_log_store.sync()?;
+        }
+        Ok(BVT_COMPACTION_INTERVAL)
+    }
+}
+
+/// The background task issuing the pending discards while the foreground
+/// is idle, see `Config::discard_granularity`.
+struct DiscardIssuer<D: BlockSet> {
+    disk: Weak<DiskInner<D>>,
+}
+
+impl<D: BlockSet + 'static> BackgroundTask for DiscardIssuer<D> {
+    fn name(&self) -> &'static str {
+        "discard_issue"
+    }
+
+    // The task is removed from the scheduler once the disk is dropped
+    fn run(&self, _ctx: &TaskContext) -> Result<core::time::Duration> {
+        let Some(disk) = self.disk.upgrade() else {
+            return_errno_with_msg!(NotFound, "disk is dropped");
+        };
+        if !disk.discard_queue.is_empty() {
+            let _rguard = disk.write_sync_region.read();
+            disk.issue_discards(disk.discard_queue.take_all())?;
+        }
+        Ok(DISCARD_ISSUE_INTERVAL)
+    }
+}
+
+/// A group commit of sync operations.
+///
+/// Each sync takes a ticket. The sync that finds no commit in progress becomes
+/// the leader, which commits on behalf of all the tickets taken so far, while
+/// the syncs arriving during the commit wait to form the next group. A waiting
+/// sync returns once a successful commit covers its ticket, or retries as the
+/// next leader if the commit fails.
+struct SyncGroup {
+    state: CvarMutex<SyncGroupState>,
+    cvar: Condvar,
+}
+
+struct SyncGroupState {
+    /// The last ticket taken.
+    last_ticket: u64,
+    /// All tickets up to this one are committed.
+    committed: u64,
+    is_committing: bool,
 }
 
-impl<D> TxLsmTreeListenerFactory<D> {
-    fn new(
-        store: Arc<TxLogStore<D>>,
-        alloc_table: Arc<AllocTable>,
-        reverse_index_table: Arc<DeallocTable>,
-    ) -> Self {
-        Self {
-            store,
-            alloc_table,
-            dealloc_table: reverse_index_table,
+impl SyncGroup {
+    fn new() -> Self {
+        Self {
+            state: CvarMutex::new(SyncGroupState {
+                last_ticket: 0,
+                committed: 0,
+                is_committing: false,
+            }),
+            cvar: Condvar::new(),
+        }
+    }
+
+    /// Waits until the sync is committed, either by a leader or by `commit`.
+    fn sync<F: FnOnce() -> Result<()>>(&self, commit: F) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.last_ticket += 1;
+        let ticket = state.last_ticket;
+        while state.is_committing && state.committed < ticket {
+            state = self.cvar.wait(state).unwrap();
+        }
+        if state.committed >= ticket {
+            return Ok(());
+        }
+
+        // Become the leader of the tickets taken so far
+        state.is_committing = true;
+        let group_end = state.last_ticket;
+        drop(state);
+
+        let res = commit();
+
+        let mut state = self.state.lock().unwrap();
+        state.is_committing = false;
+        if res.is_ok() {
+            state.committed = group_end;
+        }
+        drop(state);
+        self.cvar.notify_all();
+        res
+    }
+}
+
+/// A writer to bulk load blocks in ascending LBA order, see
+/// `SwornDisk::bulk_writer()`.
+///
+/// The records of the written blocks are buffered and loaded in batches,
+/// a loaded batch is durable and visible to reads at once. The blocks
+/// written since the last batch are loaded by `finish()`, which syncs the
+/// device then. They are lost if the writer is dropped without `finish()`.
+pub struct BulkWriter<'a, D: BlockSet + 'static> {
+    disk: &'a SwornDisk<D>,
+    /// Excludes the other writes and syncs.
+    _wguard: RwLockWriteGuard<'a, ()>,
+    /// Excludes GC, which would free the written but unindexed blocks.
+    gc_guard: Option<GcGuard<'a>>,
+    /// The records of the written blocks to be loaded.
+    records: Vec<(RecordKey, RecordValue)>,
+    /// The LBA next to the last written block.
+    next_lba: Lba,
+}
+
+impl<'a, D: BlockSet + 'static> BulkWriter<'a, D> {
+    fn new(disk: &'a SwornDisk<D>) -> Result<Self> {
+        let inner = &disk.inner;
+        let wguard = inner.write_sync_region.write();
+        // The buffered blocks would shadow the loaded ones, and the pending
+        // compaction must not be left waiting for the excluded GC
+        inner.sync()?;
+        let gc_guard = inner
+            .config
+            .enable_gc
+            .then(|| inner.shared_state.begin_gc());
+        Ok(Self {
+            disk,
+            _wguard: wguard,
+            gc_guard,
+            records: Vec::new(),
+            next_lba: 0,
+        })
+    }
+
+    /// Write the blocks at `lba`, which must not precede the end of the
+    /// blocks written before.
+    pub fn write(&mut self, lba: Lba, buf: BufRef) -> Result<()> {
+        let nblocks = buf.nblocks();
+        self.disk.check_rw_args(lba, nblocks)?;
+        if lba < self.next_lba {
+            return_errno_with_msg!(InvalidArgs, "bulk writes must be in ascending LBA order");
+        }
+
+        let inner = &self.disk.inner;
+        if inner.config.stat_waf {
+            inner
+                .stats
+                .record_waf(|waf| waf.add_logical(buf.as_slice().len() as u64));
+        }
+        let data_blocks: Vec<_> = buf
+            .as_slice()
+            .chunks(BLOCK_SIZE)
+            .enumerate()
+            .map(|(nth, block)| (RecordKey { lba: lba + nth }, block))
+            .collect();
+        for batch in data_blocks.chunks(BULK_WRITE_NBLOCKS) {
+            let records = inner.write_blocks(batch)?;
+            self.records.extend(records);
+        }
+        self.next_lba = lba + nblocks;
+
+        if self.records.len() >= BULK_LOAD_NRECORDS {
+            self.load_records()?;
+        }
+        Ok(())
+    }
+
+    /// Load the buffered records, then sync the device.
+    pub fn finish(mut self) -> Result<()> {
+        self.load_records()?;
+        // The sync waits for the compactions, which wait for GC
+        drop(self.gc_guard.take());
+        self.disk.inner.sync()
+    }
+
+    /// Load the buffered records into the logical block table (and the
+    /// reverse index table).
+    fn load_records(&mut self) -> Result<()> {
+        if self.records.is_empty() {
+            return Ok(());
+        }
+        let inner = &self.disk.inner;
+        // The blocks must be durable before their records
+        inner.user_data_disk.flush()?;
+
+        // The reverse index is loaded first, so that a crash in between only
+        // leaves the entries of unallocated blocks, which GC never visits
+        if let Some(reverse_index_table) = &inner.reverse_index_table {
+            let mut reverse_records: Vec<_> = self
+                .records
+                .iter()
+                .map(|(key, value)| (ReverseKey { hba: value.hba }, ReverseValue { lba: key.lba }))
+                .collect();
+            reverse_records.sort_unstable_by(|(k1, _), (k2, _)| k1.cmp(k2));
+            reverse_index_table.bulk_load(&reverse_records)?;
+        }
+        inner.logical_block_table.bulk_load(&self.records)?;
+        inner
+            .digest_tree
+            .invalidate_lbas(self.records.iter().map(|(key, _)| key.lba));
+        self.records.clear();
+        Ok(())
+    }
+}
+
+impl<D: BlockSet + 'static> Drop for BulkWriter<'_, D> {
+    // The written blocks whose records are not loaded are freed
+    fn drop(&mut self) {
+        for (_, value) in self.records.drain(..) {
+            self.disk
+                .inner
+                .block_validity_table
+                .set_deallocated(value.hba);
+        }
+    }
+}
+
+/// A wrapper for `[BufMut]` used in `readv()`.
+struct BufMutVec<'a> {
+    bufs: &'a mut [BufMut<'a>],
+    nblocks: usize,
+}
+
+impl<'a> BufMutVec<'a> {
+    pub fn from_bufs(bufs: &'a mut [BufMut<'a>]) -> Self {
+        debug_assert!(bufs.len() > 0);
+        let nblocks = bufs
+            .iter()
+            .map(|buf| buf.nblocks())
+            .fold(0_usize, |sum, nblocks| sum.saturating_add(nblocks));
+        Self { bufs, nblocks }
+    }
+
+    pub fn nblocks(&self) -> usize {
+        self.nblocks
+    }
+
+    pub fn nth_buf_mut_slice(&mut self, mut nth: usize) -> &mut [u8] {
+        debug_assert!(nth < self.nblocks);
+        for buf in self.bufs.iter_mut() {
+            let nblocks = buf.nblocks();
+            if nth >= buf.nblocks() {
+                nth -= nblocks;
+            } else {
+                return &mut buf.as_mut_slice()[nth * BLOCK_SIZE..(nth + 1) * BLOCK_SIZE];
+            }
+        }
+        &mut []
+    }
+}
+
+/// The result of verifying the integrity of a logical block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyResult {
+    /// The ciphertext on disk passes the integrity check.
+    Pass,
+    /// The ciphertext on disk fails the integrity check.
+    Fail,
+    /// The block has never been written.
+    Hole,
+    /// The block is buffered in memory and not written to disk yet.
+    Buffered,
+}
+
+/// Check the decrypted plaintext of a user data block against the CRC in
+/// its record, a mismatch is reported to the handler (see `Config::corruption_handler`).
+#[cfg(feature = "debug_crc")]
+fn check_plain_crc(
+    value: &RecordValue,
+    plain: &[u8],
+    handler: Option<&CorruptionHandlerRef>,
+) -> Result<()> {
+    let actual_crc = crate::util::crc32(plain);
+    if actual_crc as u64 == value.crc {
+        return Ok(());
+    }
+    let report = CorruptionReport {
+        hba: value.hba,
+        expected_crc: value.crc as u32,
+        actual_crc,
+    };
+    match handler {
+        Some(handler) => handler.on_corruption(&report),
+        None => {
+            #[cfg(not(feature = "linux"))]
+            error!("[SwornDisk] corrupted block detected: {report:?}");
+        }
+    }
+    return_errno_with_msg!(DecryptFailed, "plaintext mismatches its CRC");
+}
+
+/// Merge the sorted `lbas` into consecutive ranges.
+fn lbas_to_ranges(lbas: &[Lba]) -> Vec<Range<Lba>> {
+    let mut ranges: Vec<Range<Lba>> = Vec::new();
+    for &lba in lbas {
+        match ranges.last_mut() {
+            Some(range) if range.end == lba => range.end += 1,
+            _ => ranges.push(lba..lba + 1),
+        }
+    }
+    ranges
+}
+
+// SAFETY: `SwornDisk` is concurrency-safe.
+unsafe impl<D: BlockSet> Send for DiskInner<D> {}
+unsafe impl<D: BlockSet> Sync for DiskInner<D> {}
+
+/// Listener factory for `TxLsmTree`.
+struct TxLsmTreeListenerFactory<D> {
+    store: Arc<TxLogStore<D>>,
+    alloc_table: Arc<AllocTable>,
+    dealloc_table: Arc<DeallocTable>,
+    ref_counts: Option<Arc<RefCountTable<D>>>,
+    enable_gc: bool,
+}
+
+impl<D> TxLsmTreeListenerFactory<D> {
+    fn new(
+        store: Arc<TxLogStore<D>>,
+        alloc_table: Arc<AllocTable>,
+        reverse_index_table: Arc<DeallocTable>,
+        ref_counts: Option<Arc<RefCountTable<D>>>,
+        enable_gc: bool,
+    ) -> Self {
+        Self {
+            store,
+            alloc_table,
+            dealloc_table: reverse_index_table,
+            ref_counts,
+            enable_gc,
+        }
+    }
+}
+
+impl<D: BlockSet + 'static> TxEventListenerFactory<RecordKey, RecordValue>
+    for TxLsmTreeListenerFactory<D>
+{
+    fn new_event_listener(
+        &self,
+        tx_type: TxType,
+    ) -> Arc<dyn TxEventListener<RecordKey, RecordValue>> {
+        Arc::new(TxLsmTreeListener::new(
+            tx_type,
+            Arc::new(BlockAlloc::new(
+                self.alloc_table.clone(),
+                self.store.clone(),
+            )),
+            self.dealloc_table.clone(),
+            self.ref_counts.clone(),
+            self.enable_gc,
+        ))
+    }
+}
+
+pub(super) struct EmptyFactory;
+struct EmptyListener;
+
+impl<K, V> TxEventListenerFactory<K, V> for EmptyFactory {
+    fn new_event_listener(&self, _tx_type: TxType) -> Arc<dyn TxEventListener<K, V>> {
+        Arc::new(EmptyListener)
+    }
+}
+impl<K, V> TxEventListener<K, V> for EmptyListener {
+    fn on_add_record(&self, _record: &dyn AsKV<K, V>) -> Result<()> {
+        Ok(())
+    }
+    fn on_drop_record(&self, _record: &dyn AsKV<K, V>) -> Result<()> {
+        Ok(())
+    }
+    fn on_tx_begin(&self, _tx: &mut Tx) -> Result<()> {
+        Ok(())
+    }
+    fn on_tx_precommit(&self, _tx: &mut Tx) -> Result<()> {
+        Ok(())
+    }
+    fn on_tx_commit(&self) {}
+}
+
+/// Event listener for `TxLsmTree`.
+struct TxLsmTreeListener<D> {
+    tx_type: TxType,
+    block_alloc: Arc<BlockAlloc<D>>,
+    dealloc_table: Arc<DeallocTable>,
+    ref_counts: Option<Arc<RefCountTable<D>>>,
+    enable_gc: bool,
+}
+
+impl<D> TxLsmTreeListener<D> {
+    fn new(
+        tx_type: TxType,
+        block_alloc: Arc<BlockAlloc<D>>,
+        reverse_index_table: Arc<DeallocTable>,
+        ref_counts: Option<Arc<RefCountTable<D>>>,
+        enable_gc: bool,
+    ) -> Self {
+        Self {
+            tx_type,
+            block_alloc,
+            dealloc_table: reverse_index_table,
+            ref_counts,
+            enable_gc,
+        }
+    }
+}
+
+/// Register callbacks for different TXs in `TxLsmTree`.
+impl<D: BlockSet + 'static> TxEventListener<RecordKey, RecordValue> for TxLsmTreeListener<D> {
+    fn on_add_record(&self, record: &dyn AsKV<RecordKey, RecordValue>) -> Result<()> {
+        match self.tx_type {
+            TxType::Compaction { to_level } if to_level == LsmLevel::L0 => {
+                let hba = record.value().hba;
+                match &self.ref_counts {
+                    Some(ref_counts) if ref_counts.is_counted(hba) => {
+                        self.block_alloc.alloc_shared_block(hba)
+                    }
+                    _ => self.block_alloc.alloc_block(hba),
+                }
+            }
+            // Major Compaction TX and Migration TX do not add new records
+            TxType::Compaction { .. } | TxType::Migration => {
+                // Do nothing
+                Ok(())
+            }
+        }
+    }
+
+    fn on_drop_record(&self, record: &dyn AsKV<RecordKey, RecordValue>) -> Result<()> {
+        match self.tx_type {
+            // Minor Compaction TX doesn't compact records
+            TxType::Compaction { to_level } if to_level == LsmLevel::L0 => {
+                unreachable!();
+            }
+            TxType::Compaction { .. } | TxType::Migration => {
+                // Only check dealloc_table when GC is enabled to avoid unnecessary mutex operations
+                if self.enable_gc && self.dealloc_table.has_deallocated(record.value().hba) {
+                    self.dealloc_table.finish_deallocated(record.value().hba);
+                    return Ok(());
+                }
+                // A shared block is only deallocated with its last record
+                if let Some(ref_counts) = &self.ref_counts
+                    && !ref_counts.release(record.value().hba)
+                {
+                    return Ok(());
+                }
+                self.block_alloc.dealloc_block(record.value().hba)
+            }
+        }
+    }
+
+    fn on_tx_begin(&self, tx: &mut Tx) -> Result<()> {
+        match self.tx_type {
+            TxType::Compaction { .. } | TxType::Migration => {
+                tx.context(|| self.block_alloc.prepare_diff_log())
+            }
+        }
+    }
+
+    fn on_tx_precommit(&self, tx: &mut Tx) -> Result<()> {
+        match self.tx_type {
+            TxType::Compaction { .. } | TxType::Migration => {
+                tx.context(|| self.block_alloc.update_diff_log())
+            }
+        }
+    }
+
+    fn on_tx_commit(&self) {
+        match self.tx_type {
+            TxType::Compaction { .. } | TxType::Migration => self.block_alloc.update_alloc_table(),
+        }
+    }
+}
+
+/// Key-Value record for `TxLsmTree`.
+pub(super) struct Record {
+    key: RecordKey,
+    value: RecordValue,
+}
+
+/// The key of a `Record`.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub(super) struct RecordKey {
+    /// Logical block address of user data block.
+    pub lba: Lba,
+}
+
+/// The value of a `Record`.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Debug)]
+pub(super) struct RecordValue {
+    /// Host block address of user data block.
+    pub hba: Hba,
+    /// Encryption key of the data block, or the per-block nonce
+    /// in `BlockCryptoMode::PerBlockNonce`, or the per-block epoch in
+    /// `BlockCryptoMode::DerivedKey`. The latter two are stored compactly
+    /// (see `FORMAT_COMPACT_SECRET`).
+    pub key: Key,
+    /// Encrypted MAC of the data block.
+    pub mac: Mac,
+    /// The sync ID of the logical block table which commits the record,
+    /// which tells the blocks changed since a sync. It's not stored
+    /// (thus zero) on the disks without `FEATURE_SYNC_ID_RECORDS`.
+    pub sync_id: SyncId,
+    /// CRC-32 of the plaintext of the data block (widened to keep the record
+    /// free of padding), validated after decryption.
+    #[cfg(feature = "debug_crc")]
+    pub crc: u64,
+}
+
+/// The associated data of a user data block, which binds the ciphertext to
+/// its LBA and the disk, so that a block relocated to another LBA (or copied
+/// from another disk) fails the integrity check. The per-block key (or nonce,
+/// or epoch) in `RecordValue` already keeps an old version of the block from
+/// being replayed at its own LBA.
+///
+/// It's empty for the disks created without `FEATURE_LBA_AAD`.
+pub(super) struct BlockAad {
+    bytes: [u8; BLOCK_AAD_SIZE],
+    len: usize,
+}
+const BLOCK_AAD_SIZE: usize = size_of::<u64>() + DISK_ID_SIZE;
+
+impl BlockAad {
+    /// Returns the associated data of the block of `lba` on the disk of
+    /// `disk_id`, `None` for the disks without `FEATURE_LBA_AAD`.
+    pub fn new(lba: Lba, disk_id: Option<&DiskId>) -> Self {
+        let mut bytes = [0u8; BLOCK_AAD_SIZE];
+        let Some(disk_id) = disk_id else {
+            return Self { bytes, len: 0 };
+        };
+        bytes[..size_of::<u64>()].copy_from_slice(&(lba as u64).to_le_bytes());
+        bytes[size_of::<u64>()..].copy_from_slice(disk_id);
+        Self {
+            bytes,
+            len: BLOCK_AAD_SIZE,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl Add<usize> for RecordKey {
+    type Output = Self;
+
+    fn add(self, other: usize) -> Self::Output {
+        Self {
+            lba: self.lba + other,
+        }
+    }
+}
+
+impl Sub<RecordKey> for RecordKey {
+    type Output = usize;
+
+    fn sub(self, other: RecordKey) -> Self::Output {
+        self.lba - other.lba
+    }
+}
+
+impl RecordK<RecordKey> for RecordKey {}
+
+impl RecordValue {
+    /// The flag of the formats whose secret is an epoch (or a nonce),
+    /// only the low `COMPACT_SECRET_SIZE` bytes of the secret are stored
+    /// (see `FEATURE_COMPACT_RECORDS`).
+    const FORMAT_COMPACT_SECRET: ValueFormat = 1 << 0;
+    /// The flag of the formats storing the sync ID of each record
+    /// (see `FEATURE_SYNC_ID_RECORDS`).
+    const FORMAT_SYNC_ID: ValueFormat = 1 << 1;
+    /// The flag of the formats storing the records of a sequential run
+    /// as extents (see `FEATURE_EXTENT_RECORDS`).
+    const FORMAT_EXTENTS: ValueFormat = 1 << 2;
+    const COMPACT_SECRET_SIZE: usize = size_of::<u64>();
+
+    /// Returns the format of the records of a disk, which is zero (i.e.,
+    /// `hba | key | mac`) for the disks without any of the features.
+    fn value_format(compact_secret: bool, sync_id: bool, extents: bool) -> ValueFormat {
+        let mut format = 0;
+        if compact_secret {
+            format |= Self::FORMAT_COMPACT_SECRET;
+        }
+        if sync_id {
+            format |= Self::FORMAT_SYNC_ID;
+        }
+        if extents {
+            format |= Self::FORMAT_EXTENTS;
+        }
+        format
+    }
+
+    /// Returns the number of bytes of the secret stored in `format`.
+    fn secret_size(format: ValueFormat) -> usize {
+        if format & Self::FORMAT_COMPACT_SECRET != 0 {
+            Self::COMPACT_SECRET_SIZE
+        } else {
+            size_of::<Key>()
+        }
+    }
+}
+
+impl RecordV for RecordValue {
+    fn encoded_size(format: ValueFormat) -> usize {
+        let mut size = size_of::<Self>() - size_of::<Key>() + Self::secret_size(format);
+        if format & Self::FORMAT_SYNC_ID == 0 {
+            size -= size_of::<SyncId>();
+        }
+        size
+    }
+
+    fn encode(&self, format: ValueFormat, buf: &mut Vec<u8>) {
+        let secret_size = Self::secret_size(format);
+        debug_assert!(self.key.as_bytes()[secret_size..]
+            .iter()
+            .all(|&byte| byte == 0));
+        buf.extend_from_slice(self.hba.as_bytes());
+        buf.extend_from_slice(&self.key.as_bytes()[..secret_size]);
+        buf.extend_from_slice(self.mac.as_bytes());
+        if format & Self::FORMAT_SYNC_ID != 0 {
+            buf.extend_from_slice(self.sync_id.as_bytes());
+        }
+        #[cfg(feature = "debug_crc")]
+        buf.extend_from_slice(self.crc.as_bytes());
+    }
+
+    fn decode(format: ValueFormat, buf: &[u8]) -> Self {
+        let secret_size = Self::secret_size(format);
+        let mut value = Self::new_zeroed();
+        let (hba, rest) = buf.split_at(size_of::<Hba>());
+        let (secret, rest) = rest.split_at(secret_size);
+        let (mac, rest) = rest.split_at(size_of::<Mac>());
+        value.hba = Hba::from_bytes(hba);
+        value.key.as_bytes_mut()[..secret_size].copy_from_slice(secret);
+        value.mac = Mac::from_bytes(mac);
+        #[cfg_attr(not(feature = "debug_crc"), allow(unused_variables))]
+        let rest = if format & Self::FORMAT_SYNC_ID != 0 {
+            let (sync_id, rest) = rest.split_at(size_of::<SyncId>());
+            value.sync_id = SyncId::from_bytes(sync_id);
+            rest
+        } else {
+            rest
+        };
+        #[cfg(feature = "debug_crc")]
+        {
+            value.crc = u64::from_bytes(rest);
+        }
+        value
+    }
+
+    fn successor_size(format: ValueFormat) -> Option<usize> {
+        if format & Self::FORMAT_EXTENTS == 0 {
+            return None;
+        }
+        // The nonce (or epoch) of a compact secret is derived
+        let mut size = size_of::<Mac>();
+        if format & Self::FORMAT_COMPACT_SECRET == 0 {
+            size += size_of::<Key>();
+        }
+        #[cfg(feature = "debug_crc")]
+        {
+            size += size_of::<u64>();
+        }
+        Some(size)
+    }
+
+    /// The blocks of a sequential run are allocated consecutive HBAs (and
+    /// nonces or epochs), and committed by the same sync.
+    fn succeeds(&self, prev: &Self, format: ValueFormat) -> bool {
+        if self.hba != prev.hba + 1 || self.sync_id != prev.sync_id {
+            return false;
+        }
+        if format & Self::FORMAT_COMPACT_SECRET == 0 {
+            return true;
+        }
+        let secret_size = Self::secret_size(format);
+        let secret = |value: &Self| u64::from_bytes(&value.key.as_bytes()[..secret_size]);
+        secret(self) == secret(prev).wrapping_add(1)
+    }
+
+    fn encode_successor(&self, format: ValueFormat, buf: &mut Vec<u8>) {
+        if format & Self::FORMAT_COMPACT_SECRET == 0 {
+            buf.extend_from_slice(self.key.as_bytes());
+        }
+        buf.extend_from_slice(self.mac.as_bytes());
+        #[cfg(feature = "debug_crc")]
+        buf.extend_from_slice(self.crc.as_bytes());
+    }
+
+    fn decode_successor(prev: &Self, format: ValueFormat, buf: &[u8]) -> Self {
+        let mut value = *prev;
+        value.hba = prev.hba + 1;
+        let rest = if format & Self::FORMAT_COMPACT_SECRET == 0 {
+            let (key, rest) = buf.split_at(size_of::<Key>());
+            value.key = Key::from_bytes(key);
+            rest
+        } else {
+            let secret_size = Self::secret_size(format);
+            let secret = u64::from_bytes(&prev.key.as_bytes()[..secret_size]).wrapping_add(1);
+            value.key.as_bytes_mut()[..secret_size].copy_from_slice(secret.as_bytes());
+            buf
+        };
+        #[cfg_attr(not(feature = "debug_crc"), allow(unused_variables))]
+        let (mac, rest) = rest.split_at(size_of::<Mac>());
+        value.mac = Mac::from_bytes(mac);
+        #[cfg(feature = "debug_crc")]
+        {
+            value.crc = u64::from_bytes(rest);
+        }
+        value
+    }
+}
+
+impl AsKV<RecordKey, RecordValue> for Record {
+    fn key(&self) -> &RecordKey {
+        &self.key
+    }
+
+    fn value(&self) -> &RecordValue {
+        &self.value
+    }
+}
+
+#[cfg(feature = "ext2")]
+mod impl_block_device {
+    use super::{BlockSet, BufMut, BufRef, SwornDisk, Vec};
+    use ext2_rs::{Bid, BlockDevice, FsError as Ext2Error};
+
+    impl<D: BlockSet + 'static> BlockDevice for SwornDisk<D> {
+        fn total_blocks(&self) -> usize {
+            self.total_blocks()
+        }
+
+        fn read_blocks(&self, bid: Bid, blocks: &mut [&mut [u8]]) -> Result<(), Ext2Error> {
+            // A block of other sizes is rejected rather than panicking
+            if blocks.len() == 1 {
+                self.read(
+                    bid as _,
+                    BufMut::try_from(blocks.first_mut().unwrap().as_mut())?,
+                )?;
+                return Ok(());
+            }
+
+            let mut bufs = blocks
+                .iter_mut()
+                .map(|block| BufMut::try_from(block.as_mut()))
+                .collect::<crate::prelude::Result<Vec<_>>>()?;
+            self.readv(bid as _, &mut bufs)?;
+            Ok(())
+        }
+
+        fn write_blocks(&self, bid: Bid, blocks: &[&[u8]]) -> Result<(), Ext2Error> {
+            if blocks.len() == 1 {
+                self.write(
+                    bid as _,
+                    BufRef::try_from(blocks.first().unwrap().as_ref())?,
+                )?;
+                return Ok(());
+            }
+
+            let bufs = blocks
+                .iter()
+                .map(|block| BufRef::try_from(block.as_ref()))
+                .collect::<crate::prelude::Result<Vec<_>>>()?;
+            self.writev(bid as _, &bufs)?;
+            Ok(())
+        }
+
+        fn sync(&self) -> Result<(), Ext2Error> {
+            self.sync()?;
+            Ok(())
+        }
+    }
+
+    impl From<crate::Error> for Ext2Error {
+        fn from(value: crate::Error) -> Self {
+            match value.errno() {
+                crate::Errno::NotFound => Self::EntryNotFound,
+                crate::Errno::InvalidArgs | crate::Errno::NotBlockSizeAligned => Self::InvalidParam,
+                crate::Errno::OutOfDisk | crate::Errno::NoSpaceLeft => Self::NoDeviceSpace,
+                crate::Errno::PermissionDenied => Self::PermError,
+                _ => {
+                    println!("[SwornDisk] Error occurred: {value:?}");
+                    Self::DeviceError(0)
+                }
+            }
+        }
+    }
+
+    // The tests cover the block I/O semantics of the adapter (block sizes,
+    // vectored I/O, sync before remount). Formatting and checking a file
+    // system (mkfs, fsck) is out of their scope.
+    #[cfg(test)]
+    mod tests {
+        use super::super::Key;
+        use super::*;
+        use crate::layers::bio::{MemDisk, BLOCK_SIZE};
+        use crate::util::RandomInit;
+
+        use core::mem::size_of;
+
+        fn fill_block(block: &mut [u8], bid: Bid, round: u8) {
+            block.fill(round);
+            block[..size_of::<Bid>()].copy_from_slice(&bid.to_le_bytes());
+        }
+
+        // The access patterns of ext2: single and vectored blocks,
+        // then a sync before remounting
+        #[test]
+        fn block_device_rw_remount() -> crate::prelude::Result<()> {
+            let nblocks = 64 * 1024;
+            let mem_disk = MemDisk::create(nblocks)?;
+            let root_key = Key::random();
+            let disk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
+            assert_eq!(BlockDevice::total_blocks(&disk), disk.total_blocks());
+
+            let num_blocks: Bid = 64;
+            let mut block = vec![0u8; BLOCK_SIZE];
+            for bid in 0..num_blocks {
+                fill_block(&mut block, bid, 1);
+                BlockDevice::write_blocks(&disk, bid, &[&block]).unwrap();
+            }
+            // Overwrite the blocks 8 at a time, as a vectored write
+            let mut blocks = vec![vec![0u8; BLOCK_SIZE]; 8];
+            for first_bid in (0..num_blocks).step_by(16) {
+                for (nth, block) in blocks.iter_mut().enumerate() {
+                    fill_block(block, first_bid + nth as Bid, 2);
+                }
+                let bufs: Vec<&[u8]> = blocks.iter().map(|block| block.as_slice()).collect();
+                BlockDevice::write_blocks(&disk, first_bid, &bufs).unwrap();
+            }
+            BlockDevice::sync(&disk).unwrap();
+            drop(disk);
+
+            let disk = SwornDisk::open(mem_disk, root_key, None, None)?;
+            let mut expected = vec![0u8; BLOCK_SIZE];
+            for first_bid in (0..num_blocks).step_by(8) {
+                let mut bufs: Vec<&mut [u8]> = blocks
+                    .iter_mut()
+                    .map(|block| block.as_mut_slice())
+                    .collect();
+                BlockDevice::read_blocks(&disk, first_bid, &mut bufs).unwrap();
+                for (nth, block) in blocks.iter().enumerate() {
+                    let bid = first_bid + nth as Bid;
+                    let round = if bid % 16 < 8 { 2 } else { 1 };
+                    fill_block(&mut expected, bid, round);
+                    assert_eq!(block, &expected);
+                }
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn block_device_invalid_blocks() -> crate::prelude::Result<()> {
+            let disk = SwornDisk::create(MemDisk::create(64 * 1024)?, Key::random(), None, None)?;
+            let total_blocks = BlockDevice::total_blocks(&disk);
+
+            // Blocks not of `BLOCK_SIZE`, e.g., those of a 1 KiB ext2
+            let mut small_block = vec![0u8; 1024];
+            let res = BlockDevice::write_blocks(&disk, 0, &[&small_block]);
+            assert!(matches!(res, Err(Ext2Error::InvalidParam)));
+            let mut bufs: Vec<&mut [u8]> = vec![&mut small_block];
+            let res = BlockDevice::read_blocks(&disk, 0, &mut bufs);
+            assert!(matches!(res, Err(Ext2Error::InvalidParam)));
+
+            // Beyond the end of the device
+            let mut block = vec![0u8; BLOCK_SIZE];
+            let res = BlockDevice::write_blocks(&disk, total_blocks as Bid, &[&block]);
+            assert!(matches!(res, Err(Ext2Error::NoDeviceSpace)));
+            let mut bufs: Vec<&mut [u8]> = vec![&mut block];
+            let res = BlockDevice::read_blocks(&disk, total_blocks as Bid, &mut bufs);
+            assert!(matches!(res, Err(Ext2Error::NoDeviceSpace)));
+
+            // Unwritten blocks are read as zeros
+            let mut bufs: Vec<&mut [u8]> = vec![&mut block];
+            BlockDevice::read_blocks(&disk, 0, &mut bufs).unwrap();
+            assert!(block.iter().all(|byte| *byte == 0));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::bio::MemDisk;
+    use crate::layers::disk::bio::{BioReqBuilder, BlockBuf};
+    use crate::layers::disk::superblock::LEGACY_FORMAT_VERSION;
+    use crate::layers::lsm::CompactionPolicyKind;
+
+    use crate::os::Rng;
+    use crate::util::Rng as _;
+
+    use core::ptr::NonNull;
+    use std::collections::HashMap;
+    use std::thread;
+
+    #[test]
+    fn sworndisk_fns() -> Result<()> {
+        let nblocks = 128 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        // Create a new `SwornDisk` then do some writes
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
+        let num_rw = 1024;
+
+        // // Submit a write block I/O request
+        let mut wbuf = Buf::alloc(num_rw)?;
+        let bufs = {
+            let mut bufs = Vec::with_capacity(num_rw);
+            for i in 0..num_rw {
+                let buf_slice = &mut wbuf.as_mut_slice()[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE];
+                buf_slice.fill(i as u8);
+                bufs.push(unsafe {
+                    BlockBuf::from_raw_parts(
+                        NonNull::new(buf_slice.as_mut_ptr()).unwrap(),
+                        BLOCK_SIZE,
+                    )
+                });
+            }
+            bufs
+        };
+        let bio_req = BioReqBuilder::new(BioType::Write)
+            .addr(0 as BlockId)
+            .bufs(bufs)
+            .build();
+        sworndisk.submit_bio_sync(bio_req)?;
+
+        // // Sync the `SwornDisk` then do some reads
+        sworndisk.submit_bio_sync(BioReqBuilder::new(BioType::Sync).build())?;
+
+        let mut rbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            sworndisk.read(i as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], i as u8);
+        }
+
+        // Open the closed `SwornDisk` then test its data's existence
+        drop(sworndisk);
+        thread::spawn(move || -> Result<()> {
+            let opened_sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+            let mut rbuf = Buf::alloc(2)?;
+            opened_sworndisk.read(5 as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], 5u8);
+            assert_eq!(rbuf.as_slice()[4096], 6u8);
+            Ok(())
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn sworndisk_per_block_nonce() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let config = Config {
+            crypto_mode: BlockCryptoMode::PerBlockNonce,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config))?;
+
+        let num_rw = 128;
+        let mut rw_buf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            rw_buf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, rw_buf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        for i in 0..num_rw {
+            sworndisk.read(i as Lba, rw_buf.as_mut())?;
+            assert_eq!(rw_buf.as_slice()[0], i as u8);
+        }
+
+        // Only the nonce is stored in the record, in the compact format
+        let value = sworndisk
+            .inner
+            .logical_block_table
+            .get(&RecordKey { lba: 0 })?;
+        assert!(value.key[size_of::<u64>()..].iter().all(|byte| *byte == 0));
+        assert!(sworndisk
+            .inner
+            .superblock
+            .lock()
+            .has_feature(FEATURE_COMPACT_RECORDS));
+
+        // The crypto mode is recorded in the superblock, not taken from the config
+        drop(sworndisk);
+        let opened_sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        let mut rbuf = Buf::alloc(2)?;
+        opened_sworndisk.read(7 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice()[0], 7u8);
+        assert_eq!(rbuf.as_slice()[BLOCK_SIZE], 8u8);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_per_block_nonce_full_records() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let config = Config {
+            crypto_mode: BlockCryptoMode::PerBlockNonce,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config))?;
+        drop(sworndisk);
+
+        // Turn it into an image of the older format, whose nonces are
+        // stored in full-size secrets
+        let superblock_disk = SwornDisk::subdisk_for_superblock(&mem_disk)?;
+        let mut superblock = Superblock::open(&superblock_disk, &root_key)?;
+        superblock.clear_feature(FEATURE_COMPACT_RECORDS);
+        superblock.set_lsm_params(LsmParams {
+            value_format: RecordValue::value_format(false, true, true),
+            ..*superblock.lsm_params()
+        });
+        superblock.persist(&superblock_disk, &root_key)?;
+
+        let sworndisk = SwornDisk::open(mem_disk.clone(), root_key, None, None)?;
+        let num_rw = 128;
+        let mut rw_buf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            rw_buf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, rw_buf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        assert!(!sworndisk
+            .inner
+            .superblock
+            .lock()
+            .has_feature(FEATURE_COMPACT_RECORDS));
+        drop(sworndisk);
+
+        let opened_sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        for i in 0..num_rw {
+            opened_sworndisk.read(i as Lba, rw_buf.as_mut())?;
+            assert_eq!(rw_buf.as_slice()[0], i as u8);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_legacy_superblock() -> Result<()> {
+        // Its layout ends right before the last block of the image
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks + 1)?;
+        let root_key = Key::random();
+        let config = Config {
+            crypto_mode: BlockCryptoMode::PerBlockNonce,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config))?;
+        drop(sworndisk);
+
+        // Turn it into an image of the legacy format, whose superblock
+        // is a single copy in the last block
+        let mut superblock =
+            Superblock::open(&SwornDisk::subdisk_for_superblock(&mem_disk)?, &root_key)?;
+        for feature in [
+            FEATURE_LBA_AAD,
+            FEATURE_COMPACT_RECORDS,
+            FEATURE_SYNC_ID_RECORDS,
+            FEATURE_EXTENT_RECORDS,
+        ] {
+            superblock.clear_feature(feature);
+        }
+        superblock.set_lsm_params(LsmParams {
+            value_format: RecordValue::value_format(false, false, false),
+            ..*superblock.lsm_params()
+        });
+        superblock.set_version(LEGACY_FORMAT_VERSION);
+        let legacy_disk = mem_disk.subset(0..nblocks)?;
+        superblock.persist(&SwornDisk::subdisk_for_superblock(&legacy_disk)?, &root_key)?;
+
+        let sworndisk = SwornDisk::open(legacy_disk.clone(), root_key, None, None)?;
+        assert_eq!(sworndisk.inner.superblock.lock().nblocks(), 1);
+        let num_rw = 128;
+        let mut rw_buf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            rw_buf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, rw_buf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        drop(sworndisk);
+
+        let opened_sworndisk = SwornDisk::open(legacy_disk, root_key, None, None)?;
+        for i in 0..num_rw {
+            opened_sworndisk.read(i as Lba, rw_buf.as_mut())?;
+            assert_eq!(rw_buf.as_slice()[0], i as u8);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_lba_aad() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let config = Config {
+            crypto_mode: BlockCryptoMode::PerBlockNonce,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), Key::random(), None, Some(config))?;
+        assert!(sworndisk.inner.disk_id.is_some());
+
+        let mut wbuf = Buf::alloc(2)?;
+        wbuf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+
+        // The ciphertext of lba 0 is bound to lba 0 (data blocks are allocated from 0)
+        let inner = &sworndisk.inner;
+        let value = inner.logical_block_table.get(&RecordKey { lba: 0 })?;
+        let mut cipher = Buf::alloc(1)?;
+        mem_disk.read(value.hba, cipher.as_mut())?;
+        let mut plain = Buf::alloc(1)?;
+        inner.decrypt_block(0, &value, cipher.as_slice(), plain.as_mut_slice())?;
+        assert_eq!(plain.as_slice()[0], 1);
+        assert!(inner
+            .decrypt_block(1, &value, cipher.as_slice(), plain.as_mut_slice())
+            .is_err());
+
+        // The disks without `FEATURE_LBA_AAD` use empty associated data
+        assert!(BlockAad::new(0, None).as_slice().is_empty());
+        let disk_id = inner.superblock.lock().disk_id();
+        assert_ne!(
+            BlockAad::new(0, Some(&disk_id)).as_slice(),
+            BlockAad::new(1, Some(&disk_id)).as_slice()
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "debug_crc")]
+    #[test]
+    fn sworndisk_debug_crc() -> Result<()> {
+        use crate::layers::disk::corruption::CorruptionHandler;
+
+        struct ReportCollector(Mutex<Vec<CorruptionReport>>);
+        impl CorruptionHandler for ReportCollector {
+            fn on_corruption(&self, report: &CorruptionReport) {
+                self.0.lock().push(*report);
+            }
+        }
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let collector = Arc::new(ReportCollector(Mutex::new(Vec::new())));
+        let config = Config {
+            corruption_handler: Some(collector.clone()),
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk, root_key, None, Some(config))?;
+
+        let mut rw_buf = Buf::alloc(1)?;
+        rw_buf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, rw_buf.as_ref())?;
+        sworndisk.sync()?;
+        sworndisk.read(0 as Lba, rw_buf.as_mut())?;
+        assert!(collector.0.lock().is_empty());
+
+        // A record whose CRC mismatches the (authenticated) plaintext
+        let table = &sworndisk.inner.logical_block_table;
+        let mut value = table.get(&RecordKey { lba: 0 })?;
+        value.crc ^= 1;
+        table.put(RecordKey { lba: 0 }, value)?;
+        let err = sworndisk.read(0 as Lba, rw_buf.as_mut()).unwrap_err();
+        assert_eq!(err.errno(), DecryptFailed);
+        let reports = collector.0.lock();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].hba, value.hba);
+        assert_eq!(reports[0].expected_crc as u64, value.crc);
+        Ok(())
+    }
+
+    #[test]
+    fn record_value_formats() {
+        let mut value = RecordValue::new_zeroed();
+        value.hba = 42;
+        value.key = Key::random();
+        value.sync_id = 7;
+
+        // The records of the disks without any feature have no sync ID
+        let format = RecordValue::value_format(false, false, false);
+        let mut encoded = Vec::new();
+        value.encode(format, &mut encoded);
+        assert_eq!(encoded.len(), RecordValue::encoded_size(format));
+        assert_eq!(
+            encoded.len(),
+            size_of::<RecordValue>() - size_of::<SyncId>()
+        );
+        let decoded = RecordValue::decode(format, &encoded);
+        assert_eq!(decoded.hba, value.hba);
+        assert_eq!(decoded.key.as_bytes(), value.key.as_bytes());
+        assert_eq!(decoded.sync_id, 0);
+
+        let format = RecordValue::value_format(false, true, false);
+        let mut encoded = Vec::new();
+        value.encode(format, &mut encoded);
+        assert_eq!(encoded.as_slice(), value.as_bytes());
+        assert_eq!(RecordValue::decode(format, &encoded).sync_id, 7);
+        assert!(RecordValue::successor_size(format).is_none());
+
+        // Only the MAC of a successor is stored along with its derived epoch
+        let format = RecordValue::value_format(true, true, true);
+        value.key = Key::new_zeroed();
+        value.key[..size_of::<u64>()].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut successor = value;
+        successor.hba += 1;
+        successor.key[..size_of::<u64>()].copy_from_slice(&0u64.to_le_bytes());
+        successor.mac = Mac::random();
+        assert!(successor.succeeds(&value, format));
+        assert!(!value.succeeds(&successor, format));
+        let mut encoded = Vec::new();
+        successor.encode_successor(format, &mut encoded);
+        assert_eq!(Some(encoded.len()), RecordValue::successor_size(format));
+        assert!(encoded.len() < RecordValue::encoded_size(format));
+        let decoded = RecordValue::decode_successor(&value, format, &encoded);
+        assert_eq!(decoded.as_bytes(), successor.as_bytes());
+
+        // A random key is stored in each successor
+        let format = RecordValue::value_format(false, true, true);
+        successor.key = Key::random();
+        assert!(successor.succeeds(&value, format));
+        let mut encoded = Vec::new();
+        successor.encode_successor(format, &mut encoded);
+        let decoded = RecordValue::decode_successor(&value, format, &encoded);
+        assert_eq!(decoded.as_bytes(), successor.as_bytes());
+        successor.sync_id += 1;
+        assert!(!successor.succeeds(&value, format));
+    }
+
+    #[test]
+    fn sworndisk_derived_key() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let config = Config {
+            crypto_mode: BlockCryptoMode::DerivedKey,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config))?;
+
+        // Overwrites are encrypted under keys of new epochs
+        let num_rw = 128;
+        let mut rw_buf = Buf::alloc(1)?;
+        for round in 0..2 {
+            for i in 0..num_rw {
+                rw_buf.as_mut_slice().fill((i + round) as u8);
+                sworndisk.write(i as Lba, rw_buf.as_ref())?;
+            }
+            sworndisk.sync()?;
+        }
+        for i in 0..num_rw {
+            sworndisk.read(i as Lba, rw_buf.as_mut())?;
+            assert_eq!(rw_buf.as_slice()[0], (i + 1) as u8);
+        }
+
+        // Only the epoch is stored in the record, in the compact format
+        let value = sworndisk
+            .inner
+            .logical_block_table
+            .get(&RecordKey { lba: 0 })?;
+        assert!(value.key[size_of::<u64>()..].iter().all(|byte| *byte == 0));
+        assert!(sworndisk
+            .inner
+            .superblock
+            .lock()
+            .has_feature(FEATURE_COMPACT_RECORDS));
+        let format = RecordValue::value_format(true, true, true);
+        let mut encoded = Vec::new();
+        value.encode(format, &mut encoded);
+        assert_eq!(encoded.len(), RecordValue::encoded_size(format));
+        assert!(encoded.len() < size_of::<RecordValue>());
+        let decoded = RecordValue::decode(format, &encoded);
+        assert_eq!(decoded.as_bytes(), value.as_bytes());
+
+        drop(sworndisk);
+        let opened_sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        let mut rbuf = Buf::alloc(2)?;
+        opened_sworndisk.read(7 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice()[0], 8u8);
+        assert_eq!(rbuf.as_slice()[BLOCK_SIZE], 9u8);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_extent_records() -> Result<()> {
+        for crypto_mode in [BlockCryptoMode::RandomKey, BlockCryptoMode::DerivedKey] {
+            let nblocks = 64 * 1024;
+            let mem_disk = MemDisk::create(nblocks)?;
+            let root_key = Key::random();
+            let config = Config {
+                crypto_mode,
+                ..Default::default()
+            };
+            let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config))?;
+            assert!(sworndisk
+                .inner
+                .superblock
+                .lock()
+                .has_feature(FEATURE_EXTENT_RECORDS));
+
+            // A sequential run, partially overwritten after it's compacted
+            let num_rw = 1024;
+            let mut wbuf = Buf::alloc(num_rw)?;
+            for (i, block) in wbuf.as_mut_slice().chunks_mut(BLOCK_SIZE).enumerate() {
+                block.fill(i as u8);
+            }
+            sworndisk.write(0 as Lba, wbuf.as_ref())?;
+            sworndisk.sync()?;
+            sworndisk.inner.logical_block_table.force_compaction()?;
+            let mut block = Buf::alloc(1)?;
+            block.as_mut_slice().fill(u8::MAX);
+            for lba in [0, 100, 101, 500, num_rw - 1] {
+                sworndisk.write(lba as Lba, block.as_ref())?;
+                wbuf.as_mut_slice()[lba * BLOCK_SIZE..(lba + 1) * BLOCK_SIZE].fill(u8::MAX);
+            }
+            sworndisk.sync()?;
+            sworndisk.inner.logical_block_table.force_compaction()?;
+
+            let mut rbuf = Buf::alloc(num_rw)?;
+            sworndisk.read(0 as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+            sworndisk.read(99 as Lba, block.as_mut())?;
+            assert_eq!(block.as_slice()[0], 99);
+
+            drop(sworndisk);
+            let sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+            sworndisk.read(0 as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_digest() -> Result<()> {
+        use crate::layers::disk::digest::DIGEST_BUCKET_NBLOCKS;
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
+
+        let empty_digest = sworndisk.digest()?;
+        assert_eq!(empty_digest.num_mapped, 0);
+
+        // Blocks in two buckets, some of them still buffered
+        let mut wbuf = Buf::alloc(1)?;
+        let lbas = [0, 1, 2, DIGEST_BUCKET_NBLOCKS + 5];
+        for lba in lbas {
+            wbuf.as_mut_slice().fill(lba as u8);
+            sworndisk.write(lba as Lba, wbuf.as_ref())?;
+        }
+        let digest = sworndisk.digest()?;
+        assert_eq!(digest.num_mapped, lbas.len());
+        assert_ne!(digest, empty_digest);
+        assert_eq!(sworndisk.digest()?, digest);
+
+        // An overwrite changes the MAC of the block
+        sworndisk.write(1 as Lba, wbuf.as_ref())?;
+        let overwritten_digest = sworndisk.digest()?;
+        assert_eq!(overwritten_digest.num_mapped, lbas.len());
+        assert_ne!(overwritten_digest, digest);
+
+        sworndisk.discard(DIGEST_BUCKET_NBLOCKS + 5, 1)?;
+        let discarded_digest = sworndisk.digest()?;
+        assert_eq!(discarded_digest.num_mapped, lbas.len() - 1);
+
+        // The incremental digest equals the one computed from scratch
+        drop(sworndisk);
+        let opened_sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        assert_eq!(opened_sworndisk.digest()?, discarded_digest);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_backup_stream() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let sworndisk = SwornDisk::create(MemDisk::create(nblocks)?, Key::random(), None, None)?;
+
+        // Extents longer than a frame, separated by holes
+        let mut wbuf = Buf::alloc(1)?;
+        let lbas: Vec<Lba> = (0..300).chain(512..520).chain([4000]).collect();
+        for &lba in lbas.iter() {
+            wbuf.as_mut_slice().fill(lba as u8);
+            sworndisk.write(lba, wbuf.as_ref())?;
+        }
+        let stream_key = Key::random();
+        let mut stream = Vec::new();
+        let summary = sworndisk.export_stream(&mut stream, &stream_key)?;
+        assert_eq!(summary.num_blocks, lbas.len());
+
+        // Restore into a disk of another root key and crypto mode
+        let config = Config {
+            crypto_mode: BlockCryptoMode::PerBlockNonce,
+            ..Default::default()
+        };
+        let restored =
+            SwornDisk::create(MemDisk::create(nblocks)?, Key::random(), None, Some(config))?;
+        wbuf.as_mut_slice().fill(0xff);
+        restored.write(300 as Lba, wbuf.as_ref())?;
+        assert_eq!(
+            restored.import_stream(&mut &stream[..], &stream_key)?,
+            lbas.len()
+        );
+        let mut rbuf = Buf::alloc(1)?;
+        for &lba in lbas.iter() {
+            restored.read(lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], lba as u8);
+        }
+        // The stale block is discarded
+        let holes = restored.read_with_holes(300 as Lba, rbuf.as_mut())?;
+        assert_eq!(holes, vec![300..301]);
+
+        // A wrong key, a tampered or truncated stream is rejected
+        assert!(restored
+            .import_stream(&mut &stream[..], &Key::random())
+            .is_err());
+        let mut tampered = stream.clone();
+        tampered[BLOCK_SIZE] ^= 1;
+        assert!(restored
+            .import_stream(&mut &tampered[..], &stream_key)
+            .is_err());
+        let truncated = &stream[..stream.len() - 1];
+        assert!(restored
+            .import_stream(&mut &truncated[..], &stream_key)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_incremental_backup() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let sworndisk = SwornDisk::create(MemDisk::create(nblocks)?, Key::random(), None, None)?;
+        let restored = SwornDisk::create(MemDisk::create(nblocks)?, Key::random(), None, None)?;
+        let stream_key = Key::random();
+
+        let mut wbuf = Buf::alloc(1)?;
+        for lba in 0..16 {
+            wbuf.as_mut_slice().fill(lba as u8);
+            sworndisk.write(lba as Lba, wbuf.as_ref())?;
+        }
+        let mut stream = Vec::new();
+        let base = sworndisk.export_stream(&mut stream, &stream_key)?;
+        assert_eq!(base.num_blocks, 16);
+        assert_eq!(base.sync_id, sworndisk.sync_id());
+        assert!(sworndisk.changed_blocks_since(base.sync_id)?.is_empty());
+        restored.import_stream(&mut &stream[..], &stream_key)?;
+
+        // The buffered blocks are changed as well
+        wbuf.as_mut_slice().fill(0xaa);
+        sworndisk.write(20 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+        sworndisk.write(3 as Lba, wbuf.as_ref())?;
+        assert_eq!(sworndisk.changed_blocks_since(base.sync_id)?, vec![3, 20]);
+
+        // So are the discarded blocks, even if rewritten afterwards
+        sworndisk.discard(8, 2)?;
+        sworndisk.sync()?;
+        sworndisk.discard(5, 2)?;
+        sworndisk.write(5 as Lba, wbuf.as_ref())?;
+        assert_eq!(
+            sworndisk.changed_blocks_since(base.sync_id)?,
+            vec![3, 5, 6, 8, 9, 20]
+        );
+
+        let mut delta = Vec::new();
+        let summary = sworndisk.export_stream_since(&mut delta, &stream_key, base.sync_id)?;
+        assert_eq!(summary.num_blocks, 3);
+        assert!(summary.sync_id > base.sync_id);
+        assert!(sworndisk.changed_blocks_since(summary.sync_id)?.is_empty());
+        assert_eq!(restored.import_stream(&mut &delta[..], &stream_key)?, 3);
+
+        let mut rbuf = Buf::alloc(1)?;
+        let mut restored_rbuf = Buf::alloc(1)?;
+        for lba in 0..32 {
+            sworndisk.read(lba as Lba, rbuf.as_mut())?;
+            restored.read(lba as Lba, restored_rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice(), restored_rbuf.as_slice());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_read_repair() -> Result<()> {
+        use crate::layers::bio::MirroredDisk;
+        use crate::layers::disk::segment::SEGMENT_SIZE;
+
+        let nblocks = 64 * 1024;
+        let primary = MemDisk::create(nblocks)?;
+        let mirror = MemDisk::create(nblocks)?;
+        let mirrored_disk = MirroredDisk::new(primary.clone(), mirror.clone())?;
+        let sworndisk = SwornDisk::create(mirrored_disk, Key::random(), None, None)?;
+
+        let num_rw = 16;
+        let mut rw_buf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            rw_buf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, rw_buf.as_ref())?;
+        }
+        sworndisk.sync()?;
+
+        // Corrupt the user data blocks of the primary copy
+        let mut corrupted = Buf::alloc(SEGMENT_SIZE)?;
+        corrupted.as_mut_slice().fill(0xff);
+        primary.write(0, corrupted.as_ref())?;
+
+        for i in 0..num_rw {
+            sworndisk.read(i as Lba, rw_buf.as_mut())?;
+            assert_eq!(rw_buf.as_slice()[0], i as u8);
+        }
+        let mut rbuf = Buf::alloc(num_rw)?;
+        sworndisk.read(0 as Lba, rbuf.as_mut())?;
+        assert_eq!(
+            rbuf.as_slice()[BLOCK_SIZE * (num_rw - 1)],
+            (num_rw - 1) as u8
+        );
+
+        // A corrupted mirror copy never overwrites the primary copy
+        mirror.write(0, corrupted.as_ref())?;
+        corrupted.as_mut_slice().fill(0xee);
+        primary.write(0, corrupted.as_ref())?;
+        assert!(sworndisk.read(0 as Lba, rw_buf.as_mut()).is_err());
+        primary.read(0, rw_buf.as_mut())?;
+        assert!(rw_buf.as_slice().iter().all(|&byte| byte == 0xee));
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_read_scattered() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+
+        let num_rw = 128;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        // Some blocks are still in `DataBuf`
+        wbuf.as_mut_slice().fill(0xff);
+        sworndisk.write(7 as Lba, wbuf.as_ref())?;
+
+        let lbas = [100, 3, 7, 64, 4, 100];
+        let mut rbuf = Buf::alloc(lbas.len())?;
+        sworndisk.read_scattered(&lbas, rbuf.as_mut())?;
+        for (nth, &lba) in lbas.iter().enumerate() {
+            let expected = if lba == 7 { 0xff } else { lba as u8 };
+            assert_eq!(rbuf.as_slice()[nth * BLOCK_SIZE], expected);
+        }
+
+        assert!(sworndisk.read_scattered(&lbas[..2], rbuf.as_mut()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_read_holes() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+
+        let mut wbuf = Buf::alloc(1)?;
+        wbuf.as_mut_slice().fill(5);
+        sworndisk.write(5 as Lba, wbuf.as_ref())?;
+
+        let check_holes = |sworndisk: &SwornDisk<MemDisk>| -> Result<()> {
+            let mut rbuf = Buf::alloc(8)?;
+            rbuf.as_mut_slice().fill(0xaa);
+            let holes = sworndisk.read_with_holes(0 as Lba, rbuf.as_mut())?;
+            assert_eq!(holes, vec![0..5, 6..8]);
+            for (nth, block) in rbuf.as_slice().chunks(BLOCK_SIZE).enumerate() {
+                let expected = if nth == 5 { 5u8 } else { 0u8 };
+                assert!(block.iter().all(|&byte| byte == expected));
+            }
+
+            let mut rbuf = Buf::alloc(1)?;
+            rbuf.as_mut_slice().fill(0xaa);
+            assert_eq!(
+                sworndisk.read_with_holes(9 as Lba, rbuf.as_mut())?,
+                vec![9..10]
+            );
+            assert_eq!(rbuf.as_slice(), [0u8; BLOCK_SIZE]);
+            rbuf.as_mut_slice().fill(0xaa);
+            sworndisk.read_scattered(&[3], rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice(), [0u8; BLOCK_SIZE]);
+            Ok(())
+        };
+
+        // Holes in `DataBuf` and `TxLsmTree`
+        check_holes(&sworndisk)?;
+        sworndisk.sync()?;
+        check_holes(&sworndisk)?;
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_verify_range() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk.clone(), Key::random(), None, None)?;
+
+        let num_rw = 8;
+        let mut wbuf = Buf::alloc(num_rw)?;
+        wbuf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+        let mut wbuf = Buf::alloc(1)?;
+        sworndisk.write(num_rw as Lba, wbuf.as_ref())?;
+
+        let results = sworndisk.verify_range(0 as Lba, num_rw + 2)?;
+        assert!(results[..num_rw].iter().all(|&r| r == VerifyResult::Pass));
+        assert_eq!(results[num_rw], VerifyResult::Buffered);
+        assert_eq!(results[num_rw + 1], VerifyResult::Hole);
+
+        // Corrupt the ciphertext of the first block (data blocks are allocated from 0)
+        wbuf.as_mut_slice().fill(0xff);
+        mem_disk.write(0, wbuf.as_ref())?;
+        let results = sworndisk.verify_range(0 as Lba, num_rw)?;
+        assert_eq!(
+            results.iter().filter(|&&r| r == VerifyResult::Fail).count(),
+            1
+        );
+        assert!(sworndisk
+            .verify_range(sworndisk.total_blocks() as Lba, 1)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_iter_mappings() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+
+        let num_rw = 8;
+        let wbuf = Buf::alloc(num_rw)?;
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+        let synced: Vec<_> = sworndisk.iter_mappings()?.collect::<Result<_>>()?;
+        assert!(synced.iter().map(|(lba, _)| *lba).eq(0..num_rw as Lba));
+
+        // The overwritten blocks in `DataBuf` get new mappings
+        let wbuf = Buf::alloc(2)?;
+        sworndisk.write(4 as Lba, wbuf.as_ref())?;
+        sworndisk.write(num_rw as Lba, wbuf.as_ref())?;
+        let mappings: Vec<_> = sworndisk.iter_mappings()?.collect::<Result<_>>()?;
+        assert!(mappings
+            .iter()
+            .map(|(lba, _)| *lba)
+            .eq(0..(num_rw + 2) as Lba));
+        assert_eq!(mappings[3], synced[3]);
+        assert_ne!(mappings[4], synced[4]);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_huge_write() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+
+        // Buffered blocks to be overwritten by the huge write
+        let mut wbuf = Buf::alloc(1)?;
+        wbuf.as_mut_slice().fill(0xff);
+        for i in 0..8 {
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+
+        // A huge write spanning multiple chunks
+        let num_huge = DATA_BUF_CAP + DATA_BUF_CAP / 2;
+        assert!(num_huge >= sworndisk.inner.config.direct_write_threshold);
+        let mut huge_buf = Buf::alloc(num_huge)?;
+        for (i, block) in huge_buf.as_mut_slice().chunks_mut(BLOCK_SIZE).enumerate() {
+            block.fill(i as u8);
+        }
+        sworndisk.write(0 as Lba, huge_buf.as_ref())?;
+
+        let mut rbuf = Buf::alloc(num_huge)?;
+        sworndisk.read(0 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), huge_buf.as_slice());
+
+        sworndisk.sync()?;
+        let mut rbuf = Buf::alloc(1)?;
+        for i in [0, 7, DATA_BUF_CAP, num_huge - 1] {
+            sworndisk.read(i as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], i as u8);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_key_provider() -> Result<()> {
+        use crate::layers::disk::KekKeyProvider;
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let old_provider = KekKeyProvider::new(Key::random());
+        let sworndisk =
+            SwornDisk::create_with_key_provider(mem_disk.clone(), &old_provider, None, None)?;
+
+        let mut wbuf = Buf::alloc(1)?;
+        wbuf.as_mut_slice().fill(9);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+
+        // Re-wrap the root key with a new provider, the old one no longer works
+        let new_provider = KekKeyProvider::new(Key::random());
+        sworndisk.rewrap_root_key(&new_provider)?;
+        drop(sworndisk);
+
+        assert!(
+            SwornDisk::open_with_key_provider(mem_disk.clone(), &old_provider, None, None).is_err()
+        );
+        let opened_sworndisk =
+            SwornDisk::open_with_key_provider(mem_disk, &new_provider, None, None)?;
+        let mut rbuf = Buf::alloc(1)?;
+        opened_sworndisk.read(0 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice()[0], 9u8);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_group_commit() -> Result<()> {
+        let nthreads = 8;
+        let sync_group = Arc::new(SyncGroup::new());
+        let ncommits = Arc::new(AtomicU64::new(0));
+
+        let handles: Vec<_> = (0..nthreads)
+            .map(|_| {
+                let sync_group = sync_group.clone();
+                let ncommits = ncommits.clone();
+                thread::spawn(move || {
+                    sync_group.sync(|| {
+                        // Hold the commit until all syncs have arrived
+                        while sync_group.state.lock().unwrap().last_ticket < nthreads {
+                            thread::yield_now();
+                        }
+                        ncommits.fetch_add(1, Ordering::Relaxed);
+                        Ok(())
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        // The first commit is followed by at most one group of the rest
+        assert!(ncommits.load(Ordering::Relaxed) <= 2);
+
+        // A failed commit is retried by the waiting syncs
+        let res = sync_group.sync(|| Err(Error::with_msg(IoFailed, "sync failed")));
+        assert!(res.is_err());
+        assert_eq!(sync_group.state.lock().unwrap().committed, nthreads);
+        sync_group.sync(|| Ok(()))?;
+        assert_eq!(sync_group.state.lock().unwrap().committed, nthreads + 2);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_read_write_at() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = Arc::new(SwornDisk::create(mem_disk.clone(), root_key, None, None)?);
+
+        // Unaligned at both ends, across a few full blocks
+        let offset = 3 * BLOCK_SIZE - 100;
+        let bytes: Vec<u8> = (0..4 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+        sworndisk.write_at(offset, &bytes)?;
+        let mut rbytes = vec![0u8; bytes.len() + 200];
+        sworndisk.read_at(offset - 100, &mut rbytes)?;
+        assert!(rbytes[..100].iter().all(|&b| b == 0));
+        assert_eq!(&rbytes[100..100 + bytes.len()], &bytes[..]);
+        assert!(rbytes[100 + bytes.len()..].iter().all(|&b| b == 0));
+
+        let capacity = sworndisk.total_blocks() * BLOCK_SIZE;
+        assert_eq!(
+            sworndisk
+                .write_at(capacity - 1, &[0u8; 2])
+                .unwrap_err()
+                .errno(),
+            OutOfDisk
+        );
+        assert_eq!(
+            sworndisk
+                .read_at(usize::MAX, &mut [0u8; 1])
+                .unwrap_err()
+                .errno(),
+            OutOfDisk
+        );
+
+        // Concurrent writers of disjoint bytes in the same blocks lose nothing
+        let nthreads = 4;
+        let base = 100 * BLOCK_SIZE;
+        let handles: Vec<_> = (0..nthreads)
+            .map(|i| {
+                let sworndisk = sworndisk.clone();
+                thread::spawn(move || -> Result<()> {
+                    for j in 0..256 {
+                        sworndisk.write_at(base + j * 64 + i * 16, &[(i + 1) as u8; 16])?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        sworndisk.sync()?;
+        drop(sworndisk);
+
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        let mut rbytes = vec![0u8; 256 * 64];
+        sworndisk.read_at(base, &mut rbytes)?;
+        for (nth, byte) in rbytes.iter().enumerate() {
+            assert_eq!(*byte as usize, nth % 64 / 16 + 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_concurrent_sync() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = Arc::new(SwornDisk::create(mem_disk.clone(), root_key, None, None)?);
+
+        let nthreads = 4;
+        let handles: Vec<_> = (0..nthreads)
+            .map(|i| {
+                let sworndisk = sworndisk.clone();
+                thread::spawn(move || -> Result<()> {
+                    let mut wbuf = Buf::alloc(1)?;
+                    for j in 0..16 {
+                        wbuf.as_mut_slice().fill((i + j) as u8);
+                        sworndisk.write((i * 16 + j) as Lba, wbuf.as_ref())?;
+                        sworndisk.sync()?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        drop(sworndisk);
+
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        let mut rbuf = Buf::alloc(1)?;
+        for i in 0..nthreads {
+            for j in 0..16 {
+                sworndisk.read((i * 16 + j) as Lba, rbuf.as_mut())?;
+                assert_eq!(rbuf.as_slice()[0], (i + j) as u8);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_rollback_detection() -> Result<()> {
+        use crate::layers::disk::TrustedCounter;
+
+        struct MemCounter(AtomicU64);
+        impl TrustedCounter for MemCounter {
+            fn read(&self) -> Result<u64> {
+                Ok(self.0.load(Ordering::Acquire))
+            }
+            fn advance(&self, value: u64) -> Result<()> {
+                self.0.fetch_max(value, Ordering::AcqRel);
+                Ok(())
+            }
+        }
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let counter = Arc::new(MemCounter(AtomicU64::new(5)));
+        let config = || Config {
+            trusted_counter: Some(counter.clone()),
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config()))?;
+
+        let mut wbuf = Buf::alloc(1)?;
+        wbuf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+        assert_eq!(counter.read()?, 6);
+
+        // Keep the superblock of the old image
+        let superblock_pos = nblocks - SUPERBLOCK_NBLOCKS;
+        let mut old_superblock = Buf::alloc(SUPERBLOCK_NBLOCKS)?;
+        mem_disk.read(superblock_pos, old_superblock.as_mut())?;
+        wbuf.as_mut_slice().fill(2);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+        assert_eq!(counter.read()?, 7);
+        drop(sworndisk);
+
+        let mut new_superblock = Buf::alloc(SUPERBLOCK_NBLOCKS)?;
+        mem_disk.read(superblock_pos, new_superblock.as_mut())?;
+        mem_disk.write(superblock_pos, old_superblock.as_ref())?;
+        let res = SwornDisk::open(mem_disk.clone(), root_key, None, Some(config()));
+        assert_eq!(res.err().unwrap().errno(), RolledBack);
+
+        mem_disk.write(superblock_pos, new_superblock.as_ref())?;
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, Some(config()))?;
+        let mut rbuf = Buf::alloc(1)?;
+        sworndisk.read(0 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice()[0], 2u8);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_sync_id_store_rollback() -> Result<()> {
+        struct MemSyncIdStore(AtomicU64);
+        impl SyncIdStore for MemSyncIdStore {
+            fn read(&self) -> Result<SyncId> {
+                Ok(self.0.load(Ordering::Acquire))
+            }
+            fn write(&self, id: SyncId) -> Result<()> {
+                self.0.store(id, Ordering::Release);
+                Ok(())
+            }
+        }
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let store = Arc::new(MemSyncIdStore(AtomicU64::new(0)));
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, Some(store.clone()), None)?;
+        let mut wbuf = Buf::alloc(1)?;
+        wbuf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+        drop(sworndisk);
+        let sync_id = store.read()?;
+
+        // The store may fall behind the disk by a crash after the WAL is synced,
+        // the sync ID in the store is taken
+        store.write(sync_id - 1)?;
+        let sworndisk = SwornDisk::open(mem_disk.clone(), root_key, Some(store.clone()), None)?;
+        assert_eq!(sworndisk.sync_id(), sync_id - 1);
+        drop(sworndisk);
+
+        // The store is ahead of the disk, whose newer syncs are rolled back
+        store.write(sync_id + 1)?;
+        let res = SwornDisk::open(mem_disk, root_key, Some(store.clone()), None);
+        assert_eq!(res.err().unwrap().errno(), RolledBack);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_aead_backend() -> Result<()> {
+        use crate::os::{AeadBackend, NativeAeadBackend};
+
+        // A backend that counts the batches on top of the native one
+        struct CountingBackend(AtomicU64);
+        impl AeadBackend for CountingBackend {
+            fn name(&self) -> &'static str {
+                "counting"
+            }
+            fn encrypt(
+                &self,
+                input: &[u8],
+                key: &Key,
+                iv: &Iv,
+                aad: &[u8],
+                output: &mut [u8],
+            ) -> Result<Mac> {
+                NativeAeadBackend.encrypt(input, key, iv, aad, output)
+            }
+            fn decrypt(
+                &self,
+                input: &[u8],
+                key: &Key,
+                iv: &Iv,
+                aad: &[u8],
+                mac: &Mac,
+                output: &mut [u8],
+            ) -> Result<()> {
+                NativeAeadBackend.decrypt(input, key, iv, aad, mac, output)
+            }
+            fn encrypt_batch(
+                &self,
+                inputs: &[&[u8]],
+                keys: &[&Key],
+                ivs: &[Iv],
+                aads: &[&[u8]],
+                outputs: &mut [&mut [u8]],
+                macs: &mut [Mac],
+            ) -> Result<()> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                NativeAeadBackend.encrypt_batch(inputs, keys, ivs, aads, outputs, macs)
+            }
+        }
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let backend = Arc::new(CountingBackend(AtomicU64::new(0)));
+        let config = Config {
+            aead_backend: Some(backend.clone()),
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, Some(config))?;
+
+        let num_rw = 64;
+        let mut wbuf = Buf::alloc(num_rw)?;
+        for (i, block) in wbuf.as_mut_slice().chunks_mut(BLOCK_SIZE).enumerate() {
+            block.fill(i as u8);
+        }
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+        // The consecutive blocks are encrypted in batches rather than one by one
+        let nbatches = backend.0.load(Ordering::Relaxed);
+        assert!(nbatches > 0 && nbatches < num_rw as u64);
+
+        let mut rbuf = Buf::alloc(num_rw)?;
+        sworndisk.read(0 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+        Ok(())
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn sworndisk_queued_bio_stats() -> Result<()> {
+        use crate::layers::disk::bio::BioPriority;
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+
+        let num_rw = 8;
+        let mut wbuf = Buf::alloc(num_rw)?;
+        for i in 0..num_rw {
+            let buf_slice = &mut wbuf.as_mut_slice()[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE];
+            buf_slice.fill(i as u8);
+            let block_buf = unsafe {
+                BlockBuf::from_raw_parts(NonNull::new(buf_slice.as_mut_ptr()).unwrap(), BLOCK_SIZE)
+            };
+            let bio_req = BioReqBuilder::new(BioType::Write)
+                .addr(i as BlockId)
+                .bufs(vec![block_buf])
+                .build();
+            sworndisk.submit_bio(bio_req)?;
+        }
+        let sync_req = BioReqBuilder::new(BioType::Sync)
+            .priority(BioPriority::High)
+            .build();
+        assert_eq!(sync_req.priority(), BioPriority::High);
+        sworndisk.submit_bio(sync_req)?;
+        assert_eq!(sworndisk.handle_queued_bios(), num_rw + 1);
+
+        let stats = sworndisk.stats().bio().get_stats();
+        assert_eq!(stats.write.count, num_rw as u64);
+        assert_eq!(stats.sync.count, 1);
+        assert_eq!(stats.max_queue_depth, num_rw as u64 + 1);
+        let hist_count: u64 = stats.write.latency_hist.iter().sum();
+        assert_eq!(hist_count, num_rw as u64);
+
+        let mut rbuf = Buf::alloc(1)?;
+        sworndisk.read(3 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice()[0], 3u8);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_default_sync_id_store() -> Result<()> {
+        use crate::layers::disk::TxLogSyncIdStore;
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..3 {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+            sworndisk.sync()?;
+        }
+        let sync_id = TxLogSyncIdStore::new(sworndisk.inner.tx_log_store.clone()).read()?;
+        assert!(sync_id >= 3);
+        drop(sworndisk);
+
+        // The sync ID survives reopening, and keeps growing
+        let opened_sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        let store = TxLogSyncIdStore::new(opened_sworndisk.inner.tx_log_store.clone());
+        assert_eq!(store.read()?, sync_id);
+        opened_sworndisk.write(3 as Lba, wbuf.as_ref())?;
+        opened_sworndisk.sync()?;
+        assert!(store.read()? > sync_id);
+
+        let mut rbuf = Buf::alloc(1)?;
+        opened_sworndisk.read(1 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice()[0], 1u8);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_mem_usage() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let cache_size = 4 * 1024 * 1024;
+        let invalid_config = Config {
+            cache_size,
+            sst_cache_size: Some(cache_size + 1),
+            ..Config::default()
+        };
+        let res = SwornDisk::create(mem_disk.clone(), root_key, None, Some(invalid_config));
+        assert_eq!(res.err().unwrap().errno(), InvalidArgs);
+
+        let config = Config {
+            cache_size,
+            ..Config::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk, root_key, None, Some(config))?;
+        let budget = sworndisk.mem_usage().budget;
+        assert!(budget.data_buf + budget.sst_block + budget.log_block <= cache_size);
+
+        // The data buffer is bounded by its share
+        let data_buf_cap = budget.data_buf / BLOCK_SIZE;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..data_buf_cap / 2 {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        let usage = sworndisk.mem_usage();
+        assert_eq!(usage.data_buf, data_buf_cap / 2 * BLOCK_SIZE);
+        for i in 0..data_buf_cap * 2 {
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        assert!(sworndisk.mem_usage().data_buf <= budget.data_buf);
+        sworndisk.sync()?;
+        assert_eq!(sworndisk.mem_usage().data_buf, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_lsm_params() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let invalid_config = Config {
+            sst_block_size: Some(BLOCK_SIZE - 1),
+            ..Config::default()
+        };
+        assert!(SwornDisk::create(mem_disk.clone(), root_key, None, Some(invalid_config)).is_err());
+
+        // Tiny MemTables to trigger compactions
+        let config = Config {
+            memtable_capacity: Some(256),
+            sst_block_size: Some(BLOCK_SIZE),
+            lsm_level_ratio: Some(2),
+            compaction_policy: CompactionPolicyKind::Tiered,
+            ..Config::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config))?;
+        let num_rw = 2048;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        drop(sworndisk);
+
+        // The persisted parameters are used regardless of the config
+        let opened_sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        let lsm_params = *opened_sworndisk.inner.superblock.lock().lsm_params();
+        assert_eq!(lsm_params.memtable_capacity, 256);
+        assert_eq!(lsm_params.sst_block_size as usize, BLOCK_SIZE);
+        assert_eq!(lsm_params.level_ratio, 2);
+        assert_eq!(
+            lsm_params.compaction_policy_kind()?,
+            CompactionPolicyKind::Tiered
+        );
+        let mut rbuf = Buf::alloc(1)?;
+        for i in (0..num_rw).step_by(97) {
+            opened_sworndisk.read(i as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], i as u8);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn sworndisk_batch_stats() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+
+        // Contiguous blocks are allocated, written and read in one batch
+        let num_rw = 8;
+        let mut buf = Buf::alloc(num_rw)?;
+        buf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, buf.as_ref())?;
+        sworndisk.sync()?;
+        sworndisk.read(0 as Lba, buf.as_mut())?;
+        sworndisk.read(3 as Lba, Buf::alloc(1)?.as_mut())?;
+
+        let stats = sworndisk.stats().bio().get_stats();
+        // The nth bucket counts the batches in `[2^(n-1), 2^n)`
+        let bucket = (usize::BITS - num_rw.leading_zeros()) as usize;
+        for batches in [&stats.read_batches, &stats.write_batches] {
+            assert!(batches.size_hist[bucket] > 0);
+            assert!(batches.num_records >= num_rw as u64);
+        }
+        assert!(stats.read_batches.size_hist[1] > 0);
+        assert!(stats.read_batches.avg_batch_size() > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_isolated_instances() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let gc_config = Config {
+            enable_gc: true,
+            aggregate_stats: false,
+            ..Default::default()
+        };
+        let gc_disk = SwornDisk::create(
+            MemDisk::create(nblocks)?,
+            Key::random(),
+            None,
+            Some(gc_config),
+        )?;
+        let config = Config {
+            aggregate_stats: false,
+            ..Default::default()
+        };
+        let sworndisk =
+            SwornDisk::create(MemDisk::create(nblocks)?, Key::random(), None, Some(config))?;
+
+        // Each instance runs with its own config, whichever is created first
+        assert!(gc_disk.fragmentation_report(0.5).is_ok());
+        assert!(sworndisk.fragmentation_report(0.5).is_err());
+
+        let num_rw = 8;
+        let mut buf = Buf::alloc(num_rw)?;
+        buf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, buf.as_ref())?;
+        sworndisk.sync()?;
+        sworndisk.read(0 as Lba, buf.as_mut())?;
+        // The I/Os are counted whether the statistics are enabled or not
+        let stats = sworndisk.stats().bio().get_stats();
+        assert_eq!(stats.write_batches.num_records, num_rw as u64);
+        let stats = gc_disk.stats().bio().get_stats();
+        assert_eq!(stats.write_batches.num_records, 0);
+        assert_eq!(stats.read_batches.num_records, 0);
+        Ok(())
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn sworndisk_sim_clock() -> Result<()> {
+        use std::time::Duration;
+
+        let nblocks = 64 * 1024;
+        let sworndisk_a = SwornDisk::create(MemDisk::create(nblocks)?, Key::random(), None, None)?;
+        let sworndisk_b = SwornDisk::create(MemDisk::create(nblocks)?, Key::random(), None, None)?;
+        // Advancing the clock of one disk doesn't wake the tasks of the other
+        sworndisk_a.sim_clock().advance(Duration::from_secs(1));
+        assert_eq!(sworndisk_a.sim_clock().now(), Duration::from_secs(1));
+        assert_eq!(sworndisk_b.sim_clock().now(), Duration::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_events() -> Result<()> {
+        use crate::layers::disk::EventSubscriber;
+        use std::sync::mpsc::{channel, Sender};
+        use std::time::Duration;
+
+        struct ChannelSubscriber(std::sync::Mutex<Sender<DiskEvent>>);
+        impl EventSubscriber for ChannelSubscriber {
+            fn on_event(&self, event: &DiskEvent) {
+                let _ = self.0.lock().unwrap().send(event.clone());
+            }
+        }
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+        let (sender, receiver) = channel();
+        let id =
+            sworndisk.subscribe_events(Arc::new(ChannelSubscriber(std::sync::Mutex::new(sender))));
+
+        let mut buf = Buf::alloc(4)?;
+        buf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, buf.as_ref())?;
+        sworndisk.sync()?;
+        sworndisk.discard(1 as Lba, 2)?;
+        let recv = || receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            recv(),
+            DiskEvent::SnapshotCreated {
+                sync_id: sworndisk.sync_id()
+            }
+        );
+        assert_eq!(recv(), DiskEvent::Discarded { lba: 1, nblocks: 2 });
+
+        assert!(sworndisk.unsubscribe_events(id));
+        sworndisk.sync()?;
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "chaos")]
+    #[test]
+    fn sworndisk_chaos_unindexed_data() -> Result<()> {
+        use crate::util::{fail_hook, set_chaos_hook, ChaosPoint};
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
+        let mut buf = Buf::alloc(1)?;
+        buf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, buf.as_ref())?;
+        sworndisk.sync()?;
+
+        // Fail between the data write and the index put
+        buf.as_mut_slice().fill(2);
+        sworndisk.write(0 as Lba, buf.as_ref())?;
+        let guard = set_chaos_hook(ChaosPoint::DataWritten, fail_hook(IoFailed, 1));
+        assert_eq!(sworndisk.sync().unwrap_err().errno(), IoFailed);
+        drop(guard);
+        drop(sworndisk);
+
+        // The written but unindexed block is never visible
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        sworndisk.read(0 as Lba, buf.as_mut())?;
+        assert_eq!(buf.as_slice()[0], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_hang_timeout() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let config = Config {
+            enable_gc: true,
+            hang_timeout: Some(core::time::Duration::from_millis(10)),
+            fail_on_hang: true,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, Some(config))?;
+        let mut buf = Buf::alloc(1)?;
+
+        // A stuck GC blocks the reads missing the data buffer
+        let gc_guard = sworndisk.inner.shared_state.begin_gc();
+        let err = sworndisk.read(0 as Lba, buf.as_mut()).unwrap_err();
+        assert_eq!(err.errno(), Timeout);
+        drop(gc_guard);
+        sworndisk.read(0 as Lba, buf.as_mut())?;
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_merged_bios() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+        let num_rw = 16;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+
+        let lbas = [0, 1, 2, 10, 11];
+        let mut rbuf = Buf::alloc(lbas.len())?;
+        let queue = BioReqQueue::with_merge_window(2, Arc::new(DiskStats::new(false)));
+        for (nth, &lba) in lbas.iter().enumerate() {
+            let buf_slice = &mut rbuf.as_mut_slice()[nth * BLOCK_SIZE..(nth + 1) * BLOCK_SIZE];
+            let block_buf = unsafe {
+                BlockBuf::from_raw_parts(NonNull::new(buf_slice.as_mut_ptr()).unwrap(), BLOCK_SIZE)
+            };
+            let bio_req = BioReqBuilder::new(BioType::Read)
+                .addr(lba as BlockId)
+                .bufs(vec![block_buf])
+                .build();
+            queue.enqueue(bio_req)?;
+        }
+
+        let mut merged_addrs = Vec::new();
+        while let Some(bio_reqs) = queue.dequeue_merged() {
+            merged_addrs.push(bio_reqs.iter().map(|req| req.addr()).collect::<Vec<_>>());
+            sworndisk.inner.handle_merged_bio_reqs(&bio_reqs)?;
         }
+        assert_eq!(merged_addrs, vec![vec![0, 1], vec![2], vec![10, 11]]);
+        assert!(queue.is_empty());
+        for (nth, &lba) in lbas.iter().enumerate() {
+            assert_eq!(rbuf.as_slice()[nth * BLOCK_SIZE], lba as u8);
+        }
+        Ok(())
     }
-}
 
-impl<D: BlockSet + 'static> TxEventListenerFactory<RecordKey, RecordValue>
-    for TxLsmTreeListenerFactory<D>
-{
-    fn new_event_listener(
-        &self,
-        tx_type: TxType,
-    ) -> Arc<dyn TxEventListener<RecordKey, RecordValue>> {
-        Arc::new(TxLsmTreeListener::new(
-            tx_type,
-            Arc::new(BlockAlloc::new(
-                self.alloc_table.clone(),
-                self.store.clone(),
-            )),
-            self.dealloc_table.clone(),
-        ))
+    #[test]
+    fn sworndisk_urgent_bios() -> Result<()> {
+        use crate::layers::disk::bio::BioPriority;
+
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+        let num_rw = 16;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+
+        // The urgent reads jump the queue in order, unmerged
+        let reqs = [(0, false), (1, false), (5, true), (6, true), (2, false)];
+        let mut rbuf = Buf::alloc(reqs.len())?;
+        let queue = BioReqQueue::with_merge_window(4, Arc::new(DiskStats::new(false)));
+        for (nth, &(lba, urgent)) in reqs.iter().enumerate() {
+            let buf_slice = &mut rbuf.as_mut_slice()[nth * BLOCK_SIZE..(nth + 1) * BLOCK_SIZE];
+            let block_buf = unsafe {
+                BlockBuf::from_raw_parts(NonNull::new(buf_slice.as_mut_ptr()).unwrap(), BLOCK_SIZE)
+            };
+            let priority = if urgent {
+                BioPriority::High
+            } else {
+                BioPriority::Normal
+            };
+            let bio_req = BioReqBuilder::new(BioType::Read)
+                .addr(lba as BlockId)
+                .bufs(vec![block_buf])
+                .priority(priority)
+                .build();
+            assert_eq!(bio_req.is_urgent_read(), urgent);
+            queue.enqueue(bio_req)?;
+        }
+
+        let mut merged_addrs = Vec::new();
+        while let Some(bio_reqs) = queue.dequeue_merged() {
+            merged_addrs.push(bio_reqs.iter().map(|req| req.addr()).collect::<Vec<_>>());
+            sworndisk.inner.handle_merged_bio_reqs(&bio_reqs)?;
+        }
+        assert_eq!(merged_addrs, vec![vec![5], vec![6], vec![0, 1, 2]]);
+        for (nth, &(lba, _)) in reqs.iter().enumerate() {
+            assert_eq!(rbuf.as_slice()[nth * BLOCK_SIZE], lba as u8);
+        }
+
+        let mut rbuf = Buf::alloc(2)?;
+        sworndisk.read_urgent(3 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice()[BLOCK_SIZE], 4u8);
+        #[cfg(feature = "stats")]
+        {
+            let stats = sworndisk.stats().bio().get_stats();
+            assert_eq!(stats.urgent_read.count, 3);
+            assert_eq!(stats.read.count, 3);
+        }
+        Ok(())
     }
-}
 
-struct EmptyFactory;
-struct EmptyListener;
+    #[test]
+    fn sworndisk_fua_and_flush() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
 
-impl<K, V> TxEventListenerFactory<K, V> for EmptyFactory {
-    fn new_event_listener(&self, _tx_type: TxType) -> Arc<dyn TxEventListener<K, V>> {
-        Arc::new(EmptyListener)
+        let mut wbuf = Buf::alloc(1)?;
+        wbuf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        // The barrier orders the buffered block ahead of the FUA writes
+        sworndisk.submit_bio_sync(BioReqBuilder::new(BioType::Flush).build())?;
+        assert!(sworndisk.inner.data_buf.is_empty());
+
+        wbuf.as_mut_slice().fill(2);
+        sworndisk.write_fua(1 as Lba, wbuf.as_ref())?;
+        let block_buf = unsafe {
+            BlockBuf::from_raw_parts(
+                NonNull::new(wbuf.as_mut_slice().as_mut_ptr()).unwrap(),
+                BLOCK_SIZE,
+            )
+        };
+        let bio_req = BioReqBuilder::new(BioType::Write)
+            .addr(2 as BlockId)
+            .bufs(vec![block_buf])
+            .fua(true)
+            .build();
+        assert!(bio_req.is_fua());
+        sworndisk.submit_bio_sync(bio_req)?;
+
+        // A buffered block is left in `DataBuf` by the FUA writes
+        sworndisk.write(3 as Lba, wbuf.as_ref())?;
+        sworndisk.write_fua(4 as Lba, wbuf.as_ref())?;
+        assert!(!sworndisk.inner.data_buf.is_empty());
+
+        // Reopen without a sync, the blocks before the last FUA write survive
+        drop(sworndisk);
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        let mut rbuf = Buf::alloc(1)?;
+        for (lba, value) in [(0, 1u8), (1, 2), (2, 2), (4, 2)] {
+            sworndisk.read(lba as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], value);
+        }
+        Ok(())
     }
-}
-impl<K, V> TxEventListener<K, V> for EmptyListener {
-    fn on_add_record(&self, _record: &dyn AsKV<K, V>) -> Result<()> {
+
+    #[test]
+    fn sworndisk_relaxed_writes() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
+
+        let mut wbuf = Buf::alloc(8)?;
+        wbuf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+
+        // Persisted by the checkpoints of the following syncs, with and
+        // without the logged records
+        let mut wbuf = Buf::alloc(2)?;
+        for (lba, value) in [(0, 2u8), (2, 2), (4, 3)] {
+            wbuf.as_mut_slice().fill(value);
+            sworndisk.write_relaxed(lba as Lba, wbuf.as_ref())?;
+            if lba != 0 {
+                sworndisk.sync()?;
+            }
+        }
+        assert!(sworndisk.inner.data_buf.is_empty());
+
+        // Lost on a crash before the next sync
+        wbuf.as_mut_slice().fill(4);
+        sworndisk.write_relaxed(6 as Lba, wbuf.as_ref())?;
+        sworndisk.write_relaxed(8 as Lba, wbuf.as_ref())?;
+        let mut rbuf = Buf::alloc(1)?;
+        sworndisk.read(8 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice()[0], 4);
+
+        drop(sworndisk);
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        for (lba, value) in [(0, 2u8), (3, 2), (4, 3), (5, 3), (6, 1), (7, 1)] {
+            sworndisk.read(lba as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], value);
+        }
+        let holes = sworndisk.read_with_holes(8 as Lba, rbuf.as_mut())?;
+        assert_eq!(holes, vec![8..9]);
         Ok(())
     }
-    fn on_drop_record(&self, _record: &dyn AsKV<K, V>) -> Result<()> {
+
+    #[test]
+    fn sworndisk_clone_range() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, None)?;
+
+        let num_rw = 600;
+        let mut wbuf = Buf::alloc(num_rw)?;
+        for (i, block) in wbuf.as_mut_slice().chunks_mut(BLOCK_SIZE).enumerate() {
+            block.fill(i as u8);
+        }
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+
+        // Clone to a disjoint range
+        sworndisk.clone_range(0, 1000, num_rw)?;
+        let mut rbuf = Buf::alloc(num_rw)?;
+        sworndisk.read(1000 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+
+        // Clone to an overlapping range, in both directions
+        sworndisk.clone_range(1000, 1300, num_rw)?;
+        sworndisk.read(1300 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+        sworndisk.clone_range(1300, 1010, num_rw)?;
+        sworndisk.sync()?;
+        sworndisk.read(1010 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+
+        // Holes are cloned as zero-filled blocks
+        sworndisk.clone_range(5000, 0, 1)?;
+        let mut block = Buf::alloc(1)?;
+        sworndisk.read(0 as Lba, block.as_mut())?;
+        assert_eq!(block.as_slice(), [0u8; BLOCK_SIZE]);
+
+        assert!(sworndisk
+            .clone_range(0, sworndisk.total_blocks() as Lba, 1)
+            .is_err());
         Ok(())
     }
-    fn on_tx_begin(&self, _tx: &mut Tx) -> Result<()> {
+
+    #[test]
+    fn sworndisk_clone_range_shared() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let config = Config {
+            shared_blocks: true,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config))?;
+        assert!(sworndisk.inner.disk_id.is_none());
+
+        let num_rw = 600;
+        let mut wbuf = Buf::alloc(num_rw)?;
+        for (i, block) in wbuf.as_mut_slice().chunks_mut(BLOCK_SIZE).enumerate() {
+            block.fill(i as u8);
+        }
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+
+        // The cloned records share the blocks of the source, no block is written
+        let utilization = sworndisk.inner.block_validity_table.utilization();
+        sworndisk.clone_range(0, 1000, num_rw)?;
+        assert_eq!(
+            sworndisk.inner.block_validity_table.utilization(),
+            utilization
+        );
+        let hba = sworndisk
+            .inner
+            .logical_block_table
+            .get(&RecordKey { lba: 0 })?
+            .hba;
+        let ref_count = |sworndisk: &SwornDisk<MemDisk>| {
+            sworndisk.inner.ref_counts.as_ref().unwrap().count(hba)
+        };
+        assert_eq!(ref_count(&sworndisk), 2);
+        let mut rbuf = Buf::alloc(num_rw)?;
+        sworndisk.read(1000 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+
+        // Clone to an overlapping range, the overwritten records are released
+        sworndisk.clone_range(1000, 1300, num_rw)?;
+        sworndisk.read(1300 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+        assert_eq!(ref_count(&sworndisk), 3);
+
+        // Holes are cloned as discards
+        sworndisk.clone_range(5000, 0, 1)?;
+        let mut block = Buf::alloc(1)?;
+        assert_eq!(
+            sworndisk.read_with_holes(0 as Lba, block.as_mut())?,
+            vec![0..1]
+        );
+        // The synced record is dropped on sync
+        sworndisk.sync()?;
+        assert_eq!(ref_count(&sworndisk), 2);
+        drop(sworndisk);
+
+        // The shared blocks pass the audit
+        let sworndisk = SwornDisk::open_readonly(mem_disk.clone(), root_key, None, None)?;
+        assert!(sworndisk.audit()?.is_clean());
+        drop(sworndisk);
+
+        // The counts survive a restart, the block is deallocated with its last record
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        assert_eq!(ref_count(&sworndisk), 2);
+        sworndisk.read(1300 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+        let zeroed = Buf::alloc(1)?;
+        sworndisk.write(1000 as Lba, zeroed.as_ref())?;
+        sworndisk.write(1300 as Lba, zeroed.as_ref())?;
+        sworndisk.sync()?;
+        assert!(!sworndisk.inner.ref_counts.as_ref().unwrap().is_counted(hba));
+
+        // Can't be combined with GC
+        let config = Config {
+            shared_blocks: true,
+            enable_gc: true,
+            ..Default::default()
+        };
+        assert!(
+            SwornDisk::create(MemDisk::create(nblocks)?, root_key, None, Some(config)).is_err()
+        );
         Ok(())
     }
-    fn on_tx_precommit(&self, _tx: &mut Tx) -> Result<()> {
+
+    #[test]
+    fn sworndisk_bulk_writer() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let config = Config {
+            enable_gc: true,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config.clone()))?;
+        // An older block in the `MemTable`
+        let mut block = Buf::alloc(1)?;
+        block.as_mut_slice().fill(u8::MAX);
+        sworndisk.write(5 as Lba, block.as_ref())?;
+        sworndisk.sync()?;
+
+        let num_rw = 2048;
+        let mut wbuf = Buf::alloc(num_rw)?;
+        for (i, block) in wbuf.as_mut_slice().chunks_mut(BLOCK_SIZE).enumerate() {
+            block.fill(i as u8);
+        }
+        let mut writer = sworndisk.bulk_writer()?;
+        for (nth, chunk) in wbuf.as_slice().chunks(64 * BLOCK_SIZE).enumerate() {
+            writer.write((nth * 64) as Lba, BufRef::try_from(chunk)?)?;
+        }
+        let err = writer.write(0 as Lba, block.as_ref()).unwrap_err();
+        assert_eq!(err.errno(), InvalidArgs);
+        writer.finish()?;
+
+        let mut rbuf = Buf::alloc(num_rw)?;
+        sworndisk.read(0 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+        assert_eq!(sworndisk.iter_mappings()?.count(), num_rw);
+
+        drop(sworndisk);
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, Some(config))?;
+        sworndisk.read(0 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
         Ok(())
     }
-    fn on_tx_commit(&self) {}
-}
 
-/// Event listener for `TxLsmTree`.
-struct TxLsmTreeListener<D> {
-    tx_type: TxType,
-    block_alloc: Arc<BlockAlloc<D>>,
-    dealloc_table: Arc<DeallocTable>,
-}
+    #[test]
+    fn sworndisk_open_readonly() -> Result<()> {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hasher;
 
-impl<D> TxLsmTreeListener<D> {
-    fn new(
-        tx_type: TxType,
-        block_alloc: Arc<BlockAlloc<D>>,
-        reverse_index_table: Arc<DeallocTable>,
-    ) -> Self {
-        Self {
-            tx_type,
-            block_alloc,
-            dealloc_table: reverse_index_table,
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
+        let num_rw = 128;
+        let mut wbuf = Buf::alloc(num_rw)?;
+        for (i, block) in wbuf.as_mut_slice().chunks_mut(BLOCK_SIZE).enumerate() {
+            block.fill(i as u8);
         }
-    }
-}
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+        // The unsynced blocks are discarded by the recovery
+        sworndisk.write(num_rw as Lba, wbuf.as_ref())?;
+        drop(sworndisk);
 
-/// Register callbacks for different TXs in `TxLsmTree`.
-impl<D: BlockSet + 'static> TxEventListener<RecordKey, RecordValue> for TxLsmTreeListener<D> {
-    fn on_add_record(&self, record: &dyn AsKV<RecordKey, RecordValue>) -> Result<()> {
-        match self.tx_type {
-            TxType::Compaction { to_level } if to_level == LsmLevel::L0 => {
-                self.block_alloc.alloc_block(record.value().hba)
-            }
-            // Major Compaction TX and Migration TX do not add new records
-            TxType::Compaction { .. } | TxType::Migration => {
-                // Do nothing
-                Ok(())
+        let digest = |disk: &MemDisk| -> Result<u64> {
+            let mut hasher = DefaultHasher::new();
+            let mut chunk = Buf::alloc(1024)?;
+            for pos in (0..disk.nblocks()).step_by(1024) {
+                disk.read(pos, chunk.as_mut())?;
+                hasher.write(chunk.as_slice());
             }
+            Ok(hasher.finish())
+        };
+        let digest_before = digest(&mem_disk)?;
+
+        let sworndisk = SwornDisk::open_readonly(mem_disk.clone(), root_key, None, None)?;
+        let mut rbuf = Buf::alloc(num_rw)?;
+        sworndisk.read(0 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+
+        let is_denied = |res: Result<()>| res.is_err_and(|e| e.errno() == PermissionDenied);
+        assert!(is_denied(sworndisk.write(0 as Lba, wbuf.as_ref())));
+        assert!(is_denied(sworndisk.write_fua(0 as Lba, wbuf.as_ref())));
+        assert!(is_denied(sworndisk.sync()));
+        assert!(is_denied(sworndisk.flush()));
+        assert!(is_denied(
+            sworndisk.submit_bio_sync(BioReqBuilder::new(BioType::Sync).build())
+        ));
+        drop(sworndisk);
+
+        assert_eq!(digest(&mem_disk)?, digest_before);
+        let _ = SwornDisk::open(mem_disk, root_key, None, None)?;
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_ephemeral() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        // A stale superblock is erased
+        let _ = SwornDisk::create(mem_disk.clone(), Key::random(), None, None)?;
+        // Tiny MemTables to trigger compactions
+        let config = Config {
+            enable_gc: true,
+            memtable_capacity: Some(256),
+            ..Config::default()
+        };
+        let sworndisk = SwornDisk::create_ephemeral(mem_disk.clone(), Some(config.clone()))?;
+        let num_rw = 2048;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        let mut rbuf = Buf::alloc(1)?;
+        for i in (0..num_rw).step_by(97) {
+            sworndisk.read(i as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], i as u8);
         }
+        drop(sworndisk);
+
+        // No superblock to open, and nothing survives reopening
+        let superblock_disk = SwornDisk::subdisk_for_superblock(&mem_disk)?;
+        assert!(Superblock::open(&superblock_disk, &Key::random()).is_err());
+        let config = Config {
+            ephemeral: true,
+            ..config
+        };
+        assert!(SwornDisk::open_readonly(
+            mem_disk.clone(),
+            Key::random(),
+            None,
+            Some(config.clone())
+        )
+        .is_err());
+        let sworndisk = SwornDisk::open(mem_disk, Key::random(), None, Some(config))?;
+        let holes = sworndisk.read_with_holes(0 as Lba, rbuf.as_mut())?;
+        assert_eq!(holes, vec![0..1]);
+        Ok(())
     }
 
-    fn on_drop_record(&self, record: &dyn AsKV<RecordKey, RecordValue>) -> Result<()> {
-        match self.tx_type {
-            // Minor Compaction TX doesn't compact records
-            TxType::Compaction { to_level } if to_level == LsmLevel::L0 => {
-                unreachable!();
-            }
-            TxType::Compaction { .. } | TxType::Migration => {
-                // Only check dealloc_table when GC is enabled to avoid unnecessary mutex operations
-                if CONFIG.get().enable_gc && self.dealloc_table.has_deallocated(record.value().hba)
-                {
-                    self.dealloc_table.finish_deallocated(record.value().hba);
-                    return Ok(());
-                }
-                self.block_alloc.dealloc_block(record.value().hba)
-            }
+    #[test]
+    fn sworndisk_ephemeral_then_persistent() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let ephemeral_disk = SwornDisk::create_ephemeral(MemDisk::create(nblocks)?, None)?;
+        let mut wbuf = Buf::alloc(1)?;
+        wbuf.as_mut_slice().fill(1);
+        ephemeral_disk.write(0 as Lba, wbuf.as_ref())?;
+
+        // The WALs of another disk in the process are still logged
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
+        let num_rw = 128;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        drop(sworndisk);
+        drop(ephemeral_disk);
+
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        let mut rbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            sworndisk.read(i as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], i as u8);
         }
+        Ok(())
     }
 
-    fn on_tx_begin(&self, tx: &mut Tx) -> Result<()> {
-        match self.tx_type {
-            TxType::Compaction { .. } | TxType::Migration => {
-                tx.context(|| self.block_alloc.prepare_diff_log().unwrap())
-            }
+    #[test]
+    fn sworndisk_deferred_discard() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let config = Config {
+            discard_granularity: 64,
+            ..Config::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, Some(config))?;
+        let mut wbuf = Buf::alloc(256)?;
+        wbuf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+
+        // Merged into a single extent
+        let extents: Vec<_> = (0..16).map(|i| i * 2..i * 2 + 2).collect();
+        sworndisk.discard_batch(&extents)?;
+        // Issued before being read
+        sworndisk.discard(40, 1)?;
+        sworndisk.discard(41, 1)?;
+        let mut rbuf = Buf::alloc(2)?;
+        let holes = sworndisk.read_with_holes(40, rbuf.as_mut())?;
+        assert_eq!(holes, vec![40..42]);
+        // Issued once merged up to the granularity
+        for lba in 100..164 {
+            sworndisk.discard(lba, 1)?;
+        }
+        // Issued before being overwritten
+        sworndisk.discard(200, 1)?;
+        sworndisk.write(200, BufRef::try_from(&wbuf.as_slice()[..BLOCK_SIZE])?)?;
+
+        sworndisk.sync()?;
+        let stats = sworndisk.discard_stats();
+        assert_eq!(stats.num_requests, 16 + 2 + 64 + 1);
+        assert_eq!(stats.num_blocks, 32 + 2 + 64 + 1);
+        assert_eq!(stats.num_pending, 0);
+        assert!(stats.num_issued < stats.num_requests);
+
+        let mut rbuf = Buf::alloc(256)?;
+        let holes = sworndisk.read_with_holes(0, rbuf.as_mut())?;
+        assert_eq!(holes, vec![0..32, 40..42, 100..164]);
+        assert_eq!(rbuf.as_slice()[200 * BLOCK_SIZE], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_deferred_discard_write_at() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let config = Config {
+            discard_granularity: 64,
+            ..Config::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk, Key::random(), None, Some(config))?;
+        let mut wbuf = Buf::alloc(4)?;
+        wbuf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+
+        // An unrelated discard is pending during the read-modify-write
+        sworndisk.discard(100, 1)?;
+        sworndisk.write_at(BLOCK_SIZE + 10, &[2u8; 16])?;
+        // An overlapping one is issued before the block is read
+        sworndisk.discard(2, 1)?;
+        sworndisk.write_at(2 * BLOCK_SIZE + 10, &[3u8; 16])?;
+
+        let mut rbuf = [0u8; 3 * BLOCK_SIZE];
+        sworndisk.read_at(0, &mut rbuf)?;
+        assert!(rbuf[..BLOCK_SIZE].iter().all(|byte| *byte == 1));
+        let block = &rbuf[BLOCK_SIZE..2 * BLOCK_SIZE];
+        assert_eq!(&block[..10], &[1u8; 10]);
+        assert_eq!(&block[10..26], &[2u8; 16]);
+        assert!(block[26..].iter().all(|byte| *byte == 1));
+        let block = &rbuf[2 * BLOCK_SIZE..];
+        assert!(block[..10].iter().all(|byte| *byte == 0));
+        assert_eq!(&block[10..26], &[3u8; 16]);
+        assert!(block[26..].iter().all(|byte| *byte == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_audit() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
+        let num_rw = 16;
+        let mut wbuf = Buf::alloc(num_rw)?;
+        wbuf.as_mut_slice().fill(1);
+        sworndisk.write(0 as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+        let total_blocks = sworndisk.total_blocks();
+        drop(sworndisk);
+
+        let sworndisk = SwornDisk::open_readonly(mem_disk.clone(), root_key, None, None)?;
+        let report = sworndisk.audit()?;
+        assert!(report.is_clean());
+        assert_eq!(report.total_blocks, total_blocks);
+        assert_eq!(report.num_passed, num_rw);
+        assert_eq!(report.num_holes, total_blocks - num_rw);
+        assert_eq!(report.digest.num_mapped, num_rw);
+        drop(sworndisk);
+
+        // Corrupt the ciphertext of two blocks (data blocks are allocated from 0)
+        let mut corrupted = Buf::alloc(2)?;
+        corrupted.as_mut_slice().fill(0xff);
+        mem_disk.write(3, corrupted.as_ref())?;
+        let sworndisk = SwornDisk::open_readonly(mem_disk, root_key, None, None)?;
+        let report = sworndisk.audit()?;
+        assert!(!report.is_clean());
+        assert_eq!(report.num_failed, 2);
+        assert_eq!(report.failed_lbas, vec![3..5]);
+        Ok(())
+    }
+
+    #[test]
+    fn sworndisk_lazy_recovery() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
+        let num_rw = 1024;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        for i in 0..num_rw / 2 {
+            wbuf.as_mut_slice().fill(i as u8 + 1);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        drop(sworndisk);
+
+        let sworndisk = SwornDisk::open(mem_disk.clone(), root_key, None, None)?;
+        let utilization = sworndisk.inner.block_validity_table.utilization();
+        drop(sworndisk);
+
+        let config = Config {
+            lazy_recovery: true,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, Some(config))?;
+        // Reads don't wait for the recovery
+        let mut rbuf = Buf::alloc(1)?;
+        sworndisk.read(0 as Lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice()[0], 1u8);
+
+        // Writes and syncs wait for the recovery
+        wbuf.as_mut_slice().fill(7);
+        sworndisk.write(num_rw as Lba, wbuf.as_ref())?;
+        sworndisk.sync()?;
+        sworndisk.inner.block_validity_table.wait_for_recovery()?;
+        assert!(sworndisk.inner.block_validity_table.utilization() > utilization);
+        for (lba, value) in [(0, 1u8), (num_rw - 1, (num_rw - 1) as u8), (num_rw, 7)] {
+            sworndisk.read(lba as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], value);
         }
         Ok(())
     }
 
-    fn on_tx_precommit(&self, tx: &mut Tx) -> Result<()> {
-        match self.tx_type {
-            TxType::Compaction { .. } | TxType::Migration => {
-                tx.context(|| self.block_alloc.update_diff_log().unwrap())
+    #[test]
+    fn sworndisk_skipped_bvt_compaction() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let config = Config {
+            bvt_compaction: BvtCompactionPolicy::EveryNSyncs(1000),
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config.clone()))?;
+        let num_rw = 1024;
+        let mut wbuf = Buf::alloc(1)?;
+        for round in 0..3 {
+            for i in 0..num_rw >> round {
+                wbuf.as_mut_slice().fill((i + round) as u8);
+                sworndisk.write(i as Lba, wbuf.as_ref())?;
             }
+            sworndisk.sync()?;
+        }
+        assert_eq!(
+            sworndisk
+                .inner
+                .syncs_since_bvt_compaction
+                .load(Ordering::Relaxed),
+            3
+        );
+        let utilization = sworndisk.inner.block_validity_table.utilization();
+        drop(sworndisk);
+
+        // Recovered from the replayed records, neither the overwritten
+        // blocks are leaked nor the live ones are reused
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, Some(config))?;
+        assert_eq!(
+            sworndisk.inner.block_validity_table.utilization(),
+            utilization
+        );
+        for i in num_rw..2 * num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        let mut rbuf = Buf::alloc(1)?;
+        for i in 0..2 * num_rw {
+            let round = if i < num_rw / 4 {
+                2
+            } else if i < num_rw / 2 {
+                1
+            } else {
+                0
+            };
+            let expected = if i < num_rw { i + round } else { i };
+            sworndisk.read(i as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], expected as u8);
         }
         Ok(())
     }
 
-    fn on_tx_commit(&self) {
-        match self.tx_type {
-            TxType::Compaction { .. } | TxType::Migration => self.block_alloc.update_alloc_table(),
+    #[test]
+    fn sworndisk_repair_reverse_index() -> Result<()> {
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let config = Config {
+            enable_gc: true,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config.clone()))?;
+        let num_rw = 128;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
         }
-    }
-}
+        sworndisk.sync()?;
 
-/// Key-Value record for `TxLsmTree`.
-pub(super) struct Record {
-    key: RecordKey,
-    value: RecordValue,
-}
+        // Crash after the logical block table is synced but before the reverse index
+        for i in num_rw..num_rw * 2 {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.inner.flush_data_buf()?;
+        sworndisk.inner.logical_block_table.sync()?;
+        drop(sworndisk);
 
-/// The key of a `Record`.
-#[repr(C)]
-#[derive(Clone, Copy, Pod, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
-pub(super) struct RecordKey {
-    /// Logical block address of user data block.
-    pub lba: Lba,
-}
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, Some(config))?;
+        let inner = &sworndisk.inner;
+        let reverse_index_table = inner.reverse_index_table.as_ref().unwrap();
+        assert_eq!(inner.repair_reverse_index()?, 0);
+        for lba in 0..num_rw * 2 {
+            let hba = inner.logical_block_table.get(&RecordKey { lba })?.hba;
+            assert_eq!(reverse_index_table.get(&ReverseKey { hba })?.lba, lba);
+        }
+        Ok(())
+    }
 
-/// The value of a `Record`.
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Debug)]
-pub(super) struct RecordValue {
-    /// Host block address of user data block.
-    pub hba: Hba,
-    /// Encryption key of the data block.
-    pub key: Key,
-    /// Encrypted MAC of the data block.
-    pub mac: Mac,
-}
+    #[test]
+    fn sworndisk_segment_reverse_index() -> Result<()> {
+        use crate::layers::disk::segment::SEGMENT_SIZE;
 
-impl Add<usize> for RecordKey {
-    type Output = Self;
+        let nblocks = 64 * 1024;
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let config = Config {
+            enable_gc: true,
+            reverse_index_kind: ReverseIndexKind::SegmentBlobs,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config))?;
+        // Seal the first segment, and leave the second one open
+        let num_rw = SEGMENT_SIZE + SEGMENT_SIZE / 2;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        drop(sworndisk);
 
-    fn add(self, other: usize) -> Self::Output {
-        Self {
-            lba: self.lba + other,
+        // The kind is decided on creation, regardless of the config on open
+        let config = Config {
+            enable_gc: true,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, Some(config))?;
+        let inner = &sworndisk.inner;
+        let reverse_index_table = inner.reverse_index_table.as_ref().unwrap();
+        assert!(matches!(reverse_index_table, ReverseIndex::SegmentBlobs(_)));
+        for lba in 0..num_rw {
+            let hba = inner.logical_block_table.get(&RecordKey { lba })?.hba;
+            assert_eq!(reverse_index_table.get(&ReverseKey { hba })?.lba, lba);
         }
+        Ok(())
     }
-}
 
-impl Sub<RecordKey> for RecordKey {
-    type Output = usize;
+    #[test]
+    fn sworndisk_grow() -> Result<()> {
+        let (old_nblocks, nblocks) = (32 * 1024, 64 * 1024);
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let config = Config {
+            enable_gc: true,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(
+            mem_disk.subset(0..old_nblocks)?,
+            root_key,
+            None,
+            Some(config.clone()),
+        )?;
+        let old_total_blocks = sworndisk.total_blocks();
+        let num_rw = 1024;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        drop(sworndisk);
 
-    fn sub(self, other: RecordKey) -> Self::Output {
-        self.lba - other.lba
+        // Not grown enough to move the index tables
+        let err =
+            SwornDisk::grow_offline(mem_disk.subset(0..old_nblocks + 16)?, root_key, old_nblocks)
+                .unwrap_err();
+        assert_eq!(err.errno(), InvalidArgs);
+        SwornDisk::grow_offline(mem_disk.clone(), root_key, old_nblocks)?;
+        assert!(SwornDisk::open(
+            mem_disk.subset(0..old_nblocks)?,
+            root_key,
+            None,
+            Some(config.clone())
+        )
+        .is_err());
+
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, Some(config))?;
+        assert_eq!(
+            sworndisk.total_blocks(),
+            old_total_blocks + nblocks - old_nblocks
+        );
+        let mut rbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            sworndisk.read(i as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], i as u8);
+        }
+        // The added capacity is writable
+        let lba = sworndisk.total_blocks() - 1;
+        wbuf.as_mut_slice().fill(0xff);
+        sworndisk.write(lba, wbuf.as_ref())?;
+        sworndisk.read(lba, rbuf.as_mut())?;
+        assert_eq!(rbuf.as_slice(), wbuf.as_slice());
+        Ok(())
     }
-}
 
-impl RecordK<RecordKey> for RecordKey {}
-impl RecordV for RecordValue {}
+    #[test]
+    fn sworndisk_grow_crash() -> Result<()> {
+        let (old_nblocks, nblocks) = (32 * 1024, 64 * 1024);
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let sworndisk = SwornDisk::create(mem_disk.subset(0..old_nblocks)?, root_key, None, None)?;
+        let num_rw = 1024;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
+        }
+        sworndisk.sync()?;
+        drop(sworndisk);
 
-impl AsKV<RecordKey, RecordValue> for Record {
-    fn key(&self) -> &RecordKey {
-        &self.key
-    }
+        // Crash after the index tables are moved, before the new superblock
+        let old_superblock_disk =
+            SwornDisk::subdisk_for_superblock(&mem_disk.subset(0..old_nblocks)?)?;
+        let superblock = Superblock::open(&old_superblock_disk, &root_key)?;
+        let old_layout = *superblock.layout();
+        let layout = old_layout.grow(nblocks)?;
+        SwornDisk::move_index_tables(&mem_disk, root_key, &old_layout, &layout, &superblock)?;
+        assert!(SwornDisk::open(mem_disk.clone(), root_key, None, None).is_err());
+
+        // The old disk stays intact
+        let sworndisk = SwornDisk::open(mem_disk.subset(0..old_nblocks)?, root_key, None, None)?;
+        let mut rbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            sworndisk.read(i as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], i as u8);
+        }
+        drop(sworndisk);
 
-    fn value(&self) -> &RecordValue {
-        &self.value
+        // Then the growth can be retried
+        SwornDisk::grow_offline(mem_disk.clone(), root_key, old_nblocks)?;
+        let sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
+        for i in 0..num_rw {
+            sworndisk.read(i as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], i as u8);
+        }
+        Ok(())
     }
-}
 
-#[cfg(feature = "occlum")]
-mod impl_block_device {
-    use super::{BlockSet, BufMut, BufRef, SwornDisk, Vec};
-    use ext2_rs::{Bid, BlockDevice, FsError as Ext2Error};
-
-    impl<D: BlockSet + 'static> BlockDevice for SwornDisk<D> {
-        fn total_blocks(&self) -> usize {
-            self.total_blocks()
+    #[test]
+    fn sworndisk_shrink() -> Result<()> {
+        let (nblocks, new_nblocks) = (64 * 1024, 32 * 1024);
+        let mem_disk = MemDisk::create(nblocks)?;
+        let root_key = Key::random();
+        let config = Config {
+            enable_gc: true,
+            ..Default::default()
+        };
+        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, Some(config.clone()))?;
+        // Fence the head off, so that the data is written to the tail
+        let table = &sworndisk.inner.block_validity_table;
+        let fenced = table.fence(0..table.nblocks() - 4 * 1024);
+        let num_rw = 2 * 1024;
+        let mut wbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            wbuf.as_mut_slice().fill(i as u8);
+            sworndisk.write(i as Lba, wbuf.as_ref())?;
         }
+        sworndisk.sync()?;
+        let hba = sworndisk
+            .inner
+            .logical_block_table
+            .get(&RecordKey { lba: 0 })?
+            .hba;
+        assert!(hba >= table.nblocks() - 4 * 1024);
+        table.unfence(&fenced);
+        sworndisk.sync()?;
+        drop(sworndisk);
 
-        fn read_blocks(&self, bid: Bid, blocks: &mut [&mut [u8]]) -> Result<(), Ext2Error> {
-            if blocks.len() == 1 {
-                self.read(
-                    bid as _,
-                    BufMut::try_from(blocks.first_mut().unwrap().as_mut()).unwrap(),
-                )?;
-                return Ok(());
-            }
+        // The live data doesn't fit in a single segment
+        let err = SwornDisk::shrink(
+            mem_disk.clone(),
+            root_key,
+            5 * 1024,
+            None,
+            Some(config.clone()),
+        )
+        .unwrap_err();
+        assert_eq!(err.errno(), NoSpaceLeft);
+        SwornDisk::shrink(
+            mem_disk.clone(),
+            root_key,
+            new_nblocks,
+            None,
+            Some(config.clone()),
+        )?;
+        assert!(SwornDisk::open(mem_disk.clone(), root_key, None, Some(config.clone())).is_err());
 
-            let mut bufs = blocks
-                .iter_mut()
-                .map(|block| BufMut::try_from(block.as_mut()).unwrap())
-                .collect::<Vec<_>>();
-            self.readv(bid as _, &mut bufs)?;
-            Ok(())
+        let sworndisk = SwornDisk::open(
+            mem_disk.subset(0..new_nblocks)?,
+            root_key,
+            None,
+            Some(config),
+        )?;
+        assert_eq!(sworndisk.total_blocks(), 28 * 1024);
+        let mut rbuf = Buf::alloc(1)?;
+        for i in 0..num_rw {
+            sworndisk.read(i as Lba, rbuf.as_mut())?;
+            assert_eq!(rbuf.as_slice()[0], i as u8);
         }
+        Ok(())
+    }
 
-        fn write_blocks(&self, bid: Bid, blocks: &[&[u8]]) -> Result<(), Ext2Error> {
-            if blocks.len() == 1 {
-                self.write(
-                    bid as _,
-                    BufRef::try_from(blocks.first().unwrap().as_ref()).unwrap(),
-                )?;
-                return Ok(());
-            }
+    /// A minimal xorshift generator, seeded randomly and reported on failure,
+    /// so that a failing operation sequence can be replayed.
+    struct OpRng(u64);
 
-            let bufs = blocks
-                .iter()
-                .map(|block| BufRef::try_from(block.as_ref()).unwrap())
-                .collect::<Vec<_>>();
-            self.writev(bid as _, &bufs)?;
-            Ok(())
+    impl OpRng {
+        fn new(seed: u64) -> Self {
+            Self(seed | 1)
         }
 
-        fn sync(&self) -> Result<(), Ext2Error> {
-            self.sync()?;
-            Ok(())
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
         }
-    }
 
-    impl From<crate::Error> for Ext2Error {
-        fn from(value: crate::Error) -> Self {
-            match value.errno() {
-                crate::Errno::NotFound => Self::EntryNotFound,
-                crate::Errno::InvalidArgs => Self::InvalidParam,
-                crate::Errno::OutOfDisk => Self::NoDeviceSpace,
-                crate::Errno::PermissionDenied => Self::PermError,
-                _ => {
-                    println!("[SwornDisk] Error occurred: {value:?}");
-                    Self::DeviceError(0)
-                }
-            }
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::layers::bio::MemDisk;
-    use crate::layers::disk::bio::{BioReqBuilder, BlockBuf};
+    fn random_seed() -> Result<u64> {
+        let mut seed_bytes = [0u8; 8];
+        Rng::new(&[]).fill_bytes(&mut seed_bytes)?;
+        Ok(u64::from_le_bytes(seed_bytes))
+    }
 
-    use core::ptr::NonNull;
-    use std::thread;
+    /// The model of a disk, mapping each written LBA to the version of its content.
+    /// `synced` is the state that must survive a crash.
+    #[derive(Default)]
+    struct ModelDisk {
+        current: HashMap<Lba, u64>,
+        synced: HashMap<Lba, u64>,
+    }
 
-    #[test]
-    fn sworndisk_fns() -> Result<()> {
-        let nblocks = 128 * 1024;
+    /// Fill a block with the LBA and the version, so that misdirected
+    /// and stale blocks are both detected.
+    fn fill_model_block(block: &mut [u8], lba: Lba, version: u64) {
+        block.fill(version as u8);
+        block[..8].copy_from_slice(&(lba as u64).to_le_bytes());
+        block[8..16].copy_from_slice(&version.to_le_bytes());
+    }
+
+    /// Applies a random sequence of operations to both `SwornDisk` and `ModelDisk`,
+    /// asserts their equivalence after every read, and across crashes and reopens.
+    fn run_model_test(seed: u64, num_ops: usize, config: Config, crash: bool) -> Result<()> {
+        let nblocks = 64 * 1024;
+        let num_lbas = 512;
+        let max_nblocks = 16;
         let mem_disk = MemDisk::create(nblocks)?;
         let root_key = Key::random();
-        // Create a new `SwornDisk` then do some writes
-        let sworndisk = SwornDisk::create(mem_disk.clone(), root_key, None, None)?;
-        let num_rw = 1024;
-
-        // // Submit a write block I/O request
-        let mut wbuf = Buf::alloc(num_rw)?;
-        let bufs = {
-            let mut bufs = Vec::with_capacity(num_rw);
-            for i in 0..num_rw {
-                let buf_slice = &mut wbuf.as_mut_slice()[i * BLOCK_SIZE..(i + 1) * BLOCK_SIZE];
-                buf_slice.fill(i as u8);
-                bufs.push(unsafe {
-                    BlockBuf::from_raw_parts(
-                        NonNull::new(buf_slice.as_mut_ptr()).unwrap(),
-                        BLOCK_SIZE,
-                    )
-                });
+        let mut sworndisk =
+            SwornDisk::create(mem_disk.clone(), root_key, None, Some(config.clone()))?;
+        let mut model = ModelDisk::default();
+        let mut rng = OpRng::new(seed);
+        let mut version = 0u64;
+
+        for nth_op in 0..num_ops {
+            let lba = rng.below(num_lbas - max_nblocks);
+            let nblocks = 1 + rng.below(max_nblocks);
+            match rng.below(100) {
+                // Read
+                0..=39 => {
+                    let mut rbuf = Buf::alloc(nblocks)?;
+                    let holes = sworndisk.read_with_holes(lba, rbuf.as_mut())?;
+                    for (i, block) in rbuf.as_slice().chunks(BLOCK_SIZE).enumerate() {
+                        let block_lba = lba + i;
+                        let is_hole = holes.iter().any(|hole| hole.contains(&block_lba));
+                        match model.current.get(&block_lba) {
+                            Some(&block_version) => {
+                                let mut expected = [0u8; BLOCK_SIZE];
+                                fill_model_block(&mut expected, block_lba, block_version);
+                                assert!(
+                                    !is_hole && block == expected,
+                                    "seed {seed}, op {nth_op}: lba {block_lba} mismatches"
+                                );
+                            }
+                            None => assert!(
+                                is_hole && block.iter().all(|&b| b == 0),
+                                "seed {seed}, op {nth_op}: lba {block_lba} should be a hole"
+                            ),
+                        }
+                    }
+                }
+                // Write
+                40..=64 => {
+                    let mut wbuf = Buf::alloc(nblocks)?;
+                    for (i, block) in wbuf.as_mut_slice().chunks_mut(BLOCK_SIZE).enumerate() {
+                        version += 1;
+                        fill_model_block(block, lba + i, version);
+                        model.current.insert(lba + i, version);
+                    }
+                    sworndisk.write(lba, wbuf.as_ref())?;
+                }
+                // Writev
+                65..=84 => {
+                    let mut bufs = Vec::new();
+                    for i in 0..nblocks {
+                        let mut wbuf = Buf::alloc(1)?;
+                        version += 1;
+                        fill_model_block(wbuf.as_mut_slice(), lba + i, version);
+                        model.current.insert(lba + i, version);
+                        bufs.push(wbuf);
+                    }
+                    let buf_refs: Vec<_> = bufs.iter().map(|buf| buf.as_ref()).collect();
+                    sworndisk.writev(lba, &buf_refs)?;
+                }
+                // Sync
+                85..=94 => {
+                    sworndisk.sync()?;
+                    model.synced = model.current.clone();
+                }
+                // Crash, i.e., reopen without syncing, or a clean reopen
+                _ => {
+                    if !crash {
+                        sworndisk.sync()?;
+                        model.synced = model.current.clone();
+                    }
+                    drop(sworndisk);
+                    sworndisk =
+                        SwornDisk::open(mem_disk.clone(), root_key, None, Some(config.clone()))?;
+                    model.current = model.synced.clone();
+                }
             }
-            bufs
-        };
-        let bio_req = BioReqBuilder::new(BioType::Write)
-            .addr(0 as BlockId)
-            .bufs(bufs)
-            .build();
-        sworndisk.submit_bio_sync(bio_req)?;
-
-        // // Sync the `SwornDisk` then do some reads
-        sworndisk.submit_bio_sync(BioReqBuilder::new(BioType::Sync).build())?;
+        }
+        Ok(())
+    }
 
-        let mut rbuf = Buf::alloc(1)?;
-        for i in 0..num_rw {
-            sworndisk.read(i as Lba, rbuf.as_mut())?;
-            assert_eq!(rbuf.as_slice()[0], i as u8);
+    #[test]
+    fn sworndisk_model_equivalence() -> Result<()> {
+        let seed = random_seed()?;
+        for nth in 0..4 {
+            run_model_test(seed.wrapping_add(nth), 1000, Config::default(), true)?;
         }
+        Ok(())
+    }
 
-        // Open the closed `SwornDisk` then test its data's existence
-        drop(sworndisk);
-        thread::spawn(move || -> Result<()> {
-            let opened_sworndisk = SwornDisk::open(mem_disk, root_key, None, None)?;
-            let mut rbuf = Buf::alloc(2)?;
-            opened_sworndisk.read(5 as Lba, rbuf.as_mut())?;
-            assert_eq!(rbuf.as_slice()[0], 5u8);
-            assert_eq!(rbuf.as_slice()[4096], 6u8);
-            Ok(())
-        })
-        .join()
-        .unwrap()
+    #[test]
+    fn sworndisk_model_equivalence_with_gc() -> Result<()> {
+        let seed = random_seed()?;
+        let config = Config {
+            enable_gc: true,
+            ..Default::default()
+        };
+        run_model_test(seed, 2000, config, false)
     }
 }
@@ -0,0 +1,152 @@
+//! The built-in sync ID store.
+//!
+//! If no `SyncIdStore` is given on creating or opening a `SwornDisk`, each
+//! `TxLsmTree` stores its master sync ID to the `SYNC` bucket of its own
+//! `TxLogStore`. Unlike a trusted store, it is as vulnerable to rollback
+//! attacks as the disk itself, but keeps the sync ID durable across restarts.
+use crate::layers::bio::{BlockSet, Buf, BufRef};
+use crate::layers::log::TxLogStore;
+use crate::layers::lsm::{SyncId, SyncIdStore};
+use crate::prelude::*;
+
+use core::mem::size_of;
+
+/// The bucket name of the master sync ID.
+const BUCKET_SYNC_ID: &str = "SYNC";
+/// The number of sync IDs appended to a log before it's replaced by a new one.
+const SYNC_ID_LOG_NBLOCKS: usize = 1024;
+
+/// A `SyncIdStore` backed by the `SYNC` bucket of a `TxLogStore`.
+pub struct TxLogSyncIdStore<D> {
+    store: Arc<TxLogStore<D>>,
+}
+
+impl<D: BlockSet + 'static> TxLogSyncIdStore<D> {
+    /// Creates a `TxLogSyncIdStore` on the given `TxLogStore`.
+    pub fn new(store: Arc<TxLogStore<D>>) -> Self {
+        Self { store }
+    }
+}
+
+impl<D: BlockSet + 'static> SyncIdStore for TxLogSyncIdStore<D> {
+    /// Reads the master sync ID, i.e., the last block of the log. It fails
+    /// with `NotFound` if the sync ID has never been written.
+    fn read(&self) -> Result<SyncId> {
+        let store = &self.store;
+        let mut tx = store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            let sync_id_log = match store.open_log_in(BUCKET_SYNC_ID) {
+                Ok(sync_id_log) => sync_id_log,
+                Err(e) if e.errno() == NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let mut buf = Buf::alloc(1)?;
+            sync_id_log.read((sync_id_log.nblocks() - 1) as BlockId, buf.as_mut())?;
+            Ok(Some(SyncId::from_le_bytes(
+                buf.as_slice()[..size_of::<SyncId>()].try_into().unwrap(),
+            )))
+        });
+        let sync_id = match res {
+            Ok(sync_id) => sync_id,
+            Err(_) => {
+                tx.abort();
+                return_errno_with_msg!(TxAborted, "read sync ID TX aborted");
+            }
+        };
+        tx.commit()?;
+        sync_id.ok_or(Error::with_msg(NotFound, "sync ID has never been written"))
+    }
+
+    /// Writes the master sync ID by appending it to the log, which is
+    /// replaced by a new one once it holds `SYNC_ID_LOG_NBLOCKS` sync IDs.
+    /// It is durable once the `TxLogStore` is synced.
+    fn write(&self, id: SyncId) -> Result<()> {
+        let mut buf = Buf::alloc(1)?;
+        buf.as_mut_slice()[..size_of::<SyncId>()].copy_from_slice(&id.to_le_bytes());
+
+        let store = &self.store;
+        let mut tx = store.new_tx();
+        let res: Result<_> = tx.context(|| {
+            let sync_id_log_ids = match store.list_logs_in(BUCKET_SYNC_ID) {
+                Ok(sync_id_log_ids) => sync_id_log_ids,
+                Err(e) if e.errno() == NotFound => Vec::new(),
+                Err(e) => return Err(e),
+            };
+            let sync_id_log = match sync_id_log_ids.iter().max() {
+                Some(&sync_id_log_id) => {
+                    let sync_id_log = store.open_log(sync_id_log_id, true)?;
+                    if sync_id_log.nblocks() < SYNC_ID_LOG_NBLOCKS {
+                        sync_id_log
+                    } else {
+                        for &sync_id_log_id in sync_id_log_ids.iter() {
+                            store.delete_log(sync_id_log_id)?;
+                        }
+                        store.create_log(BUCKET_SYNC_ID)?
+                    }
+                }
+                None => store.create_log(BUCKET_SYNC_ID)?,
+            };
+            sync_id_log.append(BufRef::try_from(buf.as_slice()).unwrap())
+        });
+        if res.is_err() {
+            tx.abort();
+            return_errno_with_msg!(TxAborted, "write sync ID TX aborted");
+        }
+        tx.commit()
+    }
+}
+
+/// Returns the given `SyncIdStore`, or a `TxLogSyncIdStore` on the
+/// `TxLogStore` if not given.
+pub(super) fn sync_id_store_or_default<D: BlockSet + 'static>(
+    sync_id_store: &Option<Arc<dyn SyncIdStore>>,
+    store: &Arc<TxLogStore<D>>,
+) -> Arc<dyn SyncIdStore> {
+    match sync_id_store {
+        Some(sync_id_store) => sync_id_store.clone(),
+        None => Arc::new(TxLogSyncIdStore::new(store.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::bio::MemDisk;
+    use crate::os::AeadKey as Key;
+
+    #[test]
+    fn sync_id_store() -> Result<()> {
+        let mem_disk = MemDisk::create(4 * 1024)?;
+        let root_key = Key::random();
+        let store = Arc::new(TxLogStore::format(mem_disk.clone(), root_key.clone())?);
+        let sync_id_store = TxLogSyncIdStore::new(store.clone());
+        assert_eq!(sync_id_store.read().unwrap_err().errno(), NotFound);
+
+        sync_id_store.write(1)?;
+        sync_id_store.write(42)?;
+        assert_eq!(sync_id_store.read()?, 42);
+
+        // The log is replaced once full
+        for id in 0..SYNC_ID_LOG_NBLOCKS as SyncId {
+            sync_id_store.write(100 + id)?;
+        }
+        assert_eq!(
+            sync_id_store.read()?,
+            100 + SYNC_ID_LOG_NBLOCKS as SyncId - 1
+        );
+        let mut tx = store.new_tx();
+        let num_logs = tx.context(|| store.list_logs_in(BUCKET_SYNC_ID).map(|ids| ids.len()))?;
+        tx.commit()?;
+        assert_eq!(num_logs, 1);
+        sync_id_store.write(42)?;
+        assert_eq!(sync_id_store.read()?, 42);
+        store.sync()?;
+        drop(sync_id_store);
+        drop(store);
+
+        let store = Arc::new(TxLogStore::recover(mem_disk, root_key)?);
+        let sync_id_store = TxLogSyncIdStore::new(store);
+        assert_eq!(sync_id_store.read()?, 42);
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 //! Write Amplification Factor (WAF) statistics.
 
+use super::cost_stats::STATS_ENABLED;
+
+use core::fmt::{self, Write};
 use core::sync::atomic::{AtomicU64, Ordering};
-use lazy_static::lazy_static;
 
 /// WAF statistics collector
 pub struct WafStats {
@@ -20,11 +22,17 @@ impl WafStats {
 
     /// Add logical write bytes (writes to user_data_disk)
     pub fn add_logical(&self, bytes: u64) {
+        if !STATS_ENABLED {
+            return;
+        }
         self.logical_bytes.fetch_add(bytes, Ordering::Relaxed);
     }
 
     /// Add physical write bytes (writes to underlying block_set)
     pub fn add_physical(&self, bytes: u64) {
+        if !STATS_ENABLED {
+            return;
+        }
         self.physical_bytes.fetch_add(bytes, Ordering::Relaxed);
     }
 
@@ -55,21 +63,41 @@ impl WafStats {
         self.physical_bytes.store(0, Ordering::Relaxed);
     }
 
-    /// Print statistics
-    pub fn print(&self) {
+    /// Restore statistics from a previous snapshot
+    pub fn restore(&self, logical: u64, physical: u64) {
+        self.logical_bytes.store(logical, Ordering::Relaxed);
+        self.physical_bytes.store(physical, Ordering::Relaxed);
+    }
+
+    /// Print statistics to `sink`
+    pub fn print(&self, sink: &mut dyn Write) -> fmt::Result {
         let logical = self.get_logical();
         let physical = self.get_physical();
         let waf = self.waf();
 
-        println!("==================== WAF Statistics ====================");
-        println!("  Logical writes:  {} bytes ({:.2} MB)", logical, logical as f64 / 1024.0 / 1024.0);
-        println!("  Physical writes: {} bytes ({:.2} MB)", physical, physical as f64 / 1024.0 / 1024.0);
-        println!("  WAF:             {:.3}", waf);
-        println!("========================================================");
+        writeln!(
+            sink,
+            "==================== WAF Statistics ===================="
+        )?;
+        writeln!(
+            sink,
+            "  Logical writes:  {} bytes ({:.2} MB)",
+            logical,
+            logical as f64 / 1024.0 / 1024.0
+        )?;
+        writeln!(
+            sink,
+            "  Physical writes: {} bytes ({:.2} MB)",
+            physical,
+            physical as f64 / 1024.0 / 1024.0
+        )?;
+        writeln!(sink, "  WAF:             {:.3}", waf)?;
+        writeln!(
+            sink,
+            "========================================================"
+        )
     }
 }
 
 // Global WAF statistics
-lazy_static! {
-    pub static ref WAF_STATS: WafStats = WafStats::new();
-}
+pub static WAF_STATS: WafStats = WafStats::new();
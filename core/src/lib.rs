@@ -27,6 +27,8 @@
 )]
 
 mod error;
+#[cfg(feature = "linux")]
+pub mod ffi;
 mod layers;
 mod os;
 mod prelude;
@@ -44,13 +46,57 @@ pub use self::os::{Arc, Mutex, Vec};
 extern crate sgx_tstd;
 
 pub use self::error::{Errno, Error};
-pub use self::layers::bio::{BlockId, BlockSet, Buf, BufMut, BufRef, BLOCK_SIZE};
-pub use self::layers::disk::Config;
-pub use self::layers::disk::SwornDisk;
+pub use self::layers::bio::{
+    BlockId, BlockSet, Buf, BufMut, BufRef, MemDisk, MemDiskProfile, MirroredDisk, OverlayDisk,
+    StripedDisk, BLOCK_SIZE,
+};
+#[cfg(all(feature = "std", unix))]
+pub use self::layers::bio::{FileDisk, FileDiskOptions, MmapDisk};
 pub use self::layers::disk::{
-    print_all_cost_stats, print_cost_stats_json, CostL2Type, CostL3Type, CONFIG, COST_L2, COST_L3,
+    cost_clock, cost_timers_enabled, disable_cost_timers, print_all_cost_stats,
+    print_cost_stats_json, set_cost_clock, tsc_frequency, BloomFilterStats, CostClock, CostL2Type,
+    CostL3Type, CostTimersDisabled, BIO_STATS, CONFIG, COST_L2, COST_L3, GC_STATS, STATS_ENABLED,
     WAF_STATS,
 };
-pub use self::layers::disk::{GreedyVictimPolicy, LoopScanVictimPolicy, VictimPolicy};
-pub use self::os::{Aead, AeadIv, AeadKey, AeadMac, Rng};
+pub use self::layers::disk::{
+    hot_cold_trace, parse_trace, simulate_gc, uniform_trace, GcSimConfig, GcSimReport, TraceOp,
+};
+pub use self::layers::disk::{
+    AllocAlignStats, AllocPolicy, BlockCryptoMode, BvtCompactionPolicy, Config, ReverseIndexKind,
+    VictimPolicyKind, LAYOUT_FRACTION_BASE,
+};
+pub use self::layers::disk::{
+    AuditReport, BulkWriter, FragmentationReport, Segment, SegmentUsage, SwornDisk, VerifyResult,
+};
+pub use self::layers::disk::{BackgroundIoLimit, BioTenant, RateLimit, RateLimitStats};
+pub use self::layers::disk::{
+    BlockMigration, DiskEvent, EventSubscriber, EventSubscriberRef, SubscriptionId,
+};
+pub use self::layers::disk::{
+    CacheExport, CacheTierExport, CostExport, GcExport, QueueExport, QueueTypeExport, StatsExport,
+    WafExport,
+};
+pub use self::layers::disk::{CacheRatios, MemBudget, MemUsage};
+pub use self::layers::disk::{CacheStats, CacheTier, CacheTierSnapshot, CACHE_STATS};
+pub use self::layers::disk::{CorruptionHandler, CorruptionHandlerRef, CorruptionReport};
+pub use self::layers::disk::{DiscardStats, DiskDigest, DiskStats, DIGEST_BUCKET_NBLOCKS};
+pub use self::layers::disk::{
+    GcContext, GenerationalVictimPolicy, GreedyVictimPolicy, LazyGreedyVictimPolicy,
+    LoopScanVictimPolicy, Victim, VictimPolicy, WindowGreedyVictimPolicy,
+};
+pub use self::layers::disk::{KekKeyProvider, RootKeyProvider, TrustedCounter, TrustedCounterRef};
+pub use self::layers::disk::{StreamReader, StreamSummary, StreamWriter};
+pub use self::layers::lsm::CompactionPolicyKind;
+#[cfg(feature = "async")]
+pub use self::layers::{bio::AsyncBlockSet, disk::AsyncSwornDisk};
+pub use self::os::{
+    default_aead_backend, Aead, AeadBackend, AeadBackendRef, AeadIv, AeadKey, AeadMac,
+    CpuAesFeatures, NativeAeadBackend, Rng,
+};
+#[cfg(feature = "sim")]
+pub use self::os::{Clock, SimClock, WaitPoint};
+#[cfg(feature = "chaos")]
+pub use self::util::{
+    delay_hook, fail_hook, pass_chaos_point, set_chaos_hook, ChaosGuard, ChaosHook, ChaosPoint,
+};
 pub use self::util::{Aead as _, RandomInit, Rng as _};
@@ -0,0 +1,255 @@
+//! Pluggable backends of the AEAD cipher used to protect user data blocks.
+//!
+//! The AEAD implementation of each OS (e.g., OpenSSL in `std`) is wrapped as
+//! the `NativeAeadBackend`. Other implementations, e.g., an AES-NI or VAES
+//! batch mode of a hardware vendor, can be plugged in through `Config`.
+use super::{Aead, AeadIv, AeadKey, AeadMac, Arc};
+use crate::prelude::*;
+
+/// A backend of the AEAD cipher, with batch entry points to encrypt or decrypt
+/// multiple equal-sized chunks (e.g., blocks) in one call.
+pub trait AeadBackend: Send + Sync {
+    /// The name of the backend.
+    fn name(&self) -> &'static str;
+
+    /// Encrypt `input` with the key, IV and associated data `aad`, writes the
+    /// ciphertext to `output` and returns the MAC.
+    fn encrypt(
+        &self,
+        input: &[u8],
+        key: &AeadKey,
+        iv: &AeadIv,
+        aad: &[u8],
+        output: &mut [u8],
+    ) -> Result<AeadMac>;
+
+    /// Decrypt `input` with the key, IV, associated data `aad` and the MAC,
+    /// writes the plaintext to `output`.
+    fn decrypt(
+        &self,
+        input: &[u8],
+        key: &AeadKey,
+        iv: &AeadIv,
+        aad: &[u8],
+        mac: &AeadMac,
+        output: &mut [u8],
+    ) -> Result<()>;
+
+    /// Encrypt a batch of chunks, the nth chunk `inputs[n]` with `keys[n]`,
+    /// `ivs[n]` and the associated data `aads[n]`, writes the ciphertext to
+    /// `outputs[n]` and the MAC to `macs[n]`.
+    ///
+    /// Backends with a batch mode should override it, the default one
+    /// encrypts the chunks one by one.
+    fn encrypt_batch(
+        &self,
+        inputs: &[&[u8]],
+        keys: &[&AeadKey],
+        ivs: &[AeadIv],
+        aads: &[&[u8]],
+        outputs: &mut [&mut [u8]],
+        macs: &mut [AeadMac],
+    ) -> Result<()> {
+        check_batch_args(inputs, keys, ivs, aads, outputs, macs.len())?;
+        for (nth, output) in outputs.iter_mut().enumerate() {
+            macs[nth] = self.encrypt(inputs[nth], keys[nth], &ivs[nth], aads[nth], output)?;
+        }
+        Ok(())
+    }
+
+    /// Decrypt a batch of chunks, the nth chunk `inputs[n]` with `keys[n]`,
+    /// `ivs[n]`, `aads[n]` and `macs[n]`, writes the plaintext to `outputs[n]`.
+    /// Fails if any chunk fails.
+    fn decrypt_batch(
+        &self,
+        inputs: &[&[u8]],
+        keys: &[&AeadKey],
+        ivs: &[AeadIv],
+        aads: &[&[u8]],
+        macs: &[AeadMac],
+        outputs: &mut [&mut [u8]],
+    ) -> Result<()> {
+        check_batch_args(inputs, keys, ivs, aads, outputs, macs.len())?;
+        for (nth, output) in outputs.iter_mut().enumerate() {
+            self.decrypt(
+                inputs[nth],
+                keys[nth],
+                &ivs[nth],
+                aads[nth],
+                &macs[nth],
+                output,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub type AeadBackendRef = Arc<dyn AeadBackend>;
+
+/// Check the arguments of a batch operation.
+fn check_batch_args(
+    inputs: &[&[u8]],
+    keys: &[&AeadKey],
+    ivs: &[AeadIv],
+    aads: &[&[u8]],
+    outputs: &[&mut [u8]],
+    nmacs: usize,
+) -> Result<()> {
+    let nchunks = inputs.len();
+    if keys.len() != nchunks
+        || ivs.len() != nchunks
+        || aads.len() != nchunks
+        || outputs.len() != nchunks
+        || nmacs != nchunks
+    {
+        return_errno_with_msg!(InvalidArgs, "mismatched number of chunks in AEAD batch");
+    }
+    if inputs
+        .iter()
+        .zip(outputs.iter())
+        .any(|(input, output)| input.len() != output.len())
+    {
+        return_errno_with_msg!(InvalidArgs, "mismatched chunk size in AEAD batch");
+    }
+    Ok(())
+}
+
+/// The AEAD backend of the OS, e.g., OpenSSL in `std`, which dispatches to
+/// the AES-NI or VAES code path on its own if the CPU supports it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NativeAeadBackend;
+
+impl AeadBackend for NativeAeadBackend {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn encrypt(
+        &self,
+        input: &[u8],
+        key: &AeadKey,
+        iv: &AeadIv,
+        aad: &[u8],
+        output: &mut [u8],
+    ) -> Result<AeadMac> {
+        Aead::new().encrypt(input, key, iv, aad, output)
+    }
+
+    fn decrypt(
+        &self,
+        input: &[u8],
+        key: &AeadKey,
+        iv: &AeadIv,
+        aad: &[u8],
+        mac: &AeadMac,
+        output: &mut [u8],
+    ) -> Result<()> {
+        Aead::new().decrypt(input, key, iv, aad, mac, output)
+    }
+}
+
+/// The AES-related features of the CPU.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CpuAesFeatures {
+    /// AES-NI and carry-less multiplication (for GCM).
+    pub aes_ni: bool,
+    /// Vector AES instructions, which encrypt multiple blocks in parallel.
+    pub vaes: bool,
+}
+
+impl CpuAesFeatures {
+    /// Detect the features by CPUID. All features are reported as absent
+    /// if the detection is not supported.
+    pub fn detect() -> Self {
+        #[cfg(all(feature = "std", target_arch = "x86_64"))]
+        return Self {
+            aes_ni: std::arch::is_x86_feature_detected!("aes")
+                && std::arch::is_x86_feature_detected!("pclmulqdq"),
+            vaes: std::arch::is_x86_feature_detected!("vaes")
+                && std::arch::is_x86_feature_detected!("vpclmulqdq"),
+        };
+        #[cfg(not(all(feature = "std", target_arch = "x86_64")))]
+        Self::default()
+    }
+}
+
+/// Returns the default AEAD backend, which is always the native one.
+///
+/// It's a fixed choice rather than one made by the features of the CPU, as
+/// no other backend is built in and the native one dispatches to the
+/// hardware acceleration on its own. The detected features are only logged
+/// for diagnosis, a backend of a hardware vendor is plugged in through
+/// `Config::aead_backend` instead.
+pub fn default_aead_backend() -> AeadBackendRef {
+    #[cfg(not(feature = "linux"))]
+    debug!(
+        "[AeadBackend] CPU AES features: {:?}",
+        CpuAesFeatures::detect()
+    );
+    Arc::new(NativeAeadBackend)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aead_backend_batch() -> Result<()> {
+        let backend = default_aead_backend();
+        let nchunks = 4;
+        let chunk_size = 64;
+        let keys: Vec<_> = (0..nchunks).map(|_| AeadKey::random()).collect();
+        let key_refs: Vec<_> = keys.iter().collect();
+        let ivs: Vec<_> = (0..nchunks).map(|_| AeadIv::random()).collect();
+        let plain: Vec<u8> = (0..nchunks * chunk_size).map(|i| i as u8).collect();
+        let plains: Vec<_> = plain.chunks(chunk_size).collect();
+        let aad: Vec<u8> = (0..nchunks as u8).collect();
+        let aads: Vec<_> = (0..nchunks).map(|nth| &aad[..nth]).collect();
+
+        let mut cipher = vec![0u8; plain.len()];
+        let mut macs = vec![AeadMac::default(); nchunks];
+        let mut outputs: Vec<_> = cipher.chunks_mut(chunk_size).collect();
+        backend.encrypt_batch(&plains, &key_refs, &ivs, &aads, &mut outputs, &mut macs)?;
+        // A chunk of the batch is the same as a single encryption
+        let mut single = vec![0u8; chunk_size];
+        let mac = backend.encrypt(plains[1], &keys[1], &ivs[1], aads[1], &mut single)?;
+        assert_eq!(single, cipher[chunk_size..2 * chunk_size]);
+        assert_eq!(&*mac, &*macs[1]);
+
+        let ciphers: Vec<_> = cipher.chunks(chunk_size).collect();
+        let mut decrypted = vec![0u8; plain.len()];
+        let mut outputs: Vec<_> = decrypted.chunks_mut(chunk_size).collect();
+        backend.decrypt_batch(&ciphers, &key_refs, &ivs, &aads, &macs, &mut outputs)?;
+        assert_eq!(decrypted, plain);
+
+        // The associated data is authenticated
+        let mut outputs: Vec<_> = decrypted.chunks_mut(chunk_size).collect();
+        assert!(backend
+            .decrypt_batch(
+                &ciphers,
+                &key_refs,
+                &ivs,
+                &vec![&[][..]; nchunks],
+                &macs,
+                &mut outputs
+            )
+            .is_err());
+
+        macs.swap(0, 1);
+        let mut outputs: Vec<_> = decrypted.chunks_mut(chunk_size).collect();
+        assert!(backend
+            .decrypt_batch(&ciphers, &key_refs, &ivs, &aads, &macs, &mut outputs)
+            .is_err());
+        assert!(backend
+            .decrypt_batch(
+                &ciphers[1..],
+                &key_refs,
+                &ivs,
+                &aads,
+                &macs,
+                &mut outputs[1..]
+            )
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+//! Clocks to drive timed waits of background tasks.
+use super::sleep;
+
+use core::time::Duration;
+
+/// A clock that blocks the current thread for a duration.
+///
+/// Background tasks sleep on a `Clock` instead of calling `sleep()` directly,
+/// so that the time can be simulated in tests (see `SimClock`).
+pub trait Clock: Send + Sync {
+    /// Blocks the current thread for at least the duration. The sleep may end
+    /// early once `is_interrupted` holds upon `interrupt()`, if supported.
+    fn sleep(&self, dur: Duration, is_interrupted: &dyn Fn() -> bool);
+
+    /// Wakes up the sleeping threads to check whether they are interrupted.
+    fn interrupt(&self) {}
+
+    /// Returns the time elapsed since an arbitrary but fixed epoch of the clock.
+    fn now(&self) -> Duration;
+
+    /// Returns whether `now()` advances, i.e., the clock keeps the time.
+    fn keeps_time(&self) -> bool {
+        true
+    }
+}
+
+/// The clock of the real time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn sleep(&self, dur: Duration, _is_interrupted: &dyn Fn() -> bool) {
+        sleep(dur);
+    }
+
+    /// The real time is only available with `std`, otherwise it stays zero.
+    fn now(&self) -> Duration {
+        #[cfg(feature = "std")]
+        {
+            static EPOCH: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+            EPOCH.get_or_init(std::time::Instant::now).elapsed()
+        }
+        #[cfg(not(feature = "std"))]
+        Duration::ZERO
+    }
+
+    fn keeps_time(&self) -> bool {
+        cfg!(feature = "std")
+    }
+}
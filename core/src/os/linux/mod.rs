@@ -37,6 +37,7 @@ use serde::{Deserialize, Serialize};
 use crate::{
     error::Errno,
     prelude::{Error, Result},
+    util::SHA256_SIZE,
 };
 
 /// Reuse `BTreeMap` in `btree` crate.
@@ -1621,3 +1622,36 @@ impl crate::util::Skcipher for Skcipher {
             .map_err(|_| Error::with_msg(Errno::DecryptFailed, "ctr(aes) decryption failed"))
     }
 }
+
+/// A SHA-256 hash, with the SHA-256 library of linux kernel.
+pub struct Sha256 {
+    state: bindings::sha256_state,
+}
+
+impl Sha256 {
+    /// Construct a `Sha256` instance.
+    pub fn new() -> Self {
+        let mut state = bindings::sha256_state::default();
+        // SAFETY: `state` is non-null and valid.
+        unsafe { bindings::sha256_init(&mut state) };
+        Self { state }
+    }
+}
+
+impl crate::util::Hash for Sha256 {
+    type Digest = [u8; SHA256_SIZE];
+
+    fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(u32::MAX as usize) {
+            // SAFETY: `self.state` is initialized, and `chunk` is valid for reads.
+            unsafe { bindings::sha256_update(&mut self.state, chunk.as_ptr(), chunk.len() as _) };
+        }
+    }
+
+    fn finalize(mut self) -> Self::Digest {
+        let mut digest = [0u8; SHA256_SIZE];
+        // SAFETY: `self.state` is initialized, and `digest` is valid for writes.
+        unsafe { bindings::sha256_final(&mut self.state, digest.as_mut_ptr()) };
+        digest
+    }
+}
@@ -6,7 +6,7 @@ mod jinux;
 pub use self::jinux::{
     spawn, Aead, AeadIv, AeadKey, AeadMac, Arc, BTreeMap, Box, Condvar, CurrentThread, CvarMutex,
     HashMap, HashSet, JoinHandle, Mutex, MutexGuard, Pages, Rng, RwLock, RwLockReadGuard,
-    RwLockWriteGuard, Skcipher, SkcipherIv, SkcipherKey, String, Tid, ToString, Vec, Weak,
+    RwLockWriteGuard, Sha256, Skcipher, SkcipherIv, SkcipherKey, String, Tid, ToString, Vec, Weak,
     PAGE_SIZE,
 };
 
@@ -16,8 +16,8 @@ mod linux;
 pub use self::linux::{
     sleep, spawn, Aead, AeadIv, AeadKey, AeadMac, Arc, BTreeMap, Box, Condvar, CurrentThread,
     CvarMutex, HashMap, HashSet, JoinHandle, Mutex, MutexGuard, Pages, Rng, RwLock,
-    RwLockReadGuard, RwLockWriteGuard, Skcipher, SkcipherIv, SkcipherKey, String, Tid, ToString,
-    Vec, Weak, PAGE_SIZE,
+    RwLockReadGuard, RwLockWriteGuard, Sha256, Skcipher, SkcipherIv, SkcipherKey, String, Tid,
+    ToString, Vec, Weak, PAGE_SIZE,
 };
 
 #[cfg(feature = "occlum")]
@@ -26,8 +26,8 @@ mod occlum;
 pub use self::occlum::{
     sleep, spawn, Aead, AeadIv, AeadKey, AeadMac, Arc, BTreeMap, Box, Condvar, CurrentThread,
     CvarMutex, HashMap, HashSet, JoinHandle, Mutex, MutexGuard, Pages, Rng, RwLock,
-    RwLockReadGuard, RwLockWriteGuard, Skcipher, SkcipherIv, SkcipherKey, String, Tid, ToString,
-    Vec, Weak, PAGE_SIZE,
+    RwLockReadGuard, RwLockWriteGuard, Sha256, Skcipher, SkcipherIv, SkcipherKey, String, Tid,
+    ToString, Vec, Weak, PAGE_SIZE,
 };
 
 #[cfg(feature = "std")]
@@ -36,6 +36,22 @@ mod std;
 pub use self::std::{
     sleep, spawn, Aead, AeadIv, AeadKey, AeadMac, Arc, BTreeMap, Box, Condvar, CurrentThread,
     CvarMutex, HashMap, HashSet, JoinHandle, Mutex, MutexGuard, Pages, Rng, RwLock,
-    RwLockReadGuard, RwLockWriteGuard, Skcipher, SkcipherIv, SkcipherKey, String, Tid, ToString,
-    Vec, Weak, PAGE_SIZE,
+    RwLockReadGuard, RwLockWriteGuard, Sha256, Skcipher, SkcipherIv, SkcipherKey, String, Tid,
+    ToString, Vec, Weak, PAGE_SIZE,
+};
+
+mod scheduler;
+pub use self::scheduler::{BackgroundTask, TaskContext, TaskPriority, TaskScheduler};
+
+mod clock;
+pub use self::clock::{Clock, RealClock};
+
+#[cfg(feature = "sim")]
+mod sim;
+#[cfg(feature = "sim")]
+pub use self::sim::{SimClock, WaitPoint};
+
+mod aead_backend;
+pub use self::aead_backend::{
+    default_aead_backend, AeadBackend, AeadBackendRef, CpuAesFeatures, NativeAeadBackend,
 };
@@ -2,6 +2,7 @@
 
 use crate::error::Errno;
 use crate::prelude::{Error, Result};
+use crate::util::SHA256_SIZE;
 
 use core::marker::PhantomData;
 use core::ptr::NonNull;
@@ -9,6 +10,7 @@ pub use hashbrown::{HashMap, HashSet};
 use pod::Pod;
 use serde::{Deserialize, Serialize};
 use sgx_rand::{thread_rng, Rng as _};
+use sgx_tcrypto::SgxShaHandle;
 use sgx_tcrypto::{rsgx_aes_ctr_decrypt, rsgx_aes_ctr_encrypt};
 use sgx_tcrypto::{rsgx_rijndael128GCM_decrypt, rsgx_rijndael128GCM_encrypt};
 use sgx_tstd::alloc::{alloc, dealloc, Layout};
@@ -298,3 +300,27 @@ impl crate::util::Skcipher for Skcipher {
             .map_err(|_| Error::with_msg(Errno::DecryptFailed, "skcipher decrypt failed"))
     }
 }
+
+/// A SHA-256 hash.
+pub struct Sha256(SgxShaHandle);
+
+impl Sha256 {
+    /// Construct a `Sha256` instance.
+    pub fn new() -> Self {
+        let handle = SgxShaHandle::new();
+        handle.init().expect("init sha256 state failed");
+        Self(handle)
+    }
+}
+
+impl crate::util::Hash for Sha256 {
+    type Digest = [u8; SHA256_SIZE];
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update_slice(data).expect("sha256 update failed");
+    }
+
+    fn finalize(self) -> Self::Digest {
+        self.0.get_hash().expect("sha256 finalize failed")
+    }
+}
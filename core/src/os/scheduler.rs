@@ -0,0 +1,359 @@
+//! A scheduler of background tasks.
+//!
+//! `TaskScheduler` runs all registered `BackgroundTask`s (e.g., GC) on one
+//! worker thread, so that background work is serialized and coordinated
+//! instead of being driven by ad-hoc sleeps in dedicated threads.
+//!
+//! The scheduler keeps a virtual clock advanced by fixed ticks, the worker
+//! thread sleeps a tick on a `Clock` in between. Tasks are run
+//! when due, in the order of their priorities. Foreground activities are
+//! reported by `mark_active()`, from which each task learns whether the
+//! system has been idle since its last run, and the system is considered
+//! idle if there is no activity during the last tick. The scheduler can also be driven
+//! manually by `tick()` without the worker thread, which makes it testable.
+use super::clock::{Clock, RealClock};
+use super::{spawn, Arc, JoinHandle, Mutex, Vec};
+use crate::prelude::*;
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+/// A task run periodically by `TaskScheduler`.
+pub trait BackgroundTask: Send + Sync {
+    /// Returns the name of the task.
+    fn name(&self) -> &'static str;
+
+    /// Runs the task once, returns the delay until the next run.
+    ///
+    /// The task is removed from the scheduler if an error is returned.
+    fn run(&self, ctx: &TaskContext) -> Result<Duration>;
+}
+
+/// The priority of a `BackgroundTask`. Tasks due at the same tick are
+/// run from the highest priority to the lowest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TaskPriority {
+    High,
+    Normal,
+    /// The task only runs when there is no activity during the last tick.
+    Idle,
+}
+
+/// The context of a run of a `BackgroundTask`.
+#[derive(Clone, Copy, Debug)]
+pub struct TaskContext {
+    now: Duration,
+    is_idle: bool,
+}
+
+impl TaskContext {
+    /// Returns the time of the virtual clock of the scheduler.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Whether there is no foreground activity since the last run of the task.
+    pub fn is_idle(&self) -> bool {
+        self.is_idle
+    }
+}
+
+/// A scheduler that runs `BackgroundTask`s on a worker thread.
+pub struct TaskScheduler {
+    inner: Arc<SchedulerInner>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct SchedulerInner {
+    tasks: Mutex<Vec<TaskEntry>>,
+    tick: Duration,
+    sleep_clock: Arc<dyn Clock>,
+    clock: Mutex<Duration>,
+    // Incremented upon each foreground activity
+    activity_seq: AtomicU64,
+    // The `activity_seq` observed by the last tick
+    last_activity_seq: AtomicU64,
+    is_shutdown: AtomicBool,
+}
+
+struct TaskEntry {
+    task: Arc<dyn BackgroundTask>,
+    priority: TaskPriority,
+    next_run: Duration,
+    // The `activity_seq` observed by the last run
+    seen_activity_seq: u64,
+}
+
+impl TaskScheduler {
+    /// Creates a `TaskScheduler` whose clock advances by `tick` each time.
+    /// The worker thread is not started until `start()`.
+    pub fn new(tick: Duration) -> Self {
+        Self::with_clock(tick, Arc::new(RealClock))
+    }
+
+    /// Creates a `TaskScheduler` whose worker thread sleeps on the given clock.
+    pub fn with_clock(tick: Duration, sleep_clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner: Arc::new(SchedulerInner {
+                tasks: Mutex::new(Vec::new()),
+                tick,
+                sleep_clock,
+                clock: Mutex::new(Duration::ZERO),
+                activity_seq: AtomicU64::new(0),
+                last_activity_seq: AtomicU64::new(0),
+                is_shutdown: AtomicBool::new(false),
+            }),
+            worker: Mutex::new(None),
+        }
+    }
+
+    /// Registers a task, which is due on the next tick.
+    pub fn add_task(&self, task: Arc<dyn BackgroundTask>, priority: TaskPriority) {
+        let next_run = *self.inner.clock.lock();
+        let seen_activity_seq = self.inner.activity_seq.load(Ordering::Acquire);
+        self.inner.tasks.lock().push(TaskEntry {
+            task,
+            priority,
+            next_run,
+            seen_activity_seq,
+        });
+    }
+
+    /// Returns the number of registered tasks.
+    pub fn num_tasks(&self) -> usize {
+        self.inner.tasks.lock().len()
+    }
+
+    /// Reports a foreground activity, so the system is not idle.
+    pub fn mark_active(&self) {
+        self.inner.activity_seq.fetch_add(1, Ordering::Release);
+    }
+
+    /// Starts the worker thread, which ticks until `shutdown()`.
+    pub fn start(&self) {
+        let mut worker = self.worker.lock();
+        if worker.is_some() {
+            return;
+        }
+        let inner = self.inner.clone();
+        let _ = worker.insert(spawn(move || {
+            while !inner.is_shutdown.load(Ordering::Acquire) {
+                inner
+                    .sleep_clock
+                    .sleep(inner.tick, &|| inner.is_shutdown.load(Ordering::Acquire));
+                inner.tick();
+            }
+        }));
+    }
+
+    /// Advances the clock by one tick and runs the due tasks.
+    pub fn tick(&self) {
+        self.inner.tick();
+    }
+
+    /// Stops the worker thread after the running task (if any) is done.
+    pub fn shutdown(&self) {
+        self.inner.is_shutdown.store(true, Ordering::Release);
+        self.inner.sleep_clock.interrupt();
+        if let Some(worker) = self.worker.lock().take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for TaskScheduler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl SchedulerInner {
+    fn tick(&self) {
+        let now = {
+            let mut clock = self.clock.lock();
+            *clock += self.tick;
+            *clock
+        };
+        let activity_seq = self.activity_seq.load(Ordering::Acquire);
+        let is_system_idle =
+            self.last_activity_seq.swap(activity_seq, Ordering::AcqRel) == activity_seq;
+
+        // Collect the due tasks, never hold the lock while running a task
+        let mut due_tasks: Vec<_> = self
+            .tasks
+            .lock()
+            .iter()
+            .filter(|entry| entry.next_run <= now)
+            .filter(|entry| entry.priority != TaskPriority::Idle || is_system_idle)
+            .map(|entry| {
+                let is_idle = entry.seen_activity_seq == activity_seq;
+                (entry.priority, entry.task.clone(), is_idle)
+            })
+            .collect();
+        due_tasks.sort_by_key(|(priority, _, _)| *priority);
+
+        for (_, task, is_idle) in due_tasks {
+            if self.is_shutdown.load(Ordering::Acquire) {
+                return;
+            }
+            let res = task.run(&TaskContext { now, is_idle });
+
+            let mut tasks = self.tasks.lock();
+            let Some(idx) = tasks
+                .iter()
+                .position(|entry| Arc::ptr_eq(&entry.task, &task))
+            else {
+                continue;
+            };
+            match res {
+                Ok(delay) => {
+                    let entry = &mut tasks[idx];
+                    entry.next_run = now + delay;
+                    entry.seen_activity_seq = activity_seq;
+                }
+                Err(_e) => {
+                    #[cfg(not(feature = "linux"))]
+                    error!("background task {} failed: {:?}", task.name(), _e);
+                    let _ = tasks.remove(idx);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::os::sleep;
+
+    use core::sync::atomic::AtomicUsize;
+
+    struct CountTask {
+        name: &'static str,
+        delay: Duration,
+        runs: AtomicUsize,
+        idle_runs: AtomicUsize,
+        fail_at: usize,
+        order: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl CountTask {
+        fn new(name: &'static str, delay: Duration, order: &Arc<Mutex<Vec<&'static str>>>) -> Self {
+            Self {
+                name,
+                delay,
+                runs: AtomicUsize::new(0),
+                idle_runs: AtomicUsize::new(0),
+                fail_at: usize::MAX,
+                order: order.clone(),
+            }
+        }
+    }
+
+    impl BackgroundTask for CountTask {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn run(&self, ctx: &TaskContext) -> Result<Duration> {
+            let runs = self.runs.fetch_add(1, Ordering::Relaxed) + 1;
+            if ctx.is_idle() {
+                self.idle_runs.fetch_add(1, Ordering::Relaxed);
+            }
+            self.order.lock().push(self.name);
+            if runs == self.fail_at {
+                return_errno_with_msg!(IoFailed, "task failed");
+            }
+            Ok(self.delay)
+        }
+    }
+
+    #[test]
+    fn scheduler_priority_and_idle() {
+        let tick = Duration::from_millis(10);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let scheduler = TaskScheduler::new(tick);
+
+        let idle_task = Arc::new(CountTask::new("idle", tick, &order));
+        let normal_task = Arc::new(CountTask::new("normal", tick * 3, &order));
+        let high_task = Arc::new(CountTask::new("high", tick, &order));
+        scheduler.add_task(idle_task.clone(), TaskPriority::Idle);
+        scheduler.add_task(normal_task.clone(), TaskPriority::Normal);
+        scheduler.add_task(high_task.clone(), TaskPriority::High);
+
+        scheduler.tick();
+        assert_eq!(*order.lock(), vec!["high", "normal", "idle"]);
+        scheduler.tick();
+        assert_eq!(idle_task.runs.load(Ordering::Relaxed), 2);
+        assert_eq!(normal_task.runs.load(Ordering::Relaxed), 1);
+        assert_eq!(high_task.runs.load(Ordering::Relaxed), 2);
+        assert_eq!(high_task.idle_runs.load(Ordering::Relaxed), 2);
+
+        // Idle tasks don't run after an activity until a tick passes without any
+        scheduler.mark_active();
+        scheduler.tick();
+        assert_eq!(idle_task.runs.load(Ordering::Relaxed), 2);
+        assert_eq!(high_task.runs.load(Ordering::Relaxed), 3);
+        assert_eq!(high_task.idle_runs.load(Ordering::Relaxed), 2);
+        scheduler.tick();
+        assert_eq!(idle_task.runs.load(Ordering::Relaxed), 3);
+        assert_eq!(high_task.idle_runs.load(Ordering::Relaxed), 3);
+        // The activity happened since the last run of the normal task
+        assert_eq!(normal_task.runs.load(Ordering::Relaxed), 2);
+        assert_eq!(normal_task.idle_runs.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn scheduler_failure_and_shutdown() {
+        let tick = Duration::from_millis(1);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let scheduler = TaskScheduler::new(tick);
+
+        let mut failed_task = CountTask::new("failed", tick, &order);
+        failed_task.fail_at = 2;
+        let failed_task = Arc::new(failed_task);
+        scheduler.add_task(failed_task.clone(), TaskPriority::Normal);
+        scheduler.tick();
+        scheduler.tick();
+        assert_eq!(scheduler.num_tasks(), 0);
+        scheduler.tick();
+        assert_eq!(failed_task.runs.load(Ordering::Relaxed), 2);
+
+        let task = Arc::new(CountTask::new("task", tick, &order));
+        scheduler.add_task(task.clone(), TaskPriority::Normal);
+        scheduler.start();
+        while task.runs.load(Ordering::Relaxed) < 3 {
+            sleep(tick);
+        }
+        scheduler.shutdown();
+        let runs = task.runs.load(Ordering::Relaxed);
+        sleep(tick * 10);
+        assert_eq!(task.runs.load(Ordering::Relaxed), runs);
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn scheduler_on_sim_clock() {
+        use crate::os::SimClock;
+
+        let tick = Duration::from_secs(1);
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let clock = Arc::new(SimClock::new());
+        let scheduler = TaskScheduler::with_clock(tick, clock.clone());
+        let task = Arc::new(CountTask::new("task", tick * 2, &order));
+        scheduler.add_task(task.clone(), TaskPriority::Normal);
+        scheduler.start();
+
+        // The worker runs the due tasks between two sleeps
+        for (nticks, runs) in [(1, 1), (2, 1), (3, 2), (4, 2), (5, 3)] {
+            clock.sleep_point().wait_for_entries(nticks);
+            clock.advance(tick);
+            clock.sleep_point().wait_for_entries(nticks + 1);
+            assert_eq!(task.runs.load(Ordering::Relaxed), runs);
+        }
+
+        // Shutdown doesn't wait for the clock
+        scheduler.shutdown();
+        assert_eq!(clock.sleep_point().nwaiters(), 0);
+    }
+}
@@ -0,0 +1,148 @@
+//! Deterministic simulation support, enabled by the `sim` feature.
+//!
+//! A `SimClock` replaces the real time with a virtual one, which only advances
+//! when a test says so. A `WaitPoint` counts the threads blocked at a specific
+//! point, so that a test proceeds only after the expected threads are blocked,
+//! instead of guessing with real sleeps. Together they make interleavings of
+//! background tasks and foreground requests reproducible and fast.
+use super::clock::Clock;
+use super::{Condvar, CvarMutex};
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+/// A point where threads may block, which counts the blocked threads.
+#[derive(Debug, Default)]
+pub struct WaitPoint {
+    nwaiters: AtomicUsize,
+    nentries: AtomicUsize,
+}
+
+impl WaitPoint {
+    pub const fn new() -> Self {
+        Self {
+            nwaiters: AtomicUsize::new(0),
+            nentries: AtomicUsize::new(0),
+        }
+    }
+
+    /// Marks the current thread blocked at the point.
+    pub fn enter(&self) {
+        self.nentries.fetch_add(1, Ordering::AcqRel);
+        self.nwaiters.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Marks the current thread no longer blocked at the point.
+    pub fn leave(&self) {
+        self.nwaiters.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// Returns the number of threads blocked at the point.
+    pub fn nwaiters(&self) -> usize {
+        self.nwaiters.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of times threads have blocked at the point.
+    pub fn nentries(&self) -> usize {
+        self.nentries.load(Ordering::Acquire)
+    }
+
+    /// Waits until at least `n` threads are blocked at the point.
+    pub fn wait_for_waiters(&self, n: usize) {
+        while self.nwaiters() < n {
+            std::thread::yield_now();
+        }
+    }
+
+    /// Waits until threads have blocked at the point at least `n` times.
+    pub fn wait_for_entries(&self, n: usize) {
+        while self.nentries() < n {
+            std::thread::yield_now();
+        }
+    }
+}
+
+/// A virtual clock that only advances by `advance()`.
+///
+/// Each `SwornDisk` has its own one in simulation mode, see
+/// `SwornDisk::sim_clock()`.
+pub struct SimClock {
+    now: CvarMutex<Duration>,
+    cvar: Condvar,
+    sleep_point: WaitPoint,
+}
+
+impl SimClock {
+    pub fn new() -> Self {
+        Self {
+            now: CvarMutex::new(Duration::ZERO),
+            cvar: Condvar::new(),
+            sleep_point: WaitPoint::new(),
+        }
+    }
+
+    /// Returns the virtual time.
+    pub fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    /// Advances the virtual time, waking up the threads whose sleep is over.
+    pub fn advance(&self, dur: Duration) {
+        *self.now.lock().unwrap() += dur;
+        self.cvar.notify_all();
+    }
+
+    /// Returns the point where threads sleep on the clock.
+    pub fn sleep_point(&self) -> &WaitPoint {
+        &self.sleep_point
+    }
+}
+
+impl Clock for SimClock {
+    fn sleep(&self, dur: Duration, is_interrupted: &dyn Fn() -> bool) {
+        let mut now = self.now.lock().unwrap();
+        let deadline = *now + dur;
+        self.sleep_point.enter();
+        while *now < deadline && !is_interrupted() {
+            now = self.cvar.wait(now).unwrap();
+        }
+        self.sleep_point.leave();
+    }
+
+    fn interrupt(&self) {
+        // Hold the lock so that no sleeper misses the notification
+        // between checking `is_interrupted` and waiting
+        let _now = self.now.lock().unwrap();
+        self.cvar.notify_all();
+    }
+
+    fn now(&self) -> Duration {
+        SimClock::now(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::os::{spawn, Arc};
+
+    #[test]
+    fn sim_clock_sleep() {
+        let clock = Arc::new(SimClock::new());
+        let sleeper = spawn({
+            let clock = clock.clone();
+            move || {
+                clock.sleep(Duration::from_secs(10), &|| false);
+                clock.now()
+            }
+        });
+
+        clock.sleep_point().wait_for_waiters(1);
+        clock.advance(Duration::from_secs(6));
+        // Still sleeping, no real time passes
+        assert_eq!(clock.sleep_point().nwaiters(), 1);
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(sleeper.join().unwrap(), Duration::from_secs(12));
+        assert_eq!(clock.sleep_point().nwaiters(), 0);
+    }
+}
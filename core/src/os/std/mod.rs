@@ -3,6 +3,7 @@
 use core::{marker::PhantomData, ptr::NonNull};
 use openssl::{
     rand::rand_bytes,
+    sha,
     symm::{decrypt, decrypt_aead, encrypt, encrypt_aead, Cipher},
 };
 use pod::Pod;
@@ -11,6 +12,7 @@ use serde::{Deserialize, Serialize};
 use crate::{
     error::Errno,
     prelude::{Error, Result},
+    util::SHA256_SIZE,
 };
 
 /// Reuse implementations in `alloc` crate.
@@ -293,6 +295,29 @@ impl crate::util::Skcipher for Skcipher {
     }
 }
 
+/// A SHA-256 hash.
+#[derive(Clone)]
+pub struct Sha256(sha::Sha256);
+
+impl Sha256 {
+    /// Construct a `Sha256` instance.
+    pub fn new() -> Self {
+        Self(sha::Sha256::new())
+    }
+}
+
+impl crate::util::Hash for Sha256 {
+    type Digest = [u8; SHA256_SIZE];
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> Self::Digest {
+        self.0.finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
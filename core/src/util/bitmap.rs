@@ -39,6 +39,35 @@ impl BitMap {
         Self { bits, nbits }
     }
 
+    /// Grow the bitmap to `nbits` bits, the added bits are set to `value`.
+    ///
+    /// # Panics
+    ///
+    /// The `nbits` must be no less than the current length.
+    pub fn grow(&mut self, nbits: usize, value: bool) {
+        assert!(nbits >= self.nbits);
+        let old_nbits = self.nbits;
+        // The unused bits in the last u64 are zero already
+        self.bits.resize((nbits + 64 - 1) / 64, 0u64);
+        self.nbits = nbits;
+        if value == Self::ONE {
+            (old_nbits..nbits).for_each(|index| self.set_bit(index));
+        }
+    }
+
+    /// Truncate the bitmap to `nbits` bits, the bits beyond are dropped.
+    ///
+    /// # Panics
+    ///
+    /// The `nbits` must be no more than the current length.
+    pub fn truncate(&mut self, nbits: usize) {
+        assert!(nbits <= self.nbits);
+        // Keep the unused bits in the last u64 zero
+        (nbits..self.nbits).for_each(|index| self.clear_bit(index));
+        self.bits.truncate((nbits + 64 - 1) / 64);
+        self.nbits = nbits;
+    }
+
     /// Return the total number of bits.
     pub fn len(&self) -> usize {
         self.nbits
@@ -296,4 +325,25 @@ mod tests {
         assert_eq!(bm.first_zeros(0, 2), None);
         assert_eq!(bm.last_zero(), Some(64));
     }
+
+    #[test]
+    fn grow_and_truncate() {
+        let mut bm = BitMap::repeat(false, 100);
+        bm.grow(200, true);
+        assert_eq!(bm.len(), 200);
+        assert_eq!(bm.count_ones(), 100);
+        assert_eq!(bm.first_one(0), Some(100));
+        assert_eq!(bm.last_zero(), Some(99));
+
+        bm.grow(300, false);
+        assert_eq!(bm.count_zeros(), 200);
+        assert_eq!(bm.last_one(), Some(199));
+
+        bm.truncate(150);
+        assert_eq!(bm.len(), 150);
+        assert_eq!(bm.count_ones(), 50);
+        assert_eq!(bm.last_one(), Some(149));
+        bm.grow(200, false);
+        assert_eq!(bm.count_ones(), 50);
+    }
 }
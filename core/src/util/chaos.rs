@@ -0,0 +1,125 @@
+//! Delay and fault injection at named points, enabled by the `chaos` feature.
+//!
+//! Some crash-ordering and concurrency windows (e.g., a crash after the data
+//! blocks are written but before they are indexed) are too narrow to be hit
+//! by chance. A test registers a hook at a `ChaosPoint`, which runs each time
+//! the point is passed, to widen the window with a delay, to block until the
+//! test proceeds, or to fail the operation with an error.
+//!
+//! The hooks are global to the process, a test setting hooks that fail should
+//! clear them (see `ChaosGuard`) and not run concurrently with other I/Os.
+use crate::os::{HashMap, Mutex};
+use crate::prelude::*;
+use crate::Errno;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use lazy_static::lazy_static;
+
+/// A named point where a hook can be injected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChaosPoint {
+    /// Before a group of records is appended to the WAL of `TxLsmTree`.
+    WalAppend,
+    /// After the user data blocks are written, before their records are
+    /// put into the logical block table.
+    DataWritten,
+    /// Inside the remapping of a block migrated by GC, after its record is
+    /// updated, before its reverse entry is.
+    GcRemap,
+}
+
+/// A hook run at a `ChaosPoint`, whose error fails the ongoing operation.
+pub type ChaosHook = Arc<dyn Fn() -> Result<()> + Send + Sync>;
+
+lazy_static! {
+    static ref CHAOS_HOOKS: Mutex<HashMap<ChaosPoint, ChaosHook>> = Mutex::new(HashMap::new());
+}
+
+/// Sets the hook at `point`, replacing the old one (if any). The hook is
+/// cleared once the returned guard is dropped.
+#[must_use]
+pub fn set_chaos_hook(point: ChaosPoint, hook: ChaosHook) -> ChaosGuard {
+    CHAOS_HOOKS.lock().insert(point, hook);
+    ChaosGuard { point }
+}
+
+/// Returns a hook that delays the thread passing the point by `dur`.
+pub fn delay_hook(dur: Duration) -> ChaosHook {
+    Arc::new(move || {
+        std::thread::sleep(dur);
+        Ok(())
+    })
+}
+
+/// Returns a hook that fails the first `times` passes with `errno`.
+pub fn fail_hook(errno: Errno, times: usize) -> ChaosHook {
+    let remaining = AtomicUsize::new(times);
+    Arc::new(move || {
+        if remaining
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            return_errno_with_msg!(errno, "failure injected by chaos hook");
+        }
+        Ok(())
+    })
+}
+
+/// Runs the hook at `point` (if any).
+pub fn pass_chaos_point(point: ChaosPoint) -> Result<()> {
+    // Run the hook without the lock, it may block for long
+    let hook = CHAOS_HOOKS.lock().get(&point).cloned();
+    match hook {
+        Some(hook) => hook(),
+        None => Ok(()),
+    }
+}
+
+/// Clears the hook at a point on drop.
+pub struct ChaosGuard {
+    point: ChaosPoint,
+}
+
+impl Drop for ChaosGuard {
+    fn drop(&mut self) {
+        CHAOS_HOOKS.lock().remove(&self.point);
+    }
+}
+
+/// Runs the hook at the `ChaosPoint`, and returns its error (if any) from
+/// the current function. It's a no-op unless the `chaos` feature is enabled.
+#[macro_export]
+macro_rules! chaos_point {
+    ($point: ident) => {
+        #[cfg(feature = "chaos")]
+        $crate::pass_chaos_point($crate::ChaosPoint::$point)?;
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chaos_hooks() {
+        let guard = set_chaos_hook(ChaosPoint::GcRemap, fail_hook(IoFailed, 2));
+        for _ in 0..2 {
+            let err = pass_chaos_point(ChaosPoint::GcRemap).unwrap_err();
+            assert_eq!(err.errno(), IoFailed);
+        }
+        assert!(pass_chaos_point(ChaosPoint::GcRemap).is_ok());
+        drop(guard);
+
+        let nhits = Arc::new(AtomicUsize::new(0));
+        let _guard = set_chaos_hook(ChaosPoint::GcRemap, {
+            let nhits = nhits.clone();
+            Arc::new(move || {
+                nhits.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+        });
+        pass_chaos_point(ChaosPoint::GcRemap).unwrap();
+        assert_eq!(nhits.load(Ordering::Relaxed), 1);
+    }
+}
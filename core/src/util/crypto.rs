@@ -76,6 +76,17 @@ pub trait Skcipher {
     ) -> Result<()>;
 }
 
+/// Cryptographic hash algorithm, which digests the data incrementally.
+pub trait Hash {
+    type Digest: AsRef<[u8]>;
+
+    /// Feed the data referred by `data` into the hash.
+    fn update(&mut self, data: &[u8]);
+
+    /// Consume the hash and return the digest of all the data fed.
+    fn finalize(self) -> Self::Digest;
+}
+
 /// Random number generator.
 pub trait Rng {
     /// Create an instance, with `seed` to provide secure entropy.
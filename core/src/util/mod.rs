@@ -1,11 +1,24 @@
 //! Utilities.
 mod bitmap;
+#[cfg(feature = "chaos")]
+mod chaos;
+#[cfg(feature = "debug_crc")]
+mod crc32;
 mod crypto;
 mod lazy_delete;
+mod sha256;
+mod trace;
 
 pub use self::bitmap::BitMap;
-pub use self::crypto::{Aead, RandomInit, Rng, Skcipher};
+#[cfg(feature = "chaos")]
+pub use self::chaos::{
+    delay_hook, fail_hook, pass_chaos_point, set_chaos_hook, ChaosGuard, ChaosHook, ChaosPoint,
+};
+#[cfg(feature = "debug_crc")]
+pub use self::crc32::crc32;
+pub use self::crypto::{Aead, Hash, RandomInit, Rng, Skcipher};
 pub use self::lazy_delete::LazyDelete;
+pub use self::sha256::{sha256, SHA256_SIZE};
 
 /// Aligns `x` up to the next multiple of `align`.
 pub(crate) const fn align_up(x: usize, align: usize) -> usize {
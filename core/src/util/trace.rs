@@ -0,0 +1,15 @@
+//! Structured tracing spans.
+
+/// Enters a span of the given name and fields until the end of the current scope,
+/// e.g., `trace_span!("read", lba, nblocks = buf.nblocks())`.
+///
+/// The fields follow the syntax of `tracing::info_span!`. It's a no-op unless
+/// the `trace` feature is enabled, in which case the spans can be collected and
+/// analyzed by any `tracing` subscriber.
+#[macro_export]
+macro_rules! trace_span {
+    ($name: expr $(, $($fields: tt)*)?) => {
+        #[cfg(feature = "trace")]
+        let _span = tracing::info_span!($name $(, $($fields)*)?).entered();
+    };
+}
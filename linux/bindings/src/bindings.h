@@ -9,6 +9,7 @@
  */
 
 #include <crypto/aead.h>
+#include <crypto/sha2.h>
 #include <crypto/skcipher.h>
 #include <linux/bio.h>
 #include <linux/crypto.h>
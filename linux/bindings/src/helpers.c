@@ -21,6 +21,7 @@
  */
 
 #include <crypto/aead.h>
+#include <crypto/sha2.h>
 #include <crypto/skcipher.h>
 #include <linux/atomic.h>
 #include <linux/bio.h>
@@ -105,3 +106,8 @@ struct skcipher_request *helper_skcipher_request_alloc(
 {
 	return skcipher_request_alloc(tfm, gfp);
 }
+
+void helper_sha256_init(struct sha256_state *sctx)
+{
+	sha256_init(sctx);
+}
@@ -441,7 +441,9 @@ impl DmTargetOps for DmSwornDisk {
         let worker = dm_sworndisk.worker.lock().take().unwrap();
         worker.join().unwrap();
 
-        dm_sworndisk.queue.sync().unwrap();
+        if let Err(err) = dm_sworndisk.queue.sync() {
+            pr_err!("Error, failed to sync dm_sworndisk: {:?}\n", err);
+        }
     }
 
     fn map(target: &Target<Self>, bio: Bio) -> MapState {